@@ -0,0 +1,70 @@
+//! Clean and smudge throughput per golden corpus file (see `testdata/`).
+//!
+//! `cargo bench --bench filters [-- <filter>]`
+//!
+//! Criterion reports each file's throughput in bytes of source per second
+//! and compares against the previous run, so a change that slows the
+//! parser, serializer or printer down shows up as a regression. For a quick
+//! end-to-end number on your own repository, use `git-ast bench` instead.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use git_ast::config::RepoConfig;
+use git_ast::git_plumbing::filters;
+use git_ast::languages;
+use git_ast::parsing::ParseLimits;
+use std::path::{Path, PathBuf};
+
+fn inputs() -> Vec<(String, Vec<u8>)> {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+    let mut paths = Vec::new();
+    collect(&corpus, &mut paths);
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| {
+            let name = path
+                .strip_prefix(&corpus)
+                .unwrap()
+                .to_string_lossy()
+                .into_owned();
+            (name, std::fs::read(&path).unwrap())
+        })
+        .collect()
+}
+
+fn collect(dir: &Path, out: &mut Vec<PathBuf>) {
+    for entry in std::fs::read_dir(dir).expect("corpus directory") {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect(&path, out);
+        } else if path
+            .to_str()
+            .is_some_and(|p| languages::for_path(p).is_some())
+        {
+            out.push(path);
+        }
+    }
+}
+
+fn clean_and_smudge(c: &mut Criterion) {
+    let limits = ParseLimits::default();
+    let config = RepoConfig::default();
+    for (name, source) in inputs() {
+        let provider = languages::for_path(&name);
+        let blob = filters::clean_with(&source, &name, provider, &limits, &config).unwrap();
+        let mut group = c.benchmark_group(name.as_str());
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_function("clean", |b| {
+            b.iter(|| {
+                filters::clean_with(black_box(&source), &name, provider, &limits, &config).unwrap()
+            })
+        });
+        group.bench_function("smudge", |b| {
+            b.iter(|| filters::perform_smudge(black_box(&blob), &name).unwrap())
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, clean_and_smudge);
+criterion_main!(benches);
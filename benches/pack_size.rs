@@ -0,0 +1,126 @@
+//! Pack sizes of the golden corpus stored as plain and as zstd-compressed
+//! blobs (see `src/compression.rs`).
+//!
+//! `cargo bench --bench pack_size [-- <corpus dir>]`
+//!
+//! For each setting, every corpus input is committed, then committed again
+//! with a comment appended (a small edit Git can delta-compress), and the
+//! repository is packed with `git gc --aggressive`. Prints the total size of
+//! the blobs and of the resulting pack.
+
+use git2::{Repository, Signature};
+use git_ast::config::{Compression, RepoConfig, StorageConfig};
+use git_ast::git_plumbing::filters;
+use git_ast::languages;
+use git_ast::parsing::ParseLimits;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn main() {
+    let corpus = std::env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with('-'))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata"));
+    let mut inputs = Vec::new();
+    collect(&corpus, &mut inputs);
+    inputs.sort();
+
+    println!("{:<8} {:>12} {:>12}", "storage", "blob bytes", "pack bytes");
+    for compression in [Compression::None, Compression::Zstd] {
+        let config = RepoConfig {
+            storage: StorageConfig {
+                compression,
+                ..StorageConfig::default()
+            },
+            ..RepoConfig::default()
+        };
+        let (blobs, pack) = measure(&inputs, &config);
+        println!(
+            "{:<8} {:>12} {:>12}",
+            format!("{:?}", compression).to_lowercase(),
+            blobs,
+            pack
+        );
+    }
+}
+
+fn collect(dir: &Path, out: &mut Vec<PathBuf>) {
+    for entry in std::fs::read_dir(dir).expect("corpus directory") {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect(&path, out);
+        } else if path
+            .to_str()
+            .is_some_and(|p| languages::for_path(p).is_some())
+        {
+            out.push(path);
+        }
+    }
+}
+
+/// Commits the corpus twice under `config` and returns (blob bytes, pack bytes).
+fn measure(inputs: &[PathBuf], config: &RepoConfig) -> (u64, u64) {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let signature = Signature::now("bench", "bench@localhost").unwrap();
+    let mut blob_bytes = 0;
+    let mut parent = None;
+    for revision in 0..2 {
+        let mut builder = repo.treebuilder(None).unwrap();
+        for (i, path) in inputs.iter().enumerate() {
+            let name = path.to_string_lossy().into_owned();
+            let provider = languages::for_path(&name);
+            let mut source = std::fs::read(path).unwrap();
+            if revision == 1 {
+                let comment = match provider.map(|p| p.name) {
+                    Some("python" | "ruby") => "# edited\n",
+                    _ => "// edited\n",
+                };
+                source.extend_from_slice(comment.as_bytes());
+            }
+            let Ok(blob) =
+                filters::clean_with(&source, &name, provider, &ParseLimits::default(), config)
+            else {
+                continue;
+            };
+            blob_bytes += blob.len() as u64;
+            let id = repo.blob(&blob).unwrap();
+            builder
+                .insert(
+                    format!("{}-{}", i, path.file_name().unwrap().to_string_lossy()),
+                    id,
+                    0o100644,
+                )
+                .unwrap();
+        }
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let parents: Vec<_> = parent.iter().collect();
+        let id = repo
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "corpus",
+                &tree,
+                &parents,
+            )
+            .unwrap();
+        parent = Some(repo.find_commit(id).unwrap());
+    }
+
+    let status = Command::new("git")
+        .args(["gc", "--aggressive", "--prune=now", "--quiet"])
+        .current_dir(dir.path())
+        .status()
+        .expect("git gc");
+    assert!(status.success());
+    let packs = dir.path().join(".git/objects/pack");
+    let pack_bytes = std::fs::read_dir(packs)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "pack"))
+        .map(|path| path.metadata().unwrap().len())
+        .sum();
+    (blob_bytes, pack_bytes)
+}
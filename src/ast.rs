@@ -0,0 +1,264 @@
+//! Stored Syntax Trees
+//!
+//! [`Node`] is the tree `git-ast` actually stores: a Tree-sitter CST with
+//! everything that is *formatting* removed and everything that is *code* kept.
+//!
+//! -   **Kept:** every token (keywords, punctuation, identifiers, literals),
+//!     every comment, node kinds and field names.
+//! -   **Dropped:** whitespace and line breaks between tokens, indentation,
+//!     and layout-only nodes listed in
+//!     [`SyntaxRules::skip`](crate::languages::SyntaxRules::skip).
+//!
+//! Two pieces of layout survive as flags because they carry meaning for
+//! humans and cannot be recomputed by a printer:
+//!
+//! -   `blank_line_before`: a statement/item that was separated from the
+//!     previous one by an empty line (paragraphs of code).
+//! -   `own_line`: a comment that started its own line instead of trailing
+//!     code.
+//!
+//! String literals and comments (the provider's
+//! [`atomic`](crate::languages::SyntaxRules::atomic) kinds) are stored as
+//! single leaves with their exact text, since whitespace inside them matters.
+//! Code embedded in such leaves may additionally carry a parsed tree; see
+//! [`injections`](crate::injections).
+//!
+//! ## Verbatim Regions
+//!
+//! Work-in-progress code often has a syntax error in one place and is fine
+//! everywhere else. [`from_tree_tolerant`] keeps such files: each top-level
+//! item containing an `ERROR` or missing node becomes a single [`VERBATIM`]
+//! leaf holding the item's exact text, and the rest of the file is stored as
+//! usual. The printer writes the leaf back unchanged on its own line, so the
+//! broken code survives checkout byte for byte while the code around it is
+//! still diffed and merged as a tree.
+
+use crate::injections::Injected;
+use crate::languages::LanguageProvider;
+use crate::Error;
+use std::ops::Range;
+use tree_sitter::{Tree, TreeCursor};
+
+/// Kind of a leaf holding a top-level item with a syntax error as written.
+pub const VERBATIM: &str = "verbatim";
+
+/// A node of a stored syntax tree.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Node {
+    /// Grammar node kind (`function_item`), or the token itself for anonymous nodes (`{`).
+    pub kind: String,
+    /// Whether the grammar names this node (as opposed to a literal token).
+    pub named: bool,
+    /// Field name under which the parent holds this node (`name`, `body`).
+    pub field: Option<String>,
+    /// Source text; `Some` exactly for leaves.
+    pub text: Option<String>,
+    /// Separated from the previous statement by an empty line.
+    pub blank_line_before: bool,
+    /// Comment that started its own line.
+    pub own_line: bool,
+    pub children: Vec<Node>,
+    /// Embedded-language trees parsed from this leaf's text.
+    pub injections: Vec<Injected>,
+}
+
+impl Node {
+    /// Whether this node is a token (has text and no children).
+    pub fn is_leaf(&self) -> bool {
+        self.text.is_some()
+    }
+
+    /// The leaf tokens below this node, in source order.
+    pub fn leaves(&self) -> Vec<&Node> {
+        let mut out = Vec::new();
+        self.collect_leaves(&mut out);
+        out
+    }
+
+    fn collect_leaves<'a>(&'a self, out: &mut Vec<&'a Node>) {
+        if self.is_leaf() {
+            out.push(self);
+        }
+        for child in &self.children {
+            child.collect_leaves(out);
+        }
+    }
+
+    /// Mutable access to the leaf tokens below this node, in source order.
+    pub fn leaves_mut(&mut self) -> Vec<&mut Node> {
+        let mut out = Vec::new();
+        self.collect_leaves_mut(&mut out);
+        out
+    }
+
+    /// Whether any top-level item below this root is [`VERBATIM`].
+    pub fn has_verbatim(&self) -> bool {
+        self.children
+            .iter()
+            .any(|child| child.kind == VERBATIM && child.is_leaf())
+    }
+
+    fn collect_leaves_mut<'a>(&'a mut self, out: &mut Vec<&'a mut Node>) {
+        if self.is_leaf() {
+            out.push(self);
+            return;
+        }
+        for child in &mut self.children {
+            child.collect_leaves_mut(out);
+        }
+    }
+}
+
+/// Converts a parsed Tree-sitter tree into the stored representation.
+///
+/// `source` must be the exact text `tree` was parsed from, and must be UTF-8.
+pub fn from_tree(tree: &Tree, source: &[u8], provider: &LanguageProvider) -> Result<Node, Error> {
+    Ok(from_tree_with_spans(tree, source, provider)?.0)
+}
+
+/// Like [`from_tree`], turning each top-level item with a syntax error into
+/// a [`VERBATIM`] leaf (see [Verbatim Regions](self#verbatim-regions)).
+pub fn from_tree_tolerant(
+    tree: &Tree,
+    source: &[u8],
+    provider: &LanguageProvider,
+) -> Result<Node, Error> {
+    Ok(build(tree, source, provider, true)?.0)
+}
+
+/// Like [`from_tree`], but also returns the byte range in `source` of every
+/// leaf, in [`Node::leaves`] order.
+pub fn from_tree_with_spans(
+    tree: &Tree,
+    source: &[u8],
+    provider: &LanguageProvider,
+) -> Result<(Node, Vec<Range<usize>>), Error> {
+    build(tree, source, provider, false)
+}
+
+fn build(
+    tree: &Tree,
+    source: &[u8],
+    provider: &LanguageProvider,
+    verbatim_errors: bool,
+) -> Result<(Node, Vec<Range<usize>>), Error> {
+    std::str::from_utf8(source)
+        .map_err(|e| Error::Parsing(format!("source is not valid UTF-8: {}", e)))?;
+    let mut builder = Builder {
+        source,
+        provider,
+        prev_end: 0,
+        spans: Vec::new(),
+        verbatim_errors,
+    };
+    let mut cursor = tree.walk();
+    let root = builder.build(&mut cursor, None);
+    Ok((root, builder.spans))
+}
+
+struct Builder<'a> {
+    source: &'a [u8],
+    provider: &'a LanguageProvider,
+    /// End offset of the last token emitted, for measuring the gap before the next one.
+    prev_end: usize,
+    /// Byte range of every leaf built so far.
+    spans: Vec<Range<usize>>,
+    /// Store top-level items with syntax errors as [`VERBATIM`] leaves.
+    verbatim_errors: bool,
+}
+
+impl Builder<'_> {
+    fn build(&mut self, cursor: &mut TreeCursor<'_>, field: Option<&str>) -> Node {
+        let node = cursor.node();
+        let rules = &self.provider.syntax;
+        let mut out = Node {
+            kind: node.kind().to_string(),
+            named: node.is_named(),
+            field: field.map(str::to_string),
+            ..Node::default()
+        };
+
+        if node.child_count() == 0 || rules.atomic.contains(&node.kind()) {
+            let mut range = node.byte_range();
+            let is_comment = rules.comments.contains(&node.kind());
+            out.own_line =
+                is_comment && (self.prev_end == 0 || self.newlines_before(range.start) > 0);
+            if is_comment {
+                // Keep at most the newline ending the line; blank lines after
+                // it are layout, recorded on the next node.
+                while range.len() > 1 && self.source[range.end - 2..range.end] == *b"\n\n" {
+                    range.end -= 1;
+                }
+            }
+            out.text = Some(String::from_utf8_lossy(&self.source[range.clone()]).into_owned());
+            self.prev_end = range.end;
+            self.spans.push(range);
+            return out;
+        }
+
+        let is_container =
+            rules.blocks.contains(&node.kind()) || rules.roots.contains(&node.kind());
+        if cursor.goto_first_child() {
+            let mut statements = 0;
+            loop {
+                let child = cursor.node();
+                if !rules.skip.contains(&child.kind()) && !child.is_missing() {
+                    let field = cursor.field_name();
+                    let blank = self.newlines_before(child.start_byte()) >= 2;
+                    let is_statement = child.is_named() || !is_delimiter(child.kind());
+                    let mut built = match self.verbatim_errors
+                        && node.parent().is_none()
+                        && child.has_error()
+                    {
+                        true => self.verbatim(child, field),
+                        false => self.build(cursor, field),
+                    };
+                    if is_container && is_statement {
+                        // Leading blank lines inside a block carry no meaning.
+                        built.blank_line_before = blank && statements > 0;
+                        statements += 1;
+                    }
+                    out.children.push(built);
+                }
+                if !cursor.goto_next_sibling() {
+                    break;
+                }
+            }
+            cursor.goto_parent();
+        }
+        out
+    }
+
+    /// A [`VERBATIM`] leaf with the text of `node`, less trailing whitespace.
+    fn verbatim(&mut self, node: tree_sitter::Node<'_>, field: Option<&str>) -> Node {
+        let mut range = node.byte_range();
+        while range.end > range.start && self.source[range.end - 1].is_ascii_whitespace() {
+            range.end -= 1;
+        }
+        self.prev_end = range.end;
+        self.spans.push(range.clone());
+        Node {
+            kind: VERBATIM.to_string(),
+            named: true,
+            field: field.map(str::to_string),
+            text: Some(String::from_utf8_lossy(&self.source[range]).into_owned()),
+            ..Node::default()
+        }
+    }
+
+    fn newlines_before(&self, offset: usize) -> usize {
+        let start = self.prev_end.min(offset);
+        // Some grammars end line comments with the newline itself.
+        let ended_line = start > 0 && self.source[start - 1] == b'\n';
+        self.source[start..offset]
+            .iter()
+            .filter(|&&b| b == b'\n')
+            .count()
+            + ended_line as usize
+    }
+}
+
+/// Tokens that open, close, or separate entries of a block rather than being entries.
+pub(crate) fn is_delimiter(token: &str) -> bool {
+    matches!(token, "{" | "}" | "(" | ")" | "[" | "]" | "," | ";")
+}
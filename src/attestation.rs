@@ -0,0 +1,486 @@
+//! Blob Attestations
+//!
+//! With `ast.attest = true`, the clean filter signs a statement about every
+//! AST blob it stores: the blob id (a hash of its content), the header it
+//! was written with and the `git-ast` version that produced it. Signatures
+//! use the same setup as `git commit -S`: `gpg.format` (`openpgp` or `ssh`),
+//! `user.signingKey` and `gpg.program`/`gpg.ssh.program`.
+//!
+//! The filter cannot know which blobs end up committed, so it saves records
+//! below `.git/ast-attestations/`; the `post-commit` hook moves them into
+//! notes on [`ATTESTATIONS_REF`], one note per blob:
+//!
+//! ```text
+//! git-ast attestation 1
+//! blob 5b1c0f...
+//! language rust
+//! grammar 0.23.2
+//! format 1
+//! compression none
+//! sparse false
+//! toolchain git-ast 0.1.0
+//! signer ssh
+//! -----BEGIN SSH SIGNATURE-----
+//! ...
+//! -----END SSH SIGNATURE-----
+//! ```
+//!
+//! The signature covers every line before it. `git-ast verify --attestations`
+//! checks that each AST blob of a revision has a record, that the record
+//! matches the blob's header, and that the signature is good: OpenPGP
+//! signatures against the keyring, SSH signatures against
+//! `gpg.ssh.allowedSignersFile`. Share the records with
+//! `git push <remote> refs/notes/ast-attestations`.
+
+use crate::git_plumbing::notes;
+use crate::serialization::BlobHeader;
+use crate::util::atomic_io;
+use crate::{log_info, Error};
+use git2::{Config, ErrorCode, Oid, Repository};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Notes ref holding one attestation per AST blob.
+pub const ATTESTATIONS_REF: &str = "refs/notes/ast-attestations";
+
+/// Setting that makes the clean filter attest the blobs it stores.
+pub const ATTEST_KEY: &str = "ast.attest";
+
+/// Directory below `.git` holding attestations until they are committed.
+pub const PENDING_ATTESTATIONS_DIR: &str = "ast-attestations";
+
+/// First line of every attestation.
+const MAGIC: &str = "git-ast attestation 1";
+
+/// SSH signature namespace, so these signatures cannot pass for commit ones.
+const NAMESPACE: &str = "git-ast";
+
+/// A signed statement about one AST blob.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attestation {
+    pub blob: Oid,
+    pub header: BlobHeader,
+    /// `git-ast <version>` of the binary that cleaned the blob.
+    pub toolchain: String,
+    /// `openpgp` or `ssh`.
+    pub signer: String,
+    /// Armored detached signature over [`payload`](Attestation::payload).
+    pub signature: String,
+}
+
+impl Attestation {
+    /// The signed lines.
+    pub fn payload(&self) -> String {
+        let header = &self.header;
+        format!(
+            "{}\nblob {}\nlanguage {}\ngrammar {}\nformat {}\ncompression {}\nsparse {}\ntoolchain {}\nsigner {}\n",
+            MAGIC,
+            self.blob,
+            header.language,
+            header.grammar,
+            header.format_version,
+            header.compression.as_deref().unwrap_or("none"),
+            header.sparse,
+            self.toolchain,
+            self.signer,
+        )
+    }
+
+    /// The note text: the payload followed by the signature.
+    pub fn to_note(&self) -> String {
+        let mut note = self.payload() + &self.signature;
+        if !note.ends_with('\n') {
+            note.push('\n');
+        }
+        note
+    }
+
+    /// Parses a note written by [`to_note`](Attestation::to_note).
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let invalid = |what: &str| Error::Verification(format!("invalid attestation: {}", what));
+        let mut lines = text.lines();
+        if lines.next() != Some(MAGIC) {
+            return Err(invalid("unknown format"));
+        }
+        let mut fields = BTreeMap::new();
+        for key in [
+            "blob",
+            "language",
+            "grammar",
+            "format",
+            "compression",
+            "sparse",
+            "toolchain",
+            "signer",
+        ] {
+            let line = lines.next().ok_or_else(|| invalid("truncated"))?;
+            let value = line
+                .strip_prefix(key)
+                .and_then(|rest| rest.strip_prefix(' '))
+                .ok_or_else(|| invalid(&format!("expected {:?}", key)))?;
+            fields.insert(key, value.to_string());
+        }
+        let signature: String = lines.map(|line| format!("{}\n", line)).collect();
+        Ok(Attestation {
+            blob: Oid::from_str(&fields["blob"]).map_err(|_| invalid("bad blob id"))?,
+            header: BlobHeader {
+                format_version: fields["format"]
+                    .parse()
+                    .map_err(|_| invalid("bad format"))?,
+                language: fields["language"].clone(),
+                grammar: fields["grammar"].clone(),
+                compression: Some(fields["compression"].clone()).filter(|c| c != "none"),
+                sparse: fields["sparse"] == "true",
+            },
+            toolchain: fields["toolchain"].clone(),
+            signer: fields["signer"].clone(),
+            signature,
+        })
+    }
+}
+
+/// How to sign and verify, from Git's signing configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Signer {
+    OpenPgp {
+        program: String,
+        /// `user.signingKey`; the default key if unset.
+        key: Option<String>,
+    },
+    Ssh {
+        program: String,
+        /// Path of the private (or agent-backed public) key.
+        key: Option<PathBuf>,
+        allowed_signers: Option<PathBuf>,
+    },
+}
+
+impl Signer {
+    /// Reads `gpg.format`, `user.signingKey`, `gpg.program`,
+    /// `gpg.ssh.program` and `gpg.ssh.allowedSignersFile`.
+    pub fn from_config(config: &Config) -> Result<Self, Error> {
+        let get = |key: &str| match config.get_string(key) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.code() == ErrorCode::NotFound => Ok(None),
+            Err(e) => Err(Error::from(e)),
+        };
+        let path = |key: &str| match config.get_path(key) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.code() == ErrorCode::NotFound => Ok(None),
+            Err(e) => Err(Error::from(e)),
+        };
+        match get("gpg.format")?.as_deref().unwrap_or("openpgp") {
+            "openpgp" => Ok(Signer::OpenPgp {
+                program: get("gpg.openpgp.program")?
+                    .or(get("gpg.program")?)
+                    .unwrap_or_else(|| "gpg".to_string()),
+                key: get("user.signingKey")?,
+            }),
+            "ssh" => Ok(Signer::Ssh {
+                program: get("gpg.ssh.program")?.unwrap_or_else(|| "ssh-keygen".to_string()),
+                key: path("user.signingKey")?,
+                allowed_signers: path("gpg.ssh.allowedSignersFile")?,
+            }),
+            other => Err(Error::Config(format!(
+                "unsupported gpg.format for attestations: {:?}",
+                other
+            ))),
+        }
+    }
+
+    /// The `signer` line of attestations made with this signer.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Signer::OpenPgp { .. } => "openpgp",
+            Signer::Ssh { .. } => "ssh",
+        }
+    }
+
+    /// Attests `blob`, stored with `header`, as produced by this build.
+    pub fn attest(&self, blob: Oid, header: &BlobHeader) -> Result<Attestation, Error> {
+        let mut attestation = Attestation {
+            blob,
+            header: header.clone(),
+            toolchain: format!("git-ast {}", env!("CARGO_PKG_VERSION")),
+            signer: self.name().to_string(),
+            signature: String::new(),
+        };
+        attestation.signature = self.sign(attestation.payload().as_bytes())?;
+        Ok(attestation)
+    }
+
+    /// An armored detached signature over `payload`.
+    pub fn sign(&self, payload: &[u8]) -> Result<String, Error> {
+        let mut command = match self {
+            Signer::OpenPgp { program, key } => {
+                let mut command = Command::new(program);
+                command.args(["--batch", "--detach-sign", "--armor"]);
+                if let Some(key) = key {
+                    command.args(["--local-user", key]);
+                }
+                command
+            }
+            Signer::Ssh { program, key, .. } => {
+                let key = key.as_ref().ok_or_else(|| {
+                    Error::Config("ssh attestations need user.signingKey".to_string())
+                })?;
+                let mut command = Command::new(program);
+                command.args(["-Y", "sign", "-n", NAMESPACE, "-f"]).arg(key);
+                command
+            }
+        };
+        let output = pipe(&mut command, payload)?;
+        if !output.status.success() {
+            return Err(Error::Driver(format!(
+                "signing failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        String::from_utf8(output.stdout)
+            .map_err(|_| Error::Driver("signature is not text".to_string()))
+    }
+
+    /// Checks the signature of `attestation`, returning who made it.
+    pub fn verify(&self, attestation: &Attestation) -> Result<String, Error> {
+        if attestation.signer != self.name() {
+            return Err(Error::Verification(format!(
+                "signed with {}, but gpg.format is {}",
+                attestation.signer,
+                self.name()
+            )));
+        }
+        let signature = SignatureFile::new(attestation)?;
+        let payload = attestation.payload();
+        match self {
+            Signer::OpenPgp { program, .. } => {
+                let mut command = Command::new(program);
+                command
+                    .args(["--batch", "--status-fd=1", "--verify"])
+                    .arg(&signature.path)
+                    .arg("-");
+                let output = pipe(&mut command, payload.as_bytes())?;
+                let status = String::from_utf8_lossy(&output.stdout);
+                let good = status
+                    .lines()
+                    .find_map(|line| line.strip_prefix("[GNUPG:] GOODSIG "));
+                match (output.status.success(), good) {
+                    (true, Some(signer)) => Ok(signer.to_string()),
+                    _ => Err(Error::Verification("bad OpenPGP signature".to_string())),
+                }
+            }
+            Signer::Ssh {
+                program,
+                allowed_signers,
+                ..
+            } => {
+                let allowed = allowed_signers.as_ref().ok_or_else(|| {
+                    Error::Config(
+                        "verifying ssh attestations needs gpg.ssh.allowedSignersFile".to_string(),
+                    )
+                })?;
+                let output = Command::new(program)
+                    .args(["-Y", "find-principals", "-f"])
+                    .arg(allowed)
+                    .arg("-s")
+                    .arg(&signature.path)
+                    .output()?;
+                let principals = String::from_utf8_lossy(&output.stdout);
+                let Some(principal) = principals
+                    .lines()
+                    .next()
+                    .filter(|_| output.status.success())
+                else {
+                    return Err(Error::Verification(
+                        "no allowed signer made this signature".to_string(),
+                    ));
+                };
+                let mut command = Command::new(program);
+                command
+                    .args(["-Y", "verify", "-n", NAMESPACE, "-f"])
+                    .arg(allowed)
+                    .args(["-I", principal, "-s"])
+                    .arg(&signature.path);
+                match pipe(&mut command, payload.as_bytes())?.status.success() {
+                    true => Ok(principal.to_string()),
+                    false => Err(Error::Verification("bad SSH signature".to_string())),
+                }
+            }
+        }
+    }
+}
+
+/// Outcome of checking one blob.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Check {
+    /// Good signature by the named signer.
+    Valid(String),
+    /// No attestation for the blob.
+    Missing,
+    /// The attestation does not describe the blob, or its signature is bad.
+    Invalid(String),
+}
+
+/// Checks the attestation of `blob`, stored with `header`.
+pub fn check(
+    repo: &Repository,
+    signer: &Signer,
+    blob: Oid,
+    header: &BlobHeader,
+) -> Result<Check, Error> {
+    let Some(text) = notes::read(repo, ATTESTATIONS_REF, blob)? else {
+        return Ok(Check::Missing);
+    };
+    let attestation = match Attestation::parse(&text) {
+        Ok(attestation) => attestation,
+        Err(e) => return Ok(Check::Invalid(e.to_string())),
+    };
+    if attestation.blob != blob {
+        return Ok(Check::Invalid(format!("attests {}", attestation.blob)));
+    }
+    if attestation.header != *header {
+        return Ok(Check::Invalid("header differs from the blob's".to_string()));
+    }
+    match signer.verify(&attestation) {
+        Ok(name) => Ok(Check::Valid(name)),
+        Err(Error::Verification(reason)) => Ok(Check::Invalid(reason)),
+        Err(e) => Err(e),
+    }
+}
+
+/// Saves `attestation` until [`flush`] commits it.
+pub fn record(repo: &Repository, attestation: &Attestation) -> Result<(), Error> {
+    let dir = repo.path().join(PENDING_ATTESTATIONS_DIR);
+    std::fs::create_dir_all(&dir)?;
+    atomic_io::write(
+        dir.join(attestation.blob.to_string()),
+        attestation.to_note(),
+    )?;
+    Ok(())
+}
+
+/// Moves saved attestations into [`ATTESTATIONS_REF`] with one notes
+/// commit, returning how many there were. Records of blobs that were never
+/// committed go along; `git-ast gc` drops them once unreachable.
+pub fn flush(repo: &Repository) -> Result<usize, Error> {
+    let dir = repo.path().join(PENDING_ATTESTATIONS_DIR);
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return Ok(0);
+    };
+    let mut records = BTreeMap::new();
+    for entry in entries {
+        let text = std::fs::read_to_string(entry?.path())?;
+        if let Ok(attestation) = Attestation::parse(&text) {
+            records.insert(attestation.blob, text);
+        }
+    }
+    notes::write(repo, ATTESTATIONS_REF, &records, "git-ast: attestations")?;
+    std::fs::remove_dir_all(&dir)?;
+    log_info!("attestation", "Recorded {} attestation(s)", records.len());
+    Ok(records.len())
+}
+
+/// Runs `command` with `input` on its stdin.
+fn pipe(command: &mut Command, input: &[u8]) -> Result<std::process::Output, Error> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Driver(format!("cannot run {:?}: {}", command.get_program(), e)))?;
+    child.stdin.take().expect("piped").write_all(input)?;
+    Ok(child.wait_with_output()?)
+}
+
+/// The signature of an attestation in a private temporary directory, as
+/// the verifiers want it; removed on drop.
+struct SignatureFile {
+    path: PathBuf,
+    _dir: atomic_io::PrivateDir,
+}
+
+impl SignatureFile {
+    fn new(attestation: &Attestation) -> Result<Self, Error> {
+        let dir = atomic_io::PrivateDir::create("git-ast-sig")?;
+        let name = format!("{}.sig", attestation.blob);
+        let path = dir.write(name, attestation.signature.as_ref())?;
+        Ok(SignatureFile { path, _dir: dir })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_records_and_verifies_ssh_attestations() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = dir.path().join("key");
+        let status = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-C", "t@example.com", "-f"])
+            .arg(&key)
+            .status();
+        if !status.is_ok_and(|s| s.success()) {
+            return; // No ssh-keygen on this machine.
+        }
+        let public = std::fs::read_to_string(dir.path().join("key.pub")).unwrap();
+        let allowed = dir.path().join("allowed_signers");
+        std::fs::write(&allowed, format!("t@example.com {}", public)).unwrap();
+
+        let repo = Repository::init(dir.path().join("repo")).unwrap();
+        let mut config = repo
+            .config()
+            .unwrap()
+            .open_level(git2::ConfigLevel::Local)
+            .unwrap();
+        config.set_str("gpg.format", "ssh").unwrap();
+        config
+            .set_str("user.signingKey", key.to_str().unwrap())
+            .unwrap();
+        config
+            .set_str("gpg.ssh.allowedSignersFile", allowed.to_str().unwrap())
+            .unwrap();
+        let signer = Signer::from_config(&config.snapshot().unwrap()).unwrap();
+
+        let header = BlobHeader::for_provider(crate::languages::by_name("rust").unwrap());
+        let blob = repo.blob(b"not really an AST blob").unwrap();
+        let attestation = signer.attest(blob, &header).unwrap();
+        assert_eq!(
+            Attestation::parse(&attestation.to_note()).unwrap(),
+            attestation
+        );
+
+        assert_eq!(
+            check(&repo, &signer, blob, &header).unwrap(),
+            Check::Missing
+        );
+        record(&repo, &attestation).unwrap();
+        assert_eq!(flush(&repo).unwrap(), 1);
+        assert!(!repo.path().join(PENDING_ATTESTATIONS_DIR).exists());
+        assert_eq!(
+            check(&repo, &signer, blob, &header).unwrap(),
+            Check::Valid("t@example.com".to_string())
+        );
+
+        let mut other = header.clone();
+        other.grammar = "0.0.0".to_string();
+        assert!(matches!(
+            check(&repo, &signer, blob, &other).unwrap(),
+            Check::Invalid(_)
+        ));
+        let mut forged = attestation.clone();
+        forged.toolchain = "git-ast 9.9.9".to_string();
+        notes::write(
+            &repo,
+            ATTESTATIONS_REF,
+            &BTreeMap::from([(blob, forged.to_note())]),
+            "t",
+        )
+        .unwrap();
+        assert!(matches!(
+            check(&repo, &signer, blob, &header).unwrap(),
+            Check::Invalid(_)
+        ));
+    }
+}
@@ -0,0 +1,428 @@
+//! Audit Log
+//!
+//! With `ast.audit = true`, every clean and smudge the filter answers and
+//! every run of the diff and merge drivers appends one JSON line to
+//! `.git/ast-audit/log.jsonl`:
+//!
+//! ```text
+//! {"ts":1760601600.123,"pid":4242,"command":"clean","path":"src/lib.rs","blobs":["5b1c0f..."],"language":"rust","grammar":"0.23.3","duration_ms":1.8,"outcome":"success","version":"0.1.0"}
+//! ```
+//!
+//! `blobs` names the objects involved: the blob a clean produced, the blob a
+//! smudge printed (as Git reports it), the two sides of a diff, and the
+//! base, ours and theirs of a merge. `outcome` is `success` or `error`, with
+//! the message in `error`.
+//!
+//! Lines are written with a single `O_APPEND` write, so concurrent filters
+//! and drivers do not interleave them; nothing in `git-ast` rewrites or
+//! truncates the file. `git-ast audit` prints and filters it, for debugging
+//! a checkout or for compliance reviews; rotate it with ordinary tools.
+//!
+//! [`usage`] condenses it for `git-ast report usage`: time per command, the
+//! slowest files, the paths whose merges conflict most, and how often cleans
+//! fail. All of it stays in the repository; nothing is sent anywhere.
+
+use crate::config;
+use crate::Error;
+use git2::Repository;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Setting that turns the audit log on.
+pub const AUDIT_KEY: &str = "ast.audit";
+
+/// Directory below `.git` holding the log.
+pub const AUDIT_DIR: &str = "ast-audit";
+
+/// One invocation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// Seconds since the Unix epoch, with milliseconds.
+    pub ts: f64,
+    pub pid: u32,
+    /// `clean`, `smudge`, `diff` or `merge`.
+    pub command: String,
+    pub path: String,
+    pub blobs: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grammar: Option<String>,
+    pub duration_ms: f64,
+    /// `success` or `error`.
+    pub outcome: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// `git-ast` version of the process.
+    pub version: String,
+}
+
+impl Record {
+    /// A record of `command` on `path`, starting now.
+    pub fn new(command: &str, path: &str) -> Self {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        Record {
+            ts: (ts * 1000.0).round() / 1000.0,
+            pid: std::process::id(),
+            command: command.to_string(),
+            path: path.to_string(),
+            blobs: Vec::new(),
+            language: None,
+            grammar: None,
+            duration_ms: 0.0,
+            outcome: "success".to_string(),
+            error: None,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    /// Sets the language and grammar version the invocation used.
+    pub fn grammar(mut self, language: &str, grammar: &str) -> Self {
+        self.language = Some(language.to_string());
+        self.grammar = Some(grammar.to_string());
+        self
+    }
+
+    /// Sets the duration since `started`, and the error if the invocation failed.
+    pub fn finish(mut self, started: Instant, error: Option<String>) -> Self {
+        self.duration_ms = (started.elapsed().as_secs_f64() * 1e6).round() / 1e3;
+        if error.is_some() {
+            self.outcome = "error".to_string();
+        }
+        self.error = error;
+        self
+    }
+
+    /// `ts` as `YYYY-MM-DDTHH:MM:SSZ`.
+    pub fn time(&self) -> String {
+        format_time(self.ts)
+    }
+}
+
+/// Seconds since the Unix epoch as `YYYY-MM-DDTHH:MM:SSZ`.
+pub fn format_time(ts: f64) -> String {
+    let secs = ts as i64;
+    let (days, rest) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest / 60 % 60,
+        rest % 60
+    )
+}
+
+/// The audit log of a repository.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    /// The log of `repo`, whether or not auditing is on.
+    pub fn at(repo: &Repository) -> Self {
+        AuditLog {
+            path: repo.path().join(AUDIT_DIR).join("log.jsonl"),
+        }
+    }
+
+    /// The log of `repo` if `ast.audit` is on.
+    pub fn open(repo: &Repository) -> Option<Self> {
+        config::Settings::load(Some(repo))
+            .ok()?
+            .audit
+            .then(|| AuditLog::at(repo))
+    }
+
+    /// Path of the log file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends `record` as one line.
+    pub fn append(&self, record: &Record) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(record)
+            .map_err(|e| Error::Serialization(format!("audit record: {}", e)))?;
+        line.push(b'\n');
+        std::fs::create_dir_all(self.path.parent().expect("below .git"))?;
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(&line)?;
+        Ok(())
+    }
+
+    /// All records, oldest first; lines that do not parse are skipped.
+    pub fn read(&self) -> Result<Vec<Record>, Error> {
+        let file = match std::fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut records = Vec::new();
+        for line in BufReader::new(file).lines() {
+            if let Ok(record) = serde_json::from_str(&line?) {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// Runs a driver invocation on `path`, recording it with the ids `blobs`
+/// returns (asked before the run) if `ast.audit` is on.
+pub fn audited<T>(
+    command: &str,
+    path: &str,
+    blobs: impl FnOnce() -> Vec<String>,
+    run: impl FnOnce() -> Result<T, Error>,
+) -> Result<T, Error> {
+    let repo = Repository::open_from_env().ok();
+    let log = repo.as_ref().and_then(AuditLog::open);
+    let blobs = log.as_ref().map(|_| blobs());
+    let started = Instant::now();
+    let result = run();
+    if let (Some(repo), Some(blobs)) = (&repo, blobs) {
+        let mut entry = Record::new(command, path);
+        entry.blobs = blobs;
+        if let Ok(Some(provider)) = config::language_for_path(Some(repo), path, b"") {
+            entry = entry.grammar(provider.name, provider.grammar_version);
+        }
+        record(
+            log.as_ref(),
+            entry.finish(started, result.as_ref().err().map(ToString::to_string)),
+        );
+    }
+    result
+}
+
+/// Appends `record` to `log`, if auditing is on. An unwritable log is
+/// reported but does not fail the invocation.
+pub fn record(log: Option<&AuditLog>, record: Record) {
+    if let Some(log) = log {
+        if let Err(e) = log.append(&record) {
+            crate::log_error!("audit", "cannot write {}: {}", log.path().display(), e);
+        }
+    }
+}
+
+/// Totals over a set of invocations.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Totals {
+    pub count: usize,
+    pub failed: usize,
+    pub total_ms: f64,
+    pub max_ms: f64,
+}
+
+impl Totals {
+    fn add(&mut self, record: &Record) {
+        self.count += 1;
+        self.failed += usize::from(record.outcome != "success");
+        self.total_ms += record.duration_ms;
+        self.max_ms = self.max_ms.max(record.duration_ms);
+    }
+
+    /// Mean duration in milliseconds.
+    pub fn mean_ms(&self) -> f64 {
+        self.total_ms / self.count.max(1) as f64
+    }
+}
+
+/// A summary of the audit log (see [`usage`]).
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Usage {
+    /// Time of the oldest record.
+    pub since: Option<f64>,
+    /// Per command (`clean`, `smudge`, `diff`, `merge`).
+    pub commands: BTreeMap<String, Totals>,
+    /// Command and path pairs with the longest single invocation, slowest first.
+    pub slowest: Vec<(String, String, Totals)>,
+    /// Paths with failed merges, most first.
+    pub conflicted: Vec<(String, Totals)>,
+}
+
+impl Usage {
+    /// Failed cleans per clean (syntax errors, limits, lint failures).
+    pub fn clean_failure_rate(&self) -> f64 {
+        self.commands
+            .get("clean")
+            .map_or(0.0, |t| t.failed as f64 / t.count.max(1) as f64)
+    }
+}
+
+/// Summarizes `records`, keeping the `top` slowest and most-conflicted paths.
+pub fn usage(records: &[Record], top: usize) -> Usage {
+    let mut usage = Usage {
+        since: records.iter().map(|r| r.ts).reduce(f64::min),
+        ..Usage::default()
+    };
+    let mut per_path: BTreeMap<(String, String), Totals> = BTreeMap::new();
+    let mut merges: BTreeMap<String, Totals> = BTreeMap::new();
+    for record in records {
+        usage
+            .commands
+            .entry(record.command.clone())
+            .or_default()
+            .add(record);
+        per_path
+            .entry((record.command.clone(), record.path.clone()))
+            .or_default()
+            .add(record);
+        if record.command == "merge" {
+            merges.entry(record.path.clone()).or_default().add(record);
+        }
+    }
+    usage.slowest = per_path.into_iter().map(|((c, p), t)| (c, p, t)).collect();
+    usage
+        .slowest
+        .sort_by(|a, b| b.2.max_ms.total_cmp(&a.2.max_ms));
+    usage.slowest.truncate(top);
+    usage.conflicted = merges.into_iter().filter(|(_, t)| t.failed > 0).collect();
+    usage
+        .conflicted
+        .sort_by_key(|(_, t)| std::cmp::Reverse(t.failed));
+    usage.conflicted.truncate(top);
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appends_reads_and_summarizes_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let log = AuditLog::at(&repo);
+        assert!(log.read().unwrap().is_empty());
+
+        let started = Instant::now();
+        let mut clean = Record::new("clean", "a.rs")
+            .grammar("rust", "0.23.3")
+            .finish(started, None);
+        clean.blobs.push("5b1c".to_string());
+        let merge =
+            Record::new("merge", "a.rs").finish(started, Some("2 conflict(s) in a.rs".to_string()));
+        record(Some(&log), clean.clone());
+        record(Some(&log), merge.clone());
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(log.path())
+            .unwrap()
+            .write_all(b"{torn\n")
+            .unwrap();
+
+        let records = log.read().unwrap();
+        assert_eq!(records, vec![clean, merge]);
+        assert_eq!(records[0].outcome, "success");
+        assert_eq!(records[1].outcome, "error");
+
+        let epoch = Record {
+            ts: 1760601600.5,
+            ..records[0].clone()
+        };
+        assert_eq!(epoch.time(), "2025-10-16T08:00:00Z");
+
+        let mut slow = records[0].clone();
+        slow.path = "b.rs".to_string();
+        slow.duration_ms = 90.0;
+        let failed_clean =
+            Record::new("clean", "c.rs").finish(started, Some("parse error".to_string()));
+        let all = [
+            records.clone(),
+            vec![slow, failed_clean, records[1].clone()],
+        ]
+        .concat();
+        let summary = usage(&all, 2);
+        assert_eq!(summary.commands["clean"].count, 3);
+        assert_eq!(summary.commands["merge"].failed, 2);
+        assert_eq!(summary.clean_failure_rate(), 1.0 / 3.0);
+        assert_eq!(summary.slowest.len(), 2);
+        assert_eq!(summary.slowest[0].1, "b.rs");
+        assert_eq!(
+            summary.conflicted,
+            vec![("a.rs".to_string(), summary.commands["merge"].clone())]
+        );
+    }
+
+    #[test]
+    fn usage_totals_per_command_path_and_merge() {
+        let record = |ts: f64, command: &str, path: &str, ms: f64, ok: bool| Record {
+            ts,
+            command: command.to_string(),
+            path: path.to_string(),
+            duration_ms: ms,
+            outcome: if ok { "success" } else { "error" }.to_string(),
+            ..Record::new(command, path)
+        };
+        let records = [
+            record(30.0, "clean", "a.rs", 10.0, true),
+            record(10.0, "clean", "a.rs", 30.0, false),
+            record(20.0, "clean", "b.rs", 20.0, true),
+            record(40.0, "merge", "a.rs", 5.0, false),
+            record(50.0, "merge", "b.rs", 5.0, true),
+            record(60.0, "merge", "c.rs", 1.0, false),
+            record(70.0, "merge", "c.rs", 1.0, false),
+        ];
+        let summary = usage(&records, 2);
+        assert_eq!(summary.since, Some(10.0));
+        let totals = |count, failed, total_ms, max_ms| Totals {
+            count,
+            failed,
+            total_ms,
+            max_ms,
+        };
+        assert_eq!(
+            summary.commands,
+            BTreeMap::from([
+                ("clean".to_string(), totals(3, 1, 60.0, 30.0)),
+                ("merge".to_string(), totals(4, 3, 12.0, 5.0)),
+            ])
+        );
+        assert_eq!(summary.commands["clean"].mean_ms(), 20.0);
+        assert_eq!(summary.clean_failure_rate(), 1.0 / 3.0);
+        assert_eq!(
+            summary.slowest,
+            [
+                (
+                    "clean".to_string(),
+                    "a.rs".to_string(),
+                    totals(2, 1, 40.0, 30.0)
+                ),
+                (
+                    "clean".to_string(),
+                    "b.rs".to_string(),
+                    totals(1, 0, 20.0, 20.0)
+                ),
+            ]
+        );
+        assert_eq!(
+            summary.conflicted,
+            [
+                ("c.rs".to_string(), totals(2, 2, 2.0, 1.0)),
+                ("a.rs".to_string(), totals(1, 1, 5.0, 5.0)),
+            ]
+        );
+        assert_eq!(usage(&[], 2), Usage::default());
+    }
+}
@@ -0,0 +1,19 @@
+//! `git-remote-ast <remote> [<url>]`: Git's remote helper for `ast::<url>`
+//! remotes (see [`git_ast::remote_helper`]).
+
+use git2::Repository;
+use std::error::Error;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (remote, url) = match args.as_slice() {
+        [remote] => (remote.as_str(), remote.as_str()),
+        [remote, url] => (remote.as_str(), url.as_str()),
+        _ => return Err("usage: git-remote-ast <remote> [<url>]".into()),
+    };
+    let repo = Repository::open_from_env()?;
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    git_ast::remote_helper::run(&repo, remote, url, stdin.lock(), stdout.lock())?;
+    Ok(())
+}
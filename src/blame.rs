@@ -0,0 +1,261 @@
+//! Blame Support: Ignoring Format-Only Commits
+//!
+//! Adopting `git-ast` (or changing the canonical pretty-printer) produces
+//! commits that touch many lines without changing any code: every file gets
+//! re-rendered into canonical form. Plain `git blame` attributes those lines
+//! to the migration commit, which destroys the history users care about.
+//!
+//! Git already has the right mechanism (`--ignore-revs-file`,
+//! `blame.ignoreRevsFile`); what is missing is knowing *which* commits are
+//! formatting-only. This module detects them structurally: a commit is
+//! format-only when every file it changes parses to the same token sequence
+//! (kinds and token text, comments included) before and after, i.e. only the
+//! whitespace between tokens moved.
+//!
+//! Projects can widen "formatting" with an `ignore.scm` query pack (see
+//! [`queries`](crate::queries)): tokens inside captured nodes are left out of
+//! the comparison, so e.g. comment-only commits can be skipped too.
+//!
+//! ## Storage
+//!
+//! Detected commits are kept in `.git/ast/format-only-revs`, in the same
+//! format as a `.git-blame-ignore-revs` file (one full commit id per line,
+//! `#` comments), so it can be handed to `git blame` as-is, or exported into
+//! the worktree to share with the team and hosting platforms.
+
+use crate::config;
+use crate::git_plumbing::filters;
+use crate::parsing::{self, ParseLimits};
+use crate::queries::{self, QueryPacks};
+use crate::util::atomic_io;
+use crate::Error;
+use git2::{Commit, Delta, Oid, Repository};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Conventional name of the shared ignore-revs file (understood by GitHub).
+pub const EXPORT_FILE_NAME: &str = ".git-blame-ignore-revs";
+
+/// Location of the auto-generated list inside the Git directory.
+pub fn format_only_revs_path(repo: &Repository) -> PathBuf {
+    repo.path().join("ast").join("format-only-revs")
+}
+
+/// Reads an ignore-revs style file, whose ids must be unabbreviated. A
+/// missing file is an empty list.
+pub fn read_revs(path: &Path) -> Result<BTreeSet<Oid>, Error> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeSet::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut revs = BTreeSet::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        // `Oid::from_str` pads abbreviated ids with zeros; Git wants full ones.
+        if line.len() != 40 || !line.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(Error::Config(format!(
+                "{}: {:?} is not a full commit id",
+                path.display(),
+                line
+            )));
+        }
+        revs.insert(Oid::from_str(line)?);
+    }
+    Ok(revs)
+}
+
+/// Writes an ignore-revs style file with an explanatory header.
+pub fn write_revs(path: &Path, revs: &BTreeSet<Oid>) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut text = String::from(
+        "# Formatting-only commits detected by `git-ast ignore-revs scan`.\n\
+         # Use with `git blame --ignore-revs-file` or `git config blame.ignoreRevsFile`.\n",
+    );
+    for rev in revs {
+        text.push_str(&rev.to_string());
+        text.push('\n');
+    }
+    atomic_io::write(path, text)?;
+    Ok(())
+}
+
+/// Walks `range` (a revision or `A..B`; all of `HEAD` when `None`) and returns
+/// the non-merge commits whose changes are formatting-only.
+pub fn scan(repo: &Repository, range: Option<&str>) -> Result<Vec<Oid>, Error> {
+    let mut walk = repo.revwalk()?;
+    match range {
+        Some(r) if r.contains("..") => walk.push_range(r)?,
+        Some(r) => walk.push(repo.revparse_single(r)?.peel_to_commit()?.id())?,
+        None => walk.push_head()?,
+    }
+    let limits = ParseLimits::default();
+    let mut packs = QueryPacks::for_repo(repo);
+    let mut found = Vec::new();
+    for oid in walk {
+        let commit = repo.find_commit(oid?)?;
+        if is_format_only(repo, &commit, &limits, &mut packs)? {
+            found.push(commit.id());
+        }
+    }
+    Ok(found)
+}
+
+/// Whether `commit` only changes formatting relative to its single parent.
+///
+/// Root commits, merges, empty commits, and commits adding, deleting or
+/// renaming files are never format-only; neither are changes to files without
+/// a registered language, since there is nothing to compare them structurally.
+/// Nodes captured by the language's `ignore` query in `packs` do not count
+/// as changes.
+pub fn is_format_only(
+    repo: &Repository,
+    commit: &Commit<'_>,
+    limits: &ParseLimits,
+    packs: &mut QueryPacks,
+) -> Result<bool, Error> {
+    if commit.parent_count() != 1 {
+        return Ok(false);
+    }
+    let old_tree = commit.parent(0)?.tree()?;
+    let new_tree = commit.tree()?;
+    let diff = repo.diff_tree_to_tree(Some(&old_tree), Some(&new_tree), None)?;
+    if diff.deltas().len() == 0 {
+        return Ok(false);
+    }
+    for delta in diff.deltas() {
+        if delta.status() != Delta::Modified {
+            return Ok(false);
+        }
+        let Some(path) = delta.new_file().path().and_then(Path::to_str) else {
+            return Ok(false);
+        };
+        let old_blob = repo.find_blob(delta.old_file().id())?;
+        let new_blob = repo.find_blob(delta.new_file().id())?;
+        let old_source = filters::perform_smudge(old_blob.content(), path)?;
+        let new_source = filters::perform_smudge(new_blob.content(), path)?;
+        let Some(provider) = config::language_for_path(Some(repo), path, &new_source)? else {
+            return Ok(false);
+        };
+        let (Ok(old), Ok(new)) = (
+            parsing::parse(&old_source, provider, limits),
+            parsing::parse(&new_source, provider, limits),
+        ) else {
+            return Ok(false);
+        };
+        let pack = packs.get(provider)?;
+        let old_ignored = pack.ranges(queries::IGNORE, &old, &old_source);
+        let new_ignored = pack.ranges(queries::IGNORE, &new, &new_source);
+        if parsing::tokens_excluding(&old, &old_source, &old_ignored)
+            != parsing::tokens_excluding(&new, &new_source, &new_ignored)
+        {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Signature;
+
+    /// Commits `files` (path and source) with `parents`.
+    fn commit(repo: &Repository, parents: &[Oid], files: &[(&str, &str)]) -> Oid {
+        let mut builder = repo.treebuilder(None).unwrap();
+        for (path, source) in files {
+            let blob = repo.blob(source.as_bytes()).unwrap();
+            builder.insert(path, blob, 0o100644).unwrap();
+        }
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let signature = Signature::new("t", "t@example.com", &git2::Time::new(0, 0)).unwrap();
+        let parents: Vec<Commit<'_>> = parents
+            .iter()
+            .map(|&id| repo.find_commit(id).unwrap())
+            .collect();
+        let parents: Vec<&Commit<'_>> = parents.iter().collect();
+        repo.commit(None, &signature, &signature, "c", &tree, &parents)
+            .unwrap()
+    }
+
+    #[test]
+    fn detects_only_commits_that_move_whitespace() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let format_only = |id: Oid| {
+            let commit = repo.find_commit(id).unwrap();
+            let mut packs = QueryPacks::for_repo(&repo);
+            is_format_only(&repo, &commit, &ParseLimits::default(), &mut packs).unwrap()
+        };
+        let base = commit(&repo, &[], &[("a.rs", "fn a() -> i32 { 1 }\n")]);
+        let reflowed = commit(&repo, &[base], &[("a.rs", "fn a() -> i32 {\n    1\n}\n")]);
+        let edited = commit(
+            &repo,
+            &[reflowed],
+            &[("a.rs", "fn a() -> i32 {\n    2\n}\n")],
+        );
+        let added = commit(
+            &repo,
+            &[edited],
+            &[
+                ("a.rs", "fn a() -> i32 {\n    2\n}\n"),
+                ("b.rs", "fn b() {}\n"),
+            ],
+        );
+        let deleted = commit(&repo, &[added], &[("a.rs", "fn a() -> i32 {\n    2\n}\n")]);
+        let side = commit(&repo, &[deleted], &[("a.rs", "fn a() -> i32 { 2 }\n")]);
+        let merge = commit(
+            &repo,
+            &[deleted, side],
+            &[("a.rs", "fn a() -> i32 { 2 }\n")],
+        );
+
+        assert!(!format_only(base), "root commit");
+        assert!(format_only(reflowed));
+        assert!(!format_only(edited), "token change");
+        assert!(!format_only(added), "added file");
+        assert!(!format_only(deleted), "deleted file");
+        assert!(format_only(side));
+        assert!(!format_only(merge), "merge commit");
+
+        repo.reference("refs/heads/main", merge, true, "test")
+            .unwrap();
+        repo.set_head("refs/heads/main").unwrap();
+        let mut found = scan(&repo, None).unwrap();
+        found.sort();
+        let mut expected = vec![reflowed, side];
+        expected.sort();
+        assert_eq!(found, expected);
+        assert_eq!(
+            scan(&repo, Some(&format!("{}..{}", edited, deleted))).unwrap(),
+            []
+        );
+    }
+
+    #[test]
+    fn reads_only_full_commit_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("revs");
+        let id = "0123456789abcdef0123456789abcdef01234567";
+        fs::write(&path, format!("# header\n{}  # reformat\n\n", id)).unwrap();
+        assert_eq!(
+            read_revs(&path).unwrap().into_iter().collect::<Vec<_>>(),
+            [Oid::from_str(id).unwrap()]
+        );
+        assert!(read_revs(&dir.path().join("missing")).unwrap().is_empty());
+
+        fs::write(&path, "0123456\n").unwrap();
+        let error = read_revs(&path).unwrap_err().to_string();
+        assert!(
+            error.contains("\"0123456\" is not a full commit id"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,339 @@
+//! Offline Bundles
+//!
+//! Air-gapped machines cannot fetch crates, so `git-ast setup-toolchain`
+//! cannot build the release a project pins there (see
+//! [`toolchain`](crate::toolchain)). A bundle is a single file, written on a
+//! connected machine by `git-ast bundle create`, with everything a
+//! repository needs from outside its history:
+//!
+//! -   The toolchain: the `git-ast` executable the project's `.git-ast.lock`
+//!     asks for (grammars are compiled into it), for one platform.
+//! -   The project files that decide what the toolchain does: `.git-ast.lock`,
+//!     the root `.git-ast.toml` (normalization profiles and other project
+//!     settings) and the query packs under `.git-ast/queries/`.
+//!
+//! `git-ast bundle install` puts the toolchain into the per-user cache,
+//! where `setup-toolchain` finds it without building anything, and restores
+//! project files the worktree lacks. Files the worktree has with other
+//! content are kept (and reported) unless `--force` is given.
+//!
+//! ## Format
+//!
+//! A `GASB` magic and a format version, the zstd-compressed body, and a
+//! 20-byte checksum (the Git blob hash of everything before it). The body
+//! holds the toolchain's version, platform and executable, then the project
+//! files as path and content pairs; strings are prefixed with their length
+//! as a big-endian `u16`, contents and the file count with a `u64`.
+
+use crate::config::REPO_CONFIG_FILE;
+use crate::lockfile::LOCKFILE;
+use crate::queries::QUERY_DIR;
+use crate::toolchain::{self, Plan, Requirements};
+use crate::util::atomic_io;
+use crate::Error;
+use git2::{ObjectType, Oid, Repository};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+const MAGIC: &[u8; 4] = b"GASB";
+const FORMAT_VERSION: u32 = 1;
+/// zstd level; higher levels take minutes on an executable for little gain.
+const LEVEL: i32 = 9;
+
+/// The contents of a bundle file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bundle {
+    /// `git-ast` release of the toolchain.
+    pub version: String,
+    /// Platform the toolchain runs on (see [`platform`]).
+    pub platform: String,
+    /// The `git-ast` executable.
+    pub toolchain: Vec<u8>,
+    /// Project files by worktree path.
+    pub files: BTreeMap<String, Vec<u8>>,
+}
+
+/// What [`Bundle::install`] did.
+#[derive(Debug, Default)]
+pub struct InstallReport {
+    /// Where the toolchain now is.
+    pub toolchain: PathBuf,
+    /// Project files written into the worktree.
+    pub written: Vec<String>,
+    /// Project files the worktree has with other content, left alone.
+    pub kept: Vec<String>,
+}
+
+/// The platform this build runs on, e.g. `linux-x86_64`.
+pub fn platform() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+impl Bundle {
+    /// Collects what `repo` needs: the toolchain its lockfile asks for (this
+    /// binary, or one `setup-toolchain` built) and its project files.
+    pub fn create(repo: &Repository) -> Result<Self, Error> {
+        let (version, exe) = match toolchain::plan(&Requirements::load(repo)?)? {
+            Plan::Current => (
+                env!("CARGO_PKG_VERSION").to_string(),
+                std::env::current_exe()?,
+            ),
+            Plan::Install(version) => {
+                let exe = toolchain::cache_dir()
+                    .map(|cache| toolchain::toolchain_exe(&cache, &version))
+                    .filter(|exe| exe.is_file())
+                    .ok_or_else(|| {
+                        Error::Config(format!(
+                            "git-ast {} is not installed; run `git-ast setup-toolchain` first",
+                            version
+                        ))
+                    })?;
+                (version, exe)
+            }
+        };
+        let workdir = repo
+            .workdir()
+            .ok_or_else(|| Error::Config("git-ast bundle needs a working tree".to_string()))?;
+        let mut files = BTreeMap::new();
+        for path in [LOCKFILE, REPO_CONFIG_FILE] {
+            if let Some(content) = read_optional(&workdir.join(path))? {
+                files.insert(path.to_string(), content);
+            }
+        }
+        collect_queries(workdir, QUERY_DIR, &mut files)?;
+        Ok(Bundle {
+            version,
+            platform: platform(),
+            toolchain: fs::read(exe)?,
+            files,
+        })
+    }
+
+    /// The bundle file's bytes.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        let mut body = Vec::new();
+        put_str(&mut body, &self.version);
+        put_str(&mut body, &self.platform);
+        put_bytes(&mut body, &self.toolchain);
+        body.extend((self.files.len() as u64).to_be_bytes());
+        for (path, content) in &self.files {
+            put_str(&mut body, path);
+            put_bytes(&mut body, content);
+        }
+        let mut out = MAGIC.to_vec();
+        out.extend(FORMAT_VERSION.to_be_bytes());
+        out.extend(zstd::stream::encode_all(&body[..], LEVEL)?);
+        let checksum = Oid::hash_object(ObjectType::Blob, &out)?;
+        out.extend(checksum.as_bytes());
+        Ok(out)
+    }
+
+    /// Reads a bundle file, checking its checksum and every path in it.
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < 28 || &bytes[..4] != MAGIC {
+            return Err(corrupt("not a git-ast bundle"));
+        }
+        let (sealed, checksum) = bytes.split_at(bytes.len() - 20);
+        if Oid::hash_object(ObjectType::Blob, sealed)?.as_bytes() != checksum {
+            return Err(corrupt("checksum mismatch"));
+        }
+        let version = u32::from_be_bytes(sealed[4..8].try_into().unwrap());
+        if version != FORMAT_VERSION {
+            return Err(corrupt(&format!("unsupported format version {}", version)));
+        }
+        let body = zstd::stream::decode_all(&sealed[8..])?;
+        let mut input = &body[..];
+        let mut bundle = Bundle {
+            version: take_str(&mut input)?,
+            platform: take_str(&mut input)?,
+            toolchain: take_bytes(&mut input)?.to_vec(),
+            files: BTreeMap::new(),
+        };
+        toolchain::validate_version(&bundle.version)?;
+        for _ in 0..take_u64(&mut input)? {
+            let path = take_str(&mut input)?;
+            if !is_project_file(&path) {
+                return Err(corrupt(&format!("unexpected path {:?}", path)));
+            }
+            bundle.files.insert(path, take_bytes(&mut input)?.to_vec());
+        }
+        match input.is_empty() {
+            true => Ok(bundle),
+            false => Err(corrupt("trailing bytes")),
+        }
+    }
+
+    /// Installs the toolchain under `cache` and, with a worktree, the
+    /// project files (see the module documentation).
+    pub fn install(
+        &self,
+        workdir: Option<&Path>,
+        cache: &Path,
+        force: bool,
+    ) -> Result<InstallReport, Error> {
+        if self.platform != platform() {
+            return Err(Error::Config(format!(
+                "the bundle's toolchain is for {}, not {}",
+                self.platform,
+                platform()
+            )));
+        }
+        let exe = toolchain::toolchain_exe(cache, &self.version);
+        if read_optional(&exe)?.as_deref() != Some(&self.toolchain[..]) {
+            atomic_io::write(&exe, &self.toolchain)?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&exe, fs::Permissions::from_mode(0o755))?;
+            }
+        }
+        let mut report = InstallReport {
+            toolchain: exe,
+            ..InstallReport::default()
+        };
+        let Some(workdir) = workdir else {
+            return Ok(report);
+        };
+        for (path, content) in &self.files {
+            let target = workdir.join(path);
+            match read_optional(&target)? {
+                Some(existing) if existing == *content => {}
+                Some(_) if !force => report.kept.push(path.clone()),
+                _ => {
+                    atomic_io::write(&target, content)?;
+                    report.written.push(path.clone());
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// Adds the `.scm` files under `dir` (worktree-relative) to `files`.
+fn collect_queries(
+    workdir: &Path,
+    dir: &str,
+    files: &mut BTreeMap<String, Vec<u8>>,
+) -> Result<(), Error> {
+    let entries = match fs::read_dir(workdir.join(dir)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let path = format!("{}/{}", dir, name);
+        if entry.file_type()?.is_dir() {
+            collect_queries(workdir, &path, files)?;
+        } else if is_project_file(&path) {
+            files.insert(path, fs::read(entry.path())?);
+        }
+    }
+    Ok(())
+}
+
+/// Whether a bundle may carry `path`: it decides what only a bundle's
+/// contents may write into a worktree.
+fn is_project_file(path: &str) -> bool {
+    let relative = Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)));
+    relative
+        && (path == LOCKFILE
+            || path == REPO_CONFIG_FILE
+            || (path.starts_with(&format!("{}/", QUERY_DIR)) && path.ends_with(".scm")))
+}
+
+fn read_optional(path: &Path) -> Result<Option<Vec<u8>>, Error> {
+    match fs::read(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn put_str(out: &mut Vec<u8>, text: &str) {
+    out.extend((text.len() as u16).to_be_bytes());
+    out.extend(text.as_bytes());
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend((bytes.len() as u64).to_be_bytes());
+    out.extend(bytes);
+}
+
+fn take<'b>(input: &mut &'b [u8], n: usize) -> Result<&'b [u8], Error> {
+    if input.len() < n {
+        return Err(corrupt("truncated"));
+    }
+    let (head, rest) = input.split_at(n);
+    *input = rest;
+    Ok(head)
+}
+
+fn take_u64(input: &mut &[u8]) -> Result<u64, Error> {
+    Ok(u64::from_be_bytes(take(input, 8)?.try_into().unwrap()))
+}
+
+fn take_bytes<'b>(input: &mut &'b [u8]) -> Result<&'b [u8], Error> {
+    let len = usize::try_from(take_u64(input)?).map_err(|_| corrupt("truncated"))?;
+    take(input, len)
+}
+
+fn take_str(input: &mut &[u8]) -> Result<String, Error> {
+    let len = u16::from_be_bytes(take(input, 2)?.try_into().unwrap());
+    String::from_utf8(take(input, len as usize)?.to_vec()).map_err(|_| corrupt("invalid UTF-8"))
+}
+
+fn corrupt(what: &str) -> Error {
+    Error::Parsing(format!("git-ast bundle: {}", what))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_installs_missing_files() {
+        let bundle = Bundle {
+            version: "0.0.9".to_string(),
+            platform: platform(),
+            toolchain: b"#!/bin/sh\n".to_vec(),
+            files: [
+                (LOCKFILE.to_string(), b"git-ast = \"0.0.9\"\n".to_vec()),
+                (
+                    format!("{}/rust/ignore.scm", QUERY_DIR),
+                    b"(line_comment) @ignore\n".to_vec(),
+                ),
+            ]
+            .into(),
+        };
+        let mut bytes = bundle.encode().unwrap();
+        assert_eq!(Bundle::decode(&bytes).unwrap(), bundle);
+        bytes[10] ^= 1;
+        assert!(Bundle::decode(&bytes).is_err());
+        assert!(!is_project_file(".git-ast/queries/../../hooks/x.scm"));
+        assert!(!is_project_file(".git/config"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let (workdir, cache) = (dir.path().join("repo"), dir.path().join("cache"));
+        fs::create_dir(&workdir).unwrap();
+        fs::write(workdir.join(LOCKFILE), "ours\n").unwrap();
+        let report = bundle.install(Some(&workdir), &cache, false).unwrap();
+        assert_eq!(report.toolchain, toolchain::toolchain_exe(&cache, "0.0.9"));
+        assert_eq!(fs::read(&report.toolchain).unwrap(), bundle.toolchain);
+        assert_eq!(report.kept, [LOCKFILE]);
+        assert_eq!(report.written, [".git-ast/queries/rust/ignore.scm"]);
+        assert_eq!(
+            fs::read_to_string(workdir.join(LOCKFILE)).unwrap(),
+            "ours\n"
+        );
+
+        let report = bundle.install(Some(&workdir), &cache, true).unwrap();
+        assert_eq!(report.written, [LOCKFILE]);
+    }
+}
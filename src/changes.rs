@@ -0,0 +1,435 @@
+//! Changed Files
+//!
+//! What the diff driver renders for one file Git hands it, the commands
+//! comparing whole trees render for every file that differs: a
+//! [`FileChange`] holds both sides of a file in canonical form, and
+//! [`FileChange::render`] turns it into the driver's output, in either
+//! [`DiffFormat`]. So `git diff`, `git-ast index diff`, `git-ast show` and
+//! `git-ast diff` print the same thing for the same change.
+//!
+//! ## Canonical Sides
+//!
+//! [`canonical`] prints AST blobs; plain source (the working tree, a blob
+//! stored before the file was adopted) is put through a clean and smudge,
+//! so both sides compare without formatting. Content that does not parse is
+//! compared as it is. Symlink targets and submodule commits are never
+//! cleaned, so never printed either.
+//!
+//! ## Renames
+//!
+//! A file Git pairs with another path (a rename, or a copy) is headed by
+//! `rename from`/`rename to` (or `copy`) lines and a `similarity index`
+//! counted in syntax tokens (see [`fingerprint::similarity`]), so a file
+//! moved and reformatted still scores 100%. A deleted file and an added
+//! one that are identical in canonical form are paired even where Git,
+//! comparing bytes, did not (see [`pair_identical`]).
+//!
+//! ## Items
+//!
+//! [`FileChange::items`] lists the top-level items (functions, classes,
+//! imports, ...) that differ, the way [`staging::hunks`] splits a file for
+//! partial staging, for summaries above the diffs, and which of them were
+//! modified rather than removed and added.
+
+use crate::ast::{self, Node};
+use crate::config;
+use crate::diff_backend::{self, DiffBackend, DiffFormat, Rename};
+use crate::fingerprint;
+use crate::git_plumbing::filters;
+use crate::languages;
+use crate::matching;
+use crate::parsing;
+use crate::serialization;
+use crate::staging::{self, Hunk};
+use crate::util::parallel;
+use crate::{log_info, Error};
+use git2::{Delta, DiffFindOptions, DiffOptions, Oid, Repository, Tree};
+
+/// Mode of a submodule entry.
+const GITLINK: &str = "160000";
+
+/// A file that differs between two versions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChange {
+    /// Its path on the new side (for a deletion, on the old side).
+    pub path: String,
+    /// The old path of a rename or copy, and whether it was a copy.
+    pub from: Option<(String, bool)>,
+    /// Each side's mode (`100644`), or `None` where the file does not exist.
+    pub old_mode: Option<String>,
+    pub new_mode: Option<String>,
+    /// Each side in canonical form; empty where the file does not exist.
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
+}
+
+impl FileChange {
+    /// The path on the old side.
+    pub fn old_path(&self) -> &str {
+        self.from.as_ref().map_or(&self.path, |(from, _)| from)
+    }
+
+    /// `A`, `D`, `M`, `T` or `R`/`C` with the similarity, as in
+    /// `git diff --name-status`.
+    pub fn status(&self, rename: Option<&Rename<'_>>) -> String {
+        match (rename, &self.old_mode, &self.new_mode) {
+            (Some(rename), ..) if rename.copy => format!("C{:03}", rename.similarity),
+            (Some(rename), ..) => format!("R{:03}", rename.similarity),
+            (_, None, _) => "A".to_string(),
+            (_, _, None) => "D".to_string(),
+            (_, Some(old), Some(new)) if old[..3] != new[..3] => "T".to_string(),
+            _ => "M".to_string(),
+        }
+    }
+
+    /// The rename or copy this change is, with its structural similarity.
+    pub fn rename(&self, repo: Option<&Repository>) -> Result<Option<Rename<'_>>, Error> {
+        let Some((from, copy)) = &self.from else {
+            return Ok(None);
+        };
+        let limits = config::Settings::load(repo)?.parse_limits;
+        let provider = match repo {
+            Some(repo) => config::language_for_path(Some(repo), &self.path, &self.new)?,
+            None => languages::detect(&self.path, &self.new),
+        };
+        Ok(Some(Rename {
+            from,
+            copy: *copy,
+            similarity: fingerprint::similarity(provider, &self.old, &self.new, &limits),
+        }))
+    }
+
+    /// The backend `.git-ast.toml` configures for the file; the native line
+    /// diff for symlinks, submodules and outside a repository.
+    pub fn backend(&self, repo: Option<&Repository>) -> Result<Box<dyn DiffBackend>, Error> {
+        let regular = [&self.old_mode, &self.new_mode]
+            .into_iter()
+            .flatten()
+            .all(|mode| is_regular(mode));
+        match repo {
+            Some(repo) if regular => diff_backend::for_path(repo, &self.path, &self.new),
+            _ => Ok(Box::new(diff_backend::NativeBackend)),
+        }
+    }
+
+    /// Renders the change as the diff driver does.
+    pub fn render(&self, repo: Option<&Repository>, format: DiffFormat) -> Result<Vec<u8>, Error> {
+        let rename = self.rename(repo)?;
+        let backend = self.backend(repo)?;
+        self.render_with(rename.as_ref(), backend.as_ref(), format)
+    }
+
+    /// [`render`](Self::render) with the rename and backend already known.
+    pub fn render_with(
+        &self,
+        rename: Option<&Rename<'_>>,
+        backend: &dyn DiffBackend,
+        format: DiffFormat,
+    ) -> Result<Vec<u8>, Error> {
+        let (old_mode, new_mode) = (self.old_mode.as_deref(), self.new_mode.as_deref());
+        match format {
+            DiffFormat::Readable => {
+                let mut diff = rename.map(|r| r.lines(&self.path)).unwrap_or_default();
+                diff.push_str(&diff_backend::mode_lines(old_mode, new_mode));
+                let mut diff = diff.into_bytes();
+                if self.old != self.new {
+                    diff.extend(backend.diff(&self.path, &self.old, &self.new)?);
+                }
+                Ok(diff)
+            }
+            DiffFormat::Patch => Ok(diff_backend::patch(
+                &self.path, rename, old_mode, new_mode, &self.old, &self.new,
+            )),
+        }
+    }
+
+    /// Both sides' trees and the top-level items that differ; `None` if the
+    /// file has no language or a side does not parse.
+    pub fn items(&self, repo: Option<&Repository>) -> Option<Items> {
+        let provider = match repo {
+            Some(repo) => config::language_for_path(Some(repo), &self.path, &self.new)
+                .ok()
+                .flatten(),
+            None => languages::detect(&self.path, &self.new),
+        }?;
+        let limits = config::Settings::load(repo).ok()?.parse_limits;
+        let tree = |source: &[u8]| {
+            let tree = parsing::parse(source, provider, &limits).ok()?;
+            ast::from_tree(&tree, source, provider).ok()
+        };
+        let (old, new) = (tree(&self.old)?, tree(&self.new)?);
+        let hunks = staging::hunks(&old, &new);
+        let diff = match repo {
+            Some(repo) => config::repo_config_for(repo, &self.path).ok()?.diff,
+            None => config::DiffConfig::default(),
+        };
+        let threshold = |kind: &str| diff.similarity_for(Some(provider.name), kind);
+        let mut modified = Vec::new();
+        for hunk in &hunks {
+            let (a, b) = (
+                &old.children[hunk.index.clone()],
+                &new.children[hunk.worktree.clone()],
+            );
+            for (i, j) in matching::modified(a, b, &threshold) {
+                modified.push((hunk.index.start + i, hunk.worktree.start + j));
+            }
+        }
+        Some(Items {
+            old,
+            new,
+            hunks,
+            modified,
+        })
+    }
+}
+
+/// The trees of a [`FileChange`]'s two sides, and the runs of top-level
+/// items that differ between them.
+#[derive(Debug, Clone)]
+pub struct Items {
+    pub old: Node,
+    pub new: Node,
+    pub hunks: Vec<Hunk>,
+    /// Old and new items (by index) that are one item, modified (see
+    /// [`matching`](crate::matching#modified-items)); the hunks' other items
+    /// were removed or added.
+    pub modified: Vec<(usize, usize)>,
+}
+
+impl Items {
+    /// The old and the new items of `hunk`.
+    pub fn sides(&self, hunk: &Hunk) -> (&[Node], &[Node]) {
+        (
+            &self.old.children[hunk.index.clone()],
+            &self.new.children[hunk.worktree.clone()],
+        )
+    }
+}
+
+/// Whether `mode` is a regular file's (as opposed to a symlink's or a
+/// submodule's).
+pub fn is_regular(mode: &str) -> bool {
+    mode.starts_with("100")
+}
+
+/// `content` of `path` in canonical form (see [Canonical Sides](self#canonical-sides)).
+pub fn canonical(
+    repo: Option<&Repository>,
+    path: &str,
+    mode: &str,
+    content: Vec<u8>,
+) -> Result<Vec<u8>, Error> {
+    if !is_regular(mode) {
+        return Ok(content);
+    }
+    if serialization::is_ast_blob(&content) {
+        return filters::perform_smudge(&content, path);
+    }
+    match (repo, content.is_empty()) {
+        (Some(repo), false) => match canonical_source(repo, path, &content) {
+            Ok(printed) => Ok(printed),
+            Err(e) => {
+                log_info!("driver", "Comparing {} as it is: {}", path, e);
+                Ok(content)
+            }
+        },
+        _ => Ok(content),
+    }
+}
+
+/// Plain source as a round trip through the filter would print it.
+fn canonical_source(repo: &Repository, path: &str, content: &[u8]) -> Result<Vec<u8>, Error> {
+    let limits = config::Settings::load(Some(repo))?.parse_limits;
+    let repo_config = config::repo_config_for(repo, path)?;
+    let provider = config::language_for_path(Some(repo), path, content)?;
+    let blob = filters::clean_with(content, path, provider, &limits, &repo_config)?;
+    filters::perform_smudge(&blob, path)
+}
+
+/// The files that differ between two trees (`None` for an empty one),
+/// limited to `paths` if any are given, with renames detected.
+pub fn between(
+    repo: &Repository,
+    old: Option<&Tree<'_>>,
+    new: Option<&Tree<'_>>,
+    paths: &[String],
+) -> Result<Vec<FileChange>, Error> {
+    let mut options = DiffOptions::new();
+    for path in paths {
+        options.pathspec(path);
+    }
+    let mut diff = repo.diff_tree_to_tree(old, new, Some(&mut options))?;
+    diff.find_similar(Some(DiffFindOptions::new().renames(true)))?;
+    from_diff(repo, &diff)
+}
+
+/// The files of a Git diff, read from the object database, or for a diff
+/// against the working tree, from there. Sides are put in canonical form in
+/// parallel, and deleted and added files found identical are then paired
+/// (see [`pair_identical`]).
+pub fn from_diff(repo: &Repository, diff: &git2::Diff<'_>) -> Result<Vec<FileChange>, Error> {
+    let mut entries = Vec::new();
+    for delta in diff.deltas() {
+        let (old_file, new_file) = (delta.old_file(), delta.new_file());
+        let path_of = |file: &git2::DiffFile<'_>| {
+            file.path()
+                .map(|p| p.to_string_lossy().replace('\\', "/"))
+                .unwrap_or_default()
+        };
+        let (old_path, new_path) = (path_of(&old_file), path_of(&new_file));
+        let path = match delta.status() {
+            Delta::Deleted => old_path.clone(),
+            _ => new_path,
+        };
+        let from = match delta.status() {
+            Delta::Renamed => Some((old_path.clone(), false)),
+            Delta::Copied => Some((old_path.clone(), true)),
+            _ => None,
+        };
+        let mode = |file: &git2::DiffFile<'_>| format!("{:o}", u32::from(file.mode()));
+        let change = FileChange {
+            old_mode: (delta.status() != Delta::Added).then(|| mode(&old_file)),
+            new_mode: (delta.status() != Delta::Deleted).then(|| mode(&new_file)),
+            path,
+            from,
+            old: Vec::new(),
+            new: Vec::new(),
+        };
+        entries.push((change, old_path, old_file.id(), new_file.id()));
+    }
+    let changes = parallel::map(
+        repo,
+        &entries,
+        |repo, (change, old_path, old_id, new_id)| {
+            let side = |id: Oid, mode: &Option<String>, path: &str| {
+                let Some(mode) = mode else {
+                    return Ok(Vec::new());
+                };
+                let content = match mode.as_str() {
+                    GITLINK => format!("Subproject commit {}\n", id).into_bytes(),
+                    _ => match repo.find_blob(id) {
+                        Ok(blob) => blob.content().to_vec(),
+                        // A working-tree side: its content is not an object.
+                        Err(e) => worktree_file(repo, path, mode).ok_or(e)?,
+                    },
+                };
+                canonical(Some(repo), path, mode, content)
+            };
+            Ok(FileChange {
+                old: side(*old_id, &change.old_mode, old_path)?,
+                new: side(*new_id, &change.new_mode, &change.path)?,
+                ..change.clone()
+            })
+        },
+    )?;
+    Ok(pair_identical(changes))
+}
+
+/// `changes` with each added file whose canonical content is that of a
+/// deleted one (the first in `changes`) turned into a rename of it.
+///
+/// Git pairs files by their bytes. A file renamed in the working tree and
+/// reformatted there, or renamed in the commit that adopted `git-ast` (a
+/// source blob on one side, an AST blob on the other), differs byte for
+/// byte from its old self while its tree is the same; Git lists a deletion
+/// and an addition, and this puts them back together.
+pub fn pair_identical(changes: Vec<FileChange>) -> Vec<FileChange> {
+    let pairable = |mode: &Option<String>, content: &[u8]| {
+        mode.as_deref().is_some_and(is_regular) && !content.is_empty()
+    };
+    let mut twins: Vec<Option<usize>> = vec![None; changes.len()];
+    let mut paired = vec![false; changes.len()];
+    for (i, added) in changes.iter().enumerate() {
+        if added.old_mode.is_some() || !pairable(&added.new_mode, &added.new) {
+            continue;
+        }
+        let twin = changes.iter().zip(&paired).position(|(deleted, &paired)| {
+            !paired
+                && deleted.new_mode.is_none()
+                && pairable(&deleted.old_mode, &deleted.old)
+                && deleted.old == added.new
+        });
+        if let Some(j) = twin {
+            twins[i] = Some(j);
+            paired[j] = true;
+        }
+    }
+    let mut out = Vec::new();
+    for (i, change) in changes.iter().enumerate() {
+        match twins[i] {
+            Some(j) => out.push(FileChange {
+                from: Some((changes[j].path.clone(), false)),
+                old_mode: changes[j].old_mode.clone(),
+                old: changes[j].old.clone(),
+                ..change.clone()
+            }),
+            None if !paired[i] => out.push(change.clone()),
+            None => {}
+        }
+    }
+    out
+}
+
+/// The working-tree content of `path`, or its target for a symlink.
+fn worktree_file(repo: &Repository, path: &str, mode: &str) -> Option<Vec<u8>> {
+    let file = repo.workdir()?.join(path);
+    match mode {
+        "120000" => Some(
+            std::fs::read_link(file)
+                .ok()?
+                .to_string_lossy()
+                .into_owned()
+                .into_bytes(),
+        ),
+        _ => std::fs::read(file).ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_renamed_files_with_their_changed_items() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let limits = parsing::ParseLimits::default();
+        let rust = languages::by_name("rust");
+        let tree = |files: &[(&str, &str)]| {
+            let mut builder = repo.treebuilder(None).unwrap();
+            for (name, source) in files {
+                let blob = filters::clean_as(source.as_bytes(), name, rust, &limits).unwrap();
+                builder
+                    .insert(name, repo.blob(&blob).unwrap(), 0o100644)
+                    .unwrap();
+            }
+            repo.find_tree(builder.write().unwrap()).unwrap()
+        };
+        let body = "fn one() {\n    1;\n    2;\n    3;\n}\n\nfn two() {}\n";
+        let old = tree(&[("a.rs", body)]);
+        let new = tree(&[("b.rs", &body.replace("two", "three"))]);
+
+        let changes = between(&repo, Some(&old), Some(&new), &[]).unwrap();
+        assert_eq!(changes.len(), 1);
+        let change = &changes[0];
+        assert_eq!((change.old_path(), change.path.as_str()), ("a.rs", "b.rs"));
+        let rename = change.rename(Some(&repo)).unwrap().unwrap();
+        assert_eq!(change.status(Some(&rename)), "R094");
+        let items = change.items(Some(&repo)).unwrap();
+        assert_eq!(items.hunks.len(), 1);
+        assert_eq!(items.hunks[0].names, ["two", "three"]);
+
+        let patch = change.render(Some(&repo), DiffFormat::Patch).unwrap();
+        let patch = String::from_utf8(patch).unwrap();
+        assert!(
+            patch.starts_with("diff --git a/a.rs b/b.rs\nsimilarity index 94%\nrename from a.rs\n"),
+            "{}",
+            patch
+        );
+        assert!(
+            patch.contains("-fn two() {}\n+fn three() {}\n"),
+            "{}",
+            patch
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! Code Metrics
+//!
+//! Size and complexity figures read off the syntax tree, per file and per
+//! definition (see [`symbols`](crate::symbols)), for dashboards:
+//!
+//! -   **`lines`:** lines the definition spans, comments included.
+//! -   **`params`:** named children of its `parameters` field (0 without one).
+//! -   **`depth`:** deepest nesting of the language's block kinds (the
+//!     provider's [`blocks`](crate::languages::SyntaxRules::blocks)) below
+//!     its body; a function without nested blocks has depth 0.
+//! -   **`cyclomatic`:** 1 plus the number of branch points in it: the
+//!     conditional, loop, `case`/match-arm and `catch` kinds of
+//!     [`BRANCH_KINDS`] and the short-circuit operators. This approximates
+//!     McCabe's number without a control-flow graph; nested definitions
+//!     count towards their parents too.
+//!
+//! A file's `depth` and `cyclomatic` are the largest of its definitions'.
+
+use crate::languages::LanguageProvider;
+use crate::parsing::ParseLimits;
+use crate::queries::QueryPacks;
+use crate::symbols;
+use crate::Error;
+use git2::Repository;
+use serde::Serialize;
+use tree_sitter::Node as TsNode;
+
+/// Node kinds that branch, across the supported grammars.
+pub const BRANCH_KINDS: &[&str] = &[
+    "if_statement",
+    "if_expression",
+    "elif_clause",
+    "if",
+    "elsif",
+    "unless",
+    "if_modifier",
+    "unless_modifier",
+    "while_statement",
+    "while_expression",
+    "while",
+    "while_modifier",
+    "until",
+    "for_statement",
+    "for_expression",
+    "for_in_statement",
+    "foreach_statement",
+    "enhanced_for_statement",
+    "for",
+    "do_statement",
+    "loop_expression",
+    "match_arm",
+    "switch_case",
+    "switch_label",
+    "switch_section",
+    "case_clause",
+    "case_statement",
+    "when",
+    "catch_clause",
+    "except_clause",
+    "rescue",
+    "conditional_expression",
+    "ternary_expression",
+    "conditional",
+];
+
+/// Short-circuit operator tokens.
+const BRANCH_OPERATORS: &[&str] = &["&&", "||", "and", "or", "??"];
+
+/// Figures for one definition.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SymbolMetrics {
+    pub kind: String,
+    pub name: String,
+    pub line: usize,
+    pub lines: usize,
+    pub params: usize,
+    pub depth: usize,
+    pub cyclomatic: usize,
+}
+
+/// Figures for one file and its definitions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileMetrics {
+    pub path: String,
+    pub language: &'static str,
+    pub lines: usize,
+    pub depth: usize,
+    pub cyclomatic: usize,
+    pub symbols: Vec<SymbolMetrics>,
+}
+
+/// The metrics of the files of `rev` under any of `paths` (all when empty).
+pub fn measure(
+    repo: &Repository,
+    rev: &str,
+    paths: &[String],
+    limits: &ParseLimits,
+    packs: &mut QueryPacks,
+) -> Result<Vec<FileMetrics>, Error> {
+    let mut files = Vec::new();
+    for (path, id) in symbols::files(repo, rev)? {
+        if !paths.is_empty()
+            && !paths
+                .iter()
+                .any(|p| path.starts_with(p.trim_start_matches("./")))
+        {
+            continue;
+        }
+        let Some((provider, source, tree)) = symbols::parse_blob(repo, id, &path, limits)? else {
+            continue;
+        };
+        let pack = packs.get(provider)?;
+        let mut symbols: Vec<SymbolMetrics> =
+            symbols::definition_nodes(&tree, &source, &path, provider, pack)
+                .into_iter()
+                .map(|(symbol, node)| SymbolMetrics {
+                    kind: symbol.kind,
+                    name: symbol.name,
+                    line: node.start_position().row + 1,
+                    lines: node.end_position().row - node.start_position().row + 1,
+                    params: params(node, provider),
+                    depth: depth(node, provider).saturating_sub(1),
+                    cyclomatic: 1 + branches(node),
+                })
+                .collect();
+        symbols.sort_by_key(|s| s.line);
+        files.push(FileMetrics {
+            path,
+            language: provider.name,
+            lines: source.split(|&b| b == b'\n').count() - usize::from(source.ends_with(b"\n")),
+            depth: symbols.iter().map(|s| s.depth).max().unwrap_or(0),
+            cyclomatic: symbols.iter().map(|s| s.cyclomatic).max().unwrap_or(0),
+            symbols,
+        });
+    }
+    Ok(files)
+}
+
+/// Named, non-comment children of `node`'s `parameters` field.
+fn params(node: TsNode<'_>, provider: &LanguageProvider) -> usize {
+    let Some(parameters) = node.child_by_field_name("parameters") else {
+        return 0;
+    };
+    let mut cursor = parameters.walk();
+    parameters
+        .named_children(&mut cursor)
+        .filter(|p| !provider.syntax.comments.contains(&p.kind()))
+        .count()
+}
+
+/// The most block nodes on a path down from `node`, counting itself.
+fn depth(node: TsNode<'_>, provider: &LanguageProvider) -> usize {
+    let mut cursor = node.walk();
+    let below = node
+        .children(&mut cursor)
+        .map(|child| depth(child, provider))
+        .max()
+        .unwrap_or(0);
+    below + usize::from(provider.syntax.blocks.contains(&node.kind()))
+}
+
+/// Branch points in `node` (see the module docs).
+fn branches(node: TsNode<'_>) -> usize {
+    let mut count = 0;
+    symbols::visit(&mut node.walk(), &mut |n| {
+        let branch = match n.is_named() {
+            true => BRANCH_KINDS.contains(&n.kind()),
+            // Only as operators: Rust's `||` also opens closures.
+            false => {
+                BRANCH_OPERATORS.contains(&n.kind())
+                    && n.parent().is_some_and(|p| {
+                        p.kind().contains("binary") || p.kind() == "boolean_operator"
+                    })
+            }
+        };
+        count += usize::from(branch);
+    });
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Signature;
+    use std::path::Path;
+
+    #[test]
+    fn measures_definitions() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let source = "fn plain() {}\n\nfn busy(a: u32, b: u32) -> u32 {\n    if a > 0 && b > 0 {\n        for _ in 0..a {\n            return 1;\n        }\n    }\n    match b {\n        0 => 0,\n        _ => 2,\n    }\n}\n";
+        std::fs::write(dir.path().join("a.rs"), source).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("a.rs")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = Signature::now("t", "t@example.com").unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "one", &tree, &[])
+            .unwrap();
+
+        let mut packs = QueryPacks::default();
+        let files = measure(&repo, "HEAD", &[], &ParseLimits::default(), &mut packs).unwrap();
+        let file = &files[0];
+        let figures: Vec<_> = file
+            .symbols
+            .iter()
+            .map(|s| (s.name.as_str(), s.lines, s.params, s.depth, s.cyclomatic))
+            .collect();
+        // `busy`: if, &&, for and two match arms.
+        assert_eq!(figures, [("plain", 1, 0, 0, 1), ("busy", 11, 2, 2, 6)]);
+        assert_eq!((file.lines, file.depth, file.cyclomatic), (13, 2, 6));
+    }
+}
@@ -0,0 +1,238 @@
+//! Smudge-Time Hooks
+//!
+//! Some workflows want generated content in the working tree that must never
+//! reach history: a build stamp at the top of each file, a license header
+//! added by tooling, code generated from the file's own annotations. Per
+//! language, a pair of commands can be configured to add it on checkout and
+//! take it out again on `git add`:
+//!
+//! ```ini
+//! [ast "rust"]
+//!     # Runs on the printed source before it is written to the worktree
+//!     postSmudge = ./tools/stamp-build-info %f
+//!     # Runs on the worktree file before it is parsed, undoing postSmudge
+//!     preClean = ./tools/strip-build-info %f
+//! ```
+//!
+//! Both read the source on stdin and write the result to stdout, like Git's
+//! own `filter.<driver>.clean`/`smudge` commands: they run through the shell,
+//! `%f` is replaced by the quoted path, and `GIT_AST_PATH` and
+//! `GIT_AST_LANGUAGE` are set. A language needs both or neither.
+//!
+//! Every smudge checks that `preClean` turns the hook's output back into the
+//! printed source, and fails the file otherwise, so a hook can never change
+//! what gets stored. The commands come from Git config only, never from
+//! `.git-ast.toml`: a cloned repository cannot make checkouts run programs.
+
+use crate::config;
+use crate::languages::LanguageProvider;
+use crate::Error;
+use git2::Repository;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// The hook commands of one language.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LanguageHooks {
+    pub post_smudge: String,
+    pub pre_clean: String,
+}
+
+/// The configured hooks, by language name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Hooks {
+    pub languages: BTreeMap<String, LanguageHooks>,
+}
+
+impl Hooks {
+    /// Reads `ast.<language>.postSmudge` and `ast.<language>.preClean`.
+    pub fn load(config: &git2::Config) -> Result<Self, Error> {
+        let mut found: BTreeMap<String, (Option<String>, Option<String>)> = BTreeMap::new();
+        let mut entries = config.entries(Some(r"^ast\..+\.(postsmudge|preclean)$"))?;
+        while let Some(entry) = entries.next() {
+            let entry = entry?;
+            let (Some(name), Some(value)) = (entry.name(), entry.value()) else {
+                continue;
+            };
+            let Some((language, key)) = name["ast.".len()..].rsplit_once('.') else {
+                continue;
+            };
+            let slot = found.entry(language.to_string()).or_default();
+            match key {
+                "postsmudge" => slot.0 = Some(value.to_string()),
+                _ => slot.1 = Some(value.to_string()),
+            }
+        }
+        let mut hooks = Hooks::default();
+        for (language, pair) in found {
+            match pair {
+                (Some(post_smudge), Some(pre_clean)) => {
+                    hooks.languages.insert(
+                        language,
+                        LanguageHooks {
+                            post_smudge,
+                            pre_clean,
+                        },
+                    );
+                }
+                _ => {
+                    return Err(Error::Config(format!(
+                        "ast.{}: postSmudge and preClean must be configured together",
+                        language
+                    )))
+                }
+            }
+        }
+        Ok(hooks)
+    }
+
+    /// The hooks of `repo`, or `None` if there are none.
+    pub fn for_repo(repo: &Repository) -> Result<Option<Self>, Error> {
+        let hooks = Hooks::load(&repo.config()?.snapshot()?)?;
+        Ok(Some(hooks).filter(|h| !h.languages.is_empty()))
+    }
+
+    /// Runs the `postSmudge` hook of `language` on `source`, checking that
+    /// `preClean` undoes it.
+    pub fn post_smudge(
+        &self,
+        language: &str,
+        pathname: &str,
+        source: Vec<u8>,
+    ) -> Result<Vec<u8>, Error> {
+        let Some(hooks) = self.languages.get(language) else {
+            return Ok(source);
+        };
+        let output = run(&hooks.post_smudge, language, pathname, &source)?;
+        if run(&hooks.pre_clean, language, pathname, &output)? != source {
+            return Err(Error::Verification(format!(
+                "{}: preClean does not undo postSmudge; the hook output would be stored",
+                pathname
+            )));
+        }
+        Ok(output)
+    }
+
+    /// Runs the `preClean` hook of `language` on `content`.
+    pub fn pre_clean(
+        &self,
+        language: &str,
+        pathname: &str,
+        content: Vec<u8>,
+    ) -> Result<Vec<u8>, Error> {
+        match self.languages.get(language) {
+            Some(hooks) => run(&hooks.pre_clean, language, pathname, &content),
+            None => Ok(content),
+        }
+    }
+
+    /// [`pre_clean`](Hooks::pre_clean) for whatever language `pathname` has
+    /// in `repo`.
+    pub fn pre_clean_path(
+        &self,
+        repo: Option<&Repository>,
+        pathname: &str,
+        content: Vec<u8>,
+    ) -> Result<Vec<u8>, Error> {
+        match config::language_for_path(repo, pathname, &content)? {
+            Some(provider) => self.pre_clean(provider.name, pathname, content),
+            None => Ok(content),
+        }
+    }
+
+    /// [`post_smudge`](Hooks::post_smudge) for `provider`, or for whatever
+    /// language `pathname` has in `repo` if not known.
+    pub fn post_smudge_path(
+        &self,
+        repo: Option<&Repository>,
+        provider: Option<&LanguageProvider>,
+        pathname: &str,
+        source: Vec<u8>,
+    ) -> Result<Vec<u8>, Error> {
+        let name = match provider {
+            Some(provider) => Some(provider.name),
+            None => config::language_for_path(repo, pathname, &source)?.map(|p| p.name),
+        };
+        match name {
+            Some(name) => self.post_smudge(name, pathname, source),
+            None => Ok(source),
+        }
+    }
+}
+
+/// Runs `command` through the shell with `input` on stdin.
+fn run(command: &str, language: &str, pathname: &str, input: &[u8]) -> Result<Vec<u8>, Error> {
+    let script = command.replace("%f", &crate::toolchain::shell_quote(pathname));
+    let mut child = Command::new("sh")
+        .args(["-c", &script])
+        .env("GIT_AST_PATH", pathname)
+        .env("GIT_AST_LANGUAGE", language)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| Error::Driver(format!("cannot run {:?}: {}", command, e)))?;
+    let mut stdin = child.stdin.take().expect("piped");
+    // Write from another thread so a hook that answers before reading all of
+    // its input cannot deadlock us.
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output()?;
+    let _ = writer.join();
+    if !output.status.success() {
+        return Err(Error::Driver(format!(
+            "{:?} failed for {}: {}",
+            command, pathname, output.status
+        )));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hooks_add_and_strip_generated_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let mut config = repo
+            .config()
+            .unwrap()
+            .open_level(git2::ConfigLevel::Local)
+            .unwrap();
+        assert_eq!(Hooks::for_repo(&repo).unwrap(), None);
+
+        config
+            .set_str("ast.rust.postSmudge", "sed '1i // stamp: %f'")
+            .unwrap();
+        let err = Hooks::for_repo(&repo).unwrap_err();
+        assert!(err.to_string().contains("together"), "{}", err);
+
+        config
+            .set_str("ast.rust.preClean", "sed '/^\\/\\/ stamp: /d'")
+            .unwrap();
+        let hooks = Hooks::for_repo(&repo).unwrap().unwrap();
+        let source = b"fn main() {}\n".to_vec();
+        let smudged = hooks
+            .post_smudge("rust", "src/a.rs", source.clone())
+            .unwrap();
+        assert_eq!(smudged, b"// stamp: src/a.rs\nfn main() {}\n");
+        assert_eq!(
+            hooks.pre_clean_path(None, "src/a.rs", smudged).unwrap(),
+            source
+        );
+        assert_eq!(
+            hooks
+                .post_smudge("python", "a.py", b"x\n".to_vec())
+                .unwrap(),
+            b"x\n"
+        );
+
+        config.set_str("ast.rust.preClean", "cat").unwrap();
+        let hooks = Hooks::for_repo(&repo).unwrap().unwrap();
+        let err = hooks.post_smudge("rust", "src/a.rs", source).unwrap_err();
+        assert!(matches!(err, Error::Verification(_)), "{}", err);
+    }
+}
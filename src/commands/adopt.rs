@@ -0,0 +1,449 @@
+//! `git-ast adopt <pattern>... [--rewrite <rev>] [--dry-run]`
+//!
+//! Turns on AST handling for more files in an existing repository. Setting
+//! `filter=ast` only affects what is added from then on: blobs already in
+//! the index and in history stay plain source. `git-ast adopt '*.py'`
+//!
+//! -   adds `*.py filter=ast diff=ast merge=ast` to the top-level
+//!     `.gitattributes` (replaced atomically, then staged), unless the
+//!     pattern already has a `filter=ast` line there;
+//! -   cleans every staged plain blob the patterns match (as pathspecs) into
+//!     an AST blob, as `git add` would now do. Working-tree files are left
+//!     alone; they already hold the source, and `git-ast fmt` brings them
+//!     into canonical form.
+//!
+//! ```text
+//! $ git-ast adopt '*.py'
+//! .gitattributes: added *.py
+//! tools/gen.py
+//! tools/lint.py
+//! adopt: 2 file(s) staged as AST blobs
+//! ```
+//!
+//! ## Rewriting History
+//!
+//! With `--rewrite <rev>`, the current branch's commits after `<rev>` are
+//! rewritten the same way: matching plain blobs are cleaned and each
+//! commit's `.gitattributes` gets the new lines, keeping authors, dates and
+//! messages. Files that do not parse keep their plain blob. The branch is
+//! moved to the rewritten tip (the old one stays in the reflog); since this
+//! changes published commit ids, it is meant for recent, unpushed commits.
+//! It needs an index without staged changes, so that the index matches the
+//! rewritten `HEAD` afterwards.
+//!
+//! `--dry-run` prints what would change and writes nothing.
+
+use crate::config::{self, RepoConfigs};
+use crate::git_plumbing::filters;
+use crate::parsing::ParseLimits;
+use crate::serialization;
+use crate::util::atomic_io;
+use crate::{log_error, log_info, Error};
+use clap::Args;
+use git2::{Commit, ObjectType, Oid, Pathspec, PathspecFlags, Repository, Sort, Tree};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The attributes `git-ast adopt` gives each pattern.
+const ATTRIBUTES: &str = "filter=ast diff=ast merge=ast";
+
+/// Arguments for `git-ast adopt`.
+#[derive(Debug, Args)]
+pub struct AdoptArgs {
+    /// `.gitattributes` patterns of the files to adopt, e.g. `'*.py'`.
+    #[arg(required = true)]
+    pub patterns: Vec<String>,
+    /// Also rewrite the current branch's commits after this revision.
+    #[arg(long, value_name = "REV")]
+    pub rewrite: Option<String>,
+    /// Print what would change without writing anything.
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+/// What [`adopt`] changed (or, on a dry run, would change).
+#[derive(Debug, Clone, Default)]
+pub struct AdoptReport {
+    /// Patterns added to `.gitattributes`.
+    pub added: Vec<String>,
+    /// Staged files cleaned into AST blobs.
+    pub files: Vec<String>,
+    /// Staged files that could not be cleaned, with why.
+    pub failed: Vec<(String, String)>,
+    /// Commits rewritten by `--rewrite`.
+    pub rewritten: usize,
+    /// The branch tip before the rewrite.
+    pub old_head: Option<Oid>,
+}
+
+/// Runs `git-ast adopt`.
+pub fn run(args: &AdoptArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let report = adopt(&repo, &args.patterns, args.rewrite.as_deref(), args.dry_run)?;
+    let (staged, rewritten) = match args.dry_run {
+        true => ("would be staged as AST blobs", "would be rewritten"),
+        false => ("staged as AST blobs", "rewritten"),
+    };
+    for pattern in &report.added {
+        println!(".gitattributes: added {}", pattern);
+    }
+    for path in &report.files {
+        println!("{}", path);
+    }
+    for (path, reason) in &report.failed {
+        log_error!("adopt", "{}: {}", path, reason);
+    }
+    println!("adopt: {} file(s) {}", report.files.len(), staged);
+    if args.rewrite.is_some() {
+        match report.old_head {
+            Some(old) if !args.dry_run && report.rewritten > 0 => println!(
+                "adopt: {} commit(s) {} (previous tip {})",
+                report.rewritten, rewritten, old
+            ),
+            _ => println!("adopt: {} commit(s) {}", report.rewritten, rewritten),
+        }
+    }
+    match report.failed.len() {
+        0 => Ok(()),
+        n => Err(Error::Parsing(format!(
+            "{} file(s) could not be adopted",
+            n
+        ))),
+    }
+}
+
+/// Adopts the files matching `patterns`: extends `.gitattributes`, cleans
+/// the matching staged blobs and, with `rewrite`, the current branch's
+/// commits after that revision.
+pub fn adopt(
+    repo: &Repository,
+    patterns: &[String],
+    rewrite: Option<&str>,
+    dry_run: bool,
+) -> Result<AdoptReport, Error> {
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| Error::Config("git-ast adopt needs a working tree".to_string()))?
+        .to_path_buf();
+    let spec = Pathspec::new(patterns)?;
+    let mut report = AdoptReport::default();
+
+    // Checked before anything is written, so a refusal changes nothing.
+    let head = match rewrite {
+        Some(since) => Some(rewrite_range(repo, since)?),
+        None => None,
+    };
+
+    let attributes_path = workdir.join(".gitattributes");
+    let existing = match std::fs::read_to_string(&attributes_path) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let (attributes, added) = with_patterns(&existing, patterns);
+    report.added = added;
+
+    let mut cleaner = Cleaner::new(repo, spec)?;
+    let mut index = repo.index()?;
+    let mut updates = Vec::new();
+    for entry in index.iter() {
+        if (entry.flags >> 12) & 0x3 != 0 {
+            continue; // Conflict stage.
+        }
+        let path = String::from_utf8_lossy(&entry.path).into_owned();
+        match cleaner.blob(entry.id, &path) {
+            Ok(Some(blob)) => {
+                report.files.push(path);
+                updates.push((entry, blob));
+            }
+            Ok(None) => {}
+            Err(e) => report.failed.push((path, e.to_string())),
+        }
+    }
+
+    if let Some((branch, tip, since)) = &head {
+        let mut walk = repo.revwalk()?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+        walk.push(*tip)?;
+        walk.hide(*since)?;
+        let commits = walk.collect::<Result<Vec<_>, _>>()?;
+        report.rewritten = commits.len();
+        report.old_head = Some(*tip);
+        if !dry_run && !commits.is_empty() {
+            let mut rewritten = HashMap::new();
+            for id in commits {
+                let commit = repo.find_commit(id)?;
+                let new = rewrite_commit(&mut cleaner, &commit, &rewritten, patterns)?;
+                rewritten.insert(id, new);
+            }
+            let new_tip = rewritten[tip];
+            let message = format!("git-ast adopt: rewrite since {}", since);
+            repo.reference(branch, new_tip, true, &message)?;
+        }
+    }
+
+    if dry_run {
+        return Ok(report);
+    }
+    if !report.added.is_empty() {
+        atomic_io::write(&attributes_path, &attributes)?;
+        index.add_path(Path::new(".gitattributes"))?;
+    }
+    for (entry, blob) in updates {
+        // Keeps the entry's stat data, which still describes the unchanged
+        // working-tree file, so Git does not see it as modified.
+        let id = repo.blob(&blob)?;
+        index.add(&git2::IndexEntry { id, ..entry })?;
+    }
+    index.write()?;
+    Ok(report)
+}
+
+/// `existing` `.gitattributes` content with a line for each pattern that
+/// does not already have `filter=ast`, and the patterns that were added.
+pub fn with_patterns(existing: &str, patterns: &[String]) -> (String, Vec<String>) {
+    let mut content = existing.to_string();
+    let mut added = Vec::new();
+    for pattern in patterns {
+        let present = content.lines().any(|line| {
+            let mut fields = line.split_whitespace();
+            fields.next() == Some(pattern) && fields.any(|f| f == "filter=ast")
+        });
+        if present || added.contains(pattern) {
+            continue;
+        }
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content += &format!("{} {}\n", pattern, ATTRIBUTES);
+        added.push(pattern.clone());
+    }
+    (content, added)
+}
+
+/// The branch `HEAD` is on, its tip and the resolved `since`, after
+/// checking that a rewrite can leave the index matching `HEAD`.
+fn rewrite_range(repo: &Repository, since: &str) -> Result<(String, Oid, Oid), Error> {
+    let head = repo.head()?;
+    if !head.is_branch() {
+        return Err(Error::Config(
+            "--rewrite needs HEAD on a branch, not detached".to_string(),
+        ));
+    }
+    let branch = head
+        .name()
+        .ok_or_else(|| Error::Config("HEAD is not a valid branch name".to_string()))?
+        .to_string();
+    let tip = head.peel_to_commit()?;
+    let since = repo.revparse_single(since)?.peel_to_commit()?.id();
+    if since != tip.id() && !repo.graph_descendant_of(tip.id(), since)? {
+        return Err(Error::Config(format!(
+            "{} is not an ancestor of {}",
+            since, branch
+        )));
+    }
+    let staged = repo.diff_tree_to_index(Some(&tip.tree()?), None, None)?;
+    if staged.deltas().len() > 0 {
+        return Err(Error::Config(
+            "--rewrite needs an index without staged changes; commit or stash them first"
+                .to_string(),
+        ));
+    }
+    Ok((branch, tip.id(), since))
+}
+
+/// Rewrites `commit` onto the already rewritten parents.
+fn rewrite_commit(
+    cleaner: &mut Cleaner<'_>,
+    commit: &Commit<'_>,
+    rewritten: &HashMap<Oid, Oid>,
+    patterns: &[String],
+) -> Result<Oid, Error> {
+    let repo = cleaner.repo;
+    let tree = cleaner.tree(&commit.tree()?, "")?;
+    let tree = repo.find_tree(tree)?;
+    let mut builder = repo.treebuilder(Some(&tree))?;
+    let existing = match tree.get_name(".gitattributes") {
+        Some(entry) => repo.find_blob(entry.id())?.content().to_vec(),
+        None => Vec::new(),
+    };
+    let (attributes, added) = with_patterns(&String::from_utf8_lossy(&existing), patterns);
+    if !added.is_empty() {
+        builder.insert(
+            ".gitattributes",
+            repo.blob(attributes.as_bytes())?,
+            0o100644,
+        )?;
+    }
+    let tree = repo.find_tree(builder.write()?)?;
+
+    let parents = commit
+        .parent_ids()
+        .map(|id| repo.find_commit(rewritten.get(&id).copied().unwrap_or(id)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let parents: Vec<&Commit<'_>> = parents.iter().collect();
+    let message = String::from_utf8_lossy(commit.message_bytes());
+    Ok(repo.commit(
+        None,
+        &commit.author(),
+        &commit.committer(),
+        &message,
+        &tree,
+        &parents,
+    )?)
+}
+
+/// Cleans the plain blobs of matching paths, remembering the results.
+struct Cleaner<'r> {
+    repo: &'r Repository,
+    spec: Pathspec,
+    limits: ParseLimits,
+    repo_configs: RepoConfigs,
+    /// Rewritten trees and blobs, by original id and path (matching and
+    /// language detection depend on the path).
+    trees: HashMap<(Oid, String), Oid>,
+    blobs: HashMap<(Oid, String), Option<Oid>>,
+}
+
+impl<'r> Cleaner<'r> {
+    fn new(repo: &'r Repository, spec: Pathspec) -> Result<Self, Error> {
+        Ok(Cleaner {
+            repo,
+            spec,
+            limits: config::Settings::load(Some(repo))?.parse_limits,
+            repo_configs: RepoConfigs::new(Some(repo)),
+            trees: HashMap::new(),
+            blobs: HashMap::new(),
+        })
+    }
+
+    /// The AST blob for plain blob `id` at `path`, or `None` if the path
+    /// does not match, the blob already is one, or it has no language.
+    fn blob(&mut self, id: Oid, path: &str) -> Result<Option<Vec<u8>>, Error> {
+        if !self
+            .spec
+            .matches_path(Path::new(path), PathspecFlags::DEFAULT)
+        {
+            return Ok(None);
+        }
+        let blob = self.repo.find_blob(id)?;
+        let content = blob.content();
+        if serialization::is_ast_blob(content) {
+            return Ok(None);
+        }
+        let provider = config::language_for_path(Some(self.repo), path, content)?;
+        if provider.is_none() {
+            return Ok(None);
+        }
+        let repo_config = self.repo_configs.for_path(path)?;
+        let ast = filters::clean_with(content, path, provider, &self.limits, &repo_config)?;
+        Ok(Some(ast).filter(|ast| ast.as_slice() != content))
+    }
+
+    /// [`blob`](Self::blob) for history: the new blob id, or `id` itself.
+    fn blob_id(&mut self, id: Oid, path: &str) -> Result<Oid, Error> {
+        let key = (id, path.to_string());
+        if let Some(cleaned) = self.blobs.get(&key) {
+            return Ok(cleaned.unwrap_or(id));
+        }
+        let cleaned = match self.blob(id, path) {
+            Ok(Some(ast)) => Some(self.repo.blob(&ast)?),
+            Ok(None) => None,
+            Err(e) => {
+                log_info!("adopt", "keeping {} as text in history: {}", path, e);
+                None
+            }
+        };
+        self.blobs.insert(key, cleaned);
+        Ok(cleaned.unwrap_or(id))
+    }
+
+    fn tree(&mut self, tree: &Tree<'_>, prefix: &str) -> Result<Oid, Error> {
+        let key = (tree.id(), prefix.to_string());
+        if let Some(&id) = self.trees.get(&key) {
+            return Ok(id);
+        }
+        let mut builder = self.repo.treebuilder(None)?;
+        for entry in tree.iter() {
+            let path = format!("{}{}", prefix, entry.name().unwrap_or_default());
+            let id = match entry.kind() {
+                Some(ObjectType::Tree) => {
+                    self.tree(&self.repo.find_tree(entry.id())?, &format!("{}/", path))?
+                }
+                Some(ObjectType::Blob) => self.blob_id(entry.id(), &path)?,
+                _ => entry.id(), // Submodule commit.
+            };
+            builder.insert(entry.name_bytes(), id, entry.filemode())?;
+        }
+        let id = builder.write()?;
+        self.trees.insert(key, id);
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adopts_index_and_rewrites_recent_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let signature = git2::Signature::now("t", "t@example.com").unwrap();
+        let mut parent = None;
+        for (message, source) in [("one", "fn a() {}\n"), ("two", "fn b() {}\n")] {
+            std::fs::write(dir.path().join("a.rs"), source).unwrap();
+            std::fs::write(dir.path().join("notes.txt"), message).unwrap();
+            let mut index = repo.index().unwrap();
+            index.add_path(Path::new("a.rs")).unwrap();
+            index.add_path(Path::new("notes.txt")).unwrap();
+            index.write().unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let parents: Vec<Commit<'_>> = parent
+                .iter()
+                .map(|&id| repo.find_commit(id).unwrap())
+                .collect();
+            let parents: Vec<&Commit<'_>> = parents.iter().collect();
+            parent = Some(
+                repo.commit(
+                    Some("HEAD"),
+                    &signature,
+                    &signature,
+                    message,
+                    &tree,
+                    &parents,
+                )
+                .unwrap(),
+            );
+        }
+        let first = repo.revparse_single("HEAD~").unwrap().id();
+        let patterns = ["*.rs".to_string()];
+
+        let preview = adopt(&repo, &patterns, Some("HEAD~"), true).unwrap();
+        assert_eq!(preview.added, ["*.rs"]);
+        assert_eq!(preview.files, ["a.rs"]);
+        assert_eq!(preview.rewritten, 1);
+        assert!(!dir.path().join(".gitattributes").exists());
+
+        let report = adopt(&repo, &patterns, Some("HEAD~"), false).unwrap();
+        assert_eq!(report.rewritten, 1);
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(".gitattributes")).unwrap(),
+            "*.rs filter=ast diff=ast merge=ast\n"
+        );
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.parent_id(0).unwrap(), first);
+        assert_eq!(head.message(), Some("two"));
+        let tree = head.tree().unwrap();
+        let blob = |name: &str| repo.find_blob(tree.get_name(name).unwrap().id()).unwrap();
+        assert!(serialization::is_ast_blob(blob("a.rs").content()));
+        assert_eq!(blob("notes.txt").content(), b"two");
+        assert!(tree.get_name(".gitattributes").is_some());
+        // The index now matches the rewritten HEAD.
+        let staged = repo.diff_tree_to_index(Some(&tree), None, None).unwrap();
+        assert_eq!(staged.deltas().len(), 0);
+
+        let again = adopt(&repo, &patterns, None, false).unwrap();
+        assert!(again.added.is_empty() && again.files.is_empty());
+    }
+}
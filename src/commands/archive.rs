@@ -0,0 +1,57 @@
+//! `git-ast archive <git archive args>`
+//!
+//! `git archive` runs smudge filters on the files it writes, but only the
+//! ones configured where it runs: in a fresh clone, on a build machine, or
+//! anywhere `filter.ast` is missing, it silently packs the stored AST blobs.
+//! This wrapper runs it with this binary configured as the required `ast`
+//! filter, so every file with `filter=ast` is printed as source (and a file
+//! that cannot be is an error, not a blob in the tarball):
+//!
+//! ```text
+//! $ git-ast archive --format=tar.gz --prefix=project-1.2/ -o project-1.2.tar.gz v1.2
+//! ```
+//!
+//! Attributes come from the archived tree (and `.git/info/attributes`), as
+//! for `git archive`. Archives the server builds (`git archive --remote`,
+//! a forge's download links) are out of reach: publish this command's
+//! output instead.
+
+use crate::toolchain::shell_quote;
+use crate::{log_info, Error};
+use clap::Args;
+use std::process::Command;
+
+/// Arguments for `git-ast archive`.
+#[derive(Debug, Args)]
+pub struct ArchiveArgs {
+    /// Arguments passed through to `git archive` (e.g. `--format=zip HEAD`).
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+    pub args: Vec<String>,
+}
+
+/// Runs `git archive` with this binary as the `ast` filter.
+pub fn run(args: &ArchiveArgs) -> Result<(), Error> {
+    if args
+        .args
+        .iter()
+        .any(|a| a == "--remote" || a.starts_with("--remote="))
+    {
+        return Err(Error::Config(
+            "git-ast archive cannot smudge a remote archive; run it in a clone".to_string(),
+        ));
+    }
+    let exe = std::env::current_exe()?;
+    let process = format!("{} filter-process", shell_quote(&exe.display().to_string()));
+    log_info!("archive", "Running git archive with filter {}", process);
+    let status = Command::new("git")
+        .arg("-c")
+        .arg(format!("filter.ast.process={}", process))
+        .args(["-c", "filter.ast.required=true"])
+        .arg("archive")
+        .args(&args.args)
+        .status()?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(Error::Driver(format!("git archive failed: {}", status))),
+    }
+}
@@ -0,0 +1,92 @@
+//! `git-ast audit [--command <c>] [--path <p>] [--errors] [-n <count>]`
+//!
+//! Prints the [`audit`](crate::audit) log, oldest first, one invocation per
+//! line:
+//!
+//! ```text
+//! 2026-10-16T09:12:03Z  clean   success   1.8ms  src/lib.rs  5b1c0f2  rust 0.23.3
+//! 2026-10-16T09:14:40Z  merge   error    12.0ms  src/main.rs  e69de29 8d2a1b0 77f0c3e  rust 0.23.3
+//!     2 conflict(s) in src/main.rs
+//! ```
+//!
+//! `--format jsonl` prints the matching lines as stored.
+
+use crate::audit::{AuditLog, Record};
+use crate::Error;
+use clap::{Args, ValueEnum};
+use git2::Repository;
+
+/// Arguments for `git-ast audit`.
+#[derive(Debug, Args)]
+pub struct AuditArgs {
+    /// Only invocations of this command (clean, smudge, diff or merge).
+    #[arg(long)]
+    pub command: Option<String>,
+    /// Only invocations on this path.
+    #[arg(long)]
+    pub path: Option<String>,
+    /// Only failed invocations.
+    #[arg(long)]
+    pub errors: bool,
+    /// Only the last this many matching invocations.
+    #[arg(short = 'n', long)]
+    pub max_count: Option<usize>,
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = AuditFormat::Text)]
+    pub format: AuditFormat,
+}
+
+/// Output formats of `git-ast audit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AuditFormat {
+    /// One aligned line per invocation, and the error below it.
+    Text,
+    /// The stored JSON lines.
+    Jsonl,
+}
+
+/// Runs `git-ast audit`.
+pub fn run(args: &AuditArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let log = AuditLog::at(&repo);
+    let mut records: Vec<Record> = log
+        .read()?
+        .into_iter()
+        .filter(|r| args.command.as_ref().is_none_or(|c| r.command == *c))
+        .filter(|r| args.path.as_ref().is_none_or(|p| r.path == *p))
+        .filter(|r| !args.errors || r.outcome != "success")
+        .collect();
+    if let Some(n) = args.max_count {
+        records.drain(..records.len().saturating_sub(n));
+    }
+    for record in &records {
+        match args.format {
+            AuditFormat::Jsonl => println!(
+                "{}",
+                serde_json::to_string(record).map_err(|e| Error::Serialization(e.to_string()))?
+            ),
+            AuditFormat::Text => {
+                let mut fields = vec![record.path.clone()];
+                let blobs: Vec<&str> = record.blobs.iter().map(|b| &b[..b.len().min(7)]).collect();
+                if !blobs.is_empty() {
+                    fields.push(blobs.join(" "));
+                }
+                if let (Some(language), Some(grammar)) = (&record.language, &record.grammar) {
+                    fields.push(format!("{} {}", language, grammar));
+                }
+                println!(
+                    "{}  {:<6}  {:<7}  {:>6.1}ms  {}",
+                    record.time(),
+                    record.command,
+                    record.outcome,
+                    record.duration_ms,
+                    fields.join("  ")
+                );
+                if let Some(error) = &record.error {
+                    println!("    {}", error);
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,469 @@
+//! `git-ast bench [--corpus <dir>] [--format json]`
+//!
+//! Measures how `git-ast` performs on a corpus of source files: by default
+//! the tracked files of the current repository that have a language, or
+//! every such file below `--corpus`. It reports
+//!
+//! -   clean and smudge throughput in MB of source per second,
+//! -   diff driver latency (both sides smudged, then diffed) by file size,
+//!     for a one-line edit of each file,
+//! -   on a sample repository holding the corpus in two commits, a cold
+//!     checkout (the first one in a fresh clone, smudging every file)
+//!     against a warm one (checking the files out again), and `git log -p
+//!     --ext-diff` of the edit with an empty diff cache against a filled
+//!     one.
+//!
+//! `--format json` prints the same as one JSON document, to be kept per
+//! commit or release and compared, so performance regressions show up as
+//! numbers. The sample repository runs this `git-ast` binary with
+//! the machine's Git config shut out, and needs `git` on the `PATH`.
+//! For per-file statistics during development, see `cargo bench --bench
+//! filters`.
+
+use crate::config::RepoConfig;
+use crate::diff_backend::{DiffBackend, NativeBackend};
+use crate::git_plumbing::filters;
+use crate::languages::{self, LanguageProvider};
+use crate::parsing::ParseLimits;
+use crate::telemetry;
+use crate::toolchain::shell_quote;
+use crate::util::atomic_io;
+use crate::Error;
+use clap::{Args, ValueEnum};
+use git2::Repository;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Instant;
+
+/// Arguments for `git-ast bench`.
+#[derive(Debug, Args)]
+pub struct BenchArgs {
+    /// Benchmark the source files below this directory instead of the
+    /// current repository's tracked files.
+    #[arg(long, value_name = "DIR")]
+    pub corpus: Option<PathBuf>,
+    /// How many times to clean and smudge each file.
+    #[arg(long, default_value_t = 3)]
+    pub iterations: u32,
+    /// Skip the sample repository (checkout and `git log -p` timings).
+    #[arg(long)]
+    pub no_repo: bool,
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = BenchFormat::Text)]
+    pub format: BenchFormat,
+}
+
+/// Output formats of `git-ast bench`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BenchFormat {
+    /// A short table.
+    Text,
+    /// A single JSON document.
+    Json,
+}
+
+/// Everything `git-ast bench` measured.
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchReport {
+    /// `git-ast` version that was measured.
+    pub version: String,
+    /// Files benchmarked (those that clean successfully).
+    pub files: usize,
+    /// Files skipped because they do not clean (syntax errors, limits).
+    pub skipped: usize,
+    /// Source bytes of the benchmarked files.
+    pub bytes: u64,
+    pub clean: Throughput,
+    pub smudge: Throughput,
+    /// Diff driver latency by file size.
+    pub diff: Vec<DiffLatency>,
+    /// Checkout of the sample repository.
+    pub checkout: Option<ColdWarm>,
+    /// `git log -p --ext-diff` on the sample repository.
+    pub log_patch: Option<ColdWarm>,
+}
+
+/// Time spent converting `bytes` of source.
+#[derive(Debug, Clone, Serialize)]
+pub struct Throughput {
+    pub seconds: f64,
+    pub mb_per_second: f64,
+}
+
+/// Median diff driver latency of the files in one size class.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffLatency {
+    /// `<4KiB`, `4-64KiB` or `>=64KiB`.
+    pub size: &'static str,
+    pub files: usize,
+    pub median_ms: f64,
+}
+
+/// A first (cold) and a repeated (warm) run of the same operation.
+#[derive(Debug, Clone, Serialize)]
+pub struct ColdWarm {
+    pub cold_ms: f64,
+    pub warm_ms: f64,
+}
+
+/// Size classes of the diff latency report: label and exclusive upper bound.
+const SIZE_CLASSES: &[(&str, u64)] = &[
+    ("<4KiB", 4 << 10),
+    ("4-64KiB", 64 << 10),
+    (">=64KiB", u64::MAX),
+];
+
+/// Runs `git-ast bench`.
+pub fn run(args: &BenchArgs) -> Result<(), Error> {
+    let inputs = match &args.corpus {
+        Some(dir) => corpus_files(dir)?,
+        None => tracked_files(&Repository::open_from_env()?)?,
+    };
+    telemetry::errors_only();
+    let report = measure(&inputs, args.iterations.max(1), !args.no_repo)?;
+    match args.format {
+        BenchFormat::Json => {
+            let json = serde_json::to_string_pretty(&report)
+                .map_err(|e| Error::Serialization(format!("bench report: {}", e)))?;
+            println!("{}", json);
+        }
+        BenchFormat::Text => print_text(&report),
+    }
+    Ok(())
+}
+
+/// Runs every measurement over `inputs` (`(path, source)` pairs).
+pub fn measure(
+    inputs: &[(String, Vec<u8>)],
+    iterations: u32,
+    sample_repo: bool,
+) -> Result<BenchReport, Error> {
+    let limits = ParseLimits::default();
+    let config = RepoConfig::default();
+    let mut files = Vec::new();
+    let mut skipped = 0;
+    for (path, source) in inputs {
+        let provider = languages::for_path(path);
+        let Ok(blob) = filters::clean_with(source, path, provider, &limits, &config) else {
+            skipped += 1;
+            continue;
+        };
+        let mut edited = source.clone();
+        edited.extend_from_slice(edit_for(provider).as_bytes());
+        let Ok(edited_blob) = filters::clean_with(&edited, path, provider, &limits, &config) else {
+            skipped += 1;
+            continue;
+        };
+        files.push((
+            path.as_str(),
+            source.as_slice(),
+            provider,
+            blob,
+            edited,
+            edited_blob,
+        ));
+    }
+    let bytes: u64 = files.iter().map(|f| f.1.len() as u64).sum();
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        for (path, source, provider, ..) in &files {
+            filters::clean_with(source, path, *provider, &limits, &config)?;
+        }
+    }
+    let clean = throughput(bytes * iterations as u64, start);
+    let start = Instant::now();
+    for _ in 0..iterations {
+        for (path, _, _, blob, ..) in &files {
+            filters::perform_smudge(blob, path)?;
+        }
+    }
+    let smudge = throughput(bytes * iterations as u64, start);
+
+    let mut latencies: Vec<Vec<f64>> = vec![Vec::new(); SIZE_CLASSES.len()];
+    for (path, source, _, blob, _, edited_blob) in &files {
+        let start = Instant::now();
+        let old = filters::perform_smudge(blob, path)?;
+        let new = filters::perform_smudge(edited_blob, path)?;
+        NativeBackend.diff(path, &old, &new)?;
+        let class = SIZE_CLASSES
+            .iter()
+            .position(|(_, max)| (source.len() as u64) < *max)
+            .unwrap_or_default();
+        latencies[class].push(start.elapsed().as_secs_f64() * 1000.0);
+    }
+    let diff = SIZE_CLASSES
+        .iter()
+        .zip(latencies)
+        .filter(|(_, times)| !times.is_empty())
+        .map(|((size, _), times)| DiffLatency {
+            size,
+            files: times.len(),
+            median_ms: median(times),
+        })
+        .collect();
+
+    let (checkout, log_patch) = match sample_repo && !files.is_empty() {
+        true => {
+            let sample: Vec<_> = files.iter().map(|f| (f.0, f.1, f.4.as_slice())).collect();
+            let (checkout, log_patch) = sample_repo_timings(&sample)?;
+            (Some(checkout), Some(log_patch))
+        }
+        false => (None, None),
+    };
+
+    Ok(BenchReport {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        files: files.len(),
+        skipped,
+        bytes,
+        clean,
+        smudge,
+        diff,
+        checkout,
+        log_patch,
+    })
+}
+
+/// A one-line change appended to a file of `provider`'s language.
+fn edit_for(provider: Option<&LanguageProvider>) -> &'static str {
+    match provider.map(|p| p.name) {
+        Some("python" | "ruby") => "# edited\n",
+        Some("css") => "/* edited */\n",
+        Some("html") => "<!-- edited -->\n",
+        _ => "// edited\n",
+    }
+}
+
+fn throughput(bytes: u64, start: Instant) -> Throughput {
+    let seconds = start.elapsed().as_secs_f64();
+    Throughput {
+        seconds,
+        mb_per_second: match seconds > 0.0 {
+            true => bytes as f64 / seconds / 1e6,
+            false => 0.0,
+        },
+    }
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    match values.len() % 2 {
+        0 => (values[mid - 1] + values[mid]) / 2.0,
+        _ => values[mid],
+    }
+}
+
+/// Commits `(path, source, edited source)` to a sample repository in two
+/// commits and times checkouts and `git log -p` there.
+fn sample_repo_timings(files: &[(&str, &[u8], &[u8])]) -> Result<(ColdWarm, ColdWarm), Error> {
+    let dir = tempfile_dir()?;
+    let origin = dir.join("origin");
+    std::fs::create_dir_all(&origin)?;
+    git(&origin, &["init", "--quiet"])?;
+    use_git_ast(&origin)?;
+    for revision in [0, 1] {
+        for (path, source, edited) in files {
+            let file = origin.join(path);
+            atomic_io::write(file, if revision == 0 { source } else { edited })?;
+        }
+        git(&origin, &["add", "--all"])?;
+        git(&origin, &["commit", "--quiet", "--message", "corpus"])?;
+    }
+
+    // Cold: a fresh clone's first checkout. Warm: the same files again.
+    let clone = dir.join("clone");
+    git(
+        &dir,
+        &["clone", "--quiet", "--no-checkout", "origin", "clone"],
+    )?;
+    use_git_ast(&clone)?;
+    let start = Instant::now();
+    git(&clone, &["checkout", "--quiet", "--force", "HEAD"])?;
+    let cold_ms = ms(start);
+    for (path, ..) in files {
+        std::fs::remove_file(clone.join(path))?;
+    }
+    let start = Instant::now();
+    git(&clone, &["checkout", "--quiet", "--", "."])?;
+    let checkout = ColdWarm {
+        cold_ms,
+        warm_ms: ms(start),
+    };
+
+    // The edit commit's diffs: the first run fills the diff cache, the
+    // second reads it.
+    let log = ["log", "-p", "--ext-diff", "-1", "HEAD"];
+    let start = Instant::now();
+    git(&origin, &log)?;
+    let cold_ms = ms(start);
+    let start = Instant::now();
+    git(&origin, &log)?;
+    let log_patch = ColdWarm {
+        cold_ms,
+        warm_ms: ms(start),
+    };
+
+    let _ = std::fs::remove_dir_all(&dir);
+    Ok((checkout, log_patch))
+}
+
+/// Configures the repository at `dir` to run this binary as filter and
+/// diff driver for every file.
+fn use_git_ast(dir: &Path) -> Result<(), Error> {
+    let exe = shell_quote(&std::env::current_exe()?.display().to_string());
+    git(
+        dir,
+        &[
+            "config",
+            "filter.ast.process",
+            &format!("{} filter-process", exe),
+        ],
+    )?;
+    git(dir, &["config", "filter.ast.required", "true"])?;
+    git(
+        dir,
+        &[
+            "config",
+            "diff.ast.command",
+            &format!("{} diff-driver", exe),
+        ],
+    )?;
+    atomic_io::write(dir.join(".git/info/attributes"), "* filter=ast diff=ast\n")?;
+    Ok(())
+}
+
+fn ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+/// A fresh directory for the sample repository.
+fn tempfile_dir() -> Result<PathBuf, Error> {
+    let dir = std::env::temp_dir().join(format!("git-ast-bench-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Runs `git` hermetically in `dir`, discarding its output.
+fn git(dir: &Path, args: &[&str]) -> Result<(), Error> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env(
+            "GIT_CONFIG_GLOBAL",
+            if cfg!(windows) { "NUL" } else { "/dev/null" },
+        )
+        .env(crate::config::NO_USER_CONFIG_ENV, "1")
+        .env("GIT_AUTHOR_NAME", "bench")
+        .env("GIT_AUTHOR_EMAIL", "bench@localhost")
+        .env("GIT_COMMITTER_NAME", "bench")
+        .env("GIT_COMMITTER_EMAIL", "bench@localhost")
+        .env_remove("GIT_DIR")
+        .env_remove("GIT_WORK_TREE")
+        .output()?;
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(Error::Driver(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))),
+    }
+}
+
+/// Source files with a language below `dir`.
+fn corpus_files(dir: &Path) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    let mut out = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in std::fs::read_dir(&current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let relative = path
+                .strip_prefix(dir)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .replace('\\', "/");
+            if languages::for_path(&relative).is_some() {
+                out.push((relative, std::fs::read(&path)?));
+            }
+        }
+    }
+    out.sort();
+    Ok(out)
+}
+
+/// Tracked working-tree files with a language.
+fn tracked_files(repo: &Repository) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| Error::Config("no working tree (use --corpus)".to_string()))?;
+    let mut out = Vec::new();
+    for entry in repo.index()?.iter() {
+        let path = String::from_utf8_lossy(&entry.path).into_owned();
+        if languages::for_path(&path).is_none() {
+            continue;
+        }
+        if let Ok(source) = std::fs::read(workdir.join(&path)) {
+            out.push((path, source));
+        }
+    }
+    Ok(out)
+}
+
+fn print_text(report: &BenchReport) {
+    println!(
+        "{} files, {} bytes ({} skipped), git-ast {}",
+        report.files, report.bytes, report.skipped, report.version
+    );
+    println!("clean   {:>10.2} MB/s", report.clean.mb_per_second);
+    println!("smudge  {:>10.2} MB/s", report.smudge.mb_per_second);
+    for latency in &report.diff {
+        println!(
+            "diff    {:>10.2} ms median, {} files {}",
+            latency.median_ms, latency.files, latency.size
+        );
+    }
+    for (name, times) in [
+        ("checkout", &report.checkout),
+        ("log -p", &report.log_patch),
+    ] {
+        if let Some(times) = times {
+            println!(
+                "{:<8}{:>10.1} ms cold, {:.1} ms warm",
+                name, times.cold_ms, times.warm_ms
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_throughput_and_diff_latency() {
+        let inputs = vec![
+            ("a.rs".to_string(), b"fn a() -> i32 {\n    1\n}\n".to_vec()),
+            ("b.py".to_string(), b"def b():\n    return 2\n".to_vec()),
+            ("broken.rs".to_string(), b"fn broken( {\n".to_vec()),
+        ];
+        let report = measure(&inputs, 1, false).unwrap();
+        assert_eq!((report.files, report.skipped), (2, 1));
+        assert_eq!(
+            report.bytes,
+            inputs[0].1.len() as u64 + inputs[1].1.len() as u64
+        );
+        assert_eq!(report.diff.len(), 1);
+        assert_eq!((report.diff[0].size, report.diff[0].files), ("<4KiB", 2));
+        assert!(report.checkout.is_none());
+        assert_eq!(median(vec![3.0, 1.0, 2.0, 10.0]), 2.5);
+    }
+}
@@ -0,0 +1,113 @@
+//! `git-ast blame` and `git-ast ignore-revs`
+//!
+//! See the [`blame`](crate::blame) module for how formatting-only commits are
+//! detected and stored.
+
+use crate::blame;
+use crate::{log_info, Error};
+use clap::{Args, Subcommand};
+use git2::Repository;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Arguments for `git-ast blame`.
+#[derive(Debug, Args)]
+pub struct BlameArgs {
+    /// Arguments passed through to `git blame` (e.g. `-L 10,20 -- src/lib.rs`).
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+    pub args: Vec<String>,
+}
+
+/// `git-ast ignore-revs` subcommands.
+#[derive(Debug, Subcommand)]
+pub enum IgnoreRevsCommand {
+    /// Detect formatting-only commits and add them to the local list.
+    Scan {
+        /// Revision or `A..B` range to scan (default: all of HEAD).
+        range: Option<String>,
+    },
+    /// Print the local list of formatting-only commits.
+    List,
+    /// Write the list to a shareable `.git-blame-ignore-revs` file.
+    Export {
+        /// Output file (default: `.git-blame-ignore-revs` in the worktree root).
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+/// Runs `git blame`, skipping formatting-only commits.
+///
+/// Both the auto-generated local list and a committed
+/// `.git-blame-ignore-revs` (if present) are honoured.
+pub fn run_blame(args: &BlameArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let mut command = Command::new("git");
+    command.arg("blame");
+    let local = blame::format_only_revs_path(&repo);
+    if local.exists() {
+        command.arg("--ignore-revs-file").arg(&local);
+    }
+    if let Some(shared) = repo.workdir().map(|w| w.join(blame::EXPORT_FILE_NAME)) {
+        if shared.exists() {
+            command.arg("--ignore-revs-file").arg(shared);
+        }
+    }
+    let status = command.args(&args.args).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::Driver(format!("git blame failed: {}", status)))
+    }
+}
+
+/// Runs a `git-ast ignore-revs` subcommand.
+pub fn run_ignore_revs(command: &IgnoreRevsCommand) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let list_path = blame::format_only_revs_path(&repo);
+    match command {
+        IgnoreRevsCommand::Scan { range } => {
+            let mut revs = blame::read_revs(&list_path)?;
+            let found = blame::scan(&repo, range.as_deref())?;
+            let mut added = 0;
+            for oid in found {
+                if revs.insert(oid) {
+                    println!("{}", oid);
+                    added += 1;
+                }
+            }
+            blame::write_revs(&list_path, &revs)?;
+            log_info!(
+                "blame",
+                "{} new formatting-only commit(s), {} total",
+                added,
+                revs.len()
+            );
+        }
+        IgnoreRevsCommand::List => {
+            for oid in blame::read_revs(&list_path)? {
+                println!("{}", oid);
+            }
+        }
+        IgnoreRevsCommand::Export { output } => {
+            let output = match output {
+                Some(path) => path.clone(),
+                None => repo
+                    .workdir()
+                    .ok_or_else(|| Error::Config("bare repository: pass --output".to_string()))?
+                    .join(blame::EXPORT_FILE_NAME),
+            };
+            // Keep entries that were added to the shared file by hand.
+            let mut revs = blame::read_revs(&output)?;
+            revs.extend(blame::read_revs(&list_path)?);
+            blame::write_revs(&output, &revs)?;
+            log_info!(
+                "blame",
+                "Wrote {} commit(s) to {}",
+                revs.len(),
+                output.display()
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,75 @@
+//! `git-ast bundle create|install`
+//!
+//! Moves a repository's toolchain and project files to a machine without
+//! network access as one file (see [`bundle`](crate::bundle)):
+//!
+//! ```text
+//! $ git-ast setup-toolchain                # on a connected machine
+//! $ git-ast bundle create project.gasb
+//! $ git-ast bundle install project.gasb    # on the air-gapped one
+//! $ git-ast setup-toolchain                # finds the installed toolchain
+//! ```
+
+use crate::bundle::Bundle;
+use crate::toolchain;
+use crate::util::atomic_io;
+use crate::Error;
+use clap::Subcommand;
+use git2::Repository;
+use std::path::PathBuf;
+
+/// `git-ast bundle` subcommands.
+#[derive(Debug, Subcommand)]
+pub enum BundleCommand {
+    /// Write the repository's toolchain and project files into a bundle.
+    Create {
+        /// The bundle file to write.
+        file: PathBuf,
+    },
+    /// Install a bundle's toolchain and restore missing project files.
+    Install {
+        /// The bundle file to read.
+        file: PathBuf,
+        /// Overwrite project files that differ from the bundle's.
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+/// Runs a `git-ast bundle` subcommand.
+pub fn run(command: &BundleCommand) -> Result<(), Error> {
+    match command {
+        BundleCommand::Create { file } => {
+            let repo = Repository::open_from_env()?;
+            let bundle = Bundle::create(&repo)?;
+            atomic_io::write(file, bundle.encode()?)?;
+            println!(
+                "git-ast {} for {}, {} project file(s)",
+                bundle.version,
+                bundle.platform,
+                bundle.files.len()
+            );
+            Ok(())
+        }
+        BundleCommand::Install { file, force } => {
+            let bundle = Bundle::decode(&std::fs::read(file)?)?;
+            let cache = toolchain::cache_dir().ok_or_else(|| {
+                Error::Config("no cache directory; set GIT_AST_CACHE_DIR".to_string())
+            })?;
+            let repo = Repository::open_from_env().ok();
+            let workdir = repo.as_ref().and_then(|repo| repo.workdir());
+            let report = bundle.install(workdir, &cache, *force)?;
+            println!("toolchain: {}", report.toolchain.display());
+            for path in &report.written {
+                println!("wrote {}", path);
+            }
+            for path in &report.kept {
+                println!(
+                    "kept {} (differs from the bundle; --force replaces it)",
+                    path
+                );
+            }
+            Ok(())
+        }
+    }
+}
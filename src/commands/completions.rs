@@ -0,0 +1,112 @@
+//! `git-ast completions <shell>` and `git-ast manpage [--out-dir <dir>]`
+//!
+//! Both are generated from the same clap command tree that parses the
+//! command line, so they cover every subcommand and flag of this build:
+//!
+//! ```bash
+//! git-ast completions bash > /usr/share/bash-completion/completions/git-ast
+//! git-ast completions zsh > "${fpath[1]}/_git-ast"
+//! git-ast manpage --out-dir /usr/share/man/man1   # git-ast.1, git-ast-fmt.1, ...
+//! ```
+//!
+//! Without `--out-dir`, `manpage` prints the top-level page (`git-ast.1`)
+//! to stdout. With it, each subcommand gets its own page, named the way
+//! `git help ast-<subcommand>` and `man git-ast-<subcommand>` look for it.
+
+use super::Cli;
+use crate::util::atomic_io;
+use crate::Error;
+use clap::{Args, CommandFactory};
+use clap_complete::Shell;
+use std::path::PathBuf;
+
+/// Arguments for `git-ast completions`.
+#[derive(Debug, Args)]
+pub struct CompletionsArgs {
+    /// Shell to generate the completion script for.
+    #[arg(value_enum)]
+    pub shell: Shell,
+}
+
+/// Arguments for `git-ast manpage`.
+#[derive(Debug, Args)]
+pub struct ManpageArgs {
+    /// Write `git-ast.1` and one page per subcommand into this directory.
+    #[arg(long, value_name = "DIR")]
+    pub out_dir: Option<PathBuf>,
+}
+
+/// Runs `git-ast completions`.
+pub fn run_completions(args: &CompletionsArgs) -> Result<(), Error> {
+    let mut command = Cli::command();
+    clap_complete::generate(args.shell, &mut command, "git-ast", &mut std::io::stdout());
+    Ok(())
+}
+
+/// Runs `git-ast manpage`.
+pub fn run_manpage(args: &ManpageArgs) -> Result<(), Error> {
+    match &args.out_dir {
+        None => {
+            let (_, page) = manpages().into_iter().next().expect("top-level page");
+            std::io::Write::write_all(&mut std::io::stdout(), &page)?;
+        }
+        Some(dir) => {
+            for (name, page) in manpages() {
+                atomic_io::write(dir.join(&name), page)?;
+                println!("{}", dir.join(name).display());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `(file name, roff source)` of the top-level page, then of each
+/// subcommand's page.
+pub fn manpages() -> Vec<(String, Vec<u8>)> {
+    let command = Cli::command();
+    let mut pages = vec![("git-ast.1".to_string(), render(command.clone()))];
+    for sub in command.get_subcommands().filter(|s| !s.is_hide_set()) {
+        let name = format!("git-ast-{}", sub.get_name());
+        let sub = sub
+            .clone()
+            .display_name(name.clone())
+            .bin_name(name.clone());
+        let page = render(sub.version(env!("CARGO_PKG_VERSION")));
+        pages.push((format!("{}.1", name), page));
+    }
+    pages
+}
+
+fn render(command: clap::Command) -> Vec<u8> {
+    let mut out = Vec::new();
+    clap_mangen::Man::new(command)
+        .render(&mut out)
+        .expect("rendering into memory cannot fail");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn covers_every_subcommand() {
+        let pages = manpages();
+        assert_eq!(pages[0].0, "git-ast.1");
+        let fmt = pages
+            .iter()
+            .find(|(name, _)| name == "git-ast-fmt.1")
+            .unwrap();
+        let fmt = String::from_utf8_lossy(&fmt.1);
+        assert!(
+            fmt.contains("git\\-ast\\-fmt") && fmt.contains("\\-\\-check"),
+            "{}",
+            fmt
+        );
+
+        let mut script = Vec::new();
+        clap_complete::generate(Shell::Bash, &mut Cli::command(), "git-ast", &mut script);
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("filter-process") && script.contains("review-anchors"));
+    }
+}
@@ -0,0 +1,163 @@
+//! `git-ast config list|get|set`
+//!
+//! Shows the effective settings (see [`Settings`]) and changes them:
+//!
+//! ```text
+//! $ git-ast config list --show-origin
+//! local   ast.conflictMarkers=node
+//! default ast.diffCache=true
+//! ...
+//! .git-ast.toml   storage.compression=zstd
+//! $ git-ast config set parseMaxBytes 64m
+//! ```
+//!
+//! `list` also prints the project settings of `.git-ast.toml` (`diff.*`,
+//! `normalize.*`, `storage.*`, `sparse.*`). Given a path, it prints those in
+//! effect for that file in a monorepo, and `--show-origin` names the
+//! `.git-ast.toml` that decided each:
+//!
+//! ```text
+//! $ git-ast config list --show-origin services/api/src/main.rs
+//! ...
+//! .git-ast.toml   normalize.rust.trailing_commas=true
+//! services/api/.git-ast.toml      normalize.rust.sort_imports=false
+//! ```
+//!
+//! Project settings decide what the stored blobs look like, so they are only
+//! ever read from those files; `set` refuses them and writes `ast.*`
+//! settings to the repository's Git config (or, with `--global`, to the
+//! user's), after checking the value.
+
+use crate::config::{self, Origin, RepoConfigs, Settings, REPO_CONFIG_FILE};
+use crate::Error;
+use clap::Subcommand;
+use git2::Repository;
+
+/// `git-ast config` subcommands.
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print every setting's effective value.
+    List {
+        /// Prefix each line with the layer (or file) the value came from.
+        #[arg(long)]
+        show_origin: bool,
+        /// Show the project settings in effect for this file.
+        path: Option<String>,
+    },
+    /// Print one setting's effective value.
+    Get {
+        /// Setting key, with or without `ast.` (`parseTimeoutMs`).
+        key: String,
+        /// Prefix the value with the layer it came from.
+        #[arg(long)]
+        show_origin: bool,
+    },
+    /// Check a value and store it in Git config.
+    Set {
+        key: String,
+        value: String,
+        /// Write to the user's global config instead of the repository's.
+        #[arg(long)]
+        global: bool,
+    },
+}
+
+/// Runs `git-ast config`.
+pub fn run(command: &ConfigCommand) -> Result<(), Error> {
+    let repo = Repository::open_from_env().ok();
+    match command {
+        ConfigCommand::List { show_origin, path } => {
+            let path = match (path, &repo) {
+                (Some(path), Some(repo)) => worktree_path(repo, path)?,
+                _ => String::new(),
+            };
+            for (key, value, origin) in entries(repo.as_ref(), &path)? {
+                print_entry(&key, &value, &origin, *show_origin, true);
+            }
+            Ok(())
+        }
+        ConfigCommand::Get { key, show_origin } => {
+            let wanted = config::setting(key).map_or(key.as_str(), |s| s.key);
+            let (key, value, origin) = entries(repo.as_ref(), "")?
+                .into_iter()
+                .find(|(k, _, _)| k == wanted)
+                .ok_or_else(|| Error::Config(format!("{} is not set", key)))?;
+            print_entry(&key, &value, &origin, *show_origin, false);
+            Ok(())
+        }
+        ConfigCommand::Set { key, value, global } => {
+            let setting = config::setting(key).ok_or_else(|| {
+                Error::Config(format!(
+                    "{} is not an ast.* setting; project settings are edited in {}",
+                    key, REPO_CONFIG_FILE
+                ))
+            })?;
+            let origin = if *global {
+                Origin::Global
+            } else {
+                Origin::Local
+            };
+            config::validate(setting.key, value, origin)?;
+            let mut target = match (global, &repo) {
+                (true, _) => git2::Config::open_default()?.open_global()?,
+                (false, Some(repo)) => repo.config()?.open_level(git2::ConfigLevel::Local)?,
+                (false, None) => {
+                    return Err(Error::Config(
+                        "not in a repository (use --global)".to_string(),
+                    ));
+                }
+            };
+            target.set_str(setting.key, value)?;
+            // A higher layer would hide the new value; say so.
+            let settings = Settings::load(repo.as_ref())?;
+            let (effective, origin) = &settings.values[setting.key];
+            if effective != value {
+                eprintln!(
+                    "warning: {} is overridden by {} ({})",
+                    setting.key, origin, effective
+                );
+            }
+            Ok(())
+        }
+    }
+}
+
+/// `(key, value, origin)` of every setting: the `ast.*` settings, then the
+/// project settings in effect for `path`.
+fn entries(repo: Option<&Repository>, path: &str) -> Result<Vec<(String, String, String)>, Error> {
+    let settings = Settings::load(repo)?;
+    let mut out: Vec<(String, String, String)> = settings
+        .values
+        .iter()
+        .map(|(key, (value, origin))| (key.to_string(), value.clone(), origin.to_string()))
+        .collect();
+    out.extend(RepoConfigs::new(repo).entries(path)?);
+    Ok(out)
+}
+
+fn print_entry(key: &str, value: &str, origin: &str, show_origin: bool, with_key: bool) {
+    let origin = if show_origin {
+        format!("{}\t", origin)
+    } else {
+        String::new()
+    };
+    match with_key {
+        true => println!("{}{}={}", origin, key, value),
+        false => println!("{}{}", origin, value),
+    }
+}
+
+/// `path` (relative to the current directory) relative to the worktree root.
+fn worktree_path(repo: &Repository, path: &str) -> Result<String, Error> {
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| Error::Config("no working tree".to_string()))?
+        .canonicalize()?;
+    let absolute = std::env::current_dir()?.join(path);
+    // The file need not exist; only its place in the tree matters.
+    let absolute = absolute.canonicalize().unwrap_or(absolute);
+    let relative = absolute
+        .strip_prefix(&workdir)
+        .map_err(|_| Error::Config(format!("{} is outside the working tree", path)))?;
+    Ok(relative.to_string_lossy().replace('\\', "/"))
+}
@@ -0,0 +1,135 @@
+//! `git-ast diff [--staged] [<rev> [<rev>] | <rev>..<rev>] [--format readable|patch] [--directories] [-- <path>...]`
+//!
+//! `git diff` on canonical source, without Git invoking the diff driver: the
+//! command resolves both sides itself and renders them like
+//! [`show`](super::show), summary of changed items and refactorings first.
+//! It works the same whether or not `diff=ast` is configured, which makes it
+//! the place to start when trying `git-ast` on a repository.
+//!
+//! ```text
+//! $ git-ast diff                    # working tree vs the index
+//! $ git-ast diff HEAD               # working tree vs HEAD
+//! $ git-ast diff --staged           # the index vs HEAD
+//! $ git-ast diff main feature       # two revisions
+//! $ git-ast diff main..feature -- src/
+//! $ git-ast diff main...feature     # feature since it left main
+//! ```
+//!
+//! As with `git diff`, files whose two sides print the same (reformatted
+//! only) are not listed, and untracked files are left out. A file renamed
+//! without changing its tree is a 100% rename, even where its bytes differ
+//! (see [`changes::pair_identical`]).
+//!
+//! Files are read, parsed and rendered on all CPUs. For a long range,
+//! `--directories` prints an overview instead of the diffs: the
+//! refactorings, then per directory the number of changed files and of the
+//! items they changed, removed and added:
+//!
+//! ```text
+//!  refactor: move read_magic from src/util.rs to src/parse.rs
+//!  src/: 2 files; items 1 changed
+//!  src/parse/: 3 files; items 4 changed, 1 removed, 2 added
+//! ```
+
+use super::show;
+use crate::changes::{self, FileChange};
+use crate::diff_backend::DiffFormat;
+use crate::Error;
+use clap::Args;
+use git2::{Diff, DiffFindOptions, DiffOptions, Repository, Tree};
+use std::io::Write;
+
+/// Arguments for `git-ast diff`.
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    /// Compare the index (rather than the working tree) with a revision.
+    #[arg(long, alias = "cached")]
+    pub staged: bool,
+    /// What to compare: none, one revision, two, or `A..B` / `A...B`.
+    #[arg(num_args = 0..=2)]
+    pub revs: Vec<String>,
+    /// How to render each file.
+    #[arg(long, value_enum, default_value = "readable")]
+    pub format: DiffFormat,
+    /// Summarize changes per directory instead of printing the diffs.
+    #[arg(long)]
+    pub directories: bool,
+    /// Limit to these paths (pathspecs, relative to the repository root).
+    #[arg(last = true)]
+    pub paths: Vec<String>,
+}
+
+/// Runs `git-ast diff`.
+pub fn run(args: &DiffArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let files = changed_files(&repo, args)?;
+    let out = match args.directories {
+        true => show::directories(&repo, &files)?,
+        false => show::report(&repo, &files, args.format)?,
+    };
+    std::io::stdout().write_all(&out)?;
+    Ok(())
+}
+
+/// The files that differ between the two sides `args` name, reformatting
+/// aside.
+pub fn changed_files(repo: &Repository, args: &DiffArgs) -> Result<Vec<FileChange>, Error> {
+    let mut options = DiffOptions::new();
+    for path in &args.paths {
+        options.pathspec(path);
+    }
+    let mut diff = sides(repo, args, &mut options)?;
+    diff.find_similar(Some(DiffFindOptions::new().renames(true)))?;
+    Ok(changes::from_diff(repo, &diff)?
+        .into_iter()
+        .filter(|c| c.old != c.new || c.old_mode != c.new_mode || c.from.is_some())
+        .collect())
+}
+
+/// The Git diff between the sides `args` name.
+fn sides<'r>(
+    repo: &'r Repository,
+    args: &DiffArgs,
+    options: &mut DiffOptions,
+) -> Result<Diff<'r>, Error> {
+    let revs: Vec<&str> = match args.revs.as_slice() {
+        [range] if range.contains("...") => {
+            let (a, b) = range.split_once("...").expect("contains ...");
+            let (a, b) = (commit_or_head(repo, a)?, commit_or_head(repo, b)?);
+            let base = repo.merge_base(a, b)?;
+            let (old, new) = (
+                repo.find_commit(base)?.tree()?,
+                repo.find_commit(b)?.tree()?,
+            );
+            return Ok(repo.diff_tree_to_tree(Some(&old), Some(&new), Some(options))?);
+        }
+        [range] if range.contains("..") => range.splitn(2, "..").collect(),
+        revs => revs.iter().map(String::as_str).collect(),
+    };
+    let tree = |rev: &str| -> Result<Tree<'r>, Error> {
+        Ok(repo.find_commit(commit_or_head(repo, rev)?)?.tree()?)
+    };
+    match (revs.as_slice(), args.staged) {
+        ([], false) => Ok(repo.diff_index_to_workdir(None, Some(options))?),
+        ([], true) => {
+            let head = repo.head().ok().map(|h| h.peel_to_tree()).transpose()?;
+            Ok(repo.diff_tree_to_index(head.as_ref(), None, Some(options))?)
+        }
+        ([rev], false) => {
+            Ok(repo.diff_tree_to_workdir_with_index(Some(&tree(rev)?), Some(options))?)
+        }
+        ([rev], true) => Ok(repo.diff_tree_to_index(Some(&tree(rev)?), None, Some(options))?),
+        ([a, b], false) => {
+            Ok(repo.diff_tree_to_tree(Some(&tree(a)?), Some(&tree(b)?), Some(options))?)
+        }
+        _ => Err(Error::Config(
+            "--staged compares the index with one revision".to_string(),
+        )),
+    }
+}
+
+/// The commit `rev` names; an empty side of a range is `HEAD`.
+fn commit_or_head(repo: &Repository, rev: &str) -> Result<git2::Oid, Error> {
+    let rev = if rev.is_empty() { "HEAD" } else { rev };
+    Ok(repo.revparse_single(rev)?.peel_to_commit()?.id())
+}
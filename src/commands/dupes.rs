@@ -0,0 +1,90 @@
+//! `git-ast dupes [<rev>] [--min-tokens <n>] [--identical-only]`
+//!
+//! Lists code that appears more than once in a revision, largest first
+//! (see [`dupes`](crate::dupes)):
+//!
+//! ```text
+//! $ git-ast dupes --min-tokens 60
+//! renamed    112 tokens, 2 places
+//!     src/merge.rs:210-241  function_item  (blob 1a2b3c4)
+//!     src/drivers.rs:88-119  function_item  (blob 5d6e7f8)
+//! identical  64 tokens, 3 places
+//!     ...
+//! ```
+
+use crate::config;
+use crate::dupes;
+use crate::Error;
+use clap::{Args, ValueEnum};
+use git2::Repository;
+
+/// Arguments for `git-ast dupes`.
+#[derive(Debug, Args)]
+pub struct DupesArgs {
+    /// Revision to search.
+    #[arg(default_value = "HEAD")]
+    pub rev: String,
+    /// Smallest duplicate to report, in tokens.
+    #[arg(long, default_value_t = 50)]
+    pub min_tokens: usize,
+    /// Only report exact copies, not copies with renamed identifiers.
+    #[arg(long)]
+    pub identical_only: bool,
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = DupesFormat::Text)]
+    pub format: DupesFormat,
+}
+
+/// Output formats of `git-ast dupes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DupesFormat {
+    /// One line per group, then one per place.
+    Text,
+    /// A single JSON document.
+    Json,
+}
+
+/// Runs `git-ast dupes`.
+pub fn run(args: &DupesArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let limits = config::Settings::load(Some(&repo))?.parse_limits;
+    let groups = dupes::find(
+        &repo,
+        &args.rev,
+        args.min_tokens,
+        !args.identical_only,
+        &limits,
+    )?;
+    match args.format {
+        DupesFormat::Json => {
+            let json = serde_json::to_string_pretty(&groups)
+                .map_err(|e| Error::Serialization(format!("dupes: {}", e)))?;
+            println!("{}", json);
+        }
+        DupesFormat::Text => {
+            for group in &groups {
+                let similarity = match group.similarity {
+                    dupes::Similarity::Identical => "identical",
+                    dupes::Similarity::Renamed => "renamed",
+                };
+                println!(
+                    "{:<10} {} tokens, {} places",
+                    similarity,
+                    group.tokens,
+                    group.places.len()
+                );
+                for place in &group.places {
+                    println!(
+                        "    {}:{}-{}  {}  (blob {})",
+                        place.path,
+                        place.start_line,
+                        place.end_line,
+                        place.kind,
+                        &place.blob[..7]
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,466 @@
+//! `git-ast eject [--tip] [--dry-run]`
+//!
+//! Leaves `git-ast` behind, turning the repository into an ordinary source
+//! repository:
+//!
+//! -   Every local branch is rewritten into source form, the same rewrite
+//!     as the [source mirror](crate::mirror), and moved to the result. The
+//!     OID map in `refs/notes/ast-map` keeps the way back to the old
+//!     commits; tags and remote-tracking branches are left alone. With
+//!     `--tip`, history is kept and only the next commit stores source.
+//! -   A final commit on the current branch stores source for any remaining
+//!     AST blob, drops `filter`, `diff` and `merge` attributes set to `ast`
+//!     from `.gitattributes` files and removes `.git-ast.toml` files.
+//! -   The `filter.ast`, `diff.ast`, `merge.ast` and `ast.*` entries are
+//!     removed from the repository's Git config, and hooks that still call
+//!     `git-ast` are listed for removal by hand.
+//!
+//! The working tree must already hold exactly the printed source of every
+//! AST blob, with nothing staged: the converted files then keep their bytes
+//! (and Git's stat cache), which is verified again at the end. Run
+//! `git-ast fmt` and commit first if it does not.
+//!
+//! `--dry-run` runs the checks and prints what would change.
+
+use crate::config::REPO_CONFIG_FILE;
+use crate::git_plumbing::filters;
+use crate::mirror::{self, Direction};
+use crate::serialization;
+use crate::util::atomic_io;
+use crate::Error;
+use clap::Args;
+use git2::{BranchType, Delta, ObjectType, Oid, Repository, Tree, TreeWalkMode, TreeWalkResult};
+use std::fs;
+use std::path::Path;
+
+/// Message of the commit that converts the tip.
+const EJECT_MESSAGE: &str = "Store source instead of git-ast syntax trees\n";
+
+/// Arguments for `git-ast eject`.
+#[derive(Debug, Args)]
+pub struct EjectArgs {
+    /// Keep history; only convert the current branch's tip with a new commit.
+    #[arg(long)]
+    pub tip: bool,
+    /// Check and print what would change without writing anything.
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+/// What [`eject`] changed (or, on a dry run, would change).
+#[derive(Debug, Clone, Default)]
+pub struct EjectReport {
+    /// Rewritten branches with their old and new tips (new is zero on a
+    /// dry run).
+    pub branches: Vec<(String, Oid, Oid)>,
+    /// AST blobs in the current branch's tip.
+    pub files: usize,
+    /// The commit converting the tip, if one was needed.
+    pub commit: Option<Oid>,
+    /// Removed Git config entries.
+    pub config: Vec<String>,
+    /// Hooks that still call `git-ast`.
+    pub hooks: Vec<String>,
+}
+
+/// Runs `git-ast eject`.
+pub fn run(args: &EjectArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let report = eject(&repo, args.tip, args.dry_run)?;
+    let would = match args.dry_run {
+        true => "would be ",
+        false => "",
+    };
+    for (branch, old, new) in &report.branches {
+        match args.dry_run {
+            true => println!("{} {} would be rewritten", branch, old),
+            false => println!("{} {} -> {}", branch, old, new),
+        }
+    }
+    println!(
+        "eject: {} AST file(s) at the tip {}stored as source",
+        report.files, would
+    );
+    if let Some(commit) = report.commit {
+        println!("eject: committed {}", commit);
+    }
+    for key in &report.config {
+        match args.dry_run {
+            true => println!("config: {} would be removed", key),
+            false => println!("config: removed {}", key),
+        }
+    }
+    for hook in &report.hooks {
+        println!("hook: {} still calls git-ast; remove it by hand", hook);
+    }
+    Ok(())
+}
+
+/// Ejects `repo`; see the [module docs](self).
+pub fn eject(repo: &Repository, tip_only: bool, dry_run: bool) -> Result<EjectReport, Error> {
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| Error::Config("git-ast eject needs a working tree".to_string()))?
+        .to_path_buf();
+    let head = repo.head()?;
+    if !head.is_branch() {
+        return Err(Error::Config("HEAD is not on a branch".to_string()));
+    }
+    let old_tree = head.peel_to_tree()?;
+    check_worktree(repo, &workdir, &old_tree)?;
+
+    let mut report = EjectReport {
+        config: ast_config_keys(repo)?,
+        hooks: ast_hooks(repo)?,
+        ..Default::default()
+    };
+    let mut local = Vec::new();
+    if !tip_only {
+        for branch in repo.branches(Some(BranchType::Local))? {
+            let reference = branch?.0.into_reference();
+            if let (Some(name), Some(id)) = (reference.name(), reference.target()) {
+                local.push((name.to_string(), id));
+            }
+        }
+    }
+    walk_blobs(&old_tree, |_, id| {
+        if repo
+            .find_blob(id)
+            .is_ok_and(|b| serialization::is_ast_blob(b.content()))
+        {
+            report.files += 1;
+        }
+    })?;
+    if dry_run {
+        report.branches = local
+            .into_iter()
+            .map(|(name, old)| (name, old, Oid::zero()))
+            .collect();
+        return Ok(report);
+    }
+
+    for (name, old) in local {
+        let synced = mirror::sync(repo, &name, Direction::ToSource)?;
+        repo.reference(&name, synced.head, true, "git-ast eject")?;
+        repo.find_reference(&synced.reference)?.delete()?;
+        report.branches.push((name, old, synced.head));
+    }
+
+    let head = repo.head()?.peel_to_commit()?;
+    let tree = source_tree(repo, &head.tree()?, "")?;
+    let tree = match tree {
+        Some(id) => repo.find_tree(id)?,
+        None => repo.find_tree(repo.treebuilder(None)?.write()?)?,
+    };
+    if tree.id() != head.tree_id() {
+        let signature = repo.signature()?;
+        report.commit = Some(repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            EJECT_MESSAGE,
+            &tree,
+            &[&head],
+        )?);
+    }
+
+    let mut config = repo.config()?.open_level(git2::ConfigLevel::Local)?;
+    for key in &report.config {
+        config.remove_multivar(key, ".*")?;
+    }
+
+    // Only the meta files change on disk; converted files already hold
+    // their new blob and keep their stat data in the index.
+    let mut index = repo.index()?;
+    let changes = repo.diff_tree_to_tree(Some(&old_tree), Some(&tree), None)?;
+    let mut changed = Vec::new();
+    for delta in changes.deltas() {
+        let Some(path) = delta.new_file().path().or(delta.old_file().path()) else {
+            continue;
+        };
+        let file = workdir.join(path);
+        if delta.status() == Delta::Deleted {
+            match fs::remove_file(&file) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+            index.remove_path(path)?;
+            continue;
+        }
+        let id = delta.new_file().id();
+        let content = repo.find_blob(id)?.content().to_vec();
+        match index.get_path(path, 0) {
+            Some(entry) if fs::read(&file).ok().as_ref() == Some(&content) => {
+                index.add(&git2::IndexEntry { id, ..entry })?;
+            }
+            _ => {
+                atomic_io::write(&file, &content)?;
+                index.add_path(path)?;
+            }
+        }
+        changed.push((path.to_path_buf(), content));
+    }
+    index.write()?;
+
+    // The tree stores source only, and the working tree did not move.
+    let mut remaining = Vec::new();
+    walk_blobs(&tree, |path, id| {
+        if repo
+            .find_blob(id)
+            .is_ok_and(|b| serialization::is_ast_blob(b.content()))
+        {
+            remaining.push(path);
+        }
+    })?;
+    if let Some(path) = remaining.first() {
+        return Err(Error::Verification(format!(
+            "{} still stores an AST blob",
+            path
+        )));
+    }
+    for (path, content) in changed {
+        if fs::read(workdir.join(&path)).ok() != Some(content) {
+            return Err(Error::Verification(format!(
+                "{} does not match its ejected blob",
+                path.display()
+            )));
+        }
+    }
+    Ok(report)
+}
+
+/// Checks that nothing is staged and that each AST blob's file holds its
+/// printed source (and each meta file its blob).
+fn check_worktree(repo: &Repository, workdir: &Path, tree: &Tree<'_>) -> Result<(), Error> {
+    let staged = repo.diff_tree_to_index(Some(tree), None, None)?;
+    if staged.deltas().len() > 0 {
+        return Err(Error::Config(
+            "git-ast eject needs an index without staged changes; commit or stash them first"
+                .to_string(),
+        ));
+    }
+    let mut paths = Vec::new();
+    walk_blobs(tree, |path, id| paths.push((path, id)))?;
+    let mut differ = Vec::new();
+    for (path, id) in paths {
+        let blob = repo.find_blob(id)?;
+        let expected = if serialization::is_ast_blob(blob.content()) {
+            filters::perform_smudge(blob.content(), &path)?
+        } else if is_meta_file(&path) {
+            blob.content().to_vec()
+        } else {
+            continue;
+        };
+        match fs::read(workdir.join(&path)) {
+            Ok(content) if content == expected => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => differ.push(path),
+            Err(e) => return Err(e.into()),
+            Ok(_) => differ.push(path),
+        }
+    }
+    match differ.len() {
+        0 => Ok(()),
+        n => Err(Error::Verification(format!(
+            "{} file(s) differ from their printed source, e.g. {}; run git-ast fmt and \
+             commit first",
+            n, differ[0]
+        ))),
+    }
+}
+
+fn is_meta_file(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name == ".gitattributes" || name == REPO_CONFIG_FILE
+}
+
+/// `tree` with source for every AST blob, `ast` attributes removed and
+/// `.git-ast.toml` files dropped; `None` if nothing is left of it.
+fn source_tree(repo: &Repository, tree: &Tree<'_>, prefix: &str) -> Result<Option<Oid>, Error> {
+    let mut builder = repo.treebuilder(None)?;
+    for entry in tree.iter() {
+        let name = entry.name().unwrap_or_default();
+        let path = format!("{}{}", prefix, name);
+        let id = match entry.kind() {
+            Some(ObjectType::Tree) => {
+                source_tree(repo, &repo.find_tree(entry.id())?, &format!("{}/", path))?
+            }
+            Some(ObjectType::Blob) if name == REPO_CONFIG_FILE => None,
+            Some(ObjectType::Blob) => {
+                let blob = repo.find_blob(entry.id())?;
+                let content = blob.content();
+                if name == ".gitattributes" {
+                    let stripped = without_ast(&String::from_utf8_lossy(content));
+                    Some(stripped)
+                        .filter(|s| !s.trim().is_empty())
+                        .map(|s| repo.blob(s.as_bytes()))
+                        .transpose()?
+                } else if serialization::is_ast_blob(content) {
+                    Some(repo.blob(&filters::perform_smudge(content, &path)?)?)
+                } else {
+                    Some(entry.id())
+                }
+            }
+            _ => Some(entry.id()), // Submodule commit.
+        };
+        if let Some(id) = id {
+            builder.insert(entry.name_bytes(), id, entry.filemode())?;
+        }
+    }
+    Ok(Some(builder.write()?).filter(|_| !builder.is_empty()))
+}
+
+/// `.gitattributes` content without `filter=ast`, `diff=ast` and
+/// `merge=ast`; lines left with a pattern only are dropped.
+pub fn without_ast(content: &str) -> String {
+    let mut out = String::new();
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let is_ast = |f: &&str| matches!(*f, "filter=ast" | "diff=ast" | "merge=ast");
+        if line.trim_start().starts_with('#') || !fields.iter().any(is_ast) {
+            out += line;
+            out.push('\n');
+            continue;
+        }
+        let kept: Vec<&str> = fields.into_iter().filter(|f| !is_ast(f)).collect();
+        if kept.len() > 1 {
+            out += &kept.join(" ");
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Local config entries that configure git-ast.
+fn ast_config_keys(repo: &Repository) -> Result<Vec<String>, Error> {
+    let config = repo.config()?.open_level(git2::ConfigLevel::Local)?;
+    let mut keys = Vec::new();
+    let mut entries = config.entries(Some(r"^(ast\.|(filter|diff|merge)\.ast\.)"))?;
+    while let Some(entry) = entries.next() {
+        if let Some(name) = entry?.name() {
+            if !keys.iter().any(|k| k == name) {
+                keys.push(name.to_string());
+            }
+        }
+    }
+    Ok(keys)
+}
+
+/// Hooks in `.git/hooks` that mention `git-ast`.
+fn ast_hooks(repo: &Repository) -> Result<Vec<String>, Error> {
+    let dir = repo.path().join("hooks");
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Ok(Vec::new());
+    };
+    let mut hooks = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "sample") {
+            continue;
+        }
+        if fs::read(&path).is_ok_and(|c| c.windows(7).any(|w| w == b"git-ast")) {
+            hooks.push(path.display().to_string());
+        }
+    }
+    hooks.sort();
+    Ok(hooks)
+}
+
+/// Calls `f` with the path and id of every blob in `tree`.
+fn walk_blobs(tree: &Tree<'_>, mut f: impl FnMut(String, Oid)) -> Result<(), Error> {
+    tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.kind() == Some(ObjectType::Blob) {
+            f(
+                format!("{}{}", dir, entry.name().unwrap_or_default()),
+                entry.id(),
+            );
+        }
+        TreeWalkResult::Ok
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::languages;
+    use crate::parsing::ParseLimits;
+
+    #[test]
+    fn ejects_tip_keeping_the_working_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let mut config = repo
+            .config()
+            .unwrap()
+            .open_level(git2::ConfigLevel::Local)
+            .unwrap();
+        config.set_str("user.name", "t").unwrap();
+        config.set_str("user.email", "t@example.com").unwrap();
+        config
+            .set_str("filter.ast.process", "git-ast filter-process")
+            .unwrap();
+        config.set_bool("ast.audit", true).unwrap();
+
+        let source = b"fn main() {}\n";
+        let ast = filters::clean_as(
+            source,
+            "a.rs",
+            languages::by_name("rust"),
+            &ParseLimits::default(),
+        )
+        .unwrap();
+        let files: [(&str, &[u8], &[u8]); 3] = [
+            ("a.rs", &ast, source),
+            (
+                ".gitattributes",
+                b"*.rs filter=ast diff=ast\n*.md text\n",
+                b"",
+            ),
+            (".git-ast.toml", b"[ast]\n", b""),
+        ];
+        let mut builder = repo.treebuilder(None).unwrap();
+        for (name, blob, worktree) in files {
+            builder
+                .insert(name, repo.blob(blob).unwrap(), 0o100644)
+                .unwrap();
+            let worktree = if worktree.is_empty() { blob } else { worktree };
+            fs::write(dir.path().join(name), worktree).unwrap();
+        }
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let signature = repo.signature().unwrap();
+        let first = repo
+            .commit(Some("HEAD"), &signature, &signature, "one", &tree, &[])
+            .unwrap();
+        let mut index = repo.index().unwrap();
+        index.read_tree(&tree).unwrap();
+        index.write().unwrap();
+
+        fs::write(dir.path().join("a.rs"), b"fn main() { }\n").unwrap();
+        let err = eject(&repo, true, false).unwrap_err();
+        assert!(matches!(err, Error::Verification(_)), "{}", err);
+        fs::write(dir.path().join("a.rs"), source).unwrap();
+
+        let preview = eject(&repo, true, true).unwrap();
+        assert_eq!(preview.files, 1);
+        assert_eq!(preview.config, ["filter.ast.process", "ast.audit"]);
+        assert!(preview.commit.is_none());
+
+        let report = eject(&repo, true, false).unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(report.commit, Some(head.id()));
+        assert_eq!(head.parent_id(0).unwrap(), first);
+        let tree = head.tree().unwrap();
+        let blob = |name: &str| repo.find_blob(tree.get_name(name).unwrap().id()).unwrap();
+        assert_eq!(blob("a.rs").content(), source);
+        assert_eq!(blob(".gitattributes").content(), b"*.md text\n");
+        assert!(tree.get_name(".git-ast.toml").is_none());
+        assert!(!dir.path().join(".git-ast.toml").exists());
+        assert_eq!(
+            fs::read(dir.path().join(".gitattributes")).unwrap(),
+            b"*.md text\n"
+        );
+        assert!(ast_config_keys(&repo).unwrap().is_empty());
+        let staged = repo.diff_tree_to_index(Some(&tree), None, None).unwrap();
+        assert_eq!(staged.deltas().len(), 0);
+    }
+}
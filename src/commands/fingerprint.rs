@@ -0,0 +1,113 @@
+//! `git-ast fingerprint <rev>...`
+//!
+//! Prints the structural [`fingerprint`](crate::fingerprint) of commits.
+//! Each argument is a revision or a range `A..B`:
+//!
+//! ```text
+//! $ git-ast fingerprint main~2..main
+//! 8f0c2d1e... 1a2b3c4 Handle empty input
+//! 3e9a7b20... 5d6e7f8 Add tests
+//! ```
+//!
+//! With `--upstream <range>` the output follows `git cherry`: `=` marks a
+//! commit whose change is already in the upstream range (naming the commit
+//! there), `+` one that is not.
+//!
+//! ```text
+//! $ git-ast fingerprint --upstream main~10..main topic~2..topic
+//! = 1a2b3c4 Handle empty input (as 9c8b7a6)
+//! + 5d6e7f8 Add tests
+//! ```
+//!
+//! With `--notes` the results are also attached to each commit, as the
+//! `fingerprint` section of its note in `refs/notes/ast` (see
+//! [`notes`](crate::git_plumbing::notes)).
+
+use crate::fingerprint::{self, CommitFingerprint};
+use crate::git_plumbing::notes;
+use crate::parsing::ParseLimits;
+use crate::queries::QueryPacks;
+use crate::Error;
+use clap::Args;
+use git2::{Oid, Repository};
+
+/// Arguments for `git-ast fingerprint`.
+#[derive(Debug, Args)]
+pub struct FingerprintArgs {
+    /// Also print the fingerprint of each changed file.
+    #[arg(long)]
+    pub files: bool,
+    /// Mark commits whose change already exists in this revision or range.
+    #[arg(long, value_name = "RANGE")]
+    pub upstream: Option<String>,
+    /// Attach the results to the commits in `refs/notes/ast`.
+    #[arg(long)]
+    pub notes: bool,
+    /// Revisions or `A..B` ranges.
+    #[arg(required = true)]
+    pub revs: Vec<String>,
+}
+
+/// Runs `git-ast fingerprint`.
+pub fn run(args: &FingerprintArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let limits = ParseLimits::default();
+    let mut packs = QueryPacks::for_repo(&repo);
+    let mut commits = Vec::new();
+    for rev in &args.revs {
+        commits.extend(fingerprint::commits(&repo, rev, &limits, &mut packs)?);
+    }
+
+    let duplicates = match &args.upstream {
+        None => vec![None; commits.len()],
+        Some(range) => {
+            let upstream = fingerprint::commits(&repo, range, &limits, &mut packs)?;
+            fingerprint::duplicates(&commits, &upstream)
+        }
+    };
+    for (c, dup) in commits.iter().zip(&duplicates) {
+        match (&args.upstream, dup) {
+            (None, _) => println!("{} {} {}", c.fingerprint, short(c.commit), c.summary),
+            (Some(_), Some(id)) => {
+                println!("= {} {} (as {})", short(c.commit), c.summary, short(*id))
+            }
+            (Some(_), None) => println!("+ {} {}", short(c.commit), c.summary),
+        }
+        if args.files {
+            for file in &c.files {
+                println!("    {} {}", file.fingerprint, file.path);
+            }
+        }
+    }
+
+    if args.notes {
+        let records = commits
+            .iter()
+            .zip(&duplicates)
+            .map(|(c, dup)| (c.commit, note(c, *dup)))
+            .collect();
+        notes::attach(&repo, "fingerprint", &records)?;
+    }
+    Ok(())
+}
+
+/// The `fingerprint` note section of a commit.
+fn note(commit: &CommitFingerprint, duplicate_of: Option<Oid>) -> serde_json::Value {
+    let files: Vec<_> = commit
+        .files
+        .iter()
+        .map(|f| serde_json::json!({ "path": f.path, "fingerprint": f.fingerprint.to_string() }))
+        .collect();
+    let mut note = serde_json::json!({
+        "fingerprint": commit.fingerprint.to_string(),
+        "files": files,
+    });
+    if let Some(id) = duplicate_of {
+        note["duplicate_of"] = id.to_string().into();
+    }
+    note
+}
+
+fn short(id: Oid) -> String {
+    id.to_string()[..7].to_string()
+}
@@ -0,0 +1,190 @@
+//! `git-ast fmt`
+//!
+//! Rewrites working-tree files into the canonical format, i.e. exactly what
+//! the smudge filter would check out for them. Running it once after adopting
+//! `git-ast` converges a checkout onto the canonical layout in one reviewable
+//! step instead of file by file as they are touched.
+//!
+//! ## File Selection
+//!
+//! -   With paths: those files (relative to the current directory).
+//! -   `--changed`: tracked files modified in the working tree, plus untracked
+//!     files that are not ignored.
+//! -   Otherwise: every file in the index.
+//!
+//! Unless paths are given explicitly, only files with `filter=ast` in
+//! `.gitattributes` are considered, since only those are smudged on checkout.
+//! Files without a language provider are skipped, and so are symlinks,
+//! submodules and directories: checkout writes no source for them.
+//!
+//! The `postSmudge` hooks run as on checkout (and `preClean` first, on the
+//! file as it is), so hook output such as a generated header is kept.
+//!
+//! `--check` reports files that would change and fails instead of writing.
+
+use crate::codegen::Hooks;
+use crate::config::{self, RepoConfig};
+use crate::git_plumbing::filters;
+use crate::languages::LanguageProvider;
+use crate::parsing::ParseLimits;
+use crate::util::atomic_io;
+use crate::{log_error, Error};
+use clap::Args;
+use git2::{AttrCheckFlags, Repository, Status, StatusOptions};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Arguments for `git-ast fmt`.
+#[derive(Debug, Args)]
+pub struct FmtArgs {
+    /// Only format files changed in the working tree.
+    #[arg(long, conflicts_with = "paths")]
+    pub changed: bool,
+    /// Report files that are not canonical instead of rewriting them.
+    #[arg(long)]
+    pub check: bool,
+    /// Files to format (default: all files tracked with `filter=ast`).
+    pub paths: Vec<PathBuf>,
+}
+
+/// Runs `git-ast fmt`.
+pub fn run(args: &FmtArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| Error::Config("git-ast fmt needs a working tree".to_string()))?
+        .to_path_buf();
+    let limits = config::Settings::load(Some(&repo))?.parse_limits;
+    let repo_configs = config::RepoConfigs::new(Some(&repo));
+    let hooks = Hooks::for_repo(&repo)?.unwrap_or_default();
+
+    let candidates = if args.paths.is_empty() {
+        let paths = if args.changed {
+            changed_paths(&repo)?
+        } else {
+            indexed_paths(&repo)?
+        };
+        paths
+            .into_iter()
+            .filter(|p| uses_ast_filter(&repo, p))
+            .collect()
+    } else {
+        let cwd = std::env::current_dir()?;
+        args.paths
+            .iter()
+            .map(|p| relative_to(&workdir, &cwd.join(p)))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    let mut changed = 0;
+    let mut failed = 0;
+    for rel in candidates {
+        let pathname = rel.to_string_lossy().replace('\\', "/");
+        let file = workdir.join(&rel);
+        match fs::symlink_metadata(&file) {
+            Ok(metadata) if metadata.is_file() => {}
+            // A symlink, a submodule or a directory: not checked out as source.
+            Ok(_) => continue,
+            // Deleted in the working tree; nothing to format.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        }
+        let source = fs::read(&file)?;
+        let provider = match config::language_for_path(Some(&repo), &pathname, &source) {
+            Ok(Some(provider)) => provider,
+            Ok(None) => continue,
+            Err(e) => {
+                log_error!("fmt", "{}", e);
+                failed += 1;
+                continue;
+            }
+        };
+        let formatted = match repo_configs.for_path(&pathname).and_then(|repo_config| {
+            canonical(&source, &pathname, provider, &limits, &repo_config, &hooks)
+        }) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                log_error!("fmt", "{}: {}", pathname, e);
+                failed += 1;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        changed += 1;
+        println!("{}", pathname);
+        if !args.check {
+            atomic_io::write(&file, formatted)?;
+        }
+    }
+
+    if failed > 0 {
+        return Err(Error::Parsing(format!(
+            "{} file(s) could not be formatted",
+            failed
+        )));
+    }
+    if args.check && changed > 0 {
+        return Err(Error::Verification(format!(
+            "{} file(s) are not in canonical format",
+            changed
+        )));
+    }
+    Ok(())
+}
+
+/// Source as it would look after a clean/smudge round trip, hooks included.
+fn canonical(
+    source: &[u8],
+    pathname: &str,
+    provider: &'static LanguageProvider,
+    limits: &ParseLimits,
+    repo_config: &RepoConfig,
+    hooks: &Hooks,
+) -> Result<Vec<u8>, Error> {
+    let source = hooks.pre_clean(provider.name, pathname, source.to_vec())?;
+    let blob = filters::clean_with(&source, pathname, Some(provider), limits, repo_config)?;
+    let printed = filters::perform_smudge(&blob, pathname)?;
+    hooks.post_smudge(provider.name, pathname, printed)
+}
+
+/// The regular files in the index (not symlinks or submodules).
+fn indexed_paths(repo: &Repository) -> Result<Vec<PathBuf>, Error> {
+    let index = repo.index()?;
+    Ok(index
+        .iter()
+        .filter(|entry| matches!(entry.mode, 0o100644 | 0o100755))
+        .map(|entry| PathBuf::from(String::from_utf8_lossy(&entry.path).into_owned()))
+        .collect())
+}
+
+fn changed_paths(repo: &Repository) -> Result<Vec<PathBuf>, Error> {
+    let mut options = StatusOptions::new();
+    options.include_untracked(true).recurse_untracked_dirs(true);
+    let statuses = repo.statuses(Some(&mut options))?;
+    Ok(statuses
+        .iter()
+        .filter(|s| {
+            s.status()
+                .intersects(Status::WT_NEW | Status::WT_MODIFIED | Status::WT_RENAMED)
+        })
+        .filter_map(|s| s.path().map(PathBuf::from))
+        .collect())
+}
+
+fn uses_ast_filter(repo: &Repository, path: &Path) -> bool {
+    matches!(
+        repo.get_attr(path, "filter", AttrCheckFlags::FILE_THEN_INDEX),
+        Ok(Some("ast"))
+    )
+}
+
+/// `path` relative to the (canonicalized) `workdir`.
+pub(crate) fn relative_to(workdir: &Path, path: &Path) -> Result<PathBuf, Error> {
+    let workdir = workdir.canonicalize()?;
+    let path = path.canonicalize()?;
+    path.strip_prefix(&workdir)
+        .map(Path::to_path_buf)
+        .map_err(|_| Error::Config(format!("{} is outside the repository", path.display())))
+}
@@ -0,0 +1,229 @@
+//! `git-ast gc [--dry-run] [--prune <date>]`
+//!
+//! Mirror and sidecar syncs (see [`mirror`](crate::mirror)) and the notes
+//! channels create objects Git knows nothing special about: branches under
+//! `refs/ast/*` and notes in `refs/notes/ast-map`, `refs/notes/ast` and
+//! `refs/notes/ast-attestations`. When the branches they were made for go
+//! away, those objects stay reachable through the namespace and the notes
+//! forever. `git-ast gc`:
+//!
+//! 1.  Deletes `refs/ast/mirror/<branch>` and `refs/ast/heads/<branch>` whose
+//!     `refs/heads/<branch>` no longer exists.
+//! 2.  Finds every object reachable from the remaining refs (and `HEAD`),
+//!     then drops notes whose target is not among them: *dangling* semantic
+//!     objects. Map notes are kept while either side is reachable.
+//! 3.  Packs refs and runs `git gc`, which prunes the objects that became
+//!     unreachable (subject to `gc.pruneExpire`, or `--prune`).
+//!
+//! It reports how many semantic objects are reachable and how many were
+//! dangling. `--dry-run` only reports.
+
+use crate::attestation;
+use crate::git_plumbing::notes;
+use crate::mirror::{self, MIRROR_PREFIX, SIDECAR_PREFIX};
+use crate::Error;
+use clap::Args;
+use git2::{ObjectType, Oid, Repository, Tree};
+use std::collections::{BTreeSet, HashSet};
+use std::process::Command;
+
+/// Arguments for `git-ast gc`.
+#[derive(Debug, Args)]
+pub struct GcArgs {
+    /// Report what would be pruned without changing anything.
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Passed to `git gc --prune` (e.g. `now`).
+    #[arg(long, value_name = "DATE")]
+    pub prune: Option<String>,
+}
+
+/// What a collection found (or, with `--dry-run`, would do).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GcReport {
+    /// Namespace refs whose branch is gone.
+    pub stale_refs: Vec<String>,
+    /// Notes whose target is still reachable, per notes ref.
+    pub reachable: Vec<(String, usize)>,
+    /// Notes whose target is unreachable, per notes ref.
+    pub dangling: Vec<(String, BTreeSet<Oid>)>,
+}
+
+/// Runs `git-ast gc`.
+pub fn run(args: &GcArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let report = collect(&repo, args.dry_run)?;
+    for name in &report.stale_refs {
+        println!("stale ref: {}", name);
+    }
+    for ((notes_ref, reachable), (_, dangling)) in report.reachable.iter().zip(&report.dangling) {
+        println!(
+            "{}: {} reachable, {} dangling",
+            notes_ref,
+            reachable,
+            dangling.len()
+        );
+    }
+    if args.dry_run {
+        return Ok(());
+    }
+    git(&repo, &["pack-refs", "--all"])?;
+    let prune = args.prune.as_ref().map(|date| format!("--prune={}", date));
+    let mut gc = vec!["gc", "--quiet"];
+    gc.extend(prune.as_deref());
+    git(&repo, &gc)
+}
+
+/// Finds (and unless `dry_run`, removes) stale namespace refs and dangling
+/// notes.
+pub fn collect(repo: &Repository, dry_run: bool) -> Result<GcReport, Error> {
+    let mut report = GcReport::default();
+    for prefix in [MIRROR_PREFIX, SIDECAR_PREFIX] {
+        for reference in repo.references_glob(&format!("{}*", prefix))? {
+            let reference = reference?;
+            let Some(name) = reference.name() else {
+                continue;
+            };
+            let branch = format!("refs/heads/{}", &name[prefix.len()..]);
+            if repo.find_reference(&branch).is_err() {
+                report.stale_refs.push(name.to_string());
+            }
+        }
+    }
+    if !dry_run {
+        for name in &report.stale_refs {
+            repo.find_reference(name)?.delete()?;
+        }
+    }
+
+    let reachable = reachable_objects(repo, &report.stale_refs)?;
+    for notes_ref in [
+        mirror::MAP_REF,
+        notes::AST_NOTES_REF,
+        attestation::ATTESTATIONS_REF,
+    ] {
+        let mut live = 0;
+        let mut dangling = BTreeSet::new();
+        for target in notes::targets(repo, notes_ref)? {
+            let counterpart = match notes_ref {
+                mirror::MAP_REF => mirror::lookup(repo, target)?.map(|c| c.id),
+                _ => None,
+            };
+            if reachable.contains(&target) || counterpart.is_some_and(|c| reachable.contains(&c)) {
+                live += 1;
+            } else {
+                dangling.insert(target);
+            }
+        }
+        if !dry_run {
+            notes::remove(repo, notes_ref, &dangling, "git-ast gc")?;
+        }
+        report.reachable.push((notes_ref.to_string(), live));
+        report.dangling.push((notes_ref.to_string(), dangling));
+    }
+    Ok(report)
+}
+
+/// Every commit, tree and blob reachable from `HEAD` and the refs, not
+/// counting notes refs and the refs in `excluded`.
+fn reachable_objects(repo: &Repository, excluded: &[String]) -> Result<HashSet<Oid>, Error> {
+    let mut walk = repo.revwalk()?;
+    if let Ok(head) = repo.head() {
+        if let Ok(commit) = head.peel_to_commit() {
+            walk.push(commit.id())?;
+        }
+    }
+    for reference in repo.references()? {
+        let reference = reference?;
+        let name = reference.name().unwrap_or_default();
+        if name.starts_with("refs/notes/") || excluded.iter().any(|e| e == name) {
+            continue;
+        }
+        if let Ok(commit) = reference.peel_to_commit() {
+            walk.push(commit.id())?;
+        }
+    }
+    let mut seen = HashSet::new();
+    for id in walk {
+        let commit = repo.find_commit(id?)?;
+        seen.insert(commit.id());
+        add_tree(repo, &commit.tree()?, &mut seen)?;
+    }
+    Ok(seen)
+}
+
+fn add_tree(repo: &Repository, tree: &Tree<'_>, seen: &mut HashSet<Oid>) -> Result<(), Error> {
+    if !seen.insert(tree.id()) {
+        return Ok(());
+    }
+    for entry in tree.iter() {
+        match entry.kind() {
+            Some(ObjectType::Tree) => add_tree(repo, &repo.find_tree(entry.id())?, seen)?,
+            Some(ObjectType::Blob) => {
+                seen.insert(entry.id());
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn git(repo: &Repository, args: &[&str]) -> Result<(), Error> {
+    let status = Command::new("git")
+        .arg("--git-dir")
+        .arg(repo.path())
+        .args(args)
+        .status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::Driver(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            status
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mirror::Direction;
+
+    #[test]
+    fn prunes_namespace_and_notes_of_deleted_branches() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let signature = git2::Signature::now("t", "t@example.com").unwrap();
+        let commit = |branch: &str, content: &[u8]| {
+            let mut builder = repo.treebuilder(None).unwrap();
+            builder
+                .insert("a.rs", repo.blob(content).unwrap(), 0o100644)
+                .unwrap();
+            let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+            let reference = format!("refs/heads/{}", branch);
+            repo.commit(Some(&reference), &signature, &signature, branch, &tree, &[])
+                .unwrap()
+        };
+        commit("main", b"fn main() {}\n");
+        commit("topic", b"fn topic() {}\n");
+        mirror::sync(&repo, "main", Direction::ToAst).unwrap();
+        mirror::sync(&repo, "topic", Direction::ToAst).unwrap();
+        repo.find_reference("refs/heads/topic")
+            .unwrap()
+            .delete()
+            .unwrap();
+
+        let preview = collect(&repo, true).unwrap();
+        assert_eq!(preview.stale_refs, ["refs/ast/heads/topic"]);
+        // Source and AST commit and blob on each side.
+        assert_eq!(preview.reachable[0], (mirror::MAP_REF.to_string(), 4));
+        assert_eq!(preview.dangling[0].1.len(), 4);
+
+        collect(&repo, false).unwrap();
+        assert!(repo.find_reference("refs/ast/heads/topic").is_err());
+        let after = collect(&repo, true).unwrap();
+        assert!(after.stale_refs.is_empty());
+        assert!(after.dangling[0].1.is_empty());
+    }
+}
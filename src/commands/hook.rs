@@ -0,0 +1,231 @@
+//! `git-ast hook <name>`
+//!
+//! Entry points for Git hooks. Install by adding a one-line hook script:
+//!
+//! ```sh
+//! # .git/hooks/pre-push
+//! #!/bin/sh
+//! exec git-ast hook pre-push "$@"
+//! ```
+//!
+//! ## `pre-push`
+//!
+//! Before anything leaves the local repository, every AST blob added or
+//! changed by the outgoing commits is re-verified with
+//! [`verify_round_trip`](crate::git_plumbing::filters::verify_round_trip):
+//! it must smudge and clean back to the identical bytes, and its recorded
+//! grammar version must match the one this build uses. A failure aborts the
+//! push and lists the offending paths. Blobs that are not AST blobs are
+//! ignored.
+//!
+//! ## `post-commit`
+//!
+//! Attaches merge driver reports saved with `ast.mergeNotes = true` to the
+//! commit that concluded the merge, as the `merge` section of its note in
+//! `refs/notes/ast` (see [`record_merge_note`](crate::drivers::record_merge_note)).
+//! Reports from a merge that was aborted instead are discarded.
+//!
+//! It also moves the attestations the clean filter signed with
+//! `ast.attest = true` into `refs/notes/ast-attestations` (see
+//! [`attestation`](crate::attestation)).
+//!
+//! ## `post-merge`
+//!
+//! With `ast.sidecar = true`, both `post-commit` and `post-merge` also bring
+//! the current branch's sidecar AST history up to date, and with
+//! `ast.exportBranch = true` its `source/` export (see
+//! [`mirror`](crate::mirror)).
+
+use crate::attestation;
+use crate::config;
+use crate::drivers;
+use crate::git_plumbing::{filters, notes};
+use crate::mirror;
+use crate::serialization;
+use crate::{log_error, log_info, Error};
+use clap::Subcommand;
+use git2::{FileMode, Oid, Repository};
+use std::collections::{BTreeMap, HashSet};
+use std::io::BufRead;
+
+/// Supported hooks.
+#[derive(Debug, Subcommand)]
+pub enum HookCommand {
+    /// Verify outgoing AST blobs (reads ref updates from stdin, as Git passes them).
+    PrePush {
+        /// Name of the remote (unused).
+        remote: Option<String>,
+        /// URL of the remote (unused).
+        url: Option<String>,
+    },
+    /// Attach pending merge reports and attestations; update the sidecar and export.
+    PostCommit,
+    /// Update the sidecar and export after a merge or pull.
+    PostMerge {
+        /// Whether the merge was a squash (unused).
+        squash: Option<String>,
+    },
+}
+
+/// Runs the selected hook.
+pub fn run(command: &HookCommand) -> Result<(), Error> {
+    match command {
+        HookCommand::PrePush { .. } => {
+            let repo = Repository::open_from_env()?;
+            let stdin = std::io::stdin();
+            let mut updates = Vec::new();
+            for line in stdin.lock().lines() {
+                updates.push(line?);
+            }
+            pre_push(&repo, &updates)
+        }
+        HookCommand::PostCommit => {
+            let repo = Repository::open_from_env()?;
+            post_commit(&repo)?;
+            attestation::flush(&repo)?;
+            update_sidecar(&repo)?;
+            update_export(&repo)
+        }
+        HookCommand::PostMerge { .. } => {
+            let repo = Repository::open_from_env()?;
+            update_sidecar(&repo)?;
+            update_export(&repo)
+        }
+    }
+}
+
+/// Syncs the sidecar of the current branch if `ast.sidecar` is enabled.
+pub fn update_sidecar(repo: &Repository) -> Result<(), Error> {
+    if !config::Settings::load(Some(repo))?.sidecar {
+        return Ok(());
+    }
+    let head = repo.head()?;
+    let Some(branch) = head.shorthand().filter(|_| head.is_branch()) else {
+        return Ok(()); // Detached HEAD: nothing to name the sidecar after.
+    };
+    let report = mirror::sync(repo, branch, mirror::Direction::ToAst)?;
+    log_info!(
+        "hook",
+        "sidecar: {} ({} new commits)",
+        report.reference,
+        report.rewritten
+    );
+    Ok(())
+}
+
+/// Exports the current branch if `ast.exportBranch` is enabled.
+pub fn update_export(repo: &Repository) -> Result<(), Error> {
+    if !config::Settings::load(Some(repo))?.export_branch {
+        return Ok(());
+    }
+    let head = repo.head()?;
+    let Some(branch) = head.shorthand().filter(|_| head.is_branch()) else {
+        return Ok(());
+    };
+    if branch.starts_with("source/") {
+        return Ok(());
+    }
+    let report = mirror::export(repo, branch, false)?;
+    log_info!(
+        "hook",
+        "export: {} ({} new commits)",
+        report.reference,
+        report.rewritten
+    );
+    Ok(())
+}
+
+/// Moves pending merge reports onto `HEAD` if they were recorded on its
+/// first parent, and clears them either way.
+pub fn post_commit(repo: &Repository) -> Result<(), Error> {
+    let dir = repo.path().join(drivers::PENDING_MERGE_NOTES_DIR);
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return Ok(());
+    };
+    let head = repo.head()?.peel_to_commit()?;
+    let parent = head.parent_id(0).ok().map(|id| id.to_string());
+    let mut reports = Vec::new();
+    for entry in entries {
+        let text = std::fs::read_to_string(entry?.path())?;
+        let Ok(record) = serde_json::from_str::<serde_json::Value>(&text) else {
+            continue;
+        };
+        if record["head"].as_str() == parent.as_deref() {
+            reports.push(record["report"].clone());
+        }
+    }
+    std::fs::remove_dir_all(&dir)?;
+    if !reports.is_empty() {
+        let records = BTreeMap::from([(head.id(), serde_json::Value::Array(reports))]);
+        notes::attach(repo, "merge", &records)?;
+        log_info!("hook", "post-commit: recorded merge notes on {}", head.id());
+    }
+    Ok(())
+}
+
+/// Verifies the AST blobs introduced by the given pre-push ref updates
+/// (`<local ref> <local sha> <remote ref> <remote sha>` lines).
+pub fn pre_push(repo: &Repository, updates: &[String]) -> Result<(), Error> {
+    let mut seen = HashSet::new();
+    let mut failures = Vec::new();
+    let mut checked = 0;
+    for update in updates {
+        let fields: Vec<&str> = update.split_whitespace().collect();
+        let [_, local, _, remote] = fields[..] else {
+            return Err(Error::Verification(format!(
+                "malformed ref update: {:?}",
+                update
+            )));
+        };
+        let local = Oid::from_str(local)?;
+        if local.is_zero() {
+            continue; // Deleting a remote ref sends nothing.
+        }
+        let mut walk = repo.revwalk()?;
+        walk.push(local)?;
+        let remote = Oid::from_str(remote)?;
+        if !remote.is_zero() && repo.find_commit(remote).is_ok() {
+            walk.hide(remote)?;
+        }
+        for commit in walk {
+            let commit = repo.find_commit(commit?)?;
+            let parent = match commit.parent(0) {
+                Ok(parent) => Some(parent.tree()?),
+                Err(_) => None,
+            };
+            let diff = repo.diff_tree_to_tree(parent.as_ref(), Some(&commit.tree()?), None)?;
+            for delta in diff.deltas() {
+                let file = delta.new_file();
+                if file.id().is_zero() || file.mode() == FileMode::Commit || !seen.insert(file.id())
+                {
+                    continue;
+                }
+                let blob = repo.find_blob(file.id())?;
+                if !serialization::is_ast_blob(blob.content()) {
+                    continue;
+                }
+                let path = file
+                    .path()
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_default();
+                checked += 1;
+                if let Err(e) = filters::verify_round_trip(blob.content(), &path) {
+                    failures.push(e.to_string());
+                }
+            }
+        }
+    }
+
+    log_info!("hook", "pre-push: verified {} AST blob(s)", checked);
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        for failure in &failures {
+            log_error!("hook", "{}", failure);
+        }
+        Err(Error::Verification(format!(
+            "{} AST blob(s) failed verification; push aborted",
+            failures.len()
+        )))
+    }
+}
@@ -0,0 +1,114 @@
+//! `git-ast index diff|verify|show|write|build`
+//!
+//! Works on the staged AST blobs directly (see [`staged`]), except for
+//! `build`, which updates the [symbol history index](crate::symbol_history):
+//!
+//! ```text
+//! $ git-ast index diff                 # staged changes vs HEAD, semantically
+//! $ git-ast index verify               # round-trip check of every staged blob
+//! $ git-ast index show src/lib.rs      # the staged version as source
+//! $ git-ast index write src/lib.rs < fixed.rs   # stage new content, worktree untouched
+//! $ git-ast index build                # index the symbols changed by new commits
+//! ```
+
+use crate::config;
+use crate::diff_backend::DiffFormat;
+use crate::staged;
+use crate::symbol_history::{BuildOptions, SymbolHistoryIndex};
+use crate::Error;
+use clap::Subcommand;
+use git2::Repository;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+/// `git-ast index` subcommands.
+#[derive(Debug, Subcommand)]
+pub enum IndexCommand {
+    /// Show staged changes against HEAD on canonical source.
+    Diff {
+        /// Output format.
+        #[arg(long, value_enum, default_value = "readable")]
+        format: DiffFormat,
+        /// Limit to these paths (pathspecs, relative to the repository root).
+        paths: Vec<String>,
+    },
+    /// Check that every staged AST blob prints and cleans back unchanged.
+    Verify,
+    /// Print the staged version of a file as source.
+    Show {
+        /// Path relative to the repository root.
+        path: String,
+    },
+    /// Clean content and stage it for a path, without touching the worktree.
+    Write {
+        /// Path relative to the repository root.
+        path: String,
+        /// Read the content from this file (default: stdin).
+        #[arg(long)]
+        from: Option<PathBuf>,
+    },
+    /// Build or update the symbol history index (`.git/ast/symbol-history`).
+    Build {
+        /// Rebuild from scratch instead of indexing only new commits.
+        #[arg(long)]
+        full: bool,
+        /// Worker threads (default: one per CPU).
+        #[arg(long)]
+        jobs: Option<usize>,
+    },
+}
+
+/// Runs a `git-ast index` subcommand.
+pub fn run(command: &IndexCommand) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    match command {
+        IndexCommand::Diff { format, paths } => {
+            std::io::stdout().write_all(&staged::diff(&repo, paths, *format)?)?;
+            Ok(())
+        }
+        IndexCommand::Verify => {
+            let failures = staged::verify(&repo)?;
+            for (path, reason) in &failures {
+                println!("{}: {}", path, reason);
+            }
+            match failures.len() {
+                0 => Ok(()),
+                n => Err(Error::Verification(format!("{} staged file(s) failed", n))),
+            }
+        }
+        IndexCommand::Show { path } => {
+            std::io::stdout().write_all(&staged::source(&repo, path)?)?;
+            Ok(())
+        }
+        IndexCommand::Write { path, from } => {
+            let source = match from {
+                Some(file) => std::fs::read(file)?,
+                None => {
+                    let mut source = Vec::new();
+                    std::io::stdin().read_to_end(&mut source)?;
+                    source
+                }
+            };
+            let id = staged::write(&repo, path, &source)?;
+            println!("{} {}", id, path);
+            Ok(())
+        }
+        IndexCommand::Build { full, jobs } => {
+            let limits = config::Settings::load(Some(&repo))?.parse_limits;
+            let mut options = BuildOptions {
+                full: *full,
+                ..BuildOptions::default()
+            };
+            options.jobs = jobs.unwrap_or(options.jobs);
+            let stats = SymbolHistoryIndex::build(&repo, &options, &limits)?;
+            if stats.resumed {
+                println!("resumed an interrupted build");
+            }
+            println!(
+                "indexed {} new commit(s); {} commits, {} symbols",
+                stats.new_commits, stats.commits, stats.symbols
+            );
+            Ok(())
+        }
+    }
+}
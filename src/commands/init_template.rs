@@ -0,0 +1,132 @@
+//! `git-ast init-template <dir> [--command <cmd>] [--force]`
+//!
+//! Writes a Git template directory, so that `git init --template=<dir>` and
+//! `git clone --template=<dir>` (or `init.templateDir` in the global config)
+//! create repositories already set up for `git-ast`:
+//!
+//! ```text
+//! <dir>/config            [include] of git-ast.config (copied into .git/config)
+//! <dir>/git-ast.config    the ast filter and drivers (see toolchain::driver_config)
+//! <dir>/info/attributes   filter=ast diff=ast merge=ast for every known extension
+//! <dir>/hooks/pre-push, post-commit, post-merge   exec git-ast hook <name>
+//! ```
+//!
+//! The driver definitions stay in `git-ast.config`, which every repository
+//! created from the template includes by absolute path, so an organization
+//! can change them in one place. `--command` sets how they run `git-ast`
+//! (default: `git-ast` on `PATH`). Existing files with other content are
+//! only replaced with `--force`.
+
+use crate::languages;
+use crate::toolchain;
+use crate::util::atomic_io;
+use crate::Error;
+use clap::Args;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Hooks the template installs (see [`hook`](super::hook)).
+const HOOKS: &[&str] = &["pre-push", "post-commit", "post-merge"];
+
+/// Arguments for `git-ast init-template`.
+#[derive(Debug, Args)]
+pub struct InitTemplateArgs {
+    /// The template directory to write.
+    pub dir: PathBuf,
+    /// How the filter, drivers and hooks run git-ast.
+    #[arg(long, default_value = "git-ast")]
+    pub command: String,
+    /// Replace existing files that differ.
+    #[arg(long)]
+    pub force: bool,
+}
+
+/// Runs `git-ast init-template`.
+pub fn run(args: &InitTemplateArgs) -> Result<(), Error> {
+    fs::create_dir_all(&args.dir)?;
+    let dir = fs::canonicalize(&args.dir)?;
+    let files = template_files(&dir, &args.command);
+    let conflicts: Vec<&str> = files
+        .iter()
+        .filter(|(path, content)| {
+            fs::read(dir.join(path)).is_ok_and(|existing| existing != content.as_bytes())
+        })
+        .map(|(path, _)| path.as_str())
+        .collect();
+    if !conflicts.is_empty() && !args.force {
+        return Err(Error::Config(format!(
+            "{} already exist(s) with other content; use --force to replace",
+            conflicts.join(", ")
+        )));
+    }
+    for (path, content) in &files {
+        let target = dir.join(path);
+        atomic_io::write(&target, content)?;
+        #[cfg(unix)]
+        if path.starts_with("hooks/") {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&target, fs::Permissions::from_mode(0o755))?;
+        }
+        println!("wrote {}", target.display());
+    }
+    println!(
+        "use it with `git init --template={0}` or `git config --global init.templateDir {0}`",
+        dir.display()
+    );
+    Ok(())
+}
+
+/// The template's files by path relative to `dir`.
+fn template_files(dir: &Path, command: &str) -> Vec<(String, String)> {
+    let include = dir.join("git-ast.config");
+    let mut files = vec![
+        (
+            "config".to_string(),
+            format!(
+                "[include]\n\tpath = {}\n",
+                quote(&include.display().to_string())
+            ),
+        ),
+        (
+            "git-ast.config".to_string(),
+            config_file(&toolchain::driver_config(command)),
+        ),
+    ];
+    let mut attributes = String::from("# Written by `git-ast init-template`.\n");
+    for provider in languages::all() {
+        for extension in provider.extensions {
+            attributes.push_str(&format!("*.{} filter=ast diff=ast merge=ast\n", extension));
+        }
+    }
+    files.push(("info/attributes".to_string(), attributes));
+    for hook in HOOKS {
+        let script = format!("#!/bin/sh\nexec {} hook {} \"$@\"\n", command, hook);
+        files.push((format!("hooks/{}", hook), script));
+    }
+    files
+}
+
+/// `entries` (`section.subsection.key`) as a Git config file.
+fn config_file(entries: &[(&str, String)]) -> String {
+    let mut out = String::new();
+    let mut current = "";
+    for (key, value) in entries {
+        let (section, name) = key.rsplit_once('.').expect("section.key");
+        if section != current {
+            match section.split_once('.') {
+                Some((section, subsection)) => {
+                    out.push_str(&format!("[{} {}]\n", section, quote(subsection)))
+                }
+                None => out.push_str(&format!("[{}]\n", section)),
+            }
+            current = section;
+        }
+        out.push_str(&format!("\t{} = {}\n", name, quote(value)));
+    }
+    out
+}
+
+/// A double-quoted Git config string.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,241 @@
+//! `git-ast languages [--paths] [--format json]`
+//!
+//! Lists the languages this build supports and, inside a repository, how
+//! much of it they cover, so gaps show up before adopting `git-ast`:
+//!
+//! ```text
+//! $ git-ast languages
+//! LANGUAGE    GRAMMAR  EXTENSIONS         FILES  FEATURES
+//! rust        0.23.3   rs                 12/14  clean smudge diff merge symbols sort-imports injections
+//! python      0.23.6   py pyi             0      clean smudge diff merge symbols sort-imports injections
+//! ...
+//! regex       0.24.3   (embedded)         0      clean smudge diff merge symbols
+//! ...
+//! not covered: 23 *.md, 4 *.toml, 1 *.go
+//! ```
+//!
+//! `FILES` counts the tracked files mapped to each language (by extension,
+//! shebang or the `ast-lang` attribute, see
+//! [`language_for_path`](crate::config::language_for_path)), and of those
+//! how many have `filter=ast` and so are stored as trees; `--paths` lists
+//! them, marking the ones without the filter. Every language supports the
+//! core features (`clean`, `smudge`, `diff`, `merge`, `symbols`); the
+//! optional ones are `sort-imports` (see [`normalize`](crate::normalize))
+//! and `injections` (see [`injections`](crate::injections)).
+
+use crate::config;
+use crate::languages::{self, LanguageProvider};
+use crate::Error;
+use clap::{Args, ValueEnum};
+use git2::{AttrCheckFlags, Repository};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Arguments for `git-ast languages`.
+#[derive(Debug, Args)]
+pub struct LanguagesArgs {
+    /// List the tracked paths of each language.
+    #[arg(long)]
+    pub paths: bool,
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = LanguagesFormat::Text)]
+    pub format: LanguagesFormat,
+}
+
+/// Output formats of `git-ast languages`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LanguagesFormat {
+    /// A table.
+    Text,
+    /// A single JSON document.
+    Json,
+}
+
+/// A supported language and its files in the repository.
+#[derive(Debug, Clone, Serialize)]
+pub struct LanguageInfo {
+    pub name: &'static str,
+    pub grammar_version: &'static str,
+    pub extensions: &'static [&'static str],
+    pub features: Vec<&'static str>,
+    /// Tracked paths mapped to the language, and whether each has `filter=ast`.
+    pub paths: Vec<(String, bool)>,
+}
+
+/// Every language and the tracked files none of them covers.
+#[derive(Debug, Clone, Serialize)]
+pub struct Coverage {
+    pub languages: Vec<LanguageInfo>,
+    /// Tracked files without a language, by extension (`""` for none).
+    pub not_covered: BTreeMap<String, usize>,
+}
+
+/// The features `provider` supports.
+pub fn features(provider: &LanguageProvider) -> Vec<&'static str> {
+    let mut features = vec!["clean", "smudge", "diff", "merge", "symbols"];
+    if !provider.syntax.imports.is_empty() {
+        features.push("sort-imports");
+    }
+    if !provider.injections.trim().is_empty() {
+        features.push("injections");
+    }
+    features
+}
+
+/// The supported languages and, given a repository, its tracked files.
+pub fn coverage(repo: Option<&Repository>) -> Result<Coverage, Error> {
+    let mut languages: Vec<LanguageInfo> = languages::all()
+        .iter()
+        .map(|provider| LanguageInfo {
+            name: provider.name,
+            grammar_version: provider.grammar_version,
+            extensions: provider.extensions,
+            features: features(provider),
+            paths: Vec::new(),
+        })
+        .collect();
+    let mut not_covered = BTreeMap::new();
+    let Some(repo) = repo else {
+        return Ok(Coverage {
+            languages,
+            not_covered,
+        });
+    };
+    let workdir = repo.workdir();
+    for entry in repo.index()?.iter() {
+        let path = String::from_utf8_lossy(&entry.path).into_owned();
+        // The head of the file is enough to sniff a shebang or modeline.
+        let content = workdir
+            .and_then(|dir| std::fs::read(dir.join(&path)).ok())
+            .map(|mut content| {
+                content.truncate(1024);
+                content
+            })
+            .unwrap_or_default();
+        match config::language_for_path(Some(repo), &path, &content)? {
+            Some(provider) => {
+                let filtered = matches!(
+                    repo.get_attr(Path::new(&path), "filter", AttrCheckFlags::FILE_THEN_INDEX),
+                    Ok(Some("ast"))
+                );
+                if let Some(info) = languages.iter_mut().find(|l| l.name == provider.name) {
+                    info.paths.push((path, filtered));
+                }
+            }
+            None => {
+                let extension = Path::new(&path)
+                    .extension()
+                    .map(|e| e.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                *not_covered.entry(extension).or_default() += 1;
+            }
+        }
+    }
+    Ok(Coverage {
+        languages,
+        not_covered,
+    })
+}
+
+/// Runs `git-ast languages`.
+pub fn run(args: &LanguagesArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env().ok();
+    let coverage = coverage(repo.as_ref())?;
+    if args.format == LanguagesFormat::Json {
+        let json = serde_json::to_string_pretty(&coverage)
+            .map_err(|e| Error::Serialization(format!("languages: {}", e)))?;
+        println!("{}", json);
+        return Ok(());
+    }
+    // Languages without extensions are only parsed inside others (regexes,
+    // templates); see `injections`.
+    let extensions = |info: &LanguageInfo| match info.extensions {
+        [] => "(embedded)".to_string(),
+        extensions => extensions.join(" "),
+    };
+    let width = coverage
+        .languages
+        .iter()
+        .map(|info| extensions(info).len())
+        .max()
+        .unwrap_or(0);
+    println!(
+        "{:<11} {:<8} {:<width$} {:<6} FEATURES",
+        "LANGUAGE", "GRAMMAR", "EXTENSIONS", "FILES"
+    );
+    for info in &coverage.languages {
+        let filtered = info.paths.iter().filter(|(_, filtered)| *filtered).count();
+        let files = match (repo.is_some(), info.paths.len()) {
+            (false, _) => "-".to_string(),
+            (true, 0) => "0".to_string(),
+            (true, n) => format!("{}/{}", filtered, n),
+        };
+        println!(
+            "{:<11} {:<8} {:<width$} {:<6} {}",
+            info.name,
+            info.grammar_version,
+            extensions(info),
+            files,
+            info.features.join(" ")
+        );
+        if args.paths {
+            for (path, filtered) in &info.paths {
+                let note = if *filtered { "" } else { "  (no filter=ast)" };
+                println!("    {}{}", path, note);
+            }
+        }
+    }
+    if !coverage.not_covered.is_empty() {
+        let mut counts: Vec<_> = coverage.not_covered.iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let counts: Vec<String> = counts
+            .into_iter()
+            .map(|(extension, n)| match extension.as_str() {
+                "" => format!("{} without extension", n),
+                extension => format!("{} *.{}", n, extension),
+            })
+            .collect();
+        println!("not covered: {}", counts.join(", "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_tracked_paths_to_languages() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let files = [
+            ("src/lib.rs", "fn main() {}\n"),
+            ("tools/gen.rs", "fn main() {}\n"),
+            ("bin/run", "#!/usr/bin/env python3\nprint(1)\n"),
+            ("README.md", "# readme\n"),
+            (".gitattributes", "src/*.rs filter=ast\n"),
+        ];
+        let mut index = repo.index().unwrap();
+        for (path, text) in files {
+            let file = dir.path().join(path);
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(&file, text).unwrap();
+            index.add_path(Path::new(path)).unwrap();
+        }
+        index.write().unwrap();
+
+        let coverage = coverage(Some(&repo)).unwrap();
+        let language = |name: &str| coverage.languages.iter().find(|l| l.name == name).unwrap();
+        assert_eq!(
+            language("rust").paths,
+            [
+                ("src/lib.rs".to_string(), true),
+                ("tools/gen.rs".to_string(), false)
+            ]
+        );
+        assert_eq!(language("python").paths, [("bin/run".to_string(), false)]);
+        assert_eq!(coverage.not_covered.get("md"), Some(&1));
+        assert!(language("rust").features.contains(&"sort-imports"));
+    }
+}
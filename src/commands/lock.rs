@@ -0,0 +1,66 @@
+//! `git-ast lock`
+//!
+//! Writes `.git-ast.lock`, pinning the grammar and printer of every language
+//! with AST blobs in the index to this build (see
+//! [`lockfile`](crate::lockfile)). `--check` verifies the committed file
+//! instead: every locked language must match this build, and every language
+//! in the index must be locked. CI runs it so that a contributor's upgrade
+//! cannot change the printed source without also updating the lockfile.
+
+use crate::languages;
+use crate::lockfile::{self, Lockfile, LOCKFILE};
+use crate::Error;
+use clap::Args;
+use git2::Repository;
+
+/// Arguments for `git-ast lock`.
+#[derive(Debug, Args)]
+pub struct LockArgs {
+    /// Verify the lockfile against this build instead of writing it.
+    #[arg(long)]
+    pub check: bool,
+}
+
+/// Runs `git-ast lock`.
+pub fn run(args: &LockArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let indexed = lockfile::indexed_languages(&repo)?;
+    if !args.check {
+        let lock = Lockfile::for_languages(indexed.iter().copied());
+        lock.write(&repo)?;
+        for (name, locked) in &lock.languages {
+            println!(
+                "{}: grammar {}, printer {}",
+                name, locked.grammar, locked.printer
+            );
+        }
+        return Ok(());
+    }
+
+    let lock = Lockfile::load(&repo)?.ok_or_else(|| {
+        Error::Config(format!("no {}; run `git-ast lock` to write one", LOCKFILE))
+    })?;
+    for name in lock.languages.keys() {
+        let provider = languages::by_name(name).ok_or_else(|| {
+            Error::Config(format!(
+                "{} pins {}, which this git-ast does not support",
+                LOCKFILE, name
+            ))
+        })?;
+        lock.check(provider)?;
+    }
+    let unlocked: Vec<&str> = indexed
+        .iter()
+        .map(|provider| provider.name)
+        .filter(|name| !lock.languages.contains_key(*name))
+        .collect();
+    if !unlocked.is_empty() {
+        return Err(Error::Config(format!(
+            "{} does not pin {}; run `git-ast lock` and commit it",
+            LOCKFILE,
+            unlocked.join(", ")
+        )));
+    }
+    println!("{} matches this git-ast", LOCKFILE);
+    Ok(())
+}
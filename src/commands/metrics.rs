@@ -0,0 +1,33 @@
+//! `git-ast metrics [--reset]`
+//!
+//! Prints the metrics collected in this repository (with `ast.metrics =
+//! true`) in the Prometheus text format; see
+//! [`telemetry`](crate::telemetry). `--reset` deletes the totals instead.
+
+use crate::telemetry;
+use crate::Error;
+use clap::Args;
+use git2::Repository;
+use std::fs;
+
+/// Arguments for `git-ast metrics`.
+#[derive(Debug, Args)]
+pub struct MetricsArgs {
+    /// Delete the collected totals.
+    #[arg(long)]
+    pub reset: bool,
+}
+
+/// Runs `git-ast metrics`.
+pub fn run(args: &MetricsArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let path = telemetry::metrics_path(&repo);
+    if args.reset {
+        return match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        };
+    }
+    print!("{}", telemetry::render(&path)?);
+    Ok(())
+}
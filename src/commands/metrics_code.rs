@@ -0,0 +1,104 @@
+//! `git-ast metrics-code [<rev>] [<path>...] [--format text|json|csv]`
+//!
+//! Per-file and per-definition code metrics (see
+//! [`code_metrics`](crate::code_metrics)). CSV has one row per file, with
+//! an empty `kind` and `name`, followed by one per definition in it:
+//!
+//! ```text
+//! $ git-ast metrics-code src/merge.rs --format csv
+//! path,kind,name,line,lines,params,depth,cyclomatic
+//! src/merge.rs,,,1,412,,4,17
+//! src/merge.rs,function_item,merge,40,96,4,4,17
+//! ...
+//! ```
+
+use crate::code_metrics::{self, FileMetrics};
+use crate::config;
+use crate::queries::QueryPacks;
+use crate::Error;
+use clap::{Args, ValueEnum};
+use git2::Repository;
+
+/// Arguments for `git-ast metrics-code`.
+#[derive(Debug, Args)]
+pub struct MetricsCodeArgs {
+    /// Revision to measure.
+    #[arg(long, default_value = "HEAD")]
+    pub rev: String,
+    /// Only files under these paths, relative to the repository root.
+    pub paths: Vec<String>,
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = MetricsCodeFormat::Text)]
+    pub format: MetricsCodeFormat,
+}
+
+/// Output formats of `git-ast metrics-code`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MetricsCodeFormat {
+    /// A table per file.
+    Text,
+    /// A single JSON document.
+    Json,
+    /// Comma-separated values with a header row.
+    Csv,
+}
+
+/// Runs `git-ast metrics-code`.
+pub fn run(args: &MetricsCodeArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let limits = config::Settings::load(Some(&repo))?.parse_limits;
+    let mut packs = QueryPacks::for_repo(&repo);
+    let files = code_metrics::measure(&repo, &args.rev, &args.paths, &limits, &mut packs)?;
+    match args.format {
+        MetricsCodeFormat::Json => {
+            let json = serde_json::to_string_pretty(&files)
+                .map_err(|e| Error::Serialization(format!("metrics-code: {}", e)))?;
+            println!("{}", json);
+        }
+        MetricsCodeFormat::Csv => print_csv(&files),
+        MetricsCodeFormat::Text => {
+            for file in &files {
+                println!(
+                    "{}  {} lines, depth {}, cyclomatic {}",
+                    file.path, file.lines, file.depth, file.cyclomatic
+                );
+                for s in &file.symbols {
+                    println!(
+                        "    {:>5}  {:<20} {:<24} {:>4} lines  {} params  depth {}  cyclomatic {}",
+                        s.line, s.kind, s.name, s.lines, s.params, s.depth, s.cyclomatic
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_csv(files: &[FileMetrics]) {
+    // Quotes a field if it needs it (RFC 4180).
+    let field = |text: &str| match text.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", text.replace('"', "\"\"")),
+        false => text.to_string(),
+    };
+    println!("path,kind,name,line,lines,params,depth,cyclomatic");
+    for file in files {
+        let path = field(&file.path);
+        println!(
+            "{},,,1,{},,{},{}",
+            path, file.lines, file.depth, file.cyclomatic
+        );
+        for s in &file.symbols {
+            println!(
+                "{},{},{},{},{},{},{},{}",
+                path,
+                field(&s.kind),
+                field(&s.name),
+                s.line,
+                s.lines,
+                s.params,
+                s.depth,
+                s.cyclomatic
+            );
+        }
+    }
+}
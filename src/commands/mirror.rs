@@ -0,0 +1,120 @@
+//! `git-ast mirror sync`, `git-ast sidecar sync`, `git-ast export-branch`
+//! and `git-ast map`
+//!
+//! See the [`mirror`](crate::mirror) module for how the source mirror is
+//! produced and how the OID map is stored.
+
+use crate::mirror::{self, Direction};
+use crate::Error;
+use clap::{Args, Subcommand};
+use git2::Repository;
+
+/// `git-ast mirror` subcommands.
+#[derive(Debug, Subcommand)]
+pub enum MirrorCommand {
+    /// Rewrite branches into source form under `refs/ast/mirror/`.
+    Sync {
+        /// Branches to mirror.
+        #[arg(required = true)]
+        branches: Vec<String>,
+    },
+}
+
+/// `git-ast sidecar` subcommands.
+#[derive(Debug, Subcommand)]
+pub enum SidecarCommand {
+    /// Record AST versions of branches under `refs/ast/heads/`.
+    Sync {
+        /// Branches to process (default: the current branch).
+        branches: Vec<String>,
+    },
+}
+
+/// Arguments for `git-ast export-branch`.
+#[derive(Debug, Args)]
+pub struct ExportBranchArgs {
+    /// AST branches to export (default: the current branch).
+    pub branches: Vec<String>,
+    /// Replace `source/<branch>` even if it was moved outside git-ast.
+    #[arg(long)]
+    pub force: bool,
+}
+
+/// Arguments for `git-ast map`.
+#[derive(Debug, Args)]
+pub struct MapArgs {
+    /// Commit or blob, on either side (anything `git rev-parse` accepts).
+    pub object: String,
+}
+
+/// Runs a `git-ast mirror` subcommand.
+pub fn run_mirror(command: &MirrorCommand) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    match command {
+        MirrorCommand::Sync { branches } => sync(&repo, branches, Direction::ToSource),
+    }
+}
+
+/// Runs a `git-ast sidecar` subcommand.
+pub fn run_sidecar(command: &SidecarCommand) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    match command {
+        SidecarCommand::Sync { branches } if branches.is_empty() => {
+            sync(&repo, &[current_branch(&repo)?], Direction::ToAst)
+        }
+        SidecarCommand::Sync { branches } => sync(&repo, branches, Direction::ToAst),
+    }
+}
+
+/// Runs `git-ast export-branch`.
+pub fn run_export_branch(args: &ExportBranchArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let branches = match args.branches.is_empty() {
+        true => vec![current_branch(&repo)?],
+        false => args.branches.clone(),
+    };
+    for branch in &branches {
+        let report = mirror::export(&repo, branch, args.force)?;
+        println!(
+            "{} {} ({} new commits)",
+            report.reference, report.head, report.rewritten
+        );
+    }
+    Ok(())
+}
+
+fn current_branch(repo: &Repository) -> Result<String, Error> {
+    let head = repo.head()?;
+    head.shorthand()
+        .filter(|_| head.is_branch())
+        .map(str::to_string)
+        .ok_or_else(|| Error::Config("HEAD is not on a branch".to_string()))
+}
+
+fn sync(repo: &Repository, branches: &[String], direction: Direction) -> Result<(), Error> {
+    for branch in branches {
+        let report = mirror::sync(repo, branch, direction)?;
+        println!(
+            "{} {} ({} new commits)",
+            report.reference, report.head, report.rewritten
+        );
+    }
+    Ok(())
+}
+
+/// Prints the counterpart of an object, as `ast <oid>` or `source <oid>`.
+pub fn run_map(args: &MapArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let id = repo.revparse_single(&args.object)?.id();
+    match mirror::lookup(&repo, id)? {
+        Some(counterpart) => {
+            println!("{} {}", counterpart.side.name(), counterpart.id);
+            Ok(())
+        }
+        None => Err(Error::Config(format!(
+            "{} is not in {} (run `git-ast mirror sync` or fetch the notes ref)",
+            id,
+            mirror::MAP_REF
+        ))),
+    }
+}
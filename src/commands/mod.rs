@@ -0,0 +1,547 @@
+//! Command-Line Interface
+//!
+//! This module defines the `git-ast` command tree and dispatches each
+//! subcommand to the library code that implements it.
+//!
+//! Every subcommand accepts `--no-user-config`, which ignores the system and
+//! global Git config (hermetic mode, see [`config`](crate::config)), and
+//! `--log-format text|json` (see [`telemetry`](crate::telemetry)).
+//!
+//! ## Subcommands Invoked by Git
+//!
+//! These are not meant to be run by hand; Git calls them based on the
+//! `[filter "ast"]`, `[diff "ast"]` and `[merge "ast"]` configuration
+//! (see the [`config`](crate::config) module).
+//!
+//! -   `git-ast filter-process`: Long-running clean/smudge filter.
+//! -   `git-ast clean <path>`, `git-ast smudge <path>`: One file from stdin
+//!     to stdout, for the single-shot `clean`/`smudge` filter form and for
+//!     scripts (see [`run_single`](crate::git_plumbing::filters::run_single)).
+//! -   `git-ast diff-driver [--format patch] <7 args>`: External diff driver
+//!     (`GIT_EXTERNAL_DIFF` calling convention).
+//! -   `git-ast merge-driver %O %A %B %L %P`: Custom merge driver. Run by
+//!     hand as `git-ast merge-driver --explain BASE OURS THEIRS [SIZE [PATH]]`
+//!     it prints how each changed region would be decided (`--dry-run`
+//!     prints the merged file instead); neither writes anything.
+//!
+//! ## User Subcommands
+//!
+//! -   `git-ast archive <git archive args>`: `git archive` that writes source
+//!     rather than stored AST blobs (see [`archive`](self::archive)).
+//! -   `git-ast blame <git blame args>`: `git blame` that skips formatting-only
+//!     commits (see [`blame`](crate::blame)).
+//! -   `git-ast ignore-revs scan|list|export`: Maintain the list of
+//!     formatting-only commits and export it as `.git-blame-ignore-revs`.
+//! -   `git-ast fmt [--changed] [--check] [paths]`: Rewrite working-tree files
+//!     into the canonical format (see [`fmt`]).
+//! -   `git-ast adopt <pattern>... [--rewrite <rev>]`: Enable AST handling
+//!     for more files and clean their staged (and, optionally, recent
+//!     committed) blobs (see [`adopt`]).
+//! -   `git-ast eject [--tip]`: Turn the repository back into a plain
+//!     source repository, history included unless `--tip` (see [`eject`]).
+//! -   `git-ast index diff|verify|show|write`: Diff, verify, print or
+//!     replace staged AST blobs without going through the working tree (see
+//!     [`staged`](crate::staged)); `git-ast index build` updates the symbol
+//!     history index (see [`symbol_history`](crate::symbol_history)).
+//! -   `git-ast diff [--staged] [<rev>...] [-- <path>...]`: The working
+//!     tree, the index or revisions compared on canonical source, without
+//!     the diff driver (see [`diff`](self::diff)).
+//! -   `git-ast show [<rev>] [--format patch] [-- <path>...]`: A commit's
+//!     changes on canonical source, with the top-level items each file
+//!     changes (see [`show`]).
+//! -   `git-ast stage <path> [--hunk <n>]...`: Stage some of a file's changed
+//!     top-level items, where `git add -p` would split the stored tree (see
+//!     [`stage`]).
+//! -   `git-ast fingerprint [--upstream <range>] <rev>...`: Structural
+//!     fingerprints of commits, and which of them are already upstream (see
+//!     [`fingerprint`](self::fingerprint)).
+//! -   `git-ast pickaxe <query> [<range>]`: Commits that change the number
+//!     of matches of a Tree-sitter query, like `git log -S` (see
+//!     [`pickaxe`](self::pickaxe)).
+//! -   `git-ast report unused [--range <range>]`: Symbols added and later
+//!     removed within a range, and symbols nothing refers to;
+//!     `git-ast report usage`: slowest files, most-conflicted paths and
+//!     failure rates from the audit log and caches (see [`report`]).
+//! -   `git-ast when-changed <symbol> [<range>]`: Commits that changed a
+//!     symbol's signature, with the signature before and after (see
+//!     [`when_changed`]).
+//! -   `git-ast dupes [<rev>] [--min-tokens <n>]`: Code that appears more
+//!     than once, exactly or with renamed identifiers (see [`dupes`]).
+//! -   `git-ast metrics-code [<path>...]`: Length, parameters, nesting and
+//!     cyclomatic complexity per file and definition, as text, JSON or CSV
+//!     (see [`metrics_code`]).
+//! -   `git-ast owners <path>[:<symbol>]`: Who changed each symbol most
+//!     often and most recently, and a suggested `CODEOWNERS` rule (see
+//!     [`owners`](self::owners)).
+//! -   `git-ast rpc`: JSON-RPC service on standard input and output for
+//!     editor plugins: node-level diffs, symbol history, the node at a
+//!     position (see [`rpc`](crate::rpc)); with `--lsp`, a language server
+//!     with document symbols and history-aware hovers (see
+//!     [`lsp`](crate::lsp)).
+//! -   `git-ast mirror sync <branch>...`: Rewrite branches into source form
+//!     for the mirror repository; `git-ast map <oid>` finds the corresponding
+//!     object on the other side (see [`mirror`](crate::mirror)).
+//! -   `git-ast export-branch [<branch>...]`: Keep `source/<branch>` branches
+//!     with the source form of AST branches in the same repository.
+//! -   `git-ast sidecar sync [<branch>...]`: Keep AST versions of a source
+//!     repository's branches under `refs/ast/heads/`.
+//! -   `git-ast range-diff A..B C..D`: Compare two versions of a patch series
+//!     by structural change (see [`range_diff`]).
+//! -   `git-ast review-anchors <range> [--format json]`: Where each structural
+//!     change lies in the AST blobs and in the source mirror, for review bots
+//!     (see [`review_anchors`]).
+//! -   `git-ast review <range> [--format json]`: A prioritized checklist of
+//!     risky structural changes (public signatures, removed error handling,
+//!     new `unsafe`, project queries) for reviewers (see [`review`]).
+//! -   `git-ast config list|get|set`: Effective settings and where each comes
+//!     from (see [`config`](self::config)).
+//! -   `git-ast languages [--paths]`: Supported languages, their features,
+//!     and the tracked files each covers (see [`languages`](self::languages)).
+//! -   `git-ast lock [--check]`: Pin the grammar and printer of each language
+//!     in `.git-ast.lock`, or verify the pins (see [`lock`]).
+//! -   `git-ast setup-toolchain [--dry-run]`: Build the `git-ast` release the
+//!     project pins, if this one does not suit it, and point the filter and
+//!     drivers at it (see [`toolchain`](crate::toolchain)).
+//! -   `git-ast init-template <dir>`: A Git template directory whose
+//!     repositories come configured for `git-ast`: drivers, attributes and
+//!     hooks (see [`init_template`]).
+//! -   `git-ast bundle create|install <file>`: Carry the toolchain and project
+//!     files to an air-gapped machine in one file (see
+//!     [`bundle`](self::bundle)).
+//!
+//! ## Hooks
+//!
+//! -   `git-ast hook pre-push`: Re-verifies outgoing AST blobs before a push
+//!     (see [`hook`]).
+//! -   `git-ast hook post-commit`: Records merge reports and attestations
+//!     saved for the new commit.
+//!
+//! ## Maintenance Subcommands
+//!
+//! -   `git-ast prewarm <rev> [--output-dir <dir>]`: Print a revision's AST
+//!     blobs into the smudge cache ahead of a checkout, optionally writing
+//!     the source tree elsewhere (see [`prewarm`](self::prewarm)).
+//! -   `git-ast verify [--attestations] [<rev>]`: Round-trip check of a
+//!     revision's AST blobs and, optionally, of their signed attestations
+//!     (see [`verify`]).
+//! -   `git-ast audit [--command <c>] [--path <p>] [--errors]`: The log of
+//!     filter and driver invocations kept with `ast.audit` (see
+//!     [`audit`](self::audit)).
+//! -   `git-ast gc [--dry-run]`: Prune `refs/ast/*` branches and notes left
+//!     behind by deleted branches, then run `git gc` (see [`gc`]).
+//! -   `git-ast metrics [--reset]`: Metrics collected with `ast.metrics`, in
+//!     the Prometheus text format (see [`telemetry`](crate::telemetry)).
+//! -   `git-ast conformance [--root <dir>]...`: Checks that printed and
+//!     randomly re-laid-out source cleans back to the same tree, with
+//!     minimized reproducers (see [`conformance`](self::conformance)).
+//! -   `git-ast selftest [--bless]`: Runs the golden corpus under `testdata/`
+//!     through the clean/smudge pipeline and compares against the stored
+//!     snapshots (see [`selftest`]).
+//! -   `git-ast bench [--corpus <dir>] [--format json]`: Filter throughput,
+//!     diff latency and checkout times on a corpus (see [`bench`]).
+//! -   `git-ast completions <shell>`, `git-ast manpage [--out-dir <dir>]`:
+//!     Shell completions and man pages for packagers (see [`completions`]).
+
+use crate::diff_backend::DiffFormat;
+use crate::drivers;
+use crate::git_plumbing::filters;
+use crate::telemetry::{self, LogFormat};
+use crate::{log_error, Error};
+use clap::{Parser, Subcommand};
+use git2::Repository;
+
+pub mod adopt;
+pub mod archive;
+pub mod audit;
+pub mod bench;
+pub mod blame;
+pub mod bundle;
+pub mod completions;
+pub mod config;
+pub mod conformance;
+pub mod diff;
+pub mod dupes;
+pub mod eject;
+pub mod fingerprint;
+pub mod fmt;
+pub mod gc;
+pub mod hook;
+pub mod index;
+pub mod init_template;
+pub mod languages;
+pub mod lock;
+pub mod metrics;
+pub mod metrics_code;
+pub mod mirror;
+pub mod owners;
+pub mod pickaxe;
+pub mod prewarm;
+pub mod range_diff;
+pub mod report;
+pub mod review;
+pub mod review_anchors;
+pub mod rpc;
+pub mod selftest;
+pub mod setup_toolchain;
+pub mod show;
+pub mod stage;
+pub mod verify;
+pub mod when_changed;
+
+/// Top-level `git-ast` command line.
+#[derive(Debug, Parser)]
+#[command(name = "git-ast", version, about = "Language-aware Git extensions")]
+pub struct Cli {
+    /// Ignore system and global Git config (hermetic mode, for CI and tests).
+    #[arg(long, global = true)]
+    pub no_user_config: bool,
+    /// Log format on stderr (overrides `ast.logFormat`).
+    #[arg(long, global = true, value_enum)]
+    pub log_format: Option<LogFormat>,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+/// All `git-ast` subcommands.
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the long-running clean/smudge filter process (invoked by Git).
+    FilterProcess,
+    /// Clean one file from stdin to stdout (single-shot filter form).
+    Clean {
+        /// The file's path, for language detection and settings (`%f`).
+        path: String,
+    },
+    /// Smudge one file from stdin to stdout (single-shot filter form).
+    Smudge {
+        /// The file's path, for language detection and settings (`%f`).
+        path: String,
+    },
+    /// Act as the external diff driver (invoked by Git).
+    DiffDriver {
+        /// Output style; `patch` is always a diff `git apply` accepts.
+        #[arg(long, value_enum, default_value_t = DiffFormat::Readable)]
+        format: DiffFormat,
+        /// `path old-file old-hex old-mode new-file new-hex new-mode`
+        #[arg(allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Act as the custom merge driver (invoked by Git).
+    MergeDriver {
+        /// Print the merge result instead of writing `%A`.
+        #[arg(long)]
+        dry_run: bool,
+        /// Print how each changed region would be decided; writes nothing.
+        #[arg(long, conflicts_with = "dry_run")]
+        explain: bool,
+        /// `%O %A %B %L %P [%S %X %Y]`; by hand, `BASE OURS THEIRS [SIZE [PATH]]`
+        #[arg(allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// `git archive` that writes source, not stored AST blobs.
+    Archive(archive::ArchiveArgs),
+    /// `git blame` that skips formatting-only commits.
+    Blame(blame::BlameArgs),
+    /// Maintain the list of formatting-only commits.
+    IgnoreRevs {
+        #[command(subcommand)]
+        command: blame::IgnoreRevsCommand,
+    },
+    /// Show and change git-ast settings.
+    Config {
+        #[command(subcommand)]
+        command: config::ConfigCommand,
+    },
+    /// Compare the working tree, the index or revisions on canonical source.
+    Diff(diff::DiffArgs),
+    /// Show a commit's changes on canonical source.
+    Show(show::ShowArgs),
+    /// Stage some of a file's changed top-level items.
+    Stage(stage::StageArgs),
+    /// Work on staged AST blobs directly, or build the symbol history index.
+    Index {
+        #[command(subcommand)]
+        command: index::IndexCommand,
+    },
+    /// Print structural fingerprints of commits; find duplicated changes.
+    Fingerprint(fingerprint::FingerprintArgs),
+    /// Find commits that change the number of matches of a query.
+    Pickaxe(pickaxe::PickaxeArgs),
+    /// List the commits that changed a symbol's signature.
+    WhenChanged(when_changed::WhenChangedArgs),
+    /// List duplicated code.
+    Dupes(dupes::DupesArgs),
+    /// Print code metrics per file and definition.
+    MetricsCode(metrics_code::MetricsCodeArgs),
+    /// Show who owns each symbol of a file.
+    Owners(owners::OwnersArgs),
+    /// Serve the editor protocol on standard input and output.
+    Rpc(rpc::RpcArgs),
+    /// Reports over the symbol index and the audit log.
+    Report {
+        #[command(subcommand)]
+        command: report::ReportCommand,
+    },
+    /// Rewrite working-tree files into the canonical format.
+    Fmt(fmt::FmtArgs),
+    /// Enable AST handling for more files, converting their existing blobs.
+    Adopt(adopt::AdoptArgs),
+    /// Convert the repository back to plain source and remove git-ast.
+    Eject(eject::EjectArgs),
+    /// Verify a revision's AST blobs and their attestations.
+    Verify(verify::VerifyArgs),
+    /// Show the log of filter and driver invocations.
+    Audit(audit::AuditArgs),
+    /// Prune semantic objects of deleted branches and repack.
+    Gc(gc::GcArgs),
+    /// Print a revision's AST blobs into the smudge cache.
+    Prewarm(prewarm::PrewarmArgs),
+    /// Print the collected metrics in the Prometheus text format.
+    Metrics(metrics::MetricsArgs),
+    /// Run a Git hook.
+    Hook {
+        #[command(subcommand)]
+        command: hook::HookCommand,
+    },
+    /// Maintain the source mirror of AST branches.
+    Mirror {
+        #[command(subcommand)]
+        command: mirror::MirrorCommand,
+    },
+    /// Maintain AST versions of source branches (non-invasive mode).
+    Sidecar {
+        #[command(subcommand)]
+        command: mirror::SidecarCommand,
+    },
+    /// Point `source/<branch>` at the source form of AST branches.
+    ExportBranch(mirror::ExportBranchArgs),
+    /// Find the mirror counterpart of a commit or blob (or vice versa).
+    Map(mirror::MapArgs),
+    /// Compare two commit series by structural change (for reviewing rebases).
+    RangeDiff(range_diff::RangeDiffArgs),
+    /// List risky structural changes in a range as a review checklist.
+    Review(review::ReviewArgs),
+    /// Locate structural changes in both the AST and the source rendering.
+    ReviewAnchors(review_anchors::ReviewAnchorsArgs),
+    /// List supported languages and the tracked files each covers.
+    Languages(languages::LanguagesArgs),
+    /// Pin (or check) each language's grammar and printer in `.git-ast.lock`.
+    Lock(lock::LockArgs),
+    /// Install the git-ast the project pins and configure the repository to use it.
+    SetupToolchain(setup_toolchain::SetupToolchainArgs),
+    /// Write a Git template directory that sets new repositories up for git-ast.
+    InitTemplate(init_template::InitTemplateArgs),
+    /// Create or install an offline bundle of the toolchain and project files.
+    Bundle {
+        #[command(subcommand)]
+        command: bundle::BundleCommand,
+    },
+    /// Check the golden corpus against its snapshots.
+    Selftest(selftest::SelftestArgs),
+    /// Check the printer's round trip over a corpus.
+    Conformance(conformance::ConformanceArgs),
+    /// Measure filter throughput, diff latency and checkout times.
+    Bench(bench::BenchArgs),
+    /// Print a shell completion script.
+    Completions(completions::CompletionsArgs),
+    /// Generate man pages.
+    Manpage(completions::ManpageArgs),
+}
+
+impl Command {
+    /// Whether Git runs the subcommand itself (as a filter, driver or hook),
+    /// sharing the output of the Git command the user typed.
+    fn invoked_by_git(&self) -> bool {
+        matches!(
+            self,
+            Command::FilterProcess
+                | Command::Clean { .. }
+                | Command::Smudge { .. }
+                | Command::DiffDriver { .. }
+                | Command::MergeDriver { .. }
+                | Command::Hook { .. }
+        )
+    }
+
+    /// The subcommand as typed, for logs and metrics.
+    fn name(&self) -> &'static str {
+        match self {
+            Command::FilterProcess => "filter-process",
+            Command::Clean { .. } => "clean",
+            Command::Smudge { .. } => "smudge",
+            Command::DiffDriver { .. } => "diff-driver",
+            Command::MergeDriver { .. } => "merge-driver",
+            Command::Archive(_) => "archive",
+            Command::Blame(_) => "blame",
+            Command::IgnoreRevs { .. } => "ignore-revs",
+            Command::Config { .. } => "config",
+            Command::Diff(_) => "diff",
+            Command::Show(_) => "show",
+            Command::Stage(_) => "stage",
+            Command::Index { .. } => "index",
+            Command::Fingerprint(_) => "fingerprint",
+            Command::Pickaxe(_) => "pickaxe",
+            Command::WhenChanged(_) => "when-changed",
+            Command::Dupes(_) => "dupes",
+            Command::MetricsCode(_) => "metrics-code",
+            Command::Owners(_) => "owners",
+            Command::Rpc(_) => "rpc",
+            Command::Report { .. } => "report",
+            Command::Fmt(_) => "fmt",
+            Command::Adopt(_) => "adopt",
+            Command::Eject(_) => "eject",
+            Command::Verify(_) => "verify",
+            Command::Audit(_) => "audit",
+            Command::Gc(_) => "gc",
+            Command::Prewarm(_) => "prewarm",
+            Command::Metrics(_) => "metrics",
+            Command::Hook { .. } => "hook",
+            Command::Mirror { .. } => "mirror",
+            Command::Sidecar { .. } => "sidecar",
+            Command::ExportBranch(_) => "export-branch",
+            Command::Map(_) => "map",
+            Command::RangeDiff(_) => "range-diff",
+            Command::Review(_) => "review",
+            Command::ReviewAnchors(_) => "review-anchors",
+            Command::Languages(_) => "languages",
+            Command::Lock(_) => "lock",
+            Command::SetupToolchain(_) => "setup-toolchain",
+            Command::InitTemplate(_) => "init-template",
+            Command::Bundle { .. } => "bundle",
+            Command::Selftest(_) => "selftest",
+            Command::Conformance(_) => "conformance",
+            Command::Bench(_) => "bench",
+            Command::Completions(_) => "completions",
+            Command::Manpage(_) => "manpage",
+        }
+    }
+}
+
+/// Runs the selected subcommand.
+pub fn run(cli: Cli) -> Result<(), Error> {
+    // Both also reach the `git-ast` processes started by the Git commands we run.
+    if cli.no_user_config {
+        std::env::set_var(crate::config::NO_USER_CONFIG_ENV, "1");
+    }
+    if let Some(format) = cli.log_format {
+        std::env::set_var(
+            crate::config::env_var(telemetry::LOG_FORMAT_KEY),
+            format.as_str(),
+        );
+    }
+    // Invalid settings are reported by the command itself, which may well
+    // be `git-ast config set` fixing them.
+    let repo = Repository::open_from_env().ok();
+    let settings = crate::config::Settings::load(repo.as_ref()).ok();
+    telemetry::set_log_format(settings.as_ref().map(|s| s.log_format).unwrap_or_default());
+    let level = settings
+        .as_ref()
+        .map_or(telemetry::Level::Error, |s| s.log_level);
+    if cli.command.invoked_by_git() && level == telemetry::Level::Error {
+        telemetry::errors_only();
+    }
+
+    let name = cli.command.name();
+    let result = run_command(cli.command);
+    if let Err(e) = &result {
+        telemetry::add("git_ast_errors_total", &[("command", name)], 1.0);
+        // `main` prints the error itself; JSON logs get it too.
+        if settings
+            .as_ref()
+            .is_some_and(|s| s.log_format == LogFormat::Json)
+        {
+            log_error!(name, "{}", e);
+        }
+    }
+    if let (Some(repo), Some(true)) = (&repo, settings.map(|s| s.metrics)) {
+        if let Err(e) = telemetry::flush(&telemetry::metrics_path(repo)) {
+            log_error!("metrics", "{}", e);
+        }
+    }
+    result
+}
+
+fn run_command(command: Command) -> Result<(), Error> {
+    match command {
+        Command::FilterProcess => filters::run_long_running_filter(),
+        Command::Clean { path } => filters::run_single("clean", &path),
+        Command::Smudge { path } => filters::run_single("smudge", &path),
+        Command::DiffDriver { format, args } => {
+            let path = args.first().cloned().unwrap_or_default();
+            let blobs = || {
+                [2, 5]
+                    .iter()
+                    .filter_map(|&i| args.get(i).cloned())
+                    .collect()
+            };
+            crate::audit::audited("diff", &path, blobs, || {
+                drivers::run_diff_driver(format, &args)
+            })
+        }
+        Command::MergeDriver {
+            dry_run,
+            explain,
+            args,
+        } => {
+            let mode = match (dry_run, explain) {
+                (_, true) => drivers::MergeMode::Explain,
+                (true, _) => drivers::MergeMode::DryRun,
+                _ => drivers::MergeMode::Write,
+            };
+            // Hashed before the driver overwrites %A.
+            let blobs = || {
+                args.iter()
+                    .take(3)
+                    .filter_map(|file| git2::Oid::hash_file(git2::ObjectType::Blob, file).ok())
+                    .map(|id| id.to_string())
+                    .collect()
+            };
+            let path = args.get(4).or(args.get(1)).cloned().unwrap_or_default();
+            crate::audit::audited("merge", &path, blobs, || {
+                drivers::run_merge_driver(&args, mode)
+            })
+        }
+        Command::Archive(args) => archive::run(&args),
+        Command::Blame(args) => blame::run_blame(&args),
+        Command::IgnoreRevs { command } => blame::run_ignore_revs(&command),
+        Command::Config { command } => config::run(&command),
+        Command::Diff(args) => diff::run(&args),
+        Command::Show(args) => show::run(&args),
+        Command::Stage(args) => stage::run(&args),
+        Command::Index { command } => index::run(&command),
+        Command::Fingerprint(args) => fingerprint::run(&args),
+        Command::Pickaxe(args) => pickaxe::run(&args),
+        Command::WhenChanged(args) => when_changed::run(&args),
+        Command::Dupes(args) => dupes::run(&args),
+        Command::MetricsCode(args) => metrics_code::run(&args),
+        Command::Owners(args) => owners::run(&args),
+        Command::Rpc(args) => rpc::run(&args),
+        Command::Report { command } => report::run(&command),
+        Command::Fmt(args) => fmt::run(&args),
+        Command::Adopt(args) => adopt::run(&args),
+        Command::Eject(args) => eject::run(&args),
+        Command::Verify(args) => verify::run(&args),
+        Command::Audit(args) => audit::run(&args),
+        Command::Gc(args) => gc::run(&args),
+        Command::Prewarm(args) => prewarm::run(&args),
+        Command::Metrics(args) => metrics::run(&args),
+        Command::Hook { command } => hook::run(&command),
+        Command::Mirror { command } => mirror::run_mirror(&command),
+        Command::Sidecar { command } => mirror::run_sidecar(&command),
+        Command::ExportBranch(args) => mirror::run_export_branch(&args),
+        Command::Map(args) => mirror::run_map(&args),
+        Command::RangeDiff(args) => range_diff::run(&args),
+        Command::Review(args) => review::run(&args),
+        Command::ReviewAnchors(args) => review_anchors::run(&args),
+        Command::Languages(args) => languages::run(&args),
+        Command::Lock(args) => lock::run(&args),
+        Command::SetupToolchain(args) => setup_toolchain::run(&args),
+        Command::InitTemplate(args) => init_template::run(&args),
+        Command::Bundle { command } => bundle::run(&command),
+        Command::Selftest(args) => selftest::run(&args),
+        Command::Conformance(args) => conformance::run(&args),
+        Command::Bench(args) => bench::run(&args),
+        Command::Completions(args) => completions::run_completions(&args),
+        Command::Manpage(args) => completions::run_manpage(&args),
+    }
+}
@@ -0,0 +1,115 @@
+//! `git-ast owners <path>[:<symbol>] [--format text|json|codeowners]`
+//!
+//! Who changed each symbol of a file most often and most recently (see
+//! [`ownership`](crate::ownership)). `--format codeowners` prints a
+//! suggested `CODEOWNERS` rule for the file, with one comment line per
+//! symbol explaining it:
+//!
+//! ```text
+//! $ git-ast owners src/parse.rs --format codeowners
+//! # function_item parse_header: @acme/infra
+//! # function_item parse_body: ada@example.com
+//! /src/parse.rs @acme/infra ada@example.com
+//! ```
+
+use crate::config;
+use crate::model::Document;
+use crate::ownership::{self, SymbolOwners};
+use crate::queries::QueryPacks;
+use crate::Error;
+use clap::{Args, ValueEnum};
+use git2::Repository;
+use serde_json::json;
+
+/// Arguments for `git-ast owners`.
+#[derive(Debug, Args)]
+pub struct OwnersArgs {
+    /// File to report on, optionally followed by `:<symbol>` for one symbol.
+    #[arg(value_name = "PATH[:SYMBOL]")]
+    pub target: String,
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = OwnersFormat::Text)]
+    pub format: OwnersFormat,
+}
+
+/// Output formats of `git-ast owners`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OwnersFormat {
+    /// Each symbol, then one line per owner.
+    Text,
+    /// A single JSON document.
+    Json,
+    /// A suggested `CODEOWNERS` rule.
+    Codeowners,
+}
+
+/// Runs `git-ast owners`.
+pub fn run(args: &OwnersArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| Error::Config("git-ast owners needs a working tree".to_string()))?
+        .to_path_buf();
+    let (path, symbol) = match args.target.rsplit_once(':') {
+        Some((path, symbol)) if !symbol.is_empty() => (path, Some(symbol)),
+        _ => (args.target.as_str(), None),
+    };
+    let cwd = std::env::current_dir()?;
+    let relative = super::fmt::relative_to(&workdir, &cwd.join(path))?;
+    let pathname = relative.to_string_lossy().replace('\\', "/");
+    let limits = config::Settings::load(Some(&repo))?.parse_limits;
+    let repo_config = config::RepoConfigs::new(Some(&repo)).for_path(&pathname)?;
+    let mut packs = QueryPacks::for_repo(&repo);
+    let found = ownership::owners(
+        &repo,
+        &pathname,
+        symbol,
+        &repo_config.owners,
+        &limits,
+        &mut packs,
+    )?;
+    match args.format {
+        OwnersFormat::Json => {
+            let report = Document::new(json!({ "symbols": found }));
+            println!("{}", report.to_json()?);
+        }
+        OwnersFormat::Codeowners => {
+            for s in &found {
+                let top = s.owners.first().map_or("(none)", |o| o.handle.as_str());
+                println!("# {} {}: {}", s.symbol.kind, s.symbol.name, top);
+            }
+            if let Some(rule) = ownership::codeowners_rule(&pathname, &found) {
+                println!("{}", rule);
+            }
+        }
+        OwnersFormat::Text => print_text(&found),
+    }
+    Ok(())
+}
+
+fn print_text(found: &[SymbolOwners]) {
+    for s in found {
+        let symbol = &s.symbol;
+        println!(
+            "{} {} ({}:{})",
+            symbol.kind, symbol.name, symbol.path, symbol.line
+        );
+        let width = s.owners.iter().map(|o| o.display.len()).max().unwrap_or(0);
+        for owner in &s.owners {
+            let plural = if owner.commits == 1 { "" } else { "s" };
+            let latest = if s.latest.as_ref() == Some(&owner.handle) {
+                "  (latest)"
+            } else {
+                ""
+            };
+            println!(
+                "    {:<width$}  {} commit{}, last {}{}",
+                owner.display,
+                owner.commits,
+                plural,
+                &owner.last_commit[..7],
+                latest
+            );
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! `git-ast pickaxe <query> [<range>]`
+//!
+//! Lists the commits that change the number of matches of a Tree-sitter
+//! query, like `git log -S` for syntax (see [`pickaxe`](crate::pickaxe)):
+//!
+//! ```text
+//! $ git-ast pickaxe --lang rust '(macro_invocation macro: (identifier) @m (#eq? @m "dbg"))'
+//! 9f8e7d6 Remove leftover debugging
+//!     src/merge.rs: 2 -> 0
+//! 5a4b3c2 Trace the hunk expansion
+//!     src/merge.rs: 0 -> 2
+//! ```
+//!
+//! `--include-literals` also counts matches inside comments and strings.
+
+use crate::languages;
+use crate::model::Document;
+use crate::pickaxe::{self, Pickaxe};
+use crate::{config, Error};
+use clap::{Args, ValueEnum};
+use git2::Repository;
+use serde_json::json;
+
+/// Arguments for `git-ast pickaxe`.
+#[derive(Debug, Args)]
+pub struct PickaxeArgs {
+    /// Tree-sitter query; each match counts once.
+    pub query: String,
+    /// Revision or `A..B` range to search (default: all of `HEAD`).
+    pub range: Option<String>,
+    /// Language the query is written for (default: every language it compiles for).
+    #[arg(long)]
+    pub lang: Option<String>,
+    /// Also count matches inside comments and string literals.
+    #[arg(long)]
+    pub include_literals: bool,
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = PickaxeFormat::Text)]
+    pub format: PickaxeFormat,
+}
+
+/// Output formats of `git-ast pickaxe`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PickaxeFormat {
+    /// One line per commit, then one per file.
+    Text,
+    /// A single JSON document.
+    Json,
+}
+
+/// Runs `git-ast pickaxe`.
+pub fn run(args: &PickaxeArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let language = match &args.lang {
+        Some(name) => Some(
+            languages::by_name(name)
+                .ok_or_else(|| Error::Config(format!("unknown language {:?}", name)))?,
+        ),
+        None => None,
+    };
+    let pickaxe = Pickaxe::new(&args.query, language, args.include_literals)?;
+    let limits = config::Settings::load(Some(&repo))?.parse_limits;
+    let hits = pickaxe::search(&repo, args.range.as_deref(), &pickaxe, &limits)?;
+    match args.format {
+        PickaxeFormat::Json => {
+            let report = Document::new(json!({ "commits": hits }));
+            println!("{}", report.to_json()?);
+        }
+        PickaxeFormat::Text => {
+            for hit in &hits {
+                println!("{} {}", &hit.commit[..7], hit.summary);
+                for file in &hit.files {
+                    println!("    {}: {} -> {}", file.path, file.before, file.after);
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,220 @@
+//! `git-ast prewarm <rev> [--output-dir <dir>] [--jobs <n>]`
+//!
+//! Smudges every AST blob reachable from a revision's tree into the
+//! [`smudge_cache`](crate::smudge_cache), spread over several threads, so
+//! that a checkout afterwards only copies source out of the cache. Meant for
+//! CI runners, where a cache step can restore `.git/ast-cache/smudge`
+//! between jobs:
+//!
+//! ```text
+//! $ git-ast prewarm origin/main
+//! prewarm: 1843 AST blobs (1790 printed, 53 already cached)
+//! $ git checkout origin/main
+//! ```
+//!
+//! With `--output-dir`, the revision's files are also written below that
+//! directory as source (executable bits and, on Unix, symbolic links
+//! included; submodules are skipped), without touching the worktree or the
+//! index. The directory must not exist or be empty.
+
+use crate::git_plumbing::filters;
+use crate::serialization;
+use crate::smudge_cache::SmudgeCache;
+use crate::util::atomic_io;
+use crate::Error;
+use clap::Args;
+use git2::{ObjectType, Oid, Repository, TreeWalkMode, TreeWalkResult};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Arguments for `git-ast prewarm`.
+#[derive(Debug, Args)]
+pub struct PrewarmArgs {
+    /// Revision whose tree to smudge.
+    #[arg(default_value = "HEAD")]
+    pub rev: String,
+    /// Also write the revision's files as source below this directory.
+    #[arg(long)]
+    pub output_dir: Option<PathBuf>,
+    /// Worker threads (default: one per CPU).
+    #[arg(long)]
+    pub jobs: Option<usize>,
+}
+
+/// What a prewarm did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrewarmReport {
+    /// Distinct AST blobs in the tree.
+    pub ast_blobs: usize,
+    /// Blobs printed into the cache by this run.
+    pub printed: usize,
+    /// Files written below the output directory.
+    pub written: usize,
+}
+
+/// Runs `git-ast prewarm`.
+pub fn run(args: &PrewarmArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let jobs = args
+        .jobs
+        .or_else(|| std::thread::available_parallelism().ok().map(|n| n.get()))
+        .unwrap_or(1);
+    let report = prewarm(&repo, &args.rev, args.output_dir.as_deref(), jobs)?;
+    println!(
+        "prewarm: {} AST blobs ({} printed, {} already cached)",
+        report.ast_blobs,
+        report.printed,
+        report.ast_blobs - report.printed
+    );
+    if let Some(dir) = &args.output_dir {
+        println!("wrote {} files to {}", report.written, dir.display());
+    }
+    Ok(())
+}
+
+/// A file of the tree: path, blob and mode.
+type Entry = (String, Oid, i32);
+
+/// Smudges the AST blobs of `rev` into the cache using `jobs` threads,
+/// writing the tree's files below `output_dir` if given.
+pub fn prewarm(
+    repo: &Repository,
+    rev: &str,
+    output_dir: Option<&Path>,
+    jobs: usize,
+) -> Result<PrewarmReport, Error> {
+    let tree = repo.revparse_single(rev)?.peel_to_tree()?;
+    let mut entries: Vec<Entry> = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.kind() == Some(ObjectType::Blob) {
+            let path = format!("{}{}", dir, entry.name().unwrap_or_default());
+            entries.push((path, entry.id(), entry.filemode()));
+        }
+        TreeWalkResult::Ok
+    })?;
+    if let Some(dir) = output_dir {
+        if dir.exists() && std::fs::read_dir(dir)?.next().is_some() {
+            return Err(Error::Config(format!("{} is not empty", dir.display())));
+        }
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let cache = SmudgeCache::open(repo);
+    let git_dir = repo.path().to_path_buf();
+    let next = AtomicUsize::new(0);
+    let report = Mutex::new(PrewarmReport::default());
+    let seen = Mutex::new(std::collections::HashSet::new());
+    let worker = || -> Result<(), Error> {
+        let repo = Repository::open(&git_dir)?;
+        while let Some((path, id, mode)) = entries.get(next.fetch_add(1, Ordering::Relaxed)) {
+            let blob = repo.find_blob(*id)?;
+            let is_ast = serialization::is_ast_blob(blob.content());
+            let first = is_ast && seen.lock().expect("prewarm lock").insert(*id);
+            let mut source = None;
+            if first && !cache.contains(*id) {
+                let printed = filters::perform_smudge(blob.content(), path)?;
+                cache.put(*id, &printed)?;
+                source = Some(printed);
+                report.lock().expect("prewarm lock").printed += 1;
+            }
+            if let Some(dir) = output_dir {
+                let content = match (source, is_ast) {
+                    (Some(source), _) => source,
+                    (None, true) => match cache.get(*id) {
+                        Some(cached) => cached,
+                        None => filters::perform_smudge(blob.content(), path)?,
+                    },
+                    (None, false) => blob.content().to_vec(),
+                };
+                write_file(&dir.join(path), &content, *mode)?;
+                report.lock().expect("prewarm lock").written += 1;
+            }
+        }
+        Ok(())
+    };
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.max(1)).map(|_| scope.spawn(worker)).collect();
+        workers
+            .into_iter()
+            .map(|w| {
+                w.join().unwrap_or_else(|_| {
+                    Err(Error::Generation("prewarm worker panicked".to_string()))
+                })
+            })
+            .collect::<Result<Vec<()>, Error>>()
+    })?;
+    let mut report = report.into_inner().expect("prewarm lock");
+    report.ast_blobs = seen.into_inner().expect("prewarm lock").len();
+    Ok(report)
+}
+
+/// Writes one file of the tree with its mode.
+fn write_file(path: &Path, content: &[u8], mode: i32) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    #[cfg(unix)]
+    if mode == 0o120000 {
+        let target = String::from_utf8_lossy(content).into_owned();
+        std::os::unix::fs::symlink(target, path)?;
+        return Ok(());
+    }
+    atomic_io::write(path, content)?;
+    #[cfg(unix)]
+    if mode == 0o100755 {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::languages;
+    use crate::parsing::ParseLimits;
+
+    #[test]
+    fn fills_the_cache_and_materializes_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path().join("repo")).unwrap();
+        let signature = git2::Signature::now("t", "t@example.com").unwrap();
+        let rust = languages::by_name("rust");
+        let ast =
+            filters::clean_as(b"fn  main(){}\n", "a.rs", rust, &ParseLimits::default()).unwrap();
+        let ast = repo.blob(&ast).unwrap();
+        let text = repo.blob(b"notes\n").unwrap();
+        let mut src = repo.treebuilder(None).unwrap();
+        src.insert("b.rs", ast, 0o100644).unwrap();
+        let src = src.write().unwrap();
+        let mut root = repo.treebuilder(None).unwrap();
+        root.insert("a.rs", ast, 0o100755).unwrap();
+        root.insert("README", text, 0o100644).unwrap();
+        root.insert("src", src, 0o040000).unwrap();
+        let tree = repo.find_tree(root.write().unwrap()).unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "one", &tree, &[])
+            .unwrap();
+
+        let out = dir.path().join("out");
+        let report = prewarm(&repo, "HEAD", Some(&out), 3).unwrap();
+        assert_eq!(
+            report,
+            PrewarmReport {
+                ast_blobs: 1,
+                printed: 1,
+                written: 3
+            }
+        );
+        let cache = SmudgeCache::open(&repo);
+        assert_eq!(cache.get(ast).unwrap(), b"fn main() {}\n");
+        assert_eq!(
+            std::fs::read(out.join("src/b.rs")).unwrap(),
+            b"fn main() {}\n"
+        );
+        assert_eq!(std::fs::read(out.join("README")).unwrap(), b"notes\n");
+
+        assert_eq!(prewarm(&repo, "HEAD", None, 1).unwrap().printed, 0);
+        assert!(prewarm(&repo, "HEAD", Some(&out), 1).is_err());
+    }
+}
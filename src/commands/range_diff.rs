@@ -0,0 +1,215 @@
+//! `git-ast range-diff <old-range> <new-range>`
+//!
+//! Compares two versions of a patch series, typically before and after a
+//! rebase, like `git range-diff`, but pairs commits by their structural
+//! [`fingerprint`](crate::fingerprint) instead of their text patches. A
+//! commit that was only moved onto a new base (and perhaps reformatted)
+//! pairs up as unchanged even when every line number shifted.
+//!
+//! ## Output
+//!
+//! One line per commit, in the order of the new series, with commits that
+//! were dropped shown where they used to be:
+//!
+//! ```text
+//! 1: 1a2b3c4 = 1: 5d6e7f8 Add parser
+//! 2: 2b3c4d5 ! 2: 6e7f8a9 Handle empty input
+//!     src/parse.rs: changed differently
+//! 3: 3c4d5e6 < -: ------- Remove debug output
+//! -: ------- > 3: 7f8a9b0 Add tests
+//! ```
+//!
+//! `=` marks identical structural changes, `!` commits paired by subject
+//! whose changes differ (the files that differ are listed below), `<` a
+//! commit only in the old series and `>` one only in the new series.
+
+use crate::fingerprint::{self, CommitFingerprint};
+use crate::parsing::ParseLimits;
+use crate::queries::QueryPacks;
+use crate::Error;
+use clap::Args;
+use git2::Repository;
+use std::collections::BTreeSet;
+
+/// Arguments for `git-ast range-diff`.
+#[derive(Debug, Args)]
+pub struct RangeDiffArgs {
+    /// The original series, as `A..B`.
+    pub old: String,
+    /// The rewritten series, as `C..D`.
+    pub new: String,
+}
+
+/// How a commit of one series relates to the other series.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pairing {
+    /// Same structural change (`=`).
+    Unchanged(usize, usize),
+    /// Same subject, different change (`!`).
+    Modified(usize, usize),
+    /// Only in the old series (`<`).
+    Dropped(usize),
+    /// Only in the new series (`>`).
+    Added(usize),
+}
+
+/// Runs `git-ast range-diff`.
+pub fn run(args: &RangeDiffArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let limits = ParseLimits::default();
+    let mut packs = QueryPacks::for_repo(&repo);
+    let old = series(&repo, &args.old, &limits, &mut packs)?;
+    let new = series(&repo, &args.new, &limits, &mut packs)?;
+
+    for pairing in pair(&old, &new) {
+        match pairing {
+            Pairing::Unchanged(i, j) => {
+                println!("{} = {} {}", label(&old, i), label(&new, j), new[j].summary)
+            }
+            Pairing::Modified(i, j) => {
+                println!("{} ! {} {}", label(&old, i), label(&new, j), new[j].summary);
+                for line in differences(&old[i], &new[j]) {
+                    println!("    {}", line);
+                }
+            }
+            Pairing::Dropped(i) => println!("{} < -: ------- {}", label(&old, i), old[i].summary),
+            Pairing::Added(j) => println!("-: ------- > {} {}", label(&new, j), new[j].summary),
+        }
+    }
+    Ok(())
+}
+
+/// Fingerprints the commits of `range` (`A..B`), oldest first.
+fn series(
+    repo: &Repository,
+    range: &str,
+    limits: &ParseLimits,
+    packs: &mut QueryPacks,
+) -> Result<Vec<CommitFingerprint>, Error> {
+    if !range.contains("..") {
+        return Err(Error::Config(format!(
+            "expected a range like A..B, got {:?}",
+            range
+        )));
+    }
+    fingerprint::commits(repo, range, limits, packs)
+}
+
+/// Pairs the commits of two series: first by identical fingerprint, then by
+/// identical subject. The result follows the new series, with each dropped
+/// commit placed before the first pairing that comes after it.
+pub fn pair(old: &[CommitFingerprint], new: &[CommitFingerprint]) -> Vec<Pairing> {
+    let mut matched: Vec<Option<Pairing>> = vec![None; new.len()];
+    let mut taken = vec![false; old.len()];
+    for (j, n) in new.iter().enumerate() {
+        if let Some(i) = (0..old.len()).find(|&i| !taken[i] && old[i].fingerprint == n.fingerprint)
+        {
+            taken[i] = true;
+            matched[j] = Some(Pairing::Unchanged(i, j));
+        }
+    }
+    for (j, n) in new.iter().enumerate() {
+        if matched[j].is_some() {
+            continue;
+        }
+        if let Some(i) = (0..old.len()).find(|&i| !taken[i] && old[i].summary == n.summary) {
+            taken[i] = true;
+            matched[j] = Some(Pairing::Modified(i, j));
+        }
+    }
+
+    let mut out = Vec::new();
+    let mut next_old = 0;
+    for (j, m) in matched.into_iter().enumerate() {
+        match m {
+            Some(p @ (Pairing::Unchanged(i, _) | Pairing::Modified(i, _))) => {
+                while next_old < i {
+                    if !taken[next_old] {
+                        out.push(Pairing::Dropped(next_old));
+                    }
+                    next_old += 1;
+                }
+                next_old = next_old.max(i + 1);
+                out.push(p);
+            }
+            _ => out.push(Pairing::Added(j)),
+        }
+    }
+    out.extend(
+        (next_old..old.len())
+            .filter(|&i| !taken[i])
+            .map(Pairing::Dropped),
+    );
+    out
+}
+
+/// Per-file explanation of how two paired commits differ.
+fn differences(old: &CommitFingerprint, new: &CommitFingerprint) -> Vec<String> {
+    let paths: BTreeSet<&str> = old
+        .files
+        .iter()
+        .chain(&new.files)
+        .map(|f| f.path.as_str())
+        .collect();
+    let find = |c: &CommitFingerprint, path: &str| {
+        c.files
+            .iter()
+            .find(|f| f.path == path)
+            .map(|f| f.fingerprint)
+    };
+    paths
+        .into_iter()
+        .filter_map(|path| match (find(old, path), find(new, path)) {
+            (Some(a), Some(b)) if a != b => Some(format!("{}: changed differently", path)),
+            (Some(_), None) => Some(format!("{}: only changed in old", path)),
+            (None, Some(_)) => Some(format!("{}: only changed in new", path)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn label(series: &[CommitFingerprint], i: usize) -> String {
+    let id = series[i].commit.to_string();
+    format!("{}: {}", i + 1, &id[..7])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Oid;
+
+    fn commit(summary: &str, change: &str) -> CommitFingerprint {
+        CommitFingerprint {
+            commit: Oid::zero(),
+            summary: summary.to_string(),
+            files: Vec::new(),
+            fingerprint: Oid::hash_object(git2::ObjectType::Blob, change.as_bytes()).unwrap(),
+        }
+    }
+
+    #[test]
+    fn pairs_by_fingerprint_then_subject() {
+        let old = [
+            commit("a", "1"),
+            commit("b", "2"),
+            commit("c", "3"),
+            commit("d", "4"),
+        ];
+        let new = [
+            commit("a'", "1"),
+            commit("c", "3x"),
+            commit("d", "4"),
+            commit("e", "5"),
+        ];
+        assert_eq!(
+            pair(&old, &new),
+            [
+                Pairing::Unchanged(0, 0),
+                Pairing::Dropped(1),
+                Pairing::Modified(2, 1),
+                Pairing::Unchanged(3, 2),
+                Pairing::Added(3),
+            ]
+        );
+    }
+}
@@ -0,0 +1,295 @@
+//! `git-ast report unused [--range <range>]`, `git-ast report usage`
+//!
+//! `unused` is built on the [symbol index](crate::symbols). It lists, for
+//! cleanup audits, the symbols that a commit in the range added and a later
+//! one removed again, then the symbols at the tip of the range that nothing
+//! in the repository refers to:
+//!
+//! ```text
+//! $ git-ast report unused --range v1.0..main
+//! removed  function_item  parse_legacy  added 1a2b3c4, removed 5d6e7f8
+//! unused   function_item  old_helper    src/util.rs:12
+//! ```
+//!
+//! References are matched by name only (see the module docs of
+//! [`symbols`](crate::symbols)), so treat `unused` entries as candidates.
+//!
+//! `usage` summarizes how `git-ast` performs in this repository, from the
+//! [audit log](crate::audit) (`ast.audit = true`), the caches below
+//! `.git/ast-cache` and the metrics (`ast.metrics = true`), to help tune
+//! limits and `.gitattributes` without any outside telemetry:
+//!
+//! ```text
+//! $ git-ast report usage
+//! since 2026-10-01T08:12:44Z: 1204 invocations
+//! clean     812 runs,   3 failed, mean   1.9ms, max 120.4ms
+//! smudge    361 runs,   0 failed, mean   0.8ms, max  14.0ms
+//! clean failure rate: 0.4%
+//! slowest:
+//!   120.4ms  clean   src/generated/tables.rs  (4 runs)
+//! most conflicted:
+//!   3 of 5 merges  src/lib.rs
+//! diff cache: 312 entries, 1.2 MiB, 85% hits
+//! smudge cache: 1843 entries, 21.5 MiB, 97% hits
+//! ```
+
+use crate::audit::{self, AuditLog, Usage};
+use crate::config;
+use crate::model::{Document, SymbolRecord};
+use crate::queries::QueryPacks;
+use crate::symbols::{self, ShortLived};
+use crate::telemetry;
+use crate::Error;
+use clap::{Args, Subcommand, ValueEnum};
+use git2::Repository;
+use serde::Serialize;
+use std::path::Path;
+
+/// `git-ast report` subcommands.
+#[derive(Debug, Subcommand)]
+pub enum ReportCommand {
+    /// List short-lived and unreferenced symbols.
+    Unused(UnusedArgs),
+    /// Summarize performance and failures from the audit log and caches.
+    Usage(UsageArgs),
+}
+
+/// Arguments for `git-ast report unused`.
+#[derive(Debug, Args)]
+pub struct UnusedArgs {
+    /// Revision or `A..B` range whose history to search (default: all of
+    /// `HEAD`); references are counted at its tip.
+    #[arg(long)]
+    pub range: Option<String>,
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+    pub format: ReportFormat,
+}
+
+/// Arguments for `git-ast report usage`.
+#[derive(Debug, Args)]
+pub struct UsageArgs {
+    /// How many slow and conflicted paths to list.
+    #[arg(long, default_value_t = 10)]
+    pub top: usize,
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+    pub format: ReportFormat,
+}
+
+/// Output formats of `git-ast report`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    /// One line per symbol.
+    Text,
+    /// A single JSON document.
+    Json,
+}
+
+/// The result of `git-ast report unused`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Unused {
+    pub removed: Vec<ShortLived>,
+    pub unreferenced: Vec<SymbolRecord>,
+}
+
+/// Runs a `git-ast report` subcommand.
+pub fn run(command: &ReportCommand) -> Result<(), Error> {
+    match command {
+        ReportCommand::Unused(args) => unused(args),
+        ReportCommand::Usage(args) => usage(args),
+    }
+}
+
+fn unused(args: &UnusedArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let limits = config::Settings::load(Some(&repo))?.parse_limits;
+    let mut packs = QueryPacks::for_repo(&repo);
+    let range = args.range.as_deref();
+    let tip = match range {
+        Some(r) => r
+            .rsplit("..")
+            .next()
+            .filter(|tip| !tip.is_empty())
+            .unwrap_or("HEAD"),
+        None => "HEAD",
+    };
+    let report = Unused {
+        removed: symbols::removed_in(&repo, range, &limits, &mut packs)?,
+        unreferenced: symbols::unreferenced(&repo, tip, &limits, &mut packs)?
+            .into_iter()
+            .map(SymbolRecord::from)
+            .collect(),
+    };
+    match args.format {
+        ReportFormat::Json => {
+            println!("{}", Document::new(&report).to_json()?);
+        }
+        ReportFormat::Text => {
+            let pairs = report.removed.iter().map(|s| (&s.kind, &s.name));
+            let pairs = pairs.chain(report.unreferenced.iter().map(|s| (&s.kind, &s.name)));
+            let (kind_width, name_width) = pairs.fold((0, 0), |(k, n), (kind, name)| {
+                (k.max(kind.len()), n.max(name.len()))
+            });
+            for s in &report.removed {
+                println!(
+                    "removed  {:<kind_width$}  {:<name_width$}  added {}, removed {}",
+                    s.kind,
+                    s.name,
+                    &s.added[..7],
+                    &s.removed[..7]
+                );
+            }
+            for s in &report.unreferenced {
+                println!(
+                    "unused   {:<kind_width$}  {:<name_width$}  {}:{}",
+                    s.kind, s.name, s.path, s.line
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Entries, bytes and hit rate of one cache.
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheUsage {
+    pub name: &'static str,
+    pub entries: u64,
+    pub bytes: u64,
+    /// Share of lookups answered from the cache, if metrics were collected.
+    pub hit_rate: Option<f64>,
+}
+
+/// The result of `git-ast report usage`.
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageReport {
+    #[serde(flatten)]
+    pub usage: Usage,
+    pub clean_failure_rate: f64,
+    pub caches: Vec<CacheUsage>,
+}
+
+fn usage(args: &UsageArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let records = AuditLog::at(&repo).read()?;
+    let usage = audit::usage(&records, args.top);
+    let totals = telemetry::read_totals(&telemetry::metrics_path(&repo))?;
+    let hit_rate = |family: &str| {
+        let hits = totals
+            .get(&format!("{}{{result=\"hit\"}}", family))
+            .copied()
+            .unwrap_or(0.0);
+        let misses = totals
+            .get(&format!("{}{{result=\"miss\"}}", family))
+            .copied()
+            .unwrap_or(0.0);
+        (hits + misses > 0.0).then(|| hits / (hits + misses))
+    };
+    let cache_dir = repo.path().join("ast-cache");
+    let caches = [
+        ("diff cache", "diffs", "git_ast_diff_cache_requests_total"),
+        (
+            "smudge cache",
+            "smudge",
+            "git_ast_smudge_cache_requests_total",
+        ),
+    ]
+    .into_iter()
+    .map(|(name, dir, family)| {
+        let (entries, bytes) = dir_usage(&cache_dir.join(dir));
+        CacheUsage {
+            name,
+            entries,
+            bytes,
+            hit_rate: hit_rate(family),
+        }
+    })
+    .collect();
+    let report = UsageReport {
+        clean_failure_rate: usage.clean_failure_rate(),
+        usage,
+        caches,
+    };
+
+    if args.format == ReportFormat::Json {
+        let json = serde_json::to_string_pretty(&report)
+            .map_err(|e| Error::Serialization(format!("report: {}", e)))?;
+        println!("{}", json);
+        return Ok(());
+    }
+    let usage = &report.usage;
+    match usage.since {
+        Some(ts) => {
+            println!(
+                "since {}: {} invocations",
+                audit::format_time(ts),
+                records.len()
+            );
+        }
+        None => println!(
+            "no audit records (enable them with `git config {} true`)",
+            audit::AUDIT_KEY
+        ),
+    }
+    for (command, t) in &usage.commands {
+        println!(
+            "{:<7} {:>5} runs, {:>3} failed, mean {:>6.1}ms, max {:>6.1}ms",
+            command,
+            t.count,
+            t.failed,
+            t.mean_ms(),
+            t.max_ms
+        );
+    }
+    if usage.commands.contains_key("clean") {
+        println!(
+            "clean failure rate: {:.1}%",
+            report.clean_failure_rate * 100.0
+        );
+    }
+    if !usage.slowest.is_empty() {
+        println!("slowest:");
+        for (command, path, t) in &usage.slowest {
+            println!(
+                "  {:>6.1}ms  {:<6}  {}  ({} runs)",
+                t.max_ms, command, path, t.count
+            );
+        }
+    }
+    if !usage.conflicted.is_empty() {
+        println!("most conflicted:");
+        for (path, t) in &usage.conflicted {
+            println!("  {} of {} merges  {}", t.failed, t.count, path);
+        }
+    }
+    for cache in &report.caches {
+        let hits = cache
+            .hit_rate
+            .map(|rate| format!(", {:.0}% hits", rate * 100.0))
+            .unwrap_or_default();
+        let mib = cache.bytes as f64 / (1024.0 * 1024.0);
+        println!(
+            "{}: {} entries, {:.1} MiB{}",
+            cache.name, cache.entries, mib, hits
+        );
+    }
+    Ok(())
+}
+
+/// Number of files below `dir` and their total size.
+fn dir_usage(dir: &Path) -> (u64, u64) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return (0, 0);
+    };
+    entries
+        .flatten()
+        .fold((0, 0), |(files, bytes), entry| match entry.file_type() {
+            Ok(kind) if kind.is_dir() => {
+                let (f, b) = dir_usage(&entry.path());
+                (files + f, bytes + b)
+            }
+            Ok(_) => (files + 1, bytes + entry.metadata().map_or(0, |m| m.len())),
+            Err(_) => (files, bytes),
+        })
+}
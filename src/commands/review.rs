@@ -0,0 +1,86 @@
+//! `git-ast review <range> [--format markdown|json]`
+//!
+//! A checklist of the structurally risky changes in a range, for reviewers
+//! and review bots: public signatures that changed, error handling that was
+//! removed, new `unsafe` blocks and whatever `[[review]]` rules the project
+//! adds to `.git-ast.toml` (see [`review`](crate::review)).
+//!
+//! ```text
+//! $ git-ast review main..topic
+//! # Review checklist: main..topic
+//!
+//! ## High priority
+//!
+//! - [ ] `src/parse.rs:12` **public-signature**: public signature changed
+//!   - before: `pub fn parse(source: &[u8]) -> Tree`
+//!   - after: `pub fn parse(source: &[u8], strict: bool) -> Tree`
+//! ```
+//!
+//! `A..B` and `A...B` are both compared from their merge base, as a pull
+//! request is; a single commit against its first parent.
+
+use crate::commands::review_anchors;
+use crate::model::Document;
+use crate::review::{self, Item};
+use crate::Error;
+use clap::{Args, ValueEnum};
+use git2::Repository;
+use serde::Serialize;
+
+/// Arguments for `git-ast review`.
+#[derive(Debug, Args)]
+pub struct ReviewArgs {
+    /// The changes to review: `A..B`, `A...B` or a single commit.
+    pub range: String,
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = ReviewFormat::Markdown)]
+    pub format: ReviewFormat,
+}
+
+/// Output formats of `git-ast review`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReviewFormat {
+    /// A Markdown task list, grouped by priority.
+    Markdown,
+    /// A single JSON document.
+    Json,
+}
+
+/// The JSON output.
+#[derive(Debug, Serialize)]
+struct Checklist<'a> {
+    range: &'a str,
+    base: Option<String>,
+    head: String,
+    items: &'a [Item],
+}
+
+/// Runs `git-ast review`.
+pub fn run(args: &ReviewArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let (base, head) = review_anchors::endpoints(&repo, &args.range)?;
+    // `A..B` is reviewed like a pull request, from the merge base.
+    let base = match (base, args.range.contains("..")) {
+        (Some(base), true) => Some(repo.merge_base(base, head)?),
+        (base, _) => base,
+    };
+    let base_tree = match base {
+        Some(base) => Some(repo.find_commit(base)?.tree()?),
+        None => None,
+    };
+    let head_tree = repo.find_commit(head)?.tree()?;
+    let items = review::checklist(&repo, base_tree.as_ref(), &head_tree)?;
+    match args.format {
+        ReviewFormat::Markdown => print!("{}", review::markdown(&args.range, &items)),
+        ReviewFormat::Json => {
+            let checklist = Document::new(Checklist {
+                range: &args.range,
+                base: base.map(|id| id.to_string()),
+                head: head.to_string(),
+                items: &items,
+            });
+            println!("{}", checklist.to_json()?);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,401 @@
+//! `git-ast review-anchors <range> [--format json]`
+//!
+//! Hosting platforms review the source mirror (see the crate docs on the
+//! mirrored repository), while the change itself lives in the AST
+//! repository. This command lists every structural change in a range with
+//! where it appears in both renderings, so a bot can turn findings from
+//! either side into review comments on the mirror pull request:
+//!
+//! -   **`source`:** lines of the smudged source, i.e. the file as the mirror
+//!     holds it and as the pull request diff shows it.
+//! -   **`ast`:** lines of the stored AST blob, as the platform shows it for
+//!     the AST repository itself.
+//!
+//! Each side has an `old` and a `new` range (inclusive, 1-based); `old` is
+//! `null` for pure insertions and `new` for pure deletions, which matches
+//! how review APIs anchor comments on the left or right side of a diff.
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "base": "1a2b...",
+//!   "head": "5d6e...",
+//!   "anchors": [
+//!     {
+//!       "path": "src/parse.rs",
+//!       "source": { "old": { "start": 12, "end": 12 }, "new": { "start": 12, "end": 14 } },
+//!       "ast": { "old": { "start": 80, "end": 83 }, "new": { "start": 80, "end": 97 } }
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! The document is a [`ChangeSet`](crate::model::ChangeSet); `base` is
+//! `null` when `head` is a root commit.
+//!
+//! Changes are found by diffing leaf tokens, so formatting-only differences
+//! never produce anchors. In a source repository with a sidecar (see
+//! [`mirror`](crate::mirror)), `ast` ranges refer to the sidecar's AST blobs
+//! and `source` ranges to the committed source. Other files that are not AST
+//! blobs are compared by line and have identical ranges on both sides.
+
+use crate::ast;
+use crate::drivers::LineRange;
+use crate::git_plumbing::filters;
+use crate::languages;
+use crate::mirror;
+use crate::model::{Change, ChangeSet, Document};
+use crate::parsing::{self, ParseLimits};
+use crate::serialization;
+use crate::Error;
+use clap::{Args, ValueEnum};
+use git2::{FileMode, Oid, Repository, RevparseMode};
+use similar::{Algorithm, DiffOp};
+use std::ops::Range;
+
+/// Arguments for `git-ast review-anchors`.
+#[derive(Debug, Args)]
+pub struct ReviewAnchorsArgs {
+    /// The changes to anchor: `A..B`, `A...B` (from the merge base), or a
+    /// single commit (against its first parent).
+    pub range: String,
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = AnchorFormat::Text)]
+    pub format: AnchorFormat,
+}
+
+/// Output formats of `git-ast review-anchors`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AnchorFormat {
+    /// One line per change.
+    Text,
+    /// A single JSON document.
+    Json,
+}
+
+/// All anchors of a range.
+/// One structural change. Output uses [`Change`](crate::model::Change).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Anchor {
+    pub path: String,
+    /// Lines in the smudged source (the mirror).
+    pub source: Sides,
+    /// Lines in the stored AST blob.
+    pub ast: Sides,
+}
+
+/// Line ranges before and after a change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sides {
+    pub old: Option<LineRange>,
+    pub new: Option<LineRange>,
+}
+
+/// Runs `git-ast review-anchors`.
+pub fn run(args: &ReviewAnchorsArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let (base, head) = endpoints(&repo, &args.range)?;
+    let anchors = anchors(&repo, base, head)?;
+    match args.format {
+        AnchorFormat::Json => {
+            let report = Document::new(ChangeSet {
+                base: base.map(|id| id.to_string()),
+                head: Some(head.to_string()),
+                anchors: anchors.into_iter().map(Change::from).collect(),
+            });
+            println!("{}", report.to_json()?);
+        }
+        AnchorFormat::Text => {
+            for anchor in &anchors {
+                println!(
+                    "{}: source {} ast {}",
+                    anchor.path,
+                    describe(&anchor.source),
+                    describe(&anchor.ast)
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The commits to compare for `spec`; `None` as base for a root commit.
+pub(crate) fn endpoints(repo: &Repository, spec: &str) -> Result<(Option<Oid>, Oid), Error> {
+    let revspec = repo.revparse(spec)?;
+    let commit = |o: &git2::Object<'_>| o.peel_to_commit().map(|c| c.id());
+    match (revspec.from(), revspec.to()) {
+        (Some(from), Some(to)) => {
+            let (from, to) = (commit(from)?, commit(to)?);
+            if revspec.mode().contains(RevparseMode::MERGE_BASE) {
+                Ok((Some(repo.merge_base(from, to)?), to))
+            } else {
+                Ok((Some(from), to))
+            }
+        }
+        (Some(single), None) => {
+            let head = single.peel_to_commit()?;
+            let base = head.parent_ids().next();
+            Ok((base, head.id()))
+        }
+        _ => Err(Error::Config(format!("cannot resolve {:?}", spec))),
+    }
+}
+
+/// Anchors of every file changed between `base` and `head`.
+pub fn anchors(repo: &Repository, base: Option<Oid>, head: Oid) -> Result<Vec<Anchor>, Error> {
+    let base_tree = match base {
+        Some(id) => Some(repo.find_commit(id)?.tree()?),
+        None => None,
+    };
+    let head_tree = repo.find_commit(head)?.tree()?;
+    let diff = repo.diff_tree_to_tree(base_tree.as_ref(), Some(&head_tree), None)?;
+    let mut out = Vec::new();
+    for delta in diff.deltas() {
+        let (old, new) = (delta.old_file(), delta.new_file());
+        if old.mode() == FileMode::Commit || new.mode() == FileMode::Commit {
+            continue;
+        }
+        let Some(path) = new.path().or(old.path()).and_then(|p| p.to_str()) else {
+            continue;
+        };
+        let old = Rendering::of_blob(repo, old.id(), path)?;
+        let new = Rendering::of_blob(repo, new.id(), path)?;
+        out.extend(file_anchors(path, &old, &new));
+    }
+    Ok(out)
+}
+
+/// A file version as a token sequence, with each token's position in both
+/// renderings.
+#[derive(Debug, Default)]
+pub struct Rendering {
+    tokens: Vec<(String, String)>,
+    /// Line of each token in the AST blob.
+    ast_lines: Vec<usize>,
+    /// Lines spanned by each token in the source.
+    source_lines: Vec<LineRange>,
+}
+
+impl Rendering {
+    /// Tokens of a blob, positioned in its smudged source, or in the source
+    /// blob itself if it has a sidecar AST version (see [`mirror`]).
+    pub fn of_blob(repo: &Repository, id: Oid, path: &str) -> Result<Self, Error> {
+        if id.is_zero() {
+            return Ok(Rendering::default());
+        }
+        let blob = repo.find_blob(id)?;
+        if blob.is_binary() {
+            return Ok(Rendering::default());
+        }
+        if !serialization::is_ast_blob(blob.content()) {
+            if let Some(ast) = mirror::ast_blob_of(repo, id)? {
+                let ast = repo.find_blob(ast)?;
+                if let Some(rendering) =
+                    Self::of_ast_blob(ast.content(), path, Some(blob.content()))?
+                {
+                    return Ok(rendering);
+                }
+            }
+        }
+        Self::of_content(blob.content(), path)
+    }
+
+    /// Tokens of an AST blob, or lines of any other content.
+    pub fn of_content(content: &[u8], path: &str) -> Result<Self, Error> {
+        if serialization::is_ast_blob(content) {
+            if let Some(rendering) = Self::of_ast_blob(content, path, None)? {
+                return Ok(rendering);
+            }
+        }
+        Ok(Self::of_text(content))
+    }
+
+    /// Tokens of an AST blob, positioned in `source` (the printed blob if
+    /// `None`). `None` if the blob is sparse, or `source` does not parse to
+    /// its tokens.
+    pub fn of_ast_blob(
+        content: &[u8],
+        path: &str,
+        source: Option<&[u8]>,
+    ) -> Result<Option<Self>, Error> {
+        let (header, root) = serialization::decode(content)?;
+        let Some(provider) = languages::by_name(&header.language) else {
+            return Ok(None);
+        };
+        if header.sparse {
+            // The skeleton's tokens are not the file's.
+            return Ok(None);
+        }
+        let source = match source {
+            Some(source) => source.to_vec(),
+            None => filters::perform_smudge(content, path)?,
+        };
+        let tree = parsing::parse(&source, provider, &ParseLimits::default())?;
+        let (printed, spans) = ast::from_tree_with_spans(&tree, &source, provider)?;
+        let leaves = root.leaves();
+        if printed.leaves().len() != leaves.len() {
+            // The source does not parse to the stored tokens.
+            return Ok(None);
+        }
+        let starts = line_starts(&source);
+        Ok(Some(Rendering {
+            tokens: leaves
+                .iter()
+                .map(|l| (l.kind.clone(), l.text.clone().unwrap_or_default()))
+                .collect(),
+            ast_lines: serialization::leaf_lines(&header, &root),
+            source_lines: spans
+                .iter()
+                .map(|span| LineRange {
+                    start: line_of(&starts, span.start),
+                    end: line_of(&starts, span.end.max(span.start + 1) - 1),
+                })
+                .collect(),
+        }))
+    }
+
+    fn of_text(content: &[u8]) -> Self {
+        let text = String::from_utf8_lossy(content);
+        let mut rendering = Rendering::default();
+        for (i, line) in text.lines().enumerate() {
+            rendering
+                .tokens
+                .push(("line".to_string(), line.to_string()));
+            rendering.ast_lines.push(i + 1);
+            rendering.source_lines.push(LineRange {
+                start: i + 1,
+                end: i + 1,
+            });
+        }
+        rendering
+    }
+
+    fn ast_range(&self, tokens: &Range<usize>) -> Option<LineRange> {
+        (!tokens.is_empty()).then(|| LineRange {
+            start: self.ast_lines[tokens.start],
+            end: self.ast_lines[tokens.end - 1],
+        })
+    }
+
+    fn source_range(&self, tokens: &Range<usize>) -> Option<LineRange> {
+        (!tokens.is_empty()).then(|| LineRange {
+            start: self.source_lines[tokens.start].start,
+            end: self.source_lines[tokens.end - 1].end,
+        })
+    }
+}
+
+/// Anchors for `old` → `new`. Changes that touch a common source line are
+/// reported together, since a review comment can only point at lines.
+pub fn file_anchors(path: &str, old: &Rendering, new: &Rendering) -> Vec<Anchor> {
+    let mut changes: Vec<(Range<usize>, Range<usize>)> = Vec::new();
+    for op in similar::capture_diff_slices(Algorithm::Myers, &old.tokens, &new.tokens) {
+        let (o, n) = match op {
+            DiffOp::Equal { .. } => continue,
+            DiffOp::Delete {
+                old_index,
+                old_len,
+                new_index,
+            } => (old_index..old_index + old_len, new_index..new_index),
+            DiffOp::Insert {
+                old_index,
+                new_index,
+                new_len,
+            } => (old_index..old_index, new_index..new_index + new_len),
+            DiffOp::Replace {
+                old_index,
+                old_len,
+                new_index,
+                new_len,
+            } => (
+                old_index..old_index + old_len,
+                new_index..new_index + new_len,
+            ),
+        };
+        if let Some((prev_o, prev_n)) = changes.last_mut() {
+            let touches = |r: &Rendering, prev: &Range<usize>, next: &Range<usize>| {
+                !prev.is_empty()
+                    && !next.is_empty()
+                    && r.source_lines[next.start].start <= r.source_lines[prev.end - 1].end
+            };
+            if touches(old, prev_o, &o) || touches(new, prev_n, &n) {
+                *prev_o = prev_o.start..o.end.max(prev_o.end);
+                *prev_n = prev_n.start..n.end.max(prev_n.end);
+                continue;
+            }
+        }
+        changes.push((o, n));
+    }
+    changes
+        .into_iter()
+        .map(|(o, n)| Anchor {
+            path: path.to_string(),
+            source: Sides {
+                old: old.source_range(&o),
+                new: new.source_range(&n),
+            },
+            ast: Sides {
+                old: old.ast_range(&o),
+                new: new.ast_range(&n),
+            },
+        })
+        .collect()
+}
+
+fn line_starts(source: &[u8]) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(
+            source
+                .iter()
+                .enumerate()
+                .filter(|(_, &b)| b == b'\n')
+                .map(|(i, _)| i + 1),
+        )
+        .collect()
+}
+
+/// 1-based line containing byte `offset`.
+fn line_of(starts: &[usize], offset: usize) -> usize {
+    starts.partition_point(|&start| start <= offset)
+}
+
+fn describe(sides: &Sides) -> String {
+    let side = |sign: char, range: Option<LineRange>| match range {
+        Some(r) if r.start == r.end => format!("{}{}", sign, r.start),
+        Some(r) => format!("{}{}-{}", sign, r.start, r.end),
+        None => format!("{}none", sign),
+    };
+    format!("{} {}", side('-', sides.old), side('+', sides.new))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blob(source: &str) -> Rendering {
+        let rust = languages::by_name("rust");
+        let limits = ParseLimits::default();
+        let content = filters::clean_as(source.as_bytes(), "a.rs", rust, &limits).unwrap();
+        Rendering::of_content(&content, "a.rs").unwrap()
+    }
+
+    #[test]
+    fn anchors_point_at_both_renderings() {
+        let old = blob("fn a() {\n    x(1);\n}\n\nfn b() {}\n");
+        let new = blob("fn a() {\n    x(2);\n}\n\nfn b() {}\n\nfn c() {}\n");
+        let anchors = file_anchors("a.rs", &old, &new);
+        assert_eq!(anchors.len(), 2, "{:?}", anchors);
+        let line = |n| Some(LineRange { start: n, end: n });
+        assert_eq!(
+            anchors[0].source,
+            Sides {
+                old: line(2),
+                new: line(2)
+            }
+        );
+        assert_eq!(anchors[0].ast.old, anchors[0].ast.new);
+        assert_eq!(anchors[1].source.old, None);
+        assert_eq!(anchors[1].source.new, Some(LineRange { start: 7, end: 7 }));
+        assert!(anchors[1].ast.new.unwrap().start > anchors[0].ast.new.unwrap().end);
+    }
+}
@@ -0,0 +1,32 @@
+//! `git-ast rpc [--lsp]`
+//!
+//! Serves the editor protocol (see [`rpc`](crate::rpc)) on standard input
+//! and output, for plugins that start it as a child process in the
+//! repository. With `--lsp` it is a language server instead (see
+//! [`lsp`](crate::lsp)), for editors' generic LSP clients.
+
+use crate::lsp::LanguageServer;
+use crate::rpc::{self, Handler, Server};
+use crate::Error;
+use clap::Args;
+use git2::Repository;
+use std::io::BufReader;
+
+/// Arguments for `git-ast rpc`.
+#[derive(Debug, Args)]
+pub struct RpcArgs {
+    /// Speak the Language Server Protocol.
+    #[arg(long)]
+    pub lsp: bool,
+}
+
+/// Runs `git-ast rpc`.
+pub fn run(args: &RpcArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let mut server: Box<dyn Handler> = match args.lsp {
+        true => Box::new(LanguageServer::new(repo)?),
+        false => Box::new(Server::new(repo)?),
+    };
+    let stdin = BufReader::new(std::io::stdin());
+    rpc::serve(server.as_mut(), stdin, std::io::stdout().lock())
+}
@@ -0,0 +1,276 @@
+//! Golden Corpus Regression Suite (`git-ast selftest`)
+//!
+//! The golden corpus is a set of real-world source files, grouped by language,
+//! together with the output the clean/smudge pipeline is *expected* to produce
+//! for them. Any change to the serialization format or the pretty-printer shows
+//! up as a snapshot mismatch, and blessing the new output turns it into a
+//! reviewable diff of the committed snapshot files.
+//!
+//! ## Layout
+//!
+//! ```text
+//! testdata/
+//!   rust/
+//!     poc_sample.rs          # input, exactly as a developer wrote it
+//!     poc_sample.rs.ast      # expected `clean` output (the stored blob)
+//!     poc_sample.rs.out      # expected `smudge` output (the checked-out source)
+//!   python/
+//!     ...
+//! ```
+//!
+//! Every file that does not end in a snapshot extension is an input. The
+//! directory name is the language label; the pipeline itself still selects
+//! behaviour from the file path, just as it would when invoked by Git.
+//!
+//! ## Usage
+//!
+//! -   `git-ast selftest`: Compare, report mismatches, fail if any.
+//! -   `git-ast selftest --bless`: Rewrite all snapshots from the current
+//!     pipeline output (and delete snapshots whose input is gone).
+//!
+//! Independently of the snapshots, every stored blob must survive
+//! smudge-then-clean unchanged, and the same input with CRLF line endings
+//! must produce the identical blob; a failure there is always reported.
+
+use crate::git_plumbing::filters;
+use crate::parsing::ParseLimits;
+use crate::serialization;
+use crate::util::atomic_io;
+use crate::Error;
+use clap::Args;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Extension of the snapshot holding the expected `clean` output.
+pub const CLEAN_SNAPSHOT_EXT: &str = "ast";
+/// Extension of the snapshot holding the expected `smudge` output.
+pub const SMUDGE_SNAPSHOT_EXT: &str = "out";
+
+/// Arguments for `git-ast selftest`.
+#[derive(Debug, Args)]
+pub struct SelftestArgs {
+    /// Rewrite the snapshots from the current pipeline output.
+    #[arg(long)]
+    pub bless: bool,
+    /// Root of the golden corpus.
+    #[arg(long, default_value = "testdata")]
+    pub root: PathBuf,
+    /// Only run inputs whose corpus path contains this string.
+    #[arg(long)]
+    pub filter: Option<String>,
+}
+
+/// A single snapshot that did not match the pipeline output.
+#[derive(Debug, Clone)]
+pub struct Mismatch {
+    /// Snapshot path, relative to the corpus root.
+    pub snapshot: PathBuf,
+    /// Human-readable description of the difference.
+    pub detail: String,
+}
+
+/// Outcome of a corpus run.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Number of input files processed.
+    pub inputs: usize,
+    /// Snapshots that were (re)written because of `--bless`.
+    pub blessed: Vec<PathBuf>,
+    /// Snapshots without a corresponding input (removed when blessing).
+    pub stale: Vec<PathBuf>,
+    /// Snapshots that differ from (or are missing for) the current output.
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl Report {
+    /// Whether the corpus matched its snapshots.
+    pub fn is_success(&self) -> bool {
+        self.mismatches.is_empty() && self.stale.is_empty()
+    }
+}
+
+/// Entry point for `git-ast selftest`.
+pub fn run(args: &SelftestArgs) -> Result<(), Error> {
+    let report = run_corpus(&args.root, args.bless, args.filter.as_deref())?;
+    for mismatch in &report.mismatches {
+        println!(
+            "MISMATCH {}\n{}",
+            mismatch.snapshot.display(),
+            mismatch.detail
+        );
+    }
+    for stale in &report.stale {
+        if args.bless {
+            println!("removed stale snapshot {}", stale.display());
+        } else {
+            println!("STALE {} (input no longer exists)", stale.display());
+        }
+    }
+    for blessed in &report.blessed {
+        println!("blessed {}", blessed.display());
+    }
+    println!(
+        "selftest: {} inputs, {} mismatches, {} stale",
+        report.inputs,
+        report.mismatches.len(),
+        report.stale.len()
+    );
+    if args.bless || report.is_success() {
+        Ok(())
+    } else {
+        Err(Error::Verification(format!(
+            "{} snapshot(s) out of date; rerun with --bless and review the diff",
+            report.mismatches.len() + report.stale.len()
+        )))
+    }
+}
+
+/// Runs every input under `root` through clean and smudge and compares (or,
+/// with `bless`, rewrites) the snapshots next to it.
+pub fn run_corpus(root: &Path, bless: bool, filter: Option<&str>) -> Result<Report, Error> {
+    let mut files = Vec::new();
+    collect_files(root, &mut files)?;
+    files.sort();
+
+    let mut report = Report::default();
+    for file in &files {
+        let rel = file.strip_prefix(root).unwrap_or(file);
+        if let Some(input) = snapshot_input(file) {
+            if !input.exists() {
+                if bless {
+                    fs::remove_file(file)?;
+                }
+                report.stale.push(rel.to_path_buf());
+            }
+            continue;
+        }
+        if filter.is_some_and(|f| !rel.to_string_lossy().contains(f)) {
+            continue;
+        }
+        report.inputs += 1;
+
+        let source = fs::read(file)?;
+        let pathname = rel.to_string_lossy();
+        let cleaned = filters::perform_clean(&source, &pathname, &ParseLimits::default())?;
+        let smudged = filters::perform_smudge(&cleaned, &pathname)?;
+        // Not a snapshot property, so blessing cannot paper over it.
+        if serialization::is_ast_blob(&cleaned) {
+            if let Err(e) = filters::verify_round_trip(&cleaned, &pathname) {
+                report.mismatches.push(Mismatch {
+                    snapshot: rel.to_path_buf(),
+                    detail: format!("  {}", e),
+                });
+            }
+        }
+        // The blob must not depend on the checkout's line endings either.
+        let crlf = source.iter().fold(Vec::new(), |mut out, &b| {
+            if b == b'\n' && out.last() != Some(&b'\r') {
+                out.push(b'\r');
+            }
+            out.push(b);
+            out
+        });
+        if filters::perform_clean(&crlf, &pathname, &ParseLimits::default())? != cleaned {
+            report.mismatches.push(Mismatch {
+                snapshot: rel.to_path_buf(),
+                detail: "  blob differs when the input has CRLF line endings".to_string(),
+            });
+        }
+
+        for (ext, actual) in [
+            (CLEAN_SNAPSHOT_EXT, &cleaned),
+            (SMUDGE_SNAPSHOT_EXT, &smudged),
+        ] {
+            let snapshot = snapshot_path(file, ext);
+            let snapshot_rel = snapshot
+                .strip_prefix(root)
+                .unwrap_or(&snapshot)
+                .to_path_buf();
+            if bless {
+                if fs::read(&snapshot).ok().as_ref() != Some(actual) {
+                    atomic_io::write(&snapshot, actual)?;
+                    report.blessed.push(snapshot_rel);
+                }
+                continue;
+            }
+            match fs::read(&snapshot) {
+                Ok(expected) if &expected == actual => {}
+                Ok(expected) => report.mismatches.push(Mismatch {
+                    snapshot: snapshot_rel,
+                    detail: describe_difference(&expected, actual),
+                }),
+                Err(_) => report.mismatches.push(Mismatch {
+                    snapshot: snapshot_rel,
+                    detail: "  snapshot missing".to_string(),
+                }),
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// The corpus inputs under `root` (every file but snapshots and READMEs),
+/// sorted.
+pub fn inputs(root: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut files = Vec::new();
+    collect_files(root, &mut files)?;
+    files.retain(|file| snapshot_input(file).is_none());
+    files.sort();
+    Ok(files)
+}
+
+fn collect_files(dir: &Path, out: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, out)?;
+        } else if path.file_name().is_some_and(|n| n != "README.md") {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the input path if `path` is a snapshot file.
+fn snapshot_input(path: &Path) -> Option<PathBuf> {
+    let ext = path.extension()?;
+    if ext == CLEAN_SNAPSHOT_EXT || ext == SMUDGE_SNAPSHOT_EXT {
+        Some(path.with_extension(""))
+    } else {
+        None
+    }
+}
+
+fn snapshot_path(input: &Path, ext: &str) -> PathBuf {
+    let mut name = input.as_os_str().to_owned();
+    name.push(".");
+    name.push(ext);
+    PathBuf::from(name)
+}
+
+/// Points at the first differing line, which is usually enough to tell a
+/// format change from a printer change; the full diff is visible in
+/// `git diff` after blessing.
+pub(crate) fn describe_difference(expected: &[u8], actual: &[u8]) -> String {
+    let expected = String::from_utf8_lossy(expected);
+    let actual = String::from_utf8_lossy(actual);
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    let mut line = 1;
+    loop {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(e), Some(a)) if e == a => line += 1,
+            (None, None) => {
+                return "  contents differ only in trailing newline".to_string();
+            }
+            (e, a) => {
+                return format!(
+                    "  first difference at line {}\n  - {}\n  + {}",
+                    line,
+                    e.unwrap_or("<end of file>"),
+                    a.unwrap_or("<end of file>")
+                );
+            }
+        }
+    }
+}
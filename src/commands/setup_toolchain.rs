@@ -0,0 +1,64 @@
+//! `git-ast setup-toolchain [--dry-run]`
+//!
+//! One command from a fresh clone to a working checkout: reads the project's
+//! `.git-ast.lock` and `.git-ast.toml`, builds the `git-ast` release they
+//! need into the per-user cache unless the running binary already suits them,
+//! and points the repository's `ast` filter and drivers at that binary (see
+//! [`toolchain`](crate::toolchain)). `--dry-run` only reports what it would
+//! do.
+
+use crate::toolchain::{self, Plan, Requirements};
+use crate::Error;
+use clap::Args;
+use git2::Repository;
+
+/// Arguments for `git-ast setup-toolchain`.
+#[derive(Debug, Args)]
+pub struct SetupToolchainArgs {
+    /// Report what would be built and configured without doing it.
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+/// Runs `git-ast setup-toolchain`.
+pub fn run(args: &SetupToolchainArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let requirements = Requirements::load(&repo)?;
+    let exe = match toolchain::plan(&requirements)? {
+        Plan::Current => {
+            println!(
+                "this git-ast {} suits the project",
+                env!("CARGO_PKG_VERSION")
+            );
+            std::env::current_exe()?
+        }
+        Plan::Install(version) => {
+            let cache = toolchain::cache_dir().ok_or_else(|| {
+                Error::Config("no cache directory; set GIT_AST_CACHE_DIR".to_string())
+            })?;
+            if args.dry_run {
+                let exe = toolchain::toolchain_exe(&cache, &version);
+                match exe.is_file() {
+                    true => println!("would use git-ast {} from {}", version, exe.display()),
+                    false => println!("would build git-ast {} into {}", version, exe.display()),
+                }
+                exe
+            } else {
+                let exe = toolchain::install(&cache, &version)?;
+                println!("using git-ast {} from {}", version, exe.display());
+                exe
+            }
+        }
+    };
+    if args.dry_run {
+        println!(
+            "would point the ast filter and drivers at {}",
+            exe.display()
+        );
+        return Ok(());
+    }
+    for (key, value) in toolchain::configure(&repo, &exe)? {
+        println!("{} = {}", key, value);
+    }
+    Ok(())
+}
@@ -0,0 +1,284 @@
+//! `git-ast show [<rev>] [--format readable|patch] [-- <path>...]`
+//!
+//! `git show` for a whole commit, on canonical source: every file the commit
+//! changes is rendered the way the diff driver renders it (see
+//! [`changes`]), so reformatting never shows and renames are scored by
+//! syntax tokens. Files without `diff=ast` are treated the same way, so the
+//! command also works where the driver is not configured.
+//!
+//! ```text
+//! commit 5d6e7f8...
+//! Author: Ada <ada@example.com>
+//! Date:   2026-03-01T12:00:00Z
+//!
+//!     Split the header parser
+//!
+//!  refactor: move read_magic from src/util.rs to src/parse.rs
+//!  M    src/parse.rs: changed parse_header
+//!  M    src/util.rs
+//!
+//! diff --git a/src/parse.rs b/src/parse.rs
+//! ...
+//! ```
+//!
+//! The summary lists each file with its status as in
+//! `git diff --name-status` and the top-level items (functions, types,
+//! imports, ...) that were added, removed or changed. A merge commit is
+//! compared with its first parent, a root commit with the empty tree.
+//!
+//! Renames and moves that span files are listed once above the files, as
+//! `refactor:` lines (see [`refactorings`]); the items they account for are
+//! left out of the files' lines, so a function renamed along with its fifty
+//! call sites reads as one edit.
+
+use crate::audit::format_time;
+use crate::changes::{self, FileChange, Items};
+use crate::diff_backend::DiffFormat;
+use crate::refactorings::{self, Detected};
+use crate::util::parallel;
+use crate::Error;
+use clap::Args;
+use git2::{Commit, Repository};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Arguments for `git-ast show`.
+#[derive(Debug, Args)]
+pub struct ShowArgs {
+    /// The commit to show.
+    #[arg(default_value = "HEAD")]
+    pub rev: String,
+    /// How to render each file.
+    #[arg(long, value_enum, default_value = "readable")]
+    pub format: DiffFormat,
+    /// Limit to these paths (pathspecs, relative to the repository root).
+    #[arg(last = true)]
+    pub paths: Vec<String>,
+}
+
+/// Runs `git-ast show`.
+pub fn run(args: &ShowArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let shown = show(&repo, &args.rev, &args.paths, args.format)?;
+    std::io::stdout().write_all(&shown)?;
+    Ok(())
+}
+
+/// The header, file summary and per-file diffs of `rev`.
+pub fn show(
+    repo: &Repository,
+    rev: &str,
+    paths: &[String],
+    format: DiffFormat,
+) -> Result<Vec<u8>, Error> {
+    let commit = repo.revparse_single(rev)?.peel_to_commit()?;
+    let parent = match commit.parent_count() {
+        0 => None,
+        _ => Some(commit.parent(0)?.tree()?),
+    };
+    let files = changes::between(repo, parent.as_ref(), Some(&commit.tree()?), paths)?;
+    let mut out = header(&commit).into_bytes();
+    out.extend(report(repo, &files, format)?);
+    Ok(out)
+}
+
+/// The refactorings and file summary of `files`, then each file's diff, as
+/// `git-ast show` prints them below the commit header (and `git-ast diff`
+/// prints them alone). Files are parsed and rendered in parallel.
+pub fn report(
+    repo: &Repository,
+    files: &[FileChange],
+    format: DiffFormat,
+) -> Result<Vec<u8>, Error> {
+    let (items, detected) = analyze(repo, files)?;
+    let mut out = refactor_lines(&detected);
+    let indices: Vec<usize> = (0..files.len()).collect();
+    let rendered = parallel::map(repo, &indices, |repo, &f| {
+        let change = &files[f];
+        let rename = change.rename(Some(repo))?;
+        let backend = change.backend(Some(repo))?;
+        let edits = match &items[f] {
+            Some(items) => detected.edits(f, items),
+            None => Vec::new(),
+        };
+        let status = change.status(rename.as_ref());
+        let mut diff = Vec::new();
+        if format == DiffFormat::Readable {
+            let line = format!("diff --git a/{} b/{}\n", change.old_path(), change.path);
+            diff.extend(line.into_bytes());
+        }
+        diff.extend(change.render_with(rename.as_ref(), backend.as_ref(), format)?);
+        Ok((summary(change, &status, &edits), diff))
+    })?;
+    let mut diffs = Vec::new();
+    for (line, diff) in rendered {
+        out.extend(line.into_bytes());
+        diffs.extend(diff);
+    }
+    if !files.is_empty() {
+        out.push(b'\n');
+    }
+    out.extend(diffs);
+    Ok(out)
+}
+
+/// The refactorings of `files`, then a line per directory with how many of
+/// them it holds and how many items they changed, removed and added, as
+/// `git-ast diff --directories` prints them.
+pub fn directories(repo: &Repository, files: &[FileChange]) -> Result<Vec<u8>, Error> {
+    const EDITS: [&str; 3] = ["changed", "removed", "added"];
+    let (items, detected) = analyze(repo, files)?;
+    let mut dirs: BTreeMap<&str, (usize, [usize; 3])> = BTreeMap::new();
+    for (f, change) in files.iter().enumerate() {
+        let dir = change.path.rsplit_once('/').map_or(".", |(dir, _)| dir);
+        let (count, edits) = dirs.entry(dir).or_default();
+        *count += 1;
+        if let Some(items) = &items[f] {
+            for (edit, names) in detected.edited(f, items) {
+                let k = EDITS.iter().position(|&e| e == edit).expect("known edit");
+                edits[k] += names.len();
+            }
+        }
+    }
+    let mut out = refactor_lines(&detected);
+    for (dir, (count, edits)) in dirs {
+        let mut line = match count {
+            1 => format!(" {}/: 1 file", dir),
+            n => format!(" {}/: {} files", dir, n),
+        };
+        let edits: Vec<String> = EDITS
+            .iter()
+            .zip(edits)
+            .filter(|&(_, n)| n > 0)
+            .map(|(edit, n)| format!("{} {}", n, edit))
+            .collect();
+        if !edits.is_empty() {
+            line += &format!("; items {}", edits.join(", "));
+        }
+        out.extend(format!("{}\n", line).into_bytes());
+    }
+    Ok(out)
+}
+
+/// Each of `files`' items, parsed in parallel, and the refactorings across
+/// them.
+fn analyze(
+    repo: &Repository,
+    files: &[FileChange],
+) -> Result<(Vec<Option<Items>>, Detected), Error> {
+    let items = parallel::map(repo, files, |repo, change| Ok(change.items(Some(repo))))?;
+    let paths: Vec<(&str, Option<&Items>)> = files
+        .iter()
+        .zip(&items)
+        .map(|(change, items)| (change.path.as_str(), items.as_ref()))
+        .collect();
+    let detected = refactorings::detect(&paths);
+    Ok((items, detected))
+}
+
+/// One ` refactor:` line per refactoring.
+fn refactor_lines(detected: &Detected) -> Vec<u8> {
+    let mut out = Vec::new();
+    for refactoring in &detected.refactorings {
+        out.extend(format!(" refactor: {}\n", refactoring).into_bytes());
+    }
+    out
+}
+
+/// `commit`'s id, author, date and indented message.
+fn header(commit: &Commit<'_>) -> String {
+    let author = commit.author();
+    let mut out = format!(
+        "commit {}\nAuthor: {} <{}>\nDate:   {}\n\n",
+        commit.id(),
+        String::from_utf8_lossy(author.name_bytes()),
+        String::from_utf8_lossy(author.email_bytes()),
+        format_time(author.when().seconds() as f64)
+    );
+    for line in String::from_utf8_lossy(commit.message_bytes()).lines() {
+        match line.is_empty() {
+            true => out.push('\n'),
+            false => out += &format!("    {}\n", line),
+        }
+    }
+    out.push('\n');
+    out
+}
+
+/// One summary line: status, path and the item edits no refactoring
+/// accounts for.
+fn summary(change: &FileChange, status: &str, edits: &[String]) -> String {
+    let path = match &change.from {
+        Some((from, _)) => format!("{} -> {}", from, change.path),
+        None => change.path.clone(),
+    };
+    match edits.is_empty() {
+        true => format!(" {:<4} {}\n", status, path),
+        false => format!(" {:<4} {}: {}\n", status, path, edits.join("; ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_plumbing::filters;
+    use crate::languages;
+    use crate::parsing::ParseLimits;
+
+    #[test]
+    fn shows_a_commit_with_its_changed_items() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let signature = git2::Signature::new("t", "t@example.com", &git2::Time::new(0, 0)).unwrap();
+        let mut parent: Option<git2::Oid> = None;
+        for (message, source) in [
+            ("one", "fn a() {}\n"),
+            ("two\n\nbody", "fn  a() {}\nfn b() {}\n"),
+        ] {
+            let blob = filters::clean_as(
+                source.as_bytes(),
+                "a.rs",
+                languages::by_name("rust"),
+                &ParseLimits::default(),
+            )
+            .unwrap();
+            let mut builder = repo.treebuilder(None).unwrap();
+            builder
+                .insert("a.rs", repo.blob(&blob).unwrap(), 0o100644)
+                .unwrap();
+            let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+            let parents: Vec<Commit<'_>> = parent
+                .iter()
+                .map(|&id| repo.find_commit(id).unwrap())
+                .collect();
+            let parents: Vec<&Commit<'_>> = parents.iter().collect();
+            parent = Some(
+                repo.commit(
+                    Some("HEAD"),
+                    &signature,
+                    &signature,
+                    message,
+                    &tree,
+                    &parents,
+                )
+                .unwrap(),
+            );
+        }
+
+        let shown = show(&repo, "HEAD", &[], DiffFormat::Readable).unwrap();
+        let shown = String::from_utf8(shown).unwrap();
+        let expected = format!(
+            "commit {}\nAuthor: t <t@example.com>\nDate:   1970-01-01T00:00:00Z\n\n    two\n\n    \
+             body\n\n M    a.rs: added b\n\ndiff --git a/a.rs b/a.rs\n",
+            parent.unwrap()
+        );
+        assert!(shown.starts_with(&expected), "{}", shown);
+        assert!(shown.contains("+fn b() {}\n"), "{}", shown);
+        assert!(!shown.contains("-fn a"), "{}", shown);
+
+        let root = show(&repo, "HEAD~", &[], DiffFormat::Patch).unwrap();
+        let root = String::from_utf8(root).unwrap();
+        assert!(root.contains(" A    a.rs: added a\n"), "{}", root);
+        assert!(root.contains("new file mode 100644\n"), "{}", root);
+    }
+}
@@ -0,0 +1,87 @@
+//! `git-ast stage <path> [--hunk <n>]...`
+//!
+//! Partial staging by top-level item, for files `git add -p` cannot split
+//! (see [`staging`]). Without `--hunk`, lists the hunks between the index
+//! and the working tree:
+//!
+//! ```text
+//! $ git-ast stage src/lib.rs
+//! 1  changed  parse
+//! 2  added    helper, Options
+//! 3  removed  old_parse
+//! $ git-ast stage src/lib.rs --hunk 2 --hunk 3
+//! ```
+//!
+//! With `--hunk`, stages those hunks and leaves the others for later; the
+//! working tree is not touched.
+
+use crate::config;
+use crate::staging;
+use crate::Error;
+use clap::Args;
+use git2::Repository;
+use std::path::{Path, PathBuf};
+
+/// Arguments for `git-ast stage`.
+#[derive(Debug, Args)]
+pub struct StageArgs {
+    /// File to stage part of; it must already be in the index.
+    pub path: PathBuf,
+    /// Stage this hunk (1-based, as listed); may be repeated.
+    #[arg(long = "hunk", value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub hunks: Vec<u32>,
+}
+
+/// Runs `git-ast stage`.
+pub fn run(args: &StageArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| Error::Config("git-ast stage needs a working tree".to_string()))?
+        .to_path_buf();
+    let cwd = std::env::current_dir()?;
+    let relative = super::fmt::relative_to(&workdir, &cwd.join(&args.path))?;
+    let pathname = relative.to_string_lossy().replace('\\', "/");
+    let limits = config::Settings::load(Some(&repo))?.parse_limits;
+    let repo_config = config::RepoConfigs::new(Some(&repo)).for_path(&pathname)?;
+
+    let mut index = repo.index()?;
+    let entry = index.get_path(Path::new(&pathname), 0).ok_or_else(|| {
+        Error::Config(format!(
+            "{} is not in the index; stage it whole with git add",
+            pathname
+        ))
+    })?;
+    let staged = repo.find_blob(entry.id)?;
+    let source = std::fs::read(workdir.join(&relative))?;
+    let provider = config::language_for_path(Some(&repo), &pathname, &source)?
+        .ok_or_else(|| Error::Config(format!("{}: no language to split it by", pathname)))?;
+
+    if args.hunks.is_empty() {
+        let (old, new) = staging::trees(
+            staged.content(),
+            &source,
+            &pathname,
+            provider,
+            &limits,
+            &repo_config,
+        )?;
+        for (i, hunk) in staging::hunks(&old, &new).iter().enumerate() {
+            println!("{}  {:<7}  {}", i + 1, hunk.change(), hunk.names.join(", "));
+        }
+        return Ok(());
+    }
+    let selected: Vec<usize> = args.hunks.iter().map(|&n| n as usize - 1).collect();
+    let blob = staging::partial_clean(
+        staged.content(),
+        &source,
+        &pathname,
+        provider,
+        &selected,
+        &limits,
+        &repo_config,
+    )?;
+    index.add_frombuffer(&entry, &blob)?;
+    index.write()?;
+    Ok(())
+}
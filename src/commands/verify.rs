@@ -0,0 +1,115 @@
+//! `git-ast verify [--attestations] [<rev>]`
+//!
+//! Checks every AST blob in a revision's tree the way the `pre-push` hook
+//! checks outgoing ones (see [`verify_round_trip`](filters::verify_round_trip)).
+//! With `--attestations`, each blob must also carry a valid signed record of
+//! the toolchain that produced it (see [`attestation`]):
+//!
+//! Blobs at paths the working tree's `.gitattributes` do not give
+//! `filter=ast` fail too: a checkout would write them as serialized text.
+//!
+//! ```text
+//! $ git-ast verify --attestations v1.2
+//! src/lib.rs: no attestation
+//! verify: 212 AST blobs, 1 failed (211 attested by alice@example.com)
+//! ```
+
+use crate::attestation::{self, Check, Signer};
+use crate::git_plumbing::filters;
+use crate::serialization;
+use crate::Error;
+use clap::Args;
+use git2::{AttrCheckFlags, ObjectType, Repository, TreeWalkMode, TreeWalkResult};
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+
+/// Arguments for `git-ast verify`.
+#[derive(Debug, Args)]
+pub struct VerifyArgs {
+    /// Revision whose tree to verify.
+    #[arg(default_value = "HEAD")]
+    pub rev: String,
+    /// Also require a valid attestation for every AST blob.
+    #[arg(long)]
+    pub attestations: bool,
+}
+
+/// Runs `git-ast verify`.
+pub fn run(args: &VerifyArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let tree = repo.revparse_single(&args.rev)?.peel_to_tree()?;
+    let mut files = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.kind() == Some(ObjectType::Blob) {
+            files.push((
+                format!("{}{}", dir, entry.name().unwrap_or_default()),
+                entry.id(),
+            ));
+        }
+        TreeWalkResult::Ok
+    })?;
+    let signer = match args.attestations {
+        true => Some(Signer::from_config(&repo.config()?.snapshot()?)?),
+        false => None,
+    };
+
+    let mut seen = HashSet::new();
+    let mut failures = Vec::new();
+    let mut signers = BTreeSet::new();
+    let mut checked = 0;
+    for (path, id) in files {
+        if !seen.insert(id) {
+            continue;
+        }
+        let blob = repo.find_blob(id)?;
+        if !serialization::is_ast_blob(blob.content()) {
+            continue;
+        }
+        checked += 1;
+        if let Err(e) = filters::verify_round_trip(blob.content(), &path) {
+            failures.push((path, e.to_string()));
+            continue;
+        }
+        let filtered = repo.workdir().is_none()
+            || matches!(
+                repo.get_attr(Path::new(&path), "filter", AttrCheckFlags::FILE_THEN_INDEX),
+                Ok(Some("ast"))
+            );
+        if !filtered {
+            failures.push((path, "AST blob without filter=ast".to_string()));
+            continue;
+        }
+        let Some(signer) = &signer else {
+            continue;
+        };
+        let (header, _) = serialization::decode(blob.content())?;
+        match attestation::check(&repo, signer, id, &header)? {
+            Check::Valid(name) => {
+                signers.insert(name);
+            }
+            Check::Missing => failures.push((path, "no attestation".to_string())),
+            Check::Invalid(reason) => failures.push((path, format!("attestation: {}", reason))),
+        }
+    }
+
+    for (path, reason) in &failures {
+        println!("{}: {}", path, reason);
+    }
+    let mut summary = format!("verify: {} AST blobs, {} failed", checked, failures.len());
+    if signer.is_some() {
+        let names: Vec<_> = signers.into_iter().collect();
+        summary += &format!(" ({} attested", checked - failures.len());
+        if !names.is_empty() {
+            summary += &format!(" by {}", names.join(", "));
+        }
+        summary.push(')');
+    }
+    println!("{}", summary);
+    match failures.len() {
+        0 => Ok(()),
+        n => Err(Error::Verification(format!(
+            "{} AST blob(s) failed verification",
+            n
+        ))),
+    }
+}
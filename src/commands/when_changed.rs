@@ -0,0 +1,79 @@
+//! `git-ast when-changed <symbol> [<range>]`
+//!
+//! Every commit that changed the signature of a function, type or other
+//! definition named `<symbol>` (its parameters, return type, visibility, ...;
+//! see [`symbols`](crate::symbols#signatures)), newest first, with the
+//! signatures before and after:
+//!
+//! ```text
+//! $ git-ast when-changed parse
+//! 1a2b3c4 Take the limits by reference
+//!     - pub fn parse(source: &[u8], limits: ParseLimits) -> Tree  (src/parsing.rs:64)
+//!     + pub fn parse(source: &[u8], limits: &ParseLimits) -> Tree  (src/parsing.rs:64)
+//! 5d6e7f8 Add the parser
+//!     + pub fn parse(source: &[u8]) -> Tree  (src/parsing.rs:12)
+//! ```
+//!
+//! Changes to bodies, and moves between files, are not listed.
+
+use crate::config;
+use crate::model::Document;
+use crate::queries::QueryPacks;
+use crate::symbols;
+use crate::Error;
+use clap::{Args, ValueEnum};
+use git2::Repository;
+use serde_json::json;
+
+/// Arguments for `git-ast when-changed`.
+#[derive(Debug, Args)]
+pub struct WhenChangedArgs {
+    /// Name of the symbol.
+    pub symbol: String,
+    /// Revision or `A..B` range to search (default: all of `HEAD`).
+    pub range: Option<String>,
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = WhenChangedFormat::Text)]
+    pub format: WhenChangedFormat,
+}
+
+/// Output formats of `git-ast when-changed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum WhenChangedFormat {
+    /// One line per commit, then the signatures before and after.
+    Text,
+    /// A single JSON document.
+    Json,
+}
+
+/// Runs `git-ast when-changed`.
+pub fn run(args: &WhenChangedArgs) -> Result<(), Error> {
+    let repo = Repository::open_from_env()?;
+    let limits = config::Settings::load(Some(&repo))?.parse_limits;
+    let mut packs = QueryPacks::for_repo(&repo);
+    let changes = symbols::signature_changes(
+        &repo,
+        &args.symbol,
+        args.range.as_deref(),
+        &limits,
+        &mut packs,
+    )?;
+    match args.format {
+        WhenChangedFormat::Json => {
+            let report = Document::new(json!({ "symbol": args.symbol, "changes": changes }));
+            println!("{}", report.to_json()?);
+        }
+        WhenChangedFormat::Text => {
+            for change in &changes {
+                println!("{} {}", &change.commit[..7], change.summary);
+                let sides = [("-", &change.before), ("+", &change.after)];
+                for (sign, signatures) in sides {
+                    for s in signatures.iter() {
+                        println!("    {} {}  ({}:{})", sign, s.text, s.path, s.line);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
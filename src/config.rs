@@ -0,0 +1,1602 @@
+//! Configuration Handling
+//!
+//! This module is responsible for reading and interpreting configuration
+//! related to `git-ast` from Git sources like `.gitattributes` and `.gitconfig`.
+//!
+//! ## `.gitattributes`
+//!
+//! Defines which files are handled by `git-ast` filters and drivers.
+//!
+//! ```gitattributes
+//! # Example: Handle all Rust files
+//! *.rs filter=ast diff=ast merge=ast
+//!
+//! # Example: Handle Python files, but only filtering and diffing
+//! *.py filter=ast diff=ast
+//!
+//! # Example: Generated Rust with an unusual extension
+//! *.rs.in filter=ast ast-lang=rust
+//!
+//! # Example: Treat images as binary (passthrough for filters/drivers)
+//! *.png binary
+//! ```
+//!
+//! Key attributes used by `git-ast`:
+//! - `filter=ast`: Enables the clean/smudge filter.
+//! - `diff=ast`: Enables the custom diff driver.
+//! - `merge=ast`: Enables the custom merge driver.
+//! - `ast-lang=<name>`: Parses the file with the named language provider
+//!   instead of the one its extension selects; `-ast-lang` treats it as having
+//!   no language (stored verbatim). See [`language_for_path`].
+//! - `binary` or `-filter -diff -merge`: Explicitly marks files to be ignored by `git-ast`.
+//!
+//! ## `.gitconfig` (or `.git/config`)
+//!
+//! Defines *how* the filters and drivers invoke the `git-ast` tool.
+//!
+//! ```ini
+//! [filter "ast"]
+//!     # Use the long-running process protocol for efficiency
+//!     process = git-ast filter-process
+//!     # Ensure filter failures block Git operations
+//!     required = true
+//!     # Or one process per file (slower), as Git's single-shot form:
+//!     # clean = git-ast clean %f
+//!     # smudge = git-ast smudge %f
+//!
+//! [diff "ast"]
+//!     # Specify the command for Git to call for diffing
+//!     command = git-ast diff-driver
+//!     # Optional: Enable caching if git-ast diff-driver acts like textconv
+//!     # cachetextconv = true
+//!     # Optional: Tell Git the driver is producing binary output
+//!     # binary = true
+//!
+//! [merge "ast"]
+//!     # Human-readable name (optional)
+//!     name = AST-based merge driver
+//!     # Command for Git to call for merging
+//!     # %O=base, %A=ours, %B=theirs, %L=marker_size, %P=pathname
+//!     # (Git 2.44+ can append the marker labels: %S %X %Y)
+//!     driver = git-ast merge-driver %O %A %B %L %P
+//!     # Optional: Specify a driver for recursive internal merges (often `binary`)
+//!     recursive = binary
+//! ```
+//!
+//! ## `ast.*` Settings
+//!
+//! Tuning knobs read by `git-ast` itself (from any Git config level):
+//!
+//! ```ini
+//! [ast]
+//!     # Abandon parsing a single file after this many milliseconds (0 = no limit)
+//!     parseTimeoutMs = 10000
+//!     # Refuse to parse files larger than this; bounds per-file memory (0 = no limit)
+//!     parseMaxBytes = 16m
+//!     # Leave base/ours/theirs + JSON report next to conflicted files
+//!     mergeSidecars = true
+//!     # Conflict markers around changed lines (line) or whole top-level items (node)
+//!     conflictMarkers = line
+//!     # Merge with Git's text merge past this many conflict regions or milliseconds
+//!     mergeMaxConflicts = 50
+//!     mergeTimeoutMs = 10000
+//!     # Attach merge driver reports to merge commits (refs/notes/ast)
+//!     mergeNotes = true
+//!     # Keep AST versions of source branches under refs/ast/heads/ from hooks
+//!     sidecar = true
+//!     # Point source/<branch> at the source form of the current branch from hooks
+//!     exportBranch = true
+//!     # Sign a record of every stored AST blob (refs/notes/ast-attestations)
+//!     attest = true
+//!     # Append every filter and driver invocation to .git/ast-audit/log.jsonl
+//!     audit = true
+//!     # Cache rendered diffs in .git/ast-cache/diffs (default: true)
+//!     diffCache = true
+//!     # Diff strings and comments word by word (or ignore them) in the tokens backend
+//!     diffLiterals = words
+//!     # Log lines on stderr as text or JSON (see the telemetry module)
+//!     logFormat = json
+//!     # Progress lines from the filter, drivers and hooks too (default: error)
+//!     logLevel = info
+//!     # Collect metrics for `git-ast metrics` (default: false)
+//!     metrics = true
+//!     # Re-clean every smudged file and compare with its blob: off, warn or fail
+//!     verifyOnSmudge = fail
+//! ```
+//!
+//! [`SETTINGS`] lists them with their types and defaults. The effective
+//! value of each (see [`Settings`]) comes from the last of these layers that
+//! sets it: built-in default, system config, global config, the `[ast]`
+//! section of `.git-ast.toml` (project defaults), the repository's own
+//! config, and finally a `GIT_AST_*` environment variable
+//! (`GIT_AST_PARSE_TIMEOUT_MS` for `ast.parseTimeoutMs`). Values are
+//! validated in every layer. `git-ast config list --show-origin` prints the
+//! result, and `git-ast config set` validates a value before storing it.
+//!
+//! ## Hermetic Mode
+//!
+//! With `GIT_AST_NO_USER_CONFIG=1` in the environment (or `--no-user-config`
+//! on the command line, which sets it for the process and the commands it
+//! runs), the system, XDG and global Git config are ignored: settings come
+//! only from the defaults, `.git-ast.toml`, the repository's config and
+//! `GIT_AST_*` variables, and so does everything else `git-ast` reads from
+//! Git config (such as `merge.conflictStyle`). CI jobs and tests export it so
+//! the filter and drivers Git starts behave the same on every machine. It
+//! does not change what Git itself reads; combine it with
+//! `GIT_CONFIG_NOSYSTEM=1` and `GIT_CONFIG_GLOBAL=/dev/null` for that.
+//!
+//! Project settings (`[storage]`, `[normalize.*]`, ...) have no environment
+//! overrides: they decide the stored blobs, which must not depend on the
+//! machine that wrote them.
+//!
+//! ## `.git-ast.toml`
+//!
+//! Settings that belong to the project rather than to one clone live in an
+//! optional `.git-ast.toml` at the worktree root (see [`RepoConfig`]);
+//! subdirectories may add their own to override it (see [`RepoConfigs`]):
+//!
+//! ```toml
+//! [diff]
+//! # Backend for languages without their own entry: "native", "tokens" or
+//! # "difftastic"
+//! backend = "native"
+//! # Percent of its syntax tokens a changed item must keep to be listed as
+//! # modified rather than removed and added (default 50)
+//! similarity = 50
+//!
+//! [diff.languages.php]
+//! backend = "difftastic"
+//!
+//! # Python functions get rewritten a lot; keep calling them the same one
+//! [diff.languages.python]
+//! similarity = 40
+//! [diff.languages.python.similarity_by_kind]
+//! class_definition = 60
+//!
+//! # Rewrites applied when cleaning; they change the stored blobs, so every
+//! # contributor must use the same settings (hence this file, not git config)
+//! [normalize.rust]
+//! sort_imports = true
+//! trailing_commas = true
+//! comment_whitespace = true
+//!
+//! # Compress stored trees ("none" or "zstd"); also a format choice for everyone
+//! [storage]
+//! compression = "zstd"
+//! # Keep top-level items with syntax errors as written ("reject" or "verbatim")
+//! syntax_errors = "verbatim"
+//!
+//! # Store only a skeleton plus the verbatim text for these pathspecs
+//! [sparse]
+//! paths = ["vendor/", "*.pb.rs"]
+//!
+//! # Keep these files as plain text: stored as written, diffed and merged
+//! # line by line (like `-ast-lang`, but also by size and content)
+//! [passthrough]
+//! paths = ["third_party/", "*.min.js"]
+//! max_bytes = "2m"
+//! # Checked in the first 1 KiB of the file
+//! markers = ["@generated", "DO NOT EDIT"]
+//!
+//! # Teams for `git-ast owners`, by member email
+//! [owners.teams]
+//! "@acme/parser" = ["ada@example.com", "lin@example.com"]
+//!
+//! # Structural lints checked by the clean filter ("warn" or "deny")
+//! [[lint]]
+//! name = "no-unwrap"
+//! language = "rust"
+//! paths = ["src/prod/"]
+//! query = '(call_expression function: (field_expression field: (field_identifier) @m (#eq? @m "unwrap")))'
+//! level = "deny"
+//! message = "handle the error instead"
+//!
+//! # Checks for `git-ast review` (replacing a built-in one of the same name)
+//! [[review]]
+//! name = "new-sql"
+//! language = "python"
+//! query = '(call function: (attribute attribute: (identifier) @m (#eq? @m "execute")))'
+//! when = "added"
+//! priority = "high"
+//! message = "raw SQL; check for injection"
+//! ```
+//!
+//! This module would contain functions to:
+//! - Query gitattributes for a given path.
+//! - Query gitconfig for filter/driver definitions.
+
+use crate::git_plumbing::filters::{SmudgeMode, VerifyOnSmudge};
+use crate::languages::{self, LanguageProvider};
+use crate::parsing::ParseLimits;
+use crate::telemetry::{self, LogFormat};
+use crate::token_diff::Literals;
+use crate::Error;
+use git2::{AttrCheckFlags, AttrValue, Pathspec, PathspecFlags, Repository};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Git config key for the per-file parse timeout.
+pub const PARSE_TIMEOUT_KEY: &str = "ast.parseTimeoutMs";
+/// Git config key for the per-file input size ceiling.
+pub const PARSE_MAX_BYTES_KEY: &str = "ast.parseMaxBytes";
+/// Git attribute overriding the language selected by a path's extension.
+pub const LANGUAGE_ATTR: &str = "ast-lang";
+
+/// How `git-ast` handles one file.
+#[derive(Debug, Clone, Default)]
+pub struct FileConfig {
+    pub use_filter: bool,
+    pub use_diff_driver: bool,
+    pub use_merge_driver: bool,
+    /// Why the file is kept as plain text despite a supported language, if
+    /// a `[passthrough]` rule matches it.
+    pub passthrough: Option<String>,
+}
+
+/// The `filter`, `diff` and `merge` attributes of `path` and the
+/// `[passthrough]` rules of `repo_config` for it, given its `content`.
+///
+/// The filter, the diff driver and the merge driver all decide with this,
+/// so a file is treated the same way by each of them.
+pub fn get_config_for_path(
+    repo: Option<&Repository>,
+    repo_config: &RepoConfig,
+    path: &str,
+    content: &[u8],
+) -> Result<FileConfig, Error> {
+    let is_ast = |attr: &str| -> Result<bool, Error> {
+        match repo {
+            Some(repo) => Ok(repo.get_attr(
+                Path::new(path),
+                attr,
+                AttrCheckFlags::FILE_THEN_INDEX,
+            )? == Some("ast")),
+            None => Ok(false),
+        }
+    };
+    Ok(FileConfig {
+        use_filter: is_ast("filter")?,
+        use_diff_driver: is_ast("diff")?,
+        use_merge_driver: is_ast("merge")?,
+        passthrough: repo_config.passthrough_reason(path, content),
+    })
+}
+
+/// Environment variable turning on hermetic mode (see the module docs).
+pub const NO_USER_CONFIG_ENV: &str = "GIT_AST_NO_USER_CONFIG";
+
+/// Whether hermetic mode is on in this process.
+pub fn no_user_config() -> bool {
+    no_user_config_in(|name| std::env::var(name).ok())
+}
+
+fn no_user_config_in(env: impl Fn(&str) -> Option<String>) -> bool {
+    env(NO_USER_CONFIG_ENV)
+        .and_then(|v| parse_bool(&v))
+        .unwrap_or(false)
+}
+
+/// Opens the Git configuration in effect for the current directory: the
+/// repository's config layered over global and system config, or just the
+/// global/system config when not inside a repository. In hermetic mode only
+/// the repository's own config is read.
+pub fn git_config() -> Result<git2::Config, Error> {
+    let repo = git2::Repository::open_from_env();
+    if no_user_config() {
+        return match repo {
+            Ok(repo) => match repo.config()?.open_level(git2::ConfigLevel::Local) {
+                Ok(mut local) => Ok(local.snapshot()?),
+                Err(e) if e.code() == git2::ErrorCode::NotFound => Ok(git2::Config::new()?),
+                Err(e) => Err(e.into()),
+            },
+            Err(_) => Ok(git2::Config::new()?),
+        };
+    }
+    match repo {
+        Ok(repo) => Ok(repo.config()?.snapshot()?),
+        Err(_) => Ok(git2::Config::open_default()?.snapshot()?),
+    }
+}
+
+/// Value type of a setting, which decides how its values are validated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// `true`/`yes`/`on`/`1` or `false`/`no`/`off`/`0`.
+    Bool,
+    /// A non-negative integer; `k`, `m` and `g` suffixes multiply by 1024.
+    Size,
+    /// One of a fixed set of words.
+    Choice(&'static [&'static str]),
+}
+
+impl Kind {
+    /// Human description of the accepted values, for error messages.
+    pub fn expected(&self) -> String {
+        match self {
+            Kind::Bool => "a boolean (true or false)".to_string(),
+            Kind::Size => "a non-negative number (k/m/g suffixes allowed)".to_string(),
+            Kind::Choice(words) => format!("one of {}", words.join(", ")),
+        }
+    }
+}
+
+/// An `ast.*` setting: its key, type, built-in default and meaning.
+#[derive(Debug, Clone, Copy)]
+pub struct Setting {
+    pub key: &'static str,
+    pub kind: Kind,
+    pub default: &'static str,
+    pub doc: &'static str,
+}
+
+/// Every `ast.*` setting `git-ast` reads.
+pub const SETTINGS: &[Setting] = &[
+    Setting {
+        key: PARSE_TIMEOUT_KEY,
+        kind: Kind::Size,
+        default: "10000",
+        doc: "Abandon parsing a single file after this many milliseconds (0 = no limit)",
+    },
+    Setting {
+        key: PARSE_MAX_BYTES_KEY,
+        kind: Kind::Size,
+        default: "16m",
+        doc: "Refuse to parse files larger than this (0 = no limit)",
+    },
+    Setting {
+        key: crate::drivers::MERGE_SIDECARS_KEY,
+        kind: Kind::Bool,
+        default: "false",
+        doc: "Leave base/ours/theirs and a JSON report next to conflicted files",
+    },
+    Setting {
+        key: crate::drivers::CONFLICT_MARKERS_KEY,
+        kind: Kind::Choice(&["line", "node"]),
+        default: "line",
+        doc: "Conflict markers around changed lines or whole top-level items",
+    },
+    Setting {
+        key: crate::drivers::MERGE_MAX_CONFLICTS_KEY,
+        kind: Kind::Size,
+        default: "50",
+        doc: "Use Git's text merge when a structural merge leaves more conflict regions (0 = no limit)",
+    },
+    Setting {
+        key: crate::drivers::MERGE_TIMEOUT_KEY,
+        kind: Kind::Size,
+        default: "10000",
+        doc: "Use Git's text merge when a structural merge takes longer, in milliseconds (0 = no limit)",
+    },
+    Setting {
+        key: crate::drivers::MERGE_NOTES_KEY,
+        kind: Kind::Bool,
+        default: "false",
+        doc: "Attach merge driver reports to merge commits (refs/notes/ast)",
+    },
+    Setting {
+        key: crate::mirror::SIDECAR_KEY,
+        kind: Kind::Bool,
+        default: "false",
+        doc: "Keep AST versions of source branches under refs/ast/heads/ from hooks",
+    },
+    Setting {
+        key: crate::mirror::EXPORT_BRANCH_KEY,
+        kind: Kind::Bool,
+        default: "false",
+        doc: "Point source/<branch> at the source form of the current branch from hooks",
+    },
+    Setting {
+        key: crate::attestation::ATTEST_KEY,
+        kind: Kind::Bool,
+        default: "false",
+        doc: "Sign a record of every stored AST blob (refs/notes/ast-attestations)",
+    },
+    Setting {
+        key: crate::audit::AUDIT_KEY,
+        kind: Kind::Bool,
+        default: "false",
+        doc: "Append every filter and driver invocation to .git/ast-audit/log.jsonl",
+    },
+    Setting {
+        key: crate::diff_cache::DIFF_CACHE_KEY,
+        kind: Kind::Bool,
+        default: "true",
+        doc: "Cache rendered diffs in .git/ast-cache/diffs",
+    },
+    Setting {
+        key: crate::token_diff::DIFF_LITERALS_KEY,
+        kind: Kind::Choice(&["token", "words", "ignore"]),
+        default: "token",
+        doc: "Diff strings and comments as whole tokens, word by word, or not at all (tokens backend)",
+    },
+    Setting {
+        key: crate::telemetry::LOG_FORMAT_KEY,
+        kind: Kind::Choice(&["text", "json"]),
+        default: "text",
+        doc: "Write log lines on stderr as text or as JSON objects",
+    },
+    Setting {
+        key: crate::telemetry::LOG_LEVEL_KEY,
+        kind: Kind::Choice(&["error", "info"]),
+        default: "error",
+        doc: "Log progress (info) or only errors from the filter, drivers and hooks",
+    },
+    Setting {
+        key: crate::telemetry::METRICS_KEY,
+        kind: Kind::Bool,
+        default: "false",
+        doc: "Collect metrics in .git/ast-cache/metrics for `git-ast metrics`",
+    },
+    Setting {
+        key: crate::git_plumbing::filters::VERIFY_ON_SMUDGE_KEY,
+        kind: Kind::Choice(&["off", "warn", "fail"]),
+        default: "off",
+        doc: "Re-clean smudged files and warn or fail unless the blob comes back",
+    },
+    Setting {
+        key: crate::git_plumbing::filters::SMUDGE_MODE_KEY,
+        kind: Kind::Choice(&["canonical", "original", "annotated"]),
+        default: "canonical",
+        doc: "Check out printed source, the recorded original, or printed source with provenance",
+    },
+];
+
+/// The [`Setting`] named `key`, with or without the `ast.` prefix and in
+/// any case (like Git keys).
+pub fn setting(key: &str) -> Option<&'static Setting> {
+    SETTINGS.iter().find(|s| {
+        s.key.eq_ignore_ascii_case(key) || s.key["ast.".len()..].eq_ignore_ascii_case(key)
+    })
+}
+
+/// The environment variable overriding `key`: `ast.parseTimeoutMs` is
+/// `GIT_AST_PARSE_TIMEOUT_MS`.
+pub fn env_var(key: &str) -> String {
+    let mut out = String::from("GIT_");
+    for (i, c) in key.chars().enumerate() {
+        match c {
+            '.' => out.push('_'),
+            c if c.is_ascii_uppercase() && i > 0 => {
+                out.push('_');
+                out.push(c);
+            }
+            c => out.push(c.to_ascii_uppercase()),
+        }
+    }
+    out
+}
+
+/// Where a setting's value came from, from lowest to highest precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Origin {
+    /// Built into `git-ast`.
+    Default,
+    /// System-wide Git config.
+    System,
+    /// `~/.gitconfig` or `$XDG_CONFIG_HOME/git/config`.
+    Global,
+    /// The `[ast]` section of `.git-ast.toml`.
+    RepoFile,
+    /// The repository's `.git/config`.
+    Local,
+    /// A `GIT_AST_*` environment variable.
+    Env,
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Origin::Default => "default",
+            Origin::System => "system",
+            Origin::Global => "global",
+            Origin::RepoFile => REPO_CONFIG_FILE,
+            Origin::Local => "local",
+            Origin::Env => "env",
+        })
+    }
+}
+
+/// The effective `ast.*` settings, resolved through every layer (see
+/// [`Settings::load`]) and validated.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub parse_limits: ParseLimits,
+    pub merge_sidecars: bool,
+    /// `line` or `node`.
+    pub conflict_markers: String,
+    /// Conflict regions a structural merge may leave (`None`: no limit).
+    pub merge_max_conflicts: Option<usize>,
+    /// Time a structural merge may take (`None`: no limit).
+    pub merge_timeout: Option<Duration>,
+    pub merge_notes: bool,
+    pub sidecar: bool,
+    pub export_branch: bool,
+    pub attest: bool,
+    pub audit: bool,
+    pub diff_cache: bool,
+    pub diff_literals: Literals,
+    pub log_format: LogFormat,
+    pub log_level: telemetry::Level,
+    pub metrics: bool,
+    pub verify_on_smudge: VerifyOnSmudge,
+    pub smudge_mode: SmudgeMode,
+    /// The effective value of each setting as written, and its origin.
+    pub values: BTreeMap<&'static str, (String, Origin)>,
+}
+
+impl Settings {
+    /// Resolves the settings for `repo` (or, outside a repository, from the
+    /// system and global config alone). Later layers win: built-in
+    /// defaults, system config, global config, `.git-ast.toml`, the
+    /// repository's config, then `GIT_AST_*` environment variables. In
+    /// hermetic mode the system and global config are skipped.
+    ///
+    /// An invalid value is an error naming the key, the value and where it
+    /// came from, whichever layer it is in, so typos do not go unnoticed
+    /// merely because a higher layer overrides them.
+    pub fn load(repo: Option<&Repository>) -> Result<Settings, Error> {
+        let config = match repo {
+            Some(repo) => repo.config()?,
+            None => git2::Config::open_default()?,
+        };
+        let repo_file = match repo {
+            Some(repo) => repo_config(repo)?.ast,
+            None => BTreeMap::new(),
+        };
+        Settings::resolve(&config, &repo_file, |name| std::env::var(name).ok())
+    }
+
+    /// [`Settings::load`] from explicit layers.
+    pub fn resolve(
+        config: &git2::Config,
+        repo_file: &BTreeMap<String, toml::Value>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Settings, Error> {
+        let mut values: BTreeMap<&'static str, (String, Origin)> = SETTINGS
+            .iter()
+            .map(|s| (s.key, (s.default.to_string(), Origin::Default)))
+            .collect();
+        let mut set = |key: &'static str, value: String, origin: Origin| -> Result<(), Error> {
+            validate(key, &value, origin)?;
+            values.insert(key, (value, origin));
+            Ok(())
+        };
+        use git2::ConfigLevel as Level;
+        let levels = [
+            (Level::System, Origin::System),
+            (Level::XDG, Origin::Global),
+            (Level::Global, Origin::Global),
+        ];
+        if !no_user_config_in(&env) {
+            for (level, origin) in levels {
+                read_level(config, level, origin, &mut set)?;
+            }
+        }
+        for (key, value) in repo_file {
+            let setting = setting(key)
+                .filter(|s| s.key["ast.".len()..] == **key)
+                .ok_or_else(|| {
+                    Error::Config(format!(
+                        "{}: unknown setting ast.{} (`git-ast config list` shows them all)",
+                        REPO_CONFIG_FILE, key
+                    ))
+                })?;
+            let value = match value {
+                toml::Value::String(s) => s.clone(),
+                toml::Value::Integer(n) => n.to_string(),
+                toml::Value::Boolean(b) => b.to_string(),
+                other => other.to_string(),
+            };
+            set(setting.key, value, Origin::RepoFile)?;
+        }
+        for level in [Level::Local, Level::App] {
+            read_level(config, level, Origin::Local, &mut set)?;
+        }
+        for setting in SETTINGS {
+            if let Some(value) = env(&env_var(setting.key)) {
+                set(setting.key, value, Origin::Env)?;
+            }
+        }
+
+        let get = |key: &str| values[key].0.as_str();
+        let flag = |key: &str| parse_bool(get(key)).unwrap_or_default();
+        let size = |key: &str| parse_size(get(key)).unwrap_or_default();
+        let timeout_ms = size(PARSE_TIMEOUT_KEY);
+        let max_bytes = size(PARSE_MAX_BYTES_KEY) as usize;
+        let max_conflicts = size(crate::drivers::MERGE_MAX_CONFLICTS_KEY) as usize;
+        let merge_timeout_ms = size(crate::drivers::MERGE_TIMEOUT_KEY);
+        Ok(Settings {
+            parse_limits: ParseLimits {
+                timeout: (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms)),
+                max_bytes: (max_bytes > 0).then_some(max_bytes),
+                cancel: None,
+            },
+            merge_sidecars: flag(crate::drivers::MERGE_SIDECARS_KEY),
+            conflict_markers: get(crate::drivers::CONFLICT_MARKERS_KEY).to_string(),
+            merge_max_conflicts: (max_conflicts > 0).then_some(max_conflicts),
+            merge_timeout: (merge_timeout_ms > 0).then(|| Duration::from_millis(merge_timeout_ms)),
+            merge_notes: flag(crate::drivers::MERGE_NOTES_KEY),
+            sidecar: flag(crate::mirror::SIDECAR_KEY),
+            export_branch: flag(crate::mirror::EXPORT_BRANCH_KEY),
+            attest: flag(crate::attestation::ATTEST_KEY),
+            audit: flag(crate::audit::AUDIT_KEY),
+            diff_cache: flag(crate::diff_cache::DIFF_CACHE_KEY),
+            diff_literals: match get(crate::token_diff::DIFF_LITERALS_KEY) {
+                "words" => Literals::Words,
+                "ignore" => Literals::Ignore,
+                _ => Literals::Token,
+            },
+            log_format: match get(crate::telemetry::LOG_FORMAT_KEY) {
+                "json" => LogFormat::Json,
+                _ => LogFormat::Text,
+            },
+            log_level: match get(crate::telemetry::LOG_LEVEL_KEY) {
+                "info" => telemetry::Level::Info,
+                _ => telemetry::Level::Error,
+            },
+            metrics: flag(crate::telemetry::METRICS_KEY),
+            verify_on_smudge: match get(crate::git_plumbing::filters::VERIFY_ON_SMUDGE_KEY) {
+                "warn" => VerifyOnSmudge::Warn,
+                "fail" => VerifyOnSmudge::Fail,
+                _ => VerifyOnSmudge::Off,
+            },
+            smudge_mode: match get(crate::git_plumbing::filters::SMUDGE_MODE_KEY) {
+                "original" => SmudgeMode::Original,
+                "annotated" => SmudgeMode::Annotated,
+                _ => SmudgeMode::Canonical,
+            },
+            values,
+        })
+    }
+}
+
+/// Feeds the settings present at one Git config level to `set`.
+fn read_level(
+    config: &git2::Config,
+    level: git2::ConfigLevel,
+    origin: Origin,
+    set: &mut impl FnMut(&'static str, String, Origin) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let level = match config.open_level(level) {
+        Ok(level) => level,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for setting in SETTINGS {
+        match level.get_string(setting.key) {
+            Ok(value) => set(setting.key, value, origin)?,
+            Err(e) if e.code() == git2::ErrorCode::NotFound => {}
+            Err(e) => {
+                return Err(Error::Config(format!(
+                    "invalid {}: {}",
+                    setting.key,
+                    e.message()
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks `value` against the type of setting `key`.
+pub fn validate(key: &str, value: &str, origin: Origin) -> Result<(), Error> {
+    let setting = setting(key).ok_or_else(|| Error::Config(format!("unknown setting {}", key)))?;
+    let valid = match setting.kind {
+        Kind::Bool => parse_bool(value).is_some(),
+        Kind::Size => parse_size(value).is_some(),
+        Kind::Choice(words) => words.contains(&value),
+    };
+    match valid {
+        true => Ok(()),
+        false => Err(Error::Config(format!(
+            "{} = {:?} (from {}): expected {}",
+            setting.key,
+            value,
+            origin,
+            setting.kind.expected()
+        ))),
+    }
+}
+
+/// Git's boolean spellings.
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" | "" => Some(false),
+        _ => None,
+    }
+}
+
+/// A non-negative integer with an optional `k`/`m`/`g` suffix.
+fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (digits, factor) = match value.char_indices().last()? {
+        (i, 'k' | 'K') => (&value[..i], 1 << 10),
+        (i, 'm' | 'M') => (&value[..i], 1 << 20),
+        (i, 'g' | 'G') => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(factor)
+}
+
+/// Selects the language provider for a repository path.
+///
+/// The `ast-lang` attribute wins over the file extension: `ast-lang=<name>`
+/// forces a provider (an unknown name is an error, so typos do not silently
+/// store source verbatim) and `-ast-lang` disables language handling. Without
+/// a repository, or with the attribute unspecified, the extension decides,
+/// falling back to sniffing `content` for extensionless files
+/// (see [`languages::detect`]).
+pub fn language_for_path(
+    repo: Option<&Repository>,
+    path: &str,
+    content: &[u8],
+) -> Result<Option<&'static LanguageProvider>, Error> {
+    let attr = match repo {
+        Some(repo) => repo.get_attr_bytes(
+            path.as_ref(),
+            LANGUAGE_ATTR,
+            AttrCheckFlags::FILE_THEN_INDEX,
+        )?,
+        None => None,
+    };
+    let name = match AttrValue::from_bytes(attr) {
+        AttrValue::String(name) => name.to_string(),
+        AttrValue::Bytes(name) => String::from_utf8_lossy(name).into_owned(),
+        AttrValue::False => return Ok(None),
+        AttrValue::True | AttrValue::Unspecified => return Ok(languages::detect(path, content)),
+    };
+    languages::by_name(&name).map(Some).ok_or_else(|| {
+        Error::Config(format!(
+            "{}: unknown language {:?} in {} attribute",
+            path, name, LANGUAGE_ATTR
+        ))
+    })
+}
+
+/// Name of the per-repository configuration file, at the worktree root.
+pub const REPO_CONFIG_FILE: &str = ".git-ast.toml";
+
+/// Contents of `.git-ast.toml`. Every section is optional.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RepoConfig {
+    pub diff: DiffConfig,
+    /// Per-language `[normalize.<name>]` sections, keyed by provider name.
+    pub normalize: BTreeMap<String, NormalizeConfig>,
+    pub storage: StorageConfig,
+    pub sparse: SparseConfig,
+    pub passthrough: PassthroughConfig,
+    pub owners: OwnersConfig,
+    /// `[[lint]]` rules (see [`lint`](crate::lint)).
+    pub lint: Vec<LintRule>,
+    /// `[[review]]` checks (see [`review`](crate::review)).
+    pub review: Vec<ReviewRule>,
+    /// Project defaults for the `ast.*` settings, keyed without the prefix
+    /// (see [`Settings`]).
+    pub ast: BTreeMap<String, toml::Value>,
+}
+
+/// The `[storage]` section: how the clean filter encodes blobs.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub compression: Compression,
+    pub syntax_errors: SyntaxErrors,
+}
+
+/// Blob payload compression (see [`compression`](crate::compression)).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// Plain text trees.
+    #[default]
+    None,
+    /// zstd with the grammar's dictionary.
+    Zstd,
+}
+
+/// What the clean filter does with a file that has syntax errors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyntaxErrors {
+    /// Fail the file.
+    #[default]
+    Reject,
+    /// Store the items containing them as text (see
+    /// [Verbatim Regions](crate::ast#verbatim-regions)).
+    Verbatim,
+}
+
+/// The `[sparse]` section: paths stored as [`sparse`](crate::sparse) blobs.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SparseConfig {
+    /// Git pathspecs (`vendor/`, `*.pb.rs`); `*` also matches `/`.
+    pub paths: Vec<String>,
+}
+
+/// The `[passthrough]` section: files kept as plain text although their
+/// language is supported.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PassthroughConfig {
+    /// Git pathspecs; `*` also matches `/`.
+    pub paths: Vec<String>,
+    /// Files larger than this many bytes (`k`/`m`/`g` suffixes allowed).
+    #[serde(deserialize_with = "size_value")]
+    pub max_bytes: Option<u64>,
+    /// Text marking a file as generated, looked for in its first
+    /// [`MARKER_WINDOW`] bytes.
+    pub markers: Vec<String>,
+}
+
+/// How much of a file `[passthrough] markers` are looked for in.
+pub const MARKER_WINDOW: usize = 1024;
+
+/// A size as a TOML integer or a string with a `k`/`m`/`g` suffix.
+fn size_value<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    match toml::Value::deserialize(deserializer)? {
+        toml::Value::Integer(n) if n >= 0 => Ok(Some(n as u64)),
+        toml::Value::String(s) => parse_size(&s)
+            .map(Some)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid size {:?}", s))),
+        other => Err(serde::de::Error::custom(format!("invalid size {}", other))),
+    }
+}
+
+/// The `[owners]` section: how `git-ast owners` groups authors.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OwnersConfig {
+    /// Member emails by team handle (`@org/team`, as in `CODEOWNERS`).
+    pub teams: BTreeMap<String, Vec<String>>,
+}
+
+/// A `[[lint]]` rule: a Tree-sitter query whose matches the clean filter
+/// reports.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LintRule {
+    pub name: String,
+    /// Provider name the query is written for.
+    pub language: String,
+    /// Git pathspecs it applies to (every file of the language when empty).
+    #[serde(default)]
+    pub paths: Vec<String>,
+    pub query: String,
+    #[serde(default)]
+    pub level: LintLevel,
+    /// Shown with each match, after the rule's name.
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// What a [`LintRule`] match does to `git add`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LintLevel {
+    /// Log it and store the file anyway.
+    #[default]
+    Warn,
+    /// Fail the clean filter.
+    Deny,
+}
+
+/// A `[[review]]` check: a Tree-sitter query whose matches `git-ast review`
+/// lists when a change adds (or removes) them.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReviewRule {
+    /// A built-in check of the same name and language is replaced; an empty
+    /// `query` turns it off.
+    pub name: String,
+    /// Provider name the query is written for.
+    pub language: String,
+    /// Git pathspecs it applies to (every file of the language when empty).
+    #[serde(default)]
+    pub paths: Vec<String>,
+    pub query: String,
+    #[serde(default)]
+    pub when: ReviewWhen,
+    #[serde(default)]
+    pub priority: ReviewPriority,
+    /// Shown with each item, after the check's name.
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// Which matches of a [`ReviewRule`] are listed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReviewWhen {
+    /// Matches the change adds.
+    #[default]
+    Added,
+    /// Matches the change removes.
+    Removed,
+}
+
+/// Where a [`ReviewRule`]'s items go in the checklist.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReviewPriority {
+    High,
+    #[default]
+    Medium,
+    Low,
+}
+
+impl RepoConfig {
+    /// The normalizations enabled for `language` (none if unconfigured).
+    pub fn normalize_for(&self, language: &str) -> NormalizeConfig {
+        self.normalize.get(language).copied().unwrap_or_default()
+    }
+
+    /// Why `path` with `content` is kept as plain text, if a `[passthrough]`
+    /// rule says so.
+    pub fn passthrough_reason(&self, path: &str, content: &[u8]) -> Option<String> {
+        let rules = &self.passthrough;
+        if !rules.paths.is_empty()
+            && Pathspec::new(&rules.paths)
+                .is_ok_and(|spec| spec.matches_path(Path::new(path), PathspecFlags::DEFAULT))
+        {
+            return Some("matches [passthrough] paths".to_string());
+        }
+        if let Some(max) = rules.max_bytes.filter(|&max| content.len() as u64 > max) {
+            return Some(format!(
+                "larger than [passthrough] max_bytes ({} > {})",
+                content.len(),
+                max
+            ));
+        }
+        let head = &content[..content.len().min(MARKER_WINDOW)];
+        rules
+            .markers
+            .iter()
+            .find(|marker| {
+                !marker.is_empty() && head.windows(marker.len()).any(|w| w == marker.as_bytes())
+            })
+            .map(|marker| format!("contains the [passthrough] marker {:?}", marker))
+    }
+
+    /// Whether `path` (relative to the worktree root) is stored sparse.
+    pub fn is_sparse(&self, path: &str) -> bool {
+        !self.sparse.paths.is_empty()
+            && Pathspec::new(&self.sparse.paths)
+                .is_ok_and(|spec| spec.matches_path(Path::new(path), PathspecFlags::DEFAULT))
+    }
+}
+
+/// A `[normalize.<name>]` section: optional rewrites applied while cleaning,
+/// so equivalent code stores as identical blobs (see
+/// [`normalize`](crate::normalize)). All are off by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NormalizeConfig {
+    /// Sort import statements within a paragraph, and the items of import
+    /// lists (`use a::{c, b}`).
+    pub sort_imports: bool,
+    /// Drop the trailing comma of lists with two or more items.
+    pub trailing_commas: bool,
+    /// Strip trailing whitespace from comment lines.
+    pub comment_whitespace: bool,
+}
+
+/// The `[diff]` section: which backend renders diffs for which language,
+/// and when a changed item is still the same item.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiffConfig {
+    /// Backend for languages without an entry in `languages`.
+    pub backend: DiffBackendKind,
+    /// Similarity (percent) for languages and kinds without their own; see
+    /// [`matching`](crate::matching#modified-items).
+    pub similarity: Option<u32>,
+    /// Per-language overrides, keyed by provider name.
+    pub languages: BTreeMap<String, LanguageDiffConfig>,
+    /// A `[diff.difftastic]` table is accepted but ignored: the program to
+    /// run comes from Git config only (see [`DifftasticConfig`]).
+    pub difftastic: Option<toml::Table>,
+}
+
+/// A `[diff.languages.<name>]` entry; unset keys fall back to `[diff]`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LanguageDiffConfig {
+    pub backend: Option<DiffBackendKind>,
+    pub similarity: Option<u32>,
+    /// Similarity per node kind (`function_definition`) of the language.
+    pub similarity_by_kind: BTreeMap<String, u32>,
+}
+
+/// Available diff backends (see [`diff_backend`](crate::diff_backend)).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffBackendKind {
+    /// Line diff of the canonical pretty-printed source.
+    #[default]
+    Native,
+    /// Line diff with the changed tokens of changed lines marked.
+    Tokens,
+    /// The external `difft` tool.
+    Difftastic,
+}
+
+/// Git config key naming the difftastic executable.
+pub const DIFFTASTIC_COMMAND_KEY: &str = "ast.difftastic.command";
+/// Git config key holding one difftastic argument per value.
+pub const DIFFTASTIC_ARGS_KEY: &str = "ast.difftastic.args";
+
+/// How to run difftastic: `ast.difftastic.command` (default `difft`) and
+/// every `ast.difftastic.args` value, from Git config only. `.git-ast.toml`
+/// may choose the `difftastic` backend but not the program, so that a
+/// cloned repository cannot make `git diff` run programs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DifftasticConfig {
+    /// Executable to run.
+    pub command: String,
+    /// Extra arguments, placed before the two file names.
+    pub args: Vec<String>,
+}
+
+impl DifftasticConfig {
+    /// Reads the command and arguments from `config`.
+    pub fn load(config: &git2::Config) -> Result<Self, Error> {
+        let mut difftastic = DifftasticConfig::default();
+        match config.get_string(DIFFTASTIC_COMMAND_KEY) {
+            Ok(command) => difftastic.command = command,
+            Err(e) if e.code() == git2::ErrorCode::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        let mut args = config.multivar(DIFFTASTIC_ARGS_KEY, None)?;
+        while let Some(arg) = args.next() {
+            if let Some(arg) = arg?.value() {
+                difftastic.args.push(arg.to_string());
+            }
+        }
+        Ok(difftastic)
+    }
+}
+
+impl Default for DifftasticConfig {
+    fn default() -> Self {
+        DifftasticConfig {
+            command: "difft".to_string(),
+            args: Vec::new(),
+        }
+    }
+}
+
+impl DiffConfig {
+    /// The backend configured for `language` (a provider name, if known).
+    pub fn backend_for(&self, language: Option<&str>) -> DiffBackendKind {
+        language
+            .and_then(|name| self.languages.get(name))
+            .and_then(|l| l.backend)
+            .unwrap_or(self.backend)
+    }
+
+    /// The similarity a changed top-level item of `kind` in `language` needs
+    /// to be listed as modified: the most specific setting, else
+    /// [`DEFAULT_SIMILARITY`](crate::matching::DEFAULT_SIMILARITY).
+    pub fn similarity_for(&self, language: Option<&str>, kind: &str) -> u32 {
+        let entry = language.and_then(|name| self.languages.get(name));
+        entry
+            .and_then(|l| l.similarity_by_kind.get(kind).copied())
+            .or(entry.and_then(|l| l.similarity))
+            .or(self.similarity)
+            .unwrap_or(crate::matching::DEFAULT_SIMILARITY)
+    }
+}
+
+/// Reads the root `.git-ast.toml` from `repo`'s worktree. A missing file (or
+/// no worktree) yields the defaults; a malformed one is an error. For the
+/// configuration in effect at a path, see [`RepoConfigs`].
+pub fn repo_config(repo: &Repository) -> Result<RepoConfig, Error> {
+    Ok(RepoConfigs::new(Some(repo)).for_path("")?.as_ref().clone())
+}
+
+/// The configuration in effect for `path` (see [`RepoConfigs`]).
+pub fn repo_config_for(repo: &Repository, path: &str) -> Result<RepoConfig, Error> {
+    Ok(RepoConfigs::new(Some(repo))
+        .for_path(path)?
+        .as_ref()
+        .clone())
+}
+
+/// The `.git-ast.toml` files of a worktree, resolved per path.
+///
+/// A monorepo can place further `.git-ast.toml` files in subdirectories. The
+/// configuration for a path merges every file from the root down to the
+/// path's directory, deeper files winning key by key: `[normalize.rust]` in
+/// `services/api/.git-ast.toml` replaces the root's `sort_imports` but keeps
+/// its `trailing_commas`. Three things differ:
+///
+/// -   `sparse.paths` accumulate, each relative to the directory of the file
+///     listing it.
+/// -   `[[lint]]` and `[[review]]` rules accumulate too; a deeper file's
+///     rules only apply below its directory, their `paths` relative to it.
+/// -   `[ast]` is only allowed in the root file, as those settings apply to
+///     whole processes rather than to paths (see [`Settings`]).
+///
+/// Files are read lazily and cached, once per directory.
+#[derive(Debug, Default)]
+pub struct RepoConfigs {
+    workdir: Option<std::path::PathBuf>,
+    /// Each directory's own file, if it has one.
+    files: RefCell<HashMap<String, Option<Rc<toml::Table>>>>,
+    /// The merged configuration per directory.
+    merged: RefCell<HashMap<String, Rc<RepoConfig>>>,
+}
+
+impl RepoConfigs {
+    /// The files of `repo`'s worktree (none without a repository or worktree).
+    pub fn new(repo: Option<&Repository>) -> Self {
+        RepoConfigs {
+            workdir: repo.and_then(|repo| repo.workdir()).map(Path::to_path_buf),
+            ..RepoConfigs::default()
+        }
+    }
+
+    /// The configuration in effect for `path`, relative to the worktree root.
+    pub fn for_path(&self, path: &str) -> Result<Rc<RepoConfig>, Error> {
+        let dir = parent_dir(path);
+        if let Some(config) = self.merged.borrow().get(dir) {
+            return Ok(config.clone());
+        }
+        let mut table = toml::Table::new();
+        for (dir, file) in self.chain(dir)? {
+            merge_table(&mut table, &file, &dir, "");
+        }
+        let config: RepoConfig = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| Error::Config(format!("{}: {}", REPO_CONFIG_FILE, e)))?;
+        let config = Rc::new(config);
+        self.merged
+            .borrow_mut()
+            .insert(dir.to_string(), config.clone());
+        Ok(config)
+    }
+
+    /// Every project setting in effect for `path` as `(key, value, file)`,
+    /// `file` being the one that decided it.
+    pub fn entries(&self, path: &str) -> Result<Vec<(String, String, String)>, Error> {
+        self.for_path(path)?;
+        let mut out: BTreeMap<String, (String, String)> = BTreeMap::new();
+        for (dir, file) in self.chain(parent_dir(path))? {
+            let origin = config_file_path(&dir);
+            let mut table = toml::Table::new();
+            merge_table(&mut table, &file, &dir, "");
+            for (key, value) in flatten("", &toml::Value::Table(table)) {
+                if key.starts_with("ast.") {
+                    continue;
+                }
+                match out.get_mut(&key) {
+                    Some(entry) if key == "sparse.paths" || key == "lint" || key == "review" => {
+                        entry.0 = format!("{},{}", entry.0, value);
+                        entry.1 = format!("{},{}", entry.1, origin);
+                    }
+                    _ => {
+                        out.insert(key, (value, origin.clone()));
+                    }
+                }
+            }
+        }
+        Ok(out
+            .into_iter()
+            .map(|(key, (value, origin))| (key, value, origin))
+            .collect())
+    }
+
+    /// The files from the root down to `dir`, with their directories.
+    fn chain(&self, dir: &str) -> Result<Vec<(String, Rc<toml::Table>)>, Error> {
+        let mut dirs = vec![String::new()];
+        let mut prefix = String::new();
+        for part in dir.split('/').filter(|p| !p.is_empty()) {
+            if !prefix.is_empty() {
+                prefix.push('/');
+            }
+            prefix.push_str(part);
+            dirs.push(prefix.clone());
+        }
+        let mut out = Vec::new();
+        for dir in dirs {
+            if let Some(file) = self.file(&dir)? {
+                out.push((dir, file));
+            }
+        }
+        Ok(out)
+    }
+
+    /// The parsed and checked file of `dir`, if there is one.
+    fn file(&self, dir: &str) -> Result<Option<Rc<toml::Table>>, Error> {
+        if let Some(file) = self.files.borrow().get(dir) {
+            return Ok(file.clone());
+        }
+        let Some(workdir) = &self.workdir else {
+            return Ok(None);
+        };
+        let name = config_file_path(dir);
+        let file = match std::fs::read_to_string(workdir.join(&name)) {
+            Ok(text) => {
+                let error = |e: &dyn std::fmt::Display| Error::Config(format!("{}: {}", name, e));
+                // Check the file on its own, so errors name it.
+                toml::from_str::<RepoConfig>(&text).map_err(|e| error(&e))?;
+                let table: toml::Table = toml::from_str(&text).map_err(|e| error(&e))?;
+                if !dir.is_empty() && table.contains_key("ast") {
+                    return Err(error(&format!(
+                        "[ast] settings apply to the whole repository; move them to the root {}",
+                        REPO_CONFIG_FILE
+                    )));
+                }
+                Some(Rc::new(table))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        self.files
+            .borrow_mut()
+            .insert(dir.to_string(), file.clone());
+        Ok(file)
+    }
+}
+
+/// The directory part of a worktree path (`""` for the root).
+fn parent_dir(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(dir, _)| dir)
+}
+
+/// Worktree path of the `.git-ast.toml` in `dir`.
+fn config_file_path(dir: &str) -> String {
+    match dir {
+        "" => REPO_CONFIG_FILE.to_string(),
+        dir => format!("{}/{}", dir, REPO_CONFIG_FILE),
+    }
+}
+
+/// Merges the file of `dir` into `into`, at the table below `key`.
+fn merge_table(into: &mut toml::Table, file: &toml::Table, dir: &str, key: &str) {
+    for (name, value) in file {
+        let path = if key.is_empty() {
+            name.clone()
+        } else {
+            format!("{}.{}", key, name)
+        };
+        match (into.get_mut(name), value) {
+            (Some(toml::Value::Table(into)), toml::Value::Table(value)) => {
+                merge_table(into, value, dir, &path)
+            }
+            (existing, toml::Value::Array(items)) if path == "sparse.paths" => {
+                let rebased = items.iter().map(|item| match (item, dir) {
+                    (toml::Value::String(spec), dir) if !dir.is_empty() => {
+                        toml::Value::String(format!("{}/{}", dir, spec))
+                    }
+                    (item, _) => item.clone(),
+                });
+                match existing {
+                    Some(toml::Value::Array(paths)) => paths.extend(rebased),
+                    _ => {
+                        into.insert(name.clone(), toml::Value::Array(rebased.collect()));
+                    }
+                }
+            }
+            (existing, toml::Value::Array(rules)) if path == "lint" || path == "review" => {
+                let rebased = rules.iter().map(|rule| match (rule, dir) {
+                    (toml::Value::Table(rule), dir) if !dir.is_empty() => {
+                        let mut rule = rule.clone();
+                        let paths = match rule.get("paths").and_then(toml::Value::as_array) {
+                            Some(paths) if !paths.is_empty() => paths
+                                .iter()
+                                .map(|p| {
+                                    toml::Value::String(format!(
+                                        "{}/{}",
+                                        dir,
+                                        p.as_str().unwrap_or_default()
+                                    ))
+                                })
+                                .collect(),
+                            _ => vec![toml::Value::String(format!("{}/", dir))],
+                        };
+                        rule.insert("paths".to_string(), toml::Value::Array(paths));
+                        toml::Value::Table(rule)
+                    }
+                    (rule, _) => rule.clone(),
+                });
+                match existing {
+                    Some(toml::Value::Array(existing)) => existing.extend(rebased),
+                    _ => {
+                        into.insert(name.clone(), toml::Value::Array(rebased.collect()));
+                    }
+                }
+            }
+            (_, toml::Value::Table(value)) => {
+                let mut table = toml::Table::new();
+                merge_table(&mut table, value, dir, &path);
+                into.insert(name.clone(), toml::Value::Table(table));
+            }
+            (_, value) => {
+                into.insert(name.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// `(dotted key, value)` of every leaf of `value`; arrays are joined by
+/// commas.
+fn flatten(prefix: &str, value: &toml::Value) -> Vec<(String, String)> {
+    match value {
+        toml::Value::Table(table) => table
+            .iter()
+            .flat_map(|(key, value)| {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(&key, value)
+            })
+            .collect(),
+        toml::Value::String(s) => vec![(prefix.to_string(), s.clone())],
+        toml::Value::Array(items) => {
+            let items: Vec<String> = items
+                .iter()
+                .map(|item| {
+                    item.as_str()
+                        .map_or_else(|| item.to_string(), str::to_string)
+                })
+                .collect();
+            vec![(prefix.to_string(), items.join(","))]
+        }
+        other => vec![(prefix.to_string(), other.to_string())],
+    }
+}
+
+// Potentially add functions here to read specific [filter "ast"], [diff "ast"],
+// or [merge "ast"] sections from git config if needed directly by the tool,
+// although Git usually handles invoking the correct command based on the config.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ast_lang_attribute_overrides_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        std::fs::write(
+            dir.path().join(".gitattributes"),
+            "*.in ast-lang=python\nvendor/*.rs -ast-lang\n*.bad ast-lang=cobol\n",
+        )
+        .unwrap();
+
+        let name = |path| {
+            language_for_path(Some(&repo), path, b"")
+                .unwrap()
+                .map(|p| p.name)
+        };
+        assert_eq!(name("setup.py.in"), Some("python"));
+        assert_eq!(name("src/lib.rs"), Some("rust"));
+        assert_eq!(name("vendor/lib.rs"), None);
+        assert!(language_for_path(Some(&repo), "x.bad", b"").is_err());
+    }
+
+    #[test]
+    fn repo_config_selects_diff_backend_per_language() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        assert_eq!(
+            repo_config(&repo).unwrap().diff.backend_for(Some("php")),
+            DiffBackendKind::Native
+        );
+
+        std::fs::write(
+            dir.path().join(REPO_CONFIG_FILE),
+            "[diff.languages.php]\nbackend = \"difftastic\"\n",
+        )
+        .unwrap();
+        let diff = repo_config(&repo).unwrap().diff;
+        assert_eq!(diff.backend_for(Some("php")), DiffBackendKind::Difftastic);
+        assert_eq!(diff.backend_for(Some("rust")), DiffBackendKind::Native);
+        assert_eq!(diff.similarity_for(Some("php"), "function_definition"), 50);
+
+        std::fs::write(
+            dir.path().join(REPO_CONFIG_FILE),
+            "[diff]\nsimilarity = 70\n[diff.languages.python]\nsimilarity = 40\n\
+             [diff.languages.python.similarity_by_kind]\nclass_definition = 60\n",
+        )
+        .unwrap();
+        let diff = repo_config(&repo).unwrap().diff;
+        assert_eq!(diff.similarity_for(Some("python"), "class_definition"), 60);
+        assert_eq!(
+            diff.similarity_for(Some("python"), "function_definition"),
+            40
+        );
+        assert_eq!(diff.similarity_for(Some("rust"), "function_item"), 70);
+        assert_eq!(diff.backend_for(Some("python")), DiffBackendKind::Native);
+
+        std::fs::write(
+            dir.path().join(REPO_CONFIG_FILE),
+            "[diff]\nbackend = \"meld\"\n",
+        )
+        .unwrap();
+        assert!(repo_config(&repo).is_err());
+
+        std::fs::write(
+            dir.path().join(REPO_CONFIG_FILE),
+            "[sparse]\npaths = [\"vendor/\", \"*.pb.rs\"]\n",
+        )
+        .unwrap();
+        let config = repo_config(&repo).unwrap();
+        assert!(config.is_sparse("vendor/a/b.rs"));
+        assert!(config.is_sparse("src/gen/api.pb.rs"));
+        assert!(!config.is_sparse("src/vendor.rs"));
+    }
+
+    #[test]
+    fn passthrough_rules_match_paths_sizes_and_markers() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        std::fs::write(
+            dir.path().join(".gitattributes"),
+            "*.rs filter=ast diff=ast\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join(REPO_CONFIG_FILE),
+            "[passthrough]\npaths = [\"third_party/\"]\nmax_bytes = \"2k\"\nmarkers = [\"@generated\"]\n",
+        )
+        .unwrap();
+        let config = repo_config(&repo).unwrap();
+        let small = b"fn f() {}\n";
+        let file = get_config_for_path(Some(&repo), &config, "src/a.rs", small).unwrap();
+        assert!(file.use_filter && file.use_diff_driver && !file.use_merge_driver);
+        assert_eq!(file.passthrough, None);
+        assert!(config
+            .passthrough_reason("third_party/x/a.rs", small)
+            .is_some());
+        assert!(config
+            .passthrough_reason("src/a.rs", &[b' '; 2049])
+            .unwrap()
+            .contains("max_bytes"));
+        let generated = b"// @generated by protoc\nfn f() {}\n";
+        assert!(config
+            .passthrough_reason("src/a.rs", generated)
+            .unwrap()
+            .contains("marker"));
+        let late = [vec![b'\n'; MARKER_WINDOW], b"// @generated".to_vec()].concat();
+        assert_eq!(config.passthrough_reason("src/b.rs", &late), None);
+
+        let rust = languages::by_name("rust");
+        let stored = crate::git_plumbing::filters::prepare_clean(
+            generated,
+            "src/a.rs",
+            rust,
+            &Default::default(),
+            &config,
+        )
+        .unwrap()
+        .to_vec()
+        .unwrap();
+        assert_eq!(stored, generated);
+
+        std::fs::write(
+            dir.path().join(REPO_CONFIG_FILE),
+            "[passthrough]\nmax_bytes = \"lots\"\n",
+        )
+        .unwrap();
+        assert!(repo_config(&repo).is_err());
+    }
+
+    #[test]
+    fn settings_layer_and_validate() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        // Hermetic, so the machine's own config cannot interfere.
+        let no_env = |name: &str| (name == NO_USER_CONFIG_ENV).then(|| "1".to_string());
+        let empty = BTreeMap::new();
+        let settings = Settings::resolve(&repo.config().unwrap(), &empty, no_env).unwrap();
+        let defaults = ParseLimits::default();
+        assert_eq!(settings.parse_limits.timeout, defaults.timeout);
+        assert_eq!(settings.parse_limits.max_bytes, defaults.max_bytes);
+
+        let mut local = repo
+            .config()
+            .unwrap()
+            .open_level(git2::ConfigLevel::Local)
+            .unwrap();
+        local.set_str("ast.conflictMarkers", "node").unwrap();
+        local.set_str("ast.parseMaxBytes", "1k").unwrap();
+        let project: BTreeMap<String, toml::Value> =
+            toml::from_str("parseMaxBytes = \"2m\"\nmergeNotes = true").unwrap();
+        let env = |name: &str| match name {
+            "GIT_AST_CONFLICT_MARKERS" => Some("line".to_string()),
+            _ => no_env(name),
+        };
+        let settings = Settings::resolve(&repo.config().unwrap(), &project, env).unwrap();
+        assert_eq!(settings.parse_limits.max_bytes, Some(1024));
+        assert!(settings.merge_notes);
+        assert_eq!(settings.values["ast.mergeNotes"].1, Origin::RepoFile);
+        assert_eq!(
+            settings.values["ast.conflictMarkers"],
+            ("line".to_string(), Origin::Env)
+        );
+
+        local.set_str("ast.conflictMarkers", "nodes").unwrap();
+        let err = Settings::resolve(&repo.config().unwrap(), &empty, no_env).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("from local): expected one of line, node"),
+            "{}",
+            err
+        );
+        let typo: BTreeMap<String, toml::Value> = toml::from_str("parseTimeout = 1").unwrap();
+        assert!(Settings::resolve(&repo.config().unwrap(), &typo, no_env).is_err());
+
+        // User config counts unless hermetic.
+        let global = dir.path().join("global.gitconfig");
+        std::fs::write(&global, "[ast]\n\tsidecar = true\n").unwrap();
+        let mut config = git2::Config::new().unwrap();
+        config
+            .add_file(&global, git2::ConfigLevel::Global, false)
+            .unwrap();
+        assert!(
+            Settings::resolve(&config, &empty, |_| None)
+                .unwrap()
+                .sidecar
+        );
+        assert!(!Settings::resolve(&config, &empty, no_env).unwrap().sidecar);
+    }
+
+    #[test]
+    fn nested_repo_configs_override_by_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let write = |path: &str, text: &str| {
+            let file = dir.path().join(path);
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(file, text).unwrap();
+        };
+        write(
+            REPO_CONFIG_FILE,
+            "[normalize.rust]\nsort_imports = true\ntrailing_commas = true\n[sparse]\npaths = [\"vendor/\"]\n",
+        );
+        write(
+            "svc/api/.git-ast.toml",
+            "[normalize.rust]\nsort_imports = false\n[sparse]\npaths = [\"gen/\"]\n\
+             [[lint]]\nname = \"x\"\nlanguage = \"rust\"\nquery = \"(x)\"\n",
+        );
+
+        let configs = RepoConfigs::new(Some(&repo));
+        let api = configs.for_path("svc/api/src/main.rs").unwrap();
+        assert!(!api.normalize_for("rust").sort_imports);
+        assert!(api.normalize_for("rust").trailing_commas);
+        assert!(api.is_sparse("svc/api/gen/x.rs") && api.is_sparse("vendor/x.rs"));
+        assert!(!api.is_sparse("gen/x.rs"));
+        assert_eq!(api.lint[0].paths, ["svc/api/"]);
+        assert!(
+            configs
+                .for_path("svc/web/main.rs")
+                .unwrap()
+                .normalize_for("rust")
+                .sort_imports
+        );
+
+        let entries = configs.entries("svc/api/main.rs").unwrap();
+        assert!(entries.contains(&(
+            "normalize.rust.sort_imports".to_string(),
+            "false".to_string(),
+            "svc/api/.git-ast.toml".to_string()
+        )));
+
+        write("svc/.git-ast.toml", "[ast]\nsidecar = true\n");
+        let err = RepoConfigs::new(Some(&repo))
+            .for_path("svc/a.rs")
+            .unwrap_err();
+        assert!(err.to_string().contains("svc/.git-ast.toml"), "{}", err);
+    }
+}
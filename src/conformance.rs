@@ -0,0 +1,365 @@
+//! Printer Conformance
+//!
+//! The whole system rests on one property of the printer: for any file a
+//! language accepts, printing its stored tree gives source that cleans back
+//! to the same tree, however the original was laid out. [`run`] checks this
+//! over a corpus (the golden corpus under `testdata/`, or any source tree),
+//! for each file:
+//!
+//! 1.  The canonical print of its blob cleans back to the blob.
+//! 2.  The canonical print, re-laid out by each of a number of randomized
+//!     [`Profile`]s (another indentation unit, extra spaces between tokens
+//!     and at line ends), cleans back to the blob too. Only whitespace
+//!     between tokens changes, never inside them, and never whether two
+//!     tokens touch or a line is blank, so every variant is the same program.
+//!
+//! A file that breaks either is cut down line by line to a small input that
+//! still breaks the same property the same way (delta debugging), which is
+//! reported as a reproducer. Profiles derive from a seed, so a failure is
+//! reproduced with the same `--seed`. Files that do not clean in the first
+//! place (syntax errors, unsupported languages) are skipped.
+
+use crate::ast;
+use crate::commands::selftest;
+use crate::git_plumbing::filters;
+use crate::languages::{self, LanguageProvider};
+use crate::parsing::{self, ParseLimits};
+use crate::Error;
+use std::path::PathBuf;
+
+/// Indentation units a [`Profile`] may use.
+const INDENTS: &[&str] = &["  ", "   ", "    ", "\t"];
+
+/// A randomized layout for [`reformat`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Profile {
+    pub seed: u64,
+    /// Replaces the printer's indentation unit.
+    pub indent: &'static str,
+    /// Chance, in percent, of widening a space between two tokens.
+    pub widen: u64,
+    /// Chance, in percent, of adding spaces at the end of a line.
+    pub trailing: u64,
+}
+
+impl Profile {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Rng(seed);
+        Profile {
+            seed,
+            indent: INDENTS[rng.below(INDENTS.len() as u64) as usize],
+            widen: rng.below(60),
+            trailing: rng.below(60),
+        }
+    }
+}
+
+impl std::fmt::Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "seed {} (indent {:?}, widen {}%, trailing {}%)",
+            self.seed, self.indent, self.widen, self.trailing
+        )
+    }
+}
+
+/// The invariants a file can break.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Property {
+    /// The canonical print does not parse.
+    PrintedParses,
+    /// The canonical print cleans to another blob.
+    PrintedRoundTrips,
+    /// A reformatted print does not parse.
+    ReformattedParses,
+    /// A reformatted print cleans to another blob.
+    ReformattedRoundTrips,
+}
+
+impl std::fmt::Display for Property {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Property::PrintedParses => "printed source does not parse",
+            Property::PrintedRoundTrips => "printed source cleans to a different blob",
+            Property::ReformattedParses => "reformatted source does not parse",
+            Property::ReformattedRoundTrips => "reformatted source cleans to a different blob",
+        })
+    }
+}
+
+/// A broken invariant, as found by [`check`].
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub property: Property,
+    /// The profile that broke it, for the reformatted properties.
+    pub profile: Option<Profile>,
+    pub detail: String,
+}
+
+/// A corpus file that breaks an invariant.
+#[derive(Debug, Clone)]
+pub struct Violation {
+    /// Relative to the corpus root.
+    pub path: PathBuf,
+    pub failure: Failure,
+    /// A smaller input breaking it the same way.
+    pub reproducer: String,
+}
+
+/// Outcome of a conformance run.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Files checked.
+    pub inputs: usize,
+    /// Files that did not clean, and why.
+    pub skipped: Vec<(PathBuf, String)>,
+    /// Languages with file extensions but no checked file.
+    pub uncovered: Vec<&'static str>,
+    pub violations: Vec<Violation>,
+}
+
+/// Checks every file under `roots` with `profiles` profiles seeded from
+/// `seed` on.
+pub fn run(
+    roots: &[PathBuf],
+    profiles: usize,
+    seed: u64,
+    limits: &ParseLimits,
+) -> Result<Report, Error> {
+    let profiles: Vec<Profile> = (0..profiles as u64)
+        .map(|i| Profile::new(seed.wrapping_add(i)))
+        .collect();
+    let mut report = Report::default();
+    let mut covered = Vec::new();
+    for root in roots {
+        for file in selftest::inputs(root)? {
+            let rel = file.strip_prefix(root).unwrap_or(&file).to_path_buf();
+            let pathname = rel.to_string_lossy().replace('\\', "/");
+            let Some(provider) = languages::for_path(&pathname) else {
+                continue;
+            };
+            let source = std::fs::read(&file)?;
+            let failure = match check(&source, &pathname, provider, &profiles, limits) {
+                Ok(failure) => failure,
+                Err(e) => {
+                    report.skipped.push((rel, e.to_string()));
+                    continue;
+                }
+            };
+            report.inputs += 1;
+            covered.push(provider.name);
+            if let Some(failure) = failure {
+                let reproducer = minimize(&source, &pathname, provider, &failure, limits);
+                report.violations.push(Violation {
+                    path: rel,
+                    failure,
+                    reproducer,
+                });
+            }
+        }
+    }
+    report.uncovered = languages::all()
+        .iter()
+        .filter(|p| !p.extensions.is_empty() && !covered.contains(&p.name))
+        .map(|p| p.name)
+        .collect();
+    Ok(report)
+}
+
+/// The first invariant `source` breaks, if any; an error if it does not
+/// clean at all.
+pub fn check(
+    source: &[u8],
+    pathname: &str,
+    provider: &'static LanguageProvider,
+    profiles: &[Profile],
+    limits: &ParseLimits,
+) -> Result<Option<Failure>, Error> {
+    let blob = filters::clean_as(source, pathname, Some(provider), limits)?;
+    let printed = filters::perform_smudge(&blob, pathname)?;
+    let round_trip = |text: &[u8], profile: Option<Profile>| {
+        let (parses, round_trips) = match profile {
+            None => (Property::PrintedParses, Property::PrintedRoundTrips),
+            Some(_) => (Property::ReformattedParses, Property::ReformattedRoundTrips),
+        };
+        match filters::clean_as(text, pathname, Some(provider), limits) {
+            Err(e) => Some(Failure {
+                property: parses,
+                profile,
+                detail: e.to_string(),
+            }),
+            Ok(again) if again != blob => Some(Failure {
+                property: round_trips,
+                profile,
+                detail: selftest::describe_difference(&blob, &again),
+            }),
+            Ok(_) => None,
+        }
+    };
+    if let Some(failure) = round_trip(&printed, None) {
+        return Ok(Some(failure));
+    }
+    for profile in profiles {
+        let variant = reformat(&printed, provider, profile, limits)?;
+        if let Some(failure) = round_trip(&variant, Some(*profile)) {
+            return Ok(Some(failure));
+        }
+    }
+    Ok(None)
+}
+
+/// `source` laid out by `profile`: whitespace between tokens changes as
+/// described in the module docs.
+pub fn reformat(
+    source: &[u8],
+    provider: &LanguageProvider,
+    profile: &Profile,
+    limits: &ParseLimits,
+) -> Result<Vec<u8>, Error> {
+    let tree = parsing::parse(source, provider, limits)?;
+    let (_, spans) = ast::from_tree_with_spans(&tree, source, provider)?;
+    let unit = provider.syntax.indent.as_bytes();
+    let comment = provider.syntax.line_comment.as_bytes();
+    let mut rng = Rng(profile.seed ^ 0x5eed);
+    let mut out = Vec::with_capacity(source.len() * 2);
+    let mut at = 0;
+    let mut prev = 0..0;
+    let end = source.len()..source.len();
+    for span in spans.iter().chain(std::iter::once(&end)) {
+        if span.start < at {
+            continue;
+        }
+        let gap = &source[at..span.start];
+        if !gap.iter().all(|b| matches!(b, b' ' | b'\t' | b'\n')) {
+            // Line continuations and the like.
+            out.extend_from_slice(gap);
+        } else {
+            let lines: Vec<&[u8]> = gap.split(|&b| b == b'\n').collect();
+            let starts_line = at == 0 || source[at - 1] == b'\n';
+            // A line comment runs to the end of the line, spaces included.
+            let after_comment = !comment.is_empty() && source[prev.clone()].starts_with(comment);
+            for (i, line) in lines.iter().enumerate() {
+                if i + 1 < lines.len() {
+                    out.extend_from_slice(line);
+                    if rng.chance(profile.trailing) && !(i == 0 && after_comment) {
+                        out.extend(std::iter::repeat_n(b' ', 1 + rng.below(3) as usize));
+                    }
+                    out.push(b'\n');
+                } else if i > 0 || starts_line {
+                    // Indentation.
+                    let depth = line.len() / unit.len().max(1);
+                    match !unit.is_empty() && *line == unit.repeat(depth).as_slice() {
+                        true => out.extend(profile.indent.repeat(depth).bytes()),
+                        false => out.extend_from_slice(line),
+                    }
+                } else {
+                    out.extend_from_slice(line);
+                    if !line.is_empty() && rng.chance(profile.widen) {
+                        out.extend(std::iter::repeat_n(b' ', 1 + rng.below(3) as usize));
+                    }
+                }
+            }
+        }
+        out.extend_from_slice(&source[span.clone()]);
+        at = span.end;
+        prev = span.clone();
+    }
+    Ok(out)
+}
+
+/// The smallest selection of `source`'s lines found that still breaks
+/// `failure`'s property, with its profile.
+pub fn minimize(
+    source: &[u8],
+    pathname: &str,
+    provider: &'static LanguageProvider,
+    failure: &Failure,
+    limits: &ParseLimits,
+) -> String {
+    let text = String::from_utf8_lossy(source);
+    let profiles: Vec<Profile> = failure.profile.into_iter().collect();
+    let fails = |lines: &[&str]| {
+        let candidate = lines.iter().map(|l| format!("{}\n", l)).collect::<String>();
+        matches!(
+            check(candidate.as_bytes(), pathname, provider, &profiles, limits),
+            Ok(Some(f)) if f.property == failure.property
+        )
+    };
+    let mut lines: Vec<&str> = text.lines().collect();
+    let mut chunk = lines.len() / 2;
+    while chunk > 0 {
+        let mut removed = false;
+        let mut i = 0;
+        while i < lines.len() {
+            let end = (i + chunk).min(lines.len());
+            let candidate: Vec<&str> = lines[..i].iter().chain(&lines[end..]).copied().collect();
+            if !candidate.is_empty() && fails(&candidate) {
+                lines = candidate;
+                removed = true;
+            } else {
+                i += chunk;
+            }
+        }
+        if !removed {
+            chunk /= 2;
+        }
+    }
+    lines.iter().map(|l| format!("{}\n", l)).collect()
+}
+
+/// SplitMix64: small, seedable, and the same on every platform.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.below(100) < percent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reformats_only_between_tokens() {
+        let python = languages::by_name("python").unwrap();
+        let source = b"def f(a, b):\n    if a:  # c\n        return \"x  y\"\n    return b\n";
+        let limits = ParseLimits::default();
+        let profile = Profile {
+            seed: 1,
+            indent: "\t",
+            widen: 100,
+            trailing: 100,
+        };
+        let variant =
+            String::from_utf8(reformat(source, python, &profile, &limits).unwrap()).unwrap();
+        assert!(
+            variant.contains("\n\t\treturn ") && variant.contains("\"x  y\""),
+            "{:?}",
+            variant
+        );
+        assert!(variant.contains("(a,  "), "{:?}", variant);
+        assert!(variant.contains("# c\n"), "{:?}", variant);
+        assert!(
+            variant.lines().filter(|l| l.ends_with(' ')).count() == 3,
+            "{:?}",
+            variant
+        );
+        assert!(check(source, "a.py", python, &[profile], &limits)
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,411 @@
+//! Diff Backends
+//!
+//! The diff driver hands both canonical sides of a file to a [`DiffBackend`]
+//! and prints whatever it renders. Backends are selected per language in
+//! `.git-ast.toml` (see [`config::DiffConfig`]):
+//!
+//! -   **`native`** ([`NativeBackend`], default): unified line diff of the
+//!     pretty-printed source. Formatting noise is already gone because both
+//!     sides are canonical.
+//! -   **`tokens`** ([`TokenBackend`]): like `native`, but changed lines of
+//!     a modified item show which tokens changed, `git diff --word-diff`
+//!     style, with strings and comments diffed as `ast.diffLiterals` says
+//!     (see [`token_diff`](crate::token_diff)).
+//! -   **`difftastic`** ([`Difftastic`]): runs the external `difft` tool on
+//!     the two versions, for languages where a tree-aware rendering reads
+//!     better than lines. The tool must be installed separately; the program
+//!     and its arguments come from Git config only (see
+//!     [`config::DifftasticConfig`]), never from `.git-ast.toml`.
+//!
+//! ## Patch Format
+//!
+//! With `git-ast diff-driver --format patch`, the driver ignores the
+//! configured backend and renders [`patch`]: a native diff with full Git
+//! headers (`diff --git`, creation/deletion modes, `/dev/null` sides) that
+//! `git apply` and `patch -p1` accept. Line numbers refer to the canonical
+//! source, so it applies to a checkout in canonical form (see `git-ast fmt`)
+//! or to a source-only mirror. For one command, override the driver with `-c`:
+//!
+//! ```sh
+//! git -c diff.ast.command='git-ast diff-driver --format patch' diff --ext-diff HEAD~1 > undo.patch
+//! patch -p1 -R < undo.patch
+//! ```
+//!
+//! Inside a `filter=ast` checkout prefer `patch`: `git apply` runs the clean
+//! filter on the files it patches and so compares against AST blobs.
+//!
+//! Every backend reports a fingerprint of its settings, which becomes part
+//! of the [`diff_cache`](crate::diff_cache) key.
+
+use crate::config::{self, DiffBackendKind};
+use crate::languages::LanguageProvider;
+use crate::parsing::ParseLimits;
+use crate::token_diff;
+use crate::util::atomic_io;
+use crate::{log_error, Error};
+use similar::TextDiff;
+use std::path::Path;
+use std::process::Command;
+
+/// Lines of context around each hunk of a native diff.
+pub const CONTEXT_LINES: usize = 3;
+
+/// Renders the difference between two versions of a file.
+pub trait DiffBackend {
+    /// Identifies the backend and every setting that affects its output.
+    fn fingerprint(&self) -> String;
+
+    /// Renders `old` → `new` for repository path `path`.
+    fn diff(&self, path: &str, old: &[u8], new: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// Creates the backend configured as `kind` for files of `language`
+/// (`tokens` needs one, and falls back to `native` without).
+pub fn backend(
+    kind: DiffBackendKind,
+    config: &config::DiffConfig,
+    language: Option<&'static LanguageProvider>,
+    settings: &config::Settings,
+    difftastic: &config::DifftasticConfig,
+) -> Box<dyn DiffBackend> {
+    let (limits, literals) = (&settings.parse_limits, settings.diff_literals);
+    match (kind, language) {
+        (DiffBackendKind::Native, _) | (DiffBackendKind::Tokens, None) => Box::new(NativeBackend),
+        (DiffBackendKind::Tokens, Some(provider)) => Box::new(TokenBackend {
+            provider,
+            limits: limits.clone(),
+            options: token_diff::Options {
+                similarity: config.similarity_for(Some(provider.name), ""),
+                literals,
+            },
+        }),
+        (DiffBackendKind::Difftastic, _) => Box::new(Difftastic {
+            command: difftastic.command.clone(),
+            args: difftastic.args.clone(),
+        }),
+    }
+}
+
+/// The backend `.git-ast.toml` configures for `path` in `repo`, whose
+/// canonical content is `content` (for language detection). Files kept as
+/// text by `[passthrough]` rules get the native line diff.
+pub fn for_path(
+    repo: &git2::Repository,
+    path: &str,
+    content: &[u8],
+) -> Result<Box<dyn DiffBackend>, Error> {
+    let repo_config = config::repo_config_for(repo, path)?;
+    if config::get_config_for_path(Some(repo), &repo_config, path, content)?
+        .passthrough
+        .is_some()
+    {
+        return Ok(Box::new(NativeBackend));
+    }
+    let diff_config = &repo_config.diff;
+    let language = config::language_for_path(Some(repo), path, content)?;
+    let kind = diff_config.backend_for(language.map(|p| p.name));
+    let settings = config::Settings::load(Some(repo))?;
+    if diff_config.difftastic.is_some() {
+        log_error!(
+            "driver",
+            "warning: ignoring [diff.difftastic] in {}; set {} in Git config instead",
+            config::REPO_CONFIG_FILE,
+            config::DIFFTASTIC_COMMAND_KEY
+        );
+    }
+    let difftastic = match kind {
+        DiffBackendKind::Difftastic => config::DifftasticConfig::load(&repo.config()?.snapshot()?)?,
+        _ => config::DifftasticConfig::default(),
+    };
+    Ok(backend(kind, diff_config, language, &settings, &difftastic))
+}
+
+/// Unified diff of the canonical source.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NativeBackend;
+
+impl DiffBackend for NativeBackend {
+    fn fingerprint(&self) -> String {
+        format!("native context={}", CONTEXT_LINES)
+    }
+
+    fn diff(&self, path: &str, old: &[u8], new: &[u8]) -> Result<Vec<u8>, Error> {
+        let (a, b) = (format!("a/{}", path), format!("b/{}", path));
+        Ok(unified(&a, &b, old, new).into_bytes())
+    }
+}
+
+/// Native diff with changed tokens marked inside changed lines.
+#[derive(Debug, Clone)]
+pub struct TokenBackend {
+    pub provider: &'static LanguageProvider,
+    pub limits: ParseLimits,
+    pub options: token_diff::Options,
+}
+
+impl DiffBackend for TokenBackend {
+    fn fingerprint(&self) -> String {
+        format!(
+            "tokens context={} similarity={} literals={} language={}",
+            CONTEXT_LINES,
+            self.options.similarity,
+            self.options.literals.name(),
+            self.provider.name
+        )
+    }
+
+    fn diff(&self, path: &str, old: &[u8], new: &[u8]) -> Result<Vec<u8>, Error> {
+        let (a, b) = (format!("a/{}", path), format!("b/{}", path));
+        let rendered =
+            token_diff::render(&a, &b, old, new, self.provider, &self.limits, self.options);
+        Ok(rendered
+            .unwrap_or_else(|| unified(&a, &b, old, new))
+            .into_bytes())
+    }
+}
+
+/// How the diff driver presents its output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DiffFormat {
+    /// Whatever the configured backend renders.
+    Readable,
+    /// A native diff with Git headers, applicable with `git apply`.
+    Patch,
+}
+
+/// One side of a change as Git describes it to the driver: `None` for a
+/// file that does not exist on that side, otherwise its mode (`100644`).
+pub type Side<'a> = Option<&'a str>;
+
+/// A rename or copy Git detected for the file being diffed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rename<'a> {
+    /// The path on the old side.
+    pub from: &'a str,
+    /// Whether the old path still exists (a copy rather than a rename).
+    pub copy: bool,
+    /// How much of the old content the new one keeps, in percent.
+    pub similarity: u32,
+}
+
+impl Rename<'_> {
+    /// Git's extended header lines for it (`similarity index 92%`,
+    /// `rename from a.rs`, `rename to b.rs`).
+    pub fn lines(&self, to: &str) -> String {
+        let verb = if self.copy { "copy" } else { "rename" };
+        format!(
+            "similarity index {}%\n{verb} from {}\n{verb} to {}\n",
+            self.similarity, self.from, to
+        )
+    }
+}
+
+/// Renders an applicable Git patch for `path`, which came from
+/// `rename.from` if given. Empty when neither content nor mode changed and
+/// the path stayed the same.
+pub fn patch(
+    path: &str,
+    rename: Option<&Rename<'_>>,
+    old_mode: Side<'_>,
+    new_mode: Side<'_>,
+    old: &[u8],
+    new: &[u8],
+) -> Vec<u8> {
+    let from = rename.map_or(path, |r| r.from);
+    let mut header = format!("diff --git a/{} b/{}\n", from, path);
+    if let Some(rename) = rename {
+        header.push_str(&rename.lines(path));
+    }
+    header.push_str(&mode_lines(old_mode, new_mode));
+    let label = |side: Side<'_>, prefix, path| match side {
+        Some(_) => format!("{}/{}", prefix, path),
+        None => "/dev/null".to_string(),
+    };
+    let hunks = if old == new {
+        String::new()
+    } else {
+        unified(
+            &label(old_mode, "a", from),
+            &label(new_mode, "b", path),
+            old,
+            new,
+        )
+    };
+    let header_only = header.lines().count() > 1;
+    if hunks.is_empty() && !header_only {
+        return Vec::new();
+    }
+    header.push_str(&hunks);
+    header.into_bytes()
+}
+
+/// Git's extended header lines for a creation, deletion or mode change
+/// (`new file mode 100644`, ...); empty for a plain modification.
+pub fn mode_lines(old_mode: Side<'_>, new_mode: Side<'_>) -> String {
+    match (old_mode, new_mode) {
+        (None, Some(mode)) => format!("new file mode {}\n", mode),
+        (Some(mode), None) => format!("deleted file mode {}\n", mode),
+        (Some(old), Some(new)) if old != new => format!("old mode {}\nnew mode {}\n", old, new),
+        _ => String::new(),
+    }
+}
+
+/// Unified line diff with `---`/`+++` labels.
+fn unified(old_label: &str, new_label: &str, old: &[u8], new: &[u8]) -> String {
+    let (old, new) = (String::from_utf8_lossy(old), String::from_utf8_lossy(new));
+    TextDiff::from_lines(old.as_ref(), new.as_ref())
+        .unified_diff()
+        .context_radius(CONTEXT_LINES)
+        .header(old_label, new_label)
+        .to_string()
+}
+
+/// Delegates to difftastic (`difft [args] <old> <new>`).
+#[derive(Debug, Clone)]
+pub struct Difftastic {
+    pub command: String,
+    pub args: Vec<String>,
+}
+
+impl DiffBackend for Difftastic {
+    fn fingerprint(&self) -> String {
+        format!("difftastic {} {:?}", self.command, self.args)
+    }
+
+    fn diff(&self, path: &str, old: &[u8], new: &[u8]) -> Result<Vec<u8>, Error> {
+        // difft picks its parser from the file name, so keep the real one.
+        let name = Path::new(path)
+            .file_name()
+            .map_or_else(|| "file".into(), |n| n.to_os_string());
+        // Removed with the files in it however this returns.
+        let dir = atomic_io::PrivateDir::create("git-ast-difft")?;
+        let old_file = dir.write(Path::new("a").join(&name), old)?;
+        let new_file = dir.write(Path::new("b").join(&name), new)?;
+        let output = Command::new(&self.command)
+            .args(&self.args)
+            .arg(&old_file)
+            .arg(&new_file)
+            .output();
+        let output =
+            output.map_err(|e| Error::Driver(format!("cannot run {}: {}", self.command, e)))?;
+        if !output.status.success() {
+            return Err(Error::Driver(format!(
+                "{} failed ({}): {}",
+                self.command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(output.stdout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patch_has_git_headers() {
+        let added = String::from_utf8(patch(
+            "src/a.rs",
+            None,
+            None,
+            Some("100644"),
+            b"",
+            b"fn f() {}\n",
+        ))
+        .unwrap();
+        assert_eq!(
+            added,
+            "diff --git a/src/a.rs b/src/a.rs\nnew file mode 100644\n--- /dev/null\n+++ b/src/a.rs\n@@ -0,0 +1 @@\n+fn f() {}\n"
+        );
+        let same = patch(
+            "src/a.rs",
+            None,
+            Some("100644"),
+            Some("100644"),
+            b"x\n",
+            b"x\n",
+        );
+        assert!(same.is_empty());
+        let chmod = String::from_utf8(patch(
+            "run.sh",
+            None,
+            Some("100644"),
+            Some("100755"),
+            b"x\n",
+            b"x\n",
+        ))
+        .unwrap();
+        assert_eq!(
+            chmod,
+            "diff --git a/run.sh b/run.sh\nold mode 100644\nnew mode 100755\n"
+        );
+        let rename = Rename {
+            from: "old.rs",
+            copy: false,
+            similarity: 100,
+        };
+        let moved = String::from_utf8(patch(
+            "new.rs",
+            Some(&rename),
+            Some("100644"),
+            Some("100644"),
+            b"x\n",
+            b"x\n",
+        ))
+        .unwrap();
+        assert_eq!(
+            moved,
+            "diff --git a/old.rs b/new.rs\nsimilarity index 100%\nrename from old.rs\nrename to new.rs\n"
+        );
+    }
+
+    #[test]
+    fn difftastic_command_comes_from_git_config_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init(dir.path()).unwrap();
+        std::fs::write(
+            dir.path().join(config::REPO_CONFIG_FILE),
+            "[diff]\nbackend = \"difftastic\"\n\
+             [diff.difftastic]\ncommand = \"sh\"\nargs = [\"-c\", \"touch pwned\"]\n",
+        )
+        .unwrap();
+        let source = b"fn a() {}\n";
+        let fingerprint = || for_path(&repo, "a.rs", source).unwrap().fingerprint();
+        assert_eq!(fingerprint(), "difftastic difft []");
+
+        let mut git_config = repo.config().unwrap();
+        git_config
+            .set_str(config::DIFFTASTIC_COMMAND_KEY, "/opt/difft")
+            .unwrap();
+        for arg in ["--color", "never"] {
+            git_config
+                .set_multivar(config::DIFFTASTIC_ARGS_KEY, "^$", arg)
+                .unwrap();
+        }
+        assert_eq!(
+            fingerprint(),
+            "difftastic /opt/difft [\"--color\", \"never\"]"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn difftastic_gets_both_sides_under_their_file_name() {
+        let difftastic = Difftastic {
+            command: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                "echo \"$1\"; cat \"$1\" \"$2\"".to_string(),
+                "sh".to_string(),
+            ],
+        };
+        let output = difftastic.diff("src/lib.rs", b"old\n", b"new\n").unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let (old_file, contents) = output.split_once('\n').unwrap();
+        assert!(old_file.ends_with("/a/lib.rs"), "{}", old_file);
+        assert_eq!(contents, "old\nnew\n");
+        assert!(!Path::new(old_file).exists());
+    }
+}
@@ -0,0 +1,96 @@
+//! Diff Cache
+//!
+//! `git log -p` over a long history asks the diff driver for the same blob
+//! pairs again and again, and every request means smudging, parsing and
+//! rendering both sides. The rendered output only depends on the two blobs
+//! and on how it was rendered, so it is cached:
+//!
+//! ```text
+//! .git/ast-cache/diffs/<2 hex>/<38 hex>
+//! ```
+//!
+//! The file name is a hash of the old blob id, the new blob id and an
+//! *options fingerprint*: everything else that shapes the output (the
+//! `git-ast` version, language and grammar version, rendering options).
+//! Changing any of those selects different entries, so stale diffs are
+//! never served; they are simply no longer looked up. Deleting the directory
+//! at any time is safe.
+//!
+//! Blobs Git has not hashed (working-tree files, shown with an all-zero id)
+//! are never cached. Set `ast.diffCache = false` to disable the cache.
+
+use crate::util::atomic_io;
+use crate::Error;
+use git2::{ObjectType, Oid, Repository};
+use std::fs;
+use std::path::PathBuf;
+
+/// Git config key enabling the diff cache (default: enabled).
+pub const DIFF_CACHE_KEY: &str = "ast.diffCache";
+
+/// On-disk cache of rendered diffs, below the repository's Git directory.
+#[derive(Debug, Clone)]
+pub struct DiffCache {
+    dir: PathBuf,
+}
+
+impl DiffCache {
+    /// The cache of `repo` (`.git/ast-cache/diffs`).
+    pub fn open(repo: &Repository) -> Self {
+        DiffCache {
+            dir: repo.path().join("ast-cache").join("diffs"),
+        }
+    }
+
+    /// Cache key for rendering `old` → `new` with the given options
+    /// fingerprint, or `None` if either side is not a hashed blob.
+    pub fn key(old: &str, new: &str, options: &str) -> Option<Oid> {
+        let (old, new) = (Oid::from_str(old).ok()?, Oid::from_str(new).ok()?);
+        if old.is_zero() || new.is_zero() {
+            return None;
+        }
+        let material = format!("{}\n{}\n{}", old, new, options);
+        Oid::hash_object(ObjectType::Blob, material.as_bytes()).ok()
+    }
+
+    /// The cached diff for `key`, if any.
+    pub fn get(&self, key: Oid) -> Option<Vec<u8>> {
+        fs::read(self.path(key)).ok()
+    }
+
+    /// Stores `diff` under `key`. The entry appears atomically, so concurrent
+    /// readers never see a partial diff.
+    pub fn put(&self, key: Oid, diff: &[u8]) -> Result<(), Error> {
+        atomic_io::write(self.path(key), diff)
+    }
+
+    fn path(&self, key: Oid) -> PathBuf {
+        let hex = key.to_string();
+        self.dir.join(&hex[..2]).join(&hex[2..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_depend_on_blobs_and_options() {
+        let a = "1111111111111111111111111111111111111111";
+        let b = "2222222222222222222222222222222222222222";
+        let zero = "0000000000000000000000000000000000000000";
+        let key = DiffCache::key(a, b, "v1").unwrap();
+        assert_eq!(DiffCache::key(a, b, "v1"), Some(key));
+        assert_ne!(DiffCache::key(b, a, "v1"), Some(key));
+        assert_ne!(DiffCache::key(a, b, "v2"), Some(key));
+        assert_eq!(DiffCache::key(a, zero, "v1"), None);
+        assert_eq!(DiffCache::key(".", b, "v1"), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let cache = DiffCache::open(&repo);
+        assert_eq!(cache.get(key), None);
+        cache.put(key, b"--- a/x\n+++ b/x\n").unwrap();
+        assert_eq!(cache.get(key).as_deref(), Some(&b"--- a/x\n+++ b/x\n"[..]));
+    }
+}
@@ -0,0 +1,1031 @@
+//! Custom Diff and Merge Driver Implementation
+//!
+//! This module provides the logic for acting as a custom diff and merge driver
+//! for Git, enabling AST/CST-based comparisons and merges.
+//!
+//! ## Custom Diff Driver (`git-ast diff-driver`)
+//!
+//! Invoked by Git when `diff=ast` is set in `.gitattributes` and a corresponding
+//! `[diff "ast"] command = git-ast diff-driver` is configured.
+//!
+//! **Purpose:** Generate a textual diff representation based on structural (AST/CST)
+//! comparison, ignoring pure formatting changes.
+//!
+//! **Git Invocation:** Git typically calls the command with 7 arguments:
+//! `path old-file old-hex old-mode new-file new-hex new-mode`
+//! See `GIT_EXTERNAL_DIFF` in `git(1)` man page. The edge cases:
+//!
+//! -   An unmerged path gets the path alone; the driver prints
+//!     `* Unmerged path <path>` as `git diff` does.
+//! -   A created or deleted file has `/dev/null` and mode `.` on the missing
+//!     side; it is diffed against nothing.
+//! -   A mode change (including a typechange such as file to symlink) is
+//!     shown with Git's `old mode`/`new mode` lines above the diff, or alone
+//!     if the content is the same.
+//! -   A rename or copy adds the new path and Git's summary of it (9
+//!     arguments). Each side is read as its own path, and the diff is
+//!     headed by `rename from`/`rename to` (or `copy`) lines and a
+//!     `similarity index` counted in syntax tokens (see
+//!     [Renames](crate::changes#renames)).
+//! -   A side that is plain source rather than an AST blob (the working
+//!     tree, a blob stored before the file was adopted) is put through a
+//!     clean and smudge first, so it is compared in canonical form too.
+//! -   Symlink targets and submodule commits are compared as they are, with
+//!     the native line diff; they are never cleaned, so never smudged.
+//!
+//! **Implementation Steps:**
+//! 1.  Receive arguments from Git.
+//! 2.  Obtain the content of the old and new versions: the blobs named by
+//!     the hex IDs when the repository has them, otherwise the provided
+//!     files (see [Driver Inputs](#driver-inputs)).
+//! 3.  Parse both versions into AST/CSTs.
+//! 4.  Perform a tree diff operation (e.g., using `gumtree-rs` or a similar algorithm)
+//!     to identify structural changes (adds, deletes, moves, updates).
+//! 5.  Format the structural changes into a human-readable textual diff format.
+//!     This might resemble unified diff or be a custom format highlighting AST changes.
+//! 6.  Write the formatted diff to stdout.
+//!
+//! **Impact on `git log`:** When this driver is configured, `git log -p` will
+//! automatically use it to generate patch text for commits involving files with `diff=ast`.
+//!
+//! ## Custom Merge Driver (`git-ast merge-driver`)
+//!
+//! Invoked by Git during a merge (or cherry-pick, revert) when `merge=ast` is set
+//! in `.gitattributes` and `[merge "ast"] driver = git-ast merge-driver ...` is configured.
+//!
+//! **Purpose:** Perform a 3-way merge based on AST/CST structure, aiming to handle
+//! refactorings and concurrent structural changes more intelligently than text-based merges.
+//!
+//! **Git Invocation:** Git calls the driver command with placeholders replaced:
+//! `git-ast merge-driver %O %A %B %L %P [%S %X %Y]`
+//!   - `%O`: Path to a temporary file with the base version content.
+//!   - `%A`: Path to a temporary file with the current branch version (read/write).
+//!   - `%B`: Path to a temporary file with the other branch version.
+//!   - `%L`: Conflict marker size (integer).
+//!   - `%P`: Pathname of the file in the repository.
+//!   - `%S %X %Y`: Optional marker labels for base, ours and theirs (Git 2.44+).
+//!
+//! **Implementation Steps:**
+//! 1.  Receive arguments (resolved file paths) from Git.
+//! 2.  Read the content of the base (`%O`), current (`%A`), and other (`%B`)
+//!     versions, sniffing each (see [Driver Inputs](#driver-inputs)).
+//! 3.  Parse all three versions into AST/CSTs.
+//! 4.  Perform a 3-way tree merge algorithm.
+//! 5.  **Conflict Handling:**
+//!     - If the merge is clean, generate the resulting source code from the merged AST/CST.
+//!     - If conflicts occur that the AST merge cannot resolve, either:
+//!       a) Generate source code containing standard `<<<<<<<`, `=======`, `>>>>>>>`
+//!       conflict markers (using `%L` for marker size) around the conflicting sections.
+//!       b) Abort the merge for this file.
+//! 6.  Write the resulting merged source code (or source with conflict markers) back
+//!     to the file specified by `%A` (overwriting it).
+//! 7.  **Exit Code:**
+//!     - Exit `0` if the merge was successful (no conflicts or conflicts marked).
+//!     - Exit with a non-zero status (e.g., `1`) if the merge failed completely or requires manual resolution beyond markers.
+//!
+//! **Note:** Implementing a robust 3-way AST merge algorithm with good conflict handling is complex.
+//! The driver currently merges the canonical (pretty-printed) versions line
+//! by line, see [`merge`](crate::merge).
+//!
+//! ### Conflict Markers
+//!
+//! Markers follow `merge.conflictStyle` (`merge`, `diff3` or `zdiff3`). They
+//! are labelled with `%X`/`%Y`/`%S` when Git passes them, otherwise with the
+//! current branch, the branch being merged (or the commit being picked) and
+//! `base`; the ours and theirs labels also name the top-level items the
+//! conflict is in, e.g. `<<<<<<< main (parse_header)`. With
+//! `ast.conflictMarkers = node` each conflict spans whole top-level items
+//! instead of just the lines that differ.
+//!
+//! ### Merge Budget
+//!
+//! A structural merge that would leave more than `ast.mergeMaxConflicts`
+//! conflict regions (default 50), or that takes longer than
+//! `ast.mergeTimeoutMs` (default 10 seconds), is given up for that file: the
+//! canonical versions are merged by `git merge-file` instead, Git's own text
+//! merge, with the same marker style, size and labels. Either limit is off
+//! at 0. The reason is logged and kept as `fallback` in the conflict report
+//! and merge note, so pathological files never hold a merge up.
+//!
+//! ### Conflict Sidecars
+//!
+//! With `ast.mergeSidecars = true`, a merge that stops with conflicts also
+//! leaves the full context next to the file: `<path>.base`, `<path>.ours` and
+//! `<path>.theirs` (the pretty-printed versions, since Git only hands the
+//! driver stored blobs) plus `<path>.conflict.json`, a [`ConflictReport`]
+//! listing each unresolved region. Add `*.base`, `*.ours`, `*.theirs` and
+//! `*.conflict.json` to `.gitignore` (or `.git/info/exclude`) when enabling it.
+//!
+//! ## Driver Inputs
+//!
+//! Depending on how Git was invoked, a driver's temporary files hold stored
+//! AST blobs or source Git already smudged, and conversions on the way (a
+//! byte order mark, `core.autocrlf` applied to a blob checked out without
+//! the filter) can disguise a blob as source. So the diff driver prefers the
+//! blobs Git names by their hex IDs, read from the object database, and only
+//! falls back to the files for sides Git has no stored blob for (the work
+//! tree, an all-zero ID). Every file a driver reads is sniffed: a blob
+//! behind a byte order mark or with CRLF line ends is restored before it is
+//! smudged, so neither driver mistakes one representation for the other.
+//!
+//! ### Merge Notes
+//!
+//! With `ast.mergeNotes = true` the same report is kept under
+//! `.git/ast-merge-notes/` and, once the merge is committed, attached to the
+//! resulting commit in `refs/notes/ast` by `git-ast hook post-commit`, so the
+//! record of what the driver did travels with the repository.
+
+use crate::changes::{self, FileChange};
+use crate::config;
+use crate::diff_backend::{self, DiffBackend, DiffFormat};
+use crate::diff_cache::DiffCache;
+use crate::git_plumbing::filters;
+use crate::languages;
+use crate::merge::{self, ConflictStyle, Labels};
+use crate::serialization;
+use crate::telemetry;
+use crate::util::atomic_io;
+use crate::{log_info, Error};
+use git2::{Oid, Repository};
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+
+/// Executes the custom diff driver logic.
+///
+/// Called by Git based on `[diff "ast"] command`.
+/// Arguments are provided by Git (path, old-file, old-hex, etc.).
+///
+/// Both sides are smudged to canonical source (AST blobs are printed, other
+/// content passes through) and rendered by the [`diff_backend`] configured
+/// for the file's language in `.git-ast.toml`, so formatting differences
+/// between stored blobs never show up. Rendered diffs of committed blob
+/// pairs are cached (see [`diff_cache`](crate::diff_cache)).
+pub fn run_diff_driver(format: DiffFormat, args: &[String]) -> Result<(), Error> {
+    log_info!("driver", "Running diff driver with args: {:?}", args);
+    if args.len() == 1 {
+        // An unmerged path: Git has no single pair of sides to pass.
+        println!("* Unmerged path {}", args[0]);
+        return Ok(());
+    }
+    if args.len() < 7 {
+        return Err(Error::Driver(
+            "Insufficient arguments for diff driver".to_string(),
+        ));
+    }
+    // A rename or copy adds the new path and Git's description of it.
+    let (old_path, path) = match args.get(7) {
+        Some(new_path) => (&args[0], new_path),
+        None => (&args[0], &args[0]),
+    };
+    let (old_file, old_hex, old_mode) = (&args[1], &args[2], side(&args[1], &args[3]));
+    let (new_file, new_hex, new_mode) = (&args[4], &args[5], side(&args[4], &args[6]));
+
+    let repo = Repository::open_from_env().ok();
+    let change = FileChange {
+        path: path.clone(),
+        from: (old_path != path).then(|| {
+            let copy = args
+                .get(8)
+                .is_some_and(|message| message.contains("copy from "));
+            (old_path.clone(), copy)
+        }),
+        old_mode: old_mode.map(str::to_string),
+        new_mode: new_mode.map(str::to_string),
+        old: canonical_side(repo.as_ref(), old_path, old_file, old_hex, old_mode)?,
+        new: canonical_side(repo.as_ref(), path, new_file, new_hex, new_mode)?,
+    };
+    let rename = change.rename(repo.as_ref())?;
+    let backend = change.backend(repo.as_ref())?;
+    let cache = match &repo {
+        Some(repo) => config::Settings::load(Some(repo))?
+            .diff_cache
+            .then(|| DiffCache::open(repo)),
+        None => None,
+    };
+    let fingerprint = match format {
+        DiffFormat::Readable => diff_fingerprint(path, backend.as_ref()),
+        DiffFormat::Patch => {
+            diff_fingerprint(path, &diff_backend::NativeBackend) + "\nformat patch"
+        }
+    } + &format!("\nmodes {:?} {:?}\nrename {:?}", old_mode, new_mode, rename);
+    let key = DiffCache::key(old_hex, new_hex, &fingerprint);
+    if let (Some(cache), Some(key)) = (&cache, key) {
+        let cached = cache.get(key);
+        let result = if cached.is_some() { "hit" } else { "miss" };
+        telemetry::add(
+            "git_ast_diff_cache_requests_total",
+            &[("result", result)],
+            1.0,
+        );
+        if let Some(diff) = cached {
+            std::io::stdout().write_all(&diff)?;
+            return Ok(());
+        }
+    }
+
+    let diff = change.render_with(rename.as_ref(), backend.as_ref(), format)?;
+    if let (Some(cache), Some(key)) = (&cache, key) {
+        cache.put(key, &diff)?;
+    }
+    std::io::stdout().write_all(&diff)?;
+    Ok(())
+}
+
+/// The mode of one side of a diff, or `None` if the file does not exist
+/// there (Git passes `/dev/null` and `.` for it).
+fn side<'a>(file: &str, mode: &'a str) -> diff_backend::Side<'a> {
+    (file != "/dev/null" && mode != ".").then_some(mode)
+}
+
+/// One side of a diff as the backends compare it: printed source for a
+/// regular file; the target of a symlink and Git's `Subproject commit`
+/// line for a submodule as they are, since neither is ever cleaned.
+fn canonical_side(
+    repo: Option<&Repository>,
+    path: &str,
+    file: &str,
+    hex: &str,
+    mode: diff_backend::Side<'_>,
+) -> Result<Vec<u8>, Error> {
+    match mode {
+        None => Ok(Vec::new()),
+        Some("160000") => Ok(format!("Subproject commit {}\n", hex).into_bytes()),
+        Some(mode) => changes::canonical(repo, path, mode, load(repo, file, hex)?),
+    }
+}
+
+/// One side of a diff: the blob `hex` names when the repository has it,
+/// else the sniffed content of `file`.
+fn load(repo: Option<&Repository>, file: &str, hex: &str) -> Result<Vec<u8>, Error> {
+    let stored = Oid::from_str(hex)
+        .ok()
+        .filter(|oid| !oid.is_zero())
+        .and_then(|oid| repo?.find_blob(oid).ok());
+    match stored {
+        Some(blob) => Ok(blob.content().to_vec()),
+        None if file == "/dev/null" => Ok(Vec::new()),
+        None => {
+            log_info!("driver", "No stored blob {}, reading {}", hex, file);
+            Ok(sniff(std::fs::read(file)?))
+        }
+    }
+}
+
+/// `content` with what disguises an AST blob undone: a leading byte order
+/// mark, and CRLF line ends (a blob's lines always end in a bare LF, and CRs
+/// in its text are escaped). Source passes through unchanged.
+pub fn sniff(content: Vec<u8>) -> Vec<u8> {
+    let body = content.strip_prefix(b"\xef\xbb\xbf").unwrap_or(&content);
+    if !serialization::is_ast_blob(body) {
+        return content;
+    }
+    let first_line = body.split(|&b| b == b'\n').next().unwrap_or_default();
+    if !first_line.ends_with(b"\r") {
+        return body.to_vec();
+    }
+    let mut out = Vec::with_capacity(body.len());
+    for (i, &byte) in body.iter().enumerate() {
+        if !(byte == b'\r' && body.get(i + 1) == Some(&b'\n')) {
+            out.push(byte);
+        }
+    }
+    out
+}
+
+/// Everything besides the two blobs that shapes [`run_diff_driver`]'s output.
+fn diff_fingerprint(path: &str, backend: &dyn DiffBackend) -> String {
+    let grammars: Vec<String> = languages::all()
+        .iter()
+        .map(|p| format!("{}@{}", p.name, p.grammar_version))
+        .collect();
+    format!(
+        "git-ast {}\npath {}\nbackend {}\ngrammars {}",
+        env!("CARGO_PKG_VERSION"),
+        path,
+        backend.fingerprint(),
+        grammars.join(" ")
+    )
+}
+
+/// Git config key enabling conflict sidecar files (see [`write_conflict_sidecars`]).
+pub const MERGE_SIDECARS_KEY: &str = "ast.mergeSidecars";
+/// Git config key recording merge results in notes (see [`record_merge_note`]).
+pub const MERGE_NOTES_KEY: &str = "ast.mergeNotes";
+
+/// Git config key selecting conflict marker granularity: `line` (default)
+/// or `node`, which widens each conflict to whole top-level items.
+pub const CONFLICT_MARKERS_KEY: &str = "ast.conflictMarkers";
+
+/// Git config key bounding the conflict regions of a structural merge
+/// before it falls back to Git's text merge (see [Merge Budget](self#merge-budget)).
+pub const MERGE_MAX_CONFLICTS_KEY: &str = "ast.mergeMaxConflicts";
+/// Git config key bounding the time of a structural merge, in milliseconds.
+pub const MERGE_TIMEOUT_KEY: &str = "ast.mergeTimeoutMs";
+
+/// Executes the custom merge driver logic.
+///
+/// Called by Git based on `[merge "ast"] driver`.
+/// Arguments are paths to base (%O), current (%A), other (%B) versions,
+/// marker size (%L), and pathname (%P), optionally followed by the
+/// ancestor, ours and theirs marker labels (%S %X %Y).
+///
+/// The three versions are smudged to canonical source and merged line by
+/// line (see [`merge`]). A clean merge is written back to `%A` in the form
+/// `%A` came in, i.e. as an AST blob when the file is stored as one.
+/// Conflicts are written with markers in the `merge.conflictStyle` style and
+/// make the driver fail, which tells Git the file needs resolving.
+///
+/// In [`MergeMode::DryRun`] and [`MergeMode::Explain`] nothing is written
+/// (no `%A`, sidecars or notes), and `%L` and `%P` are optional: the marker
+/// size defaults to 7 and the path to `%A`'s, so the driver can be run by
+/// hand on three files.
+pub fn run_merge_driver(args: &[String], mode: MergeMode) -> Result<(), Error> {
+    log_info!("driver", "Running merge driver with args: {:?}", args);
+    let required = match mode {
+        MergeMode::Write => 5,
+        MergeMode::DryRun | MergeMode::Explain => 3,
+    };
+    if args.len() < required {
+        return Err(Error::Driver(
+            "Insufficient arguments for merge driver".to_string(),
+        ));
+    }
+    let base_path = Path::new(&args[0]);
+    let current_path = Path::new(&args[1]); // Read-Write
+    let other_path = Path::new(&args[2]);
+    let marker_size = args
+        .get(3)
+        .and_then(|size| size.parse::<usize>().ok())
+        .unwrap_or(7);
+    let pathname = args.get(4).unwrap_or(&args[1]);
+
+    let current_raw = sniff(std::fs::read(current_path)?);
+    let base_content = filters::perform_smudge(&sniff(std::fs::read(base_path)?), pathname)?;
+    let current_content = filters::perform_smudge(&current_raw, pathname)?;
+    let other_content = filters::perform_smudge(&sniff(std::fs::read(other_path)?), pathname)?;
+
+    let repo = Repository::open_from_env().ok();
+    let settings = config::Settings::load(repo.as_ref())?;
+    let style = conflict_style(&config::git_config()?)?;
+    let repo_config = match &repo {
+        Some(repo) => config::repo_config_for(repo, pathname)?,
+        None => Default::default(),
+    };
+    let file =
+        config::get_config_for_path(repo.as_ref(), &repo_config, pathname, &current_content)?;
+    let provider = match file.passthrough {
+        Some(_) => None,
+        None => config::language_for_path(repo.as_ref(), pathname, &base_content)?,
+    };
+    let items = match provider {
+        Some(provider) => merge::items(&base_content, provider),
+        None => Vec::new(),
+    };
+    let per_node = settings.conflict_markers == "node";
+    let versions = MergeVersions {
+        base: &base_content,
+        ours: &current_content,
+        theirs: &other_content,
+    };
+    let merged = merge_within(&versions, &items, per_node, settings.merge_timeout);
+    let fallback = match &merged {
+        None => Some(format!(
+            "structural merge took over {} ms (ast.mergeTimeoutMs)",
+            settings.merge_timeout.unwrap_or_default().as_millis()
+        )),
+        Some(merged) => {
+            let regions = merged.conflicts().count();
+            settings
+                .merge_max_conflicts
+                .filter(|&max| regions > max)
+                .map(|max| format!("structural merge left {} conflict regions, over ast.mergeMaxConflicts = {}", regions, max))
+        }
+    };
+
+    let (conflicts, output) = match (merged, &fallback) {
+        (Some(mut merged), None) => {
+            let mut stored = None;
+            let mut reason = "both sides changed these lines differently".to_string();
+            if merged.is_clean() {
+                let source = merge::render(
+                    &merged,
+                    style,
+                    marker_size,
+                    &labels(repo.as_ref(), args),
+                    &|_| None,
+                );
+                match store(
+                    &source,
+                    &current_raw,
+                    pathname,
+                    provider,
+                    &settings,
+                    &repo_config,
+                ) {
+                    Ok(blob) => stored = Some(blob),
+                    Err(e) => {
+                        // Each side's edit is fine on its own, but not both together.
+                        reason = format!("merged result does not parse: {}", e);
+                        merged.chunks = vec![merge::Chunk::Conflict(merge::Region {
+                            base: 0..merged.base.len(),
+                            ours: 0..merged.ours.len(),
+                            theirs: 0..merged.theirs.len(),
+                        })];
+                    }
+                }
+            }
+            let conflicts: Vec<ConflictRegion> = merged
+                .conflicts()
+                .map(|conflict| ConflictRegion {
+                    ours: LineRange::of_lines(&conflict.ours),
+                    theirs: LineRange::of_lines(&conflict.theirs),
+                    reason: reason.clone(),
+                })
+                .collect();
+            if mode == MergeMode::Explain {
+                let settings = MergeSettings {
+                    language: provider.map(|p| p.name),
+                    per_node,
+                    style,
+                    stored_as_ast: stored.as_deref().is_some_and(serialization::is_ast_blob),
+                };
+                print!("{}", explain(pathname, &merged, &items, &settings, &reason));
+                return Ok(());
+            }
+            let symbol = |conflict: &merge::Region| {
+                let names: Vec<&str> = merge::touched(&items, &conflict.base)
+                    .map(|item| item.name.as_str())
+                    .collect();
+                (!names.is_empty()).then(|| names.join(", "))
+            };
+            let output = match stored {
+                Some(output) => output,
+                None => merge::render(
+                    &merged,
+                    style,
+                    marker_size,
+                    &labels(repo.as_ref(), args),
+                    &symbol,
+                ),
+            };
+            (conflicts, output)
+        }
+        (_, Some(why)) => {
+            log_info!(
+                "driver",
+                "{}: {}; falling back to Git's text merge",
+                pathname,
+                why
+            );
+            telemetry::add("git_ast_merge_fallbacks_total", &[], 1.0);
+            if mode == MergeMode::Explain {
+                println!(
+                    "merging {}: {}; Git's text merge would be used instead",
+                    pathname, why
+                );
+                return Ok(());
+            }
+            let (source, mut count) =
+                text_merge(&versions, style, marker_size, &labels(repo.as_ref(), args))?;
+            let mut reason = format!("{} conflict(s) in Git's text merge", count);
+            let output = match count {
+                0 => match store(
+                    &source,
+                    &current_raw,
+                    pathname,
+                    provider,
+                    &settings,
+                    &repo_config,
+                ) {
+                    Ok(blob) => blob,
+                    Err(e) => {
+                        count = 1;
+                        reason = format!("merged result does not parse: {}", e);
+                        source
+                    }
+                },
+                _ => source,
+            };
+            let conflicts = match count {
+                0 => Vec::new(),
+                _ => vec![ConflictRegion {
+                    ours: LineRange::covering(&current_content),
+                    theirs: LineRange::covering(&other_content),
+                    reason,
+                }],
+            };
+            (conflicts, output)
+        }
+        (None, None) => unreachable!("a merge that did not finish falls back"),
+    };
+    if mode == MergeMode::DryRun {
+        std::io::stdout().write_all(&output)?;
+        return match conflicts.len() {
+            0 => Ok(()),
+            n => Err(Error::Driver(format!("{} conflict(s) in {}", n, pathname))),
+        };
+    }
+    atomic_io::write(current_path, output)?;
+
+    let mut report = ConflictReport::new(pathname, marker_size, conflicts);
+    report.fallback = fallback;
+    if !report.conflicts.is_empty() && settings.merge_sidecars {
+        write_conflict_sidecars(Path::new("."), pathname, &versions, &report)?;
+    }
+    if let (Some(repo), true) = (&repo, settings.merge_notes) {
+        record_merge_note(repo, &report)?;
+    }
+
+    match report.conflicts.len() {
+        0 => Ok(()),
+        n => Err(Error::Driver(format!("{} conflict(s) in {}", n, pathname))),
+    }
+}
+
+/// The line merge of `versions`, or `None` if it takes longer than
+/// `timeout`. A merge given up on keeps running on its own thread until the
+/// driver exits.
+fn merge_within<'a>(
+    versions: &MergeVersions<'a>,
+    items: &[merge::Item],
+    per_node: bool,
+    timeout: Option<std::time::Duration>,
+) -> Option<merge::Merge<'a>> {
+    let chunks = move |base: &[u8], ours: &[u8], theirs: &[u8], items: &[merge::Item]| {
+        let expand = |range| match per_node {
+            true => merge::expand_to_items(items, range),
+            false => range,
+        };
+        merge::merge(base, ours, theirs, &expand).chunks
+    };
+    let chunks = match timeout {
+        None => chunks(versions.base, versions.ours, versions.theirs, items),
+        Some(timeout) => {
+            let (base, ours, theirs) = (
+                versions.base.to_vec(),
+                versions.ours.to_vec(),
+                versions.theirs.to_vec(),
+            );
+            let items = items.to_vec();
+            let (send, receive) = std::sync::mpsc::channel();
+            std::thread::spawn(move || {
+                let _ = send.send(chunks(&base, &ours, &theirs, &items));
+            });
+            receive.recv_timeout(timeout).ok()?
+        }
+    };
+    Some(merge::Merge {
+        base: merge::lines(versions.base),
+        ours: merge::lines(versions.ours),
+        theirs: merge::lines(versions.theirs),
+        chunks,
+    })
+}
+
+/// What the driver writes for a clean merge result: `source` cleaned back
+/// to an AST blob if `%A` came in as one, else `source` itself.
+fn store(
+    source: &[u8],
+    current_raw: &[u8],
+    pathname: &str,
+    provider: Option<&'static languages::LanguageProvider>,
+    settings: &config::Settings,
+    repo_config: &config::RepoConfig,
+) -> Result<Vec<u8>, Error> {
+    match serialization::is_ast_blob(current_raw) {
+        true => filters::clean_with(
+            source,
+            pathname,
+            provider,
+            &settings.parse_limits,
+            repo_config,
+        ),
+        false => Ok(source.to_vec()),
+    }
+}
+
+/// Git's own text merge of `versions` (`git merge-file`), for files the
+/// structural merge gave up on: the merged text with markers, and the number
+/// of conflicts.
+fn text_merge(
+    versions: &MergeVersions<'_>,
+    style: ConflictStyle,
+    marker_size: usize,
+    labels: &Labels,
+) -> Result<(Vec<u8>, usize), Error> {
+    // Removed with the files in it however this returns.
+    let dir = atomic_io::PrivateDir::create("git-ast-merge")?;
+    let paths = [
+        dir.write("ours", versions.ours)?,
+        dir.write("base", versions.base)?,
+        dir.write("theirs", versions.theirs)?,
+    ];
+    let mut command = std::process::Command::new("git");
+    command.args([
+        "merge-file",
+        "-p",
+        &format!("--marker-size={}", marker_size),
+    ]);
+    match style {
+        ConflictStyle::Merge => {}
+        style => {
+            command.arg(format!("--{}", style.name()));
+        }
+    }
+    for label in [&labels.ours, &labels.base, &labels.theirs] {
+        command.args(["-L", label]);
+    }
+    let output = command.args(&paths).output()?;
+    // The exit status is the number of conflicts; negative means an error.
+    match output.status.code() {
+        Some(count @ 0..=127) => Ok((output.stdout, count as usize)),
+        _ => Err(Error::Driver(format!(
+            "git merge-file failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))),
+    }
+}
+
+/// `merge.conflictStyle`, defaulting to `merge`.
+fn conflict_style(config: &git2::Config) -> Result<ConflictStyle, Error> {
+    match config.get_string("merge.conflictStyle") {
+        Ok(value) => ConflictStyle::parse(&value)
+            .ok_or_else(|| Error::Config(format!("invalid merge.conflictStyle: {:?}", value))),
+        Err(e) if e.code() == git2::ErrorCode::NotFound => Ok(ConflictStyle::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// What [`run_merge_driver`] does with the merge result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergeMode {
+    /// Write it to `%A`, as Git expects.
+    #[default]
+    Write,
+    /// Print it to stdout instead.
+    DryRun,
+    /// Print how each changed region was decided instead.
+    Explain,
+}
+
+/// The settings an explanation reports.
+struct MergeSettings {
+    language: Option<&'static str>,
+    per_node: bool,
+    style: ConflictStyle,
+    stored_as_ast: bool,
+}
+
+/// The decision log printed by `git-ast merge-driver --explain`: one line
+/// per changed region, with the lines involved in each version (1-based) and
+/// the top-level items it touches, then the outcome.
+fn explain(
+    pathname: &str,
+    merged: &merge::Merge<'_>,
+    items: &[merge::Item],
+    settings: &MergeSettings,
+    reason: &str,
+) -> String {
+    let span = |range: &std::ops::Range<usize>| match range.len() {
+        0 => format!("before line {}", range.start + 1),
+        1 => format!("line {}", range.start + 1),
+        _ => format!("lines {}-{}", range.start + 1, range.end),
+    };
+    let mut out = format!(
+        "merging {} as {} ({} markers, {} style)\n",
+        pathname,
+        settings.language.unwrap_or("plain text"),
+        if settings.per_node { "node" } else { "line" },
+        settings.style.name()
+    );
+    for chunk in &merged.chunks {
+        let (region, decision) = match chunk {
+            merge::Chunk::Stable(_) => continue,
+            merge::Chunk::Ours(r) => (r, format!("only ours changed: took ours {}", span(&r.ours))),
+            merge::Chunk::Theirs(r) => (
+                r,
+                format!("only theirs changed: took theirs {}", span(&r.theirs)),
+            ),
+            merge::Chunk::Both(r) => (
+                r,
+                format!("both made the same change: took ours {}", span(&r.ours)),
+            ),
+            merge::Chunk::Conflict(r) => (
+                r,
+                format!(
+                    "CONFLICT ({}): ours {}, theirs {}",
+                    reason,
+                    span(&r.ours),
+                    span(&r.theirs)
+                ),
+            ),
+        };
+        let names: Vec<&str> = merge::touched(items, &region.base)
+            .map(|i| i.name.as_str())
+            .collect();
+        let within = match names.is_empty() {
+            true => String::new(),
+            false => format!(" in {}", names.join(", ")),
+        };
+        out += &format!("base {}{}: {}\n", span(&region.base), within, decision);
+    }
+    let conflicts = merged.conflicts().count();
+    out += &match conflicts {
+        0 if settings.stored_as_ast => "result: clean, stored as an AST blob\n".to_string(),
+        0 => "result: clean\n".to_string(),
+        n => format!(
+            "result: {} conflict(s); Git would stop for manual resolution\n",
+            n
+        ),
+    };
+    out
+}
+
+/// Marker labels: `%S %X %Y` when Git passes them (2.44 and later), else
+/// the current branch, the branch (or commit) being merged, and `base`.
+fn labels(repo: Option<&Repository>, args: &[String]) -> Labels {
+    let given = |i: usize| {
+        args.get(i)
+            .filter(|label| !label.is_empty() && !label.starts_with('%'))
+            .cloned()
+    };
+    let ours = repo
+        .and_then(|repo| repo.head().ok())
+        .filter(|head| head.is_branch())
+        .and_then(|head| head.shorthand().map(str::to_string));
+    // `git merge` names the merged branches in `GITHEAD_<oid>`; it only
+    // writes MERGE_HEAD afterwards.
+    let merging =
+        std::env::vars().find_map(|(key, name)| key.starts_with("GITHEAD_").then_some(name));
+    let theirs = merging.or_else(|| {
+        let repo = repo?;
+        ["MERGE_HEAD", "CHERRY_PICK_HEAD", "REVERT_HEAD"]
+            .iter()
+            .find_map(|name| std::fs::read_to_string(repo.path().join(name)).ok())
+            .map(|text| text.chars().take(7).collect())
+    });
+    Labels {
+        base: given(5).unwrap_or_else(|| "base".to_string()),
+        ours: given(6).or(ours).unwrap_or_else(|| "HEAD".to_string()),
+        theirs: given(7).or(theirs).unwrap_or_else(|| "theirs".to_string()),
+    }
+}
+
+/// Pretty-printed inputs of a three-way merge.
+#[derive(Debug, Clone, Copy)]
+pub struct MergeVersions<'a> {
+    pub base: &'a [u8],
+    pub ours: &'a [u8],
+    pub theirs: &'a [u8],
+}
+
+/// An inclusive, 1-based line range in one of the merge inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl LineRange {
+    /// The range spanning every line of `content`.
+    pub fn covering(content: &[u8]) -> Self {
+        let lines = content.split(|&b| b == b'\n').count();
+        let lines = if content.ends_with(b"\n") {
+            lines - 1
+        } else {
+            lines
+        };
+        LineRange {
+            start: 1,
+            end: lines.max(1),
+        }
+    }
+
+    /// The 1-based form of a 0-based, end-exclusive line range. An empty
+    /// range (a position between lines) ends just before it starts.
+    pub fn of_lines(range: &std::ops::Range<usize>) -> Self {
+        LineRange {
+            start: range.start + 1,
+            end: range.end,
+        }
+    }
+}
+
+/// One region the merge driver could not resolve.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConflictRegion {
+    /// Lines of the pretty-printed `ours` version involved in the conflict.
+    pub ours: LineRange,
+    /// Lines of the pretty-printed `theirs` version involved in the conflict.
+    pub theirs: LineRange,
+    /// Why the region could not be resolved automatically.
+    pub reason: String,
+}
+
+/// Machine-readable companion to the conflict markers, written as
+/// `<path>.conflict.json` for mergetools and scripts.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConflictReport {
+    /// Report format version.
+    pub version: u32,
+    /// Repository path of the conflicted file (`%P`).
+    pub path: String,
+    /// Conflict marker size used in the merged file (`%L`).
+    pub marker_size: usize,
+    /// Sidecar files holding the pretty-printed inputs, relative to the worktree root.
+    pub sidecars: Sidecars,
+    pub conflicts: Vec<ConflictRegion>,
+    /// Why the structural merge was abandoned for Git's text merge, if it was.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<String>,
+}
+
+/// Paths of the per-version sidecar files.
+#[derive(Debug, Clone, Serialize)]
+pub struct Sidecars {
+    pub base: String,
+    pub ours: String,
+    pub theirs: String,
+}
+
+impl ConflictReport {
+    pub fn new(pathname: &str, marker_size: usize, conflicts: Vec<ConflictRegion>) -> Self {
+        ConflictReport {
+            version: 1,
+            path: pathname.to_string(),
+            marker_size,
+            sidecars: Sidecars {
+                base: format!("{}.base", pathname),
+                ours: format!("{}.ours", pathname),
+                theirs: format!("{}.theirs", pathname),
+            },
+            conflicts,
+            fallback: None,
+        }
+    }
+
+    /// Path of the JSON report itself.
+    pub fn report_path(&self) -> String {
+        format!("{}.conflict.json", self.path)
+    }
+}
+
+/// Directory below `.git` holding merge records until the merge is committed.
+pub const PENDING_MERGE_NOTES_DIR: &str = "ast-merge-notes";
+
+/// Saves `report` until the commit concluding the merge exists; the
+/// `post-commit` hook then attaches it as the `merge` section of the commit's
+/// note in `refs/notes/ast` (see [`notes`](crate::git_plumbing::notes)).
+/// Enabled with `ast.mergeNotes = true`.
+///
+/// The record remembers the commit checked out during the merge, so records
+/// left behind by an aborted merge are not attached to an unrelated commit.
+pub fn record_merge_note(repo: &Repository, report: &ConflictReport) -> Result<(), Error> {
+    let head = repo.head()?.peel_to_commit()?.id();
+    let record = serde_json::json!({ "head": head.to_string(), "report": report });
+    let dir = repo.path().join(PENDING_MERGE_NOTES_DIR);
+    std::fs::create_dir_all(&dir)?;
+    let name = git2::Oid::hash_object(git2::ObjectType::Blob, report.path.as_bytes())?;
+    atomic_io::write(dir.join(format!("{}.json", name)), record.to_string())?;
+    Ok(())
+}
+
+/// Writes the pretty-printed base/ours/theirs versions and the JSON conflict
+/// report next to the conflicted file, below `worktree`.
+///
+/// Similar in spirit to the `_BASE_`/`_LOCAL_`/`_REMOTE_` files `git mergetool`
+/// creates, but available as soon as the merge stops, and in source form even
+/// though Git only holds AST blobs for the three versions. Enabled with
+/// `ast.mergeSidecars = true`.
+pub fn write_conflict_sidecars(
+    worktree: &Path,
+    pathname: &str,
+    versions: &MergeVersions<'_>,
+    report: &ConflictReport,
+) -> Result<(), Error> {
+    let sidecars = &report.sidecars;
+    for (path, content) in [
+        (&sidecars.base, versions.base),
+        (&sidecars.ours, versions.ours),
+        (&sidecars.theirs, versions.theirs),
+    ] {
+        atomic_io::write(worktree.join(path), content)?;
+    }
+    let json = serde_json::to_vec_pretty(report)
+        .map_err(|e| Error::Serialization(format!("conflict report: {}", e)))?;
+    atomic_io::write(worktree.join(report.report_path()), json)?;
+    log_info!("driver", "Wrote conflict sidecars for {}", pathname);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_plumbing::notes;
+    use crate::parsing::ParseLimits;
+
+    #[test]
+    fn prefers_stored_blobs_and_sniffs_files() {
+        let rust = languages::by_name("rust").unwrap();
+        let blob =
+            filters::clean_as(b"fn  f(){}\n", "a.rs", Some(rust), &ParseLimits::default()).unwrap();
+        let crlf: Vec<u8> = String::from_utf8(blob.clone())
+            .unwrap()
+            .replace('\n', "\r\n")
+            .into();
+        assert_eq!(sniff([b"\xef\xbb\xbf".as_slice(), &crlf].concat()), blob);
+        let source = b"\xef\xbb\xbffn f() {}\r\n".to_vec();
+        assert_eq!(sniff(source.clone()), source);
+
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let id = repo.blob(&blob).unwrap();
+        let file = dir.path().join("smudged.rs");
+        std::fs::write(&file, "fn f() {}\n").unwrap();
+        let file = file.to_str().unwrap();
+        assert_eq!(load(Some(&repo), file, &id.to_string()).unwrap(), blob);
+        assert_eq!(
+            load(Some(&repo), file, &Oid::zero().to_string()).unwrap(),
+            b"fn f() {}\n"
+        );
+        assert_eq!(load(None, "/dev/null", ".").unwrap(), b"");
+    }
+
+    #[test]
+    fn writes_sidecars_and_conflict_report_next_to_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let versions = MergeVersions {
+            base: b"fn a() {}\n",
+            ours: b"fn a() {\n    1\n}\n",
+            theirs: b"fn a() {\n    2\n}\n",
+        };
+        let region = ConflictRegion {
+            ours: LineRange::of_lines(&(1..2)),
+            theirs: LineRange::of_lines(&(1..2)),
+            reason: "both sides changed a".to_string(),
+        };
+        let report = ConflictReport::new("src/a.rs", 7, vec![region]);
+        write_conflict_sidecars(dir.path(), "src/a.rs", &versions, &report).unwrap();
+
+        let read = |name: &str| std::fs::read(dir.path().join("src").join(name)).unwrap();
+        assert_eq!(read("a.rs.base"), versions.base);
+        assert_eq!(read("a.rs.ours"), versions.ours);
+        assert_eq!(read("a.rs.theirs"), versions.theirs);
+        let json: serde_json::Value = serde_json::from_slice(&read("a.rs.conflict.json")).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "version": 1,
+                "path": "src/a.rs",
+                "marker_size": 7,
+                "sidecars": {
+                    "base": "src/a.rs.base",
+                    "ours": "src/a.rs.ours",
+                    "theirs": "src/a.rs.theirs",
+                },
+                "conflicts": [{
+                    "ours": {"start": 2, "end": 2},
+                    "theirs": {"start": 2, "end": 2},
+                    "reason": "both sides changed a",
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn attaches_merge_notes_to_the_commit_that_concludes_the_merge() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let signature = git2::Signature::new("t", "t@example.com", &git2::Time::new(0, 0)).unwrap();
+        let tree = repo
+            .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+            .unwrap();
+        let commit = |message: &str, parents: &[&git2::Commit<'_>]| {
+            let id = repo
+                .commit(None, &signature, &signature, message, &tree, parents)
+                .unwrap();
+            repo.set_head_detached(id).unwrap();
+            repo.find_commit(id).unwrap()
+        };
+        let base = commit("base", &[]);
+        record_merge_note(&repo, &ConflictReport::new("a.rs", 7, Vec::new())).unwrap();
+        let merged = commit("merge", &[&base]);
+        crate::commands::hook::post_commit(&repo).unwrap();
+
+        let note = notes::read(&repo, notes::AST_NOTES_REF, merged.id()).unwrap();
+        let note: serde_json::Value = serde_json::from_str(&note.unwrap()).unwrap();
+        assert_eq!(note["merge"][0]["path"], "a.rs");
+        assert!(!repo.path().join(PENDING_MERGE_NOTES_DIR).exists());
+
+        // A record left by an aborted merge on another commit is dropped.
+        record_merge_note(&repo, &ConflictReport::new("b.rs", 7, Vec::new())).unwrap();
+        let unrelated = commit("other", &[&base]);
+        crate::commands::hook::post_commit(&repo).unwrap();
+        assert_eq!(
+            notes::read(&repo, notes::AST_NOTES_REF, unrelated.id()).unwrap(),
+            None
+        );
+        assert!(!repo.path().join(PENDING_MERGE_NOTES_DIR).exists());
+    }
+}
@@ -0,0 +1,288 @@
+//! Duplicate Code
+//!
+//! [`find`] hashes every subtree of the files of a revision twice: exactly
+//! (node kinds and token text), and with identifiers blanked out. Subtrees
+//! of at least `min_tokens` tokens that share a hash are duplicates:
+//! *identical* when the exact hashes match, *renamed* when only the blanked
+//! ones do (the same code with other names for its variables, functions or
+//! types). Comments are ignored and, since trees do not record layout,
+//! formatting never hides a copy.
+//!
+//! A copy of a function also duplicates every statement in it, so a group
+//! whose places all lie inside places already reported (larger groups come
+//! first) is left out.
+//!
+//! Each place names its blob next to the source lines. In an AST repository
+//! that is the stored tree of the file (`git show <blob>`), and
+//! `git-ast map <blob>` finds the source mirror's blob.
+
+use crate::languages::LanguageProvider;
+use crate::parsing::ParseLimits;
+use crate::symbols;
+use crate::Error;
+use git2::Repository;
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use tree_sitter::Node as TsNode;
+
+/// How alike the places of a [`Group`] are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Similarity {
+    /// Same code, up to the names of identifiers.
+    Renamed,
+    /// Same tokens.
+    Identical,
+}
+
+/// One copy of duplicated code.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Place {
+    pub path: String,
+    pub blob: String,
+    pub kind: String,
+    /// 1-based, inclusive.
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+/// Places with the same code.
+#[derive(Debug, Clone, Serialize)]
+pub struct Group {
+    pub similarity: Similarity,
+    /// Tokens in each place.
+    pub tokens: usize,
+    pub places: Vec<Place>,
+}
+
+/// A subtree big enough to report.
+struct Candidate {
+    exact: u64,
+    renamed: u64,
+    tokens: usize,
+    file: usize,
+    bytes: Range<usize>,
+    place: Place,
+}
+
+/// The duplicated code of `rev`, largest first.
+pub fn find(
+    repo: &Repository,
+    rev: &str,
+    min_tokens: usize,
+    include_renamed: bool,
+    limits: &ParseLimits,
+) -> Result<Vec<Group>, Error> {
+    let mut candidates = Vec::new();
+    for (file, (path, id)) in symbols::files(repo, rev)?.into_iter().enumerate() {
+        let Some((provider, source, tree)) = symbols::parse_blob(repo, id, &path, limits)? else {
+            continue;
+        };
+        let mut file = FileHashes {
+            provider,
+            source: &source,
+            min_tokens,
+            file,
+            path: &path,
+            blob: id.to_string(),
+            out: &mut candidates,
+        };
+        file.hash(tree.root_node());
+    }
+
+    let mut groups: Vec<(Similarity, Vec<usize>)> = Vec::new();
+    let mut by_hash: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (i, candidate) in candidates.iter().enumerate() {
+        by_hash.entry(candidate.exact).or_default().push(i);
+    }
+    groups.extend(
+        by_hash
+            .into_values()
+            .filter(|members| members.len() > 1)
+            .map(|members| (Similarity::Identical, members)),
+    );
+    if include_renamed {
+        let mut by_hash: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, candidate) in candidates.iter().enumerate() {
+            by_hash.entry(candidate.renamed).or_default().push(i);
+        }
+        groups.extend(
+            by_hash
+                .into_values()
+                .filter(|members| {
+                    let first = candidates[members[0]].exact;
+                    members.iter().any(|&i| candidates[i].exact != first)
+                })
+                .map(|members| (Similarity::Renamed, members)),
+        );
+    }
+    // Largest first; a renamed group before the identical ones inside it.
+    groups.sort_by_key(|(similarity, members)| {
+        let first = &candidates[members[0]];
+        (
+            std::cmp::Reverse(first.tokens),
+            *similarity,
+            first.file,
+            first.bytes.start,
+        )
+    });
+
+    let mut reported: Vec<(usize, Range<usize>)> = Vec::new();
+    let mut found = Vec::new();
+    for (similarity, members) in groups {
+        let inside = |c: &Candidate| {
+            reported.iter().any(|(file, bytes)| {
+                *file == c.file && bytes.start <= c.bytes.start && c.bytes.end <= bytes.end
+            })
+        };
+        if members.iter().all(|&i| inside(&candidates[i])) {
+            continue;
+        }
+        reported.extend(
+            members
+                .iter()
+                .map(|&i| (candidates[i].file, candidates[i].bytes.clone())),
+        );
+        found.push(Group {
+            similarity,
+            tokens: candidates[members[0]].tokens,
+            places: members
+                .iter()
+                .map(|&i| candidates[i].place.clone())
+                .collect(),
+        });
+    }
+    Ok(found)
+}
+
+/// Hashes the subtrees of one file.
+struct FileHashes<'a> {
+    provider: &'static LanguageProvider,
+    source: &'a [u8],
+    min_tokens: usize,
+    file: usize,
+    path: &'a str,
+    blob: String,
+    out: &'a mut Vec<Candidate>,
+}
+
+impl FileHashes<'_> {
+    /// The exact and renamed hashes and the token count of `node`, recording
+    /// it if it is big enough.
+    fn hash(&mut self, node: TsNode<'_>) -> (u64, u64, usize) {
+        let (mut exact, mut renamed) = (DefaultHasher::new(), DefaultHasher::new());
+        node.kind_id().hash(&mut exact);
+        node.kind_id().hash(&mut renamed);
+        let mut tokens = 0;
+        if node.child_count() == 0 {
+            let text = &self.source[node.byte_range()];
+            text.hash(&mut exact);
+            if !node.kind().contains("identifier") {
+                text.hash(&mut renamed);
+            }
+            tokens = 1;
+        }
+        let mut children = 0;
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            if self.provider.syntax.comments.contains(&child.kind()) {
+                continue;
+            }
+            let (child_exact, child_renamed, child_tokens) = self.hash(child);
+            child_exact.hash(&mut exact);
+            child_renamed.hash(&mut renamed);
+            tokens += child_tokens;
+            children += 1;
+        }
+        let (exact, renamed) = (exact.finish(), renamed.finish());
+        // A node with just one child is the same code as that child.
+        if node.is_named() && children != 1 && tokens >= self.min_tokens {
+            self.out.push(Candidate {
+                exact,
+                renamed,
+                tokens,
+                file: self.file,
+                bytes: node.byte_range(),
+                place: Place {
+                    path: self.path.to_string(),
+                    blob: self.blob.clone(),
+                    kind: node.kind().to_string(),
+                    start_line: node.start_position().row + 1,
+                    end_line: node.end_position().row + 1,
+                },
+            });
+        }
+        (exact, renamed, tokens)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Signature;
+    use std::path::Path;
+
+    #[test]
+    fn reports_the_largest_copies_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let body = "{\n    let mut total = 0;\n    for x in items {\n        total += x * 2;\n    }\n    total\n}\n";
+        let files = [
+            ("a.rs", format!("fn sum(items: &[u32]) -> u32 {}", body)),
+            // Reformatted, with a comment.
+            (
+                "b.rs",
+                format!("// copy\nfn sum(items: &[u32]) -> u32\n{}", body),
+            ),
+            (
+                "c.rs",
+                format!(
+                    "fn add_up(items: &[u32]) -> u32 {}",
+                    body.replace("total", "acc")
+                ),
+            ),
+        ];
+        let mut index = repo.index().unwrap();
+        for (path, text) in &files {
+            std::fs::write(dir.path().join(path), text).unwrap();
+            index.add_path(Path::new(path)).unwrap();
+        }
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = Signature::now("t", "t@example.com").unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "one", &tree, &[])
+            .unwrap();
+
+        let limits = ParseLimits::default();
+        let groups = find(&repo, "HEAD", 20, true, &limits).unwrap();
+        let summary: Vec<_> = groups
+            .iter()
+            .map(|g| {
+                let places: Vec<_> = g
+                    .places
+                    .iter()
+                    .map(|p| (p.path.as_str(), p.kind.as_str()))
+                    .collect();
+                (g.similarity, places)
+            })
+            .collect();
+        assert_eq!(
+            summary[0],
+            (
+                Similarity::Renamed,
+                vec![
+                    ("a.rs", "function_item"),
+                    ("b.rs", "function_item"),
+                    ("c.rs", "function_item")
+                ]
+            )
+        );
+        assert_eq!(groups.len(), 1, "{:?}", summary);
+
+        let identical = find(&repo, "HEAD", 20, false, &limits).unwrap();
+        assert_eq!(identical[0].similarity, Similarity::Identical);
+        assert_eq!(identical[0].places.len(), 2);
+    }
+}
@@ -0,0 +1,351 @@
+//! Structural Change Fingerprints
+//!
+//! A fingerprint identifies *what a commit changes*, independent of where:
+//! like `git patch-id`, but computed on syntax tokens instead of text lines.
+//! Two commits that make the same edits get the same fingerprint even when
+//! they apply at different line numbers or formatting differs around them,
+//! which is what rebases and cherry-picks produce.
+//!
+//! ## Computation
+//!
+//! For every file a commit changes (against its first parent):
+//!
+//! 1.  Both versions are smudged to source and tokenized with the file's
+//!     language provider. Tokens inside nodes captured by the language's
+//!     `ignore` query pack (see [`queries`](crate::queries)) are dropped.
+//!     Files without a provider, or that do not parse, fall back to lines.
+//! 2.  The token sequences are diffed. The *edit script* lists each hunk's
+//!     removed and inserted tokens (kind and text), without positions or
+//!     surrounding context.
+//! 3.  The file fingerprint hashes its path and edit script.
+//!
+//! The commit fingerprint hashes the sorted file fingerprints. A commit whose
+//! files only change formatting has an empty edit script for them, so those
+//! files do not contribute at all.
+//!
+//! ## Duplicate Detection
+//!
+//! `git-ast fingerprint <rev>` prints fingerprints; with `--upstream <range>`
+//! it marks each commit whose change already exists upstream, like
+//! `git cherry` but robust to reformatting and shifted line numbers (see
+//! [`duplicates`]).
+
+use crate::config;
+use crate::git_plumbing::filters;
+use crate::languages::LanguageProvider;
+use crate::parsing::{self, ParseLimits};
+use crate::queries::{self, QueryPacks};
+use crate::Error;
+use git2::{Commit, FileMode, ObjectType, Oid, Repository, Sort};
+use similar::{Algorithm, DiffOp};
+
+/// Fingerprint of one changed file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileFingerprint {
+    pub path: String,
+    pub fingerprint: Oid,
+}
+
+/// Fingerprint of a commit's changes.
+#[derive(Debug, Clone)]
+pub struct CommitFingerprint {
+    pub commit: Oid,
+    /// First line of the commit message.
+    pub summary: String,
+    /// Files with a non-empty edit script, sorted by path.
+    pub files: Vec<FileFingerprint>,
+    pub fingerprint: Oid,
+}
+
+impl CommitFingerprint {
+    /// Whether the commit changes nothing structurally.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+/// Fingerprints the commits selected by `spec`: a single revision, or a
+/// range `A..B` (oldest first).
+pub fn commits(
+    repo: &Repository,
+    spec: &str,
+    limits: &ParseLimits,
+    packs: &mut QueryPacks,
+) -> Result<Vec<CommitFingerprint>, Error> {
+    if !spec.contains("..") {
+        let single = repo.revparse_single(spec)?.peel_to_commit()?;
+        return Ok(vec![commit(repo, &single, limits, packs)?]);
+    }
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    walk.push_range(spec)?;
+    walk.map(|oid| commit(repo, &repo.find_commit(oid?)?, limits, packs))
+        .collect()
+}
+
+/// For each of `commits`, the first commit of `upstream` making the same
+/// structural change. Commits without structural changes never match.
+pub fn duplicates(
+    commits: &[CommitFingerprint],
+    upstream: &[CommitFingerprint],
+) -> Vec<Option<Oid>> {
+    commits
+        .iter()
+        .map(|c| {
+            upstream
+                .iter()
+                .find(|u| !c.is_empty() && u.fingerprint == c.fingerprint)
+                .map(|u| u.commit)
+        })
+        .collect()
+}
+
+/// Computes the fingerprint of `commit` against its first parent.
+pub fn commit(
+    repo: &Repository,
+    commit: &Commit<'_>,
+    limits: &ParseLimits,
+    packs: &mut QueryPacks,
+) -> Result<CommitFingerprint, Error> {
+    let parent = match commit.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None,
+    };
+    let diff = repo.diff_tree_to_tree(parent.as_ref(), Some(&commit.tree()?), None)?;
+    let mut files = Vec::new();
+    for delta in diff.deltas() {
+        let (old, new) = (delta.old_file(), delta.new_file());
+        if old.mode() == FileMode::Commit || new.mode() == FileMode::Commit {
+            continue; // Submodule pointer: no content to compare.
+        }
+        let Some(path) = new.path().or(old.path()).and_then(|p| p.to_str()) else {
+            continue;
+        };
+        let old_source = blob_source(repo, old.id(), path)?;
+        let new_source = blob_source(repo, new.id(), path)?;
+        let content = if new.id().is_zero() {
+            &old_source
+        } else {
+            &new_source
+        };
+        let provider = config::language_for_path(Some(repo), path, content)?;
+        let script = edit_script(provider, &old_source, &new_source, limits, packs)?;
+        if !script.is_empty() {
+            let material = [path.as_bytes(), b"\0", &script].concat();
+            files.push(FileFingerprint {
+                path: path.to_string(),
+                fingerprint: Oid::hash_object(ObjectType::Blob, &material)?,
+            });
+        }
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    let material: String = files
+        .iter()
+        .map(|f| format!("{} {}\n", f.fingerprint, f.path))
+        .collect();
+    Ok(CommitFingerprint {
+        commit: commit.id(),
+        summary: commit.summary().unwrap_or_default().to_string(),
+        fingerprint: Oid::hash_object(ObjectType::Blob, material.as_bytes())?,
+        files,
+    })
+}
+
+/// The edit script turning `old` into `new`: one block per hunk, listing
+/// removed (`-`) and inserted (`+`) tokens. Empty when only whitespace (or
+/// ignored nodes) changed.
+pub fn edit_script(
+    provider: Option<&'static LanguageProvider>,
+    old: &[u8],
+    new: &[u8],
+    limits: &ParseLimits,
+    packs: &mut QueryPacks,
+) -> Result<Vec<u8>, Error> {
+    if let Some(provider) = provider {
+        if let (Ok(old_tree), Ok(new_tree)) = (
+            parsing::parse(old, provider, limits),
+            parsing::parse(new, provider, limits),
+        ) {
+            let pack = packs.get(provider)?;
+            let old_tokens = parsing::tokens_excluding(
+                &old_tree,
+                old,
+                &pack.ranges(queries::IGNORE, &old_tree, old),
+            );
+            let new_tokens = parsing::tokens_excluding(
+                &new_tree,
+                new,
+                &pack.ranges(queries::IGNORE, &new_tree, new),
+            );
+            return Ok(script(&old_tokens, &new_tokens));
+        }
+    }
+    // No structure to go by: compare non-blank lines, ignoring indentation.
+    Ok(script(&lines(old), &lines(new)))
+}
+
+/// How much of `old` is kept in `new`, in percent: twice the tokens they
+/// share (in order) over the tokens of both. Like Git's similarity index,
+/// but counted in syntax tokens, so reformatting does not lower it. Falls
+/// back to lines like [`edit_script`].
+pub fn similarity(
+    provider: Option<&'static LanguageProvider>,
+    old: &[u8],
+    new: &[u8],
+    limits: &ParseLimits,
+) -> u32 {
+    if let Some(provider) = provider {
+        if let (Ok(old_tree), Ok(new_tree)) = (
+            parsing::parse(old, provider, limits),
+            parsing::parse(new, provider, limits),
+        ) {
+            return score(
+                &parsing::tokens(&old_tree, old),
+                &parsing::tokens(&new_tree, new),
+            );
+        }
+    }
+    score(&lines(old), &lines(new))
+}
+
+/// [`similarity`] of two token sequences.
+pub(crate) fn score<T: Ord + std::hash::Hash>(old: &[T], new: &[T]) -> u32 {
+    let total = old.len() + new.len();
+    if total == 0 {
+        return 100;
+    }
+    let shared: usize = similar::capture_diff_slices(Algorithm::Myers, old, new)
+        .iter()
+        .map(|op| match op {
+            DiffOp::Equal { len, .. } => *len,
+            _ => 0,
+        })
+        .sum();
+    (shared * 200 / total) as u32
+}
+
+/// Non-blank lines without their indentation, as tokens.
+fn lines(s: &[u8]) -> Vec<(&'static str, Vec<u8>)> {
+    s.split(|&b| b == b'\n')
+        .map(|l| ("line", l.trim_ascii().to_vec()))
+        .filter(|(_, l)| !l.is_empty())
+        .collect()
+}
+
+fn script<T: AsRef<[u8]> + Ord + std::hash::Hash>(old: &[(&str, T)], new: &[(&str, T)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for op in similar::capture_diff_slices(Algorithm::Myers, old, new) {
+        let (removed, inserted) = match op {
+            DiffOp::Equal { .. } => continue,
+            DiffOp::Delete {
+                old_index, old_len, ..
+            } => (old_index..old_index + old_len, 0..0),
+            DiffOp::Insert {
+                new_index, new_len, ..
+            } => (0..0, new_index..new_index + new_len),
+            DiffOp::Replace {
+                old_index,
+                old_len,
+                new_index,
+                new_len,
+            } => (
+                old_index..old_index + old_len,
+                new_index..new_index + new_len,
+            ),
+        };
+        out.extend_from_slice(b"@@\n");
+        for (sign, (kind, text)) in old[removed]
+            .iter()
+            .map(|t| (b'-', t))
+            .chain(new[inserted].iter().map(|t| (b'+', t)))
+        {
+            out.push(sign);
+            out.extend_from_slice(kind.as_bytes());
+            out.push(b' ');
+            out.extend_from_slice(text.as_ref());
+            out.push(b'\n');
+        }
+    }
+    out
+}
+
+/// Source of a blob as checked out (empty for a missing side).
+fn blob_source(repo: &Repository, id: Oid, path: &str) -> Result<Vec<u8>, Error> {
+    if id.is_zero() {
+        return Ok(Vec::new());
+    }
+    filters::perform_smudge(repo.find_blob(id)?.content(), path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::languages;
+
+    #[test]
+    fn edit_script_ignores_position_and_formatting() {
+        let rust = languages::by_name("rust");
+        let limits = ParseLimits::default();
+        let mut packs = QueryPacks::default();
+        let mut script = |old: &str, new: &str| {
+            edit_script(rust, old.as_bytes(), new.as_bytes(), &limits, &mut packs).unwrap()
+        };
+        let edit = script("fn a() { x(1); }\n", "fn a() { x(2); }\n");
+        assert_eq!(edit, b"@@\n-integer_literal 1\n+integer_literal 2\n");
+        let moved = script(
+            "fn z() {}\n\nfn a() {\n    x(1);\n}\n",
+            "fn z() {}\n\nfn a() {\n    x(2);\n}\n",
+        );
+        assert_eq!(moved, edit);
+        assert!(script("fn a(){x(1);}", "fn a() {\n    x(1);\n}\n").is_empty());
+
+        let reformatted = similarity(rust, b"fn a(){x(1);}", b"fn a() {\n    x(1);\n}\n", &limits);
+        assert_eq!(reformatted, 100);
+        // 9 of 10 tokens on each side kept.
+        assert_eq!(
+            similarity(rust, b"fn a() { x(1); }", b"fn a() { x(2); }", &limits),
+            90
+        );
+        assert_eq!(similarity(None, b"a\nb\n", b"c\n", &limits), 0);
+    }
+
+    #[test]
+    fn finds_the_same_change_made_on_differently_formatted_code() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let signature = git2::Signature::new("t", "t@example.com", &git2::Time::new(0, 0)).unwrap();
+        let commit_file = |parent: Option<Oid>, source: &str, message: &str| {
+            let mut builder = repo.treebuilder(None).unwrap();
+            let blob = repo.blob(source.as_bytes()).unwrap();
+            builder.insert("a.rs", blob, 0o100644).unwrap();
+            let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+            let parents: Vec<Commit<'_>> = parent
+                .map(|id| repo.find_commit(id).unwrap())
+                .into_iter()
+                .collect();
+            let parents: Vec<&Commit<'_>> = parents.iter().collect();
+            repo.commit(None, &signature, &signature, message, &tree, &parents)
+                .unwrap()
+        };
+        let base = commit_file(None, "fn a() {\n    x(1);\n}\n", "base");
+        let upstream = commit_file(Some(base), "fn a() {\n    x(2);\n}\n", "Use 2");
+        // The same edit on a reformatted copy, then a different one.
+        let messy = commit_file(Some(base), "fn a(){x(1);}\n", "Reformat");
+        let picked = commit_file(Some(messy), "fn a(){ x(2); }\n", "Use 2 (picked)");
+        let other = commit_file(Some(picked), "fn a(){ x(3); }\n", "Use 3");
+
+        let limits = ParseLimits::default();
+        let mut packs = QueryPacks::default();
+        let upstream = commits(&repo, &upstream.to_string(), &limits, &mut packs).unwrap();
+        let topic = commits(&repo, &format!("{}..{}", base, other), &limits, &mut packs).unwrap();
+        let ids: Vec<Oid> = topic.iter().map(|c| c.commit).collect();
+        assert_eq!(ids, [messy, picked, other]);
+        assert!(topic[0].is_empty(), "reformatting changes nothing");
+        assert_eq!(topic[1].fingerprint, upstream[0].fingerprint);
+        assert_eq!(topic[1].summary, "Use 2 (picked)");
+        assert_eq!(
+            duplicates(&topic, &upstream),
+            [None, Some(upstream[0].commit), None]
+        );
+    }
+}
@@ -1,101 +0,0 @@
-"""//! Configuration Handling
-//!
-//! This module is responsible for reading and interpreting configuration
-//! related to `git-ast` from Git sources like `.gitattributes` and `.gitconfig`.
-//!
-//! ## `.gitattributes`
-//!
-//! Defines which files are handled by `git-ast` filters and drivers.
-//!
-//! ```gitattributes
-//! # Example: Handle all Rust files
-//! *.rs filter=ast diff=ast merge=ast
-//! 
-//! # Example: Handle Python files, but only filtering and diffing
-//! *.py filter=ast diff=ast
-//! 
-//! # Example: Treat images as binary (passthrough for filters/drivers)
-//! *.png binary
-//! ```
-//!
-//! Key attributes used by `git-ast`:
-//! - `filter=ast`: Enables the clean/smudge filter.
-//! - `diff=ast`: Enables the custom diff driver.
-//! - `merge=ast`: Enables the custom merge driver.
-//! - `binary` or `-filter -diff -merge`: Explicitly marks files to be ignored by `git-ast`.
-//!
-//! ## `.gitconfig` (or `.git/config`)
-//!
-//! Defines *how* the filters and drivers invoke the `git-ast` tool.
-//!
-//! ```ini
-//! [filter "ast"]
-//!     # Use the long-running process protocol for efficiency
-//!     process = git-ast filter-process
-//!     # Ensure filter failures block Git operations
-//!     required = true 
-//!
-//! [diff "ast"]
-//!     # Specify the command for Git to call for diffing
-//!     command = git-ast diff-driver
-//!     # Optional: Enable caching if git-ast diff-driver acts like textconv
-//!     # cachetextconv = true 
-//!     # Optional: Tell Git the driver is producing binary output
-//!     # binary = true 
-//!
-//! [merge "ast"]
-//!     # Human-readable name (optional)
-//!     name = AST-based merge driver
-//!     # Command for Git to call for merging
-//!     # %O=base, %A=ours, %B=theirs, %L=marker_size, %P=pathname
-//!     driver = git-ast merge-driver %O %A %B %L %P
-//!     # Optional: Specify a driver for recursive internal merges (often `binary`)
-//!     recursive = binary
-//! ```
-//!
-//! This module would contain functions to:
-//! - Query gitattributes for a given path.
-//! - Query gitconfig for filter/driver definitions.
-
-use crate::Error;
-
-/// Represents the combined git-ast configuration for a specific file path.
-#[derive(Debug, Clone, Default)]
-pub struct FileConfig {
-    pub use_filter: bool,
-    pub use_diff_driver: bool,
-    pub use_merge_driver: bool,
-    // Add other relevant config options, e.g., language override
-}
-
-/// Parses `.gitattributes` and `.gitconfig` to determine git-ast settings for a path.
-///
-/// This function would likely involve:
-/// 1. Calling `git check-attr filter diff merge -- <path>` to get attributes.
-/// 2. Potentially querying `git config` for driver details if needed immediately,
-///    though often the calling process (filter, diff, merge) relies on Git
-///    having already read the config to invoke the correct `git-ast` command.
-pub fn get_config_for_path(path: &str) -> Result<FileConfig, Error> {
-    // --- Placeholder Implementation --- 
-    eprintln!("[config] Determining config for path: {}", path);
-    // In a real implementation, call `git check-attr` 
-    // For now, assume 'ast' is set for common code files
-    let use_ast = path.ends_with(".rs") || path.ends_with(".py") || path.ends_with(".js");
-    if use_ast {
-        Ok(FileConfig {
-            use_filter: true,
-            use_diff_driver: true,
-            use_merge_driver: true,
-        })
-    } else {
-        // Default: don't process
-        Ok(FileConfig::default())
-    }
-    // --- End Placeholder --- 
-}
-
-// Potentially add functions here to read specific [filter "ast"], [diff "ast"],
-// or [merge "ast"] sections from git config if needed directly by the tool,
-// although Git usually handles invoking the correct command based on the config.
-
-"" 
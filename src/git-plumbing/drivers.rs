@@ -1,169 +0,0 @@
-"""//! Custom Diff and Merge Driver Implementation
-//!
-//! This module provides the logic for acting as a custom diff and merge driver
-//! for Git, enabling AST/CST-based comparisons and merges.
-//!
-//! ## Custom Diff Driver (`git-ast diff-driver`)
-//!
-//! Invoked by Git when `diff=ast` is set in `.gitattributes` and a corresponding
-//! `[diff "ast"] command = git-ast diff-driver` is configured.
-//!
-//! **Purpose:** Generate a textual diff representation based on structural (AST/CST)
-//! comparison, ignoring pure formatting changes.
-//!
-//! **Git Invocation:** Git typically calls the command with 7 arguments:
-//! `path old-file old-hex old-mode new-file new-hex new-mode`
-//! See `GIT_EXTERNAL_DIFF` in `git(1)` man page.
-//!
-//! **Implementation Steps:**
-//! 1.  Receive arguments from Git.
-//! 2.  Obtain the content of the old and new versions (e.g., by reading the
-//!     provided file paths, or potentially by accessing blobs via hex IDs if needed).
-//!     *Important:* If using clean/smudge filters, the content received might be
-//!     source text already smudged by Git, or it might be the raw AST blobs if
-//!     accessed directly. The driver needs to handle this (likely by running the
-//!     smudge logic internally or by parsing directly if ASTs are accessible).
-//! 3.  Parse both versions into AST/CSTs.
-//! 4.  Perform a tree diff operation (e.g., using `gumtree-rs` or a similar algorithm)
-//!     to identify structural changes (adds, deletes, moves, updates).
-//! 5.  Format the structural changes into a human-readable textual diff format.
-//!     This might resemble unified diff or be a custom format highlighting AST changes.
-//! 6.  Write the formatted diff to stdout.
-//!
-//! **Impact on `git log`:** When this driver is configured, `git log -p` will
-//! automatically use it to generate patch text for commits involving files with `diff=ast`.
-//!
-//! ## Custom Merge Driver (`git-ast merge-driver`)
-//!
-//! Invoked by Git during a merge (or cherry-pick, revert) when `merge=ast` is set
-//! in `.gitattributes` and `[merge "ast"] driver = git-ast merge-driver ...` is configured.
-//!
-//! **Purpose:** Perform a 3-way merge based on AST/CST structure, aiming to handle
-//! refactorings and concurrent structural changes more intelligently than text-based merges.
-//!
-//! **Git Invocation:** Git calls the driver command with placeholders replaced:
-//! `git-ast merge-driver %O %A %B %L %P`
-//!   - `%O`: Path to a temporary file with the base version content.
-//!   - `%A`: Path to a temporary file with the current branch version (read/write).
-//!   - `%B`: Path to a temporary file with the other branch version.
-//!   - `%L`: Conflict marker size (integer).
-//!   - `%P`: Pathname of the file in the repository.
-//!
-//! **Implementation Steps:**
-//! 1.  Receive arguments (resolved file paths) from Git.
-//! 2.  Read the content of the base (`%O`), current (`%A`), and other (`%B`) versions.
-//!     *Important:* Similar to the diff driver, this content might be source text or
-//!     raw AST blobs depending on how Git invokes the driver relative to filters.
-//!     The driver likely needs to smudge/parse these inputs.
-//! 3.  Parse all three versions into AST/CSTs.
-//! 4.  Perform a 3-way tree merge algorithm.
-//! 5.  **Conflict Handling:**
-//!     - If the merge is clean, generate the resulting source code from the merged AST/CST.
-//!     - If conflicts occur that the AST merge cannot resolve, either:
-//!         a) Generate source code containing standard `<<<<<<<`, `=======`, `>>>>>>>`
-//!            conflict markers (using `%L` for marker size) around the conflicting sections.
-//!         b) Abort the merge for this file.
-//! 6.  Write the resulting merged source code (or source with conflict markers) back
-//!     to the file specified by `%A` (overwriting it).
-//! 7.  **Exit Code:**
-//!     - Exit `0` if the merge was successful (no conflicts or conflicts marked).
-//!     - Exit with a non-zero status (e.g., `1`) if the merge failed completely or requires manual resolution beyond markers.
-//!
-//! **Note:** Implementing a robust 3-way AST merge algorithm with good conflict handling is complex.
-
-use crate::Error;
-use std::path::Path;
-use std::process::Command;
-
-/// Executes the custom diff driver logic.
-///
-/// Called by Git based on `[diff "ast"] command`.
-/// Arguments are provided by Git (path, old-file, old-hex, etc.).
-pub fn run_diff_driver(args: &[String]) -> Result<(), Error> {
-    eprintln!("[driver] Running diff driver with args: {:?}", args);
-    // --- Placeholder Implementation --- 
-    if args.len() < 7 {
-        return Err(Error::Driver("Insufficient arguments for diff driver".to_string()));
-    }
-    let path = &args[0];
-    let old_file = &args[1];
-    let new_file = &args[4];
-
-    eprintln!("[driver] Diffing path: {}, old: {}, new: {}", path, old_file, new_file);
-
-    // 1. Get content for old_file and new_file (handle smudge/parsing)
-    // 2. Perform AST diff 
-    // 3. Format diff output
-
-    // Placeholder: Use standard diff for now
-    let output = Command::new("diff")
-        .arg("-u") // Unified format
-        .arg(old_file)
-        .arg(new_file)
-        .output()
-        .map_err(|e| Error::Io(e))?;
-
-    // Write the diff output to stdout
-    std::io::stdout().write_all(&output.stdout).map_err(|e| Error::Io(e))?;
-    // Ignore stderr for this placeholder
-
-    // Exit code 0 usually means no differences, 1 means differences found.
-    // Standard diff command handles this.
-    // If implementing custom diff, exit appropriately.
-    if output.status.success() || output.status.code() == Some(1) {
-         Ok(())
-    } else {
-        Err(Error::Driver(format!("Diff command failed: {:?}", output.status)))
-    }
-    // --- End Placeholder --- 
-}
-
-/// Executes the custom merge driver logic.
-///
-/// Called by Git based on `[merge "ast"] driver`.
-/// Arguments are paths to base (%O), current (%A), other (%B) versions,
-/// marker size (%L), and pathname (%P).
-pub fn run_merge_driver(args: &[String]) -> Result<(), Error> {
-    eprintln!("[driver] Running merge driver with args: {:?}", args);
-    // --- Placeholder Implementation --- 
-    if args.len() < 5 {
-         return Err(Error::Driver("Insufficient arguments for merge driver".to_string()));
-    }
-    let base_path = Path::new(&args[0]);
-    let current_path = Path::new(&args[1]); // Read-Write
-    let other_path = Path::new(&args[2]);
-    let _marker_size = args[3].parse::<usize>().unwrap_or(7);
-    let pathname = &args[4];
-
-    eprintln!("[driver] Merging path: {}", pathname);
-    eprintln!("  Base: {:?}, Current: {:?}, Other: {:?}", base_path, current_path, other_path);
-
-    // 1. Read content for base, current, other (handle smudge/parsing)
-    // 2. Perform 3-way AST merge
-    // 3. Handle conflicts (generate markers or fail)
-    // 4. Write result back to current_path
-    // 5. Exit 0 for success, non-zero for conflict/failure
-
-    // Placeholder: Simulate a conflict by writing dummy markers to current_path
-    let current_content = std::fs::read(current_path)?;
-    let other_content = std::fs::read(other_path)?;
-    
-    let mut merged_content = Vec::new();
-    merged_content.extend_from_slice(b"<<<<<<< HEAD\n");
-    merged_content.extend_from_slice(&current_content);
-    merged_content.extend_from_slice(b"\n=======\n");
-    merged_content.extend_from_slice(&other_content);
-    merged_content.extend_from_slice(b"\n>>>>>>> OTHER\n");
-
-    std::fs::write(current_path, merged_content)?;
-
-    // Return non-zero to indicate conflicts require resolution
-    // Use std::process::exit(1) in a real main function, 
-    // here we signal via error for placeholder.
-    eprintln!("[driver] Merge resulted in conflicts (Placeholder)");
-    Err(Error::Driver("Simulated merge conflict".to_string())) // Simulate failure exit code
-    
-    // --- End Placeholder --- 
-}
-
-"" 
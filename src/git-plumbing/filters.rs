@@ -1,109 +0,0 @@
-"""//! Clean/Smudge Filter Implementation
-//!
-//! This module implements the core logic for Git's clean and smudge filters,
-//! converting between source text and serialized AST/CST representations.
-//!
-//! It is typically invoked by Git via the `process = git-ast filter-process`
-//! command defined in `.gitconfig` (see [`config`] module).
-//!
-//! ## Filter Lifecycle (Long-Running Process Protocol)
-//!
-//! When configured with `process = ...`, Git starts the `git-ast filter-process`
-//! command once and communicates with it over stdin/stdout using a specific protocol
-//! (see `gitattributes(5)` man page or `technical/long-running-process-protocol.adoc`
-//! in Git source).
-//!
-//! 1.  **Handshake:** Git sends capabilities, `git-ast` responds with supported features (`clean`, `smudge`).
-//! 2.  **Command Loop:** For each file to filter:
-//!     a.  Git sends `command=clean` or `command=smudge`, `pathname=<path>`, etc.
-//!     b.  Git sends file content.
-//!     c.  `git-ast` performs the action (parsing/serialization for clean, deserialization/pretty-printing for smudge).
-//!     d.  `git-ast` sends `status=success` (or `error`/`abort`) and the resulting content.
-//! 3.  **Termination:** Git closes stdin when done.
-//!
-//! ## Clean Operation (`command=clean`)
-//!
-//! -   **Input:** Source code text from Git via stdin.
-//! -   **Action:**
-//!     1.  Parse source code into an AST/CST (e.g., using Tree-sitter via a `parsing` module).
-//!     2.  Serialize the AST/CST (including comments, etc.) into the canonical format (e.g., MessagePack, JSON, CBOR via a `serialization` module).
-//! -   **Output:** Serialized AST/CST data to Git via stdout.
-//!
-//! ## Smudge Operation (`command=smudge`)
-//!
-//! -   **Input:** Serialized AST/CST data from Git object store via stdin.
-//! -   **Action:**
-//!     1.  Deserialize the data into an in-memory AST/CST representation (using `serialization`).
-//!     2.  Generate formatted source code from the AST/CST using a deterministic pretty-printer (using `pretty_printing`).
-//! -   **Output:** Generated source code text to Git via stdout.
-//!
-//! ## Performance
-//!
-//! -   The long-running process avoids per-file process startup overhead.
-//! -   Efficient parsing (Tree-sitter), serialization (binary formats), and generation are key.
-//! -   Consider internal caching if the same AST/CST structures are processed repeatedly.
-
-use crate::Error;
-use std::io::{Read, Write};
-
-/// Runs the main loop for the long-running filter process.
-///
-/// Reads commands and data from stdin, performs clean/smudge operations,
-/// and writes results to stdout according to Git's filter process protocol.
-pub fn run_long_running_filter() -> Result<(), Error> {
-    // --- Placeholder Implementation --- 
-    // This would involve: 
-    // 1. Initial handshake with Git.
-    // 2. Entering a loop reading commands (clean/smudge, pathname, etc.) from stdin.
-    // 3. Reading content for each file.
-    // 4. Calling internal `perform_clean` or `perform_smudge`.
-    // 5. Writing status and results back to stdout.
-    // 6. Handling errors and the protocol specifics.
-    eprintln!("[filter] Starting long-running filter process (Placeholder)");
-    // Simulate reading one command and exiting
-    let mut buffer = Vec::new();
-    std::io::stdin().read_to_end(&mut buffer)?;
-    // In a real scenario, parse the buffer according to the protocol
-    eprintln!("[filter] Received {} bytes, pretending to process...", buffer.len());
-    
-    // Simulate a successful response for a hypothetical smudge
-    let response_status = "status=success\n";
-    let response_content = "// Smudged content placeholder\nfn main() {}\n";
-    // Using pkt-line format would be required for real implementation
-    std::io::stdout().write_all(response_status.as_bytes())?;
-    std::io::stdout().write_all(b"\0")?; // Flush packet approximation
-    std::io::stdout().write_all(response_content.as_bytes())?;
-    std::io::stdout().write_all(b"\0")?; // Flush packet approximation
-    std::io::stdout().write_all(b"\0")?; // Final flush
-    
-    eprintln!("[filter] Finished filter process (Placeholder)");
-    Ok(())
-    // --- End Placeholder --- 
-}
-
-/// Performs the 'clean' operation: source text -> serialized AST.
-fn perform_clean(input_content: &[u8], pathname: &str) -> Result<Vec<u8>, Error> {
-    eprintln!("[filter] Cleaning path: {}", pathname);
-    // 1. Parse input_content to AST/CST (using a `parsing` module)
-    // 2. Serialize AST/CST (using a `serialization` module)
-    // Placeholder: just return input slightly modified
-    let mut output = b"SERIALIZED:".to_vec();
-    output.extend_from_slice(input_content);
-    Ok(output)
-}
-
-/// Performs the 'smudge' operation: serialized AST -> source text.
-fn perform_smudge(input_content: &[u8], pathname: &str) -> Result<Vec<u8>, Error> {
-    eprintln!("[filter] Smudging path: {}", pathname);
-    // 1. Deserialize input_content to AST/CST (using `serialization`)
-    // 2. Generate source code (using `pretty_printing`)
-    // Placeholder: check for prefix and return rest
-    if input_content.starts_with(b"SERIALIZED:") {
-        Ok(input_content["SERIALIZED:".len()..].to_vec())
-    } else {
-        // Return original if not recognized (maybe log warning)
-        Ok(input_content.to_vec())
-    }
-}
-
-"" 
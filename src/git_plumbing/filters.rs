@@ -0,0 +1,1424 @@
+//! Clean/Smudge Filter Implementation
+//!
+//! This module implements the core logic for Git's clean and smudge filters,
+//! converting between source text and serialized AST/CST representations.
+//!
+//! It is typically invoked by Git via the `process = git-ast filter-process`
+//! command defined in `.gitconfig` (see [`config`](crate::config) module).
+//!
+//! ## Filter Lifecycle (Long-Running Process Protocol)
+//!
+//! When configured with `process = ...`, Git starts the `git-ast filter-process`
+//! command once and communicates with it over stdin/stdout using a specific protocol
+//! (see `gitattributes(5)` man page or `technical/long-running-process-protocol.adoc`
+//! in Git source), framed as pkt-lines (see [`pktline`](super::pktline)).
+//!
+//! 1.  **Handshake:** Git sends capabilities, `git-ast` responds with supported features (`clean`, `smudge`).
+//! 2.  **Command Loop:** For each file to filter:
+//!     a.  Git sends `command=clean` or `command=smudge`, `pathname=<path>`, etc.
+//!     b.  Git sends file content.
+//!     c.  `git-ast` performs the action (parsing/serialization for clean, deserialization/pretty-printing for smudge).
+//!     d.  `git-ast` sends `status=success` (or `error`/`abort`) and the resulting content.
+//! 3.  **Termination:** Git closes stdin when done.
+//!
+//! ## Clean Operation (`command=clean`)
+//!
+//! -   **Input:** Source code text from Git via stdin.
+//! -   **Action:**
+//!     1.  Parse source code into an AST/CST (e.g., using Tree-sitter via a `parsing` module),
+//!         after checking the project's [`lint`](crate::lint) rules.
+//!     2.  Convert it to the stored tree ([`ast`](crate::ast)) and serialize it
+//!         in the blob format described in [`serialization`](crate::serialization).
+//! -   **Output:** Serialized AST/CST data to Git via stdout.
+//!
+//! ## Smudge Operation (`command=smudge`)
+//!
+//! -   **Input:** Serialized AST/CST data from Git object store via stdin.
+//! -   **Action:**
+//!     1.  Deserialize the blob (using [`serialization`](crate::serialization)).
+//!     2.  Generate canonical source code from the tree using the deterministic
+//!         printer in [`pretty_printing`](crate::pretty_printing).
+//! -   **Output:** Generated source code text to Git via stdout.
+//!
+//! ## Performance
+//!
+//! -   The long-running process avoids per-file process startup overhead.
+//! -   Efficient parsing (Tree-sitter), serialization (binary formats), and generation are key.
+//! -   Cleaning reuses the blob the index holds for the path, see below.
+//!
+//! ### Reusing the Previous Blob
+//!
+//! Git cleans a file again whenever its stat data changed, most often with
+//! little or nothing edited since checkout. When the index has an AST blob
+//! for the path (same language and grammar, not sparse), the filter first
+//! prints it: if that is the incoming source, the blob's tree is stored
+//! again without parsing. Otherwise the source is parsed, and the top-level
+//! items that did not change, found by comparing the leading and trailing
+//! items with the previous tree's, take over the previous subtrees with
+//! their injections, so only the edited region runs the injection query and
+//! embedded-language parses. Either way the result is the blob a full clean
+//! would store: normalizations and lints run as usual, and files with syntax
+//! errors always take the full path.
+//!
+//! ## Memory
+//!
+//! Git sends a file's whole content before it reads the answer, so the
+//! filter consumes its input completely before writing, but it never holds
+//! a blob and its converted form side by side: smudge decodes the blob while
+//! reading its packets ([`serialization::decode_from`]), and both directions
+//! write their result straight into response packets
+//! ([`serialization::encode_to`], [`pretty_printing::print_to`]). What stays
+//! in memory is the tree, plus for clean the source text, which the parser
+//! needs whole. Should writing fail halfway (say, an invalid compression
+//! dictionary), the content already sent is followed by `status=error`, as
+//! the protocol allows.
+//!
+//! ## Verifying Checkouts
+//!
+//! With `ast.verifyOnSmudge = warn` or `fail`, every smudged file is cleaned
+//! again and the result compared with the blob it came from, as
+//! [`verify_round_trip`] does before a push. A difference means the printer
+//! or grammar has drifted and the working tree no longer holds what was
+//! committed: `warn` logs it and checks the file out anyway, `fail` answers
+//! `status=error` so Git reports the path. It costs a parse per file, so it
+//! is off by default.
+//!
+//! ## Smudge Modes
+//!
+//! `ast.smudgeMode` chooses what a checkout writes, per clone:
+//!
+//! -   **`canonical`** (default): the printer's output.
+//! -   **`original`**: the source the blob was made from, byte for byte, when
+//!     the [OID map](crate::mirror) records a source counterpart for the blob
+//!     (as a sidecar sync does for the files it cleans); otherwise the
+//!     canonical output.
+//! -   **`annotated`**: the canonical output followed by a provenance comment,
+//!     `// git-ast provenance: blob <id>, rust grammar 0.23.3, git-ast 0.1.0`,
+//!     for audits and debugging. The clean filter drops a last line of that
+//!     form, so annotated files store the same blobs as canonical ones.
+//!
+//! Both modes other than `canonical` need the whole blob before answering, as
+//! verification does.
+//!
+//! Canonical smudges are answered from the [`smudge_cache`](crate::smudge_cache)
+//! when `git-ast prewarm` filled it.
+//!
+//! With `ast.attest = true`, each cleaned blob is also signed and recorded
+//! for the next commit (see [`attestation`](crate::attestation)). A failure
+//! to sign fails the clean, so no blob is stored unattested.
+//!
+//! With `ast.<language>.postSmudge` and `preClean` commands configured, every
+//! smudge of that language is buffered and passed through the first, and
+//! every clean through the second before parsing (see
+//! [`codegen`](crate::codegen)).
+//!
+//! When the worktree has a `.git-ast.lock`, files of a language it pins to
+//! another grammar or printer than this build's fail to clean and smudge
+//! (see [`lockfile`](crate::lockfile)).
+//!
+//! ## Mixed Trees
+//!
+//! Mid-migration, some paths with `filter=ast` still hold plain source
+//! blobs, and an AST blob may have reached the working tree as serialized
+//! text (checked out by a Git without the filter, then added back). Every
+//! step decides by the content, never by the attributes alone:
+//!
+//! -   Smudge and the diff and merge drivers print a blob only if it starts
+//!     with the [`MAGIC`](serialization::MAGIC) header; anything else passes
+//!     through as source.
+//! -   Clean stores content that already is an AST blob (after undoing a
+//!     byte order mark and CRLF line ends, see [`sniff`](crate::drivers::sniff))
+//!     unchanged instead of parsing it as source, provided it decodes.
+//!
+//! `git-ast verify` reports AST blobs at paths without `filter=ast`, which
+//! would be checked out as serialized text.
+//!
+//! ## Shutdown and Recovery
+//!
+//! The process ends when Git closes its input, or on `SIGTERM`, `SIGINT` or
+//! `SIGHUP` after answering the file at hand (see
+//! [`shutdown`](crate::util::shutdown)). Either way it flushes its metrics
+//! and deletes its recovery journal before exiting. If it is killed instead,
+//! the next filter process removes the temporary files and the metrics lock
+//! it left behind (see [`journal`](crate::util::journal)), so a crash
+//! mid-checkout never blocks or corrupts the next one.
+//!
+//! ## Failure Isolation
+//!
+//! A file that fails to clean or smudge (syntax error, parse timeout, size
+//! limit from [`ParseLimits`]) is answered with `status=error` for that path
+//! only. Git then reports the failure for that file (and, with
+//! `required = true`, refuses to stage it) while the process keeps serving the
+//! remaining files.
+
+use crate::ast;
+use crate::attestation::{self, Signer};
+use crate::audit::{self, AuditLog};
+use crate::codegen::Hooks;
+use crate::compression;
+use crate::config::{self, Compression, RepoConfig, SyntaxErrors};
+use crate::git_plumbing::pktline::{
+    read_content, read_text_list, write_flush, write_packet, write_text, PacketReader, PacketWriter,
+};
+use crate::injections;
+use crate::languages::{self, LanguageProvider};
+use crate::lint;
+use crate::lockfile::Lockfile;
+use crate::mirror;
+use crate::normalize;
+use crate::parsing::{self, ParseLimits};
+use crate::pretty_printing;
+use crate::serialization::{self, BlobHeader};
+use crate::smudge_cache::SmudgeCache;
+use crate::sparse;
+use crate::telemetry;
+use crate::util::journal;
+use crate::util::shutdown::{self, Interruptible};
+use crate::{log_error, log_info, Error};
+use git2::Repository;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::time::Instant;
+
+/// Git config key checking each smudge by cleaning its output again.
+pub const VERIFY_ON_SMUDGE_KEY: &str = "ast.verifyOnSmudge";
+
+/// What to do when a smudged file does not clean back to its blob.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VerifyOnSmudge {
+    /// Do not check.
+    #[default]
+    Off,
+    /// Log the mismatch and check the file out anyway.
+    Warn,
+    /// Fail the file.
+    Fail,
+}
+
+/// Git config key choosing what smudge writes.
+pub const SMUDGE_MODE_KEY: &str = "ast.smudgeMode";
+
+/// Start of a provenance comment, after the language's comment marker.
+const PROVENANCE: &str = " git-ast provenance: ";
+
+/// What smudge writes (see [Smudge Modes](self#smudge-modes)).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SmudgeMode {
+    /// The printer's output.
+    #[default]
+    Canonical,
+    /// The recorded original source, if there is one.
+    Original,
+    /// The printer's output and a provenance comment.
+    Annotated,
+}
+
+/// Runs the main loop for the long-running filter process.
+///
+/// Reads commands and data from stdin, performs clean/smudge operations,
+/// and writes results to stdout according to Git's filter process protocol.
+///
+/// See [Shutdown and Recovery](self#shutdown-and-recovery) for how it ends.
+pub fn run_long_running_filter() -> Result<(), Error> {
+    // For `ast-lang` attribute lookups; Git runs us inside the repository.
+    let repo = Repository::open_from_env().ok();
+    let settings = config::Settings::load(repo.as_ref())?;
+    shutdown::install();
+    let _journal = repo.as_ref().map(journal::start).transpose()?;
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    let mut input = BufReader::new(Interruptible(stdin.lock()));
+    let mut output = BufWriter::new(stdout.lock());
+    log_info!("filter", "Starting long-running filter process");
+    let result = serve(&mut input, &mut output, &settings, repo.as_ref());
+    let result = match (result, shutdown::requested()) {
+        (Err(e), Some(signal)) => {
+            log_info!(
+                "filter",
+                "Stopped by signal {} while waiting for Git ({})",
+                signal,
+                e
+            );
+            Ok(())
+        }
+        (result, Some(signal)) => {
+            result.map(|()| log_info!("filter", "Stopped by signal {}, exiting", signal))
+        }
+        (result, None) => {
+            result.map(|()| log_info!("filter", "Git closed the connection, exiting"))
+        }
+    };
+    // While the journal is still open, so a kill in between is recovered.
+    if let (Some(repo), true) = (&repo, settings.metrics) {
+        if let Err(e) = telemetry::flush(&telemetry::metrics_path(repo)) {
+            log_error!("metrics", "{}", e);
+        }
+    }
+    result
+}
+
+/// Cleans or smudges one file from stdin to stdout, for Git's single-shot
+/// `clean = git-ast clean %f` and `smudge = git-ast smudge %f` interface
+/// and for scripts.
+///
+/// Holds a one-request conversation with [`serve`], so that both interfaces
+/// behave the same; failures are logged by `serve` as usual.
+pub fn run_single(command: &str, pathname: &str) -> Result<(), Error> {
+    let repo = Repository::open_from_env().ok();
+    let settings = config::Settings::load(repo.as_ref())?;
+    let mut content = Vec::new();
+    std::io::stdin().read_to_end(&mut content)?;
+    let request = single_request(command, pathname, &content)?;
+    let mut response = Vec::new();
+    serve(&mut &request[..], &mut response, &settings, repo.as_ref())?;
+    let filtered = single_response(&response)
+        .ok_or_else(|| Error::Driver(format!("{} {} failed", command, pathname)))?;
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(&filtered)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// The filter protocol input of one request.
+fn single_request(command: &str, pathname: &str, content: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut request = Vec::new();
+    for list in [
+        &["git-filter-client", "version=2"][..],
+        &["capability=clean", "capability=smudge"],
+    ] {
+        for line in list {
+            write_text(&mut request, line)?;
+        }
+        write_flush(&mut request)?;
+    }
+    write_text(&mut request, &format!("command={}", command))?;
+    write_text(&mut request, &format!("pathname={}", pathname))?;
+    write_flush(&mut request)?;
+    let mut packets = PacketWriter::new(&mut request);
+    packets.write_all(content)?;
+    packets.finish()?;
+    write_flush(&mut request)?;
+    Ok(request)
+}
+
+/// The content [`serve`] answered a [`single_request`] with, unless it failed.
+fn single_response(response: &[u8]) -> Option<Vec<u8>> {
+    let mut input = response;
+    handshake_reply(&mut input)?;
+    let status = read_text_list(&mut input).ok()??;
+    if status.iter().any(|s| s != "status=success") {
+        return None;
+    }
+    let content = read_content(&mut input).ok()?;
+    let status = read_text_list(&mut input).ok()??;
+    match status.iter().all(|s| s == "status=success") {
+        true => Some(content),
+        false => None,
+    }
+}
+
+/// Skips the server's half of the handshake.
+fn handshake_reply(input: &mut &[u8]) -> Option<()> {
+    read_text_list(input).ok()??;
+    read_text_list(input).ok()??;
+    Some(())
+}
+
+/// Speaks the filter protocol over arbitrary streams until `input` is closed.
+///
+/// `settings` are the caller's, loaded once for the whole conversation.
+/// `repo`, if given, is consulted for per-path language overrides and for
+/// the normalizations in `.git-ast.toml`.
+pub fn serve<R: Read, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    settings: &config::Settings,
+    repo: Option<&Repository>,
+) -> Result<(), Error> {
+    let limits = &settings.parse_limits;
+    let (verify, mode) = (settings.verify_on_smudge, settings.smudge_mode);
+    let repo_configs = config::RepoConfigs::new(repo);
+    let cache = repo.map(SmudgeCache::open).filter(SmudgeCache::exists);
+    let signer = match repo {
+        Some(repo) if settings.attest => Some(Signer::from_config(&repo.config()?.snapshot()?)?),
+        _ => None,
+    };
+    let audit = repo.filter(|_| settings.audit).map(AuditLog::at);
+    let hooks = repo.map(Hooks::for_repo).transpose()?.flatten();
+    let lock = repo.map(Lockfile::load).transpose()?.flatten();
+    handshake(input, output)?;
+    loop {
+        // Between requests, so that no file is left half answered.
+        if shutdown::requested().is_some() {
+            break;
+        }
+        let Some(headers) = read_text_list(input)? else {
+            break;
+        };
+        let command = header(&headers, "command").unwrap_or_default();
+        let pathname = header(&headers, "pathname").unwrap_or_default();
+        let start = Instant::now();
+        let mut bytes = 0;
+        let content;
+        let result = match command {
+            "clean" => {
+                let raw = read_content(input)?;
+                bytes = raw.len() as u64;
+                let (stripped, failure) = match &hooks {
+                    Some(hooks) => match hooks.pre_clean_path(repo, pathname, raw) {
+                        Ok(stripped) => (stripped, None),
+                        Err(e) => (Vec::new(), Some(e)),
+                    },
+                    None => (raw, None),
+                };
+                content = stripped;
+                let provider = match failure {
+                    Some(e) => Err(e),
+                    None => config::language_for_path(repo, pathname, &content),
+                };
+                provider.and_then(|provider| {
+                    let repo_config = repo_configs.for_path(pathname)?;
+                    let previous = repo.and_then(|repo| indexed_blob(repo, pathname));
+                    let filtered = prepare_clean_from(
+                        &content,
+                        pathname,
+                        provider,
+                        limits,
+                        &repo_config,
+                        previous.as_deref(),
+                    )?;
+                    if let (Some(repo), Some(signer)) = (repo, &signer) {
+                        attest(repo, signer, &filtered)?;
+                    }
+                    Ok(filtered)
+                })
+            }
+            "smudge" if verify != VerifyOnSmudge::Off || mode != SmudgeMode::Canonical => {
+                content = read_content(input)?;
+                smudge_as(&content, pathname, verify, mode, repo)
+                    .map(|source| Filtered::Verbatim(source.into()))
+            }
+            "smudge" if cache.is_some() => {
+                content = read_content(input)?;
+                smudge_cached(&content, pathname, cache.as_ref().expect("checked above"))
+            }
+            "smudge" => {
+                let mut packets = PacketReader::new(input);
+                let result = prepare_smudge(&mut packets, pathname);
+                packets.drain()?;
+                result
+            }
+            other => {
+                read_content(input)?;
+                Err(Error::Driver(format!(
+                    "unsupported filter command {:?}",
+                    other
+                )))
+            }
+        };
+        // Checked once the content is read, so the protocol stays in step.
+        let result = match &lock {
+            Some(lock) => result.and_then(|filtered| {
+                lock.check_path(repo, pathname)?;
+                Ok(filtered)
+            }),
+            None => result,
+        };
+        let result = match (command, &hooks) {
+            ("smudge", Some(hooks)) => result.and_then(|filtered| {
+                let provider = match &filtered {
+                    Filtered::Source(provider, _) => Some(*provider),
+                    _ => None,
+                };
+                let source =
+                    hooks.post_smudge_path(repo, provider, pathname, filtered.to_vec()?)?;
+                Ok(Filtered::Verbatim(source.into()))
+            }),
+            _ => result,
+        };
+        let audited = audit
+            .as_ref()
+            .map(|_| audit_record(command, pathname, &headers, &result));
+        let mut failure = None;
+        let status = match result {
+            Ok(filtered) => {
+                write_packet(output, b"status=success\n")?;
+                write_flush(output)?;
+                let mut packets = PacketWriter::new(output);
+                let written = filtered
+                    .write_to(&mut packets)
+                    .and_then(|()| Ok(packets.finish()?));
+                write_flush(output)?;
+                match written {
+                    // Empty status list: keep "success".
+                    Ok(smudged) => {
+                        write_flush(output)?;
+                        if command == "smudge" {
+                            bytes = smudged;
+                        }
+                        "success"
+                    }
+                    Err(e) => {
+                        log_error!(
+                            "filter",
+                            "{} {} failed while writing: {}",
+                            command,
+                            pathname,
+                            e
+                        );
+                        failure = Some(e.to_string());
+                        write_packet(output, b"status=error\n")?;
+                        write_flush(output)?;
+                        "error"
+                    }
+                }
+            }
+            Err(e) => {
+                log_error!("filter", "{} {} failed: {}", command, pathname, e);
+                failure = Some(e.to_string());
+                write_packet(output, b"status=error\n")?;
+                write_flush(output)?;
+                "error"
+            }
+        };
+        output.flush()?;
+        let labels = [("command", command)];
+        telemetry::add(
+            "git_ast_filter_requests_total",
+            &[("command", command), ("status", status)],
+            1.0,
+        );
+        telemetry::add("git_ast_filter_bytes_total", &labels, bytes as f64);
+        telemetry::add(
+            "git_ast_filter_seconds_total",
+            &labels,
+            start.elapsed().as_secs_f64(),
+        );
+        if status == "error" {
+            telemetry::add(
+                "git_ast_errors_total",
+                &[("command", "filter-process")],
+                1.0,
+            );
+        }
+        if let Some(record) = audited {
+            audit::record(audit.as_ref(), record.finish(start, failure));
+        }
+    }
+    Ok(())
+}
+
+/// The audit record of a request: the blob stored by a clean (hashed here,
+/// as Git only learns it afterwards) or printed by a smudge (from Git's
+/// `blob` metadata), and the grammar used.
+fn audit_record(
+    command: &str,
+    pathname: &str,
+    headers: &[String],
+    result: &Result<Filtered<'_>, Error>,
+) -> audit::Record {
+    let mut record = audit::Record::new(command, pathname);
+    match result {
+        Ok(Filtered::Blob(header, _)) => record = record.grammar(&header.language, &header.grammar),
+        Ok(Filtered::Source(provider, _)) => {
+            record = record.grammar(provider.name, provider.grammar_version)
+        }
+        _ => {}
+    }
+    let blob = match (command, result) {
+        ("clean", Ok(filtered)) => filtered
+            .to_vec()
+            .ok()
+            .and_then(|content| git2::Oid::hash_object(git2::ObjectType::Blob, &content).ok())
+            .map(|id| id.to_string()),
+        _ => header(headers, "blob").map(str::to_string),
+    };
+    record.blobs.extend(blob);
+    record
+}
+
+/// The content of the blob staged for `pathname`, if any.
+fn indexed_blob(repo: &Repository, pathname: &str) -> Option<Vec<u8>> {
+    let index = repo.index().ok()?;
+    let entry = index.get_path(std::path::Path::new(pathname), 0)?;
+    Some(repo.find_blob(entry.id).ok()?.content().to_vec())
+}
+
+/// Performs the welcome and capability negotiation.
+fn handshake<R: Read, W: Write>(input: &mut R, output: &mut W) -> Result<(), Error> {
+    let welcome = read_text_list(input)?.unwrap_or_default();
+    if welcome.first().map(String::as_str) != Some("git-filter-client")
+        || !welcome.iter().any(|l| l == "version=2")
+    {
+        return Err(Error::Driver(format!(
+            "unexpected filter handshake: {:?}",
+            welcome
+        )));
+    }
+    write_packet(output, b"git-filter-server\n")?;
+    write_packet(output, b"version=2\n")?;
+    write_flush(output)?;
+    // Git waits for our version before sending capabilities.
+    output.flush()?;
+
+    let capabilities = read_text_list(input)?.unwrap_or_default();
+    for capability in ["clean", "smudge"] {
+        if capabilities
+            .iter()
+            .any(|c| c == &format!("capability={}", capability))
+        {
+            write_packet(output, format!("capability={}\n", capability).as_bytes())?;
+        }
+    }
+    write_flush(output)?;
+    output.flush()?;
+    Ok(())
+}
+
+fn header<'a>(headers: &'a [String], key: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find_map(|h| h.strip_prefix(key).and_then(|rest| rest.strip_prefix('=')))
+}
+
+/// Performs the 'clean' operation: source text -> serialized AST.
+///
+/// The language is chosen by extension (or, for extensionless files, by
+/// shebang/modeline); files without a language provider pass through
+/// unchanged.
+pub fn perform_clean(
+    input_content: &[u8],
+    pathname: &str,
+    limits: &ParseLimits,
+) -> Result<Vec<u8>, Error> {
+    clean_as(
+        input_content,
+        pathname,
+        languages::detect(pathname, input_content),
+        limits,
+    )
+}
+
+/// Like [`perform_clean`], with the language already resolved (e.g., by
+/// [`config::language_for_path`]). `None` passes the content through.
+///
+/// CRLF line endings are normalized to LF first: Git runs the clean filter
+/// before its own end-of-line conversion, so a Windows checkout with
+/// `core.autocrlf` hands over `\r\n` that would otherwise end up in comment
+/// and string tokens, and the same file would get a different blob there.
+/// Only a file whose every line ends in CRLF is converted; in any other
+/// file a `\r\n` is content (say, inside a string literal) and is kept.
+pub fn clean_as(
+    input_content: &[u8],
+    pathname: &str,
+    provider: Option<&'static LanguageProvider>,
+    limits: &ParseLimits,
+) -> Result<Vec<u8>, Error> {
+    clean_with(
+        input_content,
+        pathname,
+        provider,
+        limits,
+        &RepoConfig::default(),
+    )
+}
+
+/// Like [`clean_as`], applying the `[normalize.<language>]` rewrites of
+/// `repo_config` to the tree (see [`normalize`]).
+pub fn clean_with(
+    input_content: &[u8],
+    pathname: &str,
+    provider: Option<&'static LanguageProvider>,
+    limits: &ParseLimits,
+    repo_config: &RepoConfig,
+) -> Result<Vec<u8>, Error> {
+    prepare_clean(input_content, pathname, provider, limits, repo_config)?.to_vec()
+}
+
+/// The result of a clean or smudge, ready to be written out; producing the
+/// bytes is left to [`write_to`](Self::write_to) so they can be streamed.
+pub enum Filtered<'a> {
+    /// Content passed through unchanged.
+    Verbatim(std::borrow::Cow<'a, [u8]>),
+    /// A cleaned tree, written as an AST blob.
+    Blob(BlobHeader, ast::Node),
+    /// A smudged tree, written as canonical source.
+    Source(&'static LanguageProvider, ast::Node),
+}
+
+impl Filtered<'_> {
+    /// Writes the filtered content to `out`.
+    pub fn write_to<W: Write>(&self, mut out: W) -> Result<(), Error> {
+        match self {
+            Filtered::Verbatim(content) => out.write_all(content)?,
+            Filtered::Blob(header, root) => serialization::encode_to(header, root, out)?,
+            Filtered::Source(provider, root) => pretty_printing::print_to(root, provider, out)?,
+        }
+        Ok(())
+    }
+
+    /// The filtered content as bytes.
+    pub fn to_vec(&self) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::new();
+        self.write_to(&mut out)?;
+        Ok(out)
+    }
+}
+
+/// Like [`clean_with`], stopping short of serializing the tree.
+pub fn prepare_clean<'a>(
+    input_content: &'a [u8],
+    pathname: &str,
+    provider: Option<&'static LanguageProvider>,
+    limits: &ParseLimits,
+    repo_config: &RepoConfig,
+) -> Result<Filtered<'a>, Error> {
+    prepare_clean_from(input_content, pathname, provider, limits, repo_config, None)
+}
+
+/// Like [`prepare_clean`], reusing what it can of `previous`, the blob last
+/// stored for the file (see [Reusing the Previous Blob](self#reusing-the-previous-blob)).
+pub fn prepare_clean_from<'a>(
+    input_content: &'a [u8],
+    pathname: &str,
+    provider: Option<&'static LanguageProvider>,
+    limits: &ParseLimits,
+    repo_config: &RepoConfig,
+    previous: Option<&[u8]>,
+) -> Result<Filtered<'a>, Error> {
+    if let Some(blob) = already_cleaned(input_content, pathname)? {
+        return Ok(Filtered::Verbatim(blob.into()));
+    }
+    let Some(provider) = provider else {
+        return Ok(Filtered::Verbatim(input_content.into()));
+    };
+    if let Some(reason) = repo_config.passthrough_reason(pathname, input_content) {
+        log_info!("filter", "Storing {} as text: {}", pathname, reason);
+        return Ok(Filtered::Verbatim(input_content.into()));
+    }
+    log_info!("filter", "Cleaning path: {} ({})", pathname, provider.name);
+    let source = normalize_line_endings(input_content);
+    let source = without_provenance(&source, provider);
+    lint::enforce(source, pathname, provider, limits, &repo_config.lint)?;
+    let mut header = BlobHeader::for_provider(provider);
+    header.sparse = repo_config.is_sparse(pathname);
+    let syntax_errors = repo_config.storage.syntax_errors;
+    let reused = match previous {
+        Some(previous) => reuse(previous, &header, source, provider, limits, syntax_errors)?,
+        None => None,
+    };
+    let mut root = match reused {
+        Some(root) => root,
+        None => tree_for(&header, source, provider, limits, syntax_errors)?,
+    };
+    if !header.sparse {
+        normalize::apply(
+            &mut root,
+            provider,
+            &repo_config.normalize_for(provider.name),
+        );
+    }
+    if repo_config.storage.compression == Compression::Zstd {
+        header.compression = Some(compression::dictionary_id(provider));
+    }
+    Ok(Filtered::Blob(header, root))
+}
+
+/// `content` as the AST blob it already is, if it is one (see
+/// [Mixed Trees](self#mixed-trees)).
+fn already_cleaned(content: &[u8], pathname: &str) -> Result<Option<Vec<u8>>, Error> {
+    let body = content.strip_prefix(b"\xef\xbb\xbf").unwrap_or(content);
+    if !serialization::is_ast_blob(body) {
+        return Ok(None);
+    }
+    let blob = crate::drivers::sniff(content.to_vec());
+    serialization::decode(&blob).map_err(|e| {
+        Error::Serialization(format!(
+            "{}: looks like an AST blob but does not decode: {}",
+            pathname, e
+        ))
+    })?;
+    log_info!(
+        "filter",
+        "Storing {} unchanged: already an AST blob",
+        pathname
+    );
+    Ok(Some(blob))
+}
+
+/// `content` with its `\r\n` line endings replaced by `\n`, if every line
+/// ends in `\r\n`; otherwise `content` as it is (borrowed).
+pub fn normalize_line_endings(content: &[u8]) -> std::borrow::Cow<'_, [u8]> {
+    let mut newlines = content.iter().enumerate().filter(|&(_, &b)| b == b'\n');
+    let all_crlf = newlines.all(|(i, _)| i > 0 && content[i - 1] == b'\r');
+    if !all_crlf || !content.contains(&b'\n') {
+        return std::borrow::Cow::Borrowed(content);
+    }
+    let mut out = Vec::with_capacity(content.len());
+    for (i, &b) in content.iter().enumerate() {
+        if !(b == b'\r' && content.get(i + 1) == Some(&b'\n')) {
+            out.push(b);
+        }
+    }
+    std::borrow::Cow::Owned(out)
+}
+
+/// The tree stored for `source` under `header`: the full tree, or the
+/// skeleton of a sparse blob.
+fn tree_for(
+    header: &BlobHeader,
+    source: &[u8],
+    provider: &LanguageProvider,
+    limits: &ParseLimits,
+    syntax_errors: SyntaxErrors,
+) -> Result<ast::Node, Error> {
+    match header.sparse {
+        true => sparse::skeleton(source, provider, limits),
+        false => build_tree(source, provider, limits, syntax_errors),
+    }
+}
+
+/// The stored tree for `source` built with the help of `previous`, or
+/// `None` if the previous blob cannot help and a full clean is needed.
+fn reuse(
+    previous: &[u8],
+    header: &BlobHeader,
+    source: &[u8],
+    provider: &LanguageProvider,
+    limits: &ParseLimits,
+    syntax_errors: SyntaxErrors,
+) -> Result<Option<ast::Node>, Error> {
+    if header.sparse || !serialization::is_ast_blob(previous) {
+        return Ok(None);
+    }
+    let Ok((old_header, old_root)) = serialization::decode(previous) else {
+        return Ok(None);
+    };
+    if old_header.language != header.language
+        || old_header.grammar != header.grammar
+        || old_header.sparse
+        || (old_root.has_verbatim() && syntax_errors == SyntaxErrors::Reject)
+    {
+        return Ok(None);
+    }
+    if pretty_printing::print(&old_root, provider).as_bytes() == source {
+        telemetry::add("git_ast_clean_reuse_total", &[("reused", "file")], 1.0);
+        return Ok(Some(old_root));
+    }
+
+    let tree = parsing::parse(source, provider, limits)?;
+    if parsing::check_syntax(&tree).is_err() {
+        return Ok(None);
+    }
+    let (mut root, spans) = ast::from_tree_with_spans(&tree, source, provider)?;
+    let (old, new) = (&old_root.children, &mut root.children);
+    let prefix = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(a, b)| same_tokens(a, b))
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| same_tokens(a, b))
+        .count();
+    let changed = prefix..new.len() - suffix;
+    let leaves_before = |end: usize| {
+        new[..end]
+            .iter()
+            .map(|child| child.leaves().len())
+            .sum::<usize>()
+    };
+    let (first, last) = (leaves_before(changed.start), leaves_before(changed.end));
+    let within = match first < last {
+        true => spans[first].start..spans[last - 1].end,
+        false => 0..0,
+    };
+    let (new_len, old_len) = (new.len(), old.len());
+    new[..prefix].clone_from_slice(&old[..prefix]);
+    new[new_len - suffix..].clone_from_slice(&old[old_len - suffix..]);
+    log_info!("filter", "Reusing {} of {} items", prefix + suffix, new_len);
+    telemetry::add("git_ast_clean_reuse_total", &[("reused", "items")], 1.0);
+    if !within.is_empty() {
+        injections::attach_within(&mut root, &tree, source, provider, limits, within)?;
+    }
+    Ok(Some(root))
+}
+
+/// Whether two stored subtrees have the same tokens and layout, ignoring
+/// their injections.
+fn same_tokens(a: &ast::Node, b: &ast::Node) -> bool {
+    a.kind == b.kind
+        && a.named == b.named
+        && a.field == b.field
+        && a.text == b.text
+        && a.blank_line_before == b.blank_line_before
+        && a.own_line == b.own_line
+        && a.children.len() == b.children.len()
+        && a.children
+            .iter()
+            .zip(&b.children)
+            .all(|(a, b)| same_tokens(a, b))
+}
+
+/// Parses source into the stored tree, including embedded-language trees.
+///
+/// With [`SyntaxErrors::Verbatim`], a file with syntax errors is stored with
+/// the broken top-level items as text (see
+/// [Verbatim Regions](crate::ast#verbatim-regions)), but only if the printed
+/// file parses back into the same tree: an error that swallows different
+/// neighbours once the code around it is reformatted would not survive a
+/// checkout, and is rejected like any other.
+fn build_tree(
+    source: &[u8],
+    provider: &LanguageProvider,
+    limits: &ParseLimits,
+    syntax_errors: SyntaxErrors,
+) -> Result<ast::Node, Error> {
+    let tree = parsing::parse(source, provider, limits)?;
+    let Err(error) = parsing::check_syntax(&tree) else {
+        let mut root = ast::from_tree(&tree, source, provider)?;
+        injections::attach(&mut root, &tree, source, provider, limits, 0)?;
+        return Ok(root);
+    };
+    if syntax_errors == SyntaxErrors::Reject || tree.root_node().is_error() {
+        return Err(error);
+    }
+    let root = ast::from_tree_tolerant(&tree, source, provider)?;
+    let printed = pretty_printing::print(&root, provider);
+    let reparsed = parsing::parse(printed.as_bytes(), provider, limits)?;
+    if ast::from_tree_tolerant(&reparsed, printed.as_bytes(), provider)? != root {
+        return Err(error);
+    }
+    Ok(root)
+}
+
+/// Performs the 'smudge' operation: serialized AST -> source text.
+///
+/// Content that is not an AST blob (e.g., committed before the filter was
+/// enabled) passes through unchanged.
+pub fn perform_smudge(input_content: &[u8], pathname: &str) -> Result<Vec<u8>, Error> {
+    prepare_smudge(input_content, pathname)?.to_vec()
+}
+
+/// Like [`perform_smudge`], decoding the blob as it is read from `input`
+/// and stopping short of printing the tree.
+pub fn prepare_smudge<R: BufRead>(
+    mut input: R,
+    pathname: &str,
+) -> Result<Filtered<'static>, Error> {
+    let mut magic = Vec::new();
+    (&mut input)
+        .take(serialization::MAGIC.len() as u64 + 1)
+        .read_to_end(&mut magic)?;
+    if !serialization::is_ast_blob(&magic) {
+        input.read_to_end(&mut magic)?;
+        return Ok(Filtered::Verbatim(magic.into()));
+    }
+    log_info!("filter", "Smudging path: {}", pathname);
+    let (header, root) = serialization::decode_from(magic.as_slice().chain(input))?;
+    if header.sparse {
+        return Ok(Filtered::Verbatim(
+            sparse::source(&root)?.as_bytes().to_vec().into(),
+        ));
+    }
+    let provider = languages::by_name(&header.language).ok_or_else(|| {
+        Error::Serialization(format!("no language provider for {:?}", header.language))
+    })?;
+    Ok(Filtered::Source(provider, root))
+}
+
+/// Signs and records `filtered` if it is an AST blob.
+fn attest(repo: &Repository, signer: &Signer, filtered: &Filtered<'_>) -> Result<(), Error> {
+    let Filtered::Blob(header, _) = filtered else {
+        return Ok(());
+    };
+    let blob = git2::Oid::hash_object(git2::ObjectType::Blob, &filtered.to_vec()?)?;
+    attestation::record(repo, &signer.attest(blob, header)?)
+}
+
+/// A canonical smudge, answered from `cache` if it has the blob.
+fn smudge_cached<'a>(
+    blob: &'a [u8],
+    pathname: &str,
+    cache: &SmudgeCache,
+) -> Result<Filtered<'a>, Error> {
+    if serialization::is_ast_blob(blob) {
+        let cached = cache.get(git2::Oid::hash_object(git2::ObjectType::Blob, blob)?);
+        let result = if cached.is_some() { "hit" } else { "miss" };
+        telemetry::add(
+            "git_ast_smudge_cache_requests_total",
+            &[("result", result)],
+            1.0,
+        );
+        if let Some(source) = cached {
+            log_info!("filter", "Smudging path: {} (cached)", pathname);
+            return Ok(Filtered::Verbatim(source.into()));
+        }
+    }
+    prepare_smudge(blob, pathname)
+}
+
+/// Checks that an AST blob survives smudge followed by clean byte-for-byte,
+/// and that it was produced with the grammar version this build uses.
+///
+/// A blob failing either check would be rewritten on the next checkout and
+/// commit, so it should not leave the local repository.
+pub fn verify_round_trip(blob: &[u8], pathname: &str) -> Result<(), Error> {
+    let (header, root) = serialization::decode(blob)?;
+    let provider = provider_for(&header, pathname)?;
+    if header.grammar != provider.grammar_version {
+        return Err(Error::Verification(format!(
+            "{}: recorded with {} grammar {}, this build uses {}",
+            pathname, provider.name, header.grammar, provider.grammar_version
+        )));
+    }
+    round_trip(blob, &header, root, provider, pathname).map(|_| ())
+}
+
+/// Like [`perform_smudge`], also cleaning the printed source again and
+/// comparing it with `blob` (see [Verifying Checkouts](self#verifying-checkouts)).
+/// On a mismatch, `Warn` logs it and returns the source all the same.
+pub fn smudge_verified(
+    blob: &[u8],
+    pathname: &str,
+    verify: VerifyOnSmudge,
+) -> Result<Vec<u8>, Error> {
+    if !serialization::is_ast_blob(blob) {
+        return Ok(blob.to_vec());
+    }
+    log_info!("filter", "Smudging path: {} (verified)", pathname);
+    let (header, root) = serialization::decode(blob)?;
+    let provider = provider_for(&header, pathname)?;
+    match round_trip(blob, &header, root, provider, pathname) {
+        Err(Error::Verification(message)) if verify == VerifyOnSmudge::Warn => {
+            log_error!("filter", "warning: {}", message);
+            perform_smudge(blob, pathname)
+        }
+        result => result,
+    }
+}
+
+/// Smudges `blob` in `mode` (see [Smudge Modes](self#smudge-modes)),
+/// verifying the canonical output as `verify` says. `repo` holds the OID
+/// map for `Original`.
+pub fn smudge_as(
+    blob: &[u8],
+    pathname: &str,
+    verify: VerifyOnSmudge,
+    mode: SmudgeMode,
+    repo: Option<&Repository>,
+) -> Result<Vec<u8>, Error> {
+    if !serialization::is_ast_blob(blob) {
+        return Ok(blob.to_vec());
+    }
+    let id = git2::Oid::hash_object(git2::ObjectType::Blob, blob)?;
+    if mode == SmudgeMode::Original {
+        let source = match repo {
+            Some(repo) => mirror::lookup(repo, id)?
+                .filter(|c| c.side == mirror::Side::Source)
+                .map(|c| repo.find_blob(c.id))
+                .transpose()?,
+            None => None,
+        };
+        if let Some(source) = source {
+            log_info!(
+                "filter",
+                "Smudging path: {} (original {})",
+                pathname,
+                source.id()
+            );
+            return Ok(source.content().to_vec());
+        }
+        log_info!(
+            "filter",
+            "{}: no original source recorded, printing it",
+            pathname
+        );
+    }
+    let mut source = match verify {
+        VerifyOnSmudge::Off => perform_smudge(blob, pathname)?,
+        verify => smudge_verified(blob, pathname, verify)?,
+    };
+    if mode == SmudgeMode::Annotated {
+        let (header, _) = serialization::decode(blob)?;
+        let provider = provider_for(&header, pathname)?;
+        if !provider.syntax.line_comment.is_empty() && source.ends_with(b"\n") {
+            source.extend_from_slice(
+                format!(
+                    "{}{}blob {}, {} grammar {}, git-ast {}\n",
+                    provider.syntax.line_comment,
+                    PROVENANCE,
+                    id,
+                    header.language,
+                    header.grammar,
+                    env!("CARGO_PKG_VERSION")
+                )
+                .as_bytes(),
+            );
+        }
+    }
+    Ok(source)
+}
+
+/// `source` without a provenance comment on its last line.
+fn without_provenance<'a>(source: &'a [u8], provider: &LanguageProvider) -> &'a [u8] {
+    let marker = provider.syntax.line_comment;
+    let Some(body) = source.strip_suffix(b"\n") else {
+        return source;
+    };
+    let start = body.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    let last = &body[start..];
+    match !marker.is_empty()
+        && last.starts_with(marker.as_bytes())
+        && last[marker.len()..].starts_with(PROVENANCE.as_bytes())
+    {
+        true => &source[..start],
+        false => source,
+    }
+}
+
+fn provider_for(header: &BlobHeader, pathname: &str) -> Result<&'static LanguageProvider, Error> {
+    languages::by_name(&header.language).ok_or_else(|| {
+        Error::Verification(format!(
+            "{}: unknown language {:?}",
+            pathname, header.language
+        ))
+    })
+}
+
+/// Prints `root` (decoded from `blob`), cleans the source again and checks
+/// that this reproduces `blob` byte-for-byte; returns the source.
+fn round_trip(
+    blob: &[u8],
+    header: &BlobHeader,
+    root: ast::Node,
+    provider: &'static LanguageProvider,
+    pathname: &str,
+) -> Result<Vec<u8>, Error> {
+    let syntax_errors = match root.has_verbatim() {
+        true => SyntaxErrors::Verbatim,
+        false => SyntaxErrors::Reject,
+    };
+    let source = match header.sparse {
+        true => sparse::source(&root)?.as_bytes().to_vec(),
+        false => Filtered::Source(provider, root).to_vec()?,
+    };
+    let root = tree_for(
+        header,
+        &source,
+        provider,
+        &ParseLimits::default(),
+        syntax_errors,
+    )
+    .map_err(|e| {
+        Error::Verification(format!(
+            "{}: printed source does not parse: {}",
+            pathname, e
+        ))
+    })?;
+    if serialization::encode(header, &root)? != blob {
+        return Err(Error::Verification(format!(
+            "{}: blob does not round-trip through smudge and clean",
+            pathname
+        )));
+    }
+    Ok(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_plumbing::pktline::MAX_DATA;
+
+    /// The built-in settings, whatever the machine's Git config says.
+    fn defaults() -> config::Settings {
+        let config = git2::Config::new().unwrap();
+        config::Settings::resolve(&config, &Default::default(), |_| None).unwrap()
+    }
+
+    fn pkt(out: &mut Vec<u8>, text: &str) {
+        write_packet(out, text.as_bytes()).unwrap();
+    }
+
+    fn request(out: &mut Vec<u8>, command: &str, pathname: &str, content: &[u8]) {
+        pkt(out, &format!("command={}\n", command));
+        pkt(out, &format!("pathname={}\n", pathname));
+        write_flush(out).unwrap();
+        for chunk in content.chunks(MAX_DATA) {
+            write_packet(out, chunk).unwrap();
+        }
+        write_flush(out).unwrap();
+    }
+
+    fn handshake_input() -> Vec<u8> {
+        let mut input = Vec::new();
+        pkt(&mut input, "git-filter-client\n");
+        pkt(&mut input, "version=2\n");
+        write_flush(&mut input).unwrap();
+        pkt(&mut input, "capability=clean\n");
+        pkt(&mut input, "capability=smudge\n");
+        pkt(&mut input, "capability=delay\n");
+        write_flush(&mut input).unwrap();
+        input
+    }
+
+    #[test]
+    fn failing_path_gets_error_status_and_process_continues() {
+        let mut input = handshake_input();
+        request(&mut input, "clean", "broken.rs", b"fn main( {");
+        request(&mut input, "clean", "ok.rs", b"fn main() {}\n");
+
+        let mut output = Vec::new();
+        serve(&mut input.as_slice(), &mut output, &defaults(), None).unwrap();
+
+        let mut reader = output.as_slice();
+        assert_eq!(
+            read_text_list(&mut reader).unwrap().unwrap(),
+            ["git-filter-server", "version=2"]
+        );
+        assert_eq!(
+            read_text_list(&mut reader).unwrap().unwrap(),
+            ["capability=clean", "capability=smudge"]
+        );
+        assert_eq!(
+            read_text_list(&mut reader).unwrap().unwrap(),
+            ["status=error"]
+        );
+        assert_eq!(
+            read_text_list(&mut reader).unwrap().unwrap(),
+            ["status=success"]
+        );
+        assert!(serialization::is_ast_blob(
+            &read_content(&mut reader).unwrap()
+        ));
+        assert_eq!(
+            read_text_list(&mut reader).unwrap().unwrap(),
+            Vec::<String>::new()
+        );
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn large_files_stream_across_packets() {
+        let functions: Vec<String> = (0..3000)
+            .map(|i| format!("fn f{}() {{\n    g({});\n}}\n", i, i))
+            .collect();
+        let source = functions.join("\n");
+        let blob = perform_clean(source.as_bytes(), "big.rs", &ParseLimits::default()).unwrap();
+        assert!(blob.len() > 2 * MAX_DATA);
+        let mut input = handshake_input();
+        request(&mut input, "smudge", "big.rs", &blob);
+        request(
+            &mut input,
+            "smudge",
+            "bad.rs",
+            b"git-ast 1\nlanguage rust\n",
+        );
+        request(&mut input, "clean", "big.rs", source.as_bytes());
+
+        let mut output = Vec::new();
+        serve(&mut input.as_slice(), &mut output, &defaults(), None).unwrap();
+
+        let mut reader = output.as_slice();
+        read_text_list(&mut reader).unwrap();
+        read_text_list(&mut reader).unwrap();
+        assert_eq!(
+            read_text_list(&mut reader).unwrap().unwrap(),
+            ["status=success"]
+        );
+        assert_eq!(read_content(&mut reader).unwrap(), source.as_bytes());
+        assert_eq!(
+            read_text_list(&mut reader).unwrap().unwrap(),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            read_text_list(&mut reader).unwrap().unwrap(),
+            ["status=error"]
+        );
+        assert_eq!(
+            read_text_list(&mut reader).unwrap().unwrap(),
+            ["status=success"]
+        );
+        assert_eq!(read_content(&mut reader).unwrap(), blob);
+        assert_eq!(
+            read_text_list(&mut reader).unwrap().unwrap(),
+            Vec::<String>::new()
+        );
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn verified_smudge_catches_trees_that_do_not_print_back() {
+        let blob = perform_clean(b"fn f() {}\n", "a.rs", &ParseLimits::default()).unwrap();
+        let source = perform_smudge(&blob, "a.rs").unwrap();
+        assert_eq!(
+            smudge_verified(&blob, "a.rs", VerifyOnSmudge::Fail).unwrap(),
+            source
+        );
+
+        // A tree the printer cannot reproduce, as after printer drift.
+        fn rename(node: &mut ast::Node) {
+            match node.kind == "identifier" {
+                true => node.text = Some("f g".to_string()),
+                false => node.children.iter_mut().for_each(rename),
+            }
+        }
+        let (header, mut root) = serialization::decode(&blob).unwrap();
+        rename(&mut root);
+        let drifted = serialization::encode(&header, &root).unwrap();
+        let err = smudge_verified(&drifted, "a.rs", VerifyOnSmudge::Fail).unwrap_err();
+        assert!(matches!(err, Error::Verification(_)), "{}", err);
+        assert_eq!(
+            smudge_verified(&drifted, "a.rs", VerifyOnSmudge::Warn).unwrap(),
+            perform_smudge(&drifted, "a.rs").unwrap()
+        );
+    }
+
+    #[test]
+    fn content_that_already_is_an_ast_blob_is_stored_unchanged() {
+        let blob = perform_clean(b"fn f() {}\n", "a.rs", &ParseLimits::default()).unwrap();
+        assert_eq!(
+            perform_clean(&blob, "a.rs", &ParseLimits::default()).unwrap(),
+            blob
+        );
+        let crlf = String::from_utf8(blob.clone())
+            .unwrap()
+            .replace('\n', "\r\n");
+        assert_eq!(
+            perform_clean(crlf.as_bytes(), "a.rs", &ParseLimits::default()).unwrap(),
+            blob
+        );
+        let err =
+            perform_clean(b"git-ast 1\nnonsense\n", "a.rs", &ParseLimits::default()).unwrap_err();
+        assert!(matches!(err, Error::Serialization(_)), "{}", err);
+    }
+
+    #[test]
+    fn broken_items_are_kept_verbatim_when_configured() {
+        let source = "fn a( x:i32 ) {}\n\nfn wip(x: i32 {\n    let y = x +;\n}\n\nfn b() {   }\n";
+        let rust = languages::by_name("rust");
+        let limits = ParseLimits::default();
+        assert!(clean_as(source.as_bytes(), "a.rs", rust, &limits).is_err());
+
+        let mut config = RepoConfig::default();
+        config.storage.syntax_errors = SyntaxErrors::Verbatim;
+        let blob = clean_with(source.as_bytes(), "a.rs", rust, &limits, &config).unwrap();
+        let (_, root) = serialization::decode(&blob).unwrap();
+        assert!(root.has_verbatim());
+        assert_eq!(
+            String::from_utf8(perform_smudge(&blob, "a.rs").unwrap()).unwrap(),
+            "fn a(x: i32) {}\n\nfn wip(x: i32 {\n    let y = x +;\n}\n\nfn b() {}\n"
+        );
+        verify_round_trip(&blob, "a.rs").unwrap();
+    }
+
+    #[test]
+    fn reusing_the_previous_blob_stores_what_a_full_clean_would() {
+        let js = languages::by_name("javascript");
+        let limits = ParseLimits::default();
+        let config = RepoConfig::default();
+        let first = "function a() {\n  return /a+b/.test(x);\n}\n\nfunction b() {\n  return /c|d/.test(y);\n}\n";
+        let previous = clean_as(first.as_bytes(), "a.js", js, &limits).unwrap();
+        let printed = perform_smudge(&previous, "a.js").unwrap();
+        let (_, root) = serialization::decode(&previous).unwrap();
+        assert!(root.leaves().iter().any(|leaf| !leaf.injections.is_empty()));
+        let header = BlobHeader::for_provider(js.unwrap());
+        let reuse = |source: &[u8]| {
+            reuse(
+                &previous,
+                &header,
+                source,
+                js.unwrap(),
+                &limits,
+                SyntaxErrors::Reject,
+            )
+            .unwrap()
+        };
+        let cleaned = |source: &[u8]| {
+            prepare_clean_from(source, "a.js", js, &limits, &config, Some(&previous))
+                .unwrap()
+                .to_vec()
+                .unwrap()
+        };
+
+        assert!(reuse(&printed).is_some());
+        assert_eq!(cleaned(&printed), previous);
+        let edited = String::from_utf8(printed).unwrap().replace(
+            "\nfunction b",
+            "\nfunction  c(){ return /e[f]/.exec(z) }\n\nfunction b",
+        );
+        assert!(reuse(edited.as_bytes()).is_some());
+        assert_eq!(
+            cleaned(edited.as_bytes()),
+            clean_as(edited.as_bytes(), "a.js", js, &limits).unwrap()
+        );
+        assert!(reuse(b"function (\n").is_none());
+    }
+
+    #[test]
+    fn smudge_modes() {
+        let original = b"fn   f() {}\n";
+        let limits = ParseLimits::default();
+        let blob = perform_clean(original, "a.rs", &limits).unwrap();
+        let smudge =
+            |mode, repo| smudge_as(&blob, "a.rs", VerifyOnSmudge::Off, mode, repo).unwrap();
+
+        let annotated = String::from_utf8(smudge(SmudgeMode::Annotated, None)).unwrap();
+        let (printed, comment) = annotated.split_at(annotated.find("// ").unwrap());
+        assert_eq!(printed, "fn f() {}\n");
+        assert!(
+            comment.starts_with("// git-ast provenance: blob "),
+            "{}",
+            comment
+        );
+        assert_eq!(
+            perform_clean(annotated.as_bytes(), "a.rs", &limits).unwrap(),
+            blob
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        assert_eq!(
+            smudge(SmudgeMode::Original, Some(&repo)),
+            printed.as_bytes()
+        );
+        let ast = repo.blob(&blob).unwrap();
+        let source = repo.blob(original).unwrap();
+        let map = [(ast, format!("source {}", source))].into_iter().collect();
+        crate::git_plumbing::notes::write(&repo, mirror::MAP_REF, &map, "map").unwrap();
+        assert_eq!(smudge(SmudgeMode::Original, Some(&repo)), original);
+    }
+
+    #[test]
+    fn crlf_input_cleans_to_the_same_blob() {
+        let rust = crate::languages::by_name("rust");
+        let lf = b"// one\nfn a() -> &'static str {\n    \"x\"\n}\n";
+        let crlf = b"// one\r\nfn a() -> &'static str {\r\n    \"x\"\r\n}\r\n";
+        let limits = ParseLimits::default();
+        assert_eq!(
+            clean_as(crlf, "a.rs", rust, &limits).unwrap(),
+            clean_as(lf, "a.rs", rust, &limits).unwrap()
+        );
+
+        assert!(matches!(
+            normalize_line_endings(lf),
+            std::borrow::Cow::Borrowed(_)
+        ));
+        // A lone `\r` is content, not a line ending.
+        assert_eq!(&*normalize_line_endings(b"a\rb\r\n\r\r\n"), b"a\rb\n\r\n");
+        // So is a `\r\n` in a file with LF line endings.
+        assert!(matches!(
+            normalize_line_endings(b"a\r\nb\n"),
+            std::borrow::Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn crlf_inside_a_literal_survives_a_round_trip() {
+        let source = b"fn a() -> &'static str {\n    \"a\r\nb\"\n}\n";
+        let limits = ParseLimits::default();
+        let blob = perform_clean(source, "a.rs", &limits).unwrap();
+        assert_eq!(perform_smudge(&blob, "a.rs").unwrap(), source);
+    }
+}
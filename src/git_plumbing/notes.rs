@@ -0,0 +1,224 @@
+//! Git Notes Access
+//!
+//! `git-ast` keeps its per-object metadata in notes refs, so it travels with
+//! `git fetch`/`git push` of the ref and shows up in `git notes` and
+//! `git log --notes=<ref>`.
+//!
+//! Writes are batched: [`write`] and [`remove`] change any number of notes
+//! in a single notes commit, instead of one commit per note as `git notes add` (and
+//! `Repository::note`) would.
+//!
+//! ## `refs/notes/ast`
+//!
+//! Structured results that describe a commit (its fingerprint, how the merge
+//! driver handled a merge, ...) are attached to it in [`AST_NOTES_REF`]. The
+//! note is a JSON object with one section per producer, so producers do not
+//! overwrite each other (see [`attach`]):
+//!
+//! ```json
+//! {
+//!   "fingerprint": { "fingerprint": "8f0c...", "files": [ ... ] },
+//!   "merge": [ { "path": "src/lib.rs", "conflicts": [ ... ] } ]
+//! }
+//! ```
+//!
+//! Show them with `git log --notes=ast`; share them with
+//! `git push <remote> refs/notes/ast`.
+
+use crate::Error;
+use git2::{ErrorCode, Oid, Repository, Signature};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Notes ref for structured per-commit results.
+pub const AST_NOTES_REF: &str = "refs/notes/ast";
+
+/// Sets `section` of the JSON note in [`AST_NOTES_REF`] for each commit in
+/// `records`, keeping the note's other sections.
+pub fn attach(
+    repo: &Repository,
+    section: &str,
+    records: &BTreeMap<Oid, serde_json::Value>,
+) -> Result<(), Error> {
+    let mut notes = BTreeMap::new();
+    for (&commit, record) in records {
+        let mut note = match read(repo, AST_NOTES_REF, commit)? {
+            Some(text) => match serde_json::from_str(&text) {
+                Ok(serde_json::Value::Object(map)) => map,
+                _ => serde_json::Map::new(), // Not ours; replace it.
+            },
+            None => serde_json::Map::new(),
+        };
+        note.insert(section.to_string(), record.clone());
+        let text = serde_json::to_string_pretty(&note)
+            .map_err(|e| Error::Serialization(format!("{} note: {}", section, e)))?;
+        notes.insert(commit, text + "\n");
+    }
+    write(
+        repo,
+        AST_NOTES_REF,
+        &notes,
+        &format!("git-ast: {}", section),
+    )
+}
+
+/// The note attached to `target` in `notes_ref`, if any.
+pub fn read(repo: &Repository, notes_ref: &str, target: Oid) -> Result<Option<String>, Error> {
+    match repo.find_note(Some(notes_ref), target) {
+        Ok(note) => Ok(note.message().map(str::to_string)),
+        Err(e) if e.code() == ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Attaches (or replaces) the notes in `notes` to their target objects with
+/// one commit on `notes_ref`.
+pub fn write(
+    repo: &Repository,
+    notes_ref: &str,
+    notes: &BTreeMap<Oid, String>,
+    message: &str,
+) -> Result<(), Error> {
+    update(repo, notes_ref, notes, &BTreeSet::new(), message)
+}
+
+/// Removes the notes attached to `targets` with one commit on `notes_ref`.
+pub fn remove(
+    repo: &Repository,
+    notes_ref: &str,
+    targets: &BTreeSet<Oid>,
+    message: &str,
+) -> Result<(), Error> {
+    update(repo, notes_ref, &BTreeMap::new(), targets, message)
+}
+
+/// The objects that have a note in `notes_ref`.
+pub fn targets(repo: &Repository, notes_ref: &str) -> Result<Vec<Oid>, Error> {
+    let notes = match repo.notes(Some(notes_ref)) {
+        Ok(notes) => notes,
+        Err(e) if e.code() == ErrorCode::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    notes.map(|note| Ok(note?.1)).collect()
+}
+
+fn update(
+    repo: &Repository,
+    notes_ref: &str,
+    notes: &BTreeMap<Oid, String>,
+    removed: &BTreeSet<Oid>,
+    message: &str,
+) -> Result<(), Error> {
+    if notes.is_empty() && removed.is_empty() {
+        return Ok(());
+    }
+    let parent = match repo.find_reference(notes_ref) {
+        Ok(reference) => Some(reference.peel_to_commit()?),
+        Err(e) if e.code() == ErrorCode::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let base = parent.as_ref().map(|c| c.tree()).transpose()?;
+    let mut builder = repo.treebuilder(base.as_ref())?;
+    for target in notes.keys().chain(removed) {
+        let name = target.to_string();
+        // Git may have fanned existing notes out into `ab/cdef...` subtrees;
+        // a flat entry next to such a note would leave two notes behind.
+        if builder.get(&name[..2])?.is_some() {
+            remove_fanned_out(repo, &mut builder, &name)?;
+        }
+        if builder.get(&name)?.is_some() {
+            builder.remove(&name)?;
+        }
+    }
+    for (target, note) in notes {
+        builder.insert(target.to_string(), repo.blob(note.as_bytes())?, 0o100644)?;
+    }
+    let tree = repo.find_tree(builder.write()?)?;
+    let signature = signature(repo)?;
+    let parents: Vec<_> = parent.iter().collect();
+    repo.commit(
+        Some(notes_ref),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        &parents,
+    )?;
+    Ok(())
+}
+
+fn remove_fanned_out(
+    repo: &Repository,
+    builder: &mut git2::TreeBuilder<'_>,
+    name: &str,
+) -> Result<(), Error> {
+    let (dir, rest) = name.split_at(2);
+    let Some(subtree_id) = builder.get(dir)?.map(|entry| entry.id()) else {
+        return Ok(());
+    };
+    let Ok(subtree) = repo.find_tree(subtree_id) else {
+        return Ok(());
+    };
+    if subtree.get_name(rest).is_none() {
+        return Ok(());
+    }
+    let mut sub = repo.treebuilder(Some(&subtree))?;
+    sub.remove(rest)?;
+    if sub.is_empty() {
+        builder.remove(dir)?;
+    } else {
+        let id = sub.write()?;
+        builder.insert(dir, id, 0o040000)?;
+    }
+    Ok(())
+}
+
+/// The configured identity, or a fixed one for unconfigured (CI) clones.
+pub fn signature(repo: &Repository) -> Result<Signature<'static>, Error> {
+    match repo.signature() {
+        Ok(signature) => Ok(signature.to_owned()),
+        Err(_) => Ok(Signature::now("git-ast", "git-ast@localhost")?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_write_and_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let a = repo.blob(b"a").unwrap();
+        let b = repo.blob(b"b").unwrap();
+        let notes_ref = "refs/notes/test";
+        assert_eq!(read(&repo, notes_ref, a).unwrap(), None);
+
+        let batch = BTreeMap::from([(a, "first\n".to_string()), (b, "second\n".to_string())]);
+        write(&repo, notes_ref, &batch, "add").unwrap();
+        write(
+            &repo,
+            notes_ref,
+            &BTreeMap::from([(a, "again\n".to_string())]),
+            "update",
+        )
+        .unwrap();
+        assert_eq!(
+            read(&repo, notes_ref, a).unwrap().as_deref(),
+            Some("again\n")
+        );
+        assert_eq!(
+            read(&repo, notes_ref, b).unwrap().as_deref(),
+            Some("second\n")
+        );
+
+        remove(&repo, notes_ref, &BTreeSet::from([b]), "drop").unwrap();
+        assert_eq!(read(&repo, notes_ref, b).unwrap(), None);
+        assert_eq!(targets(&repo, notes_ref).unwrap(), [a]);
+
+        attach(&repo, "one", &BTreeMap::from([(a, serde_json::json!(1))])).unwrap();
+        attach(&repo, "two", &BTreeMap::from([(a, serde_json::json!([2]))])).unwrap();
+        let note = read(&repo, AST_NOTES_REF, a).unwrap().unwrap();
+        let note: serde_json::Value = serde_json::from_str(&note).unwrap();
+        assert_eq!(note, serde_json::json!({ "one": 1, "two": [2] }));
+    }
+}
@@ -0,0 +1,373 @@
+//! pkt-line Framing
+//!
+//! Git's protocols (the long-running filter process, the wire protocol,
+//! remote helpers' `stateless-connect`) frame their messages as pkt-lines:
+//! four hex digits giving the length of the line including themselves, then
+//! the data. A few lengths below four are special packets instead:
+//!
+//! | Header | Packet | Meaning |
+//! |--------|--------|---------|
+//! | `0000` | [`Packet::Flush`] | End of a list or of a stream of content. |
+//! | `0001` | [`Packet::Delim`] | Section separator (protocol v2). |
+//! | `0002` | [`Packet::ResponseEnd`] | End of a response (protocol v2). |
+//! | `0003` | - | Invalid. |
+//! | `0004` | empty [`Packet::Data`] | Allowed, though senders should avoid it. |
+//!
+//! A data packet carries at most [`MAX_DATA`] bytes. Text packets end in
+//! `\n`, which [`read_text_list`] strips; content longer than one packet is
+//! split across several and ends with a flush ([`PacketWriter`],
+//! [`PacketReader`]).
+//!
+//! See `gitprotocol-common(5)` for the format and
+//! `gitprotocol-v2(5)` for the special packets.
+
+use crate::Error;
+use std::io::{BufRead, Read, Write};
+
+/// Largest payload Git accepts in a single pkt-line.
+pub const MAX_DATA: usize = 65516;
+
+/// One pkt-line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    Data(Vec<u8>),
+    Flush,
+    Delim,
+    ResponseEnd,
+}
+
+/// Reads one packet; `None` if `input` ends before its header.
+pub fn read_packet<R: Read>(input: &mut R) -> Result<Option<Packet>, Error> {
+    let mut header = [0u8; 4];
+    let mut filled = 0;
+    while filled < header.len() {
+        match input.read(&mut header[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => {
+                return Err(Error::Driver(format!(
+                    "pkt-line header cut short: {:?}",
+                    String::from_utf8_lossy(&header[..filled])
+                )))
+            }
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let len = std::str::from_utf8(&header)
+        .ok()
+        .filter(|s| s.bytes().all(|b| b.is_ascii_hexdigit()))
+        .and_then(|s| usize::from_str_radix(s, 16).ok())
+        .ok_or_else(|| Error::Driver(format!("invalid pkt-line length {:?}", header)))?;
+    match len {
+        0 => Ok(Some(Packet::Flush)),
+        1 => Ok(Some(Packet::Delim)),
+        2 => Ok(Some(Packet::ResponseEnd)),
+        3 => Err(Error::Driver("unsupported pkt-line length 3".to_string())),
+        _ if len - 4 > MAX_DATA => Err(Error::Driver(format!(
+            "pkt-line length {} over the limit of {}",
+            len,
+            MAX_DATA + 4
+        ))),
+        _ => {
+            let mut data = vec![0u8; len - 4];
+            input.read_exact(&mut data)?;
+            Ok(Some(Packet::Data(data)))
+        }
+    }
+}
+
+/// Reads one packet of a flush-terminated list: `None` for the flush.
+/// Delimiters, response ends and the end of input are errors.
+pub fn read_data<R: Read>(input: &mut R) -> Result<Option<Vec<u8>>, Error> {
+    match read_packet(input)? {
+        Some(Packet::Data(data)) => Ok(Some(data)),
+        Some(Packet::Flush) => Ok(None),
+        Some(packet) => Err(Error::Driver(format!("unexpected {:?} packet", packet))),
+        None => Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()),
+    }
+}
+
+/// Reads text packets up to the next flush, without their trailing `\n`.
+/// Returns `None` if `input` ends before the first packet.
+pub fn read_text_list<R: Read>(input: &mut R) -> Result<Option<Vec<String>>, Error> {
+    let mut lines = Vec::new();
+    loop {
+        let packet = match read_packet(input)? {
+            None if lines.is_empty() => return Ok(None),
+            None => return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()),
+            Some(packet) => packet,
+        };
+        match packet {
+            Packet::Flush => return Ok(Some(lines)),
+            Packet::Data(data) => {
+                let text = String::from_utf8_lossy(&data);
+                lines.push(text.strip_suffix('\n').unwrap_or(&text).to_string());
+            }
+            packet => return Err(Error::Driver(format!("unexpected {:?} packet", packet))),
+        }
+    }
+}
+
+/// Reads binary content packets up to the next flush.
+pub fn read_content<R: Read>(input: &mut R) -> Result<Vec<u8>, Error> {
+    let mut content = Vec::new();
+    PacketReader::new(input).read_to_end(&mut content)?;
+    Ok(content)
+}
+
+/// Writes one data packet.
+pub fn write_packet<W: Write>(output: &mut W, data: &[u8]) -> Result<(), Error> {
+    if data.len() > MAX_DATA {
+        return Err(Error::Driver(format!(
+            "pkt-line of {} bytes over the limit of {}",
+            data.len(),
+            MAX_DATA
+        )));
+    }
+    write!(output, "{:04x}", data.len() + 4)?;
+    output.write_all(data)?;
+    Ok(())
+}
+
+/// Writes `text` and a `\n` as one data packet.
+pub fn write_text<W: Write>(output: &mut W, text: &str) -> Result<(), Error> {
+    write_packet(output, format!("{}\n", text).as_bytes())
+}
+
+pub fn write_flush<W: Write>(output: &mut W) -> Result<(), Error> {
+    output.write_all(b"0000")?;
+    Ok(())
+}
+
+pub fn write_delim<W: Write>(output: &mut W) -> Result<(), Error> {
+    output.write_all(b"0001")?;
+    Ok(())
+}
+
+pub fn write_response_end<W: Write>(output: &mut W) -> Result<(), Error> {
+    output.write_all(b"0002")?;
+    Ok(())
+}
+
+/// Reads the content packets up to the next flush as one stream.
+pub struct PacketReader<'a, R> {
+    input: &'a mut R,
+    packet: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<'a, R: Read> PacketReader<'a, R> {
+    pub fn new(input: &'a mut R) -> Self {
+        PacketReader {
+            input,
+            packet: Vec::new(),
+            pos: 0,
+            done: false,
+        }
+    }
+
+    /// Skips whatever content was not read, up to the flush.
+    pub fn drain(mut self) -> Result<(), Error> {
+        while !self.done {
+            self.done = read_data(self.input)?.is_none();
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for PacketReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for PacketReader<'_, R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        while self.pos == self.packet.len() && !self.done {
+            match read_data(self.input) {
+                Ok(Some(data)) => {
+                    self.packet = data;
+                    self.pos = 0;
+                }
+                Ok(None) => self.done = true,
+                Err(Error::Io(e)) => return Err(e),
+                Err(e) => return Err(std::io::Error::other(e.to_string())),
+            }
+        }
+        Ok(&self.packet[self.pos..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.packet.len());
+    }
+}
+
+/// Writes a stream as content packets of at most [`MAX_DATA`] bytes;
+/// [`finish`](Self::finish) sends the rest (the caller writes the flush).
+pub struct PacketWriter<'a, W: Write> {
+    output: &'a mut W,
+    buffer: Vec<u8>,
+    written: u64,
+}
+
+impl<'a, W: Write> PacketWriter<'a, W> {
+    pub fn new(output: &'a mut W) -> Self {
+        PacketWriter {
+            output,
+            buffer: Vec::with_capacity(MAX_DATA),
+            written: 0,
+        }
+    }
+
+    fn send(&mut self, len: usize) -> std::io::Result<()> {
+        write!(self.output, "{:04x}", len + 4)?;
+        self.output.write_all(&self.buffer[..len])?;
+        self.buffer.drain(..len);
+        Ok(())
+    }
+
+    /// Sends what is left; returns the number of content bytes written.
+    pub fn finish(mut self) -> std::io::Result<u64> {
+        if !self.buffer.is_empty() {
+            self.send(self.buffer.len())?;
+        }
+        Ok(self.written)
+    }
+}
+
+impl<W: Write> Write for PacketWriter<'_, W> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let n = data.len().min(MAX_DATA - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..n]);
+        self.written += n as u64;
+        if self.buffer.len() == MAX_DATA {
+            self.send(MAX_DATA)?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packets(mut input: &[u8]) -> Result<Vec<Packet>, Error> {
+        let mut out = Vec::new();
+        while let Some(packet) = read_packet(&mut input)? {
+            out.push(packet);
+        }
+        Ok(out)
+    }
+
+    #[test]
+    fn round_trips_every_kind_of_packet() {
+        let mut out = Vec::new();
+        write_text(&mut out, "command=ls-refs").unwrap();
+        write_delim(&mut out).unwrap();
+        write_packet(&mut out, b"").unwrap();
+        write_packet(&mut out, &[0, 0xff]).unwrap();
+        write_flush(&mut out).unwrap();
+        write_response_end(&mut out).unwrap();
+        assert_eq!(
+            out,
+            b"0014command=ls-refs\n000100040006\x00\xff00000002".as_slice()
+        );
+        assert_eq!(
+            packets(&out).unwrap(),
+            [
+                Packet::Data(b"command=ls-refs\n".to_vec()),
+                Packet::Delim,
+                Packet::Data(Vec::new()),
+                Packet::Data(vec![0, 0xff]),
+                Packet::Flush,
+                Packet::ResponseEnd,
+            ]
+        );
+        // Hex digits in either case.
+        assert_eq!(
+            packets(b"000Ahello\n").unwrap(),
+            [Packet::Data(b"hello\n".to_vec())]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_and_oversized_packets() {
+        for bad in [
+            b"0003".as_slice(),
+            b"00g4",
+            b"+004",
+            b" 004",
+            b"fff1",
+            b"0008ab",
+            b"00",
+        ] {
+            assert!(packets(bad).is_err(), "{:?}", String::from_utf8_lossy(bad));
+        }
+        let max = vec![b'x'; MAX_DATA];
+        let mut out = Vec::new();
+        write_packet(&mut out, &max).unwrap();
+        assert_eq!(&out[..4], b"fff0");
+        assert_eq!(packets(&out).unwrap(), [Packet::Data(max.clone())]);
+        assert!(write_packet(&mut Vec::new(), &[max.as_slice(), b"x"].concat()).is_err());
+
+        // Lists end at a flush; anything else is out of place in them.
+        let mut list = b"0006a\n0001".as_slice();
+        assert!(read_text_list(&mut list).is_err());
+        let mut cut = b"0006a\n".as_slice();
+        assert!(read_text_list(&mut cut).is_err());
+        assert_eq!(read_text_list(&mut b"".as_slice()).unwrap(), None);
+    }
+
+    #[test]
+    fn streams_content_across_packets() {
+        let content: Vec<u8> = (0..2 * MAX_DATA + 10).map(|i| (i % 251) as u8).collect();
+        let mut out = Vec::new();
+        let mut writer = PacketWriter::new(&mut out);
+        for chunk in content.chunks(1000) {
+            writer.write_all(chunk).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), content.len() as u64);
+        write_flush(&mut out).unwrap();
+        write_text(&mut out, "status=success").unwrap();
+        write_flush(&mut out).unwrap();
+        let sizes: Vec<usize> = packets(&out)
+            .unwrap()
+            .iter()
+            .map(|p| match p {
+                Packet::Data(data) => data.len(),
+                _ => 0,
+            })
+            .collect();
+        assert_eq!(sizes, [MAX_DATA, MAX_DATA, 10, 0, 15, 0]);
+
+        let mut input = out.as_slice();
+        assert_eq!(read_content(&mut input).unwrap(), content);
+        assert_eq!(
+            read_text_list(&mut input).unwrap().unwrap(),
+            ["status=success"]
+        );
+        assert!(input.is_empty());
+
+        // Content left unread is skipped up to its flush.
+        let mut input = out.as_slice();
+        let mut reader = PacketReader::new(&mut input);
+        let mut start = [0u8; 3];
+        reader.read_exact(&mut start).unwrap();
+        assert_eq!(start, content[..3]);
+        reader.drain().unwrap();
+        assert_eq!(
+            read_text_list(&mut input).unwrap().unwrap(),
+            ["status=success"]
+        );
+    }
+}
@@ -0,0 +1,272 @@
+//! Embedded-Language Injections
+//!
+//! Source files routinely contain code in a second language: regular
+//! expressions in string literals, HTML and CSS in tagged template strings.
+//! To the host grammar those are opaque tokens. Injections parse them with
+//! their own grammar and attach the nested tree to the token, so structural
+//! diffs and queries can see inside.
+//!
+//! ## Injection Queries
+//!
+//! Each [`LanguageProvider`] may carry an injection query in the usual
+//! Tree-sitter format:
+//!
+//! -   `@injection.content`: the node whose text is the embedded code.
+//! -   `#set! injection.language "<name>"`, or an `@injection.language`
+//!     capture whose text names the language.
+//! -   `#offset! @injection.content 0 <start> 0 <end>`: byte adjustments of
+//!     the content range (e.g. to drop the backticks of a template string).
+//!
+//! ## Storage
+//!
+//! Injected trees are annotations: they are attached to the atomic leaf
+//! (string literal, comment) that contains the content range, and the leaf
+//! keeps its exact text. The printer never looks at them, so injections can
+//! not change the checked-out source. Content that does not parse cleanly in
+//! the embedded language (template placeholders, partial snippets) is simply
+//! left without an injection.
+
+use crate::ast::{self, Node};
+use crate::languages::{self, LanguageProvider};
+use crate::parsing::{self, ParseLimits};
+use crate::Error;
+use std::ops::Range;
+use tree_sitter::{Query, QueryCursor, QueryPredicateArg, StreamingIterator, Tree};
+
+/// How deep injections may nest (an injection inside an injection ...).
+const MAX_DEPTH: usize = 4;
+
+/// An embedded-language tree attached to a leaf.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Injected {
+    /// Name of the embedded language's provider.
+    pub language: String,
+    /// Byte range of the embedded code within the leaf's text.
+    pub range: Range<usize>,
+    pub root: Node,
+}
+
+/// An injection site found in a parsed tree.
+#[derive(Debug)]
+pub struct Site {
+    /// Byte range of the embedded code within the host source.
+    pub range: Range<usize>,
+    pub provider: &'static LanguageProvider,
+}
+
+/// Finds the injection sites of `tree` using the provider's injection query.
+pub fn find(tree: &Tree, source: &[u8], provider: &LanguageProvider) -> Result<Vec<Site>, Error> {
+    find_within(tree, source, provider, 0..source.len())
+}
+
+/// Like [`find`], only returning sites inside the byte range `within`.
+fn find_within(
+    tree: &Tree,
+    source: &[u8],
+    provider: &LanguageProvider,
+    within: Range<usize>,
+) -> Result<Vec<Site>, Error> {
+    if provider.injections.is_empty() {
+        return Ok(Vec::new());
+    }
+    let query = Query::new(&provider.ts_language(), provider.injections)
+        .map_err(|e| Error::Parsing(format!("invalid {} injection query: {}", provider.name, e)))?;
+    let Some(content_idx) = query.capture_index_for_name("injection.content") else {
+        return Ok(Vec::new());
+    };
+    let language_idx = query.capture_index_for_name("injection.language");
+
+    let mut sites = Vec::new();
+    let mut cursor = QueryCursor::new();
+    cursor.set_byte_range(within.clone());
+    let mut matches = cursor.matches(&query, tree.root_node(), source);
+    while let Some(m) = matches.next() {
+        let Some(content) = m.captures.iter().find(|c| c.index == content_idx) else {
+            continue;
+        };
+        let named = query
+            .property_settings(m.pattern_index)
+            .iter()
+            .find(|p| &*p.key == "injection.language")
+            .and_then(|p| p.value.as_deref().map(str::to_string));
+        let captured = language_idx.and_then(|idx| {
+            let node = m.captures.iter().find(|c| c.index == idx)?.node;
+            std::str::from_utf8(&source[node.byte_range()])
+                .ok()
+                .map(|s| s.trim().to_string())
+        });
+        let Some(target) = named.or(captured).and_then(|n| languages::by_name(&n)) else {
+            continue;
+        };
+
+        let mut range = content.node.byte_range();
+        for predicate in query.general_predicates(m.pattern_index) {
+            if &*predicate.operator != "offset!" {
+                continue;
+            }
+            if let [QueryPredicateArg::Capture(idx), _, QueryPredicateArg::String(start), _, QueryPredicateArg::String(end)] =
+                &predicate.args[..]
+            {
+                if *idx == content_idx {
+                    let start: isize = start.parse().unwrap_or(0);
+                    let end: isize = end.parse().unwrap_or(0);
+                    range.start = range.start.saturating_add_signed(start);
+                    range.end = range.end.saturating_add_signed(end);
+                }
+            }
+        }
+        if range.start < range.end && within.start <= range.start && range.end <= within.end {
+            sites.push(Site {
+                range,
+                provider: target,
+            });
+        }
+    }
+    Ok(sites)
+}
+
+/// Attaches parsed injections to the leaves of `root` that contain them.
+///
+/// `root` must have been built from `tree` and `source`; `depth` is the
+/// current nesting level (0 for a file).
+pub fn attach(
+    root: &mut Node,
+    tree: &Tree,
+    source: &[u8],
+    provider: &LanguageProvider,
+    limits: &ParseLimits,
+    depth: usize,
+) -> Result<(), Error> {
+    if depth >= MAX_DEPTH {
+        return Ok(());
+    }
+    attach_sites(
+        root,
+        tree,
+        source,
+        limits,
+        find(tree, source, provider)?,
+        depth,
+        provider,
+    )
+}
+
+/// Like [`attach`] for a file, leaving out the sites outside the byte range
+/// `within` (whose leaves already carry their injections).
+pub fn attach_within(
+    root: &mut Node,
+    tree: &Tree,
+    source: &[u8],
+    provider: &LanguageProvider,
+    limits: &ParseLimits,
+    within: Range<usize>,
+) -> Result<(), Error> {
+    let sites = find_within(tree, source, provider, within)?;
+    attach_sites(root, tree, source, limits, sites, 0, provider)
+}
+
+fn attach_sites(
+    root: &mut Node,
+    tree: &Tree,
+    source: &[u8],
+    limits: &ParseLimits,
+    sites: Vec<Site>,
+    depth: usize,
+    provider: &LanguageProvider,
+) -> Result<(), Error> {
+    if sites.is_empty() {
+        return Ok(());
+    }
+    // Walk the leaves in source order alongside their byte offsets.
+    let mut offset_leaves = Vec::new();
+    let mut cursor = tree.walk();
+    collect_leaf_ranges(&mut cursor, provider, &mut offset_leaves);
+    let mut leaves = root.leaves_mut();
+    if leaves.len() != offset_leaves.len() {
+        return Ok(()); // Tree and node shape disagree; nothing sensible to attach.
+    }
+    for site in sites {
+        let Some(i) = offset_leaves
+            .iter()
+            .position(|r| r.start <= site.range.start && site.range.end <= r.end)
+        else {
+            continue;
+        };
+        let leaf_start = offset_leaves[i].start;
+        let code = &source[site.range.clone()];
+        let Ok(nested) = parsing::parse(code, site.provider, limits) else {
+            continue;
+        };
+        if parsing::check_syntax(&nested).is_err() {
+            continue;
+        }
+        let mut nested_root = ast::from_tree(&nested, code, site.provider)?;
+        attach(
+            &mut nested_root,
+            &nested,
+            code,
+            site.provider,
+            limits,
+            depth + 1,
+        )?;
+        leaves[i].injections.push(Injected {
+            language: site.provider.name.to_string(),
+            range: site.range.start - leaf_start..site.range.end - leaf_start,
+            root: nested_root,
+        });
+    }
+    Ok(())
+}
+
+/// Byte ranges of the nodes [`ast::from_tree`] turns into leaves, in order.
+fn collect_leaf_ranges(
+    cursor: &mut tree_sitter::TreeCursor<'_>,
+    provider: &LanguageProvider,
+    out: &mut Vec<Range<usize>>,
+) {
+    let node = cursor.node();
+    if node.child_count() == 0 || provider.syntax.atomic.contains(&node.kind()) {
+        out.push(node.byte_range());
+        return;
+    }
+    if cursor.goto_first_child() {
+        loop {
+            let child = cursor.node();
+            if !provider.syntax.skip.contains(&child.kind()) && !child.is_missing() {
+                collect_leaf_ranges(cursor, provider, out);
+            }
+            if !cursor.goto_next_sibling() {
+                break;
+            }
+        }
+        cursor.goto_parent();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_regex_in_python_re_calls() {
+        let source = b"import re\nWORD = re.compile(r\"\\w+\")\nname = \"\\w+\"\n";
+        let provider = languages::by_name("python").unwrap();
+        let limits = ParseLimits::default();
+        let tree = parsing::parse(source, provider, &limits).unwrap();
+        let mut root = ast::from_tree(&tree, source, provider).unwrap();
+        attach(&mut root, &tree, source, provider, &limits, 0).unwrap();
+
+        let injected: Vec<_> = root
+            .leaves()
+            .into_iter()
+            .filter(|l| !l.injections.is_empty())
+            .collect();
+        assert_eq!(injected.len(), 1, "only the re.compile argument is a regex");
+        let injection = &injected[0].injections[0];
+        assert_eq!(injection.language, "regex");
+        assert_eq!(
+            &injected[0].text.as_deref().unwrap()[injection.range.clone()],
+            "\\w+"
+        );
+    }
+}
@@ -0,0 +1,893 @@
+//! Language Providers
+//!
+//! A language provider bundles everything `git-ast` needs to know about one
+//! programming language: the Tree-sitter grammar, the grammar version that
+//! ends up recorded alongside stored ASTs, the file extensions that route
+//! a path to it, and the [`SyntaxRules`] that drive conversion to the stored
+//! tree and canonical pretty-printing.
+//!
+//! Providers are registered statically in [`all`]. Lookup is by path
+//! ([`for_path`]), by name ([`by_name`]), or by content ([`for_content`]).
+//!
+//! ## Detection Without an Extension
+//!
+//! Scripts often have no extension. For those, [`detect`] falls back to the
+//! file's first line and modelines:
+//!
+//! -   **Shebang:** `#!/usr/bin/env python3`, `#!/usr/bin/node`: the
+//!     interpreter's name, minus any version suffix, is looked up in each
+//!     provider's `interpreters`.
+//! -   **Vim modeline** (first or last five lines): `vim: set ft=python:`,
+//!     `vi: filetype=rust`.
+//! -   **Emacs file variables** (first line, or second after a shebang):
+//!     `-*- mode: python -*-` or `-*- python -*-`.
+//!
+//! Modeline names are matched against each provider's `aliases`. Whatever is
+//! detected is recorded as the `language` of the stored blob, so smudging
+//! never has to repeat the guess.
+//!
+//! ## Adding a Language
+//!
+//! 1.  Add the `tree-sitter-<lang>` grammar crate to `Cargo.toml`.
+//! 2.  Add a [`LanguageProvider`] entry to the registry below, listing its
+//!     string/comment kinds and block structure in [`SyntaxRules`], and its
+//!     functions, parameters, imports and comments in its `roles` query.
+//! 3.  Add real-world fixtures under `testdata/<lang>/` and bless them
+//!     (see [`selftest`](crate::commands::selftest)).
+//!
+//! Languages that only occur embedded in other files (regular expressions,
+//! HTML and CSS inside JavaScript templates) are registered without
+//! extensions; host providers reach them through their injection queries.
+
+use std::path::Path;
+
+/// Static description of a supported language.
+#[derive(Debug)]
+pub struct LanguageProvider {
+    /// Canonical language name (e.g., `rust`), used in config and blob headers.
+    pub name: &'static str,
+    /// File extensions (without the dot) handled by this provider.
+    pub extensions: &'static [&'static str],
+    /// Version of the grammar crate; changes whenever the tree shape may change.
+    pub grammar_version: &'static str,
+    /// Interpreter names that select this provider from a shebang line.
+    pub interpreters: &'static [&'static str],
+    /// Other names for the language, as used in editor modelines.
+    pub aliases: &'static [&'static str],
+    /// Node-kind tables for tree conversion and printing.
+    pub syntax: SyntaxRules,
+    /// Tree-sitter query locating embedded code (see [`injections`](crate::injections)).
+    pub injections: &'static str,
+    /// Tree-sitter query mapping nodes to language-agnostic roles (see
+    /// [`syntax`](crate::syntax)).
+    pub roles: &'static str,
+    language: fn() -> tree_sitter::Language,
+}
+
+/// Per-language node-kind tables used by [`ast`](crate::ast),
+/// [`pretty_printing`](crate::pretty_printing) and
+/// [`normalize`](crate::normalize).
+///
+/// The printer is generic; these tables are what make its output idiomatic
+/// (and, for whitespace-sensitive languages, correct).
+#[derive(Debug)]
+pub struct SyntaxRules {
+    /// Kinds stored as a single leaf with their exact source text (string
+    /// literals, comments), because their inner whitespace is significant.
+    pub atomic: &'static [&'static str],
+    /// Pure layout nodes dropped when cleaning (e.g. line continuations).
+    pub skip: &'static [&'static str],
+    /// Comment kinds. Comments not starting with `/*` run to end of line.
+    pub comments: &'static [&'static str],
+    /// Top-level kinds: children go one per line, without indentation.
+    pub roots: &'static [&'static str],
+    /// Statement containers: children go one per line, indented one level.
+    pub blocks: &'static [&'static str],
+    /// Kinds (or keywords) that always begin on a fresh line (e.g. Python
+    /// `else:` clauses).
+    pub line_start: &'static [&'static str],
+    /// Kinds whose first token attaches to the previous one (`f(`, `Vec<`).
+    pub glued: &'static [&'static str],
+    /// Kinds whose direct children are printed without separating spaces.
+    pub tight: &'static [&'static str],
+    /// One level of indentation.
+    pub indent: &'static str,
+    /// Import statements, which [`normalize`](crate::normalize) may sort
+    /// within a paragraph.
+    pub imports: &'static [&'static str],
+    /// Lists inside an import whose items may be sorted (`use a::{b, c}`).
+    pub import_lists: &'static [&'static str],
+    /// What starts a comment running to the end of the line; empty for
+    /// languages that are never printed.
+    pub line_comment: &'static str,
+}
+
+impl LanguageProvider {
+    /// Returns the Tree-sitter language for this provider.
+    pub fn ts_language(&self) -> tree_sitter::Language {
+        (self.language)()
+    }
+}
+
+static PROVIDERS: &[LanguageProvider] = &[
+    LanguageProvider {
+        name: "rust",
+        extensions: &["rs"],
+        grammar_version: "0.23.3",
+        interpreters: &["rust-script", "run-cargo-script"],
+        aliases: &["rs"],
+        syntax: SyntaxRules {
+            atomic: &[
+                "string_literal",
+                "raw_string_literal",
+                "char_literal",
+                "line_comment",
+                "block_comment",
+                "lifetime",
+                "label",
+            ],
+            skip: &[],
+            comments: &["line_comment", "block_comment"],
+            roots: &["source_file"],
+            blocks: &[
+                "block",
+                "declaration_list",
+                "field_declaration_list",
+                "enum_variant_list",
+                "match_block",
+            ],
+            line_start: &[],
+            glued: &[
+                "arguments",
+                "parameters",
+                "type_arguments",
+                "type_parameters",
+                "token_tree",
+                "ordered_field_declaration_list",
+                "closure_parameters",
+            ],
+            tight: &[
+                "attribute_item",
+                "inner_attribute_item",
+                "base_field_initializer",
+                "closure_parameters",
+                "generic_function",
+                "generic_type",
+                "generic_type_with_turbofish",
+                "index_expression",
+                "macro_invocation",
+                "negative_literal",
+                "pointer_type",
+                "range_expression",
+                "range_pattern",
+                "reference_expression",
+                "reference_pattern",
+                "reference_type",
+                "self_parameter",
+                "try_expression",
+                "tuple_struct_pattern",
+                "type_arguments",
+                "type_parameters",
+                "unary_expression",
+                "use_list",
+            ],
+            indent: "    ",
+            imports: &["use_declaration"],
+            import_lists: &["use_list"],
+            line_comment: "//",
+        },
+        injections: r#"
+            (call_expression
+              function: (scoped_identifier
+                path: (identifier) @_type
+                name: (identifier) @_fn)
+              arguments: (arguments . (raw_string_literal (string_content) @injection.content))
+              (#any-of? @_type "Regex" "RegexBuilder" "RegexSet")
+              (#any-of? @_fn "new" "from_str")
+              (#set! injection.language "regex"))
+        "#,
+        roles: r#"
+            [(function_item) (function_signature_item) (closure_expression)] @function
+            [(struct_item) (enum_item) (union_item) (trait_item) (type_item)] @type
+            [(parameter) (self_parameter)] @parameter
+            (closure_parameters (identifier) @parameter)
+            [(use_declaration) (extern_crate_declaration)] @import
+            [(call_expression) (macro_invocation)] @call
+            [(line_comment) (block_comment)] @comment
+        "#,
+        language: || tree_sitter_rust::LANGUAGE.into(),
+    },
+    LanguageProvider {
+        name: "python",
+        extensions: &["py", "pyi"],
+        grammar_version: "0.23.6",
+        interpreters: &["python", "pypy"],
+        aliases: &["py", "python2", "python3"],
+        syntax: SyntaxRules {
+            atomic: &["string", "comment"],
+            skip: &["line_continuation"],
+            comments: &["comment"],
+            roots: &["module"],
+            blocks: &["block"],
+            line_start: &[
+                "elif_clause",
+                "else_clause",
+                "except_clause",
+                "except_group_clause",
+                "finally_clause",
+                "case_clause",
+                "decorator",
+                "function_definition",
+                "class_definition",
+            ],
+            glued: &["argument_list", "parameters", "type_parameter"],
+            tight: &[
+                "decorator",
+                "default_parameter",
+                "dictionary",
+                "dictionary_comprehension",
+                "dictionary_splat",
+                "dictionary_splat_pattern",
+                "keyword_argument",
+                "list_splat",
+                "list_splat_pattern",
+                "set",
+                "set_comprehension",
+                "slice",
+                "subscript",
+                "unary_operator",
+            ],
+            indent: "    ",
+            imports: &["import_statement", "import_from_statement"],
+            import_lists: &[],
+            line_comment: "#",
+        },
+        injections: r#"
+            (call
+              function: (attribute
+                object: (identifier) @_module
+                attribute: (identifier))
+              arguments: (argument_list . (string (string_content) @injection.content))
+              (#eq? @_module "re")
+              (#set! injection.language "regex"))
+        "#,
+        roles: r#"
+            [(function_definition) (lambda)] @function
+            (class_definition) @type
+            (parameters
+              [(identifier)
+               (typed_parameter)
+               (default_parameter)
+               (typed_default_parameter)
+               (list_splat_pattern)
+               (dictionary_splat_pattern)] @parameter)
+            (lambda_parameters (identifier) @parameter)
+            [(import_statement) (import_from_statement) (future_import_statement)] @import
+            (call) @call
+            (comment) @comment
+        "#,
+        language: || tree_sitter_python::LANGUAGE.into(),
+    },
+    LanguageProvider {
+        name: "javascript",
+        extensions: &["js", "mjs", "cjs", "jsx"],
+        grammar_version: "0.23.1",
+        interpreters: &["node", "nodejs", "deno", "bun"],
+        aliases: &["js", "jsx", "javascriptreact"],
+        syntax: SyntaxRules {
+            atomic: &["string", "template_string", "regex", "comment"],
+            skip: &[],
+            comments: &["comment"],
+            roots: &["program"],
+            blocks: &["statement_block", "class_body", "switch_body", "object"],
+            line_start: &[],
+            glued: &["arguments", "formal_parameters", "template_string"],
+            tight: &[
+                "rest_pattern",
+                "spread_element",
+                "subscript_expression",
+                "unary_expression",
+                "update_expression",
+            ],
+            indent: "  ",
+            imports: &["import_statement"],
+            import_lists: &["named_imports"],
+            line_comment: "//",
+        },
+        injections: r#"
+            (regex pattern: (regex_pattern) @injection.content
+              (#set! injection.language "regex"))
+            (call_expression
+              function: (identifier) @_tag
+              arguments: (template_string) @injection.content
+              (#eq? @_tag "html")
+              (#offset! @injection.content 0 1 0 -1)
+              (#set! injection.language "html"))
+            (call_expression
+              function: (identifier) @_tag
+              arguments: (template_string) @injection.content
+              (#eq? @_tag "css")
+              (#offset! @injection.content 0 1 0 -1)
+              (#set! injection.language "css"))
+        "#,
+        roles: r#"
+            [(function_declaration)
+             (function_expression)
+             (arrow_function)
+             (method_definition)
+             (generator_function_declaration)
+             (generator_function)] @function
+            [(class_declaration) (class)] @type
+            (formal_parameters
+              [(identifier)
+               (assignment_pattern)
+               (object_pattern)
+               (array_pattern)
+               (rest_pattern)] @parameter)
+            (arrow_function parameter: (identifier) @parameter)
+            (import_statement) @import
+            [(call_expression) (new_expression)] @call
+            (comment) @comment
+        "#,
+        language: || tree_sitter_javascript::LANGUAGE.into(),
+    },
+    LanguageProvider {
+        name: "java",
+        extensions: &["java"],
+        grammar_version: "0.23.5",
+        interpreters: &["java"],
+        aliases: &[],
+        syntax: SyntaxRules {
+            atomic: &[
+                "string_literal",
+                "character_literal",
+                "line_comment",
+                "block_comment",
+            ],
+            skip: &[],
+            comments: &["line_comment", "block_comment"],
+            // Members after an enum's constants go one per line like any body.
+            roots: &["program", "enum_body_declarations"],
+            blocks: &[
+                "block",
+                "class_body",
+                "interface_body",
+                "annotation_type_body",
+                "enum_body",
+                "constructor_body",
+                "switch_block",
+            ],
+            // Puts declaration annotations on their own line.
+            line_start: &["public", "protected", "private"],
+            glued: &[
+                "argument_list",
+                "formal_parameters",
+                "type_arguments",
+                "type_parameters",
+                "annotation_argument_list",
+            ],
+            tight: &[
+                "annotation",
+                "array_access",
+                "array_creation_expression",
+                "array_type",
+                "dimensions",
+                "generic_type",
+                "marker_annotation",
+                "type_arguments",
+                "type_parameters",
+                "unary_expression",
+                "update_expression",
+            ],
+            indent: "    ",
+            imports: &["import_declaration"],
+            import_lists: &[],
+            line_comment: "//",
+        },
+        injections: r#"
+            (method_invocation
+              object: (identifier) @_type
+              name: (identifier) @_method
+              arguments: (argument_list . (string_literal (string_fragment) @injection.content))
+              (#eq? @_type "Pattern")
+              (#eq? @_method "compile")
+              (#set! injection.language "regex"))
+        "#,
+        roles: r#"
+            [(method_declaration)
+             (constructor_declaration)
+             (compact_constructor_declaration)
+             (lambda_expression)] @function
+            [(class_declaration)
+             (interface_declaration)
+             (enum_declaration)
+             (record_declaration)
+             (annotation_type_declaration)] @type
+            [(formal_parameter) (spread_parameter)] @parameter
+            (inferred_parameters (identifier) @parameter)
+            (import_declaration) @import
+            [(method_invocation) (object_creation_expression)] @call
+            [(line_comment) (block_comment)] @comment
+        "#,
+        language: || tree_sitter_java::LANGUAGE.into(),
+    },
+    LanguageProvider {
+        name: "csharp",
+        extensions: &["cs", "csx"],
+        grammar_version: "0.23.1",
+        interpreters: &["dotnet-script"],
+        aliases: &["cs", "c#"],
+        syntax: SyntaxRules {
+            atomic: &[
+                "string_literal",
+                "verbatim_string_literal",
+                "raw_string_literal",
+                "interpolated_string_expression",
+                "character_literal",
+                "comment",
+                "preproc_define",
+                "preproc_undef",
+                "preproc_region",
+                "preproc_endregion",
+                "preproc_pragma",
+                "preproc_nullable",
+                "preproc_line",
+                "preproc_error",
+                "preproc_warning",
+            ],
+            skip: &[],
+            // Directives run to the end of their line, just like comments.
+            comments: &[
+                "comment",
+                "preproc_define",
+                "preproc_undef",
+                "preproc_region",
+                "preproc_endregion",
+                "preproc_pragma",
+                "preproc_nullable",
+                "preproc_line",
+                "preproc_error",
+                "preproc_warning",
+            ],
+            roots: &["compilation_unit"],
+            blocks: &[
+                "block",
+                "declaration_list",
+                "enum_member_declaration_list",
+                "accessor_list",
+                "switch_body",
+                "switch_expression",
+            ],
+            // Puts declaration attributes on their own line.
+            line_start: &["public", "protected", "private", "internal"],
+            glued: &[
+                "argument_list",
+                "parameter_list",
+                "bracketed_argument_list",
+                "type_argument_list",
+                "type_parameter_list",
+                "attribute_argument_list",
+            ],
+            tight: &[
+                "array_rank_specifier",
+                "array_type",
+                "conditional_access_expression",
+                "generic_name",
+                "implicit_object_creation_expression",
+                "member_binding_expression",
+                "nullable_type",
+                "postfix_unary_expression",
+                "prefix_unary_expression",
+                "type_argument_list",
+                "type_parameter_list",
+            ],
+            indent: "    ",
+            imports: &["using_directive"],
+            import_lists: &[],
+            line_comment: "//",
+        },
+        injections: r#"
+            (object_creation_expression
+              type: (identifier) @_type
+              arguments: (argument_list . (argument
+                (string_literal (string_literal_content) @injection.content)))
+              (#eq? @_type "Regex")
+              (#set! injection.language "regex"))
+            (object_creation_expression
+              type: (identifier) @_type
+              arguments: (argument_list . (argument
+                (verbatim_string_literal) @injection.content))
+              (#eq? @_type "Regex")
+              (#offset! @injection.content 0 2 0 -1)
+              (#set! injection.language "regex"))
+        "#,
+        roles: r#"
+            [(method_declaration)
+             (constructor_declaration)
+             (local_function_statement)
+             (lambda_expression)
+             (anonymous_method_expression)] @function
+            [(class_declaration)
+             (interface_declaration)
+             (struct_declaration)
+             (enum_declaration)
+             (record_declaration)] @type
+            (parameter) @parameter
+            (using_directive) @import
+            [(invocation_expression) (object_creation_expression)] @call
+            (comment) @comment
+        "#,
+        language: || tree_sitter_c_sharp::LANGUAGE.into(),
+    },
+    LanguageProvider {
+        name: "ruby",
+        extensions: &["rb", "rake", "gemspec", "ru"],
+        grammar_version: "0.23.1",
+        interpreters: &["ruby", "jruby", "truffleruby"],
+        aliases: &["rb"],
+        syntax: SyntaxRules {
+            atomic: &[
+                "string",
+                "chained_string",
+                "character",
+                "delimited_symbol",
+                "regex",
+                "subshell",
+                "string_array",
+                "symbol_array",
+                "heredoc_beginning",
+                "heredoc_body",
+                "comment",
+                "uninterpreted",
+            ],
+            skip: &[],
+            comments: &["comment"],
+            roots: &["program"],
+            // `def`/`class`/`if` bodies, closed by an `end` on its own line.
+            blocks: &[
+                "body_statement",
+                "block_body",
+                "then",
+                "else",
+                "ensure",
+                "rescue",
+                "do",
+            ],
+            line_start: &[
+                "when",
+                "in_clause",
+                "elsif",
+                "else",
+                "rescue",
+                "ensure",
+                "end",
+            ],
+            glued: &["argument_list", "method_parameters", "lambda_parameters"],
+            tight: &[
+                "block_argument",
+                "block_parameters",
+                "element_reference",
+                "hash_splat_argument",
+                "hash_splat_parameter",
+                "range",
+                "splat_argument",
+                "splat_parameter",
+                "unary",
+            ],
+            indent: "  ",
+            imports: &[],
+            import_lists: &[],
+            line_comment: "#",
+        },
+        injections: r#"
+            (regex (string_content) @injection.content
+              (#set! injection.language "regex"))
+        "#,
+        roles: r#"
+            [(method) (singleton_method) (lambda)] @function
+            [(class) (module)] @type
+            ([(method_parameters) (lambda_parameters)]
+              [(identifier)
+               (optional_parameter)
+               (splat_parameter)
+               (hash_splat_parameter)
+               (block_parameter)
+               (keyword_parameter)] @parameter)
+            ((call
+               !receiver
+               method: (identifier) @_method) @import
+              (#any-of? @_method "require" "require_relative" "load"))
+            (call) @call
+            (comment) @comment
+        "#,
+        language: || tree_sitter_ruby::LANGUAGE.into(),
+    },
+    LanguageProvider {
+        name: "php",
+        extensions: &["php", "phtml"],
+        grammar_version: "0.24.2",
+        interpreters: &["php"],
+        aliases: &[],
+        syntax: SyntaxRules {
+            atomic: &[
+                "string",
+                "encapsed_string",
+                "heredoc",
+                "nowdoc",
+                "shell_command_expression",
+                "comment",
+                "text",
+                "text_interpolation",
+            ],
+            skip: &[],
+            comments: &["comment"],
+            roots: &["program"],
+            blocks: &[
+                "compound_statement",
+                "colon_block",
+                "declaration_list",
+                "enum_declaration_list",
+                "match_block",
+                "property_hook_list",
+                "switch_block",
+            ],
+            line_start: &[],
+            glued: &["arguments", "formal_parameters"],
+            tight: &[
+                "by_ref",
+                "class_constant_access_expression",
+                "member_access_expression",
+                "member_call_expression",
+                "namespace_name",
+                "namespace_use_declaration",
+                "namespace_use_group",
+                "nullsafe_member_access_expression",
+                "nullsafe_member_call_expression",
+                "optional_type",
+                "qualified_name",
+                "relative_name",
+                "scoped_call_expression",
+                "scoped_property_access_expression",
+                "subscript_expression",
+                "unary_op_expression",
+                "update_expression",
+                "variable_name",
+                "variadic_unpacking",
+            ],
+            indent: "    ",
+            imports: &["namespace_use_declaration"],
+            import_lists: &[],
+            line_comment: "//",
+        },
+        injections: r#"
+            ((text) @injection.content
+              (#set! injection.language "html"))
+        "#,
+        roles: r#"
+            [(function_definition)
+             (method_declaration)
+             (anonymous_function)
+             (arrow_function)] @function
+            [(class_declaration)
+             (interface_declaration)
+             (trait_declaration)
+             (enum_declaration)] @type
+            [(simple_parameter) (variadic_parameter) (property_promotion_parameter)] @parameter
+            [(namespace_use_declaration)
+             (require_expression)
+             (require_once_expression)
+             (include_expression)
+             (include_once_expression)] @import
+            [(function_call_expression)
+             (member_call_expression)
+             (scoped_call_expression)
+             (object_creation_expression)] @call
+            (comment) @comment
+        "#,
+        language: || tree_sitter_php::LANGUAGE_PHP.into(),
+    },
+    // Embedded-only languages: no extensions, reached through injections.
+    LanguageProvider {
+        name: "regex",
+        extensions: &[],
+        grammar_version: "0.24.3",
+        interpreters: &[],
+        aliases: &[],
+        syntax: EMBEDDED,
+        injections: "",
+        roles: "",
+        language: || tree_sitter_regex::LANGUAGE.into(),
+    },
+    LanguageProvider {
+        name: "html",
+        extensions: &[],
+        grammar_version: "0.23.2",
+        interpreters: &[],
+        aliases: &[],
+        syntax: SyntaxRules {
+            atomic: &["comment", "raw_text"],
+            ..EMBEDDED
+        },
+        injections: r#"
+            (script_element (raw_text) @injection.content
+              (#set! injection.language "javascript"))
+            (style_element (raw_text) @injection.content
+              (#set! injection.language "css"))
+        "#,
+        roles: r#"
+            (comment) @comment
+        "#,
+        language: || tree_sitter_html::LANGUAGE.into(),
+    },
+    LanguageProvider {
+        name: "css",
+        extensions: &[],
+        grammar_version: "0.23.2",
+        interpreters: &[],
+        aliases: &[],
+        syntax: SyntaxRules {
+            atomic: &["comment", "string_value"],
+            ..EMBEDDED
+        },
+        injections: "",
+        roles: r#"
+            (import_statement) @import
+            (call_expression) @call
+            (comment) @comment
+        "#,
+        language: || tree_sitter_css::LANGUAGE.into(),
+    },
+];
+
+/// Rules for languages that are only ever parsed inside another file, and
+/// so never printed.
+const EMBEDDED: SyntaxRules = SyntaxRules {
+    atomic: &[],
+    skip: &[],
+    comments: &[],
+    roots: &[],
+    blocks: &[],
+    line_start: &[],
+    glued: &[],
+    tight: &[],
+    indent: "  ",
+    imports: &[],
+    import_lists: &[],
+    line_comment: "",
+};
+
+/// All registered language providers.
+pub fn all() -> &'static [LanguageProvider] {
+    PROVIDERS
+}
+
+/// Looks up a provider by its canonical name.
+pub fn by_name(name: &str) -> Option<&'static LanguageProvider> {
+    PROVIDERS.iter().find(|p| p.name.eq_ignore_ascii_case(name))
+}
+
+/// Looks up a provider by its canonical name or one of its aliases.
+fn by_alias(name: &str) -> Option<&'static LanguageProvider> {
+    by_name(name).or_else(|| {
+        PROVIDERS
+            .iter()
+            .find(|p| p.aliases.iter().any(|a| a.eq_ignore_ascii_case(name)))
+    })
+}
+
+/// Selects the provider for a repository path based on its extension.
+pub fn for_path(path: &str) -> Option<&'static LanguageProvider> {
+    let ext = Path::new(path).extension()?.to_str()?;
+    PROVIDERS
+        .iter()
+        .find(|p| p.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+}
+
+/// Selects the provider for a path, sniffing `content` if the path has no
+/// extension.
+pub fn detect(path: &str, content: &[u8]) -> Option<&'static LanguageProvider> {
+    if Path::new(path).extension().is_some() {
+        return for_path(path);
+    }
+    for_content(content)
+}
+
+/// Selects a provider from a shebang line or an editor modeline.
+pub fn for_content(content: &[u8]) -> Option<&'static LanguageProvider> {
+    let text = String::from_utf8_lossy(&content[..content.len().min(4096)]);
+    let mut lines = text.lines();
+    let first = lines.next().unwrap_or_default();
+    if let Some(provider) = first.strip_prefix("#!").and_then(from_shebang) {
+        return Some(provider);
+    }
+    let emacs_line = if first.starts_with("#!") {
+        lines.next()
+    } else {
+        Some(first)
+    };
+    if let Some(provider) = emacs_line.and_then(from_emacs_modeline) {
+        return Some(provider);
+    }
+    // Vim looks at the first and last five lines; the tail may be past our
+    // prefix, so read it from the full content.
+    let tail = String::from_utf8_lossy(&content[content.len().saturating_sub(4096)..]);
+    text.lines()
+        .take(5)
+        .chain(tail.lines().rev().take(5))
+        .find_map(from_vim_modeline)
+}
+
+fn from_shebang(line: &str) -> Option<&'static LanguageProvider> {
+    let mut words = line.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        // Skip `env` options such as `-S`.
+        program = words.find(|w| !w.starts_with('-') && !w.contains('='))?;
+    }
+    let name = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    PROVIDERS
+        .iter()
+        .find(|p| p.interpreters.iter().any(|i| i.eq_ignore_ascii_case(name)))
+}
+
+fn from_emacs_modeline(line: &str) -> Option<&'static LanguageProvider> {
+    let start = line.find("-*-")? + 3;
+    let end = start + line[start..].find("-*-")?;
+    let vars = line[start..end].trim();
+    let mode = if vars.contains(':') {
+        vars.split(';').find_map(|var| {
+            let (key, value) = var.split_once(':')?;
+            key.trim()
+                .eq_ignore_ascii_case("mode")
+                .then(|| value.trim())
+        })?
+    } else {
+        vars
+    };
+    by_alias(mode.strip_suffix("-mode").unwrap_or(mode))
+}
+
+fn from_vim_modeline(line: &str) -> Option<&'static LanguageProvider> {
+    let at = ["vim:", "vi:", "ex:"]
+        .iter()
+        .find_map(|m| line.find(m).map(|i| i + m.len()))?;
+    line[at..]
+        .split(|c: char| c == ':' || c.is_whitespace())
+        .find_map(|opt| {
+            opt.strip_prefix("ft=")
+                .or_else(|| opt.strip_prefix("filetype="))
+        })
+        .and_then(by_alias)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detected(path: &str, content: &str) -> Option<&'static str> {
+        detect(path, content.as_bytes()).map(|p| p.name)
+    }
+
+    #[test]
+    fn detects_extensionless_scripts() {
+        assert_eq!(
+            detected("bin/tool", "#!/usr/bin/env python3\nprint(1)\n"),
+            Some("python")
+        );
+        assert_eq!(
+            detected("bin/tool", "#!/usr/bin/env -S node --harmony\n"),
+            Some("javascript")
+        );
+        assert_eq!(
+            detected("bin/tool", "# -*- coding: utf-8; mode: python -*-\n"),
+            Some("python")
+        );
+        assert_eq!(
+            detected("build", "fn main() {}\n// vim: set ft=rust:\n"),
+            Some("rust")
+        );
+        assert_eq!(detected("bin/tool", "#!/bin/sh\necho hi\n"), None);
+        // An extension always wins over content.
+        assert_eq!(detected("notes.txt", "#!/usr/bin/env python3\n"), None);
+    }
+}
@@ -0,0 +1,235 @@
+//! # git-ast: Language-Aware Git Extensions
+//!
+//! This crate provides the core logic for `git-ast`, a tool designed to
+//! extend Git with language-aware capabilities by storing Abstract Syntax Trees
+//! (ASTs) or Concrete Syntax Trees (CSTs) directly in the Git object database,
+//! rather than plain source code.
+//!
+//! ## Core Concepts
+//!
+//! The primary mechanism involves using Git's filter and driver system:
+//!
+//! 1.  **Clean/Smudge Filters:** Convert source code to a serialized AST/CST
+//!     representation when staging (`git add`) and back to source code when
+//!     checking out (`git checkout`). See the [`git_plumbing::filters`] module documentation
+//!     and the detailed guide in `docs/technical-architecture/clean-smudge-filters.md`.
+//! 2.  **Custom Diff Driver:** Provides semantic diffs by comparing ASTs/CSTs
+//!     instead of text lines, making `git diff` and `git log -p` output more
+//!     meaningful. See the [`drivers`] module documentation.
+//! 3.  **Custom Merge Driver:** Enables more intelligent 3-way merges by operating
+//!     on the code's structure, potentially auto-resolving conflicts caused by
+//!     refactoring or code movement. See the [`drivers`] module documentation.
+//!
+//! ## How it Hooks into Git
+//!
+//! `git-ast` integrates with Git via configuration in `.gitattributes` and
+//! `.git/config` (or global Git config). Users interact with standard Git commands.
+//!
+//! -   **`.gitattributes`:** Defines *which* files use `git-ast`'s features.
+//!     ```gitattributes
+//!     *.rs filter=ast diff=ast merge=ast
+//!     *.py filter=ast diff=ast merge=ast
+//!     ```
+//! -   **`.git/config`:** Defines *how* `git-ast` is invoked.
+//!     ```ini
+//!     [filter "ast"]
+//!         # Use git-ast for clean/smudge
+//!         process = git-ast filter-process
+//!         required = true
+//!
+//!     [diff "ast"]
+//!         # Use git-ast for diffing
+//!         command = git-ast diff-driver
+//!         # Optional: Cache text conversion results (if using textconv approach)
+//!         # cachetextconv = true
+//!
+//!     [merge "ast"]
+//!         # Use git-ast for merging
+//!         name = AST-based merge driver
+//!         driver = git-ast merge-driver %O %A %B %L %P
+//!         recursive = binary # Often fallback to binary for internal merges
+//!     ```
+//!
+//! The `git-ast` executable needs to handle the `filter-process`, `diff-driver`,
+//! and `merge-driver` subcommands accordingly.
+//!
+//! ## Platform Integration (GitHub, GitLab, etc.) - IMPORTANT LIMITATION
+//!
+//! **Remote platforms WILL NOT run your local `git-ast` filters or drivers.**
+//! They interact directly with the raw objects stored in the repository. Since
+//! `git-ast` stores *serialized AST/CST data* as Git blobs (via the clean filter),
+//! this means:
+//!
+//! -   **Web UI:** Files on GitHub/GitLab will display the raw serialized data, not source code.
+//! -   **Diffs:** Diffs in Pull Requests will compare serialized data, likely unreadable.
+//! -   **Merges:** Merging via the web UI might bypass the custom merge driver.
+//!
+//! ### Workaround: Mirrored Repository
+//!
+//! The recommended approach for collaboration using platforms is to maintain two repositories:
+//!
+//! 1.  **Primary Repo (`*-ast`):** Stores ASTs, uses `git-ast` filters/drivers. Development happens here locally.
+//! 2.  **Mirror Repo (`*-source`):** Stores standard source code. Used for PRs, code browsing on platforms.
+//!
+//! A CI/CD pipeline on the primary repo automatically checks out code (triggering the `smudge`
+//! filter to generate source) and pushes the resulting source code to the mirror repo.
+//! See the Mermaid diagram in `docs/technical-architecture/clean-smudge-filters.md`.
+//!
+//! The experimental `git-remote-ast` helper (see [`remote_helper`]) does the
+//! same translation on the fly: an `ast::<url>` remote fetches and pushes
+//! plain source without a second local repository.
+//!
+//! ## Modules
+//!
+//! -   [`ast`]: The stored syntax tree (a CST without formatting).
+//! -   [`attestation`]: Signed records of which toolchain produced each AST blob (`refs/notes/ast-attestations`).
+//! -   [`audit`]: Append-only log of filter and driver invocations (`.git/ast-audit`).
+//! -   [`changes`]: Files that differ between two trees, in canonical form, rendered like the diff driver.
+//! -   [`blame`]: Detection of formatting-only commits for `git blame --ignore-revs-file`.
+//! -   [`bundle`]: Offline bundles of a project's toolchain, lockfile, settings and query packs.
+//! -   [`code_metrics`]: Length, nesting and complexity per definition (`git-ast metrics-code`).
+//! -   [`codegen`]: Per-language commands run on smudged source, and undone before clean.
+//! -   [`compression`]: Optional zstd compression of blob payloads with per-grammar dictionaries.
+//! -   [`config`]: Handles parsing and applying configuration from Git.
+//! -   [`conformance`]: Printer round-trip checks over a corpus, with randomized layouts and minimized reproducers.
+//! -   [`diff_backend`]: Pluggable diff renderers (native line diff, difftastic), chosen per language.
+//! -   [`diff_cache`]: Cache of rendered diffs keyed by blob pair (`.git/ast-cache/diffs`).
+//! -   [`dupes`]: Duplicate-code detection by subtree hashing (`git-ast dupes`).
+//! -   [`drivers`]: Implements the custom diff and merge driver logic.
+//! -   [`fingerprint`]: Position-independent fingerprints of commits' structural changes.
+//! -   [`git_plumbing`]: (Placeholder) Logic for git-plumbing operations.
+//! -   [`injections`]: Parsing code embedded in strings (regexes, HTML/CSS templates) as nested trees.
+//! -   [`lint`]: `[[lint]]` query rules the clean filter enforces.
+//! -   [`lockfile`]: `.git-ast.lock`, pinning each language's grammar and printer for deterministic smudges.
+//! -   [`lsp`]: Language Server Protocol mode of `git-ast rpc`: document symbols and history-aware hovers.
+//! -   [`matching`]: Stable one-to-one pairing of nodes between versions, with fixed tie-breaking rules.
+//! -   [`merge`]: Line-level three-way merge of canonical source, and conflict marker rendering.
+//! -   [`mirror`]: Source mirror branches and the AST ↔ source OID map (`refs/notes/ast-map`).
+//! -   [`model`]: The versioned, serializable types of every JSON output about code and of the RPC API.
+//! -   [`ownership`]: Who changed each symbol of a file, for `git-ast owners` and `CODEOWNERS` suggestions.
+//! -   [`pickaxe`]: `git log -S` for Tree-sitter queries: commits changing a query's match count.
+//! -   [`refactorings`]: Renames and moves spanning files, found among a commit's changed items.
+//! -   [`remote_helper`]: Experimental `git-remote-ast` helper fetching from and pushing to plain-source remotes.
+//! -   [`review`]: Review checklists of risky structural changes in a range (`git-ast review`).
+//! -   [`rpc`]: JSON-RPC service for editor plugins (`git-ast rpc`).
+//! -   [`languages`]: Registry of language providers (grammar, extensions, grammar version).
+//! -   [`normalize`]: Optional clean-time rewrites (import order, trailing commas) configured per language.
+//! -   [`queries`]: Repository-provided Tree-sitter query packs (`.git-ast/queries/<lang>/*.scm`).
+//! -   [`parsing`]: Parsing source code into Tree-sitter CSTs, bounded by timeouts and size limits.
+//! -   [`serialization`]: The AST blob format (header with language and grammar version, then the tree).
+//! -   [`smudge_cache`]: Source of AST blobs printed ahead of time by `git-ast prewarm` (`.git/ast-cache/smudge`).
+//! -   [`sparse`]: Skeleton-plus-text blobs for vendored and generated paths.
+//! -   [`symbol_history`]: Commit-graph-style index of the commits that changed each symbol (`.git/ast/symbol-history`).
+//! -   [`symbols`]: Symbol definitions and references per revision, and their history (`git-ast report`).
+//! -   [`syntax`]: Syntax trees whose nodes have language-agnostic roles (function, parameter, import, ...).
+//! -   [`staged`]: Reading, writing, verifying and diffing the staged AST blobs in the index.
+//! -   [`staging`]: Staging some of a file's changed top-level items (`git-ast stage`).
+//! -   [`telemetry`]: Log lines (text or JSON) and Prometheus-style metrics (`.git/ast-cache/metrics`).
+//! -   [`token_diff`]: Diffs marking the changed tokens inside changed lines (the `tokens` diff backend).
+//! -   [`toolchain`]: Building and configuring the `git-ast` release a project pins (`git-ast setup-toolchain`).
+//! -   [`util`]: Shared helpers, such as crash-safe atomic file writes ([`util::atomic_io`]).
+//! -   [`pretty_printing`]: Canonical source generation from stored trees.
+//! -   [`commands`]: CLI command handling (`filter-process`, `diff-driver`, `merge-driver`, `hook`, `selftest`).
+
+// Unit tests set up fixtures with plain `std::fs::write`; see clippy.toml.
+#![cfg_attr(test, allow(clippy::disallowed_methods))]
+
+// Define module structure
+pub mod ast;
+pub mod attestation;
+pub mod audit;
+pub mod blame;
+pub mod bundle;
+pub mod changes;
+pub mod code_metrics;
+pub mod codegen;
+pub mod commands;
+pub mod compression;
+pub mod config;
+pub mod conformance;
+pub mod diff_backend;
+pub mod diff_cache;
+pub mod drivers;
+pub mod dupes;
+pub mod fingerprint;
+pub mod git_plumbing;
+pub mod injections;
+pub mod languages;
+pub mod lint;
+pub mod lockfile;
+pub mod lsp;
+pub mod matching;
+pub mod merge;
+pub mod mirror;
+pub mod model;
+pub mod normalize;
+pub mod ownership;
+pub mod parsing;
+pub mod pickaxe;
+pub mod queries;
+pub mod refactorings;
+pub mod remote_helper;
+pub mod review;
+pub mod rpc;
+// pub mod filters; // Removed as it's inside git_plumbing
+pub mod pretty_printing;
+pub mod serialization;
+pub mod smudge_cache;
+pub mod sparse;
+pub mod staged;
+pub mod staging;
+pub mod symbol_history;
+pub mod symbols;
+pub mod syntax;
+pub mod telemetry;
+pub mod token_diff;
+pub mod toolchain;
+pub mod util;
+
+/// Placeholder for shared error type
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Git(git2::Error),
+    Config(String),
+    Parsing(String),
+    Serialization(String),
+    Generation(String),
+    Driver(String),
+    Verification(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Git(e) => write!(f, "git error: {}", e),
+            Error::Config(msg) => write!(f, "configuration error: {}", msg),
+            Error::Parsing(msg) => write!(f, "parse error: {}", msg),
+            Error::Serialization(msg) => write!(f, "serialization error: {}", msg),
+            Error::Generation(msg) => write!(f, "generation error: {}", msg),
+            Error::Driver(msg) => write!(f, "driver error: {}", msg),
+            Error::Verification(msg) => write!(f, "verification failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<git2::Error> for Error {
+    fn from(e: git2::Error) -> Self {
+        Error::Git(e)
+    }
+}
+
+// Example of a function potentially called by a command handler
+// pub fn run_filter_process() -> Result<(), Error> {
+//     // Implementation using filters::run_long_running_filter...
+//     Ok(())
+// }
@@ -0,0 +1,171 @@
+//! Structural Lints
+//!
+//! Projects declare `[[lint]]` rules in `.git-ast.toml` (see
+//! [`LintRule`]): a Tree-sitter query for one language, optionally limited
+//! to some paths. The clean filter runs the rules that apply to each file it
+//! stores and reports every match with its position; a `deny` rule's match
+//! fails the filter, and with it `git add`:
+//!
+//! ```text
+//! $ git add src/prod/billing.rs
+//! [lint] src/prod/billing.rs:42:18: no-unwrap: handle the error instead
+//! error: external filter 'git-ast filter-process' failed
+//! ```
+//!
+//! Each match counts once, at its first capture not starting with `_` (the
+//! query-pack convention for predicate helpers). Lints never change what is
+//! stored, only whether it is stored.
+
+use crate::config::{LintLevel, LintRule};
+use crate::languages::{self, LanguageProvider};
+use crate::parsing::{self, ParseLimits};
+use crate::{log_info, Error};
+use git2::{Pathspec, PathspecFlags};
+use std::path::Path;
+use tree_sitter::{Query, QueryCursor, StreamingIterator, Tree};
+
+/// A match of a lint rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub rule: String,
+    pub level: LintLevel,
+    /// 1-based.
+    pub line: usize,
+    pub column: usize,
+    pub message: Option<String>,
+}
+
+/// The rules of `rules` that apply to `pathname`, a file of `provider`.
+pub fn applicable<'a>(
+    rules: &'a [LintRule],
+    pathname: &str,
+    provider: &LanguageProvider,
+) -> Result<Vec<&'a LintRule>, Error> {
+    let mut out = Vec::new();
+    for rule in rules {
+        let language = languages::by_name(&rule.language).ok_or_else(|| {
+            Error::Config(format!(
+                "lint {}: unknown language {:?}",
+                rule.name, rule.language
+            ))
+        })?;
+        let in_paths = rule.paths.is_empty()
+            || Pathspec::new(&rule.paths)?
+                .matches_path(Path::new(pathname), PathspecFlags::DEFAULT);
+        if language.name == provider.name && in_paths {
+            out.push(rule);
+        }
+    }
+    Ok(out)
+}
+
+/// The matches of `rules` in a parsed file.
+pub fn check(
+    tree: &Tree,
+    source: &[u8],
+    provider: &LanguageProvider,
+    rules: &[&LintRule],
+) -> Result<Vec<Finding>, Error> {
+    let mut findings = Vec::new();
+    for rule in rules {
+        let query = Query::new(&provider.ts_language(), &rule.query)
+            .map_err(|e| Error::Config(format!("lint {}: invalid query: {}", rule.name, e)))?;
+        let names = query.capture_names();
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(&query, tree.root_node(), source);
+        while let Some(m) = matches.next() {
+            let Some(capture) = m
+                .captures
+                .iter()
+                .find(|c| !names[c.index as usize].starts_with('_'))
+            else {
+                continue;
+            };
+            let position = capture.node.start_position();
+            findings.push(Finding {
+                rule: rule.name.clone(),
+                level: rule.level,
+                line: position.row + 1,
+                column: position.column + 1,
+                message: rule.message.clone(),
+            });
+        }
+    }
+    findings.sort_by_key(|f| (f.line, f.column));
+    Ok(findings)
+}
+
+/// Runs the rules that apply to `pathname` on `source`, logging each match;
+/// an error if any is denied.
+pub fn enforce(
+    source: &[u8],
+    pathname: &str,
+    provider: &LanguageProvider,
+    limits: &ParseLimits,
+    rules: &[LintRule],
+) -> Result<(), Error> {
+    let rules = applicable(rules, pathname, provider)?;
+    if rules.is_empty() {
+        return Ok(());
+    }
+    let tree = parsing::parse(source, provider, limits)?;
+    let findings = check(&tree, source, provider, &rules)?;
+    for finding in &findings {
+        log_info!("lint", "{}", describe(pathname, finding));
+    }
+    match findings.iter().find(|f| f.level == LintLevel::Deny) {
+        Some(denied) => Err(Error::Verification(describe(pathname, denied))),
+        None => Ok(()),
+    }
+}
+
+/// `path:line:column: rule: message`.
+fn describe(pathname: &str, finding: &Finding) -> String {
+    let mut text = format!(
+        "{}:{}:{}: {}",
+        pathname, finding.line, finding.column, finding.rule
+    );
+    if let Some(message) = &finding.message {
+        text.push_str(": ");
+        text.push_str(message);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn denies_matches_in_listed_paths() {
+        let rust = languages::by_name("rust").unwrap();
+        let limits = ParseLimits::default();
+        let rule = |level| {
+            LintRule {
+            name: "no-unwrap".to_string(),
+            language: "rust".to_string(),
+            paths: vec!["src/prod/".to_string()],
+            query: r#"(call_expression function: (field_expression field: (field_identifier) @m (#eq? @m "unwrap")))"#.to_string(),
+            level,
+            message: None,
+        }
+        };
+        let source = b"fn f() {\n    x.unwrap();\n    // y.unwrap()\n}\n";
+
+        let rules = [rule(LintLevel::Deny)];
+        let error = enforce(source, "src/prod/a.rs", rust, &limits, &rules).unwrap_err();
+        assert!(
+            error.to_string().contains("src/prod/a.rs:2:7: no-unwrap"),
+            "{}",
+            error
+        );
+        enforce(source, "src/dev/a.rs", rust, &limits, &rules).unwrap();
+        let python = languages::by_name("python").unwrap();
+        assert!(applicable(&rules, "src/prod/a.py", python)
+            .unwrap()
+            .is_empty());
+
+        let rules = [rule(LintLevel::Warn)];
+        enforce(source, "src/prod/a.rs", rust, &limits, &rules).unwrap();
+    }
+}
@@ -0,0 +1,202 @@
+//! Determinism Lockfile
+//!
+//! Smudged source is only the same on every machine if every contributor
+//! prints with the same grammar and the same printer rules. A newer
+//! `git-ast` with a different grammar release parses some files
+//! differently, and a printer change lays them out differently; the result
+//! is files that change on checkout, and diffs nobody wrote. `.git-ast.lock`,
+//! committed at the worktree root, records what the project uses:
+//!
+//! ```toml
+//! # Written by `git-ast lock`.
+//! git-ast = "0.1.0"
+//!
+//! [languages.rust]
+//! grammar = "0.23.3"
+//! printer = "5d2c8e1f9a3b7c40"
+//! ```
+//!
+//! `printer` is a hash of everything besides the grammar that decides the
+//! printed layout: the blob format version and the language's
+//! [`SyntaxRules`](crate::languages::SyntaxRules). `git-ast` is the release
+//! that wrote the file, which `git-ast setup-toolchain` installs when the
+//! one at hand does not match (see [`toolchain`](crate::toolchain)).
+//!
+//! `git-ast lock` writes the file for the languages of the AST blobs in the
+//! index, and `git-ast lock --check` verifies it (for CI). When the file
+//! exists, the filter refuses to clean or smudge a file whose language (by
+//! its path) this build does not match, with an error telling the
+//! contributor which version to install, instead of silently producing
+//! different source. Languages the file does not list are not checked.
+
+use crate::config;
+use crate::languages::{self, LanguageProvider};
+use crate::serialization;
+use crate::util::atomic_io;
+use crate::Error;
+use git2::{ObjectType, Oid, Repository};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+/// Name of the lockfile at the worktree root.
+pub const LOCKFILE: &str = ".git-ast.lock";
+
+/// What a language is locked to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedLanguage {
+    pub grammar: String,
+    /// See [`printer_hash`].
+    pub printer: String,
+}
+
+impl LockedLanguage {
+    /// What this build has for `provider`.
+    pub fn of(provider: &LanguageProvider) -> Self {
+        LockedLanguage {
+            grammar: provider.grammar_version.to_string(),
+            printer: printer_hash(provider),
+        }
+    }
+}
+
+/// The contents of `.git-ast.lock`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lockfile {
+    /// The `git-ast` release that wrote the file.
+    #[serde(rename = "git-ast", default, skip_serializing_if = "Option::is_none")]
+    pub git_ast: Option<String>,
+    #[serde(default)]
+    pub languages: BTreeMap<String, LockedLanguage>,
+}
+
+impl Lockfile {
+    /// The lockfile of `repo`'s worktree, if it has one.
+    pub fn load(repo: &Repository) -> Result<Option<Self>, Error> {
+        let Some(path) = lockfile_path(repo) else {
+            return Ok(None);
+        };
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        toml::from_str(&text)
+            .map(Some)
+            .map_err(|e| Error::Config(format!("{}: {}", LOCKFILE, e)))
+    }
+
+    /// Locks `languages` to this build.
+    pub fn for_languages<'p>(languages: impl IntoIterator<Item = &'p LanguageProvider>) -> Self {
+        Lockfile {
+            git_ast: Some(env!("CARGO_PKG_VERSION").to_string()),
+            languages: languages
+                .into_iter()
+                .map(|p| (p.name.to_string(), LockedLanguage::of(p)))
+                .collect(),
+        }
+    }
+
+    /// Writes the lockfile into `repo`'s worktree.
+    pub fn write(&self, repo: &Repository) -> Result<(), Error> {
+        let path = lockfile_path(repo)
+            .ok_or_else(|| Error::Config("git-ast lock needs a working tree".to_string()))?;
+        let body = toml::to_string(self).map_err(|e| Error::Serialization(e.to_string()))?;
+        let text = format!(
+            "# Written by `git-ast lock`. Commit it, so that every contributor\n\
+             # prints source with the same grammars and printer rules.\n\n{}",
+            body
+        );
+        atomic_io::write(path, text)
+    }
+
+    /// Fails if the lockfile pins `provider`'s language to a grammar or
+    /// printer other than this build's.
+    pub fn check(&self, provider: &LanguageProvider) -> Result<(), Error> {
+        let Some(locked) = self.languages.get(provider.name) else {
+            return Ok(());
+        };
+        let ours = LockedLanguage::of(provider);
+        if *locked == ours {
+            return Ok(());
+        }
+        Err(Error::Config(format!(
+            "{} pins {} to grammar {} (printer {}), but this git-ast {} has grammar {} \
+             (printer {}); run `git-ast setup-toolchain` to install the version the \
+             project uses, or `git-ast lock` and commit {} to move the project to \
+             this one",
+            LOCKFILE,
+            provider.name,
+            locked.grammar,
+            locked.printer,
+            env!("CARGO_PKG_VERSION"),
+            ours.grammar,
+            ours.printer,
+            LOCKFILE
+        )))
+    }
+
+    /// Fails if the lockfile pins the language of `path` (by its attributes
+    /// and extension) to something other than this build's.
+    pub fn check_path(&self, repo: Option<&Repository>, path: &str) -> Result<(), Error> {
+        match config::language_for_path(repo, path, b"")? {
+            Some(provider) => self.check(provider),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A hash of what decides how `provider`'s trees are printed, besides the
+/// grammar: the blob format and the language's layout rules.
+pub fn printer_hash(provider: &LanguageProvider) -> String {
+    let material = format!(
+        "format {}\nrules {:?}",
+        serialization::FORMAT_VERSION,
+        provider.syntax
+    );
+    let hash = Oid::hash_object(ObjectType::Blob, material.as_bytes()).expect("hashing a buffer");
+    hash.to_string()[..16].to_string()
+}
+
+/// The languages of the AST blobs in `repo`'s index.
+pub fn indexed_languages(repo: &Repository) -> Result<Vec<&'static LanguageProvider>, Error> {
+    let index = repo.index()?;
+    let mut names = BTreeSet::new();
+    for entry in index.iter() {
+        let blob = repo.find_blob(entry.id)?;
+        if serialization::is_ast_blob(blob.content()) {
+            names.insert(serialization::decode_header_of(blob.content())?.language);
+        }
+    }
+    Ok(names
+        .iter()
+        .filter_map(|name| languages::by_name(name))
+        .collect())
+}
+
+fn lockfile_path(repo: &Repository) -> Option<PathBuf> {
+    repo.workdir().map(|dir| dir.join(LOCKFILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_languages_against_this_build() {
+        let rust = languages::by_name("rust").unwrap();
+        let python = languages::by_name("python").unwrap();
+        let mut lock = Lockfile::for_languages([rust]);
+        let text = toml::to_string(&lock).unwrap();
+        assert_eq!(toml::from_str::<Lockfile>(&text).unwrap(), lock);
+        assert!(lock.check(rust).is_ok());
+        assert!(lock.check(python).is_ok());
+        assert_ne!(printer_hash(rust), printer_hash(python));
+
+        lock.languages.get_mut("rust").unwrap().grammar = "0.1.0".to_string();
+        let error = lock.check(rust).unwrap_err().to_string();
+        assert!(error.contains("pins rust to grammar 0.1.0"), "{}", error);
+        assert!(lock.check_path(None, "src/main.rs").is_err());
+        assert!(lock.check_path(None, "main.py").is_ok());
+    }
+}
@@ -0,0 +1,467 @@
+//! Language Server Mode
+//!
+//! `git-ast rpc --lsp` speaks enough of the Language Server Protocol for an
+//! editor to attach `git-ast` next to its usual language server:
+//!
+//! -   **`textDocument/documentSymbol`**: the definitions of a file (see
+//!     [`symbols`](crate::symbols)), nested by containment.
+//! -   **`textDocument/hover`**: on a definition, who last changed it and in
+//!     which commit, counting only commits that changed its tokens (see
+//!     [`ownership::history`]).
+//! -   **`git-ast/nodeBlame`** `{textDocument, position}`: the node at a
+//!     position, its enclosing definition and that definition's latest
+//!     commit, as `{kind, range, definition, commit, commits}` with a
+//!     [`SymbolRecord`] and a [`BlameEntry`].
+//! -   **`git-ast/symbolHistory`** `{textDocument, symbol?}`: as in the
+//!     [editor protocol](crate::rpc), for the document's file.
+//!
+//! Other `git-ast/*` methods go to the [editor protocol](crate::rpc)
+//! unchanged. Documents are synchronized in full: an open document is read
+//! from the editor's buffer, any other from the working tree. History is
+//! that of `HEAD`, cached per file until `HEAD` moves. Positions count UTF-16
+//! code units unless the client offers `utf-8` in
+//! `general.positionEncodings`.
+
+use crate::model::{BlameEntry, Document, SymbolRecord};
+use crate::ownership::{self, Touch};
+use crate::rpc::{Handler, RpcError, Server, INVALID_PARAMS, METHOD_NOT_FOUND};
+use crate::symbols::{self, Symbol};
+use crate::Error;
+use git2::{Oid, Repository};
+use serde_json::{json, Value};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tree_sitter::{Node as TsNode, Point};
+
+/// LSP `SymbolKind`s by substring of the definition kind, first match wins;
+/// anything else is a variable (13).
+const SYMBOL_KINDS: &[(&str, u32)] = &[
+    ("method", 6),
+    ("function", 12),
+    ("macro", 12),
+    ("class", 5),
+    ("impl", 5),
+    ("struct", 23),
+    ("enum", 10),
+    ("trait", 11),
+    ("interface", 11),
+    ("mod", 2),
+    ("namespace", 3),
+    ("const", 14),
+    ("static", 14),
+    ("field", 8),
+    ("type", 5),
+];
+
+/// The commits that changed each symbol of a file.
+type FileHistory = Vec<(Symbol, Vec<Touch>)>;
+
+/// An LSP session.
+pub struct LanguageServer {
+    rpc: Server,
+    workdir: PathBuf,
+    /// Positions count bytes rather than UTF-16 code units.
+    utf8: bool,
+    /// The text of open documents, by URI.
+    documents: HashMap<String, String>,
+    /// The history of each file, with the `HEAD` it was read at.
+    history: HashMap<String, (Oid, FileHistory)>,
+    done: bool,
+}
+
+/// What `git-ast/nodeBlame` and hovers report.
+struct Blame {
+    kind: String,
+    range: Value,
+    definition: Option<Symbol>,
+    /// Newest first.
+    touches: Vec<Touch>,
+}
+
+impl Handler for LanguageServer {
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "initialize" => {
+                self.utf8 = params["capabilities"]["general"]["positionEncodings"]
+                    .as_array()
+                    .is_some_and(|encodings| encodings.iter().any(|e| e == "utf-8"));
+                Ok(json!({
+                    "capabilities": {
+                        "positionEncoding": if self.utf8 { "utf-8" } else { "utf-16" },
+                        "textDocumentSync": 1,
+                        "documentSymbolProvider": true,
+                        "hoverProvider": true,
+                    },
+                    "serverInfo": {"name": "git-ast", "version": env!("CARGO_PKG_VERSION")},
+                }))
+            }
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+                if let (Some(uri), Some(text)) =
+                    (document["uri"].as_str(), document["text"].as_str())
+                {
+                    self.documents.insert(uri.to_string(), text.to_string());
+                }
+                Ok(Value::Null)
+            }
+            "textDocument/didChange" => {
+                let uri = document_uri(&params)?.to_string();
+                let changes = params["contentChanges"].as_array();
+                // Full sync: the last change holds the whole text.
+                if let Some(text) = changes
+                    .and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str())
+                {
+                    self.documents.insert(uri, text.to_string());
+                }
+                Ok(Value::Null)
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(document_uri(&params)?);
+                Ok(Value::Null)
+            }
+            "textDocument/documentSymbol" => self.document_symbols(&params),
+            "textDocument/hover" => {
+                let Some(Blame {
+                    range,
+                    definition: Some(symbol),
+                    touches,
+                    ..
+                }) = self.blame(&params)?
+                else {
+                    return Ok(Value::Null);
+                };
+                let mut text = format!("`{}` {}\n\n", symbol.kind, symbol.name);
+                match touches.first() {
+                    Some(latest) => text.push_str(&format!(
+                        "Last changed by {} in `{}`: {}\n\nChanged in {} commit{}",
+                        latest.author,
+                        &latest.commit[..7],
+                        latest.summary,
+                        touches.len(),
+                        if touches.len() == 1 { "" } else { "s" }
+                    )),
+                    None => text.push_str("Not in `HEAD`"),
+                }
+                Ok(json!({"contents": {"kind": "markdown", "value": text}, "range": range}))
+            }
+            "git-ast/nodeBlame" => Ok(match self.blame(&params)? {
+                Some(blame) => {
+                    let commits = blame.touches.len();
+                    let body = json!({
+                        "kind": blame.kind,
+                        "range": blame.range,
+                        "definition": blame.definition.map(SymbolRecord::from),
+                        "commit": blame.touches.into_iter().next().map(BlameEntry::from),
+                        "commits": commits,
+                    });
+                    Document::new(body).to_value()?
+                }
+                None => Value::Null,
+            }),
+            "git-ast/symbolHistory" => {
+                let path = self.path_of(document_uri(&params)?)?;
+                let params = json!({"path": path, "symbol": params["symbol"]});
+                self.rpc.call(method, params)
+            }
+            "shutdown" => Ok(Value::Null),
+            "exit" => {
+                self.done = true;
+                Ok(Value::Null)
+            }
+            _ if method.starts_with("git-ast/") => self.rpc.call(method, params),
+            _ => Err(RpcError {
+                code: METHOD_NOT_FOUND,
+                message: format!("unknown method {:?}", method),
+            }),
+        }
+    }
+
+    fn done(&self) -> bool {
+        self.done
+    }
+}
+
+impl LanguageServer {
+    pub fn new(repo: Repository) -> Result<Self, Error> {
+        let workdir = repo
+            .workdir()
+            .ok_or_else(|| Error::Config("lsp: the repository has no working tree".to_string()))?
+            .to_path_buf();
+        Ok(LanguageServer {
+            rpc: Server::new(repo)?,
+            workdir,
+            utf8: false,
+            documents: HashMap::new(),
+            history: HashMap::new(),
+            done: false,
+        })
+    }
+
+    fn document_symbols(&mut self, params: &Value) -> Result<Value, RpcError> {
+        let uri = document_uri(params)?;
+        let path = self.path_of(uri)?;
+        let Some(text) = self.text_of(uri, &path)? else {
+            return Ok(json!([]));
+        };
+        let Some((provider, source, tree)) =
+            self.rpc.parse_source(&path, text.clone().into_bytes())?
+        else {
+            return Ok(json!([]));
+        };
+        let pack = self.rpc.packs.get(provider)?;
+        let mut definitions = symbols::definition_nodes(&tree, &source, &path, provider, pack);
+        definitions.sort_by_key(|(_, node)| (node.start_byte(), Reverse(node.end_byte())));
+
+        // The definitions enclosing the current one, each with its end and
+        // its children so far.
+        let mut open: Vec<(usize, Value)> = Vec::new();
+        let mut roots = Vec::new();
+        let close = |open: &mut Vec<(usize, Value)>, roots: &mut Vec<Value>, before: usize| {
+            while open.last().is_some_and(|(end, _)| *end <= before) {
+                let (_, item) = open.pop().unwrap();
+                match open.last_mut() {
+                    Some((_, parent)) => parent["children"].as_array_mut().unwrap().push(item),
+                    None => roots.push(item),
+                }
+            }
+        };
+        for (symbol, node) in definitions {
+            close(&mut open, &mut roots, node.start_byte());
+            let name = node.child_by_field_name("name").unwrap_or(node);
+            let kind = SYMBOL_KINDS
+                .iter()
+                .find(|(part, _)| symbol.kind.contains(part))
+                .map_or(13, |&(_, kind)| kind);
+            open.push((
+                node.end_byte(),
+                json!({
+                    "name": symbol.name,
+                    "detail": symbol.kind,
+                    "kind": kind,
+                    "range": self.range(&text, node),
+                    "selectionRange": self.range(&text, name),
+                    "children": [],
+                }),
+            ));
+        }
+        close(&mut open, &mut roots, usize::MAX);
+        Ok(Value::Array(roots))
+    }
+
+    /// The node at `params.position` of `params.textDocument`, if the
+    /// document parses.
+    fn blame(&mut self, params: &Value) -> Result<Option<Blame>, RpcError> {
+        let uri = document_uri(params)?;
+        let path = self.path_of(uri)?;
+        let Some(text) = self.text_of(uri, &path)? else {
+            return Ok(None);
+        };
+        let point = self.point(&text, &params["position"])?;
+        let Some((provider, source, tree)) =
+            self.rpc.parse_source(&path, text.clone().into_bytes())?
+        else {
+            return Ok(None);
+        };
+        let Some((node, definition)) = self.rpc.locate(&tree, &source, &path, provider, point)?
+        else {
+            return Ok(None);
+        };
+        let touches = match &definition {
+            Some(symbol) => self
+                .history_of(&path)?
+                .iter()
+                .find(|(s, _)| s.kind == symbol.kind && s.name == symbol.name)
+                .map(|(_, touches)| touches.clone())
+                .unwrap_or_default(),
+            None => Vec::new(),
+        };
+        Ok(Some(Blame {
+            kind: node.kind().to_string(),
+            range: self.range(&text, node),
+            definition,
+            touches,
+        }))
+    }
+
+    /// The history of each symbol of `path` at `HEAD`; empty if `HEAD`
+    /// does not have the file.
+    fn history_of(&mut self, path: &str) -> Result<&[(Symbol, Vec<Touch>)], Error> {
+        let Ok(head) = self.rpc.repo.head().and_then(|h| h.peel_to_commit()) else {
+            return Ok(&[]);
+        };
+        if self
+            .history
+            .get(path)
+            .is_none_or(|(at, _)| *at != head.id())
+        {
+            let history = match head.tree()?.get_path(Path::new(path)) {
+                Ok(_) => ownership::history(
+                    &self.rpc.repo,
+                    path,
+                    None,
+                    &self.rpc.limits,
+                    &mut self.rpc.packs,
+                )?,
+                Err(_) => Vec::new(),
+            };
+            self.history.insert(path.to_string(), (head.id(), history));
+        }
+        Ok(&self.history[path].1)
+    }
+
+    /// The repository path of a `file:` URI.
+    fn path_of(&self, uri: &str) -> Result<String, RpcError> {
+        let outside = || RpcError {
+            code: INVALID_PARAMS,
+            message: format!("{} is not a file in the repository", uri),
+        };
+        let file = PathBuf::from(percent_decode(
+            uri.strip_prefix("file://").ok_or_else(outside)?,
+        ));
+        let relative = match file.strip_prefix(&self.workdir) {
+            Ok(relative) => relative.to_path_buf(),
+            // The editor may name the checkout through a symlink.
+            Err(_) => {
+                let workdir = self.workdir.canonicalize().map_err(Error::from)?;
+                let file = file.canonicalize().map_err(|_| outside())?;
+                file.strip_prefix(workdir)
+                    .map_err(|_| outside())?
+                    .to_path_buf()
+            }
+        };
+        Ok(relative.to_string_lossy().replace('\\', "/"))
+    }
+
+    /// The editor's text of `uri` if it is open, else the working tree's.
+    fn text_of(&self, uri: &str, path: &str) -> Result<Option<String>, Error> {
+        if let Some(text) = self.documents.get(uri) {
+            return Ok(Some(text.clone()));
+        }
+        let source = self.rpc.worktree_source(path)?;
+        Ok(source.map(|s| String::from_utf8_lossy(&s).into_owned()))
+    }
+
+    /// The point of an LSP position in `text`.
+    fn point(&self, text: &str, position: &Value) -> Result<Point, RpcError> {
+        let (Some(row), Some(character)) =
+            (position["line"].as_u64(), position["character"].as_u64())
+        else {
+            return Err(RpcError {
+                code: INVALID_PARAMS,
+                message: "position needs a line and a character".to_string(),
+            });
+        };
+        let (row, character) = (row as usize, character as usize);
+        let line = text.split('\n').nth(row).unwrap_or_default();
+        let column = match self.utf8 {
+            true => character,
+            false => {
+                let mut units = 0;
+                line.char_indices()
+                    .find(|(_, c)| {
+                        units += c.len_utf16();
+                        units > character
+                    })
+                    .map_or(line.len(), |(i, _)| i)
+            }
+        };
+        Ok(Point { row, column })
+    }
+
+    /// The LSP range of `node` in `text`.
+    fn range(&self, text: &str, node: TsNode<'_>) -> Value {
+        let position = |point: Point| {
+            let line = text.split('\n').nth(point.row).unwrap_or_default();
+            let character = match self.utf8 {
+                true => point.column,
+                false => line
+                    .get(..point.column)
+                    .map_or(point.column, |before| before.encode_utf16().count()),
+            };
+            json!({"line": point.row, "character": character})
+        };
+        json!({"start": position(node.start_position()), "end": position(node.end_position())})
+    }
+}
+
+fn document_uri(params: &Value) -> Result<&str, RpcError> {
+    params["textDocument"]["uri"]
+        .as_str()
+        .ok_or_else(|| RpcError {
+            code: INVALID_PARAMS,
+            message: "no textDocument.uri".to_string(),
+        })
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Signature;
+
+    #[test]
+    fn hovers_with_the_last_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let source = "mod m {\n    fn f() -> u32 {\n        1\n    }\n}\n";
+        std::fs::write(dir.path().join("a.rs"), source).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("a.rs")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = Signature::now("Ada", "ada@example.com").unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "Add f", &tree, &[])
+            .unwrap();
+        drop((tree, index));
+
+        let mut server = LanguageServer::new(repo).unwrap();
+        let uri = format!("file://{}", dir.path().join("a.rs").display());
+        let document = json!({"textDocument": {"uri": uri}});
+        let init = json!({"capabilities": {"general": {"positionEncodings": ["utf-8"]}}});
+        let capabilities = server.call("initialize", init).unwrap();
+        assert_eq!(capabilities["capabilities"]["positionEncoding"], "utf-8");
+
+        let symbols = server
+            .call("textDocument/documentSymbol", document.clone())
+            .unwrap();
+        assert_eq!(symbols[0]["name"], "m");
+        assert_eq!(symbols[0]["children"][0]["name"], "f");
+        assert_eq!(symbols[0]["children"][0]["kind"], 12);
+
+        let mut at = document.clone();
+        at["position"] = json!({"line": 2, "character": 8});
+        let hover = server.call("textDocument/hover", at.clone()).unwrap();
+        let text = hover["contents"]["value"].as_str().unwrap();
+        assert!(text.contains("Last changed by Ada in"), "{}", text);
+        assert!(text.ends_with(": Add f\n\nChanged in 1 commit"), "{}", text);
+
+        let blame = server.call("git-ast/nodeBlame", at).unwrap();
+        assert_eq!(blame["kind"], "integer_literal");
+        assert_eq!(blame["definition"]["name"], "f");
+        assert_eq!(blame["commit"]["summary"], "Add f");
+    }
+}
@@ -1,8 +1,10 @@
+use clap::Parser;
+use git_ast::commands::{self, Cli};
 use std::error::Error;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    // Update placeholder message - or determine what main should actually do
-    println!("git-ast main function.");
+    let cli = Cli::parse();
+    commands::run(cli)?;
 
     Ok(())
 }
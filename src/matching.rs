@@ -0,0 +1,221 @@
+//! Node Matching
+//!
+//! Pairing the items of one version with those of another (an item with its
+//! renamed self, a removed item with the one it moved to) often has more than
+//! one answer: a file with two identical functions, or a rename that fits two
+//! neighbours equally well. Whatever pairing wins decides what `git-ast show`
+//! and the [refactorings](crate::refactorings) it lists report, so it must
+//! not depend on hash-map order, the order candidates were found in, or the
+//! platform.
+//!
+//! [`assign`] pairs candidates one-to-one, best first, by these rules:
+//!
+//! 1.  **Position**: the pair whose items sit closest (see [`Position`]).
+//! 2.  **Content hash**: of two pairs equally close, the one whose new item,
+//!     then old item, has the smaller [`content_hash`].
+//! 3.  **Index**: between items that are identical in content (so either
+//!     choice reads the same), the earlier old item, then new item.
+//!
+//! The content hash is the Git blob hash of the item's kinds and tokens, so
+//! it is the same on every platform and in every release.
+//!
+//! ## Modified Items
+//!
+//! Within a run of changed items, [`modified`] decides which old item lives
+//! on as which new one: a pair of the same kind whose [`similarity`] reaches
+//! a threshold is one item, *modified*; everything else was removed or
+//! added. The threshold is a percentage of shared syntax tokens, like the
+//! similarity index of renamed files, and defaults to
+//! [`DEFAULT_SIMILARITY`]. What works differs by code: rewritten Python
+//! functions are still the same function at 40%, while in generated code
+//! two messages may share most tokens and still be unrelated. So
+//! `.git-ast.toml` can set it per language and per node kind (see
+//! [`DiffConfig::similarity_for`](crate::config::DiffConfig::similarity_for)).
+
+use crate::ast::Node;
+use crate::fingerprint;
+use git2::{ObjectType, Oid};
+
+/// Similarity (percent) a changed item needs to count as modified, unless
+/// configured otherwise. Git's default for renames.
+pub const DEFAULT_SIMILARITY: u32 = 50;
+
+/// How far apart two candidates are; smaller is closer.
+pub type Position = usize;
+
+/// A possible pair: the items' identifiers on each side, how far apart they
+/// sit, and their content hashes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate<T> {
+    pub old: T,
+    pub new: T,
+    pub distance: Position,
+    pub old_hash: Oid,
+    pub new_hash: Oid,
+}
+
+/// The distance between the `old`-th item of one run and the `new`-th of
+/// another: how much their offsets from the runs' starts differ.
+pub fn distance(old: usize, new: usize) -> Position {
+    old.abs_diff(new)
+}
+
+/// Pairs `candidates` one-to-one by the rules in the module docs; each
+/// identifier is used at most once. The result is sorted by old item.
+pub fn assign<T: Ord + Copy>(mut candidates: Vec<Candidate<T>>) -> Vec<(T, T)> {
+    candidates.sort_by(|a, b| {
+        (a.distance, a.new_hash, a.old_hash, a.old, a.new)
+            .cmp(&(b.distance, b.new_hash, b.old_hash, b.old, b.new))
+    });
+    let mut pairs: Vec<(T, T)> = Vec::new();
+    for candidate in candidates {
+        if !pairs
+            .iter()
+            .any(|&(old, new)| old == candidate.old || new == candidate.new)
+        {
+            pairs.push((candidate.old, candidate.new));
+        }
+    }
+    pairs.sort();
+    pairs
+}
+
+/// How much of `old` is kept in `new`, in percent of their tokens (see
+/// [`fingerprint::similarity`]).
+pub fn similarity(old: &Node, new: &Node) -> u32 {
+    let tokens = |node: &Node| -> Vec<(String, Option<String>)> {
+        node.leaves()
+            .into_iter()
+            .map(|leaf| (leaf.kind.clone(), leaf.text.clone()))
+            .collect()
+    };
+    fingerprint::score(&tokens(old), &tokens(new))
+}
+
+/// The pairs of `old` and `new` items (by index) that are one item modified:
+/// same kind, and at least as similar as `threshold` says for that kind.
+pub fn modified(
+    old: &[Node],
+    new: &[Node],
+    threshold: &dyn Fn(&str) -> u32,
+) -> Vec<(usize, usize)> {
+    let mut candidates = Vec::new();
+    for (i, a) in old.iter().enumerate() {
+        for (j, b) in new.iter().enumerate() {
+            if a.kind == b.kind && similarity(a, b) >= threshold(&a.kind) {
+                candidates.push(Candidate {
+                    old: i,
+                    new: j,
+                    distance: distance(i, j),
+                    old_hash: content_hash(a),
+                    new_hash: content_hash(b),
+                });
+            }
+        }
+    }
+    assign(candidates)
+}
+
+/// A hash of `node`'s kinds and tokens, stable across runs and platforms.
+pub fn content_hash(node: &Node) -> Oid {
+    let mut material = Vec::new();
+    write_content(node, &mut material);
+    Oid::hash_object(ObjectType::Blob, &material).expect("hashing a buffer")
+}
+
+fn write_content(node: &Node, out: &mut Vec<u8>) {
+    out.extend_from_slice(node.kind.as_bytes());
+    out.push(0);
+    if let Some(text) = &node.text {
+        out.extend_from_slice(text.as_bytes());
+    }
+    out.push(0);
+    for child in &node.children {
+        write_content(child, out);
+    }
+    out.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(text: &str) -> Node {
+        Node {
+            kind: "identifier".to_string(),
+            named: true,
+            text: Some(text.to_string()),
+            ..Node::default()
+        }
+    }
+
+    fn candidate(old: usize, new: usize, items: &[Node]) -> Candidate<usize> {
+        Candidate {
+            old,
+            new,
+            distance: distance(old, new),
+            old_hash: content_hash(&items[old]),
+            new_hash: content_hash(&items[new]),
+        }
+    }
+
+    #[test]
+    fn breaks_ties_by_position_then_content_hash() {
+        let items = [leaf("a"), leaf("b"), leaf("c"), leaf("a")];
+        // Item 1 fits 0 and 2 equally well; the content hash decides.
+        let tied = vec![candidate(1, 0, &items), candidate(1, 2, &items)];
+        let by_hash = if content_hash(&items[0]) < content_hash(&items[2]) {
+            0
+        } else {
+            2
+        };
+        assert_eq!(assign(tied.clone()), [(1, by_hash)]);
+        let mut reversed = tied;
+        reversed.reverse();
+        assert_eq!(assign(reversed), [(1, by_hash)]);
+
+        // Closer wins over hash; identical content falls back to indices.
+        let candidates = vec![
+            candidate(2, 0, &items),
+            candidate(3, 0, &items),
+            candidate(0, 3, &items),
+            candidate(0, 0, &items),
+            candidate(2, 3, &items),
+        ];
+        let expected = [(0, 0), (2, 3)];
+        assert_eq!(assign(candidates.clone()), expected);
+        for rotation in 0..candidates.len() {
+            let mut rotated = candidates.clone();
+            rotated.rotate_left(rotation);
+            assert_eq!(assign(rotated), expected, "depends on input order");
+        }
+        assert_ne!(content_hash(&items[0]), content_hash(&items[1]));
+        assert_eq!(content_hash(&items[0]), content_hash(&items[3]));
+    }
+
+    #[test]
+    fn pairs_modified_items_by_threshold() {
+        let change = crate::changes::FileChange {
+            path: "a.rs".to_string(),
+            from: None,
+            old_mode: Some("100644".to_string()),
+            new_mode: Some("100644".to_string()),
+            old: b"fn a() {\n    one(1);\n}\n\nstruct S;\n".to_vec(),
+            new: b"fn a() {\n    one(2);\n}\n\nfn b() {}\n".to_vec(),
+        };
+        let items = change.items(None).unwrap();
+        assert_eq!(items.modified, [(0, 0)]);
+        let (old, new) = items.sides(&items.hunks[0]);
+        let similar = similarity(&old[0], &new[0]);
+        assert!((50..100).contains(&similar), "{}", similar);
+        // Above its similarity, `a` was removed and a new `a` added.
+        assert_eq!(modified(old, new, &|_| similar + 1), []);
+        assert_eq!(
+            modified(old, new, &|kind| match kind {
+                "function_item" => similar,
+                _ => 0,
+            }),
+            [(0, 0)]
+        );
+    }
+}
@@ -0,0 +1,436 @@
+//! Three-Way Merge of Canonical Source
+//!
+//! The merge driver merges the *pretty-printed* versions of a file. Because
+//! all three are in canonical form, formatting never differs between them,
+//! and a line merge only sees real edits: two branches that reformatted the
+//! same code differently no longer conflict.
+//!
+//! ## Algorithm
+//!
+//! [`merge`] is a classic diff3: both sides are diffed against the base, and
+//! changes of one side that do not touch changes of the other are taken
+//! as-is. Changes from both sides that overlap (or touch) form a group; the
+//! group resolves cleanly if both sides made the identical change and is a
+//! conflict otherwise.
+//!
+//! A group can be widened by an `expand` function before it is decided. The
+//! driver uses this for per-node conflict markers: groups grow to whole
+//! top-level items ([`items`]), so a conflict always shows complete
+//! functions or classes on both sides instead of a few lines of each.
+//!
+//! ## Markers
+//!
+//! [`render`] writes conflicts in any of Git's `merge.conflictStyle`s:
+//! `merge` (ours and theirs), `diff3` (plus the base) and `zdiff3` (like
+//! `diff3`, with lines common to both sides moved out of the conflict).
+
+use crate::languages::LanguageProvider;
+use crate::parsing::{self, ParseLimits};
+use similar::{Algorithm, DiffOp};
+use std::ops::Range;
+
+/// A line-level merge result; line indices are 0-based into the versions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Merge<'a> {
+    pub base: Vec<&'a [u8]>,
+    pub ours: Vec<&'a [u8]>,
+    pub theirs: Vec<&'a [u8]>,
+    pub chunks: Vec<Chunk>,
+}
+
+/// A run of merged output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chunk {
+    /// Base lines neither side changed.
+    Stable(Range<usize>),
+    /// Only ours changed these lines; ours is taken.
+    Ours(Region),
+    /// Only theirs changed these lines; theirs is taken.
+    Theirs(Region),
+    /// Both sides made the same change; it is taken once.
+    Both(Region),
+    /// Overlapping, different changes.
+    Conflict(Region),
+}
+
+/// Corresponding line ranges of a changed region in the three versions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub base: Range<usize>,
+    pub ours: Range<usize>,
+    pub theirs: Range<usize>,
+}
+
+impl Merge<'_> {
+    /// The unresolved regions.
+    pub fn conflicts(&self) -> impl Iterator<Item = &Region> {
+        self.chunks.iter().filter_map(|chunk| match chunk {
+            Chunk::Conflict(conflict) => Some(conflict),
+            _ => None,
+        })
+    }
+
+    /// Whether every change merged cleanly.
+    pub fn is_clean(&self) -> bool {
+        self.conflicts().next().is_none()
+    }
+}
+
+/// `merge.conflictStyle`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictStyle {
+    #[default]
+    Merge,
+    Diff3,
+    Zdiff3,
+}
+
+impl ConflictStyle {
+    /// Parses a `merge.conflictStyle` value.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "merge" => Some(ConflictStyle::Merge),
+            "diff3" => Some(ConflictStyle::Diff3),
+            "zdiff3" => Some(ConflictStyle::Zdiff3),
+            _ => None,
+        }
+    }
+
+    /// The `merge.conflictStyle` value.
+    pub fn name(self) -> &'static str {
+        match self {
+            ConflictStyle::Merge => "merge",
+            ConflictStyle::Diff3 => "diff3",
+            ConflictStyle::Zdiff3 => "zdiff3",
+        }
+    }
+}
+
+/// Conflict marker labels (the text after `<<<<<<<`, `|||||||` and `>>>>>>>`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Labels {
+    pub base: String,
+    pub ours: String,
+    pub theirs: String,
+}
+
+/// A change of one side against the base.
+#[derive(Debug, Clone)]
+struct Hunk {
+    base: Range<usize>,
+    side: Range<usize>,
+}
+
+impl Hunk {
+    fn delta(&self) -> isize {
+        self.side.len() as isize - self.base.len() as isize
+    }
+}
+
+/// Splits `text` into lines, keeping each line's `\n`.
+pub fn lines(text: &[u8]) -> Vec<&[u8]> {
+    text.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Merges `ours` and `theirs` against `base`. `expand` may widen the base
+/// range of a group of changes before it is decided.
+pub fn merge<'a>(
+    base: &'a [u8],
+    ours: &'a [u8],
+    theirs: &'a [u8],
+    expand: &dyn Fn(Range<usize>) -> Range<usize>,
+) -> Merge<'a> {
+    let (base, ours, theirs) = (lines(base), lines(ours), lines(theirs));
+    let (ours_hunks, theirs_hunks) = (hunks(&base, &ours), hunks(&base, &theirs));
+    let mut chunks = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let (mut ours_delta, mut theirs_delta) = (0isize, 0isize);
+    let mut pos = 0;
+    loop {
+        let next = [ours_hunks.get(i), theirs_hunks.get(j)]
+            .into_iter()
+            .flatten()
+            .map(|h| h.base.start)
+            .min();
+        let Some(start) = next else {
+            break;
+        };
+        let mut range = start..start;
+        let (mut ei, mut ej) = (i, j);
+        loop {
+            let before = (range.clone(), ei, ej);
+            for (list, end) in [(&ours_hunks, &mut ei), (&theirs_hunks, &mut ej)] {
+                while let Some(h) = list.get(*end).filter(|h| h.base.start <= range.end) {
+                    range = range.start.min(h.base.start)..range.end.max(h.base.end);
+                    *end += 1;
+                }
+            }
+            let wider = expand(range.clone());
+            range = wider.start.max(pos).min(range.start)..wider.end.min(base.len()).max(range.end);
+            if (range.clone(), ei, ej) == before {
+                break;
+            }
+        }
+
+        if pos < range.start {
+            chunks.push(Chunk::Stable(pos..range.start));
+        }
+        let side = |delta: isize, hunks: &[Hunk]| {
+            let shift = |at: usize, d: isize| (at as isize + d) as usize;
+            let inside: isize = hunks.iter().map(Hunk::delta).sum();
+            shift(range.start, delta)..shift(range.end, delta + inside)
+        };
+        let o = side(ours_delta, &ours_hunks[i..ei]);
+        let t = side(theirs_delta, &theirs_hunks[j..ej]);
+        let region = Region {
+            base: range.clone(),
+            ours: o,
+            theirs: t,
+        };
+        chunks.push(if ej == j {
+            Chunk::Ours(region)
+        } else if ei == i {
+            Chunk::Theirs(region)
+        } else if ours[region.ours.clone()] == theirs[region.theirs.clone()] {
+            Chunk::Both(region)
+        } else {
+            Chunk::Conflict(region)
+        });
+        ours_delta += ours_hunks[i..ei].iter().map(Hunk::delta).sum::<isize>();
+        theirs_delta += theirs_hunks[j..ej].iter().map(Hunk::delta).sum::<isize>();
+        pos = range.end;
+        (i, j) = (ei, ej);
+    }
+    if pos < base.len() {
+        chunks.push(Chunk::Stable(pos..base.len()));
+    }
+    Merge {
+        base,
+        ours,
+        theirs,
+        chunks,
+    }
+}
+
+fn hunks(base: &[&[u8]], side: &[&[u8]]) -> Vec<Hunk> {
+    similar::capture_diff_slices(Algorithm::Myers, base, side)
+        .into_iter()
+        .filter_map(|op| match op {
+            DiffOp::Equal { .. } => None,
+            DiffOp::Delete {
+                old_index,
+                old_len,
+                new_index,
+            } => Some(Hunk {
+                base: old_index..old_index + old_len,
+                side: new_index..new_index,
+            }),
+            DiffOp::Insert {
+                old_index,
+                new_index,
+                new_len,
+            } => Some(Hunk {
+                base: old_index..old_index,
+                side: new_index..new_index + new_len,
+            }),
+            DiffOp::Replace {
+                old_index,
+                old_len,
+                new_index,
+                new_len,
+            } => Some(Hunk {
+                base: old_index..old_index + old_len,
+                side: new_index..new_index + new_len,
+            }),
+        })
+        .collect()
+}
+
+/// Writes the merged text, with markers of `marker_size` characters around
+/// each conflict. `symbol` may name what a conflict is in; the name is
+/// appended to the ours and theirs labels.
+pub fn render(
+    merge: &Merge<'_>,
+    style: ConflictStyle,
+    marker_size: usize,
+    labels: &Labels,
+    symbol: &dyn Fn(&Region) -> Option<String>,
+) -> Vec<u8> {
+    let mut out = Vec::new();
+    let push = |out: &mut Vec<u8>, lines: &[&[u8]]| {
+        for line in lines {
+            out.extend_from_slice(line);
+        }
+    };
+    let marker = |out: &mut Vec<u8>, c: char, label: &str| {
+        if !out.is_empty() && !out.ends_with(b"\n") {
+            out.push(b'\n');
+        }
+        out.resize(out.len() + marker_size, c as u8);
+        if !label.is_empty() {
+            out.push(b' ');
+            out.extend_from_slice(label.as_bytes());
+        }
+        out.push(b'\n');
+    };
+    for chunk in &merge.chunks {
+        match chunk {
+            Chunk::Stable(r) => push(&mut out, &merge.base[r.clone()]),
+            Chunk::Ours(r) | Chunk::Both(r) => push(&mut out, &merge.ours[r.ours.clone()]),
+            Chunk::Theirs(r) => push(&mut out, &merge.theirs[r.theirs.clone()]),
+            Chunk::Conflict(conflict) => {
+                let (mut ours, mut theirs) = (
+                    &merge.ours[conflict.ours.clone()],
+                    &merge.theirs[conflict.theirs.clone()],
+                );
+                let mut suffix: &[&[u8]] = &[];
+                if style == ConflictStyle::Zdiff3 {
+                    let prefix = ours.iter().zip(theirs).take_while(|(a, b)| a == b).count();
+                    push(&mut out, &ours[..prefix]);
+                    (ours, theirs) = (&ours[prefix..], &theirs[prefix..]);
+                    let common = ours
+                        .iter()
+                        .rev()
+                        .zip(theirs.iter().rev())
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                    suffix = &ours[ours.len() - common..];
+                    (ours, theirs) = (
+                        &ours[..ours.len() - common],
+                        &theirs[..theirs.len() - common],
+                    );
+                }
+                let named = |label: &str| match symbol(conflict) {
+                    Some(name) => format!("{} ({})", label, name),
+                    None => label.to_string(),
+                };
+                marker(&mut out, '<', &named(&labels.ours));
+                push(&mut out, ours);
+                if style != ConflictStyle::Merge {
+                    marker(&mut out, '|', &labels.base);
+                    push(&mut out, &merge.base[conflict.base.clone()]);
+                }
+                marker(&mut out, '=', "");
+                push(&mut out, theirs);
+                marker(&mut out, '>', &named(&labels.theirs));
+                push(&mut out, suffix);
+            }
+        }
+    }
+    out
+}
+
+/// A top-level syntax node of a file: its line range (0-based, end
+/// exclusive) and name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    pub lines: Range<usize>,
+    pub name: String,
+}
+
+/// The top-level items of `source` (functions, classes, ...), or nothing if
+/// it does not parse.
+pub fn items(source: &[u8], provider: &LanguageProvider) -> Vec<Item> {
+    let Ok(tree) = parsing::parse(source, provider, &ParseLimits::default()) else {
+        return Vec::new();
+    };
+    let root = tree.root_node();
+    let mut cursor = root.walk();
+    let items = root
+        .named_children(&mut cursor)
+        .filter(|node| !provider.syntax.comments.contains(&node.kind()))
+        .map(|node| {
+            let name = ["name", "type", "declarator"]
+                .iter()
+                .find_map(|field| node.child_by_field_name(field))
+                .and_then(|n| n.utf8_text(source).ok())
+                .unwrap_or(node.kind());
+            Item {
+                lines: node.start_position().row..node.end_position().row + 1,
+                name: name.to_string(),
+            }
+        })
+        .collect();
+    items
+}
+
+/// Items a base range touches: overlapping ones, or for an empty range
+/// (an insertion point) the one it falls strictly inside of.
+pub fn touched<'i>(items: &'i [Item], range: &Range<usize>) -> impl Iterator<Item = &'i Item> {
+    let range = range.clone();
+    items.iter().filter(move |item| {
+        if range.is_empty() {
+            item.lines.start < range.start && range.start < item.lines.end
+        } else {
+            item.lines.start < range.end && range.start < item.lines.end
+        }
+    })
+}
+
+/// Widens `range` to whole touched items.
+pub fn expand_to_items(items: &[Item], range: Range<usize>) -> Range<usize> {
+    touched(items, &range).fold(range.clone(), |r, item| {
+        r.start.min(item.lines.start)..r.end.max(item.lines.end)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::languages;
+
+    fn labels() -> Labels {
+        Labels {
+            base: "base".to_string(),
+            ours: "main".to_string(),
+            theirs: "topic".to_string(),
+        }
+    }
+
+    #[test]
+    fn merges_disjoint_changes_and_marks_overlaps() {
+        let base = b"a\nb\nc\nd\ne\n";
+        let clean = merge(base, b"A\nb\nc\nd\ne\n", b"a\nb\nc\nd\nE\n", &|r| r);
+        assert!(clean.is_clean());
+        let text = render(&clean, ConflictStyle::Merge, 7, &labels(), &|_| None);
+        assert_eq!(text, b"A\nb\nc\nd\nE\n");
+
+        let conflict = merge(base, b"a\nB\nc\nd\ne\n", b"a\nX\nc\nd\ne\n", &|r| r);
+        let text = render(&conflict, ConflictStyle::Diff3, 3, &labels(), &|_| {
+            Some("f".to_string())
+        });
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "a\n<<< main (f)\nB\n||| base\nb\n===\nX\n>>> topic (f)\nc\nd\ne\n"
+        );
+    }
+
+    #[test]
+    fn zdiff3_moves_common_lines_out() {
+        let merged = merge(b"x\n", b"p\nA\ns\n", b"p\nB\ns\n", &|r| r);
+        let text = render(&merged, ConflictStyle::Zdiff3, 1, &labels(), &|_| None);
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "p\n< main\nA\n| base\nx\n=\nB\n> topic\ns\n"
+        );
+    }
+
+    #[test]
+    fn node_expansion_covers_whole_items() {
+        let rust = languages::by_name("rust").unwrap();
+        let base = b"fn f() {\n    a();\n    b();\n}\n";
+        let ours = b"fn f() {\n    a1();\n    b();\n}\n";
+        let theirs = b"fn f() {\n    a2();\n    b();\n}\n";
+        let items = items(base, rust);
+        assert_eq!(
+            items,
+            [Item {
+                lines: 0..4,
+                name: "f".to_string()
+            }]
+        );
+        let merged = merge(base, ours, theirs, &|r| expand_to_items(&items, r));
+        let conflict = merged.conflicts().next().unwrap();
+        assert_eq!((conflict.base.clone(), conflict.ours.clone()), (0..4, 0..4));
+    }
+}
@@ -0,0 +1,561 @@
+//! Source Mirror and OID Map
+//!
+//! The AST repository and its source mirror (see the crate docs) hold the
+//! same history with different object ids: every AST blob has a smudged
+//! source counterpart, so every tree and commit differs too. This module
+//! produces the mirror history locally and records which objects
+//! correspond.
+//!
+//! ## Mirror Sync
+//!
+//! [`sync`] rewrites a branch commit by commit: each blob is smudged, trees
+//! and commits are rebuilt with the same names, modes, authors, dates and
+//! messages, and parents are the mirrored parents. The result goes to
+//! `refs/ast/mirror/<branch>`, ready to push to the mirror repository:
+//!
+//! ```sh
+//! git-ast mirror sync main
+//! git push source-mirror refs/ast/mirror/main:refs/heads/main
+//! ```
+//!
+//! Commits that are already mapped are not rewritten again, so repeated
+//! syncs only process new commits and always produce the same mirror ids.
+//!
+//! ## Export Branches
+//!
+//! Teams without a second repository can keep the mirror next to the AST
+//! branches instead: [`export`] syncs a branch and points
+//! `refs/heads/source/<branch>` at its mirror, so hosting platforms show
+//! readable source for it, and pull requests between `source/` branches
+//! review source diffs. Exported branches are read-only views: changes are
+//! made (and merged) on the AST branches and exported again. An export only
+//! moves forward; if `source/<branch>` was moved by anyone else, it is left
+//! alone unless forced.
+//!
+//! With `ast.exportBranch = true`, the `post-commit` and `post-merge` hooks
+//! keep the current branch's export up to date.
+//!
+//! ## OID Map
+//!
+//! The correspondence is stored as notes in `refs/notes/ast-map` (see
+//! [`notes`](crate::git_plumbing::notes)), on commits and on blobs, in both
+//! directions:
+//!
+//! ```text
+//! <ast commit>     -> "source <mirror commit>"
+//! <mirror commit>  -> "ast <ast commit>"
+//! ```
+//!
+//! Objects that are identical on both sides (files without a language) get
+//! no blob note. Push or fetch the notes ref to share the map; `git-ast map
+//! <oid>` looks objects up in either direction.
+//!
+//! ## Sidecar Mode
+//!
+//! Teams that keep plain source as their primary blobs can run the same
+//! rewrite the other way ([`Direction::ToAst`]): `git-ast sidecar sync`
+//! cleans every file with a language into an AST blob and stores the
+//! resulting history under `refs/ast/heads/<branch>`, recorded in the same
+//! OID map. Hosting platforms keep seeing ordinary source, while the AST
+//! objects are available locally ([`ast_blob_of`]); for example
+//! `git-ast review-anchors` reports AST lines for source blobs through them.
+//! Files that do not parse keep their source blob.
+//!
+//! With `ast.sidecar = true`, the `post-commit` and `post-merge` hooks
+//! (`git-ast hook post-commit`, `git-ast hook post-merge`) keep the current
+//! branch's sidecar up to date.
+
+use crate::config::{self, RepoConfigs};
+use crate::git_plumbing::{filters, notes};
+use crate::parsing::ParseLimits;
+use crate::serialization;
+use crate::{log_info, Error};
+use git2::{Commit, ObjectType, Oid, Repository, Sort, Tree};
+use std::collections::{BTreeMap, HashMap};
+
+/// Notes ref holding the OID map.
+pub const MAP_REF: &str = "refs/notes/ast-map";
+/// Namespace of mirrored branches.
+pub const MIRROR_PREFIX: &str = "refs/ast/mirror/";
+/// Namespace of sidecar AST branches.
+pub const SIDECAR_PREFIX: &str = "refs/ast/heads/";
+/// Git config key keeping the sidecar up to date from hooks.
+pub const SIDECAR_KEY: &str = "ast.sidecar";
+/// Namespace of exported source branches.
+pub const EXPORT_PREFIX: &str = "refs/heads/source/";
+/// Git config key keeping the current branch's export up to date from hooks.
+pub const EXPORT_BRANCH_KEY: &str = "ast.exportBranch";
+
+/// Which repository an object belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    /// The authoritative repository with AST blobs.
+    Ast,
+    /// The source mirror.
+    Source,
+}
+
+impl Side {
+    /// `ast` or `source`, as written in map notes.
+    pub fn name(self) -> &'static str {
+        match self {
+            Side::Ast => "ast",
+            Side::Source => "source",
+        }
+    }
+}
+
+/// The object corresponding to a looked-up object on the other side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Counterpart {
+    pub side: Side,
+    pub id: Oid,
+}
+
+/// Which way [`sync`] rewrites a branch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// AST blobs to source: the mirror of an AST repository.
+    ToSource,
+    /// Source blobs to AST: the sidecar of a source repository.
+    ToAst,
+}
+
+impl Direction {
+    /// The side the rewritten objects belong to.
+    pub fn target(self) -> Side {
+        match self {
+            Direction::ToSource => Side::Source,
+            Direction::ToAst => Side::Ast,
+        }
+    }
+
+    fn origin(self) -> Side {
+        match self {
+            Direction::ToSource => Side::Ast,
+            Direction::ToAst => Side::Source,
+        }
+    }
+
+    /// Namespace of the rewritten branches.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Direction::ToSource => MIRROR_PREFIX,
+            Direction::ToAst => SIDECAR_PREFIX,
+        }
+    }
+
+    fn command(self) -> &'static str {
+        match self {
+            Direction::ToSource => "mirror",
+            Direction::ToAst => "sidecar",
+        }
+    }
+}
+
+/// The AST blob recorded for source blob `id` by a sidecar sync.
+pub fn ast_blob_of(repo: &Repository, id: Oid) -> Result<Option<Oid>, Error> {
+    Ok(lookup(repo, id)?
+        .filter(|c| c.side == Side::Ast)
+        .map(|c| c.id))
+}
+
+/// Looks up the counterpart of `id` in the OID map.
+pub fn lookup(repo: &Repository, id: Oid) -> Result<Option<Counterpart>, Error> {
+    let Some(note) = notes::read(repo, MAP_REF, id)? else {
+        return Ok(None);
+    };
+    let malformed =
+        || Error::Serialization(format!("malformed {} note on {}: {:?}", MAP_REF, id, note));
+    let (side, hex) = note.trim().split_once(' ').ok_or_else(malformed)?;
+    let side = match side {
+        "ast" => Side::Ast,
+        "source" => Side::Source,
+        _ => return Err(malformed()),
+    };
+    Ok(Some(Counterpart {
+        side,
+        id: Oid::from_str(hex).map_err(|_| malformed())?,
+    }))
+}
+
+/// Result of a mirror sync.
+#[derive(Debug, Clone)]
+pub struct SyncReport {
+    /// The mirror ref that was updated.
+    pub reference: String,
+    /// The mirrored branch tip.
+    pub head: Oid,
+    /// Commits rewritten by this run (already mapped ones are skipped).
+    pub rewritten: usize,
+}
+
+/// Rewrites `branch` in `direction` to `refs/ast/mirror/<branch>` or
+/// `refs/ast/heads/<branch>` and records the OID map.
+pub fn sync(repo: &Repository, branch: &str, direction: Direction) -> Result<SyncReport, Error> {
+    let name = match repo.resolve_reference_from_short_name(branch) {
+        Ok(reference) => reference.shorthand().unwrap_or(branch).to_string(),
+        Err(_) => branch.to_string(),
+    };
+    let tip = repo.revparse_single(branch)?.peel_to_commit()?;
+    let message = format!("git-ast {} sync {}", direction.command(), name);
+    let (head, rewritten) = rewrite(repo, tip.id(), direction, &message)?;
+    let reference = format!("{}{}", direction.prefix(), name);
+    repo.reference(&reference, head, true, &message)?;
+    Ok(SyncReport {
+        reference,
+        head,
+        rewritten,
+    })
+}
+
+/// Rewrites commit `tip` and its history in `direction`, skipping commits
+/// the OID map already has, and records the map with `message`. Returns the
+/// rewritten tip and how many commits were rewritten.
+pub fn rewrite(
+    repo: &Repository,
+    tip: Oid,
+    direction: Direction,
+    message: &str,
+) -> Result<(Oid, usize), Error> {
+    let mut mirror = Mirror {
+        repo,
+        direction,
+        limits: config::Settings::load(Some(repo))?.parse_limits,
+        repo_configs: config::RepoConfigs::new(Some(repo)),
+        map: BTreeMap::new(),
+        trees: HashMap::new(),
+        blobs: HashMap::new(),
+    };
+    if let Some(head) = mirror.commit_of(tip)? {
+        return Ok((head, 0));
+    }
+
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    walk.push(tip)?;
+    let mut rewritten = 0;
+    for id in walk {
+        let commit = repo.find_commit(id?)?;
+        if mirror.commit_of(commit.id())?.is_none() {
+            mirror.commit(&commit)?;
+            rewritten += 1;
+        }
+    }
+
+    let head = mirror
+        .commit_of(tip)?
+        .ok_or_else(|| Error::Generation(format!("{} was not rewritten", tip)))?;
+    notes::write(repo, MAP_REF, &mirror.map, message)?;
+    Ok((head, rewritten))
+}
+
+/// Syncs `branch` to the source mirror and moves `source/<branch>` to the
+/// result (see [Export Branches](self#export-branches)). Unless `force` is
+/// set, an existing export must be an ancestor of the new one.
+pub fn export(repo: &Repository, branch: &str, force: bool) -> Result<SyncReport, Error> {
+    let short = branch.strip_prefix("refs/heads/").unwrap_or(branch);
+    if short.starts_with("source/") {
+        return Err(Error::Config(format!(
+            "{} is an exported branch; export the AST branch instead",
+            branch
+        )));
+    }
+    let mirror = sync(repo, branch, Direction::ToSource)?;
+    let name = mirror
+        .reference
+        .strip_prefix(MIRROR_PREFIX)
+        .unwrap_or(short);
+    let reference = format!("{}{}", EXPORT_PREFIX, name);
+    if let Ok(existing) = repo.refname_to_id(&reference) {
+        let forward = existing == mirror.head || repo.graph_descendant_of(mirror.head, existing)?;
+        if !forward && !force {
+            return Err(Error::Config(format!(
+                "{} ({}) is not an ancestor of the new export {}; it was moved outside \
+                 git-ast (use --force to replace it)",
+                reference, existing, mirror.head
+            )));
+        }
+    }
+    let message = format!("git-ast export-branch {}", name);
+    repo.reference(&reference, mirror.head, true, &message)?;
+    Ok(SyncReport {
+        reference,
+        head: mirror.head,
+        rewritten: mirror.rewritten,
+    })
+}
+
+struct Mirror<'r> {
+    repo: &'r Repository,
+    direction: Direction,
+    limits: ParseLimits,
+    repo_configs: RepoConfigs,
+    /// Notes to write at the end of the run.
+    map: BTreeMap<Oid, String>,
+    trees: HashMap<Oid, Oid>,
+    blobs: HashMap<Oid, Oid>,
+}
+
+impl Mirror<'_> {
+    fn record(&mut self, from: Oid, to: Oid) {
+        let (origin, target) = (self.direction.origin(), self.direction.target());
+        self.map.insert(from, format!("{} {}\n", target.name(), to));
+        if to != from {
+            self.map.insert(to, format!("{} {}\n", origin.name(), from));
+        }
+    }
+
+    /// The rewritten commit of `id`, from this run or the map.
+    fn commit_of(&self, id: Oid) -> Result<Option<Oid>, Error> {
+        let target = self.direction.target();
+        if let Some(note) = self.map.get(&id) {
+            let hex = note.strip_prefix(target.name()).unwrap_or_default();
+            return Ok(Oid::from_str(hex.trim()).ok());
+        }
+        Ok(lookup(self.repo, id)?
+            .filter(|c| c.side == target)
+            .map(|c| c.id))
+    }
+
+    fn commit(&mut self, commit: &Commit<'_>) -> Result<Oid, Error> {
+        let tree = self.tree(&commit.tree()?, "")?;
+        let tree = self.repo.find_tree(tree)?;
+        let mut parents = Vec::new();
+        for parent in commit.parent_ids() {
+            let mirrored = self.commit_of(parent)?.ok_or_else(|| {
+                Error::Generation(format!(
+                    "parent {} of {} is not mirrored",
+                    parent,
+                    commit.id()
+                ))
+            })?;
+            parents.push(self.repo.find_commit(mirrored)?);
+        }
+        let parents: Vec<&Commit<'_>> = parents.iter().collect();
+        let message = String::from_utf8_lossy(commit.message_bytes());
+        let id = self.repo.commit(
+            None,
+            &commit.author(),
+            &commit.committer(),
+            &message,
+            &tree,
+            &parents,
+        )?;
+        self.record(commit.id(), id);
+        Ok(id)
+    }
+
+    fn tree(&mut self, tree: &Tree<'_>, prefix: &str) -> Result<Oid, Error> {
+        if let Some(&id) = self.trees.get(&tree.id()) {
+            return Ok(id);
+        }
+        let mut builder = self.repo.treebuilder(None)?;
+        for entry in tree.iter() {
+            let name = entry.name().unwrap_or_default();
+            let path = format!("{}{}", prefix, name);
+            let id = match entry.kind() {
+                Some(ObjectType::Tree) => {
+                    self.tree(&self.repo.find_tree(entry.id())?, &format!("{}/", path))?
+                }
+                Some(ObjectType::Blob) => self.blob(entry.id(), &path)?,
+                _ => entry.id(), // Submodule commit.
+            };
+            builder.insert(entry.name_bytes(), id, entry.filemode())?;
+        }
+        let id = builder.write()?;
+        self.trees.insert(tree.id(), id);
+        Ok(id)
+    }
+
+    fn blob(&mut self, id: Oid, path: &str) -> Result<Oid, Error> {
+        if let Some(&converted) = self.blobs.get(&id) {
+            return Ok(converted);
+        }
+        let blob = self.repo.find_blob(id)?;
+        let content = match self.direction {
+            Direction::ToSource => filters::perform_smudge(blob.content(), path)?,
+            Direction::ToAst => self.clean(blob.content(), path)?,
+        };
+        let converted = self.repo.blob(&content)?;
+        if converted != id {
+            self.record(id, converted);
+        }
+        self.blobs.insert(id, converted);
+        Ok(converted)
+    }
+
+    /// The AST blob for source `content`, or `content` itself if it has no
+    /// language or does not parse (the sidecar must not stop on one file).
+    fn clean(&self, content: &[u8], path: &str) -> Result<Vec<u8>, Error> {
+        if serialization::is_ast_blob(content) {
+            return Ok(content.to_vec());
+        }
+        let provider = config::language_for_path(Some(self.repo), path, content)?;
+        let repo_config = self.repo_configs.for_path(path)?;
+        match filters::clean_with(content, path, provider, &self.limits, &repo_config) {
+            Ok(ast) => Ok(ast),
+            Err(e) => {
+                log_info!("sidecar", "keeping source for {}: {}", path, e);
+                Ok(content.to_vec())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::languages;
+
+    #[test]
+    fn sync_is_incremental_and_maps_both_ways() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let signature = git2::Signature::now("t", "t@example.com").unwrap();
+        let ast = filters::clean_as(
+            b"fn main() {}\n",
+            "main.rs",
+            languages::by_name("rust"),
+            &ParseLimits::default(),
+        )
+        .unwrap();
+        let blob = repo.blob(&ast).unwrap();
+        let mut builder = repo.treebuilder(None).unwrap();
+        builder.insert("main.rs", blob, 0o100644).unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let first = repo
+            .commit(
+                Some("refs/heads/main"),
+                &signature,
+                &signature,
+                "one",
+                &tree,
+                &[],
+            )
+            .unwrap();
+
+        let report = sync(&repo, "main", Direction::ToSource).unwrap();
+        assert_eq!(report.reference, "refs/ast/mirror/main");
+        assert_eq!(report.rewritten, 1);
+        let mirrored = repo.find_commit(report.head).unwrap();
+        let source = mirrored.tree().unwrap().get_name("main.rs").unwrap().id();
+        assert_eq!(repo.find_blob(source).unwrap().content(), b"fn main() {}\n");
+
+        let parent = repo.find_commit(first).unwrap();
+        repo.commit(
+            Some("refs/heads/main"),
+            &signature,
+            &signature,
+            "two",
+            &tree,
+            &[&parent],
+        )
+        .unwrap();
+        let again = sync(&repo, "main", Direction::ToSource).unwrap();
+        assert_eq!(again.rewritten, 1);
+        assert_eq!(
+            repo.find_commit(again.head).unwrap().parent_id(0).unwrap(),
+            report.head
+        );
+
+        let back = lookup(&repo, report.head).unwrap().unwrap();
+        assert_eq!(
+            back,
+            Counterpart {
+                side: Side::Ast,
+                id: first
+            }
+        );
+        let forward = lookup(&repo, blob).unwrap().unwrap();
+        assert_eq!(
+            forward,
+            Counterpart {
+                side: Side::Source,
+                id: source
+            }
+        );
+    }
+
+    #[test]
+    fn export_moves_source_branch_forward_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let signature = git2::Signature::now("t", "t@example.com").unwrap();
+        let tree = repo
+            .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+            .unwrap();
+        let first = repo
+            .commit(
+                Some("refs/heads/main"),
+                &signature,
+                &signature,
+                "one",
+                &tree,
+                &[],
+            )
+            .unwrap();
+
+        let report = export(&repo, "main", false).unwrap();
+        assert_eq!(report.reference, "refs/heads/source/main");
+        assert_eq!(
+            repo.refname_to_id("refs/heads/source/main").unwrap(),
+            report.head
+        );
+        assert!(export(&repo, "source/main", false).is_err());
+
+        let stray = repo
+            .commit(None, &signature, &signature, "stray", &tree, &[])
+            .unwrap();
+        repo.reference("refs/heads/source/main", stray, true, "")
+            .unwrap();
+        let parent = repo.find_commit(first).unwrap();
+        repo.commit(
+            Some("refs/heads/main"),
+            &signature,
+            &signature,
+            "two",
+            &tree,
+            &[&parent],
+        )
+        .unwrap();
+        assert!(export(&repo, "main", false).is_err());
+        let forced = export(&repo, "main", true).unwrap();
+        assert_eq!(
+            repo.find_commit(forced.head).unwrap().parent_id(0).unwrap(),
+            report.head
+        );
+    }
+
+    #[test]
+    fn sidecar_records_ast_blobs_for_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let signature = git2::Signature::now("t", "t@example.com").unwrap();
+        let source = repo.blob(b"fn main() {}\n").unwrap();
+        let broken = repo.blob(b"fn main( {\n").unwrap();
+        let mut builder = repo.treebuilder(None).unwrap();
+        builder.insert("main.rs", source, 0o100644).unwrap();
+        builder.insert("broken.rs", broken, 0o100644).unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        repo.commit(
+            Some("refs/heads/main"),
+            &signature,
+            &signature,
+            "one",
+            &tree,
+            &[],
+        )
+        .unwrap();
+
+        let report = sync(&repo, "main", Direction::ToAst).unwrap();
+        assert_eq!(report.reference, "refs/ast/heads/main");
+        let ast = ast_blob_of(&repo, source).unwrap().unwrap();
+        assert!(serialization::is_ast_blob(
+            repo.find_blob(ast).unwrap().content()
+        ));
+        assert_eq!(ast_blob_of(&repo, broken).unwrap(), None);
+        let sidecar = repo.find_commit(report.head).unwrap().tree().unwrap();
+        assert_eq!(sidecar.get_name("broken.rs").unwrap().id(), broken);
+    }
+}
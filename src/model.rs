@@ -0,0 +1,253 @@
+//! Public Data Model
+//!
+//! The types of the JSON documents `git-ast` emits about code: the
+//! `--format json` output of the commands that report on changes, history
+//! and symbols (`review-anchors`, `review`, `when-changed`, `owners`,
+//! `report unused`, `pickaxe`) and the results of the [`rpc`](crate::rpc)
+//! methods. They are kept apart from the types the rest of the crate works
+//! with (`Symbol`, `Touch`, `Anchor`, ...), which convert into them, so that
+//! reworking internals cannot change the output by accident. They also
+//! derive `Deserialize`, for tools written in Rust that read the output back.
+//!
+//! -   [`ChangeSet`]: the structural changes between two revisions, each with
+//!     its line ranges in the source and in the stored AST.
+//! -   [`NodeRef`]: a syntax node, by path, kind and position.
+//! -   [`BlameEntry`]: a commit that changed a symbol or line.
+//! -   [`SymbolRecord`]: a definition, by kind, name and position.
+//! -   [`Page`]: one page of a long list of items, with the cursor to the
+//!     next.
+//!
+//! ## Versioning
+//!
+//! Every document is a [`Document`]: its fields, plus `"version"` set to
+//! [`VERSION`]. Adding a field is compatible and keeps the version;
+//! renaming, retyping or removing one bumps it. Consumers should ignore
+//! fields they do not know.
+//!
+//! Reports about `git-ast` itself rather than about code (`bench`,
+//! `report usage`, `audit`, `languages`) keep their own shapes.
+
+use crate::commands::review_anchors::Anchor;
+use crate::drivers::LineRange;
+use crate::ownership::Touch;
+use crate::symbols::Symbol;
+use crate::Error;
+use serde::{Deserialize, Serialize};
+
+/// Version of the data model, written into every [`Document`].
+pub const VERSION: u32 = 1;
+
+/// A versioned JSON document.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Document<T> {
+    pub version: u32,
+    #[serde(flatten)]
+    pub body: T,
+}
+
+impl<T: Serialize> Document<T> {
+    /// `body` at the current [`VERSION`].
+    pub fn new(body: T) -> Self {
+        Document {
+            version: VERSION,
+            body,
+        }
+    }
+
+    /// The document as pretty-printed JSON.
+    pub fn to_json(&self) -> Result<String, Error> {
+        serde_json::to_string_pretty(self).map_err(|e| Error::Serialization(e.to_string()))
+    }
+
+    pub fn to_value(&self) -> Result<serde_json::Value, Error> {
+        serde_json::to_value(self).map_err(|e| Error::Serialization(e.to_string()))
+    }
+}
+
+/// A position in a file: 1-based line and column, columns counting bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A syntax node: where it is and what the grammar calls it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeRef {
+    pub path: String,
+    /// The grammar's node kind (`function_item`, `call`, ...).
+    pub kind: String,
+    pub start: Position,
+    /// Just past the node's last byte.
+    pub end: Position,
+}
+
+/// The smallest named node at a position, with the innermost definition
+/// containing it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeAt {
+    #[serde(flatten)]
+    pub node: NodeRef,
+    pub definition: Option<SymbolRecord>,
+}
+
+/// A definition.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct SymbolRecord {
+    /// Definition kind: a node kind, or the `<kind>` of a `symbols.scm`
+    /// `@definition.<kind>` capture.
+    pub kind: String,
+    pub name: String,
+    pub path: String,
+    /// 1-based line of the name.
+    pub line: usize,
+}
+
+impl From<Symbol> for SymbolRecord {
+    fn from(symbol: Symbol) -> Self {
+        SymbolRecord {
+            kind: symbol.kind,
+            name: symbol.name,
+            path: symbol.path,
+            line: symbol.line,
+        }
+    }
+}
+
+/// A commit that changed a symbol or line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlameEntry {
+    pub commit: String,
+    /// First line of the commit message.
+    pub summary: String,
+    /// Author name and email, after `.mailmap`.
+    pub author: String,
+    pub email: String,
+    /// Author time, in seconds since the epoch.
+    pub time: i64,
+}
+
+impl From<Touch> for BlameEntry {
+    fn from(touch: Touch) -> Self {
+        BlameEntry {
+            commit: touch.commit,
+            summary: touch.summary,
+            author: touch.author,
+            email: touch.email,
+            time: touch.time,
+        }
+    }
+}
+
+/// A symbol with the commits that changed it, newest first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SymbolHistory {
+    #[serde(flatten)]
+    pub symbol: SymbolRecord,
+    pub commits: Vec<BlameEntry>,
+}
+
+/// The history of each symbol of a file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct History {
+    pub symbols: Vec<SymbolHistory>,
+}
+
+/// An inclusive, 1-based line range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lines {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl From<LineRange> for Lines {
+    fn from(range: LineRange) -> Self {
+        Lines {
+            start: range.start,
+            end: range.end,
+        }
+    }
+}
+
+/// Line ranges before and after a change; `old` is `null` for insertions
+/// and `new` for deletions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sides {
+    pub old: Option<Lines>,
+    pub new: Option<Lines>,
+}
+
+/// One structural change, anchored in both renderings of its file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    pub path: String,
+    /// Lines in the smudged source (the mirror).
+    pub source: Sides,
+    /// Lines in the stored AST blob.
+    pub ast: Sides,
+}
+
+impl From<Anchor> for Change {
+    fn from(anchor: Anchor) -> Self {
+        let sides = |old: Option<LineRange>, new: Option<LineRange>| Sides {
+            old: old.map(Lines::from),
+            new: new.map(Lines::from),
+        };
+        Change {
+            path: anchor.path,
+            source: sides(anchor.source.old, anchor.source.new),
+            ast: sides(anchor.ast.old, anchor.ast.new),
+        }
+    }
+}
+
+/// The structural changes between two revisions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeSet {
+    /// Commit compared from; `null` for the empty tree.
+    pub base: Option<String>,
+    /// Commit compared to; `null` for the working tree.
+    pub head: Option<String>,
+    pub anchors: Vec<Change>,
+}
+
+/// Some of a list's items, and where to continue.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Passed back to get the next page; `null` at the end of the list.
+    pub cursor: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_carry_the_version_and_read_back() {
+        let symbol = Symbol {
+            kind: "function_item".to_string(),
+            name: "parse".to_string(),
+            path: "src/a.rs".to_string(),
+            line: 3,
+        };
+        let document = Document::new(SymbolHistory {
+            symbol: symbol.into(),
+            commits: Vec::new(),
+        });
+        let value = document.to_value().unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "version": VERSION,
+                "kind": "function_item",
+                "name": "parse",
+                "path": "src/a.rs",
+                "line": 3,
+                "commits": [],
+            })
+        );
+        let back: Document<SymbolHistory> = serde_json::from_value(value).unwrap();
+        assert_eq!(back, document);
+    }
+}
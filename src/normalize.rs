@@ -0,0 +1,179 @@
+//! Normalization Pass
+//!
+//! The stored tree already ignores layout, but two contributors can still
+//! write the *same* code differently: imports in another order, a trailing
+//! comma, a stray space at the end of a comment line. With the matching
+//! `[normalize.<language>]` settings in `.git-ast.toml` (see
+//! [`NormalizeConfig`]), the clean filter rewrites the tree before
+//! serializing it, so such variants store as identical blobs:
+//!
+//! -   `sort_imports`: Sorts runs of import statements (the provider's
+//!     [`imports`](crate::languages::SyntaxRules::imports)) by their text. A
+//!     run ends at a blank line, a comment or any other statement, and an
+//!     import carrying an attribute (`#[cfg(..)] use ...`) stays where it is.
+//!     Items of import lists (`use a::{c, b}`, `import { c, b } from`) are
+//!     sorted too, unless a comment sits among them. Note that in languages
+//!     where imports have side effects (Python, JavaScript) this can change
+//!     behaviour; only enable it where import order does not matter.
+//! -   `trailing_commas`: Drops the comma before a closing `)`, `]` or `}`
+//!     when the list holds at least two items (so one-element tuples keep
+//!     theirs) and the comma does not follow another comma (array holes).
+//!     Macro arguments are left alone.
+//! -   `comment_whitespace`: Strips spaces and tabs at the end of each line
+//!     of a comment.
+//!
+//! Every rewrite yields a tree that the printer and parser reproduce
+//! exactly, so normalized blobs pass the round-trip verification like any
+//! other.
+
+use crate::ast::Node;
+use crate::config::NormalizeConfig;
+use crate::languages::LanguageProvider;
+
+/// Kinds whose children are opaque token streams (macro arguments).
+const OPAQUE: &[&str] = &["token_tree"];
+
+/// Applies the enabled normalizations to `root` (embedded-language trees
+/// are left alone).
+pub fn apply(root: &mut Node, provider: &LanguageProvider, config: &NormalizeConfig) {
+    if *config == NormalizeConfig::default() {
+        return;
+    }
+    let rules = &provider.syntax;
+    if config.comment_whitespace {
+        for leaf in root.leaves_mut() {
+            if rules.comments.contains(&leaf.kind.as_str()) {
+                if let Some(text) = &mut leaf.text {
+                    *text = strip_trailing_whitespace(text);
+                }
+            }
+        }
+    }
+    visit(root, provider, config);
+}
+
+fn visit(node: &mut Node, provider: &LanguageProvider, config: &NormalizeConfig) {
+    if OPAQUE.contains(&node.kind.as_str()) {
+        return;
+    }
+    let rules = &provider.syntax;
+    if config.sort_imports {
+        sort_import_runs(&mut node.children, rules.imports);
+        if rules.import_lists.contains(&node.kind.as_str()) {
+            sort_list_items(&mut node.children, rules.comments);
+        }
+    }
+    if config.trailing_commas {
+        drop_trailing_comma(&mut node.children);
+    }
+    for child in &mut node.children {
+        visit(child, provider, config);
+    }
+}
+
+fn strip_trailing_whitespace(text: &str) -> String {
+    let lines: Vec<&str> = text
+        .split('\n')
+        .map(|line| line.trim_end_matches([' ', '\t']))
+        .collect();
+    lines.join("\n")
+}
+
+/// The tokens of `node`, space-separated: the sort key.
+fn key(node: &Node) -> String {
+    let texts: Vec<&str> = node
+        .leaves()
+        .iter()
+        .filter_map(|l| l.text.as_deref())
+        .collect();
+    texts.join(" ")
+}
+
+fn sort_import_runs(children: &mut [Node], imports: &[&str]) {
+    let sortable = |children: &[Node], i: usize| {
+        imports.contains(&children[i].kind.as_str())
+            && !(i > 0 && children[i - 1].kind.contains("attribute"))
+    };
+    let mut i = 0;
+    while i < children.len() {
+        if !sortable(children, i) {
+            i += 1;
+            continue;
+        }
+        let mut end = i + 1;
+        while end < children.len() && sortable(children, end) && !children[end].blank_line_before {
+            end += 1;
+        }
+        sort_in_place(&mut children[i..end], &(0..end - i).collect::<Vec<_>>());
+        i = end;
+    }
+}
+
+fn sort_list_items(children: &mut [Node], comments: &[&str]) {
+    if children.iter().any(|c| comments.contains(&c.kind.as_str())) {
+        return;
+    }
+    let positions: Vec<usize> = (0..children.len()).filter(|&i| children[i].named).collect();
+    sort_in_place(children, &positions);
+}
+
+/// Sorts the nodes at `positions` by [`key`], leaving each position's
+/// layout flags in place.
+fn sort_in_place(children: &mut [Node], positions: &[usize]) {
+    let mut nodes: Vec<Node> = positions.iter().map(|&i| children[i].clone()).collect();
+    nodes.sort_by_cached_key(key);
+    for (&i, mut node) in positions.iter().zip(nodes) {
+        node.blank_line_before = children[i].blank_line_before;
+        node.own_line = children[i].own_line;
+        node.field = children[i].field.clone();
+        children[i] = node;
+    }
+}
+
+fn drop_trailing_comma(children: &mut Vec<Node>) {
+    let n = children.len();
+    let is = |node: &Node, token: &str| !node.named && node.kind == token;
+    if n < 3
+        || !["(", "[", "{"]
+            .iter()
+            .zip([")", "]", "}"])
+            .any(|(open, close)| is(&children[0], open) && is(&children[n - 1], close))
+    {
+        return;
+    }
+    let commas = children.iter().filter(|c| is(c, ",")).count();
+    if is(&children[n - 2], ",") && !is(&children[n - 3], ",") && commas >= 2 {
+        children.remove(n - 2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git_plumbing::filters;
+    use crate::languages;
+
+    #[test]
+    fn equivalent_variants_normalize_to_one_tree() {
+        let rust = languages::by_name("rust").unwrap();
+        let all = NormalizeConfig {
+            sort_imports: true,
+            trailing_commas: true,
+            comment_whitespace: true,
+        };
+        let clean = |source: &str| {
+            let tree = crate::parsing::parse(source.as_bytes(), rust, &Default::default()).unwrap();
+            let mut root = crate::ast::from_tree(&tree, source.as_bytes(), rust).unwrap();
+            apply(&mut root, rust, &all);
+            root
+        };
+        let a = clean("use b::{y, x};\nuse a;\n\n// note  \nfn f() { g(1, 2,); h((3,)); }\n");
+        let b = clean("use a;\nuse b::{x, y};\n\n// note\nfn f() { g(1, 2); h((3,)); }\n");
+        assert_eq!(a, b);
+
+        // The normalized tree survives printing and parsing unchanged.
+        let header = crate::serialization::BlobHeader::for_provider(rust);
+        let blob = crate::serialization::encode(&header, &a).unwrap();
+        filters::verify_round_trip(&blob, "a.rs").unwrap();
+    }
+}
@@ -0,0 +1,372 @@
+//! Ownership per Symbol
+//!
+//! `CODEOWNERS` assigns whole files, and `git blame` assigns lines, so both
+//! credit whoever last reformatted or moved code. [`owners`] instead follows
+//! each definition of a file (see [`symbols`](crate::symbols)) back through
+//! the file's history and credits the commits that changed its tokens:
+//!
+//! ```text
+//! $ git-ast owners src/parse.rs:parse_header
+//! function_item parse_header (src/parse.rs:40)
+//!     Ada Lovelace <ada@example.com>  5 commits, last 1a2b3c4
+//!     @acme/infra                     1 commit, last 5d6e7f8  (latest)
+//! ```
+//!
+//! A commit touches a symbol when the definition's leaf tokens differ from
+//! the parent's (or the parent has no such definition), so formatting-only
+//! commits never count. Symbols are matched by kind and name within the
+//! file; renames of the file itself are not followed. Merge commits are
+//! skipped, and authors go through the repository's `.mailmap`.
+//!
+//! When the symbol history index (see [`symbol_history`](crate::symbol_history))
+//! covers `HEAD`, the commits are looked up there instead of walking the
+//! history and parsing every version of the file.
+//!
+//! Authors listed under `[owners.teams]` in `.git-ast.toml` are credited to
+//! their team instead (see [`OwnersConfig`]), which is also the handle a
+//! `CODEOWNERS` suggestion uses; other authors are named by email.
+
+use crate::config::OwnersConfig;
+use crate::model::SymbolRecord;
+use crate::parsing::ParseLimits;
+use crate::queries::QueryPacks;
+use crate::symbol_history::SymbolHistoryIndex;
+use crate::symbols::{self, Symbol};
+use crate::Error;
+use git2::{Commit, DiffOptions, Mailmap, Oid, Repository, Sort};
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// Someone who changed a symbol.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Owner {
+    /// A team handle, or the author's email.
+    pub handle: String,
+    /// `Name <email>` for authors, the handle for teams.
+    pub display: String,
+    /// Commits that changed the symbol.
+    pub commits: usize,
+    /// The most recent of them, and its author time (seconds since the epoch).
+    pub last_commit: String,
+    pub last_time: i64,
+}
+
+/// A symbol and its owners, most frequent first (most recent on ties).
+#[derive(Debug, Clone, Serialize)]
+pub struct SymbolOwners {
+    #[serde(flatten)]
+    pub symbol: SymbolRecord,
+    pub owners: Vec<Owner>,
+    /// Handle of whoever changed it last.
+    pub latest: Option<String>,
+}
+
+/// A commit that changed a symbol. Output uses
+/// [`BlameEntry`](crate::model::BlameEntry).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Touch {
+    pub commit: String,
+    /// First line of the commit message.
+    pub summary: String,
+    /// After `.mailmap`.
+    pub author: String,
+    pub email: String,
+    /// Author time, in seconds since the epoch.
+    pub time: i64,
+}
+
+impl Touch {
+    /// `commit`, with its author mapped through `mailmap`.
+    pub(crate) fn new(commit: &Commit, mailmap: &Mailmap) -> Result<Touch, Error> {
+        let author = commit.author_with_mailmap(mailmap)?;
+        Ok(Touch {
+            commit: commit.id().to_string(),
+            summary: commit.summary().unwrap_or_default().to_string(),
+            author: author.name().unwrap_or_default().to_string(),
+            email: author.email().unwrap_or_default().to_string(),
+            time: author.when().seconds(),
+        })
+    }
+}
+
+/// The commits, newest first, that changed each symbol of `path` at `HEAD`
+/// (or each named `symbol`), as described in the module docs.
+pub fn history(
+    repo: &Repository,
+    path: &str,
+    symbol: Option<&str>,
+    limits: &ParseLimits,
+    packs: &mut QueryPacks,
+) -> Result<Vec<(Symbol, Vec<Touch>)>, Error> {
+    let head = repo.head()?.peel_to_commit()?;
+    let mut current = fingerprints(
+        repo,
+        head.tree()?.get_path(path.as_ref())?.id(),
+        path,
+        limits,
+        packs,
+    )?;
+    if let Some(name) = symbol {
+        current.retain(|(s, _)| s.name == name);
+        if current.is_empty() {
+            return Err(Error::Config(format!(
+                "{}: no symbol named {:?}",
+                path, name
+            )));
+        }
+    }
+    let mailmap = repo.mailmap().unwrap_or(Mailmap::new()?);
+
+    // A damaged index is ignored; `git-ast index build --full` rewrites it.
+    if let Some(index) = SymbolHistoryIndex::load(repo).ok().flatten() {
+        if index.contains(head.id()) {
+            let mut found = Vec::new();
+            for (symbol, _) in current {
+                let mut touches = Vec::new();
+                for id in index.commits(path, &symbol.kind, &symbol.name) {
+                    // The index also covers other branches.
+                    if id == head.id() || repo.graph_descendant_of(head.id(), id)? {
+                        touches.push(Touch::new(&repo.find_commit(id)?, &mailmap)?);
+                    }
+                }
+                found.push((symbol, touches));
+            }
+            return Ok(found);
+        }
+    }
+
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+    walk.push(head.id())?;
+    let mut touches: Vec<Vec<Touch>> = vec![Vec::new(); current.len()];
+    let mut diff_options = DiffOptions::new();
+    diff_options.pathspec(path).disable_pathspec_match(true);
+    for oid in walk {
+        let commit = repo.find_commit(oid?)?;
+        if commit.parent_count() > 1 {
+            continue;
+        }
+        let parent = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            Err(_) => None,
+        };
+        let diff = repo.diff_tree_to_tree(
+            parent.as_ref(),
+            Some(&commit.tree()?),
+            Some(&mut diff_options),
+        )?;
+        let Some(delta) = diff.deltas().next() else {
+            continue;
+        };
+        let (old, new) = (delta.old_file().id(), delta.new_file().id());
+        if new.is_zero() {
+            continue;
+        }
+        let before = match old.is_zero() {
+            true => Vec::new(),
+            false => fingerprints(repo, old, path, limits, packs)?,
+        };
+        let after = fingerprints(repo, new, path, limits, packs)?;
+        for (i, (symbol, _)) in current.iter().enumerate() {
+            let key = |(s, _): &&(Symbol, u64)| s.kind == symbol.kind && s.name == symbol.name;
+            let new_print = after.iter().find(key).map(|(_, print)| print);
+            let old_print = before.iter().find(key).map(|(_, print)| print);
+            if new_print.is_none() || new_print == old_print {
+                continue;
+            }
+            touches[i].push(Touch::new(&commit, &mailmap)?);
+        }
+    }
+    Ok(current
+        .into_iter()
+        .map(|(symbol, _)| symbol)
+        .zip(touches)
+        .collect())
+}
+
+/// The owners of each symbol of `path` at `HEAD`, or of those named `symbol`.
+pub fn owners(
+    repo: &Repository,
+    path: &str,
+    symbol: Option<&str>,
+    config: &OwnersConfig,
+    limits: &ParseLimits,
+    packs: &mut QueryPacks,
+) -> Result<Vec<SymbolOwners>, Error> {
+    let teams: HashMap<&str, &str> = config
+        .teams
+        .iter()
+        .flat_map(|(team, members)| members.iter().map(move |m| (m.as_str(), team.as_str())))
+        .collect();
+    let history = history(repo, path, symbol, limits, packs)?;
+    Ok(history
+        .into_iter()
+        .map(|(symbol, touches)| {
+            let mut owners: Vec<Owner> = Vec::new();
+            // Newest first, so an owner's first commit is their last.
+            for touch in touches {
+                let (handle, display) = match teams.get(touch.email.as_str()) {
+                    Some(team) => (team.to_string(), team.to_string()),
+                    None => (
+                        touch.email.clone(),
+                        format!("{} <{}>", touch.author, touch.email),
+                    ),
+                };
+                match owners.iter_mut().find(|o| o.handle == handle) {
+                    Some(owner) => owner.commits += 1,
+                    None => owners.push(Owner {
+                        handle,
+                        display,
+                        commits: 1,
+                        last_commit: touch.commit,
+                        last_time: touch.time,
+                    }),
+                }
+            }
+            let latest = owners.first().map(|o| o.handle.clone());
+            owners.sort_by(|a, b| {
+                b.commits
+                    .cmp(&a.commits)
+                    .then(b.last_time.cmp(&a.last_time))
+            });
+            SymbolOwners {
+                symbol: symbol.into(),
+                owners,
+                latest,
+            }
+        })
+        .collect())
+}
+
+/// A suggested `CODEOWNERS` rule for `path`: the top owner of each symbol,
+/// those owning the most symbols first.
+pub fn codeowners_rule(path: &str, symbols: &[SymbolOwners]) -> Option<String> {
+    let mut handles: Vec<(&str, usize)> = Vec::new();
+    for top in symbols.iter().filter_map(|s| s.owners.first()) {
+        match handles.iter_mut().find(|(h, _)| *h == top.handle) {
+            Some((_, n)) => *n += 1,
+            None => handles.push((&top.handle, 1)),
+        }
+    }
+    if handles.is_empty() {
+        return None;
+    }
+    handles.sort_by_key(|&(_, n)| std::cmp::Reverse(n));
+    let handles: Vec<&str> = handles.into_iter().map(|(h, _)| h).collect();
+    Some(format!("/{} {}", path, handles.join(" ")))
+}
+
+/// The definitions of a blob with a hash of each one's leaf tokens.
+fn fingerprints(
+    repo: &Repository,
+    blob: Oid,
+    path: &str,
+    limits: &ParseLimits,
+    packs: &mut QueryPacks,
+) -> Result<Vec<(Symbol, u64)>, Error> {
+    blob_fingerprints(repo, blob, path, limits, packs)?
+        .ok_or_else(|| Error::Config(format!("{}: not a file of a supported language", path)))
+}
+
+/// Like `fingerprints`, but `None` for blobs without a language.
+pub(crate) fn blob_fingerprints(
+    repo: &Repository,
+    blob: Oid,
+    path: &str,
+    limits: &ParseLimits,
+    packs: &mut QueryPacks,
+) -> Result<Option<Vec<(Symbol, u64)>>, Error> {
+    let Some((provider, source, tree)) = symbols::parse_blob(repo, blob, path, limits)? else {
+        return Ok(None);
+    };
+    let pack = packs.get(provider)?;
+    let definitions = symbols::definition_nodes(&tree, &source, path, provider, pack);
+    Ok(Some(
+        definitions
+            .into_iter()
+            .map(|(symbol, node)| {
+                let mut hasher = DefaultHasher::new();
+                symbols::visit(&mut node.walk(), &mut |n| {
+                    if n.child_count() == 0 {
+                        source[n.byte_range()].hash(&mut hasher);
+                    }
+                });
+                (symbol, hasher.finish())
+            })
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Signature;
+    use std::path::Path;
+
+    #[test]
+    fn credits_token_changes_to_their_authors() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let commit = |who: &str, text: &str| {
+            std::fs::write(dir.path().join("a.rs"), text).unwrap();
+            let mut index = repo.index().unwrap();
+            index.add_path(Path::new("a.rs")).unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let sig = Signature::now(who, &format!("{}@example.com", who)).unwrap();
+            let parent = repo.head().ok().map(|h| h.peel_to_commit().unwrap());
+            repo.commit(
+                Some("HEAD"),
+                &sig,
+                &sig,
+                who,
+                &tree,
+                &parent.iter().collect::<Vec<_>>(),
+            )
+            .unwrap();
+        };
+        commit("ada", "fn a() {}\nfn b() {}\n");
+        commit("lin", "fn a() { 1; }\nfn b() {}\n");
+        // Reformatting only.
+        commit("max", "fn a() {\n    1;\n}\nfn b() {}\n");
+
+        let config = OwnersConfig {
+            teams: [(
+                "@acme/core".to_string(),
+                vec!["lin@example.com".to_string()],
+            )]
+            .into(),
+        };
+        let mut packs = QueryPacks::default();
+        let found = owners(
+            &repo,
+            "a.rs",
+            None,
+            &config,
+            &ParseLimits::default(),
+            &mut packs,
+        )
+        .unwrap();
+        let handles = |s: &SymbolOwners| {
+            s.owners
+                .iter()
+                .map(|o| (o.handle.clone(), o.commits))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(found[0].symbol.name, "a");
+        assert_eq!(
+            handles(&found[0]),
+            [
+                ("@acme/core".to_string(), 1),
+                ("ada@example.com".to_string(), 1)
+            ]
+        );
+        assert_eq!(found[0].latest.as_deref(), Some("@acme/core"));
+        assert_eq!(handles(&found[1]), [("ada@example.com".to_string(), 1)]);
+        assert_eq!(
+            codeowners_rule("a.rs", &found).as_deref(),
+            Some("/a.rs @acme/core ada@example.com")
+        );
+    }
+}
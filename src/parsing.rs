@@ -0,0 +1,236 @@
+//! Source Parsing
+//!
+//! Turns source text into a Tree-sitter syntax tree using the grammar of a
+//! [`LanguageProvider`](crate::languages::LanguageProvider).
+//!
+//! ## Resource Limits
+//!
+//! Parsing runs inside the long-running filter process, so a single
+//! pathological file (multi-megabyte generated code, adversarial input in an
+//! untrusted clone) must not be able to stall a whole `git add` or `git checkout`.
+//! Every parse is therefore bounded by [`ParseLimits`]:
+//!
+//! -   **Timeout** (`ast.parseTimeoutMs`): Tree-sitter calls back into us
+//!     periodically while parsing; once the deadline passes the parse is
+//!     abandoned.
+//! -   **Size ceiling** (`ast.parseMaxBytes`): Tree-sitter's memory use grows
+//!     linearly with the input, so capping the input size caps the memory a
+//!     single file can claim. Oversized inputs are rejected before parsing.
+//! -   **Cancellation:** An optional shared flag lets the caller abort an
+//!     in-flight parse (e.g., on shutdown).
+//!
+//! A limit violation is reported as an [`Error::Parsing`] for that one file;
+//! the filter turns it into a protocol `error` status for the path instead of
+//! failing the whole process.
+
+use crate::languages::LanguageProvider;
+use crate::telemetry;
+use crate::Error;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tree_sitter::{ParseOptions, ParseState, Parser, Tree};
+
+/// Default for `ast.parseTimeoutMs`.
+pub const DEFAULT_TIMEOUT_MS: u64 = 10_000;
+/// Default for `ast.parseMaxBytes` (16 MiB).
+pub const DEFAULT_MAX_BYTES: usize = 16 * 1024 * 1024;
+
+/// Bounds applied to a single parse.
+#[derive(Debug, Clone)]
+pub struct ParseLimits {
+    /// Abandon the parse after this long. `None` disables the timeout.
+    pub timeout: Option<Duration>,
+    /// Reject inputs larger than this many bytes. `None` disables the check.
+    pub max_bytes: Option<usize>,
+    /// When set to `true` by another thread, the parse is abandoned.
+    pub cancel: Option<Arc<AtomicBool>>,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            timeout: Some(Duration::from_millis(DEFAULT_TIMEOUT_MS)),
+            max_bytes: Some(DEFAULT_MAX_BYTES),
+            cancel: None,
+        }
+    }
+}
+
+/// Parses `source` with the provider's grammar, honouring `limits`.
+///
+/// The returned tree may still contain `ERROR`/`MISSING` nodes; use
+/// [`check_syntax`] to reject those.
+pub fn parse(
+    source: &[u8],
+    provider: &LanguageProvider,
+    limits: &ParseLimits,
+) -> Result<Tree, Error> {
+    if let Some(max) = limits.max_bytes {
+        if source.len() > max {
+            return Err(Error::Parsing(format!(
+                "input is {} bytes, over the {} byte limit (ast.parseMaxBytes)",
+                source.len(),
+                max
+            )));
+        }
+    }
+
+    let mut parser = Parser::new();
+    parser
+        .set_language(&provider.ts_language())
+        .map_err(|e| Error::Parsing(format!("cannot load {} grammar: {}", provider.name, e)))?;
+
+    let deadline = limits.timeout.map(|t| Instant::now() + t);
+    let mut timed_out = false;
+    let mut cancelled = false;
+    let mut progress = |_: &ParseState| {
+        if limits
+            .cancel
+            .as_ref()
+            .is_some_and(|c| c.load(Ordering::Relaxed))
+        {
+            cancelled = true;
+        } else if deadline.is_some_and(|d| Instant::now() >= d) {
+            timed_out = true;
+        }
+        timed_out || cancelled
+    };
+    let tree = telemetry::timed(
+        "git_ast_parse_seconds",
+        &[("language", provider.name)],
+        || {
+            parser.parse_with_options(
+                &mut |offset, _| &source[offset.min(source.len())..],
+                None,
+                Some(ParseOptions::new().progress_callback(&mut progress)),
+            )
+        },
+    );
+
+    match tree {
+        Some(tree) => Ok(tree),
+        None if cancelled => Err(Error::Parsing("parse cancelled".to_string())),
+        None if timed_out => Err(Error::Parsing(format!(
+            "parse timed out after {}ms (ast.parseTimeoutMs)",
+            limits.timeout.unwrap_or_default().as_millis()
+        ))),
+        None => Err(Error::Parsing("parser returned no tree".to_string())),
+    }
+}
+
+/// Fails with the position of the first `ERROR` or `MISSING` node, if any.
+pub fn check_syntax(tree: &Tree) -> Result<(), Error> {
+    let root = tree.root_node();
+    if !root.has_error() {
+        return Ok(());
+    }
+    let mut cursor = root.walk();
+    loop {
+        let node = cursor.node();
+        if node.is_error() || node.is_missing() {
+            let pos = node.start_position();
+            return Err(Error::Parsing(format!(
+                "syntax error at {}:{} ({})",
+                pos.row + 1,
+                pos.column + 1,
+                if node.is_missing() {
+                    "missing token"
+                } else {
+                    "unexpected input"
+                }
+            )));
+        }
+        // Descend only into subtrees that contain the error.
+        if node.has_error() && cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return Err(Error::Parsing("syntax error".to_string()));
+            }
+        }
+    }
+}
+
+/// The leaf tokens of `tree` in source order, as `(kind, text)` pairs.
+///
+/// Two sources with equal token sequences differ only in the whitespace
+/// between tokens.
+pub fn tokens<'s>(tree: &Tree, source: &'s [u8]) -> Vec<(&'static str, &'s [u8])> {
+    tokens_excluding(tree, source, &[])
+}
+
+/// Like [`tokens`], but leaves out tokens inside any of the `excluded` byte
+/// ranges (e.g. nodes captured by an `ignore` query).
+pub fn tokens_excluding<'s>(
+    tree: &Tree,
+    source: &'s [u8],
+    excluded: &[Range<usize>],
+) -> Vec<(&'static str, &'s [u8])> {
+    let mut out = Vec::new();
+    let mut cursor = tree.walk();
+    loop {
+        let node = cursor.node();
+        let range = node.byte_range();
+        if excluded
+            .iter()
+            .any(|r| r.start <= range.start && range.end <= r.end)
+        {
+            // Skip the whole subtree.
+        } else if node.child_count() == 0 {
+            out.push((node.kind(), &source[range]));
+        } else if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return out;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::languages;
+
+    fn rust() -> &'static LanguageProvider {
+        languages::by_name("rust").unwrap()
+    }
+
+    #[test]
+    fn rejects_oversized_input() {
+        let limits = ParseLimits {
+            max_bytes: Some(4),
+            ..ParseLimits::default()
+        };
+        let err = parse(b"fn main() {}", rust(), &limits).unwrap_err();
+        assert!(err.to_string().contains("ast.parseMaxBytes"), "{}", err);
+    }
+
+    #[test]
+    fn honours_cancellation_flag() {
+        let source = "fn f() { let x = 1; }\n".repeat(20_000);
+        let limits = ParseLimits {
+            cancel: Some(Arc::new(AtomicBool::new(true))),
+            ..ParseLimits::default()
+        };
+        let err = parse(source.as_bytes(), rust(), &limits).unwrap_err();
+        assert!(err.to_string().contains("cancelled"), "{}", err);
+    }
+
+    #[test]
+    fn reports_syntax_error_position() {
+        let tree = parse(
+            b"fn main() {\n    let = ;\n}\n",
+            rust(),
+            &ParseLimits::default(),
+        )
+        .unwrap();
+        let err = check_syntax(&tree).unwrap_err();
+        assert!(err.to_string().contains("2:"), "{}", err);
+    }
+}
@@ -0,0 +1,343 @@
+//! Structural Pickaxe
+//!
+//! `git log -S<string>` lists the commits that change how often a string
+//! occurs. [`search`] does the same for the matches of a Tree-sitter query,
+//! so it finds the commits where, say, calls to a deprecated function were
+//! added or removed, and not those that merely mention it:
+//!
+//! ```text
+//! $ git-ast pickaxe '(call_expression function: (identifier) @f (#eq? @f "old_api"))' v1.0..main
+//! 1a2b3c4 Port the importer off old_api
+//!     src/import.rs: 3 -> 0
+//! ```
+//!
+//! Each match of the query counts once. Matches with a capture inside a
+//! comment or string literal (the provider's `comments` and
+//! [`atomic`](crate::languages::SyntaxRules::atomic) kinds) do not count
+//! unless asked for, so `((line_comment) @c (#match? @c "TODO"))` only finds
+//! anything with `include_literals`.
+//!
+//! [`grep`] lists the matches themselves in the files of one revision.
+//!
+//! A query is written for one grammar. Unless a language is given, it is
+//! compiled for every language it is valid in, and files of the others are
+//! skipped. Like `git log`, merge commits are not searched.
+
+use crate::config;
+use crate::git_plumbing::filters;
+use crate::languages::{self, LanguageProvider};
+use crate::model::{NodeRef, Position};
+use crate::parsing::{self, ParseLimits};
+use crate::Error;
+use git2::{
+    Commit, FileMode, ObjectType, Oid, Repository, Sort, Tree, TreeWalkMode, TreeWalkResult,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use tree_sitter::{Node as TsNode, Point, Query, QueryCursor, StreamingIterator};
+
+/// A query compiled for the languages it applies to.
+pub struct Pickaxe {
+    queries: HashMap<&'static str, Query>,
+    include_literals: bool,
+}
+
+/// A file whose match count a commit changed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileHit {
+    pub path: String,
+    pub before: usize,
+    pub after: usize,
+}
+
+/// A commit that changed the match count of at least one file.
+#[derive(Debug, Clone, Serialize)]
+pub struct Hit {
+    pub commit: String,
+    /// First line of the commit message.
+    pub summary: String,
+    pub files: Vec<FileHit>,
+}
+
+impl Pickaxe {
+    /// Compiles `query` for `language`, or for every language it is valid in.
+    pub fn new(
+        query: &str,
+        language: Option<&'static LanguageProvider>,
+        include_literals: bool,
+    ) -> Result<Self, Error> {
+        let mut queries = HashMap::new();
+        let mut first_error = None;
+        let candidates = match language {
+            Some(provider) => vec![provider],
+            None => languages::all().iter().collect(),
+        };
+        for provider in candidates {
+            match Query::new(&provider.ts_language(), query) {
+                Ok(compiled) => {
+                    queries.insert(provider.name, compiled);
+                }
+                Err(e) => {
+                    first_error.get_or_insert(format!("{}: {}", provider.name, e));
+                }
+            }
+        }
+        if queries.is_empty() {
+            let error = first_error.unwrap_or_default();
+            return Err(Error::Config(format!("invalid query ({})", error)));
+        }
+        Ok(Pickaxe {
+            queries,
+            include_literals,
+        })
+    }
+
+    /// Whether the query applies to `provider`'s files.
+    pub fn applies_to(&self, provider: &LanguageProvider) -> bool {
+        self.queries.contains_key(provider.name)
+    }
+
+    /// Number of matches in `source` (0 if the query does not apply).
+    pub fn count(
+        &self,
+        provider: &LanguageProvider,
+        source: &[u8],
+        limits: &ParseLimits,
+    ) -> Result<usize, Error> {
+        let mut count = 0;
+        self.each_match(provider, source, limits, |_| count += 1)?;
+        Ok(count)
+    }
+
+    /// Hands the node of each match in `source` to `visit`: its first
+    /// capture, or the whole match for a query without captures.
+    fn each_match(
+        &self,
+        provider: &LanguageProvider,
+        source: &[u8],
+        limits: &ParseLimits,
+        mut visit: impl FnMut(TsNode<'_>),
+    ) -> Result<(), Error> {
+        let Some(query) = self.queries.get(provider.name) else {
+            return Ok(());
+        };
+        if source.is_empty() {
+            return Ok(());
+        }
+        let tree = parsing::parse(source, provider, limits)?;
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(query, tree.root_node(), source);
+        while let Some(m) = matches.next() {
+            let literal = m.captures.iter().any(|c| in_literal(c.node, provider));
+            if self.include_literals || !literal {
+                visit(m.captures.first().map_or(tree.root_node(), |c| c.node));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `node` is, or is inside, a comment or string literal.
+pub(crate) fn in_literal(node: TsNode<'_>, provider: &LanguageProvider) -> bool {
+    let rules = &provider.syntax;
+    let mut node = Some(node);
+    while let Some(n) = node {
+        if rules.comments.contains(&n.kind()) || rules.atomic.contains(&n.kind()) {
+            return true;
+        }
+        node = n.parent();
+    }
+    false
+}
+
+/// The commits in `range` (a revision or `A..B`; all of `HEAD` when `None`),
+/// newest first, that change the match count of some file.
+pub fn search(
+    repo: &Repository,
+    range: Option<&str>,
+    pickaxe: &Pickaxe,
+    limits: &ParseLimits,
+) -> Result<Vec<Hit>, Error> {
+    let mut hits = Vec::new();
+    search_each(repo, range, pickaxe, limits, None, |hit| {
+        hits.extend(hit);
+        Ok(true)
+    })?;
+    Ok(hits)
+}
+
+/// Like [`search`], but hands the outcome of each commit searched to
+/// `visit` as soon as it is known (`None` if the commit is no hit), so
+/// callers can stream hits and stop early by returning `false`. With
+/// `after`, the walk starts past that commit.
+pub fn search_each(
+    repo: &Repository,
+    range: Option<&str>,
+    pickaxe: &Pickaxe,
+    limits: &ParseLimits,
+    after: Option<Oid>,
+    mut visit: impl FnMut(Option<Hit>) -> Result<bool, Error>,
+) -> Result<(), Error> {
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+    match range {
+        Some(r) if r.contains("..") => walk.push_range(r)?,
+        Some(r) => walk.push(repo.revparse_single(r)?.peel_to_commit()?.id())?,
+        None => walk.push_head()?,
+    }
+    let mut skipping = after.is_some();
+    for oid in walk {
+        let oid = oid?;
+        if skipping {
+            skipping = Some(oid) != after;
+            continue;
+        }
+        let commit = repo.find_commit(oid)?;
+        let mut hit = None;
+        if commit.parent_count() <= 1 {
+            let files = changed_counts(repo, &commit, pickaxe, limits)?;
+            if !files.is_empty() {
+                hit = Some(Hit {
+                    commit: commit.id().to_string(),
+                    summary: commit.summary().unwrap_or_default().to_string(),
+                    files,
+                });
+            }
+        }
+        if !visit(hit)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Hands each match of the query in the files of `tree` to `visit`, by path
+/// and then position, skipping the first `skip` and stopping early when
+/// `visit` returns `false`: a structural `git grep`.
+pub fn grep(
+    repo: &Repository,
+    tree: &Tree,
+    pickaxe: &Pickaxe,
+    limits: &ParseLimits,
+    skip: usize,
+    mut visit: impl FnMut(NodeRef) -> Result<bool, Error>,
+) -> Result<(), Error> {
+    let mut files = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.kind() == Some(ObjectType::Blob) {
+            let path = format!("{}{}", dir, entry.name().unwrap_or_default());
+            files.push((path, entry.id()));
+        }
+        TreeWalkResult::Ok
+    })?;
+    files.sort();
+    let mut seen = 0;
+    for (path, id) in files {
+        let source = filters::perform_smudge(repo.find_blob(id)?.content(), &path)?;
+        let Some(provider) = config::language_for_path(Some(repo), &path, &source)? else {
+            continue;
+        };
+        if !pickaxe.applies_to(provider) {
+            continue;
+        }
+        let mut nodes = Vec::new();
+        pickaxe.each_match(provider, &source, limits, |node| {
+            let position = |p: Point| Position {
+                line: p.row + 1,
+                column: p.column + 1,
+            };
+            nodes.push(NodeRef {
+                path: path.clone(),
+                kind: node.kind().to_string(),
+                start: position(node.start_position()),
+                end: position(node.end_position()),
+            });
+        })?;
+        for node in nodes {
+            seen += 1;
+            if seen > skip && !visit(node)? {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The files `commit` changes whose match count differs from its parent's.
+fn changed_counts(
+    repo: &Repository,
+    commit: &Commit<'_>,
+    pickaxe: &Pickaxe,
+    limits: &ParseLimits,
+) -> Result<Vec<FileHit>, Error> {
+    let parent = match commit.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None,
+    };
+    let diff = repo.diff_tree_to_tree(parent.as_ref(), Some(&commit.tree()?), None)?;
+    let mut files = Vec::new();
+    for delta in diff.deltas() {
+        let (old, new) = (delta.old_file(), delta.new_file());
+        if old.mode() == FileMode::Commit || new.mode() == FileMode::Commit {
+            continue;
+        }
+        let Some(path) = new.path().or(old.path()).and_then(|p| p.to_str()) else {
+            continue;
+        };
+        let source = |id: Oid| match id.is_zero() {
+            true => Ok(Vec::new()),
+            false => filters::perform_smudge(repo.find_blob(id)?.content(), path),
+        };
+        let (old_source, new_source) = (source(old.id())?, source(new.id())?);
+        let content = if new.id().is_zero() {
+            &old_source
+        } else {
+            &new_source
+        };
+        let Some(provider) = config::language_for_path(Some(repo), path, content)? else {
+            continue;
+        };
+        if !pickaxe.applies_to(provider) {
+            continue;
+        }
+        let before = pickaxe.count(provider, &old_source, limits)?;
+        let after = pickaxe.count(provider, &new_source, limits)?;
+        if before != after {
+            files.push(FileHit {
+                path: path.to_string(),
+                before,
+                after,
+            });
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_matches_outside_comments_and_strings() {
+        let rust = languages::by_name("rust").unwrap();
+        let limits = ParseLimits::default();
+        let source = b"// old() is deprecated\nfn f() {\n    old();\n    log(\"old()\");\n}\n";
+        let calls = Pickaxe::new(
+            r#"(call_expression function: (identifier) @f (#eq? @f "old"))"#,
+            None,
+            false,
+        )
+        .unwrap();
+        assert!(calls.applies_to(rust));
+        assert!(!calls.applies_to(languages::by_name("python").unwrap()));
+        assert_eq!(calls.count(rust, source, &limits).unwrap(), 1);
+
+        let strings = r#"((string_literal) @s (#match? @s "old"))"#;
+        let strict = Pickaxe::new(strings, Some(rust), false).unwrap();
+        assert_eq!(strict.count(rust, source, &limits).unwrap(), 0);
+        let literal = Pickaxe::new(strings, Some(rust), true).unwrap();
+        assert_eq!(literal.count(rust, source, &limits).unwrap(), 1);
+
+        assert!(Pickaxe::new("(no_such_node) @x", None, false).is_err());
+    }
+}
@@ -0,0 +1,454 @@
+//! Canonical Pretty-Printing
+//!
+//! Turns a stored [`Node`] tree back into source text. This is what the
+//! smudge filter writes into the working directory, so its output must
+//! re-parse into exactly the tree it was printed from: the printer only ever
+//! chooses *whitespace*, never tokens.
+//!
+//! ## Layout
+//!
+//! The printer is deliberately simple and generic; per-language behaviour
+//! comes from the provider's [`SyntaxRules`]:
+//!
+//! -   **Statements:** Children of `roots` and `blocks` go one per line, with
+//!     blocks indented one level. `,` and `;` stay attached to the statement
+//!     they end. A stored blank line (`blank_line_before`) is reproduced as
+//!     exactly one empty line.
+//! -   **Comments:** Own-line comments start a new line; trailing comments stay
+//!     after the code they annotate. Anything after a line comment goes on
+//!     the next line.
+//! -   **Everything else** is printed on one line, with spaces chosen by
+//!     [`needs_space`]: always where two tokens would otherwise fuse, never
+//!     before `,` `;` `)` `]` or around `.` and `::`, and never inside `tight`
+//!     kinds (`&mut x`, `a[i]`, `x?`).
+//!
+//! Tokens that begin with a line break (heredoc bodies) are printed right
+//! after the previous token, as their text already holds the layout.
+//!
+//! [`print_to`] writes tokens to its output as it goes, so smudging a large
+//! file never holds the printed text in memory.
+//!
+//! Line width is not enforced; long expressions stay on one line.
+
+use crate::ast::{self, Node};
+use crate::languages::{LanguageProvider, SyntaxRules};
+use std::io::{self, Write};
+
+/// Prints `root` as canonical source text for `provider`'s language.
+pub fn print(root: &Node, provider: &LanguageProvider) -> String {
+    let mut out = Vec::new();
+    print_to(root, provider, &mut out).expect("writing to a Vec cannot fail");
+    // Every token is a `String`, so the output is UTF-8.
+    String::from_utf8(out).unwrap_or_default()
+}
+
+/// Like [`print`], writing the text to `out` as it is produced.
+pub fn print_to<W: Write>(root: &Node, provider: &LanguageProvider, out: W) -> io::Result<()> {
+    let mut printer = Printer {
+        rules: &provider.syntax,
+        out: Output {
+            writer: out,
+            empty: true,
+            at_line_start: false,
+            error: None,
+        },
+        indent: 0,
+        pending: Break::None,
+        after_line_comment: false,
+        glue: false,
+        tight: false,
+        spaced: false,
+        prev_named: false,
+        prev_prefix: false,
+        prev_text: String::new(),
+    };
+    printer.node(root);
+    if !printer.out.at_line_start {
+        printer.out.push_str("\n");
+    }
+    match printer.out.error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// The printer's output: where it writes, and the little it needs to know
+/// about what it wrote.
+struct Output<W> {
+    writer: W,
+    empty: bool,
+    at_line_start: bool,
+    /// The first write error; later writes are skipped.
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Output<W> {
+    fn push_str(&mut self, text: &str) {
+        if text.is_empty() || self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.writer.write_all(text.as_bytes()) {
+            self.error = Some(e);
+        }
+        self.empty = false;
+        self.at_line_start = text.ends_with('\n');
+    }
+}
+
+/// Line break requested before the next token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Break {
+    None,
+    Line,
+    Blank,
+}
+
+struct Printer<'a, W> {
+    rules: &'a SyntaxRules,
+    out: Output<W>,
+    indent: usize,
+    pending: Break,
+    after_line_comment: bool,
+    /// The next token opens a `glued` node.
+    glue: bool,
+    /// The next token follows a sibling inside a `tight` node.
+    tight: bool,
+    /// The next token is spaced regardless of [`needs_space`]: the `:` of a
+    /// conditional (`a ? b : c`), or either side of a `.` operator.
+    spaced: bool,
+    prev_named: bool,
+    /// The previous token is a prefix operator (`&x`), even where the
+    /// grammar does not say so (macro token trees).
+    prev_prefix: bool,
+    prev_text: String,
+}
+
+impl<W: Write> Printer<'_, W> {
+    fn node(&mut self, node: &Node) {
+        let kind = node.kind.as_str();
+        // Only a glued node's own opening token attaches (`f(x)`, not `f [x]`).
+        if self.rules.glued.contains(&kind) && node.children.first().is_none_or(Node::is_leaf) {
+            self.glue = true;
+        }
+        if self.rules.line_start.contains(&kind) {
+            self.request(Break::Line);
+        }
+        if node.is_leaf() {
+            self.token(node);
+            return;
+        }
+        let is_root = self.rules.roots.contains(&kind);
+        let is_block = self.rules.blocks.contains(&kind);
+        if is_root || is_block {
+            self.statements(node, is_block);
+            return;
+        }
+
+        let tight = self.rules.tight.contains(&kind);
+        for (i, child) in node.children.iter().enumerate() {
+            if i > 0 && tight {
+                self.tight = true;
+            }
+            // `.` as a binary operator (PHP concatenation) keeps its spaces.
+            let concat = child.kind == "." && node.kind == "binary_expression";
+            if concat || child.kind == ":" && node.children[..i].iter().any(|c| c.kind == "?") {
+                self.spaced = true;
+            }
+            // An own-line comment right before an indented block belongs to it.
+            let nudge = child.own_line && self.indented_block_follows(&node.children[i + 1..]);
+            self.indent += nudge as usize;
+            self.node(child);
+            self.indent -= nudge as usize;
+            self.spaced |= concat;
+        }
+    }
+
+    /// Lays out the children of a root or block node one per line.
+    fn statements(&mut self, node: &Node, is_block: bool) {
+        let braced = node.children.iter().any(is_open);
+        let mut inside = !braced && !is_block;
+        let mut count = 0;
+        let last = node.children.len().saturating_sub(1);
+        for (i, child) in node.children.iter().enumerate() {
+            if !inside {
+                if braced {
+                    self.node(child);
+                    if is_open(child) {
+                        inside = true;
+                        self.indent += 1;
+                    }
+                    continue;
+                }
+                // Leading keywords (`else`, `then`) stay on the header line.
+                if !child.named && child.is_leaf() && !ast::is_delimiter(&child.kind) {
+                    self.node(child);
+                    continue;
+                }
+                inside = true;
+                self.indent += 1;
+            }
+            if braced && i == last && !child.named && child.kind == "}" {
+                self.indent -= 1;
+                if count > 0 {
+                    self.request(Break::Line);
+                }
+                self.node(child);
+                continue;
+            }
+            let attaches = (!child.named && ast::is_delimiter(&child.kind))
+                || first_leaf(child)
+                    .is_some_and(|l| !l.named && matches!(l.kind.as_str(), "," | ";"));
+            let trailing_comment = self.is_comment(child) && !child.own_line;
+            if !attaches && !trailing_comment {
+                self.request(if child.blank_line_before {
+                    Break::Blank
+                } else {
+                    Break::Line
+                });
+            }
+            self.node(child);
+            count += 1;
+        }
+        if is_block && !braced {
+            self.indent -= inside as usize;
+            self.request(Break::Line);
+        }
+    }
+
+    fn indented_block_follows(&self, rest: &[Node]) -> bool {
+        rest.iter().find(|n| !self.is_comment(n)).is_some_and(|n| {
+            self.rules.blocks.contains(&n.kind.as_str()) && !n.children.iter().any(is_open)
+        })
+    }
+
+    fn is_comment(&self, node: &Node) -> bool {
+        self.rules.comments.contains(&node.kind.as_str())
+    }
+
+    fn request(&mut self, brk: Break) {
+        self.pending = self.pending.max(brk);
+    }
+
+    fn token(&mut self, leaf: &Node) {
+        let is_comment = self.is_comment(leaf);
+        let mut text = leaf.text.as_deref().unwrap_or_default();
+        if is_comment {
+            // Some grammars include the newline ending a line comment.
+            text = text.strip_suffix('\n').unwrap_or(text);
+        }
+        let mut brk = self.pending;
+        if self.after_line_comment || leaf.own_line {
+            brk = brk.max(Break::Line);
+        }
+
+        if text.starts_with('\n') {
+            // A token carrying its own line break (a heredoc body) continues
+            // exactly where the previous line ends.
+        } else if !self.out.empty {
+            if brk != Break::None {
+                self.out.push_str("\n");
+                if brk == Break::Blank {
+                    self.out.push_str("\n");
+                }
+                for _ in 0..self.indent {
+                    self.out.push_str(self.rules.indent);
+                }
+            } else if self.spaced
+                || needs_space(
+                    &self.prev_text,
+                    self.prev_named,
+                    text,
+                    self.glue,
+                    self.tight || self.prev_prefix,
+                )
+            {
+                self.out.push_str(" ");
+            }
+        }
+        self.out.push_str(text);
+
+        self.after_line_comment = is_comment && !text.starts_with("/*");
+        self.pending = Break::None;
+        self.glue = false;
+        self.tight = false;
+        self.spaced = false;
+        self.prev_named = leaf.named;
+        self.prev_prefix = matches!(text, "&" | "!" | "*")
+            && matches!(
+                self.prev_text.as_str(),
+                "" | "(" | "[" | "{" | "," | "=" | ";"
+            );
+        self.prev_text.clear();
+        self.prev_text.push_str(text);
+    }
+}
+
+fn first_leaf(node: &Node) -> Option<&Node> {
+    if node.is_leaf() {
+        return Some(node);
+    }
+    node.children.first().and_then(first_leaf)
+}
+
+fn is_open(node: &Node) -> bool {
+    !node.named && node.kind == "{"
+}
+
+/// Operator pairs that may touch without fusing into a different token.
+const OPERATOR_PAIRS: &[(&str, &str)] = &[
+    ("::", "<"),
+    ("::", "*"),
+    (".", "*"),
+    ("<", "&"),
+    ("<", ">"),
+    (">", ">"),
+    (">", "?"),
+    (">", "::"),
+    ("?", "."),
+    ("?", "?"),
+    ("#", "!"),
+];
+
+/// Whether a space goes between two tokens printed on the same line.
+///
+/// `glue` is set when `next` opens a `glued` node, `tight` when it follows a
+/// sibling inside a `tight` node.
+fn needs_space(prev: &str, prev_named: bool, next: &str, glue: bool, tight: bool) -> bool {
+    let (Some(last), Some(first)) = (prev.chars().last(), next.chars().next()) else {
+        return false;
+    };
+    let prev_keyword = !prev_named && is_word(last);
+    if is_word(last) && is_word(first) {
+        return true;
+    }
+    if prev == "..." && !prev_named {
+        return false; // Spread and variadics: `...args`, `...$xs`.
+    }
+    if is_operator(last) && is_operator(first) && !OPERATOR_PAIRS.contains(&(prev, next)) {
+        return true;
+    }
+    if matches!(next, "," | ";" | ")" | "]") {
+        return false;
+    }
+    if matches!(next, "." | "::" | "?.") {
+        return prev_keyword && !tight;
+    }
+    if matches!(prev, "(" | "[" | "." | "::" | "?.") {
+        return false;
+    }
+    if glue
+        && (next == "<"
+            || ((matches!(next, "(" | "[") || first == '`')
+                && (prev_named || matches!(last, ')' | ']' | '>'))))
+    {
+        return false;
+    }
+    if matches!(prev, "," | ";") {
+        return true;
+    }
+    if next == ":" || tight {
+        return false;
+    }
+    !(prev == "{" && next == "}")
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn is_operator(c: char) -> bool {
+    "+-*/%=<>!&|^~?:.@#$".contains(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::languages;
+    use crate::parsing::{self, ParseLimits};
+
+    /// Prints `source` canonically and checks that the result is a fixpoint.
+    fn canonical(lang: &str, source: &str) -> String {
+        let provider = languages::by_name(lang).unwrap();
+        let tree = parsing::parse(source.as_bytes(), provider, &ParseLimits::default()).unwrap();
+        let node = ast::from_tree(&tree, source.as_bytes(), provider).unwrap();
+        let printed = print(&node, provider);
+        let reparsed =
+            parsing::parse(printed.as_bytes(), provider, &ParseLimits::default()).unwrap();
+        parsing::check_syntax(&reparsed).unwrap();
+        let renode = ast::from_tree(&reparsed, printed.as_bytes(), provider).unwrap();
+        assert_eq!(
+            node, renode,
+            "printed output does not round-trip:\n{}",
+            printed
+        );
+        printed
+    }
+
+    #[test]
+    fn prints_rust_canonically() {
+        let printed = canonical(
+            "rust",
+            "use std::{io,fmt};\nfn  add<T>( a:&mut Vec<Vec<T>> ,b:T)->Option<T>{\n\n  let x=a[0].len()?; // why\n\n\n  x\n}\n",
+        );
+        assert_eq!(
+            printed,
+            "use std::{io, fmt};\nfn add<T>(a: &mut Vec<Vec<T>>, b: T) -> Option<T> {\n    let x = a[0].len()?; // why\n\n    x\n}\n"
+        );
+    }
+
+    #[test]
+    fn prints_python_indentation() {
+        let printed = canonical(
+            "python",
+            "def f(a,b=1):\n  if a:  return b\n  # fallthrough\n  else:\n     return -a\n",
+        );
+        assert_eq!(
+            printed,
+            "def f(a, b=1):\n    if a:\n        return b\n    # fallthrough\n    else:\n        return -a\n"
+        );
+    }
+
+    #[test]
+    fn prints_java_blocks_and_members() {
+        let printed = canonical(
+            "java",
+            "package a;\nimport java.util.List;\nclass  A{\n  private int x=1;\n  int add(int a,int b){return a+b;}\n\n\n  // note\n  void f(List<String> xs){ for(String s:xs){System.out.println(s);} }\n}\n",
+        );
+        assert_eq!(
+            printed,
+            "package a;\nimport java.util.List;\nclass A {\n    private int x = 1;\n    int add(int a, int b) {\n        return a + b;\n    }\n\n    // note\n    void f(List<String> xs) {\n        for (String s: xs) {\n            System.out.println(s);\n        }\n    }\n}\n"
+        );
+    }
+
+    #[test]
+    fn prints_csharp_blocks_and_accessors() {
+        let printed = canonical(
+            "csharp",
+            "using System;\nnamespace N{\n class A{\n  public int X{get;set;}\n  int Add(int a,int b){return a+b;}\n  // note\n  void F(string[] xs){foreach(var s in xs){Console.WriteLine(s);}}\n }\n}\n",
+        );
+        assert_eq!(
+            printed,
+            "using System;\nnamespace N {\n    class A {\n        public int X {\n            get;\n            set;\n        }\n        int Add(int a, int b) {\n            return a + b;\n        }\n        // note\n        void F(string[] xs) {\n            foreach (var s in xs) {\n                Console.WriteLine(s);\n            }\n        }\n    }\n}\n"
+        );
+    }
+
+    #[test]
+    fn prints_ruby_and_php_blocks() {
+        let printed = canonical(
+            "ruby",
+            "class A\n  def add(a,b)\n      a+b\n  end\n\n\n  # note\n  def f(xs)\n    xs.each { |x| puts x }\n  end\nend\n",
+        );
+        assert_eq!(
+            printed,
+            "class A\n  def add(a, b)\n    a + b\n  end\n\n  # note\n  def f(xs)\n    xs.each { |x|\n      puts x\n    }\n  end\nend\n"
+        );
+        let printed = canonical(
+            "php",
+            "<?php\nnamespace N;\nclass A{\n  public function add($a,$b){return $a+$b;}\n  // note\n  function f(array $xs){foreach($xs as $x){echo $x;}}\n}\n",
+        );
+        assert_eq!(
+            printed,
+            "<?php\nnamespace N;\nclass A {\n    public function add($a, $b) {\n        return $a + $b;\n    }\n    // note\n    function f(array $xs) {\n        foreach ($xs as $x) {\n            echo $x;\n        }\n    }\n}\n"
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Repository Query Packs
+//!
+//! Projects can tune how `git-ast` classifies nodes without recompiling it by
+//! shipping Tree-sitter query files in the repository:
+//!
+//! ```text
+//! .git-ast/queries/<language>/<name>.scm
+//! ```
+//!
+//! `<language>` is a provider name (`rust`, `python`, ...). All `.scm` files
+//! of a language form its query pack; each file is compiled on first use and
+//! looked up by its stem. A file that does not compile is a configuration
+//! error naming the file, rather than being skipped silently.
+//!
+//! ## Known Queries
+//!
+//! -   `ignore.scm`: Nodes whose changes carry no meaning for the project
+//!     (e.g. `(line_comment) @ignore`, generated attributes). Every capture
+//!     counts. Commits that only change captured nodes (and whitespace) are
+//!     treated as formatting-only by [`blame`](crate::blame).
+//! -   `symbols.scm`: Definitions to index, as `@name` within `@definition.*`
+//!     captures (the convention of Tree-sitter's `tags.scm`).
+//! -   `roles.scm`: Nodes playing language-agnostic roles, as `@function`,
+//!     `@parameter`, `@import`, ... captures (see [`syntax`](crate::syntax)).
+//!     Replaces the language's built-in roles query.
+//!
+//! Captures whose name starts with `_` are helpers for predicates and never
+//! count as results.
+//!
+//! Packs are read from the working tree, so they apply to whatever the
+//! current checkout ships. They only influence analysis; cleaning and
+//! smudging never consult them, so stored blobs do not depend on them.
+
+use crate::languages::LanguageProvider;
+use crate::Error;
+use git2::Repository;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use tree_sitter::{Query, QueryCursor, StreamingIterator, Tree};
+
+/// Directory of query packs, relative to the worktree root.
+pub const QUERY_DIR: &str = ".git-ast/queries";
+/// Query selecting nodes whose changes are insignificant.
+pub const IGNORE: &str = "ignore";
+/// Query selecting symbol definitions.
+pub const SYMBOLS: &str = "symbols";
+/// Query assigning [`Role`](crate::syntax::Role)s to nodes.
+pub const ROLES: &str = "roles";
+
+/// The compiled queries of one language, keyed by file stem.
+#[derive(Debug, Default)]
+pub struct QueryPack {
+    queries: BTreeMap<String, Query>,
+}
+
+impl QueryPack {
+    /// Loads `<root>/.git-ast/queries/<language>/*.scm`. A missing directory
+    /// is an empty pack.
+    pub fn load(root: &Path, provider: &LanguageProvider) -> Result<Self, Error> {
+        let dir = root.join(QUERY_DIR).join(provider.name);
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(QueryPack::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut queries = BTreeMap::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "scm") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let text = fs::read_to_string(&path)?;
+            let query = Query::new(&provider.ts_language(), &text)
+                .map_err(|e| Error::Config(format!("{}: invalid query: {}", path.display(), e)))?;
+            queries.insert(name.to_string(), query);
+        }
+        Ok(QueryPack { queries })
+    }
+
+    /// The query loaded from `<name>.scm`, if the pack has one.
+    pub fn get(&self, name: &str) -> Option<&Query> {
+        self.queries.get(name)
+    }
+
+    /// Names of the loaded queries, sorted.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.queries.keys().map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queries.is_empty()
+    }
+
+    /// Byte ranges captured by query `name` in `tree` (empty if the pack has
+    /// no such query), sorted and without helper (`_`) captures.
+    pub fn ranges(&self, name: &str, tree: &Tree, source: &[u8]) -> Vec<Range<usize>> {
+        let Some(query) = self.get(name) else {
+            return Vec::new();
+        };
+        let names = query.capture_names();
+        let mut ranges = Vec::new();
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(query, tree.root_node(), source);
+        while let Some(m) = matches.next() {
+            for capture in m.captures {
+                if !names[capture.index as usize].starts_with('_') {
+                    ranges.push(capture.node.byte_range());
+                }
+            }
+        }
+        ranges.sort_by_key(|r| (r.start, r.end));
+        ranges.dedup();
+        ranges
+    }
+}
+
+/// The query packs of one repository, loaded per language on first use.
+#[derive(Debug, Default)]
+pub struct QueryPacks {
+    root: Option<PathBuf>,
+    loaded: HashMap<&'static str, QueryPack>,
+}
+
+impl QueryPacks {
+    /// Packs shipped in `repo`'s working tree (none for a bare repository).
+    pub fn for_repo(repo: &Repository) -> Self {
+        QueryPacks {
+            root: repo.workdir().map(Path::to_path_buf),
+            loaded: HashMap::new(),
+        }
+    }
+
+    /// The pack for `provider`'s language.
+    pub fn get(&mut self, provider: &'static LanguageProvider) -> Result<&QueryPack, Error> {
+        if !self.loaded.contains_key(provider.name) {
+            let pack = match &self.root {
+                Some(root) => QueryPack::load(root, provider)?,
+                None => QueryPack::default(),
+            };
+            self.loaded.insert(provider.name, pack);
+        }
+        Ok(&self.loaded[provider.name])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::languages;
+    use crate::parsing::{self, ParseLimits};
+
+    #[test]
+    fn loads_pack_from_worktree() {
+        let dir = tempfile::tempdir().unwrap();
+        let rust = languages::by_name("rust").unwrap();
+        let queries = dir.path().join(QUERY_DIR).join("rust");
+        fs::create_dir_all(&queries).unwrap();
+        fs::write(queries.join("ignore.scm"), "(line_comment) @ignore\n").unwrap();
+        fs::write(queries.join("README.md"), "not a query").unwrap();
+
+        let pack = QueryPack::load(dir.path(), rust).unwrap();
+        assert_eq!(pack.names().collect::<Vec<_>>(), ["ignore"]);
+        let source = b"fn f() {} // note\n";
+        let tree = parsing::parse(source, rust, &ParseLimits::default()).unwrap();
+        let ranges = pack.ranges(IGNORE, &tree, source);
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(&source[ranges[0].clone()], b"// note");
+
+        fs::write(queries.join("symbols.scm"), "(no_such_node) @x").unwrap();
+        let err = QueryPack::load(dir.path(), rust).unwrap_err();
+        assert!(err.to_string().contains("symbols.scm"), "{}", err);
+    }
+}
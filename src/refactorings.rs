@@ -0,0 +1,366 @@
+//! Cross-File Refactorings
+//!
+//! A commit that renames a function touches its definition and every call
+//! site; one that moves a function removes it from one module and adds it
+//! to another. File by file, these read as dozens of unrelated edits.
+//! [`detect`] finds them among the changed top-level items of a commit's
+//! files (see [`Items`]) so they can be listed once:
+//!
+//! -   A **rename**: changed items whose tokens are all equal except for one
+//!     identifier, which is `old` on one side and `new` on the other
+//!     everywhere in the item, in at least two files. Call sites that were
+//!     updated are items of that kind too.
+//! -   A **move**: an item removed from one file and added, unchanged, to
+//!     another.
+//!
+//! Where an item could pair with more than one other, the closest wins, as
+//! [`matching`] specifies, so the same commit always lists the same
+//! refactorings.
+//!
+//! The items a refactoring accounts for are recorded, so the per-file
+//! summaries ([`Detected::edits`]) can leave them out. Edits mixed into a renamed or
+//! moved item (a renamed function that also got a new parameter) keep it
+//! from counting; they stay per-file edits.
+
+use crate::ast::Node;
+use crate::changes::Items;
+use crate::matching::{self, Candidate};
+use crate::staging;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+/// A change spanning files, to be read as one edit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Refactoring {
+    /// Identifier `from` became `to` in `files`.
+    Rename {
+        from: String,
+        to: String,
+        files: Vec<String>,
+    },
+    /// Item `name` moved from file `from` to file `to`.
+    Move {
+        name: String,
+        from: String,
+        to: String,
+    },
+}
+
+impl fmt::Display for Refactoring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Refactoring::Rename { from, to, files } => {
+                write!(f, "rename {} -> {} in {}", from, to, files.join(", "))
+            }
+            Refactoring::Move { name, from, to } => {
+                write!(f, "move {} from {} to {}", name, from, to)
+            }
+        }
+    }
+}
+
+/// What [`detect`] found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Detected {
+    pub refactorings: Vec<Refactoring>,
+    /// Items accounted for, as (file, item) indices into the input and the
+    /// old or new side's top-level items.
+    pub old: HashSet<(usize, usize)>,
+    pub new: HashSet<(usize, usize)>,
+}
+
+impl Detected {
+    /// The edits of file `f` no refactoring accounts for, as `changed` and
+    /// the names of a hunk's modified items, then `removed` and `added` and
+    /// the names of the others (see [`Items::modified`]).
+    pub fn edits(&self, f: usize, items: &Items) -> Vec<String> {
+        self.edited(f, items)
+            .into_iter()
+            .map(|(change, names)| format!("{} {}", change, names.join(", ")))
+            .collect()
+    }
+
+    /// [`edits`](Self::edits) as each hunk's `changed`, `removed` and
+    /// `added` with the distinct names of their items.
+    pub fn edited(&self, f: usize, items: &Items) -> Vec<(&'static str, Vec<String>)> {
+        let mut edits = Vec::new();
+        for hunk in &items.hunks {
+            let old: Vec<usize> = hunk
+                .index
+                .clone()
+                .filter(|&i| !self.old.contains(&(f, i)))
+                .collect();
+            let new: Vec<usize> = hunk
+                .worktree
+                .clone()
+                .filter(|&j| !self.new.contains(&(f, j)))
+                .collect();
+            let modified: Vec<(usize, usize)> = items
+                .modified
+                .iter()
+                .copied()
+                .filter(|(i, j)| old.contains(i) && new.contains(j))
+                .collect();
+            let changed: Vec<&Node> = modified
+                .iter()
+                .flat_map(|&(i, j)| [&items.old.children[i], &items.new.children[j]])
+                .collect();
+            let removed = old
+                .iter()
+                .filter(|&&i| modified.iter().all(|p| p.0 != i))
+                .map(|&i| &items.old.children[i]);
+            let added = new
+                .iter()
+                .filter(|&&j| modified.iter().all(|p| p.1 != j))
+                .map(|&j| &items.new.children[j]);
+            for (change, nodes) in [
+                ("changed", changed),
+                ("removed", removed.collect()),
+                ("added", added.collect()),
+            ] {
+                let mut names: Vec<String> = Vec::new();
+                for name in nodes.into_iter().map(staging::name) {
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+                if !names.is_empty() {
+                    edits.push((change, names));
+                }
+            }
+        }
+        edits
+    }
+}
+
+/// Finds the refactorings among the changed items of `files` (each a path
+/// and its items, if it has any).
+pub fn detect(files: &[(&str, Option<&Items>)]) -> Detected {
+    let mut detected = Detected::default();
+
+    // Within a hunk, pair old and new items that differ by an identifier
+    // swap; what is left over was removed or added.
+    type Renamed = Vec<(usize, usize, usize)>;
+    let mut renames: BTreeMap<(String, String), Renamed> = BTreeMap::new();
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    for (f, (_, items)) in files.iter().enumerate() {
+        let Some(items) = items else { continue };
+        for hunk in &items.hunks {
+            let mut swaps = BTreeMap::new();
+            let mut candidates = Vec::new();
+            for i in hunk.index.clone() {
+                let item = &items.old.children[i];
+                for j in hunk.worktree.clone() {
+                    let other = &items.new.children[j];
+                    let Some(pair) = swapped(item, other) else {
+                        continue;
+                    };
+                    swaps.insert((i, j), pair);
+                    candidates.push(Candidate {
+                        old: i,
+                        new: j,
+                        distance: matching::distance(i - hunk.index.start, j - hunk.worktree.start),
+                        old_hash: matching::content_hash(item),
+                        new_hash: matching::content_hash(other),
+                    });
+                }
+            }
+            let pairs = matching::assign(candidates);
+            for &(i, j) in &pairs {
+                let pair = swaps.remove(&(i, j)).expect("a candidate");
+                renames.entry(pair).or_default().push((f, i, j));
+            }
+            for i in hunk
+                .index
+                .clone()
+                .filter(|&i| pairs.iter().all(|p| p.0 != i))
+            {
+                removed.push((f, i, &items.old.children[i]));
+            }
+            for j in hunk
+                .worktree
+                .clone()
+                .filter(|&j| pairs.iter().all(|p| p.1 != j))
+            {
+                added.push((f, j, &items.new.children[j]));
+            }
+        }
+    }
+    for ((from, to), renamed) in renames {
+        let mut paths: Vec<String> = Vec::new();
+        for &(f, ..) in &renamed {
+            if !paths.iter().any(|p| p == files[f].0) {
+                paths.push(files[f].0.to_string());
+            }
+        }
+        if paths.len() < 2 {
+            continue;
+        }
+        for (f, i, j) in renamed {
+            detected.old.insert((f, i));
+            detected.new.insert((f, j));
+        }
+        detected.refactorings.push(Refactoring::Rename {
+            from,
+            to,
+            files: paths,
+        });
+    }
+
+    // Each added item can be the destination of one removed item.
+    let mut candidates = Vec::new();
+    for &(f, i, item) in &removed {
+        let hash = matching::content_hash(item);
+        for &(g, j, other) in &added {
+            if g != f && !detected.new.contains(&(g, j)) && other == item {
+                candidates.push(Candidate {
+                    old: (f, i),
+                    new: (g, j),
+                    distance: matching::distance(i, j),
+                    old_hash: hash,
+                    new_hash: hash,
+                });
+            }
+        }
+    }
+    for ((f, i), (g, j)) in matching::assign(candidates) {
+        detected.old.insert((f, i));
+        detected.new.insert((g, j));
+        detected.refactorings.push(Refactoring::Move {
+            name: staging::name(&files[f].1.expect("has items").old.children[i]),
+            from: files[f].0.to_string(),
+            to: files[g].0.to_string(),
+        });
+    }
+    detected
+}
+
+/// The one identifier renamed between `old` and `new`, if their tokens are
+/// otherwise equal and it is renamed everywhere in them.
+fn swapped(old: &Node, new: &Node) -> Option<(String, String)> {
+    let (old, new) = (old.leaves(), new.leaves());
+    if old.len() != new.len() {
+        return None;
+    }
+    let mut pair: Option<(&str, &str)> = None;
+    for (o, n) in old.iter().zip(&new) {
+        let (a, b) = (o.text.as_deref()?, n.text.as_deref()?);
+        if o.kind != n.kind {
+            return None;
+        }
+        match (a == b, pair) {
+            (true, _) => {}
+            (false, None) if is_identifier(a) && is_identifier(b) => pair = Some((a, b)),
+            (false, Some(p)) if p == (a, b) => {}
+            (false, _) => return None,
+        }
+    }
+    let (from, to) = pair?;
+    // An occurrence of the old name left alone: not renamed everywhere.
+    let untouched = old
+        .iter()
+        .zip(&new)
+        .any(|(o, n)| o.text.as_deref() == Some(from) && n.text.as_deref() == Some(from));
+    (!untouched).then(|| (from.to_string(), to.to_string()))
+}
+
+fn is_identifier(token: &str) -> bool {
+    let mut chars = token.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changes::FileChange;
+
+    fn items(path: &str, old: &str, new: &str) -> Items {
+        let change = FileChange {
+            path: path.to_string(),
+            from: None,
+            old_mode: Some("100644".to_string()),
+            new_mode: Some("100644".to_string()),
+            old: old.as_bytes().to_vec(),
+            new: new.as_bytes().to_vec(),
+        };
+        change.items(None).unwrap()
+    }
+
+    #[test]
+    fn finds_renames_across_files_and_moved_items() {
+        let lib = items(
+            "lib.rs",
+            "fn parse() {}\n\nfn helper() -> u8 {\n    1\n}\n",
+            "fn parse_header() {}\n",
+        );
+        let main = items(
+            "main.rs",
+            "fn main() {\n    parse();\n}\n",
+            "fn main() {\n    parse_header();\n}\n\nfn helper() -> u8 {\n    1\n}\n",
+        );
+        // `parse` is still called once: not a rename of every use.
+        let partial = items(
+            "cli.rs",
+            "fn run() {\n    parse();\n    parse();\n}\n",
+            "fn run() {\n    parse_header();\n    parse();\n}\n",
+        );
+        let detected = detect(&[
+            ("lib.rs", Some(&lib)),
+            ("main.rs", Some(&main)),
+            ("cli.rs", Some(&partial)),
+        ]);
+        assert_eq!(
+            detected
+                .refactorings
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            [
+                "rename parse -> parse_header in lib.rs, main.rs",
+                "move helper from lib.rs to main.rs",
+            ]
+        );
+        assert_eq!(detected.edits(0, &lib), Vec::<String>::new());
+        assert_eq!(detected.edits(1, &main), Vec::<String>::new());
+        assert_eq!(detected.edits(2, &partial), ["changed run"]);
+    }
+
+    #[test]
+    fn ties_resolve_the_same_way_every_time() {
+        let helper = "fn helper() -> u8 {\n    1\n}\n";
+        let lib = items(
+            "lib.rs",
+            &format!("fn a() {{}}\n\n{}", helper),
+            "fn a() {}\n",
+        );
+        // Both files gain a copy; the one at the same position wins.
+        let first = items("first.rs", "", helper);
+        let second = items(
+            "second.rs",
+            "fn b() {}\n",
+            &format!("fn b() {{}}\n\n{}", helper),
+        );
+        let files = [
+            ("first.rs", Some(&first)),
+            ("lib.rs", Some(&lib)),
+            ("second.rs", Some(&second)),
+        ];
+        let detected = detect(&files);
+        assert_eq!(
+            detected.refactorings,
+            [Refactoring::Move {
+                name: "helper".to_string(),
+                from: "lib.rs".to_string(),
+                to: "second.rs".to_string(),
+            }]
+        );
+        assert_eq!(detected.edits(0, &first), ["added helper"]);
+        for _ in 0..3 {
+            assert_eq!(detect(&files), detected);
+        }
+    }
+}
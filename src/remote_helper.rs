@@ -0,0 +1,247 @@
+//! Remote Helper (Experimental)
+//!
+//! `git-remote-ast` lets an AST repository fetch from and push to a
+//! plain-source repository directly, as an alternative to keeping a second
+//! repository in sync (see the crate docs on the mirrored repository). Git
+//! runs it for remote URLs of the form `ast::<url>`:
+//!
+//! ```sh
+//! git remote add source ast::https://example.com/acme/widget.git
+//! git push source main    # pushes the source mirror of main
+//! git fetch source        # fetches source commits as AST commits
+//! ```
+//!
+//! Both directions go through the [`mirror`] rewrite engine and its OID map,
+//! so they are incremental and consistent with each other: a pushed commit
+//! is mirrored once, and fetching its mirror back yields the original AST
+//! commit. Commits made directly in the source repository are cleaned into
+//! new AST commits (files that do not parse keep their source blobs, as in
+//! sidecar mode).
+//!
+//! ## Protocol
+//!
+//! The helper speaks the `fetch`/`push` subset of `gitremote-helpers(7)`:
+//!
+//! -   **`list`** (and `list for-push`): fetches the remote's branches and
+//!     tags into `refs/ast/remotes/<remote>/`, rewrites each tip to AST
+//!     commits ([`Direction::ToAst`]) and lists those, so Git compares and
+//!     fast-forwards in AST terms. Tags are listed as the commits they point
+//!     to; annotated tag objects are not rewritten.
+//! -   **`fetch`**: nothing left to do, since `list` already wrote the
+//!     objects.
+//! -   **`push`**: mirrors each pushed commit ([`Direction::ToSource`]) and
+//!     pushes the mirror with `git push` (without running hooks, which expect
+//!     AST blobs), reporting `ok` or `error` per ref.
+
+use crate::mirror::{self, Direction};
+use crate::Error;
+use git2::{Oid, Repository};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Namespace of the source refs fetched by `list`, per remote.
+pub const REMOTES_PREFIX: &str = "refs/ast/remotes/";
+
+/// Runs the helper for `remote` (a remote name, or the URL for an anonymous
+/// remote) at `url`, answering Git's commands from `input` on `output`.
+pub fn run(
+    repo: &Repository,
+    remote: &str,
+    url: &str,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> Result<(), Error> {
+    let helper = Helper {
+        repo,
+        url,
+        prefix: format!("{}{}/", REMOTES_PREFIX, ref_component(remote)),
+    };
+    loop {
+        let Some(line) = read_line(&mut input)? else {
+            return Ok(());
+        };
+        let (command, argument) = line.split_once(' ').unwrap_or((line.as_str(), ""));
+        match command {
+            "" => return Ok(()),
+            "capabilities" => output.write_all(b"fetch\npush\n\n")?,
+            "list" => {
+                for (name, id) in helper.list()? {
+                    writeln!(output, "{} {}", id, name)?;
+                }
+                if let Some(head) = helper.head()? {
+                    writeln!(output, "@{} HEAD", head)?;
+                }
+                writeln!(output)?;
+            }
+            "fetch" => {
+                // The objects were written by `list`; skip the rest of the batch.
+                while read_line(&mut input)?.is_some_and(|l| !l.is_empty()) {}
+                writeln!(output)?;
+            }
+            "push" => {
+                let mut specs = vec![argument.to_string()];
+                while let Some(line) = read_line(&mut input)? {
+                    match line.strip_prefix("push ") {
+                        Some(spec) => specs.push(spec.to_string()),
+                        None => break,
+                    }
+                }
+                for spec in &specs {
+                    let (destination, result) = helper.push(spec);
+                    match result {
+                        Ok(()) => writeln!(output, "ok {}", destination)?,
+                        Err(e) => writeln!(output, "error {} {}", destination, one_line(&e))?,
+                    }
+                }
+                writeln!(output)?;
+            }
+            _ => {
+                return Err(Error::Driver(format!(
+                    "git-remote-ast: unsupported command {:?}",
+                    line
+                )))
+            }
+        }
+        output.flush()?;
+    }
+}
+
+struct Helper<'r> {
+    repo: &'r Repository,
+    url: &'r str,
+    /// Where the remote's refs are fetched to.
+    prefix: String,
+}
+
+impl Helper<'_> {
+    /// The remote's branches and tags, as rewritten AST commits.
+    fn list(&self) -> Result<Vec<(String, Oid)>, Error> {
+        let heads = format!("+refs/heads/*:{}heads/*", self.prefix);
+        let tags = format!("+refs/tags/*:{}tags/*", self.prefix);
+        self.git(&[
+            "fetch",
+            "--quiet",
+            "--no-tags",
+            "--prune",
+            self.url,
+            &heads,
+            &tags,
+        ])?;
+
+        let mut listed = Vec::new();
+        for reference in self.repo.references_glob(&format!("{}*", self.prefix))? {
+            let reference = reference?;
+            let Some(name) = reference.name() else {
+                continue;
+            };
+            let name = format!("refs/{}", &name[self.prefix.len()..]);
+            let Ok(tip) = reference.peel_to_commit() else {
+                continue;
+            };
+            let message = format!("git-remote-ast fetch {}", name);
+            let (id, _) = mirror::rewrite(self.repo, tip.id(), Direction::ToAst, &message)?;
+            listed.push((name, id));
+        }
+        Ok(listed)
+    }
+
+    /// The branch the remote's `HEAD` points to, if it has one.
+    fn head(&self) -> Result<Option<String>, Error> {
+        let listed = self.git(&["ls-remote", "--symref", self.url, "HEAD"])?;
+        Ok(listed.lines().find_map(|line| {
+            let (target, name) = line.strip_prefix("ref: ")?.split_once('\t')?;
+            (name == "HEAD").then(|| target.to_string())
+        }))
+    }
+
+    /// Pushes the mirror of `spec`'s source (`[+]<src>:<dst>`, an empty
+    /// source deleting); returns the destination and the outcome.
+    fn push<'s>(&self, spec: &'s str) -> (&'s str, Result<(), Error>) {
+        let (force, spec) = match spec.strip_prefix('+') {
+            Some(spec) => ("+", spec),
+            None => ("", spec),
+        };
+        let (source, destination) = spec.split_once(':').unwrap_or((spec, spec));
+        let result = (|| {
+            let refspec = match source {
+                "" => format!(":{}", destination),
+                _ => {
+                    let tip = self.repo.revparse_single(source)?.peel_to_commit()?;
+                    let message = format!("git-remote-ast push {}", destination);
+                    let (id, _) =
+                        mirror::rewrite(self.repo, tip.id(), Direction::ToSource, &message)?;
+                    format!("{}{}:{}", force, id, destination)
+                }
+            };
+            self.git(&["push", "--quiet", "--no-verify", self.url, &refspec])?;
+            Ok(())
+        })();
+        (destination, result)
+    }
+
+    /// Runs `git` on the local repository; its output, which must not reach
+    /// Git through the helper's stdout, is returned.
+    fn git(&self, args: &[&str]) -> Result<String, Error> {
+        let output = Command::new("git")
+            .arg("--git-dir")
+            .arg(self.repo.path())
+            .args(args)
+            .stdin(Stdio::null())
+            .stderr(Stdio::piped())
+            .output()?;
+        if !output.status.success() {
+            return Err(Error::Driver(format!(
+                "git {} failed: {}",
+                args[0],
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// Reads one line without its newline; `None` at the end of `input`.
+fn read_line(input: &mut impl BufRead) -> Result<Option<String>, Error> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+/// `remote` as one ref name component: local paths and URLs of anonymous
+/// remotes keep only characters that are safe there.
+fn ref_component(remote: &str) -> String {
+    let name = Path::new(remote)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name: String = match remote.contains([':', '/', '\\']) {
+        true => format!("_{}", name),
+        false => remote.to_string(),
+    }
+    .chars()
+    .map(|c| match c.is_ascii_alphanumeric() || "-_".contains(c) {
+        true => c,
+        false => '_',
+    })
+    .collect();
+    name.trim_start_matches('.').to_string()
+}
+
+fn one_line(error: &Error) -> String {
+    error.to_string().replace('\n', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remote_names_become_one_ref_component() {
+        assert_eq!(ref_component("source"), "source");
+        assert_eq!(ref_component("ast::/srv/git/widget.git"), "_widget_git");
+        assert_eq!(ref_component("https://example.com/a b"), "_a_b");
+    }
+}
@@ -0,0 +1,496 @@
+//! Review Checklists
+//!
+//! Some changes deserve a reviewer's attention whatever their size: a public
+//! signature that changed, error handling that went away, a new `unsafe`
+//! block. [`checklist`] compares the files a range changes, on canonical
+//! source (see [`changes`]), and lists every such change as a checklist
+//! [`Item`], most important first.
+//!
+//! ## Checks
+//!
+//! -   `public-signature` (high): a definition whose [signature](symbols#signatures)
+//!     changed, if either version is public (starts with `pub`, `export` or
+//!     `public`).
+//! -   The [`BUILTIN`] query checks: `new-unsafe` (high) and
+//!     `removed-error-handling` (medium, for each language's `?`, `try`,
+//!     `catch` or `rescue`).
+//! -   The project's `[[review]]` rules (see
+//!     [`ReviewRule`](crate::config::ReviewRule)). A rule with a built-in
+//!     check's name and language replaces it; an empty query turns it off.
+//!
+//! A query check counts its matches on both sides of each file, like
+//! [`pickaxe`](crate::pickaxe), ignoring matches in comments and strings. If
+//! the count went up (`when = "added"`) or down (`"removed"`), the matches
+//! whose text only the new (or old) side has are listed, as many as the
+//! count changed by: a `catch` clause that was only edited is not reported
+//! as removed.
+
+use crate::changes::{self, FileChange};
+use crate::config::{self, RepoConfigs, ReviewPriority, ReviewRule, ReviewWhen};
+use crate::languages::{self, LanguageProvider};
+use crate::parsing::{self, ParseLimits};
+use crate::pickaxe;
+use crate::queries::QueryPacks;
+use crate::symbols;
+use crate::{log_info, Error};
+use git2::{Pathspec, PathspecFlags, Repository};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use tree_sitter::{Query, QueryCursor, StreamingIterator, Tree};
+
+/// Name of the signature check.
+pub const PUBLIC_SIGNATURE: &str = "public-signature";
+
+/// First words that make a signature public.
+const PUBLIC: &[&str] = &["pub", "export", "public"];
+
+/// The built-in query checks: name, language, query, when, priority and
+/// message.
+pub const BUILTIN: &[(&str, &str, &str, ReviewWhen, ReviewPriority, &str)] = &[
+    (
+        "new-unsafe",
+        "rust",
+        "(unsafe_block) @unsafe",
+        ReviewWhen::Added,
+        ReviewPriority::High,
+        "new unsafe block",
+    ),
+    (
+        "removed-error-handling",
+        "rust",
+        "(try_expression) @try
+         (match_arm pattern: (match_pattern (tuple_struct_pattern type: (identifier) @_err (#eq? @_err \"Err\")))) @arm",
+        ReviewWhen::Removed,
+        ReviewPriority::Medium,
+        "error handling removed",
+    ),
+    (
+        "removed-error-handling",
+        "python",
+        "(except_clause) @except",
+        ReviewWhen::Removed,
+        ReviewPriority::Medium,
+        "error handling removed",
+    ),
+    (
+        "removed-error-handling",
+        "javascript",
+        "(catch_clause) @catch",
+        ReviewWhen::Removed,
+        ReviewPriority::Medium,
+        "error handling removed",
+    ),
+    (
+        "removed-error-handling",
+        "java",
+        "(catch_clause) @catch",
+        ReviewWhen::Removed,
+        ReviewPriority::Medium,
+        "error handling removed",
+    ),
+    (
+        "removed-error-handling",
+        "csharp",
+        "(catch_clause) @catch",
+        ReviewWhen::Removed,
+        ReviewPriority::Medium,
+        "error handling removed",
+    ),
+    (
+        "removed-error-handling",
+        "ruby",
+        "(rescue) @rescue",
+        ReviewWhen::Removed,
+        ReviewPriority::Medium,
+        "error handling removed",
+    ),
+    (
+        "removed-error-handling",
+        "php",
+        "(catch_clause) @catch",
+        ReviewWhen::Removed,
+        ReviewPriority::Medium,
+        "error handling removed",
+    ),
+];
+
+/// One thing for the reviewer to look at.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Item {
+    pub check: String,
+    pub priority: ReviewPriority,
+    pub path: String,
+    /// 1-based, on the new side (the old side for removals).
+    pub line: usize,
+    pub message: String,
+    /// The code before and after, shortened to one line.
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// The checklist for the changes from `base` to `head` (`None` for an
+/// empty tree), sorted by priority, path and line.
+pub fn checklist(
+    repo: &Repository,
+    base: Option<&git2::Tree<'_>>,
+    head: &git2::Tree<'_>,
+) -> Result<Vec<Item>, Error> {
+    let limits = config::Settings::load(Some(repo))?.parse_limits;
+    let configs = RepoConfigs::new(Some(repo));
+    let mut packs = QueryPacks::for_repo(repo);
+    let mut items = Vec::new();
+    for change in changes::between(repo, base, Some(head), &[])? {
+        let Some(provider) = config::language_for_path(Some(repo), &change.path, &change.new)?
+        else {
+            continue;
+        };
+        let parsed = parse_sides(&change, provider, &limits);
+        let Some((old, new)) = parsed else {
+            log_info!("review", "Skipping {}: a side does not parse", change.path);
+            continue;
+        };
+        let rules = rules(
+            &configs.for_path(&change.path)?.review,
+            &change.path,
+            provider,
+        )?;
+        for (rule, query) in &rules {
+            match query {
+                Some(query) => {
+                    items.extend(query_items(rule, query, &change, provider, &old, &new))
+                }
+                None => {
+                    let pack = packs.get(provider)?;
+                    items.extend(signature_items(rule, &change, provider, pack, &old, &new));
+                }
+            }
+        }
+    }
+    items.sort_by(|a, b| (a.priority, &a.path, a.line).cmp(&(b.priority, &b.path, b.line)));
+    Ok(items)
+}
+
+/// Both sides of a change, parsed (an absent side as empty source).
+fn parse_sides(
+    change: &FileChange,
+    provider: &LanguageProvider,
+    limits: &ParseLimits,
+) -> Option<(Tree, Tree)> {
+    let old = parsing::parse(&change.old, provider, limits).ok()?;
+    let new = parsing::parse(&change.new, provider, limits).ok()?;
+    Some((old, new))
+}
+
+/// The checks that apply to `path`, a file of `provider`, each with its
+/// compiled query (`None` for the signature check).
+fn rules(
+    project: &[ReviewRule],
+    path: &str,
+    provider: &LanguageProvider,
+) -> Result<Vec<(ReviewRule, Option<Query>)>, Error> {
+    let mut checks: Vec<ReviewRule> = vec![ReviewRule {
+        name: PUBLIC_SIGNATURE.to_string(),
+        language: provider.name.to_string(),
+        paths: Vec::new(),
+        query: String::new(),
+        when: ReviewWhen::Added,
+        priority: ReviewPriority::High,
+        message: Some("public signature changed".to_string()),
+    }];
+    for &(name, language, query, when, priority, message) in BUILTIN {
+        checks.push(ReviewRule {
+            name: name.to_string(),
+            language: language.to_string(),
+            paths: Vec::new(),
+            query: query.to_string(),
+            when,
+            priority,
+            message: Some(message.to_string()),
+        });
+    }
+    for rule in project {
+        let language = languages::by_name(&rule.language).ok_or_else(|| {
+            Error::Config(format!(
+                "review {}: unknown language {:?}",
+                rule.name, rule.language
+            ))
+        })?;
+        checks.retain(|c| {
+            !(c.name == rule.name
+                && languages::by_name(&c.language).map(|l| l.name) == Some(language.name))
+        });
+        if !rule.query.is_empty() {
+            checks.push(rule.clone());
+        }
+    }
+
+    let mut out = Vec::new();
+    for rule in checks {
+        let in_paths = rule.paths.is_empty()
+            || Pathspec::new(&rule.paths)?.matches_path(Path::new(path), PathspecFlags::DEFAULT);
+        if languages::by_name(&rule.language).map(|l| l.name) != Some(provider.name) || !in_paths {
+            continue;
+        }
+        let query = match rule.name.as_str() {
+            PUBLIC_SIGNATURE if rule.query.is_empty() => None,
+            _ => Some(
+                Query::new(&provider.ts_language(), &rule.query).map_err(|e| {
+                    Error::Config(format!("review {}: invalid query: {}", rule.name, e))
+                })?,
+            ),
+        };
+        out.push((rule, query));
+    }
+    Ok(out)
+}
+
+/// The matches of a query check the change added or removed.
+fn query_items(
+    rule: &ReviewRule,
+    query: &Query,
+    change: &FileChange,
+    provider: &LanguageProvider,
+    old: &Tree,
+    new: &Tree,
+) -> Vec<Item> {
+    let old_matches = matches(query, old, &change.old, provider);
+    let new_matches = matches(query, new, &change.new, provider);
+    let (from, to) = match rule.when {
+        ReviewWhen::Added => (&old_matches, &new_matches),
+        ReviewWhen::Removed => (&new_matches, &old_matches),
+    };
+    let Some(changed_by) = to.len().checked_sub(from.len()).filter(|&n| n > 0) else {
+        return Vec::new();
+    };
+    // Texts on the other side, each used up once.
+    let mut others: BTreeMap<&str, usize> = BTreeMap::new();
+    for (text, _) in from {
+        *others.entry(text).or_default() += 1;
+    }
+    let mut items = Vec::new();
+    for (text, line) in to {
+        match others.get_mut(text.as_str()) {
+            Some(n) if *n > 0 => *n -= 1,
+            _ if items.len() < changed_by => {
+                let excerpt = Some(excerpt(text));
+                let (before, after) = match rule.when {
+                    ReviewWhen::Added => (None, excerpt),
+                    ReviewWhen::Removed => (excerpt, None),
+                };
+                items.push(Item {
+                    check: rule.name.clone(),
+                    priority: rule.priority,
+                    path: change.path.clone(),
+                    line: *line,
+                    message: rule.message.clone().unwrap_or_default(),
+                    before,
+                    after,
+                });
+            }
+            _ => {}
+        }
+    }
+    items
+}
+
+/// The text and line of each match of `query` outside comments and strings,
+/// at its first capture not starting with `_`.
+fn matches(
+    query: &Query,
+    tree: &Tree,
+    source: &[u8],
+    provider: &LanguageProvider,
+) -> Vec<(String, usize)> {
+    let names = query.capture_names();
+    let mut out = Vec::new();
+    let mut cursor = QueryCursor::new();
+    let mut found = cursor.matches(query, tree.root_node(), source);
+    while let Some(m) = found.next() {
+        if m.captures
+            .iter()
+            .any(|c| pickaxe::in_literal(c.node, provider))
+        {
+            continue;
+        }
+        let Some(capture) = m
+            .captures
+            .iter()
+            .find(|c| !names[c.index as usize].starts_with('_'))
+        else {
+            continue;
+        };
+        let text = String::from_utf8_lossy(&source[capture.node.byte_range()]);
+        out.push((
+            text.split_whitespace().collect::<Vec<_>>().join(" "),
+            capture.node.start_position().row + 1,
+        ));
+    }
+    out
+}
+
+/// Definitions whose signature changed, where either version is public.
+fn signature_items(
+    rule: &ReviewRule,
+    change: &FileChange,
+    provider: &LanguageProvider,
+    pack: &crate::queries::QueryPack,
+    old: &Tree,
+    new: &Tree,
+) -> Vec<Item> {
+    let signatures = |tree: &Tree, source: &[u8]| {
+        let mut out = BTreeMap::new();
+        for (symbol, node) in symbols::definition_nodes(tree, source, &change.path, provider, pack)
+        {
+            let signature = symbols::signature(node, source);
+            out.insert((symbol.kind, symbol.name), (signature, symbol.line));
+        }
+        out
+    };
+    let before = signatures(old, &change.old);
+    let mut items = Vec::new();
+    for (key, (after, line)) in signatures(new, &change.new) {
+        let Some((before, _)) = before.get(&key) else {
+            continue;
+        };
+        let public = |s: &str| {
+            s.split(|c: char| !c.is_alphanumeric())
+                .next()
+                .is_some_and(|word| PUBLIC.contains(&word))
+        };
+        if *before != after && (public(before) || public(&after)) {
+            items.push(Item {
+                check: rule.name.clone(),
+                priority: rule.priority,
+                path: change.path.clone(),
+                line,
+                message: rule.message.clone().unwrap_or_default(),
+                before: Some(excerpt(before)),
+                after: Some(excerpt(&after)),
+            });
+        }
+    }
+    items
+}
+
+/// `text` on one line, at most 80 characters.
+fn excerpt(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(79) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text,
+    }
+}
+
+/// The checklist as Markdown, grouped by priority.
+pub fn markdown(title: &str, items: &[Item]) -> String {
+    let mut out = format!("# Review checklist: {}\n", title);
+    if items.is_empty() {
+        out += "\nNo risky structural changes found.\n";
+        return out;
+    }
+    let mut priority = None;
+    for item in items {
+        if priority != Some(item.priority) {
+            priority = Some(item.priority);
+            let heading = match item.priority {
+                ReviewPriority::High => "High",
+                ReviewPriority::Medium => "Medium",
+                ReviewPriority::Low => "Low",
+            };
+            out += &format!("\n## {} priority\n\n", heading);
+        }
+        out += &format!(
+            "- [ ] `{}:{}` **{}**: {}\n",
+            item.path, item.line, item.check, item.message
+        );
+        if let Some(before) = &item.before {
+            out += &format!("  - before: `{}`\n", before);
+        }
+        if let Some(after) = &item.after {
+            out += &format!("  - after: `{}`\n", after);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_queries_compile() {
+        for &(name, language, query, ..) in BUILTIN {
+            let provider = languages::by_name(language).unwrap();
+            Query::new(&provider.ts_language(), query)
+                .unwrap_or_else(|e| panic!("{} ({}): {}", name, language, e));
+        }
+    }
+
+    #[test]
+    fn lists_risky_changes_by_priority() {
+        let rust = languages::by_name("rust").unwrap();
+        let change = FileChange {
+            path: "src/lib.rs".to_string(),
+            from: None,
+            old_mode: Some("100644".to_string()),
+            new_mode: Some("100644".to_string()),
+            old:
+                b"pub fn parse(a: u8) -> Result<u8, E> {\n    let b = check(a)?;\n    Ok(b)\n}\n\n\
+                   fn helper(a: u8) {}\n"
+                    .to_vec(),
+            new: b"pub fn parse(a: u8, strict: bool) -> Result<u8, E> {\n    \
+                   let b = unsafe { check_unchecked(a) };\n    Ok(b)\n}\n\nfn helper(a: u16) {}\n"
+                .to_vec(),
+        };
+        let limits = ParseLimits::default();
+        let (old, new) = parse_sides(&change, rust, &limits).unwrap();
+        let mut items = Vec::new();
+        for (rule, query) in rules(&[], &change.path, rust).unwrap() {
+            items.extend(match query {
+                Some(query) => query_items(&rule, &query, &change, rust, &old, &new),
+                None => signature_items(&rule, &change, rust, &Default::default(), &old, &new),
+            });
+        }
+        items.sort_by_key(|i| (i.priority, i.line));
+        let summary: Vec<(&str, usize)> =
+            items.iter().map(|i| (i.check.as_str(), i.line)).collect();
+        // The private helper's signature change is not listed.
+        assert_eq!(
+            summary,
+            [
+                ("public-signature", 1),
+                ("new-unsafe", 2),
+                ("removed-error-handling", 2)
+            ]
+        );
+        assert_eq!(items[2].before.as_deref(), Some("check(a)?"));
+
+        let markdown = markdown("HEAD", &items);
+        assert!(
+            markdown.contains(
+                "## High priority\n\n- [ ] `src/lib.rs:1` **public-signature**: public \
+                 signature changed\n  - before: `pub fn parse(a: u8) -> Result<u8, E>`\n"
+            ),
+            "{}",
+            markdown
+        );
+
+        // A project rule replaces the built-in of the same name, or turns it off.
+        let off = ReviewRule {
+            name: "new-unsafe".to_string(),
+            language: "rust".to_string(),
+            paths: Vec::new(),
+            query: String::new(),
+            when: ReviewWhen::Added,
+            priority: ReviewPriority::Low,
+            message: None,
+        };
+        let names: Vec<String> = rules(&[off], &change.path, rust)
+            .unwrap()
+            .into_iter()
+            .map(|(rule, _)| rule.name)
+            .collect();
+        assert_eq!(names, ["public-signature", "removed-error-handling"]);
+    }
+}
@@ -0,0 +1,982 @@
+//! Editor Protocol
+//!
+//! A JSON-RPC 2.0 service for editor plugins, so that a VS Code or Neovim
+//! client only has to start `git-ast rpc` in the repository and frame
+//! messages. Messages use the Language Server Protocol's framing: a
+//! `Content-Length` header, a blank line, then that many bytes of JSON.
+//!
+//! Paths are relative to the repository root; lines and columns are 1-based,
+//! columns counting bytes. Revisions are anything `git rev-parse` accepts, and
+//! a missing or `null` revision means the working tree.
+//!
+//! -   **`git-ast/diffNodes`** `{path, old, new?}`: the changed tokens of a
+//!     file between two revisions, as a [`ChangeSet`] with the line ranges
+//!     of each change in the source and in the stored AST (see
+//!     [`review_anchors`](crate::commands::review_anchors)).
+//! -   **`git-ast/symbolHistory`** `{path, symbol?}`: the commits that
+//!     changed each symbol of the file at `HEAD`, as a [`History`] (see
+//!     [`ownership::history`]).
+//! -   **`git-ast/nodeAt`** `{path, line, column, rev?}`: the smallest named
+//!     node at a position and the enclosing definition, if any, as a
+//!     [`NodeAt`]; `null` if there is none.
+//!
+//! -   **`git-ast/log`** `{query, language?, range?, includeLiterals?}`: the
+//!     commits that change the match count of a Tree-sitter query, newest
+//!     first (see [`pickaxe`]); items are [`Hit`](crate::pickaxe::Hit)s.
+//! -   **`git-ast/grep`** `{query, language?, rev?}`: the matches of a query
+//!     in the files of a revision (`HEAD` by default), by path and
+//!     position; items are [`NodeRef`]s.
+//! -   **`shutdown`** and **`exit`**: acknowledged, and end the session.
+//!
+//! Results are [`Document`]s of the [`model`](crate::model), so they carry
+//! its `version`.
+//!
+//! ## Paging and Streaming
+//!
+//! `log` and `grep` can be long over a large repository, so they answer in
+//! pages: a [`Page`] of `items` and a `cursor`, which is `null` once there
+//! is nothing more. `limit` bounds a page, and passing a page's `cursor`
+//! back (with the same other parameters) continues after it. Cursors are
+//! opaque strings.
+//!
+//! With `stream: true`, items are not collected into the result but sent as
+//! soon as they are found, one `git-ast/item` notification each
+//! (`{id, item, cursor}`, the cursor continuing after that item), and the
+//! result is a page without items. A client that goes away mid-stream, or
+//! sends `$/cancelRequest` `{id}`, stops the request; a cancelled request is
+//! answered with code `-32800`.
+//!
+//! Failures of the requested operation are answered with code `-32000` and
+//! the error's message; the server keeps running.
+//!
+//! ```text
+//! Content-Length: 86
+//!
+//! {"jsonrpc":"2.0","id":1,"method":"git-ast/nodeAt","params":{"path":"src/a.rs","line":3,"column":8}}
+//! ```
+
+use crate::commands::review_anchors::{self, Rendering};
+use crate::config::{self, RepoConfigs};
+use crate::git_plumbing::filters;
+use crate::languages::{self, LanguageProvider};
+use crate::model::{
+    Change, ChangeSet, Document, History, NodeAt, NodeRef, Page, Position, SymbolHistory,
+    SymbolRecord,
+};
+use crate::ownership;
+use crate::parsing::{self, ParseLimits};
+use crate::pickaxe::{self, Pickaxe};
+use crate::queries::QueryPacks;
+use crate::symbols::{self, Parsed, Symbol};
+use crate::Error;
+use git2::{Oid, Repository};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use tree_sitter::{Node as TsNode, Point, Tree};
+
+/// Invalid JSON.
+pub const PARSE_ERROR: i64 = -32700;
+/// JSON that is not a request.
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// The method failed.
+pub const SERVER_ERROR: i64 = -32000;
+/// The client cancelled the request.
+pub const REQUEST_CANCELLED: i64 = -32800;
+
+/// A JSON-RPC error response's `error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl From<Error> for RpcError {
+    fn from(e: Error) -> Self {
+        RpcError {
+            code: SERVER_ERROR,
+            message: e.to_string(),
+        }
+    }
+}
+
+/// Reads one framed message; `None` at the end of `input`.
+pub fn read_message(input: &mut impl BufRead) -> Result<Option<Vec<u8>>, Error> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return match length {
+                None => Ok(None),
+                Some(_) => Err(Error::Parsing(
+                    "rpc: message ends in its header".to_string(),
+                )),
+            };
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = Some(value.trim().parse::<usize>().map_err(|_| {
+                    Error::Parsing(format!("rpc: bad Content-Length {:?}", value.trim()))
+                })?);
+            }
+        }
+    }
+    let length =
+        length.ok_or_else(|| Error::Parsing("rpc: message without Content-Length".to_string()))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+/// Writes one framed message.
+pub fn write_message(output: &mut impl Write, message: &Value) -> Result<(), Error> {
+    let body =
+        serde_json::to_vec(message).map_err(|e| Error::Serialization(format!("rpc: {}", e)))?;
+    write!(output, "Content-Length: {}\r\n\r\n", body.len())?;
+    output.write_all(&body)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Answers requests by method name.
+pub trait Handler {
+    /// Runs `method`.
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, RpcError>;
+    /// Runs `method`, which may send partial results through `stream`.
+    /// Handlers without streaming methods keep the default, [`call`].
+    ///
+    /// [`call`]: Handler::call
+    fn call_streaming(
+        &mut self,
+        method: &str,
+        params: Value,
+        stream: &mut Stream,
+    ) -> Result<Value, RpcError> {
+        let _ = stream;
+        self.call(method, params)
+    }
+    /// Whether the session is over.
+    fn done(&self) -> bool;
+}
+
+/// Messages read so far, by a thread of their own so that a request can
+/// notice being cancelled while it runs.
+struct Inbox {
+    messages: Receiver<Result<Vec<u8>, Error>>,
+    /// Read while a request ran, to be answered after it.
+    pending: VecDeque<Result<Vec<u8>, Error>>,
+    /// Ids of the requests the client cancelled.
+    cancelled: Vec<Value>,
+}
+
+impl Inbox {
+    /// Reads messages from `input` on a new thread, which ends with the
+    /// input.
+    fn spawn(mut input: impl BufRead + Send + 'static) -> Self {
+        let (sender, messages) = mpsc::channel();
+        std::thread::spawn(move || loop {
+            let message = match read_message(&mut input) {
+                Ok(Some(body)) => Ok(body),
+                Ok(None) => return,
+                Err(e) => Err(e),
+            };
+            let failed = message.is_err();
+            if sender.send(message).is_err() || failed {
+                return;
+            }
+        });
+        Inbox {
+            messages,
+            pending: VecDeque::new(),
+            cancelled: Vec::new(),
+        }
+    }
+
+    /// The next message to answer, waiting for one; `None` at the end of
+    /// the input.
+    fn next(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        loop {
+            let body = match self.pending.pop_front() {
+                Some(message) => message?,
+                None => match self.messages.recv() {
+                    Ok(message) => message?,
+                    Err(_) => return Ok(None),
+                },
+            };
+            // Cancelling a request that is not running has no effect.
+            if cancel_target(&body).is_none() {
+                return Ok(Some(body));
+            }
+        }
+    }
+
+    /// Whether the request `id` has been cancelled, taking in the messages
+    /// that arrived meanwhile.
+    fn is_cancelled(&mut self, id: &Value) -> bool {
+        while let Ok(message) = self.messages.try_recv() {
+            match message.as_deref().ok().and_then(cancel_target) {
+                Some(target) => self.cancelled.push(target),
+                None => self.pending.push_back(message),
+            }
+        }
+        self.cancelled.contains(id)
+    }
+}
+
+/// The id a `$/cancelRequest` notification cancels; `None` for other
+/// messages.
+fn cancel_target(body: &[u8]) -> Option<Value> {
+    let message: Value = serde_json::from_slice(body).ok()?;
+    if message.get("method")? != "$/cancelRequest" {
+        return None;
+    }
+    Some(message.get("params")?.get("id")?.clone())
+}
+
+/// A running request's way to send partial results and to learn that it
+/// should stop.
+pub struct Stream<'s> {
+    /// The request's id; `None` for notifications.
+    id: Option<Value>,
+    output: Option<&'s mut dyn Write>,
+    inbox: Option<&'s mut Inbox>,
+}
+
+impl Stream<'_> {
+    /// A stream that sends nothing and is never cancelled.
+    pub fn detached() -> Stream<'static> {
+        Stream {
+            id: None,
+            output: None,
+            inbox: None,
+        }
+    }
+
+    /// Sends `item` as a `git-ast/item` notification, with the `cursor`
+    /// that continues after it. Fails if the request was cancelled or the
+    /// client is gone.
+    pub fn send(&mut self, item: Value, cursor: &str) -> Result<(), RpcError> {
+        self.check()?;
+        let (Some(id), Some(output)) = (&self.id, self.output.as_deref_mut()) else {
+            return Ok(());
+        };
+        let message = json!({
+            "jsonrpc": "2.0",
+            "method": "git-ast/item",
+            "params": {"id": id, "item": item, "cursor": cursor},
+        });
+        let mut output = output;
+        write_message(&mut output, &message).map_err(|_| cancelled())
+    }
+
+    /// Fails if the request was cancelled.
+    pub fn check(&mut self) -> Result<(), RpcError> {
+        match (&self.id, self.inbox.as_deref_mut()) {
+            (Some(id), Some(inbox)) => match inbox.is_cancelled(id) {
+                true => Err(cancelled()),
+                false => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+fn cancelled() -> RpcError {
+    RpcError {
+        code: REQUEST_CANCELLED,
+        message: "request cancelled".to_string(),
+    }
+}
+
+/// Answers messages from `input` on `output` until the handler is done or
+/// `input` ends.
+pub fn serve(
+    handler: &mut dyn Handler,
+    input: impl BufRead + Send + 'static,
+    mut output: impl Write,
+) -> Result<(), Error> {
+    let mut inbox = Inbox::spawn(input);
+    while !handler.done() {
+        let Some(body) = inbox.next()? else {
+            break;
+        };
+        let id = serde_json::from_slice::<Value>(&body)
+            .ok()
+            .and_then(|m| m.get("id").cloned());
+        let mut stream = Stream {
+            id,
+            output: Some(&mut output),
+            inbox: Some(&mut inbox),
+        };
+        if let Some(response) = respond(handler, &body, &mut stream) {
+            write_message(&mut output, &response)?;
+        }
+    }
+    Ok(())
+}
+
+/// The response to one message body; `None` for a notification.
+pub fn handle(handler: &mut dyn Handler, body: &[u8]) -> Option<Value> {
+    respond(handler, body, &mut Stream::detached())
+}
+
+fn respond(handler: &mut dyn Handler, body: &[u8], stream: &mut Stream) -> Option<Value> {
+    let message: Value = match serde_json::from_slice(body) {
+        Ok(message) => message,
+        Err(e) => return Some(error_response(Value::Null, PARSE_ERROR, e.to_string())),
+    };
+    let id = message.get("id").cloned();
+    let Some(method) = message.get("method").and_then(Value::as_str) else {
+        let id = id.unwrap_or(Value::Null);
+        return Some(error_response(id, INVALID_REQUEST, "no method".to_string()));
+    };
+    let params = message.get("params").cloned().unwrap_or(Value::Null);
+    let result = handler.call_streaming(method, params, stream);
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(e) => error_response(id, e.code, e.message),
+    })
+}
+
+/// The state of a session: one repository and its configuration.
+pub struct Server {
+    pub(crate) repo: Repository,
+    pub(crate) limits: ParseLimits,
+    configs: RepoConfigs,
+    pub(crate) packs: QueryPacks,
+    done: bool,
+}
+
+#[derive(Deserialize)]
+struct DiffParams {
+    path: String,
+    old: String,
+    #[serde(default)]
+    new: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct HistoryParams {
+    path: String,
+    #[serde(default)]
+    symbol: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NodeAtParams {
+    path: String,
+    line: usize,
+    column: usize,
+    #[serde(default)]
+    rev: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PageParams {
+    #[serde(default)]
+    cursor: Option<String>,
+    #[serde(default)]
+    limit: Option<usize>,
+    #[serde(default)]
+    stream: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LogParams {
+    query: String,
+    #[serde(default)]
+    language: Option<String>,
+    #[serde(default)]
+    range: Option<String>,
+    #[serde(default)]
+    include_literals: bool,
+    #[serde(flatten)]
+    page: PageParams,
+}
+
+#[derive(Deserialize)]
+struct GrepParams {
+    query: String,
+    #[serde(default)]
+    language: Option<String>,
+    #[serde(default)]
+    rev: Option<String>,
+    #[serde(flatten)]
+    page: PageParams,
+}
+
+/// Collects the items of a page, or streams them.
+struct Pager<'p, 's> {
+    stream: &'p mut Stream<'s>,
+    streaming: bool,
+    limit: usize,
+    items: Vec<Value>,
+    /// Why the request stopped early, if it did.
+    failure: Option<RpcError>,
+}
+
+impl<'p, 's> Pager<'p, 's> {
+    fn new(stream: &'p mut Stream<'s>, params: &PageParams) -> Self {
+        Pager {
+            stream,
+            streaming: params.stream,
+            limit: params.limit.unwrap_or(usize::MAX).max(1),
+            items: Vec::new(),
+            failure: None,
+        }
+    }
+
+    /// Whether to go on, noting a cancellation.
+    fn proceed(&mut self) -> bool {
+        match self.stream.check() {
+            Ok(()) => self.failure.is_none(),
+            Err(e) => {
+                self.failure = Some(e);
+                false
+            }
+        }
+    }
+
+    /// Adds an item, which `cursor` continues after; `false` once the page
+    /// is full or the request has to stop.
+    fn push(&mut self, item: impl Serialize, cursor: &str) -> bool {
+        let item = match serde_json::to_value(item) {
+            Ok(item) => item,
+            Err(e) => {
+                self.failure = Some(Error::Serialization(e.to_string()).into());
+                return false;
+            }
+        };
+        self.limit -= 1;
+        match self.streaming {
+            true => {
+                if let Err(e) = self.stream.send(item, cursor) {
+                    self.failure = Some(e);
+                    return false;
+                }
+            }
+            false => self.items.push(item),
+        }
+        self.limit > 0
+    }
+
+    /// The page, with `cursor` if the page filled up before the end.
+    fn finish(self, cursor: Option<String>) -> Result<Value, RpcError> {
+        if let Some(e) = self.failure {
+            return Err(e);
+        }
+        let page = Page {
+            items: self.items,
+            cursor,
+        };
+        Ok(Document::new(page).to_value()?)
+    }
+}
+
+impl Server {
+    pub fn new(repo: Repository) -> Result<Self, Error> {
+        let limits = config::Settings::load(Some(&repo))?.parse_limits;
+        let configs = RepoConfigs::new(Some(&repo));
+        let packs = QueryPacks::for_repo(&repo);
+        Ok(Server {
+            repo,
+            limits,
+            configs,
+            packs,
+            done: false,
+        })
+    }
+}
+
+impl Handler for Server {
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "git-ast/diffNodes" => {
+                let params: DiffParams = parse_params(params)?;
+                Ok(Document::new(self.diff_nodes(&params)?).to_value()?)
+            }
+            "git-ast/symbolHistory" => {
+                let params: HistoryParams = parse_params(params)?;
+                let history = ownership::history(
+                    &self.repo,
+                    &params.path,
+                    params.symbol.as_deref(),
+                    &self.limits,
+                    &mut self.packs,
+                )?;
+                let symbols = history
+                    .into_iter()
+                    .map(|(symbol, touches)| SymbolHistory {
+                        symbol: symbol.into(),
+                        commits: touches.into_iter().map(Into::into).collect(),
+                    })
+                    .collect();
+                Ok(Document::new(History { symbols }).to_value()?)
+            }
+            "git-ast/nodeAt" => {
+                let params: NodeAtParams = parse_params(params)?;
+                Ok(self.node_at(&params)?)
+            }
+            "git-ast/log" | "git-ast/grep" => {
+                self.call_streaming(method, params, &mut Stream::detached())
+            }
+            "shutdown" | "exit" => {
+                self.done = true;
+                Ok(Value::Null)
+            }
+            _ => Err(RpcError {
+                code: METHOD_NOT_FOUND,
+                message: format!("unknown method {:?}", method),
+            }),
+        }
+    }
+
+    fn call_streaming(
+        &mut self,
+        method: &str,
+        params: Value,
+        stream: &mut Stream,
+    ) -> Result<Value, RpcError> {
+        match method {
+            "git-ast/log" => {
+                let params: LogParams = parse_params(params)?;
+                self.log(&params, stream)
+            }
+            "git-ast/grep" => {
+                let params: GrepParams = parse_params(params)?;
+                self.grep(&params, stream)
+            }
+            _ => self.call(method, params),
+        }
+    }
+
+    fn done(&self) -> bool {
+        self.done
+    }
+}
+
+impl Server {
+    fn diff_nodes(&self, params: &DiffParams) -> Result<ChangeSet, Error> {
+        let commit = |rev: &str| -> Result<String, Error> {
+            Ok(self
+                .repo
+                .revparse_single(rev)?
+                .peel_to_commit()?
+                .id()
+                .to_string())
+        };
+        let old = Rendering::of_blob(
+            &self.repo,
+            self.blob_at(&params.old, &params.path)?,
+            &params.path,
+        )?;
+        let new = match &params.new {
+            Some(rev) => {
+                Rendering::of_blob(&self.repo, self.blob_at(rev, &params.path)?, &params.path)?
+            }
+            None => self.worktree_rendering(&params.path)?,
+        };
+        let anchors = review_anchors::file_anchors(&params.path, &old, &new);
+        Ok(ChangeSet {
+            base: Some(commit(&params.old)?),
+            head: params.new.as_deref().map(commit).transpose()?,
+            anchors: anchors.into_iter().map(Change::from).collect(),
+        })
+    }
+
+    /// A page of the commits that change a query's match count.
+    fn log(&self, params: &LogParams, stream: &mut Stream) -> Result<Value, RpcError> {
+        let pickaxe = compile(
+            &params.query,
+            params.language.as_deref(),
+            params.include_literals,
+        )?;
+        let after = match &params.page.cursor {
+            Some(cursor) => Some(Oid::from_str(cursor).map_err(|_| bad_cursor(cursor))?),
+            None => None,
+        };
+        let mut pager = Pager::new(stream, &params.page);
+        let mut cursor = None;
+        pickaxe::search_each(
+            &self.repo,
+            params.range.as_deref(),
+            &pickaxe,
+            &self.limits,
+            after,
+            |hit| {
+                // Checked on every commit, as hits may be far apart.
+                if !pager.proceed() {
+                    return Ok(false);
+                }
+                let Some(hit) = hit else {
+                    return Ok(true);
+                };
+                let commit = hit.commit.clone();
+                let more = pager.push(hit, &commit);
+                if !more {
+                    cursor = Some(commit);
+                }
+                Ok(more)
+            },
+        )?;
+        pager.finish(cursor)
+    }
+
+    /// A page of the matches of a query in a revision's files.
+    fn grep(&self, params: &GrepParams, stream: &mut Stream) -> Result<Value, RpcError> {
+        let pickaxe = compile(&params.query, params.language.as_deref(), false)?;
+        // The cursor pins the commit, so later pages do not move with a branch.
+        let (commit, skip) = match &params.page.cursor {
+            Some(cursor) => cursor
+                .split_once(':')
+                .and_then(|(id, n)| Some((Oid::from_str(id).ok()?, n.parse().ok()?)))
+                .ok_or_else(|| bad_cursor(cursor))?,
+            None => {
+                let rev = params.rev.as_deref().unwrap_or("HEAD");
+                let commit = self
+                    .repo
+                    .revparse_single(rev)
+                    .and_then(|o| o.peel_to_commit());
+                (commit.map_err(Error::from)?.id(), 0)
+            }
+        };
+        let tree = self.repo.find_commit(commit).and_then(|c| c.tree());
+        let tree = tree.map_err(Error::from)?;
+        let mut pager = Pager::new(stream, &params.page);
+        let mut offset = skip;
+        let mut cursor = None;
+        pickaxe::grep(&self.repo, &tree, &pickaxe, &self.limits, skip, |node| {
+            if !pager.proceed() {
+                return Ok(false);
+            }
+            offset += 1;
+            let next = format!("{}:{}", commit, offset);
+            let more = pager.push(node, &next);
+            if !more {
+                cursor = Some(next);
+            }
+            Ok(more)
+        })?;
+        pager.finish(cursor)
+    }
+
+    /// Tokens of the working tree's `path`, as the clean filter would
+    /// store it.
+    fn worktree_rendering(&self, path: &str) -> Result<Rendering, Error> {
+        let Some(source) = self.worktree_source(path)? else {
+            return Ok(Rendering::default());
+        };
+        if let Some(provider) = config::language_for_path(Some(&self.repo), path, &source)? {
+            let repo_config = self.configs.for_path(path)?;
+            let blob =
+                filters::clean_with(&source, path, Some(provider), &self.limits, &repo_config)?;
+            if let Some(rendering) = Rendering::of_ast_blob(&blob, path, Some(&source))? {
+                return Ok(rendering);
+            }
+        }
+        Rendering::of_content(&source, path)
+    }
+
+    fn node_at(&mut self, params: &NodeAtParams) -> Result<Value, RpcError> {
+        if params.line == 0 || params.column == 0 {
+            return Err(RpcError {
+                code: INVALID_PARAMS,
+                message: "lines and columns start at 1".to_string(),
+            });
+        }
+        let path = params.path.as_str();
+        let parsed = match &params.rev {
+            Some(rev) => {
+                let id = self.blob_at(rev, path)?;
+                match id.is_zero() {
+                    true => None,
+                    false => symbols::parse_blob(&self.repo, id, path, &self.limits)?,
+                }
+            }
+            None => match self.worktree_source(path)? {
+                Some(source) => self.parse_source(path, source)?,
+                None => None,
+            },
+        };
+        let Some((provider, source, tree)) = parsed else {
+            return Err(Error::Parsing(format!("{}: no syntax tree", path)).into());
+        };
+        let point = Point {
+            row: params.line - 1,
+            column: params.column - 1,
+        };
+        let Some((node, definition)) = self.locate(&tree, &source, path, provider, point)? else {
+            return Ok(Value::Null);
+        };
+        let position = |p: Point| Position {
+            line: p.row + 1,
+            column: p.column + 1,
+        };
+        let node_at = NodeAt {
+            node: NodeRef {
+                path: path.to_string(),
+                kind: node.kind().to_string(),
+                start: position(node.start_position()),
+                end: position(node.end_position()),
+            },
+            definition: definition.map(SymbolRecord::from),
+        };
+        Ok(Document::new(node_at).to_value()?)
+    }
+
+    /// `source`'s language and parse tree, if it has a language.
+    pub(crate) fn parse_source(
+        &self,
+        path: &str,
+        source: Vec<u8>,
+    ) -> Result<Option<Parsed>, Error> {
+        let Some(provider) = config::language_for_path(Some(&self.repo), path, &source)? else {
+            return Ok(None);
+        };
+        let tree = parsing::parse(&source, provider, &self.limits)?;
+        Ok(Some((provider, source, tree)))
+    }
+
+    /// The smallest named node at `point` and the innermost definition
+    /// containing it.
+    pub(crate) fn locate<'t>(
+        &mut self,
+        tree: &'t Tree,
+        source: &[u8],
+        path: &str,
+        provider: &'static LanguageProvider,
+        point: Point,
+    ) -> Result<Option<(TsNode<'t>, Option<Symbol>)>, Error> {
+        let Some(node) = tree
+            .root_node()
+            .named_descendant_for_point_range(point, point)
+        else {
+            return Ok(None);
+        };
+        let pack = self.packs.get(provider)?;
+        let definition = symbols::definition_nodes(tree, source, path, provider, pack)
+            .into_iter()
+            .filter(|(_, d)| d.start_byte() <= node.start_byte() && node.end_byte() <= d.end_byte())
+            .min_by_key(|(_, d)| d.end_byte() - d.start_byte())
+            .map(|(symbol, _)| symbol);
+        Ok(Some((node, definition)))
+    }
+
+    /// The blob of `path` at `rev`; the zero id if it has none.
+    fn blob_at(&self, rev: &str, path: &str) -> Result<Oid, Error> {
+        let tree = self.repo.revparse_single(rev)?.peel_to_tree()?;
+        Ok(tree
+            .get_path(Path::new(path))
+            .map(|entry| entry.id())
+            .unwrap_or_else(|_| Oid::zero()))
+    }
+
+    /// The working tree's `path`, if it exists.
+    pub(crate) fn worktree_source(&self, path: &str) -> Result<Option<Vec<u8>>, Error> {
+        let workdir = self
+            .repo
+            .workdir()
+            .ok_or_else(|| Error::Config("rpc: the repository has no working tree".to_string()))?;
+        match std::fs::read(workdir.join(path)) {
+            Ok(source) => Ok(Some(source)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// `query`, compiled for `language` or for every language it is valid in.
+fn compile(
+    query: &str,
+    language: Option<&str>,
+    include_literals: bool,
+) -> Result<Pickaxe, RpcError> {
+    let language = match language {
+        Some(name) => Some(languages::by_name(name).ok_or_else(|| RpcError {
+            code: INVALID_PARAMS,
+            message: format!("unknown language {:?}", name),
+        })?),
+        None => None,
+    };
+    Pickaxe::new(query, language, include_literals).map_err(|e| RpcError {
+        code: INVALID_PARAMS,
+        message: e.to_string(),
+    })
+}
+
+fn bad_cursor(cursor: &str) -> RpcError {
+    RpcError {
+        code: INVALID_PARAMS,
+        message: format!("bad cursor {:?}", cursor),
+    }
+}
+
+fn parse_params<T: for<'de> Deserialize<'de>>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError {
+        code: INVALID_PARAMS,
+        message: e.to_string(),
+    })
+}
+
+fn error_response(id: Value, code: i64, message: String) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Signature;
+
+    #[test]
+    fn answers_framed_requests() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        // Stored as the clean filter would store it.
+        let rust = crate::languages::by_name("rust").unwrap();
+        let stored = filters::clean_as(
+            b"fn one() -> u32 {\n    1\n}\n",
+            "a.rs",
+            Some(rust),
+            &ParseLimits::default(),
+        )
+        .unwrap();
+        let mut builder = repo.treebuilder(None).unwrap();
+        builder
+            .insert("a.rs", repo.blob(&stored).unwrap(), 0o100644)
+            .unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let sig = Signature::now("t", "t@example.com").unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "one", &tree, &[])
+            .unwrap();
+        drop((tree, builder));
+        std::fs::write(dir.path().join("a.rs"), "fn one() -> u32 {\n    2\n}\n").unwrap();
+
+        let mut input = Vec::new();
+        for request in [
+            json!({"jsonrpc": "2.0", "id": 1, "method": "git-ast/nodeAt",
+                   "params": {"path": "a.rs", "line": 2, "column": 5}}),
+            json!({"jsonrpc": "2.0", "id": 2, "method": "git-ast/diffNodes",
+                   "params": {"path": "a.rs", "old": "HEAD"}}),
+            json!({"jsonrpc": "2.0", "id": 3, "method": "nope"}),
+            json!({"jsonrpc": "2.0", "id": 4, "method": "shutdown"}),
+            json!({"jsonrpc": "2.0", "id": 5, "method": "git-ast/nodeAt"}),
+        ] {
+            write_message(&mut input, &request).unwrap();
+        }
+        let mut output = Vec::new();
+        let mut server = Server::new(repo).unwrap();
+        serve(&mut server, std::io::Cursor::new(input), &mut output).unwrap();
+
+        let mut output = output.as_slice();
+        let mut responses = Vec::new();
+        while let Some(body) = read_message(&mut output).unwrap() {
+            responses.push(serde_json::from_slice::<Value>(&body).unwrap());
+        }
+        assert_eq!(responses.len(), 4, "{:?}", responses);
+        assert_eq!(responses[0]["result"]["version"], crate::model::VERSION);
+        assert_eq!(responses[0]["result"]["kind"], "integer_literal");
+        assert_eq!(responses[0]["result"]["definition"]["name"], "one");
+        assert_eq!(responses[1]["result"]["head"], Value::Null);
+        let change = &responses[1]["result"]["anchors"][0];
+        assert_eq!(change["source"]["old"], json!({"start": 2, "end": 2}));
+        assert_eq!(change["source"]["new"], json!({"start": 2, "end": 2}));
+        assert_eq!(responses[2]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(responses[3]["result"], Value::Null);
+    }
+
+    #[test]
+    fn pages_streams_and_cancels_long_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let sig = Signature::now("t", "t@example.com").unwrap();
+        let mut calls = String::new();
+        for n in 0..3 {
+            calls.push_str("    old();\n");
+            let source = format!("fn f{}() {{\n{}}}\n", n, calls);
+            let mut builder = repo.treebuilder(None).unwrap();
+            let blob = repo.blob(source.as_bytes()).unwrap();
+            builder.insert("a.rs", blob, 0o100644).unwrap();
+            let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+            let parent = repo.head().ok().map(|h| h.peel_to_commit().unwrap());
+            let message = format!("call old {} times", n + 1);
+            let parents: Vec<_> = parent.iter().collect();
+            repo.commit(Some("HEAD"), &sig, &sig, &message, &tree, &parents)
+                .unwrap();
+        }
+        let mut server = Server::new(repo).unwrap();
+        let query = r#"(call_expression function: (identifier) @f (#eq? @f "old"))"#;
+        let mut request = |method: &str, params: Value| {
+            let body = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+            handle(&mut server, body.to_string().as_bytes()).unwrap()["result"].clone()
+        };
+
+        let first = request("git-ast/log", json!({"query": query, "limit": 2}));
+        assert_eq!(first["items"].as_array().unwrap().len(), 2);
+        assert_eq!(first["items"][0]["summary"], "call old 3 times");
+        let cursor = first["cursor"].clone();
+        assert_eq!(cursor, first["items"][1]["commit"]);
+        let rest = request("git-ast/log", json!({"query": query, "cursor": cursor}));
+        assert_eq!(rest["items"][0]["summary"], "call old 1 times");
+        assert_eq!(rest["cursor"], Value::Null);
+
+        let first = request("git-ast/grep", json!({"query": query, "limit": 2}));
+        assert_eq!(first["items"][1]["start"], json!({"line": 3, "column": 5}));
+        let rest = request(
+            "git-ast/grep",
+            json!({"query": query, "cursor": first["cursor"]}),
+        );
+        assert_eq!(rest["items"].as_array().unwrap().len(), 1);
+        assert_eq!(rest["cursor"], Value::Null);
+
+        // Streamed: one notification per item, then an empty page.
+        let mut input = Vec::new();
+        let log = json!({"jsonrpc": "2.0", "id": 7, "method": "git-ast/log",
+                         "params": {"query": query, "stream": true}});
+        write_message(&mut input, &log).unwrap();
+        let mut output = Vec::new();
+        serve(&mut server, std::io::Cursor::new(input), &mut output).unwrap();
+        let mut output = output.as_slice();
+        let mut messages = Vec::new();
+        while let Some(body) = read_message(&mut output).unwrap() {
+            messages.push(serde_json::from_slice::<Value>(&body).unwrap());
+        }
+        assert_eq!(messages.len(), 4, "{:?}", messages);
+        assert_eq!(messages[0]["method"], "git-ast/item");
+        assert_eq!(messages[0]["params"]["id"], 7);
+        assert_eq!(messages[2]["params"]["item"]["summary"], "call old 1 times");
+        assert_eq!(messages[3]["result"]["items"], json!([]));
+
+        // Cancelled while it runs.
+        let (sender, messages) = mpsc::channel();
+        let cancel = json!({"jsonrpc": "2.0", "method": "$/cancelRequest", "params": {"id": 7}});
+        sender.send(Ok(cancel.to_string().into_bytes())).unwrap();
+        let mut inbox = Inbox {
+            messages,
+            pending: VecDeque::new(),
+            cancelled: Vec::new(),
+        };
+        let mut sent = Vec::new();
+        let mut stream = Stream {
+            id: Some(json!(7)),
+            output: Some(&mut sent),
+            inbox: Some(&mut inbox),
+        };
+        let params = json!({"query": query, "stream": true});
+        let error = server
+            .call_streaming("git-ast/log", params, &mut stream)
+            .unwrap_err();
+        assert_eq!(error.code, REQUEST_CANCELLED);
+        assert!(sent.is_empty());
+    }
+}
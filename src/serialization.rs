@@ -0,0 +1,612 @@
+//! AST Blob Serialization
+//!
+//! Defines the byte format of the blobs the clean filter hands to Git.
+//!
+//! ## Format (version 1)
+//!
+//! A blob is a small text header, an empty line, and the tree, one node per
+//! line:
+//!
+//! ```text
+//! git-ast 1
+//! language rust
+//! grammar 0.23.3
+//!
+//! source_file
+//!  function_item
+//!   "fn"
+//!   name: identifier "add"
+//!   parameters: parameters
+//!    "("
+//!    ...
+//!   body: block
+//!    "{"
+//!    ^line_comment "// Simple addition"
+//!    ...
+//! ```
+//!
+//! -   **Header:** The first line (`git-ast <version>`) is the magic that
+//!     identifies an AST blob; `language` selects the provider for smudging;
+//!     `grammar` records the grammar version the tree was produced with. An
+//!     optional `compression zstd <dictionary>` line means the tree that
+//!     follows is compressed (see [`compression`](crate::compression)). An
+//!     optional `sparse` line marks a skeleton tree that carries the file
+//!     text verbatim (see [`sparse`](crate::sparse)).
+//! -   **Indentation:** One space per tree level.
+//! -   **Node line:** `[+][^][field: ]kind` for inner nodes,
+//!     `[+][^][field: ]kind "text"` for named tokens, and `"text"` for
+//!     anonymous tokens (`"kind" "text"` if the two differ). `+` marks
+//!     `blank_line_before`, `^` marks `own_line` (see [`ast`](crate::ast)).
+//! -   **Text escapes:** `\\`, `\"`, `\n`, `\r`, `\t`, and `\u{..}` for other
+//!     control characters. Everything else is literal UTF-8.
+//! -   **Injections:** A leaf may be followed, one level deeper, by
+//!     `=> <language> <start>..<end>` lines, each followed by the embedded
+//!     tree another level deeper (see [`injections`](crate::injections)).
+//!
+//! The format is line-oriented on purpose: Git delta-compresses it well,
+//! golden snapshots of it are reviewable, and it can be produced and consumed
+//! without holding more than one line of context.
+//!
+//! ## Determinism
+//!
+//! A blob's bytes decide its object ID, so the same source must encode to the
+//! same bytes on every platform and build; otherwise two clones would keep
+//! rewriting each other's blobs. The encoder guarantees this by construction:
+//!
+//! -   Children, fields and injections are kept in source order (`Vec`s);
+//!     no hash-ordered collection feeds the output.
+//! -   Everything is text: no endianness and no `usize` widths. The only
+//!     numbers (injection offsets) are printed as decimal integers, and there
+//!     are no floats.
+//! -   Lines always end in `\n`, and escapes use a fixed spelling (lowercase
+//!     hex in `\u{..}`).
+//! -   The clean filter normalizes a file whose lines all end in CRLF to LF
+//!     before parsing (see [`clean_as`](crate::git_plumbing::filters::clean_as)),
+//!     so a Windows checkout with `core.autocrlf` stores the same blob as a
+//!     Unix one.
+//!
+//! The golden corpus (`git-ast selftest`) checks this on each CI platform:
+//! the committed snapshots are compared byte for byte, and every input is
+//! cleaned a second time with CRLF line endings.
+
+use crate::ast::Node;
+use crate::compression;
+use crate::injections::Injected;
+use crate::Error;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Write};
+
+/// Magic prefix of every AST blob.
+pub const MAGIC: &str = "git-ast";
+/// Current blob format version.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Metadata recorded at the top of every AST blob.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobHeader {
+    pub format_version: u32,
+    /// Name of the [`LanguageProvider`](crate::languages::LanguageProvider).
+    pub language: String,
+    /// Grammar version the tree was produced with.
+    pub grammar: String,
+    /// Dictionary ID if the tree is zstd-compressed.
+    pub compression: Option<String>,
+    /// Whether the tree is a [`sparse`](crate::sparse) skeleton.
+    pub sparse: bool,
+}
+
+impl BlobHeader {
+    /// Header for a tree produced now by `provider`.
+    pub fn for_provider(provider: &crate::languages::LanguageProvider) -> Self {
+        BlobHeader {
+            format_version: FORMAT_VERSION,
+            language: provider.name.to_string(),
+            grammar: provider.grammar_version.to_string(),
+            compression: None,
+            sparse: false,
+        }
+    }
+
+    /// Lines before the tree, including the empty line.
+    fn lines(&self) -> usize {
+        4 + usize::from(self.compression.is_some()) + usize::from(self.sparse)
+    }
+}
+
+/// Whether `content` looks like an AST blob (as opposed to plain source).
+pub fn is_ast_blob(content: &[u8]) -> bool {
+    content.starts_with(MAGIC.as_bytes()) && content.get(MAGIC.len()) == Some(&b' ')
+}
+
+/// Serializes a tree into blob bytes.
+pub fn encode(header: &BlobHeader, root: &Node) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::new();
+    encode_to(header, root, &mut out)?;
+    Ok(out)
+}
+
+/// Like [`encode`], writing the blob to `out` a line at a time instead of
+/// building it in memory.
+pub fn encode_to<W: Write>(header: &BlobHeader, root: &Node, mut out: W) -> Result<(), Error> {
+    writeln!(out, "{} {}", MAGIC, header.format_version)?;
+    writeln!(out, "language {}", header.language)?;
+    writeln!(out, "grammar {}", header.grammar)?;
+    if let Some(dictionary) = &header.compression {
+        writeln!(out, "compression zstd {}", dictionary)?;
+    }
+    if header.sparse {
+        writeln!(out, "sparse")?;
+    }
+    out.write_all(b"\n")?;
+    let mut line = String::new();
+    match &header.compression {
+        Some(dictionary) => {
+            let mut encoder = compression::encoder(out, dictionary)?;
+            encode_node(root, 0, &mut line, &mut encoder)?;
+            encoder.finish()?;
+        }
+        None => encode_node(root, 0, &mut line, &mut out)?,
+    }
+    Ok(())
+}
+
+/// The 1-based line of every leaf of `root` (in [`Node::leaves`] order) in
+/// the blob [`encode`] produces for it, if uncompressed.
+pub fn leaf_lines(header: &BlobHeader, root: &Node) -> Vec<usize> {
+    fn walk(node: &Node, line: &mut usize, out: &mut Vec<usize>) {
+        if node.is_leaf() {
+            out.push(*line);
+        }
+        *line += 1;
+        for injected in &node.injections {
+            // The `=>` line, then the embedded tree; its leaves are not ours.
+            *line += 1;
+            walk(&injected.root, line, &mut Vec::new());
+        }
+        for child in &node.children {
+            walk(child, line, out);
+        }
+    }
+    let mut out = Vec::new();
+    let mut line = header.lines() + 1;
+    walk(root, &mut line, &mut out);
+    out
+}
+
+/// Writes the lines of `node`'s subtree to `writer`, building each in
+/// `out`.
+fn encode_node<W: Write>(
+    node: &Node,
+    depth: usize,
+    out: &mut String,
+    writer: &mut W,
+) -> io::Result<()> {
+    out.clear();
+    for _ in 0..depth {
+        out.push(' ');
+    }
+    if node.blank_line_before {
+        out.push('+');
+    }
+    if node.own_line {
+        out.push('^');
+    }
+    if let Some(field) = &node.field {
+        out.push_str(field);
+        out.push_str(": ");
+    }
+    match (&node.text, node.named) {
+        (Some(text), true) => {
+            out.push_str(&node.kind);
+            out.push(' ');
+            quote(text, out);
+        }
+        (Some(text), false) => {
+            quote(&node.kind, out);
+            if text != &node.kind {
+                out.push(' ');
+                quote(text, out);
+            }
+        }
+        (None, true) => out.push_str(&node.kind),
+        (None, false) => quote(&node.kind, out),
+    }
+    out.push('\n');
+    writer.write_all(out.as_bytes())?;
+    for injected in &node.injections {
+        for _ in 0..=depth {
+            writer.write_all(b" ")?;
+        }
+        writeln!(
+            writer,
+            "=> {} {}..{}",
+            injected.language, injected.range.start, injected.range.end
+        )?;
+        encode_node(&injected.root, depth + 2, out, writer)?;
+    }
+    for child in &node.children {
+        encode_node(child, depth + 1, out, writer)?;
+    }
+    Ok(())
+}
+
+fn quote(text: &str, out: &mut String) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{{{:x}}}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Parses blob bytes back into header and tree.
+pub fn decode(content: &[u8]) -> Result<(BlobHeader, Node), Error> {
+    decode_from(content)
+}
+
+/// The header of blob bytes, without decoding the tree.
+pub fn decode_header_of(content: &[u8]) -> Result<BlobHeader, Error> {
+    let end = content
+        .windows(2)
+        .position(|w| w == b"\n\n")
+        .ok_or_else(|| Error::Serialization("missing end of header".to_string()))?;
+    let text = std::str::from_utf8(&content[..end + 1])
+        .map_err(|_| Error::Serialization("header is not UTF-8".to_string()))?;
+    decode_header(text)
+}
+
+/// Like [`decode`], reading the blob from `input` a line at a time; only
+/// the tree is held in memory, never the blob.
+pub fn decode_from<R: BufRead>(mut input: R) -> Result<(BlobHeader, Node), Error> {
+    let mut header = String::new();
+    loop {
+        let start = header.len();
+        let read = input
+            .read_line(&mut header)
+            .map_err(|e| read_error(e, false))?;
+        if read == 0 {
+            return Err(Error::Serialization("missing end of header".to_string()));
+        }
+        if &header[start..] == "\n" {
+            header.truncate(start);
+            break;
+        }
+    }
+    let header = decode_header(&header)?;
+    match &header.compression {
+        Some(dictionary) => {
+            let body = io::BufReader::new(compression::decoder(input, dictionary)?);
+            decode_body(header, body, true)
+        }
+        None => decode_body(header, input, false),
+    }
+}
+
+fn read_error(e: io::Error, compressed: bool) -> Error {
+    if compressed {
+        Error::Serialization(format!("corrupt compressed payload: {}", e))
+    } else if e.kind() == io::ErrorKind::InvalidData {
+        Error::Serialization(format!("blob is not valid UTF-8: {}", e))
+    } else {
+        Error::from(e)
+    }
+}
+
+fn decode_body<R: BufRead>(
+    header: BlobHeader,
+    mut body: R,
+    compressed: bool,
+) -> Result<(BlobHeader, Node), Error> {
+    // Stack of (depth, entry) for the current path from the root.
+    let mut stack: Vec<(usize, Entry)> = Vec::new();
+    let mut buffer = String::new();
+    let mut lineno = 0;
+    loop {
+        buffer.clear();
+        if body
+            .read_line(&mut buffer)
+            .map_err(|e| read_error(e, compressed))?
+            == 0
+        {
+            break;
+        }
+        lineno += 1;
+        let line = buffer.strip_suffix('\n').unwrap_or(&buffer);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let depth = line.len() - line.trim_start_matches(' ').len();
+        let entry = decode_line(&line[depth..])
+            .map_err(|msg| Error::Serialization(format!("tree line {}: {}", lineno, msg)))?;
+        while stack.last().is_some_and(|(d, _)| *d >= depth) {
+            attach(&mut stack)
+                .map_err(|msg| Error::Serialization(format!("tree line {}: {}", lineno, msg)))?;
+        }
+        if depth != stack.len() {
+            return Err(Error::Serialization(format!(
+                "tree line {}: unexpected indentation",
+                lineno
+            )));
+        }
+        stack.push((depth, entry));
+    }
+    while stack.len() > 1 {
+        attach(&mut stack).map_err(Error::Serialization)?;
+    }
+    match stack.pop() {
+        Some((_, Entry::Node(root))) => Ok((header, root)),
+        Some((_, Entry::Injection(..))) => Err(Error::Serialization(
+            "tree starts with an injection".to_string(),
+        )),
+        None => Err(Error::Serialization("empty tree".to_string())),
+    }
+}
+
+/// A decoded line: a node, or the `=>` header of an injection whose tree
+/// follows.
+enum Entry {
+    Node(Node),
+    Injection(Injected, bool),
+}
+
+/// Pops the top entry and attaches it to its parent, fixing up anonymous
+/// nodes that turned out to have children.
+fn attach(stack: &mut Vec<(usize, Entry)>) -> Result<(), String> {
+    let Some((_, child)) = stack.pop() else {
+        return Ok(());
+    };
+    let Some((_, parent)) = stack.last_mut() else {
+        return Ok(());
+    };
+    match (parent, child) {
+        (Entry::Node(parent), Entry::Node(child)) => {
+            if !parent.named && parent.children.is_empty() && parent.injections.is_empty() {
+                // `"kind"` alone parses as a token; it has children, so it is inner.
+                parent.text = None;
+            }
+            parent.children.push(child);
+        }
+        (Entry::Node(parent), Entry::Injection(injected, true)) => parent.injections.push(injected),
+        (Entry::Node(_), Entry::Injection(injected, false)) => {
+            return Err(format!("{} injection without a tree", injected.language));
+        }
+        (Entry::Injection(injected, filled @ false), Entry::Node(root)) => {
+            injected.root = root;
+            *filled = true;
+        }
+        (Entry::Injection(..), _) => return Err("injection holds more than one tree".to_string()),
+    }
+    Ok(())
+}
+
+fn decode_header(text: &str) -> Result<BlobHeader, Error> {
+    let mut lines = text.lines();
+    let version = lines
+        .next()
+        .and_then(|l| l.strip_prefix(MAGIC))
+        .and_then(|v| v.trim().parse::<u32>().ok())
+        .ok_or_else(|| Error::Serialization("not an AST blob (bad magic)".to_string()))?;
+    if version != FORMAT_VERSION {
+        return Err(Error::Serialization(format!(
+            "unsupported blob format version {} (this build reads {})",
+            version, FORMAT_VERSION
+        )));
+    }
+    let mut language = None;
+    let mut grammar = None;
+    let mut compression = None;
+    let mut sparse = false;
+    for line in lines {
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "language" => language = Some(value.to_string()),
+            "grammar" => grammar = Some(value.to_string()),
+            "sparse" => sparse = true,
+            "compression" => match value.split_once(' ') {
+                Some(("zstd", dictionary)) => compression = Some(dictionary.to_string()),
+                _ => {
+                    return Err(Error::Serialization(format!(
+                        "unsupported compression {:?}",
+                        value
+                    )));
+                }
+            },
+            other => {
+                return Err(Error::Serialization(format!(
+                    "unknown header key {:?}",
+                    other
+                )));
+            }
+        }
+    }
+    Ok(BlobHeader {
+        format_version: version,
+        language: language
+            .ok_or_else(|| Error::Serialization("header lacks language".to_string()))?,
+        grammar: grammar.ok_or_else(|| Error::Serialization("header lacks grammar".to_string()))?,
+        compression,
+        sparse,
+    })
+}
+
+fn decode_line(line: &str) -> Result<Entry, String> {
+    match line.strip_prefix("=> ") {
+        Some(rest) => decode_injection(rest).map(|injected| Entry::Injection(injected, false)),
+        None => decode_node(line).map(Entry::Node),
+    }
+}
+
+fn decode_injection(line: &str) -> Result<Injected, String> {
+    let (language, range) = line.split_once(' ').ok_or("injection lacks a range")?;
+    let (start, end) = range.split_once("..").ok_or("malformed injection range")?;
+    let parse = |n: &str| {
+        n.parse::<usize>()
+            .map_err(|_| format!("bad offset {:?}", n))
+    };
+    Ok(Injected {
+        language: language.to_string(),
+        range: parse(start)?..parse(end)?,
+        root: Node::default(),
+    })
+}
+
+fn decode_node(mut line: &str) -> Result<Node, String> {
+    let mut node = Node::default();
+    if let Some(rest) = line.strip_prefix('+') {
+        node.blank_line_before = true;
+        line = rest;
+    }
+    if let Some(rest) = line.strip_prefix('^') {
+        node.own_line = true;
+        line = rest;
+    }
+    if !line.starts_with('"') {
+        if let Some((field, rest)) = line.split_once(": ") {
+            if !field.contains([' ', '"']) {
+                node.field = Some(field.to_string());
+                line = rest;
+            }
+        }
+    }
+    if line.starts_with('"') {
+        let (kind, rest) = unquote(line)?;
+        node.named = false;
+        node.text = Some(match rest.strip_prefix(' ') {
+            Some(text) => unquote(text)?.0,
+            None if rest.is_empty() => kind.clone(),
+            None => return Err(format!("trailing characters {:?}", rest)),
+        });
+        node.kind = kind;
+    } else {
+        node.named = true;
+        match line.split_once(' ') {
+            Some((kind, text)) => {
+                let (text, rest) = unquote(text)?;
+                if !rest.is_empty() {
+                    return Err(format!("trailing characters {:?}", rest));
+                }
+                node.kind = kind.to_string();
+                node.text = Some(text);
+            }
+            None => node.kind = line.to_string(),
+        }
+    }
+    if node.kind.is_empty() {
+        return Err("empty node kind".to_string());
+    }
+    Ok(node)
+}
+
+/// Parses a quoted string at the start of `s`, returning it and the rest.
+fn unquote(s: &str) -> Result<(String, &str), String> {
+    let mut chars = s.char_indices();
+    if chars.next().map(|(_, c)| c) != Some('"') {
+        return Err("expected '\"'".to_string());
+    }
+    let mut out = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((out, &s[i + 1..])),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('\\') => out.push('\\'),
+                Some('"') => out.push('"'),
+                Some('n') => out.push('\n'),
+                Some('r') => out.push('\r'),
+                Some('t') => out.push('\t'),
+                Some('u') => {
+                    let rest = &s[i + 2..];
+                    let end = rest.find('}').ok_or("unterminated \\u{...}")?;
+                    let code = rest
+                        .get(1..end)
+                        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                        .and_then(char::from_u32)
+                        .ok_or("invalid \\u{...} escape")?;
+                    out.push(code);
+                    for _ in 0..=end {
+                        chars.next();
+                    }
+                }
+                other => return Err(format!("invalid escape {:?}", other)),
+            },
+            c => out.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(kind: &str, named: bool, text: &str) -> Node {
+        Node {
+            kind: kind.to_string(),
+            named,
+            text: Some(text.to_string()),
+            ..Node::default()
+        }
+    }
+
+    #[test]
+    fn round_trips_escapes_flags_and_fields() {
+        let mut comment = leaf("line_comment", true, "// \"quoted\"\t\\ \u{7}");
+        comment.own_line = true;
+        comment.blank_line_before = true;
+        let mut name = leaf("identifier", true, "main");
+        name.field = Some("name".to_string());
+        let root = Node {
+            kind: "source_file".to_string(),
+            named: true,
+            children: vec![
+                comment,
+                Node {
+                    kind: "function_item".to_string(),
+                    named: true,
+                    children: vec![leaf("fn", false, "fn"), name, leaf("\"", false, "\"")],
+                    ..Node::default()
+                },
+            ],
+            ..Node::default()
+        };
+        let header = BlobHeader {
+            format_version: FORMAT_VERSION,
+            language: "rust".to_string(),
+            grammar: "0.23.3".to_string(),
+            compression: None,
+            sparse: false,
+        };
+        let bytes = encode(&header, &root).unwrap();
+        assert!(is_ast_blob(&bytes));
+        assert_eq!(decode(&bytes).unwrap(), (header.clone(), root.clone()));
+
+        let compressed = BlobHeader {
+            compression: Some("rust@0.23.3".to_string()),
+            ..header.clone()
+        };
+        let packed = encode(&compressed, &root).unwrap();
+        assert!(is_ast_blob(&packed));
+        assert_eq!(decode(&packed).unwrap(), (compressed, root.clone()));
+
+        let text = String::from_utf8(bytes).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        let at: Vec<&str> = leaf_lines(&header, &root)
+            .iter()
+            .map(|&l| lines[l - 1].trim())
+            .collect();
+        assert!(at[0].starts_with("+^line_comment"), "{:?}", at);
+        assert_eq!(at[1..], ["\"fn\"", "name: identifier \"main\"", "\"\\\"\""]);
+    }
+
+    #[test]
+    fn rejects_plain_source() {
+        assert!(!is_ast_blob(b"fn main() {}\n"));
+        assert!(decode(b"fn main() {}\n\nfoo").is_err());
+    }
+}
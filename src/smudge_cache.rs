@@ -0,0 +1,70 @@
+//! Smudge Cache
+//!
+//! Printing a blob only depends on the blob and on the printer, so the
+//! source of a blob can be computed ahead of time: `git-ast prewarm <rev>`
+//! smudges every AST blob reachable from a revision into
+//!
+//! ```text
+//! .git/ast-cache/smudge/<2 hex>/<38 hex>
+//! ```
+//!
+//! and the filter answers a canonical smudge from there instead of decoding
+//! and printing. The file name is a hash of the blob id and the `git-ast`
+//! version, so an upgraded printer never serves stale source.
+//!
+//! The filter only looks at the cache when the directory exists, i.e. after
+//! a prewarm; otherwise smudge streams as usual. Smudges with
+//! `ast.verifyOnSmudge` or another `ast.smudgeMode` never use it. Deleting
+//! the directory at any time is safe.
+
+use crate::util::atomic_io;
+use crate::Error;
+use git2::{ObjectType, Oid, Repository};
+use std::fs;
+use std::path::PathBuf;
+
+/// On-disk cache of smudged blobs, below the repository's Git directory.
+#[derive(Debug, Clone)]
+pub struct SmudgeCache {
+    dir: PathBuf,
+}
+
+impl SmudgeCache {
+    /// The cache of `repo` (`.git/ast-cache/smudge`).
+    pub fn open(repo: &Repository) -> Self {
+        SmudgeCache {
+            dir: repo.path().join("ast-cache").join("smudge"),
+        }
+    }
+
+    /// Whether anything was ever prewarmed.
+    pub fn exists(&self) -> bool {
+        self.dir.is_dir()
+    }
+
+    /// Cache key for the printed form of `blob`.
+    pub fn key(blob: Oid) -> Result<Oid, Error> {
+        let material = format!("{}\ngit-ast {}", blob, env!("CARGO_PKG_VERSION"));
+        Ok(Oid::hash_object(ObjectType::Blob, material.as_bytes())?)
+    }
+
+    /// The cached source of `blob`, if any.
+    pub fn get(&self, blob: Oid) -> Option<Vec<u8>> {
+        fs::read(self.path(blob).ok()?).ok()
+    }
+
+    /// Whether `blob` is cached.
+    pub fn contains(&self, blob: Oid) -> bool {
+        self.path(blob).is_ok_and(|path| path.is_file())
+    }
+
+    /// Stores `source` as the printed form of `blob`, atomically.
+    pub fn put(&self, blob: Oid, source: &[u8]) -> Result<(), Error> {
+        atomic_io::write(self.path(blob)?, source)
+    }
+
+    fn path(&self, blob: Oid) -> Result<PathBuf, Error> {
+        let hex = Self::key(blob)?.to_string();
+        Ok(self.dir.join(&hex[..2]).join(&hex[2..]))
+    }
+}
@@ -0,0 +1,157 @@
+//! Sparse Blobs
+//!
+//! Vendored and generated code is rarely edited by hand, can be huge, and is
+//! not always in the canonical format (or even free of syntax errors). Paths
+//! matching `[sparse] paths` in `.git-ast.toml` (see
+//! [`SparseConfig`](crate::config::SparseConfig)) are stored as *sparse*
+//! blobs instead: the header carries a `sparse` line, and the tree is only a
+//! skeleton of the file's top-level items followed by the file text itself:
+//!
+//! ```text
+//! git-ast 1
+//! language rust
+//! grammar 0.23.3
+//! sparse
+//!
+//! source_file
+//!  function_item
+//!   name: identifier "add"
+//!   signature "fn add(a: i32, b: i32) -> i32"
+//!  source "fn add(a: i32,b: i32) -> i32 { a+b }\n"
+//! ```
+//!
+//! Smudging a sparse blob returns the `source` text byte for byte, so the
+//! file round-trips exactly as if it had no filter, and cleaning skips the
+//! syntax check, the full tree conversion, injections and normalization. The
+//! skeleton still names every item, so tools indexing symbols (see
+//! [`symbols`]) work on sparse files without parsing them again.
+
+use crate::ast::Node;
+use crate::languages::LanguageProvider;
+use crate::parsing::{self, ParseLimits};
+use crate::Error;
+
+/// Kind of the root child holding the file text.
+const SOURCE: &str = "source";
+/// Kind of an item's signature token.
+const SIGNATURE: &str = "signature";
+
+/// Builds the sparse tree of `source`: one node per top-level item with its
+/// name and signature, then the text verbatim.
+pub fn skeleton(
+    source: &[u8],
+    provider: &LanguageProvider,
+    limits: &ParseLimits,
+) -> Result<Node, Error> {
+    let tree = parsing::parse(source, provider, limits)?;
+    let root = tree.root_node();
+    let mut cursor = root.walk();
+    let mut children: Vec<Node> = root
+        .named_children(&mut cursor)
+        .filter(|node| !provider.syntax.comments.contains(&node.kind()))
+        .map(|node| {
+            let mut item = Node {
+                kind: node.kind().to_string(),
+                named: true,
+                ..Node::default()
+            };
+            let name = ["name", "type", "declarator"]
+                .iter()
+                .find_map(|field| Some((*field, node.child_by_field_name(field)?)));
+            if let Some((field, name)) = name {
+                item.children.push(Node {
+                    kind: name.kind().to_string(),
+                    named: true,
+                    field: Some(field.to_string()),
+                    text: Some(collapse(&source[name.byte_range()])),
+                    ..Node::default()
+                });
+            }
+            // Everything before the body, or the first line for items without one.
+            let head = match node.child_by_field_name("body") {
+                Some(body) => &source[node.start_byte()..body.start_byte()],
+                None => {
+                    let text = &source[node.byte_range()];
+                    text.split(|&b| b == b'\n').next().unwrap_or_default()
+                }
+            };
+            item.children.push(Node {
+                kind: SIGNATURE.to_string(),
+                named: true,
+                text: Some(collapse(head)),
+                ..Node::default()
+            });
+            item
+        })
+        .collect();
+    children.push(Node {
+        kind: SOURCE.to_string(),
+        named: true,
+        text: Some(
+            String::from_utf8(source.to_vec())
+                .map_err(|e| Error::Parsing(format!("source is not valid UTF-8: {}", e)))?,
+        ),
+        ..Node::default()
+    });
+    Ok(Node {
+        kind: root.kind().to_string(),
+        named: true,
+        children,
+        ..Node::default()
+    })
+}
+
+/// The file text stored in a sparse tree.
+pub fn source(root: &Node) -> Result<&str, Error> {
+    root.children
+        .last()
+        .filter(|node| node.kind == SOURCE)
+        .and_then(|node| node.text.as_deref())
+        .ok_or_else(|| Error::Serialization("sparse blob lacks its source".to_string()))
+}
+
+/// The `(kind, name)` of every named item in a sparse tree.
+pub fn symbols(root: &Node) -> impl Iterator<Item = (&str, &str)> {
+    root.children.iter().filter_map(|item| {
+        let name = item.children.iter().find(|c| c.field.is_some())?;
+        Some((item.kind.as_str(), name.text.as_deref()?))
+    })
+}
+
+/// `text` with each run of whitespace turned into one space, trimmed.
+fn collapse(text: &[u8]) -> String {
+    let text = String::from_utf8_lossy(text);
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::languages;
+    use crate::serialization::{self, BlobHeader};
+
+    #[test]
+    fn keeps_text_verbatim_and_indexes_items() {
+        let rust = languages::by_name("rust").unwrap();
+        // Not canonical, and the last item does not even parse.
+        let text = "// vendored\nstruct  Point { x: i32 }\nfn add(a: i32,\n  b: i32) -> i32 { a+b }\nfn broken( {\n";
+        let root = skeleton(text.as_bytes(), rust, &ParseLimits::default()).unwrap();
+        assert_eq!(source(&root).unwrap(), text);
+        let symbols: Vec<_> = symbols(&root).collect();
+        assert_eq!(
+            symbols[..2],
+            [("struct_item", "Point"), ("function_item", "add")]
+        );
+        assert_eq!(
+            root.children[1].children[1].text.as_deref(),
+            Some("fn add(a: i32, b: i32) -> i32")
+        );
+
+        let header = BlobHeader {
+            sparse: true,
+            ..BlobHeader::for_provider(rust)
+        };
+        let blob = serialization::encode(&header, &root).unwrap();
+        assert_eq!(serialization::decode(&blob).unwrap(), (header, root));
+    }
+}
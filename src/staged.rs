@@ -0,0 +1,176 @@
+//! Staged ASTs
+//!
+//! Git only shows the filter and the drivers one file at a time, through
+//! temporary files. This module works on the index directly, where the
+//! staged AST blobs are:
+//!
+//! -   [`files`] lists the staged files and whether each is an AST blob.
+//! -   [`source`] prints a staged blob, [`write`] cleans source and stages
+//!     the result, leaving the working tree alone.
+//! -   [`verify`] checks every staged AST blob as `git-ast hook pre-push`
+//!     checks outgoing ones ([`filters::verify_round_trip`]), before the
+//!     commit is even made.
+//! -   [`diff`] compares the staged files with `HEAD` semantically, as
+//!     `git diff --cached` through the diff driver would (see
+//!     [`changes`]).
+//!
+//! Only stage-0 entries are considered: a conflicted path has no single
+//! staged version.
+
+use crate::changes;
+use crate::config;
+use crate::diff_backend::DiffFormat;
+use crate::git_plumbing::filters;
+use crate::serialization;
+use crate::Error;
+use git2::{DiffFindOptions, DiffOptions, IndexEntry, IndexTime, Oid, Repository};
+use std::path::Path;
+
+/// A stage-0 entry of the index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StagedFile {
+    pub path: String,
+    pub id: Oid,
+    pub mode: u32,
+    /// Whether the blob is an AST blob (as opposed to plain content).
+    pub is_ast: bool,
+}
+
+/// The staged files, in index order.
+pub fn files(repo: &Repository) -> Result<Vec<StagedFile>, Error> {
+    let index = repo.index()?;
+    let mut out = Vec::new();
+    for entry in index.iter() {
+        if (entry.flags >> 12) & 0x3 != 0 {
+            continue; // Conflict stage.
+        }
+        let blob = repo.find_blob(entry.id)?;
+        out.push(StagedFile {
+            path: String::from_utf8_lossy(&entry.path).into_owned(),
+            id: entry.id,
+            mode: entry.mode,
+            is_ast: serialization::is_ast_blob(blob.content()),
+        });
+    }
+    Ok(out)
+}
+
+/// The staged blob of `path`, if it is staged.
+pub fn blob(repo: &Repository, path: &str) -> Result<Option<Vec<u8>>, Error> {
+    let index = repo.index()?;
+    match index.get_path(Path::new(path), 0) {
+        Some(entry) => Ok(Some(repo.find_blob(entry.id)?.content().to_vec())),
+        None => Ok(None),
+    }
+}
+
+/// The staged version of `path` as source: AST blobs printed, anything
+/// else as stored.
+pub fn source(repo: &Repository, path: &str) -> Result<Vec<u8>, Error> {
+    let blob = blob(repo, path)?.ok_or_else(|| Error::Config(format!("{} is not staged", path)))?;
+    filters::perform_smudge(&blob, path)
+}
+
+/// Cleans `source` as the clean filter would for `path` and stages the
+/// result, keeping the entry's mode (a new entry is a regular file).
+/// Returns the staged blob.
+pub fn write(repo: &Repository, path: &str, source: &[u8]) -> Result<Oid, Error> {
+    let limits = config::Settings::load(Some(repo))?.parse_limits;
+    let repo_config = config::repo_config_for(repo, path)?;
+    let provider = config::language_for_path(Some(repo), path, source)?;
+    let blob = filters::clean_with(source, path, provider, &limits, &repo_config)?;
+    let mut index = repo.index()?;
+    let entry = match index.get_path(Path::new(path), 0) {
+        Some(entry) => entry,
+        None => IndexEntry {
+            ctime: IndexTime::new(0, 0),
+            mtime: IndexTime::new(0, 0),
+            dev: 0,
+            ino: 0,
+            mode: 0o100644,
+            uid: 0,
+            gid: 0,
+            file_size: 0,
+            id: Oid::zero(),
+            flags: 0,
+            flags_extended: 0,
+            path: path.as_bytes().to_vec(),
+        },
+    };
+    index.add_frombuffer(&entry, &blob)?;
+    index.write()?;
+    Ok(Oid::hash_object(git2::ObjectType::Blob, &blob)?)
+}
+
+/// Checks every staged AST blob; returns the paths that fail, with why.
+pub fn verify(repo: &Repository) -> Result<Vec<(String, String)>, Error> {
+    let mut failures = Vec::new();
+    for file in files(repo)?.into_iter().filter(|f| f.is_ast) {
+        let blob = repo.find_blob(file.id)?;
+        if let Err(e) = filters::verify_round_trip(blob.content(), &file.path) {
+            failures.push((file.path, e.to_string()));
+        }
+    }
+    Ok(failures)
+}
+
+/// Renders the staged changes against `HEAD` (or, before the first commit,
+/// against nothing), limited to `paths` if any are given.
+pub fn diff(repo: &Repository, paths: &[String], format: DiffFormat) -> Result<Vec<u8>, Error> {
+    let head = match repo.head() {
+        Ok(head) => Some(head.peel_to_tree()?),
+        Err(_) => None,
+    };
+    let mut options = DiffOptions::new();
+    for path in paths {
+        options.pathspec(path);
+    }
+    let mut staged = repo.diff_tree_to_index(head.as_ref(), None, Some(&mut options))?;
+    staged.find_similar(Some(DiffFindOptions::new().renames(true)))?;
+    let mut out = Vec::new();
+    for change in changes::from_diff(repo, &staged)? {
+        out.extend(change.render(Some(repo), format)?);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_verifies_and_diffs_the_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let signature = git2::Signature::now("t", "t@example.com").unwrap();
+        write(&repo, "a.rs", b"fn  a() -> i32 { 1 }\n").unwrap();
+        let tree = repo
+            .find_tree(repo.index().unwrap().write_tree().unwrap())
+            .unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "one", &tree, &[])
+            .unwrap();
+        assert!(diff(&repo, &[], DiffFormat::Readable).unwrap().is_empty());
+
+        write(&repo, "a.rs", b"fn a() -> i32 {\n    2\n}\n").unwrap();
+        write(&repo, "notes.txt", b"plain\n").unwrap();
+        assert_eq!(
+            source(&repo, "a.rs").unwrap(),
+            b"fn a() -> i32 {\n    2\n}\n"
+        );
+        let staged = files(&repo).unwrap();
+        assert_eq!(
+            staged
+                .iter()
+                .map(|f| (f.path.as_str(), f.is_ast))
+                .collect::<Vec<_>>(),
+            [("a.rs", true), ("notes.txt", false)]
+        );
+        assert!(verify(&repo).unwrap().is_empty());
+
+        let rendered = String::from_utf8(diff(&repo, &[], DiffFormat::Patch).unwrap()).unwrap();
+        assert!(rendered.contains("-    1\n+    2\n"), "{}", rendered);
+        assert!(rendered.contains("new file mode 100644\n"), "{}", rendered);
+        let only = diff(&repo, &["notes.txt".to_string()], DiffFormat::Readable).unwrap();
+        assert!(!String::from_utf8(only).unwrap().contains("a.rs"));
+    }
+}
@@ -0,0 +1,319 @@
+//! Structural Partial Staging
+//!
+//! `git add -p` cannot stage part of a file that has `filter=ast`: Git diffs
+//! the index blob against the *cleaned* working-tree file, so its hunks are
+//! hunks of the serialized tree, and staging only some of them leaves a blob
+//! the smudge filter may not print, or one the clean filter would never have
+//! produced for any text.
+//!
+//! Here the hunks are hunks of the tree instead. [`hunks`] compares the
+//! top-level items (functions, classes, imports, comments, ...) of the index
+//! version with those of the working tree. [`partial_clean`] keeps the index
+//! version's items, takes the selected hunks from the working tree, prints
+//! the combined tree and cleans that text like any other file, so the staged
+//! blob is exactly what `git add` stores for it and checks out unchanged.
+//!
+//! Files stored [sparse](crate::sparse) have no items to choose from and are
+//! only ever staged whole.
+
+use crate::ast::Node;
+use crate::config::RepoConfig;
+use crate::git_plumbing::filters::{self, Filtered};
+use crate::languages::LanguageProvider;
+use crate::parsing::ParseLimits;
+use crate::pretty_printing;
+use crate::serialization;
+use crate::Error;
+use similar::{Algorithm, DiffOp};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// A run of top-level items that differs between the index and the working
+/// tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    /// The index version's items it replaces.
+    pub index: Range<usize>,
+    /// The working tree's items that replace them.
+    pub worktree: Range<usize>,
+    /// Names of the items on either side, for listing.
+    pub names: Vec<String>,
+}
+
+impl Hunk {
+    /// `added`, `removed` or `changed`.
+    pub fn change(&self) -> &'static str {
+        match (self.index.is_empty(), self.worktree.is_empty()) {
+            (true, _) => "added",
+            (_, true) => "removed",
+            _ => "changed",
+        }
+    }
+}
+
+/// The hunks turning the top-level items of `index` into those of `worktree`.
+pub fn hunks(index: &Node, worktree: &Node) -> Vec<Hunk> {
+    // Diff item IDs; equal items get the same one.
+    let mut ids: HashMap<&Node, usize> = HashMap::new();
+    let mut id = |item| {
+        let next = ids.len();
+        *ids.entry(item).or_insert(next)
+    };
+    let old: Vec<usize> = index.children.iter().map(&mut id).collect();
+    let new: Vec<usize> = worktree.children.iter().map(&mut id).collect();
+    similar::capture_diff_slices(Algorithm::Myers, &old, &new)
+        .into_iter()
+        .filter_map(|op| {
+            let (old, new) = match op {
+                DiffOp::Equal { .. } => return None,
+                DiffOp::Delete {
+                    old_index,
+                    old_len,
+                    new_index,
+                } => (old_index..old_index + old_len, new_index..new_index),
+                DiffOp::Insert {
+                    old_index,
+                    new_index,
+                    new_len,
+                } => (old_index..old_index, new_index..new_index + new_len),
+                DiffOp::Replace {
+                    old_index,
+                    old_len,
+                    new_index,
+                    new_len,
+                } => (
+                    old_index..old_index + old_len,
+                    new_index..new_index + new_len,
+                ),
+            };
+            let mut names: Vec<String> = Vec::new();
+            for item in index.children[old.clone()]
+                .iter()
+                .chain(&worktree.children[new.clone()])
+            {
+                let name = name(item);
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            Some(Hunk {
+                index: old,
+                worktree: new,
+                names,
+            })
+        })
+        .collect()
+}
+
+/// The index version with the `selected` hunks (indices into `hunks`) taken
+/// from the working tree.
+pub fn combine(index: &Node, worktree: &Node, hunks: &[Hunk], selected: &[usize]) -> Node {
+    let mut children = Vec::new();
+    let mut pos = 0;
+    for (i, hunk) in hunks.iter().enumerate() {
+        children.extend_from_slice(&index.children[pos..hunk.index.start]);
+        match selected.contains(&i) {
+            true => children.extend_from_slice(&worktree.children[hunk.worktree.clone()]),
+            false => children.extend_from_slice(&index.children[hunk.index.clone()]),
+        }
+        pos = hunk.index.end;
+    }
+    children.extend_from_slice(&index.children[pos..]);
+    Node {
+        children,
+        ..index.clone()
+    }
+}
+
+/// The two trees to compare for a file: the decoded `index_blob`, and the
+/// cleaned `worktree` source.
+pub fn trees(
+    index_blob: &[u8],
+    worktree: &[u8],
+    pathname: &str,
+    provider: &'static LanguageProvider,
+    limits: &ParseLimits,
+    repo_config: &RepoConfig,
+) -> Result<(Node, Node), Error> {
+    if !serialization::is_ast_blob(index_blob) {
+        return Err(Error::Config(format!(
+            "{}: the staged version is not an AST blob",
+            pathname
+        )));
+    }
+    let (header, index) = serialization::decode(index_blob)?;
+    let (worktree_header, worktree) =
+        match filters::prepare_clean(worktree, pathname, Some(provider), limits, repo_config)? {
+            Filtered::Blob(header, root) => (header, root),
+            // The working tree holds an AST blob, stored as it is.
+            Filtered::Verbatim(blob) if serialization::is_ast_blob(&blob) => {
+                serialization::decode(&blob)?
+            }
+            // A `[passthrough]` rule keeps the working-tree version as text.
+            _ => {
+                return Err(Error::Config(format!(
+                    "{}: the working-tree version is stored as text; stage it whole with git add",
+                    pathname
+                )))
+            }
+        };
+    if header.sparse || worktree_header.sparse {
+        return Err(Error::Config(format!(
+            "{}: sparse files can only be staged whole",
+            pathname
+        )));
+    }
+    Ok((index, worktree))
+}
+
+/// The blob to stage for `pathname`: the index version plus the `selected`
+/// hunks of the working tree, cleaned as text (see the module docs).
+pub fn partial_clean(
+    index_blob: &[u8],
+    worktree: &[u8],
+    pathname: &str,
+    provider: &'static LanguageProvider,
+    selected: &[usize],
+    limits: &ParseLimits,
+    repo_config: &RepoConfig,
+) -> Result<Vec<u8>, Error> {
+    let (index, worktree) = trees(
+        index_blob,
+        worktree,
+        pathname,
+        provider,
+        limits,
+        repo_config,
+    )?;
+    let hunks = hunks(&index, &worktree);
+    if let Some(i) = selected.iter().find(|&&i| i >= hunks.len()) {
+        return Err(Error::Config(format!(
+            "{}: no hunk {} (there are {})",
+            pathname,
+            i + 1,
+            hunks.len()
+        )));
+    }
+    let source = pretty_printing::print(&combine(&index, &worktree, &hunks, selected), provider);
+    filters::clean_with(
+        source.as_bytes(),
+        pathname,
+        Some(provider),
+        limits,
+        repo_config,
+    )
+}
+
+/// The name of a top-level item: its `name` (or `type`, `declarator`)
+/// field's text, or else its kind.
+pub(crate) fn name(item: &Node) -> String {
+    item.children
+        .iter()
+        .find(|child| matches!(child.field.as_deref(), Some("name" | "type" | "declarator")))
+        .map(|child| {
+            child
+                .leaves()
+                .iter()
+                .filter_map(|leaf| leaf.text.as_deref())
+                .collect()
+        })
+        .unwrap_or_else(|| item.kind.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::languages;
+
+    #[test]
+    fn stages_selected_items_only() {
+        let rust = languages::by_name("rust").unwrap();
+        let limits = ParseLimits::default();
+        let config = RepoConfig::default();
+        let staged = "fn a() {}\n\nfn b() {\n    1\n}\n\nfn c() {}\n";
+        let index = filters::clean_as(staged.as_bytes(), "a.rs", Some(rust), &limits).unwrap();
+        // `b` changes, `c` goes away and `d` is new.
+        let edited = "fn a() {}\n\nfn b() {\n    2\n}\n\nfn d() {}\n";
+
+        let (old, new) = trees(&index, edited.as_bytes(), "a.rs", rust, &limits, &config).unwrap();
+        let listed: Vec<_> = hunks(&old, &new)
+            .iter()
+            .map(|h| (h.change(), h.names.clone()))
+            .collect();
+        assert_eq!(
+            listed,
+            [(
+                "changed",
+                vec!["b".to_string(), "c".to_string(), "d".to_string()]
+            )]
+        );
+
+        let edited = "fn a() {}\n\nfn b() {\n    2\n}\n\nfn c() {}\n\nfn d() {}\n";
+        let (old, new) = trees(&index, edited.as_bytes(), "a.rs", rust, &limits, &config).unwrap();
+        assert_eq!(
+            hunks(&old, &new)
+                .iter()
+                .map(Hunk::change)
+                .collect::<Vec<_>>(),
+            ["changed", "added"]
+        );
+        let blob = partial_clean(
+            &index,
+            edited.as_bytes(),
+            "a.rs",
+            rust,
+            &[1],
+            &limits,
+            &config,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(filters::perform_smudge(&blob, "a.rs").unwrap()).unwrap(),
+            "fn a() {}\n\nfn b() {\n    1\n}\n\nfn c() {}\n\nfn d() {}\n"
+        );
+        filters::verify_round_trip(&blob, "a.rs").unwrap();
+    }
+
+    #[test]
+    fn refuses_to_split_a_passthrough_file() {
+        let rust = languages::by_name("rust").unwrap();
+        let limits = ParseLimits::default();
+        let index = filters::clean_as(b"fn a() {}\n", "a.rs", Some(rust), &limits).unwrap();
+        let mut config = RepoConfig::default();
+        config.passthrough.max_bytes = Some(5);
+
+        let error = trees(
+            &index,
+            b"fn a() {}\nfn b() {}\n",
+            "a.rs",
+            rust,
+            &limits,
+            &config,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(
+            error.contains("a.rs: the working-tree version is stored as text"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn splits_a_working_tree_file_that_is_already_an_ast_blob() {
+        let rust = languages::by_name("rust").unwrap();
+        let limits = ParseLimits::default();
+        let config = RepoConfig::default();
+        let clean = |source: &str| {
+            filters::clean_as(source.as_bytes(), "a.rs", Some(rust), &limits).unwrap()
+        };
+        let index = clean("fn a() {}\n");
+        let worktree = clean("fn a() {}\n\nfn b() {}\n");
+
+        let (old, new) = trees(&index, &worktree, "a.rs", rust, &limits, &config).unwrap();
+        let listed: Vec<_> = hunks(&old, &new).iter().map(Hunk::change).collect();
+        assert_eq!(listed, ["added"]);
+        let blob = partial_clean(&index, &worktree, "a.rs", rust, &[0], &limits, &config).unwrap();
+        assert_eq!(blob, worktree);
+    }
+}
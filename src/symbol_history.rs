@@ -0,0 +1,736 @@
+//! Symbol History Index
+//!
+//! Finding the commits that changed a symbol means walking the history and
+//! parsing every version of its file (see [`ownership`](crate::ownership)),
+//! which gets slow on long histories. Like Git's commit-graph file, this
+//! index records the answer ahead of time:
+//!
+//! ```text
+//! $ git-ast index build          # index new commits
+//! $ git-ast index build --full   # rebuild from scratch
+//! ```
+//!
+//! It covers every commit reachable from `HEAD` and the local branches.
+//! Building again only processes commits added since the last build, so a
+//! `post-commit` or `post-merge` hook can keep it current cheaply. Symbol
+//! history (`git-ast owners`, `symbolHistory`, hovers) uses the index
+//! whenever it contains `HEAD`, and walks the history otherwise.
+//!
+//! A commit changes a symbol as described in [`ownership`](crate::ownership):
+//! its leaf tokens differ from the parent's, or the parent has no such
+//! definition. Merge commits are skipped, and so are file versions that do
+//! not parse.
+//!
+//! ## Format
+//!
+//! `.git/ast/symbol-history`, big-endian throughout:
+//!
+//! ```text
+//! "SYMH" version:u32
+//! commits:u32  tips:u32  symbols:u32
+//! commit ids      commits × 20 bytes, parents before children
+//! tips            tips × u32 (commit positions the last build started from)
+//! symbols         path, kind, name (each u16 length + UTF-8),
+//!                 count:u32, count × u32 positions (ascending)
+//! checksum        20 bytes, Git blob hash of everything above
+//! ```
+//!
+//! A commit's position is its index in the commit table, so new commits are
+//! appended and existing positions never change.
+//!
+//! ## Building
+//!
+//! A build first lists the commits it will index (its *plan*), then indexes
+//! them in batches of [`CHECKPOINT_COMMITS`]. The commits of a batch are
+//! spread over worker threads, which share the fingerprints of the blobs
+//! they parse, since a commit's new blobs are its child's old ones. Each
+//! finished batch is written to `.git/ast/symbol-history.build/` before the
+//! next one starts, so a batch's parsed state is all that is held besides
+//! the index itself. An interrupted build leaves its plan and finished
+//! batches there; the next build resumes the plan, skipping those batches,
+//! and removes the directory once the index is written.
+
+use crate::ownership;
+use crate::parsing::ParseLimits;
+use crate::queries::QueryPacks;
+use crate::symbols::Symbol;
+use crate::util::atomic_io;
+use crate::Error;
+use git2::{BranchType, Commit, ObjectType, Oid, Repository, Sort};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+const MAGIC: &[u8; 4] = b"SYMH";
+const PLAN_MAGIC: &[u8; 4] = b"SYMP";
+const BATCH_MAGIC: &[u8; 4] = b"SYMB";
+
+/// Version of the file format.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Commits indexed between two checkpoints.
+pub const CHECKPOINT_COMMITS: usize = 1000;
+
+/// Location of the index inside the Git directory.
+pub fn index_path(repo: &Repository) -> PathBuf {
+    repo.path().join("ast").join("symbol-history")
+}
+
+/// Where a build in progress keeps its plan and finished batches.
+fn checkpoint_dir(repo: &Repository) -> PathBuf {
+    repo.path().join("ast").join("symbol-history.build")
+}
+
+/// A symbol, as the index identifies it.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SymbolKey {
+    pub path: String,
+    pub kind: String,
+    pub name: String,
+}
+
+/// Commit positions by symbol.
+type Symbols = BTreeMap<SymbolKey, Vec<u32>>;
+
+/// The commits that changed each symbol, by position in the commit DAG.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolHistoryIndex {
+    commits: Vec<Oid>,
+    positions: HashMap<Oid, u32>,
+    tips: Vec<u32>,
+    symbols: Symbols,
+}
+
+/// How to build the index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildOptions {
+    /// Start over instead of indexing only new commits.
+    pub full: bool,
+    /// Worker threads.
+    pub jobs: usize,
+    /// Commits per checkpoint.
+    pub batch: usize,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        BuildOptions {
+            full: false,
+            jobs: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            batch: CHECKPOINT_COMMITS,
+        }
+    }
+}
+
+/// What a build did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildStats {
+    /// Commits indexed by this build, including those of a resumed one.
+    pub new_commits: usize,
+    /// Whether an interrupted build was resumed.
+    pub resumed: bool,
+    /// Commits and symbols in the index afterwards.
+    pub commits: usize,
+    pub symbols: usize,
+}
+
+impl SymbolHistoryIndex {
+    /// The index of `repo`, or `None` if it has not been built.
+    pub fn load(repo: &Repository) -> Result<Option<Self>, Error> {
+        match std::fs::read(index_path(repo)) {
+            Ok(bytes) => Self::decode(&bytes).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Whether `commit` has been indexed.
+    pub fn contains(&self, commit: Oid) -> bool {
+        self.positions.contains_key(&commit)
+    }
+
+    /// Number of indexed commits.
+    pub fn len(&self) -> usize {
+        self.commits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commits.is_empty()
+    }
+
+    /// The indexed symbols, by path, kind and name.
+    pub fn symbols(&self) -> impl Iterator<Item = &SymbolKey> {
+        self.symbols.keys()
+    }
+
+    /// The commits that changed a symbol, newest first. They may include
+    /// commits on other branches than the one checked out.
+    pub fn commits(&self, path: &str, kind: &str, name: &str) -> Vec<Oid> {
+        let key = SymbolKey {
+            path: path.to_string(),
+            kind: kind.to_string(),
+            name: name.to_string(),
+        };
+        self.symbols
+            .get(&key)
+            .map(|positions| {
+                positions
+                    .iter()
+                    .rev()
+                    .map(|&p| self.commits[p as usize])
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Indexes the commits of `repo` reachable from `HEAD` and the local
+    /// branches and writes the index, resuming an interrupted build first.
+    pub fn build(
+        repo: &Repository,
+        options: &BuildOptions,
+        limits: &ParseLimits,
+    ) -> Result<BuildStats, Error> {
+        let dir = checkpoint_dir(repo);
+        let mut index = match options.full {
+            true => None,
+            false => Self::load(repo)?,
+        }
+        .unwrap_or_default();
+
+        let mut new_commits = 0;
+        // A plan is only valid on top of the index it was made for; a damaged
+        // one is dropped like a stale one.
+        let resumable = std::fs::read(dir.join("plan"))
+            .ok()
+            .and_then(|bytes| Plan::decode(&bytes).ok())
+            .filter(|plan| !options.full && plan.base as usize == index.len());
+        let resumed = resumable.is_some();
+        if let Some(plan) = resumable {
+            index.apply(repo, &plan, options, limits)?;
+            new_commits += plan.commits.len();
+        }
+        remove_checkpoints(&dir)?;
+
+        let plan = index.plan(repo)?;
+        index.apply(repo, &plan, options, limits)?;
+        new_commits += plan.commits.len();
+        Ok(BuildStats {
+            new_commits,
+            resumed,
+            commits: index.commits.len(),
+            symbols: index.symbols.len(),
+        })
+    }
+
+    /// The commits reachable from `HEAD` and the local branches that are not
+    /// indexed yet, parents first.
+    fn plan(&self, repo: &Repository) -> Result<Plan, Error> {
+        let mut walk = repo.revwalk()?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+        let mut tips = BTreeSet::new();
+        if let Ok(head) = repo.head().and_then(|h| h.peel_to_commit()) {
+            tips.insert(head.id());
+        }
+        for branch in repo.branches(Some(BranchType::Local))? {
+            if let Ok(tip) = branch?.0.get().peel_to_commit() {
+                tips.insert(tip.id());
+            }
+        }
+        for &tip in &tips {
+            walk.push(tip)?;
+        }
+        for &position in &self.tips {
+            walk.hide(self.commits[position as usize])?;
+        }
+        let mut commits = Vec::new();
+        for id in walk {
+            let id = id?;
+            if !self.contains(id) {
+                commits.push(id);
+            }
+        }
+        Ok(Plan {
+            base: self.commits.len() as u32,
+            tips: tips.into_iter().collect(),
+            commits,
+        })
+    }
+
+    /// Indexes the commits of `plan` batch by batch, reusing the batches an
+    /// interrupted build finished, and writes the index.
+    fn apply(
+        &mut self,
+        repo: &Repository,
+        plan: &Plan,
+        options: &BuildOptions,
+        limits: &ParseLimits,
+    ) -> Result<(), Error> {
+        let dir = checkpoint_dir(repo);
+        if !plan.commits.is_empty() {
+            atomic_io::write(dir.join("plan"), plan.encode())?;
+        }
+        let size = options.batch.max(1);
+        for (n, commits) in plan.commits.chunks(size).enumerate() {
+            let first = plan.base + (n * size) as u32;
+            let positions = first..first + commits.len() as u32;
+            let path = dir.join(format!("batch-{:06}", n));
+            let finished = std::fs::read(&path)
+                .ok()
+                .and_then(|bytes| decode_batch(&bytes, &positions).ok());
+            let symbols = match finished {
+                Some(symbols) => symbols,
+                None => {
+                    let symbols = index_batch(repo, commits, first, options.jobs, limits)?;
+                    atomic_io::write(&path, encode_batch(&symbols))?;
+                    symbols
+                }
+            };
+            for (&id, position) in commits.iter().zip(positions) {
+                self.commits.push(id);
+                self.positions.insert(id, position);
+            }
+            for (key, positions) in symbols {
+                self.symbols.entry(key).or_default().extend(positions);
+            }
+        }
+        self.tips = plan.tips.iter().map(|tip| self.positions[tip]).collect();
+
+        atomic_io::write(index_path(repo), self.encode())?;
+        remove_checkpoints(&dir)
+    }
+
+    /// The index in its file format.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = header(MAGIC);
+        out.extend((self.commits.len() as u32).to_be_bytes());
+        out.extend((self.tips.len() as u32).to_be_bytes());
+        for id in &self.commits {
+            out.extend(id.as_bytes());
+        }
+        for tip in &self.tips {
+            out.extend(tip.to_be_bytes());
+        }
+        write_symbols(&mut out, &self.symbols);
+        seal(out)
+    }
+
+    /// Reads an index in its file format, checking it throughout.
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let mut input = Input::open(bytes, MAGIC)?;
+        let (commits, tips) = (input.u32()?, input.u32()?);
+
+        let mut index = SymbolHistoryIndex::default();
+        for position in 0..commits {
+            let id = Oid::from_bytes(input.take(20)?)?;
+            index.commits.push(id);
+            index.positions.insert(id, position);
+        }
+        for _ in 0..tips {
+            index.tips.push(input.position(&(0..commits))?);
+        }
+        index.symbols = input.symbols(&(0..commits))?;
+        input.finish()?;
+        Ok(index)
+    }
+}
+
+/// The commits a build indexes, in order, after the `base` commits already
+/// in the index, and the tips it starts from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Plan {
+    base: u32,
+    tips: Vec<Oid>,
+    commits: Vec<Oid>,
+}
+
+impl Plan {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = header(PLAN_MAGIC);
+        out.extend(self.base.to_be_bytes());
+        out.extend((self.tips.len() as u32).to_be_bytes());
+        out.extend((self.commits.len() as u32).to_be_bytes());
+        for id in self.tips.iter().chain(&self.commits) {
+            out.extend(id.as_bytes());
+        }
+        seal(out)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let mut input = Input::open(bytes, PLAN_MAGIC)?;
+        let (base, tips, commits) = (input.u32()?, input.u32()?, input.u32()?);
+        let mut ids = (0..tips + commits)
+            .map(|_| Ok(Oid::from_bytes(input.take(20)?)?))
+            .collect::<Result<Vec<Oid>, Error>>()?;
+        input.finish()?;
+        let commits = ids.split_off(tips as usize);
+        Ok(Plan {
+            base,
+            tips: ids,
+            commits,
+        })
+    }
+}
+
+/// A finished batch: the symbols its commits changed.
+fn encode_batch(symbols: &Symbols) -> Vec<u8> {
+    let mut out = header(BATCH_MAGIC);
+    write_symbols(&mut out, symbols);
+    seal(out)
+}
+
+fn decode_batch(bytes: &[u8], positions: &std::ops::Range<u32>) -> Result<Symbols, Error> {
+    let mut input = Input::open(bytes, BATCH_MAGIC)?;
+    let symbols = input.symbols(positions)?;
+    input.finish()?;
+    Ok(symbols)
+}
+
+fn remove_checkpoints(dir: &Path) -> Result<(), Error> {
+    match std::fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// The definitions of a blob with their token hashes.
+type Fingerprints = Arc<Vec<(Symbol, u64)>>;
+
+/// Indexes `commits`, the first at position `first`, on `jobs` threads.
+fn index_batch(
+    repo: &Repository,
+    commits: &[Oid],
+    first: u32,
+    jobs: usize,
+    limits: &ParseLimits,
+) -> Result<Symbols, Error> {
+    let git_dir = repo.path().to_path_buf();
+    let next = AtomicUsize::new(0);
+    // Blobs parsed by any worker, by id and path (the path picks the
+    // language); `None` for blobs without a language or that do not parse.
+    let parsed: Mutex<HashMap<(Oid, String), Option<Fingerprints>>> = Mutex::default();
+    let changed = Mutex::new(Vec::new());
+    let worker = || -> Result<(), Error> {
+        let repo = Repository::open(&git_dir)?;
+        let mut packs = QueryPacks::for_repo(&repo);
+        let mut prints = |blob: Oid, path: &str| {
+            let key = (blob, path.to_string());
+            if let Some(found) = parsed.lock().expect("index lock").get(&key) {
+                return found.clone();
+            }
+            let found = ownership::blob_fingerprints(&repo, blob, path, limits, &mut packs)
+                .ok()
+                .flatten()
+                .map(Arc::new);
+            parsed
+                .lock()
+                .expect("index lock")
+                .insert(key, found.clone());
+            found
+        };
+        loop {
+            let i = next.fetch_add(1, Ordering::Relaxed);
+            let Some(&id) = commits.get(i) else {
+                return Ok(());
+            };
+            let keys = changed_symbols(&repo, &repo.find_commit(id)?, &mut prints)?;
+            changed
+                .lock()
+                .expect("index lock")
+                .push((first + i as u32, keys));
+        }
+    };
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.max(1)).map(|_| scope.spawn(worker)).collect();
+        workers
+            .into_iter()
+            .map(|w| {
+                w.join()
+                    .unwrap_or_else(|_| Err(Error::Generation("index worker panicked".to_string())))
+            })
+            .collect::<Result<Vec<()>, Error>>()
+    })?;
+
+    let mut changed = changed.into_inner().expect("index lock");
+    changed.sort_by_key(|&(position, _)| position);
+    let mut symbols = Symbols::new();
+    for (position, keys) in changed {
+        for key in keys {
+            symbols.entry(key).or_default().push(position);
+        }
+    }
+    Ok(symbols)
+}
+
+/// The symbols `commit` changed, relative to its parent, with `prints`
+/// giving the fingerprints of a blob at a path.
+fn changed_symbols(
+    repo: &Repository,
+    commit: &Commit,
+    prints: &mut impl FnMut(Oid, &str) -> Option<Fingerprints>,
+) -> Result<BTreeSet<SymbolKey>, Error> {
+    let mut changed = BTreeSet::new();
+    if commit.parent_count() > 1 {
+        return Ok(changed);
+    }
+    let parent = match commit.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None,
+    };
+    let diff = repo.diff_tree_to_tree(parent.as_ref(), Some(&commit.tree()?), None)?;
+    for delta in diff.deltas() {
+        let (old, new) = (delta.old_file().id(), delta.new_file().id());
+        let Some(path) = delta.new_file().path().and_then(|p| p.to_str()) else {
+            continue;
+        };
+        if new.is_zero() {
+            continue;
+        }
+        let Some(after) = prints(new, path) else {
+            continue;
+        };
+        let before = match old.is_zero() {
+            true => None,
+            false => prints(old, path),
+        };
+        let before = before.as_deref().map_or(&[][..], Vec::as_slice);
+        for (symbol, print) in after.iter() {
+            let key = |(s, _): &&(Symbol, u64)| s.kind == symbol.kind && s.name == symbol.name;
+            // Like `ownership::history`, the first of same-named definitions
+            // stands for all of them.
+            let new_print = after.iter().find(key).map(|(_, print)| print);
+            if new_print != Some(print) {
+                continue;
+            }
+            if before.iter().find(key).map(|(_, print)| print) != new_print {
+                changed.insert(SymbolKey {
+                    path: path.to_string(),
+                    kind: symbol.kind.clone(),
+                    name: symbol.name.clone(),
+                });
+            }
+        }
+    }
+    Ok(changed)
+}
+
+/// The start of a file: its magic and the format version.
+fn header(magic: &[u8; 4]) -> Vec<u8> {
+    let mut out = magic.to_vec();
+    out.extend(FORMAT_VERSION.to_be_bytes());
+    out
+}
+
+fn write_symbols(out: &mut Vec<u8>, symbols: &Symbols) {
+    out.extend((symbols.len() as u32).to_be_bytes());
+    for (key, positions) in symbols {
+        for text in [&key.path, &key.kind, &key.name] {
+            out.extend((text.len() as u16).to_be_bytes());
+            out.extend(text.as_bytes());
+        }
+        out.extend((positions.len() as u32).to_be_bytes());
+        for position in positions {
+            out.extend(position.to_be_bytes());
+        }
+    }
+}
+
+/// `out` followed by its checksum.
+fn seal(mut out: Vec<u8>) -> Vec<u8> {
+    let checksum = Oid::hash_object(ObjectType::Blob, &out).expect("hashing a buffer");
+    out.extend(checksum.as_bytes());
+    out
+}
+
+fn corrupt(what: &str) -> Error {
+    Error::Parsing(format!("symbol history index: {}", what))
+}
+
+/// A cursor over the body of a file.
+struct Input<'b> {
+    bytes: &'b [u8],
+}
+
+impl<'b> Input<'b> {
+    /// The body of `bytes`, after checking its magic, checksum and version.
+    fn open(bytes: &'b [u8], magic: &[u8; 4]) -> Result<Self, Error> {
+        if bytes.len() < magic.len() + 20 || &bytes[..magic.len()] != magic {
+            return Err(corrupt("not an index file"));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 20);
+        if Oid::hash_object(ObjectType::Blob, body)?.as_bytes() != checksum {
+            return Err(corrupt("checksum mismatch"));
+        }
+        let mut input = Input {
+            bytes: &body[magic.len()..],
+        };
+        let version = input.u32()?;
+        if version != FORMAT_VERSION {
+            return Err(corrupt(&format!("unsupported version {}", version)));
+        }
+        Ok(input)
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'b [u8], Error> {
+        if self.bytes.len() < n {
+            return Err(corrupt("truncated"));
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn text(&mut self) -> Result<String, Error> {
+        let len = u16::from_be_bytes(self.take(2)?.try_into().unwrap());
+        String::from_utf8(self.take(len as usize)?.to_vec()).map_err(|_| corrupt("invalid UTF-8"))
+    }
+
+    /// A commit position, which must be in `valid`.
+    fn position(&mut self, valid: &std::ops::Range<u32>) -> Result<u32, Error> {
+        match self.u32()? {
+            p if valid.contains(&p) => Ok(p),
+            _ => Err(corrupt("commit position out of range")),
+        }
+    }
+
+    fn symbols(&mut self, valid: &std::ops::Range<u32>) -> Result<Symbols, Error> {
+        let mut symbols = Symbols::new();
+        for _ in 0..self.u32()? {
+            let key = SymbolKey {
+                path: self.text()?,
+                kind: self.text()?,
+                name: self.text()?,
+            };
+            let count = self.u32()?;
+            let positions = (0..count)
+                .map(|_| self.position(valid))
+                .collect::<Result<_, _>>()?;
+            symbols.insert(key, positions);
+        }
+        Ok(symbols)
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        match self.bytes.is_empty() {
+            true => Ok(()),
+            false => Err(corrupt("trailing data")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Signature;
+
+    /// Commits `text` as `a.rs` on top of `HEAD`.
+    fn commit(repo: &Repository, text: &str) -> Oid {
+        std::fs::write(repo.workdir().unwrap().join("a.rs"), text).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("a.rs")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = Signature::now("ada", "ada@example.com").unwrap();
+        let parent = repo.head().ok().map(|h| h.peel_to_commit().unwrap());
+        repo.commit(
+            Some("HEAD"),
+            &sig,
+            &sig,
+            text,
+            &tree,
+            &parent.iter().collect::<Vec<_>>(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn builds_incrementally_and_matches_the_history_walk() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let limits = ParseLimits::default();
+        let options = BuildOptions {
+            jobs: 2,
+            batch: 1,
+            ..BuildOptions::default()
+        };
+        let first = commit(&repo, "fn a() {}\nfn b() {}\n");
+        let second = commit(&repo, "fn a() { 1; }\nfn b() {}\n");
+        let stats = SymbolHistoryIndex::build(&repo, &options, &limits).unwrap();
+        assert_eq!((stats.new_commits, stats.symbols), (2, 2));
+
+        // Reformatting only, then a change to `b`.
+        commit(&repo, "fn a() {\n    1;\n}\nfn b() {}\n");
+        let third = commit(&repo, "fn a() {\n    1;\n}\nfn b() { 2; }\n");
+        let stats = SymbolHistoryIndex::build(&repo, &options, &limits).unwrap();
+        assert_eq!((stats.new_commits, stats.commits), (2, 4));
+        assert!(!checkpoint_dir(&repo).exists());
+
+        let index = SymbolHistoryIndex::load(&repo).unwrap().unwrap();
+        assert_eq!(index.commits("a.rs", "function_item", "a"), [second, first]);
+        assert_eq!(index.commits("a.rs", "function_item", "b"), [third, first]);
+        assert_eq!(SymbolHistoryIndex::decode(&index.encode()).unwrap(), index);
+
+        let mut bytes = index.encode();
+        bytes[8] ^= 1;
+        assert!(SymbolHistoryIndex::decode(&bytes).is_err());
+
+        // History gives the same answer from the index as from the walk.
+        let mut packs = QueryPacks::default();
+        let mut commits = || -> Vec<Vec<String>> {
+            ownership::history(&repo, "a.rs", None, &limits, &mut packs)
+                .unwrap()
+                .into_iter()
+                .map(|(_, touches)| touches.into_iter().map(|t| t.commit).collect())
+                .collect()
+        };
+        let indexed = commits();
+        assert_eq!(indexed[1], [third.to_string(), first.to_string()]);
+        std::fs::remove_file(index_path(&repo)).unwrap();
+        assert_eq!(commits(), indexed);
+    }
+
+    #[test]
+    fn resumes_an_interrupted_build_from_its_checkpoints() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let commits = [
+            commit(&repo, "fn a() {}\n"),
+            commit(&repo, "fn a() { 1; }\n"),
+            commit(&repo, "fn a() { 2; }\n"),
+        ];
+        let plan = SymbolHistoryIndex::default().plan(&repo).unwrap();
+        assert_eq!(plan.commits, commits);
+
+        // As if a build of one commit per batch stopped after the first
+        // batch, which (to tell it from a recomputed one) names a symbol of
+        // its own.
+        let checkpoints = checkpoint_dir(&repo);
+        atomic_io::write(checkpoints.join("plan"), plan.encode()).unwrap();
+        let marker = SymbolKey {
+            path: "a.rs".to_string(),
+            kind: "checkpoint".to_string(),
+            name: "marker".to_string(),
+        };
+        let batch = encode_batch(&[(marker, vec![0])].into());
+        atomic_io::write(checkpoints.join("batch-000000"), batch).unwrap();
+
+        let options = BuildOptions {
+            batch: 1,
+            ..BuildOptions::default()
+        };
+        let stats = SymbolHistoryIndex::build(&repo, &options, &ParseLimits::default()).unwrap();
+        assert!(stats.resumed);
+        assert_eq!(stats.new_commits, 3);
+        assert!(!checkpoints.exists());
+
+        let index = SymbolHistoryIndex::load(&repo).unwrap().unwrap();
+        assert_eq!(index.commits("a.rs", "checkpoint", "marker"), [commits[0]]);
+        assert_eq!(
+            index.commits("a.rs", "function_item", "a"),
+            [commits[2], commits[1]]
+        );
+    }
+}
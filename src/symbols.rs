@@ -0,0 +1,565 @@
+//! Symbol Index
+//!
+//! The definitions in a file, and how often each name is used across a
+//! revision, for reports that look at code by symbol rather than by line.
+//!
+//! ## Definitions
+//!
+//! If the language's query pack has a `symbols.scm` (see
+//! [`queries`](crate::queries)), its `@name` captures inside
+//! `@definition.<kind>` captures are the definitions, `<kind>` naming them
+//! (the convention of Tree-sitter's `tags.scm`). Otherwise every node with a
+//! `name` field whose kind names a definition (`function_item`,
+//! `class_definition`, `method_declaration`, ...) counts, with its node kind
+//! as the kind.
+//!
+//! ## References
+//!
+//! A reference is any identifier-like token (a named leaf outside comments
+//! and strings) with the symbol's name, other than the definition's own
+//! name. This is textual: it does not resolve scopes or imports, so two
+//! symbols sharing a name share their references. A symbol without any is
+//! certainly unused within the repository, though entry points and API used
+//! from elsewhere show up too.
+//!
+//! ## History
+//!
+//! [`removed_in`] walks a range oldest first and compares each commit's
+//! definitions with its parent's, per commit rather than per file so that
+//! moving a function between files is neither. Symbols a commit in the range
+//! added and a later one removed are short-lived code worth a look in a
+//! cleanup audit.
+//!
+//! ## Signatures
+//!
+//! A definition's [`signature`] is its text up to its `body` field (all of
+//! it if it has none, as for a `struct`), with whitespace collapsed: name,
+//! parameters, return type and modifiers such as visibility.
+//! [`signature_changes`] lists the commits that change the signatures of the
+//! definitions with a given name, compared per commit like [`removed_in`].
+
+use crate::config;
+use crate::git_plumbing::filters;
+use crate::languages::LanguageProvider;
+use crate::parsing::{self, ParseLimits};
+use crate::queries::{self, QueryPack, QueryPacks};
+use crate::Error;
+use git2::{Commit, FileMode, ObjectType, Oid, Repository, Sort, TreeWalkMode, TreeWalkResult};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use tree_sitter::{Node as TsNode, QueryCursor, StreamingIterator, Tree};
+
+/// Node kind suffixes (and whole kinds) that define a name.
+const DEFINITION_KINDS: &[&str] = &[
+    "_item",
+    "_definition",
+    "_declaration",
+    "class",
+    "module",
+    "method",
+    "singleton_method",
+];
+/// Definition kinds whose names are not symbols of their own.
+const NOT_SYMBOLS: &[&str] = &[
+    "variable_declaration",
+    "lexical_declaration",
+    "let_declaration",
+];
+
+/// A definition in a file. Output uses [`SymbolRecord`](crate::model::SymbolRecord).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Symbol {
+    pub kind: String,
+    pub name: String,
+    pub path: String,
+    /// 1-based line of the name.
+    pub line: usize,
+}
+
+/// A symbol added by one commit of a range and removed by a later one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ShortLived {
+    pub kind: String,
+    pub name: String,
+    pub added: String,
+    pub removed: String,
+}
+
+/// A definition's signature, where it is.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Signature {
+    pub kind: String,
+    pub path: String,
+    pub line: usize,
+    pub text: String,
+}
+
+/// A commit that changes the signatures of the definitions with some name.
+#[derive(Debug, Clone, Serialize)]
+pub struct SignatureChange {
+    pub commit: String,
+    /// First line of the commit message.
+    pub summary: String,
+    /// The signatures in the files it changes, before and after.
+    pub before: Vec<Signature>,
+    pub after: Vec<Signature>,
+}
+
+/// The definitions in a parsed file.
+pub fn definitions(
+    tree: &Tree,
+    source: &[u8],
+    path: &str,
+    provider: &LanguageProvider,
+    pack: &QueryPack,
+) -> Vec<Symbol> {
+    let nodes = definition_nodes(tree, source, path, provider, pack);
+    nodes.into_iter().map(|(symbol, _)| symbol).collect()
+}
+
+/// The definitions in a parsed file, each with the node that defines it.
+pub fn definition_nodes<'t>(
+    tree: &'t Tree,
+    source: &[u8],
+    path: &str,
+    provider: &LanguageProvider,
+    pack: &QueryPack,
+) -> Vec<(Symbol, TsNode<'t>)> {
+    let symbol = |kind: &str, name: TsNode<'_>| Symbol {
+        kind: kind.to_string(),
+        name: String::from_utf8_lossy(&source[name.byte_range()]).into_owned(),
+        path: path.to_string(),
+        line: name.start_position().row + 1,
+    };
+    let mut out = Vec::new();
+    if let Some(query) = pack.get(queries::SYMBOLS) {
+        let names = query.capture_names();
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(query, tree.root_node(), source);
+        while let Some(m) = matches.next() {
+            let capture = |wanted: &dyn Fn(&str) -> bool| {
+                m.captures.iter().find(|c| wanted(names[c.index as usize]))
+            };
+            let definition = capture(&|n| n.starts_with("definition."));
+            if let (Some(definition), Some(name)) = (definition, capture(&|n| n == "name")) {
+                let kind = &names[definition.index as usize]["definition.".len()..];
+                out.push((symbol(kind, name.node), definition.node));
+            }
+        }
+    } else {
+        let mut cursor = tree.walk();
+        visit(&mut cursor, &mut |node| {
+            let kind = node.kind();
+            let defines = DEFINITION_KINDS
+                .iter()
+                .any(|k| kind.ends_with(k) || kind == k.trim_start_matches('_'));
+            if !defines || NOT_SYMBOLS.contains(&kind) || provider.syntax.comments.contains(&kind) {
+                return;
+            }
+            if let Some(name) = node.child_by_field_name("name") {
+                out.push((symbol(kind, name), node));
+            }
+        });
+    }
+    out.sort_by(|a, b| a.0.cmp(&b.0));
+    out.dedup_by(|a, b| a.0 == b.0);
+    out
+}
+
+/// Adds each identifier-like token of a parsed file to `counts`.
+pub fn count_identifiers(
+    tree: &Tree,
+    source: &[u8],
+    provider: &LanguageProvider,
+    counts: &mut HashMap<String, usize>,
+) {
+    let rules = &provider.syntax;
+    let mut cursor = tree.walk();
+    visit(&mut cursor, &mut |node| {
+        if node.child_count() == 0
+            && node.is_named()
+            && !rules.comments.contains(&node.kind())
+            && !rules.atomic.contains(&node.kind())
+            && node
+                .parent()
+                .is_none_or(|p| !rules.atomic.contains(&p.kind()))
+        {
+            let text = String::from_utf8_lossy(&source[node.byte_range()]).into_owned();
+            *counts.entry(text).or_default() += 1;
+        }
+    });
+}
+
+/// Calls `f` on the cursor's node and every node below it, in pre-order.
+pub(crate) fn visit<'t>(cursor: &mut tree_sitter::TreeCursor<'t>, f: &mut dyn FnMut(TsNode<'t>)) {
+    loop {
+        f(cursor.node());
+        if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return;
+            }
+        }
+    }
+}
+
+/// The definitions of `rev` that nothing in `rev` refers to, by path.
+pub fn unreferenced(
+    repo: &Repository,
+    rev: &str,
+    limits: &ParseLimits,
+    packs: &mut QueryPacks,
+) -> Result<Vec<Symbol>, Error> {
+    let mut symbols = Vec::new();
+    let mut counts = HashMap::new();
+    for (path, id) in files(repo, rev)? {
+        let Some((provider, source, tree)) = parse_blob(repo, id, &path, limits)? else {
+            continue;
+        };
+        symbols.extend(definitions(
+            &tree,
+            &source,
+            &path,
+            provider,
+            packs.get(provider)?,
+        ));
+        count_identifiers(&tree, &source, provider, &mut counts);
+    }
+    let mut defined: HashMap<&str, usize> = HashMap::new();
+    for symbol in &symbols {
+        *defined.entry(symbol.name.as_str()).or_default() += 1;
+    }
+    let unused = symbols
+        .iter()
+        .filter(|s| counts.get(&s.name).copied().unwrap_or_default() <= defined[s.name.as_str()])
+        .cloned()
+        .collect();
+    Ok(unused)
+}
+
+/// Symbols added by one commit in `range` (a revision or `A..B`; all of
+/// `HEAD` when `None`) and removed by a later one. Merge commits are skipped.
+pub fn removed_in(
+    repo: &Repository,
+    range: Option<&str>,
+    limits: &ParseLimits,
+    packs: &mut QueryPacks,
+) -> Result<Vec<ShortLived>, Error> {
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    match range {
+        Some(r) if r.contains("..") => walk.push_range(r)?,
+        Some(r) => walk.push(repo.revparse_single(r)?.peel_to_commit()?.id())?,
+        None => walk.push_head()?,
+    }
+    // (kind, name) -> the commit that added it, while it lives.
+    let mut added: BTreeMap<(String, String), Oid> = BTreeMap::new();
+    let mut found = Vec::new();
+    for oid in walk {
+        let commit = repo.find_commit(oid?)?;
+        if commit.parent_count() > 1 {
+            continue;
+        }
+        let (gone, new) = changed_definitions(repo, &commit, limits, packs)?;
+        for key in gone {
+            if let Some(added_by) = added.remove(&key) {
+                found.push(ShortLived {
+                    kind: key.0,
+                    name: key.1,
+                    added: added_by.to_string(),
+                    removed: commit.id().to_string(),
+                });
+            }
+        }
+        for key in new {
+            added.insert(key, commit.id());
+        }
+    }
+    Ok(found)
+}
+
+/// `(kind, name)` pairs.
+type Keys = Vec<(String, String)>;
+
+/// The `(kind, name)`s `commit` removes and adds relative to its first
+/// parent, summed over its files.
+fn changed_definitions(
+    repo: &Repository,
+    commit: &Commit<'_>,
+    limits: &ParseLimits,
+    packs: &mut QueryPacks,
+) -> Result<(Keys, Keys), Error> {
+    let parent = match commit.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None,
+    };
+    let diff = repo.diff_tree_to_tree(parent.as_ref(), Some(&commit.tree()?), None)?;
+    let mut net: BTreeMap<(String, String), isize> = BTreeMap::new();
+    for delta in diff.deltas() {
+        let (old, new) = (delta.old_file(), delta.new_file());
+        if old.mode() == FileMode::Commit || new.mode() == FileMode::Commit {
+            continue;
+        }
+        for (side, sign) in [(old, -1), (new, 1)] {
+            let Some(path) = side.path().and_then(|p| p.to_str()) else {
+                continue;
+            };
+            if side.id().is_zero() {
+                continue;
+            }
+            let Some((provider, source, tree)) = parse_blob(repo, side.id(), path, limits)? else {
+                continue;
+            };
+            for symbol in definitions(&tree, &source, path, provider, packs.get(provider)?) {
+                *net.entry((symbol.kind, symbol.name)).or_default() += sign;
+            }
+        }
+    }
+    let gone = net
+        .iter()
+        .filter(|(_, &n)| n < 0)
+        .map(|(k, _)| k.clone())
+        .collect();
+    let new = net
+        .iter()
+        .filter(|(_, &n)| n > 0)
+        .map(|(k, _)| k.clone())
+        .collect();
+    Ok((gone, new))
+}
+
+/// The signature of the definition `node` (see the module docs).
+pub fn signature(node: TsNode<'_>, source: &[u8]) -> String {
+    let end = node
+        .child_by_field_name("body")
+        .map_or(node.end_byte(), |body| body.start_byte());
+    let text = String::from_utf8_lossy(&source[node.start_byte()..end]);
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The commits in `range` (a revision or `A..B`; all of `HEAD` when `None`),
+/// newest first, that change the signature of a definition named `name`, or
+/// add or remove one. Merge commits are skipped.
+pub fn signature_changes(
+    repo: &Repository,
+    name: &str,
+    range: Option<&str>,
+    limits: &ParseLimits,
+    packs: &mut QueryPacks,
+) -> Result<Vec<SignatureChange>, Error> {
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+    match range {
+        Some(r) if r.contains("..") => walk.push_range(r)?,
+        Some(r) => walk.push(repo.revparse_single(r)?.peel_to_commit()?.id())?,
+        None => walk.push_head()?,
+    }
+    let mut changes = Vec::new();
+    for oid in walk {
+        let commit = repo.find_commit(oid?)?;
+        if commit.parent_count() > 1 {
+            continue;
+        }
+        let parent = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            Err(_) => None,
+        };
+        let diff = repo.diff_tree_to_tree(parent.as_ref(), Some(&commit.tree()?), None)?;
+        let (mut before, mut after) = (Vec::new(), Vec::new());
+        for delta in diff.deltas() {
+            let (old, new) = (delta.old_file(), delta.new_file());
+            if old.mode() == FileMode::Commit || new.mode() == FileMode::Commit {
+                continue;
+            }
+            for (side, signatures) in [(old, &mut before), (new, &mut after)] {
+                let Some(path) = side.path().and_then(|p| p.to_str()) else {
+                    continue;
+                };
+                if side.id().is_zero() {
+                    continue;
+                }
+                let Some((provider, source, tree)) = parse_blob(repo, side.id(), path, limits)?
+                else {
+                    continue;
+                };
+                let pack = packs.get(provider)?;
+                for (symbol, node) in definition_nodes(&tree, &source, path, provider, pack) {
+                    if symbol.name == name {
+                        signatures.push(Signature {
+                            kind: symbol.kind,
+                            path: symbol.path,
+                            line: symbol.line,
+                            text: signature(node, &source),
+                        });
+                    }
+                }
+            }
+        }
+        // Moving a definition to another file or line is no change.
+        if unplaced(&before) != unplaced(&after) {
+            changes.push(SignatureChange {
+                commit: commit.id().to_string(),
+                summary: commit.summary().unwrap_or_default().to_string(),
+                before,
+                after,
+            });
+        }
+    }
+    Ok(changes)
+}
+
+/// The kinds and texts of `signatures`, sorted.
+fn unplaced(signatures: &[Signature]) -> Vec<(&str, &str)> {
+    let mut keys: Vec<_> = signatures
+        .iter()
+        .map(|s| (s.kind.as_str(), s.text.as_str()))
+        .collect();
+    keys.sort();
+    keys
+}
+
+/// The paths and blob ids of the files (not symlinks or submodules) of `rev`.
+pub(crate) fn files(repo: &Repository, rev: &str) -> Result<Vec<(String, Oid)>, Error> {
+    let tree = repo.revparse_single(rev)?.peel_to_tree()?;
+    let mut files = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.kind() == Some(ObjectType::Blob) && entry.filemode() != i32::from(FileMode::Link) {
+            files.push((
+                format!("{}{}", dir, entry.name().unwrap_or_default()),
+                entry.id(),
+            ));
+        }
+        TreeWalkResult::Ok
+    })?;
+    Ok(files)
+}
+
+/// A blob's language, source and parse tree.
+pub(crate) type Parsed = (&'static LanguageProvider, Vec<u8>, Tree);
+
+/// The language, source and parse tree of a blob, if it has a language.
+pub(crate) fn parse_blob(
+    repo: &Repository,
+    id: Oid,
+    path: &str,
+    limits: &ParseLimits,
+) -> Result<Option<Parsed>, Error> {
+    let source = filters::perform_smudge(repo.find_blob(id)?.content(), path)?;
+    let Some(provider) = config::language_for_path(Some(repo), path, &source)? else {
+        return Ok(None);
+    };
+    let tree = parsing::parse(&source, provider, limits)?;
+    Ok(Some((provider, source, tree)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::languages;
+
+    #[test]
+    fn finds_definitions_and_their_uses() {
+        let rust = languages::by_name("rust").unwrap();
+        let source = b"struct Point;\nfn used() -> Point { Point }\nfn unused() { used(); let s = \"unused\"; }\n";
+        let tree = parsing::parse(source, rust, &ParseLimits::default()).unwrap();
+        let symbols = definitions(&tree, source, "a.rs", rust, &QueryPack::default());
+        let names: Vec<_> = symbols
+            .iter()
+            .map(|s| (s.kind.as_str(), s.name.as_str(), s.line))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("function_item", "unused", 3),
+                ("function_item", "used", 2),
+                ("struct_item", "Point", 1)
+            ]
+        );
+        let mut counts = HashMap::new();
+        count_identifiers(&tree, source, rust, &mut counts);
+        assert_eq!(
+            (counts["Point"], counts["used"], counts["unused"]),
+            (3, 2, 1)
+        );
+    }
+
+    #[test]
+    fn lists_commits_that_change_a_signature() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let mut time = 0;
+        let mut commit = |files: &[(&str, &str)], message: &str| {
+            time += 60;
+            let signature = git2::Signature::new("t", "t@example.com", &git2::Time::new(time, 0));
+            let signature = signature.unwrap();
+            let mut builder = repo.treebuilder(None).unwrap();
+            for (path, source) in files {
+                let blob = repo.blob(source.as_bytes()).unwrap();
+                builder.insert(path, blob, 0o100644).unwrap();
+            }
+            let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+            let parent = repo.head().ok().map(|h| h.peel_to_commit().unwrap());
+            let parents: Vec<&Commit<'_>> = parent.iter().collect();
+            repo.commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &parents,
+            )
+            .unwrap()
+            .to_string()
+        };
+        let added = commit(&[("a.rs", "fn parse(a: u8) -> u8 {\n    a\n}\n")], "Add");
+        commit(
+            &[("a.rs", "fn parse(a: u8) -> u8 {\n    a + 1\n}\n")],
+            "Body",
+        );
+        let widened = commit(
+            &[("a.rs", "pub fn parse(a: u16) -> u8 {\n    1\n}\n")],
+            "Widen",
+        );
+        commit(
+            &[("b.rs", "pub fn parse(a: u16) -> u8 {\n    1\n}\n")],
+            "Move",
+        );
+
+        let changes = signature_changes(
+            &repo,
+            "parse",
+            None,
+            &ParseLimits::default(),
+            &mut QueryPacks::default(),
+        )
+        .unwrap();
+        let timeline: Vec<_> = changes
+            .iter()
+            .map(|c| {
+                let texts = |s: &[Signature]| s.iter().map(|s| s.text.clone()).collect::<Vec<_>>();
+                (c.commit.as_str(), texts(&c.before), texts(&c.after))
+            })
+            .collect();
+        assert_eq!(
+            timeline,
+            [
+                (
+                    widened.as_str(),
+                    vec!["fn parse(a: u8) -> u8".to_string()],
+                    vec!["pub fn parse(a: u16) -> u8".to_string()]
+                ),
+                (
+                    added.as_str(),
+                    vec![],
+                    vec!["fn parse(a: u8) -> u8".to_string()]
+                ),
+            ]
+        );
+        assert_eq!(
+            (changes[0].after[0].path.as_str(), changes[0].after[0].line),
+            ("a.rs", 1)
+        );
+    }
+}
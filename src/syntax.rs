@@ -0,0 +1,415 @@
+//! Typed Syntax Trees
+//!
+//! Tree-sitter nodes are typed by grammar: a function is a `function_item` in
+//! Rust, a `function_definition` in Python and a `method_declaration` in
+//! Java. [`SyntaxTree`] wraps a parsed file so its nodes ([`SyntaxNode`])
+//! also carry a language-agnostic [`Role`], for analysis that should work the
+//! same in every language:
+//!
+//! ```no_run
+//! # use git_ast::{languages, parsing::ParseLimits, queries::QueryPack, syntax::{Role, SyntaxTree}};
+//! # fn main() -> Result<(), git_ast::Error> {
+//! let python = languages::by_name("python").unwrap();
+//! let source = b"def area(w, h):\n    return w * h\n";
+//! let tree = SyntaxTree::parse(source, python, &QueryPack::default(), &ParseLimits::default())?;
+//! for function in tree.nodes(Role::Function) {
+//!     let parameters = function.find(Role::Parameter).len();
+//!     println!("{}: {} parameters", function.name().unwrap_or_default(), parameters);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! ## Roles
+//!
+//! Roles are assigned by a Tree-sitter query whose capture names are role
+//! names (`@function`, `@parameter`, ...); captures starting with `_` are
+//! helpers for predicates. Each language provider ships one (its `roles`), and
+//! a repository can replace it with a `roles.scm` in its query pack (see
+//! [`queries`](crate::queries)), for instance to count a framework's
+//! `@route` decorators as functions. A node captured more than once gets the
+//! role of the first pattern in the file.
+//!
+//! Roles classify what a node is, not where it is: a method is a
+//! [`Role::Function`] like any other, and [`SyntaxNode::enclosing`] tells
+//! whether it is inside a [`Role::Type`].
+
+use crate::languages::LanguageProvider;
+use crate::parsing::{self, ParseLimits};
+use crate::queries::{self, QueryPack};
+use crate::symbols;
+use crate::Error;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use tree_sitter::{Node as TsNode, Query, QueryCursor, StreamingIterator, Tree};
+
+/// What a node is, independent of the language.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Functions, methods, constructors and lambdas.
+    Function,
+    /// Classes, structs, enums, interfaces, traits and modules.
+    Type,
+    /// One parameter of a function.
+    Parameter,
+    /// Imports, `use` declarations and `require` calls.
+    Import,
+    /// Calls and instantiations.
+    Call,
+    Comment,
+}
+
+impl Role {
+    pub const ALL: [Role; 6] = [
+        Role::Function,
+        Role::Type,
+        Role::Parameter,
+        Role::Import,
+        Role::Call,
+        Role::Comment,
+    ];
+
+    /// The role's capture name in a roles query.
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Function => "function",
+            Role::Type => "type",
+            Role::Parameter => "parameter",
+            Role::Import => "import",
+            Role::Call => "call",
+            Role::Comment => "comment",
+        }
+    }
+
+    /// The role with capture name `name`.
+    pub fn from_name(name: &str) -> Option<Role> {
+        Role::ALL.into_iter().find(|role| role.name() == name)
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A parsed file whose nodes have roles.
+#[derive(Debug)]
+pub struct SyntaxTree {
+    tree: Tree,
+    source: Vec<u8>,
+    provider: &'static LanguageProvider,
+    /// Role of each node with one, by node id.
+    roles: HashMap<usize, Role>,
+}
+
+impl SyntaxTree {
+    /// Parses `source` as `provider`'s language, assigning roles with
+    /// `pack`'s `roles.scm` if it has one and the language's built-in query
+    /// otherwise.
+    pub fn parse(
+        source: &[u8],
+        provider: &'static LanguageProvider,
+        pack: &QueryPack,
+        limits: &ParseLimits,
+    ) -> Result<SyntaxTree, Error> {
+        let tree = parsing::parse(source, provider, limits)?;
+        SyntaxTree::new(tree, source.to_vec(), provider, pack)
+    }
+
+    /// Wraps a tree already parsed from `source`.
+    pub fn new(
+        tree: Tree,
+        source: Vec<u8>,
+        provider: &'static LanguageProvider,
+        pack: &QueryPack,
+    ) -> Result<SyntaxTree, Error> {
+        let builtin;
+        let query = match pack.get(queries::ROLES) {
+            Some(query) => query,
+            None if provider.roles.is_empty() => {
+                let roles = HashMap::new();
+                return Ok(SyntaxTree {
+                    tree,
+                    source,
+                    provider,
+                    roles,
+                });
+            }
+            None => {
+                builtin = Query::new(&provider.ts_language(), provider.roles).map_err(|e| {
+                    Error::Parsing(format!("invalid {} roles query: {}", provider.name, e))
+                })?;
+                &builtin
+            }
+        };
+        let mut capture_roles = Vec::new();
+        for name in query.capture_names() {
+            let role = match Role::from_name(name) {
+                Some(role) => Some(role),
+                None if name.starts_with('_') => None,
+                None => {
+                    return Err(Error::Config(format!(
+                        "{} roles query: unknown role @{}",
+                        provider.name, name
+                    )))
+                }
+            };
+            capture_roles.push(role);
+        }
+
+        let mut first: HashMap<usize, (usize, Role)> = HashMap::new();
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(query, tree.root_node(), source.as_slice());
+        while let Some(m) = matches.next() {
+            for capture in m.captures {
+                let Some(role) = capture_roles[capture.index as usize] else {
+                    continue;
+                };
+                let entry = first
+                    .entry(capture.node.id())
+                    .or_insert((m.pattern_index, role));
+                if m.pattern_index < entry.0 {
+                    *entry = (m.pattern_index, role);
+                }
+            }
+        }
+        let roles = first
+            .into_iter()
+            .map(|(id, (_, role))| (id, role))
+            .collect();
+        Ok(SyntaxTree {
+            tree,
+            source,
+            provider,
+            roles,
+        })
+    }
+
+    /// The root node.
+    pub fn root(&self) -> SyntaxNode<'_> {
+        SyntaxNode {
+            node: self.tree.root_node(),
+            tree: self,
+        }
+    }
+
+    /// The nodes with `role`, in document order.
+    pub fn nodes(&self, role: Role) -> Vec<SyntaxNode<'_>> {
+        self.root().find(role)
+    }
+
+    pub fn source(&self) -> &[u8] {
+        &self.source
+    }
+
+    pub fn provider(&self) -> &'static LanguageProvider {
+        self.provider
+    }
+
+    /// The underlying Tree-sitter tree.
+    pub fn tree(&self) -> &Tree {
+        &self.tree
+    }
+}
+
+/// A node of a [`SyntaxTree`].
+///
+/// Navigation ([`children`](Self::children), [`descendants`](Self::descendants),
+/// ...) only visits named nodes; punctuation and keywords are reachable
+/// through [`ts_node`](Self::ts_node).
+#[derive(Clone, Copy)]
+pub struct SyntaxNode<'t> {
+    node: TsNode<'t>,
+    tree: &'t SyntaxTree,
+}
+
+impl<'t> SyntaxNode<'t> {
+    fn wrap(&self, node: TsNode<'t>) -> SyntaxNode<'t> {
+        SyntaxNode {
+            node,
+            tree: self.tree,
+        }
+    }
+
+    /// The grammar's node kind (`function_item`, `call`, ...).
+    pub fn kind(&self) -> &'static str {
+        self.node.kind()
+    }
+
+    pub fn role(&self) -> Option<Role> {
+        self.tree.roles.get(&self.node.id()).copied()
+    }
+
+    /// The node's source text.
+    pub fn text(&self) -> Cow<'t, str> {
+        String::from_utf8_lossy(&self.tree.source[self.node.byte_range()])
+    }
+
+    /// The text of the node's `name` field, which functions, types and most
+    /// other definitions have.
+    pub fn name(&self) -> Option<Cow<'t, str>> {
+        self.field("name").map(|name| name.text())
+    }
+
+    pub fn byte_range(&self) -> Range<usize> {
+        self.node.byte_range()
+    }
+
+    /// 1-based line the node starts on.
+    pub fn line(&self) -> usize {
+        self.node.start_position().row + 1
+    }
+
+    pub fn parent(&self) -> Option<SyntaxNode<'t>> {
+        self.node.parent().map(|node| self.wrap(node))
+    }
+
+    /// The named child in field `name`.
+    pub fn field(&self, name: &str) -> Option<SyntaxNode<'t>> {
+        self.node
+            .child_by_field_name(name)
+            .map(|node| self.wrap(node))
+    }
+
+    pub fn children(&self) -> Vec<SyntaxNode<'t>> {
+        let mut cursor = self.node.walk();
+        let children: Vec<TsNode<'t>> = self.node.named_children(&mut cursor).collect();
+        children.into_iter().map(|node| self.wrap(node)).collect()
+    }
+
+    /// This node and every named node below it, in pre-order.
+    pub fn descendants(&self) -> Vec<SyntaxNode<'t>> {
+        let mut out = Vec::new();
+        let mut cursor = self.node.walk();
+        symbols::visit(&mut cursor, &mut |node| {
+            if node.is_named() {
+                out.push(self.wrap(node));
+            }
+        });
+        out
+    }
+
+    /// The nodes below this one with `role`, in document order.
+    pub fn find(&self, role: Role) -> Vec<SyntaxNode<'t>> {
+        let mut found = self.descendants();
+        found.retain(|node| node.node != self.node && node.role() == Some(role));
+        found
+    }
+
+    /// The nearest ancestor with `role`, such as the function a call is in.
+    pub fn enclosing(&self, role: Role) -> Option<SyntaxNode<'t>> {
+        let mut node = self.parent();
+        while let Some(n) = node {
+            if n.role() == Some(role) {
+                return Some(n);
+            }
+            node = n.parent();
+        }
+        None
+    }
+
+    /// The underlying Tree-sitter node.
+    pub fn ts_node(&self) -> TsNode<'t> {
+        self.node
+    }
+}
+
+impl fmt::Debug for SyntaxNode<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyntaxNode")
+            .field("kind", &self.kind())
+            .field("role", &self.role())
+            .field("range", &self.byte_range())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::languages;
+    use std::fs;
+
+    fn tree(language: &str, source: &str) -> SyntaxTree {
+        let provider = languages::by_name(language).unwrap();
+        let pack = QueryPack::default();
+        SyntaxTree::parse(source.as_bytes(), provider, &pack, &ParseLimits::default()).unwrap()
+    }
+
+    /// Each function's name, parameter count and calls, whatever the language.
+    fn outline(tree: &SyntaxTree) -> Vec<String> {
+        tree.nodes(Role::Function)
+            .into_iter()
+            .map(|function| {
+                let calls = function.find(Role::Call).len();
+                let parameters = function.find(Role::Parameter).len();
+                let name = function.name().unwrap_or_default();
+                format!("{}({}) calls {}", name, parameters, calls)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn walks_functions_the_same_way_in_every_language() {
+        let rust = tree(
+            "rust",
+            "use std::fs;\n// Reads a file.\nfn read(path: &str, limit: usize) {\n    open(path);\n}\n",
+        );
+        let python = tree(
+            "python",
+            "import os\n# Reads a file.\ndef read(path, limit=1):\n    open(path)\n",
+        );
+        let java = tree(
+            "java",
+            "import java.io.File;\n// Reads a file.\nclass A {\n    void read(String path, int limit) {\n        open(path);\n    }\n}\n",
+        );
+        for tree in [&rust, &python, &java] {
+            let language = tree.provider().name;
+            assert_eq!(outline(tree), ["read(2) calls 1"], "{}", language);
+            assert_eq!(tree.nodes(Role::Import).len(), 1, "{}", language);
+            let comments = tree.nodes(Role::Comment);
+            assert_eq!(comments.len(), 1, "{}", language);
+            assert!(comments[0].text().contains("Reads a file."), "{}", language);
+        }
+        let call = java.nodes(Role::Call)[0];
+        assert_eq!(call.enclosing(Role::Function).unwrap().line(), 4);
+        assert_eq!(call.enclosing(Role::Type).unwrap().name().unwrap(), "A");
+
+        for provider in languages::all() {
+            let source = b"x";
+            let pack = QueryPack::default();
+            let limits = ParseLimits::default();
+            let parsed = SyntaxTree::parse(source, provider, &pack, &limits);
+            assert!(parsed.is_ok(), "{}: {:?}", provider.name, parsed.err());
+        }
+    }
+
+    #[test]
+    fn repository_roles_replace_the_builtin_query() {
+        let dir = tempfile::tempdir().unwrap();
+        let rust = languages::by_name("rust").unwrap();
+        let queries = dir.path().join(queries::QUERY_DIR).join("rust");
+        fs::create_dir_all(&queries).unwrap();
+        fs::write(
+            queries.join("roles.scm"),
+            "((macro_invocation macro: (identifier) @_m) @function (#eq? @_m \"route\"))\n",
+        )
+        .unwrap();
+        let pack = QueryPack::load(dir.path(), rust).unwrap();
+        let source = b"route!(index);\nfn f() {}\n";
+        let tree = SyntaxTree::parse(source, rust, &pack, &ParseLimits::default()).unwrap();
+        let functions = tree.nodes(Role::Function);
+        assert_eq!(functions.len(), 1);
+        assert_eq!(functions[0].kind(), "macro_invocation");
+
+        fs::write(queries.join("roles.scm"), "(function_item) @fn\n").unwrap();
+        let pack = QueryPack::load(dir.path(), rust).unwrap();
+        let err = SyntaxTree::parse(source, rust, &pack, &ParseLimits::default()).unwrap_err();
+        assert!(err.to_string().contains("unknown role @fn"), "{}", err);
+    }
+}
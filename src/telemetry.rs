@@ -0,0 +1,405 @@
+//! Logging and Metrics
+//!
+//! ## Logging
+//!
+//! Everything `git-ast` reports on stderr goes through [`log_info!`] and
+//! [`log_error!`]. By default these print `[component] message` lines for
+//! humans; with `ast.logFormat = json` (or `--log-format json`, which also
+//! reaches the filters and drivers Git starts from that command) each line
+//! is a JSON object instead, for log collectors:
+//!
+//! ```text
+//! {"component":"filter","level":"error","message":"clean src/lib.rs failed: ...","pid":4242,"ts":1760601600.123}
+//! ```
+//!
+//! The filter process, the drivers and the hooks run inside `git add`,
+//! `git checkout`, `git diff` and `git merge`, whose output they share, so
+//! they log errors only. `ast.logLevel = info` (or `GIT_AST_LOG_LEVEL=info`)
+//! adds their progress lines (`Cleaning path: ...`) for debugging.
+//!
+//! ## Metrics
+//!
+//! With `ast.metrics = true`, each `git-ast` process adds its counters to
+//! `.git/ast-cache/metrics` when it exits, and `git-ast metrics` prints the
+//! totals in the Prometheus text format (for a node exporter's textfile
+//! collector, or a push to a gateway from CI):
+//!
+//! ```text
+//! # HELP git_ast_parse_seconds Time spent parsing source with Tree-sitter.
+//! # TYPE git_ast_parse_seconds summary
+//! git_ast_parse_seconds_count{language="rust"} 412
+//! git_ast_parse_seconds_sum{language="rust"} 1.734
+//! ```
+//!
+//! [`FAMILIES`] lists what is collected: parse times, filter requests,
+//! bytes and time (throughput), diff cache hits and misses, and errors.
+//! `git-ast metrics --reset` starts the totals over. Processes update the
+//! file under a `.lock` file like Git's own, so concurrent filters and
+//! drivers do not lose each other's counts.
+
+use crate::util::journal;
+use crate::Error;
+use git2::Repository;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Git config key choosing the log format (`text` or `json`).
+pub const LOG_FORMAT_KEY: &str = "ast.logFormat";
+/// Git config key choosing what the processes Git starts log (`error` or
+/// `info`).
+pub const LOG_LEVEL_KEY: &str = "ast.logLevel";
+/// Git config key enabling metrics collection (default: disabled).
+pub const METRICS_KEY: &str = "ast.metrics";
+
+/// How log lines are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum LogFormat {
+    /// `[component] message`.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+impl LogFormat {
+    /// The `ast.logFormat` spelling.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogFormat::Text => "text",
+            LogFormat::Json => "json",
+        }
+    }
+}
+
+/// Severity of a log line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Info,
+    Error,
+}
+
+static LOG_FORMAT: OnceLock<LogFormat> = OnceLock::new();
+/// Only `Level::Error` lines are written when set.
+static ERRORS_ONLY: AtomicBool = AtomicBool::new(false);
+static METRICS: Mutex<BTreeMap<String, f64>> = Mutex::new(BTreeMap::new());
+
+/// Sets the log format for the rest of the process (only the first call
+/// counts).
+pub fn set_log_format(format: LogFormat) {
+    let _ = LOG_FORMAT.set(format);
+}
+
+/// Drops informational lines for the rest of the process, for commands
+/// that call the filters in bulk and report on their own.
+pub fn errors_only() {
+    ERRORS_ONLY.store(true, Ordering::Relaxed);
+}
+
+/// Writes one log line to stderr; see [`log_info!`] and [`log_error!`].
+pub fn log(level: Level, component: &str, message: fmt::Arguments) {
+    if level < Level::Error && ERRORS_ONLY.load(Ordering::Relaxed) {
+        return;
+    }
+    let line = format_line(
+        LOG_FORMAT.get().copied().unwrap_or_default(),
+        level,
+        component,
+        message,
+        SystemTime::now(),
+    );
+    eprintln!("{}", line);
+}
+
+fn format_line(
+    format: LogFormat,
+    level: Level,
+    component: &str,
+    message: fmt::Arguments,
+    now: SystemTime,
+) -> String {
+    match format {
+        LogFormat::Text => format!("[{}] {}", component, message),
+        LogFormat::Json => {
+            let ts = now
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64();
+            serde_json::json!({
+                "ts": (ts * 1000.0).round() / 1000.0,
+                "level": match level {
+                    Level::Info => "info",
+                    Level::Error => "error",
+                },
+                "component": component,
+                "pid": std::process::id(),
+                "message": message.to_string(),
+            })
+            .to_string()
+        }
+    }
+}
+
+/// Logs a progress message: `log_info!("filter", "Cleaning path: {}", path)`.
+#[macro_export]
+macro_rules! log_info {
+    ($component:expr, $($arg:tt)+) => {
+        $crate::telemetry::log($crate::telemetry::Level::Info, $component, format_args!($($arg)+))
+    };
+}
+
+/// Logs a failure, like [`log_info!`].
+#[macro_export]
+macro_rules! log_error {
+    ($component:expr, $($arg:tt)+) => {
+        $crate::telemetry::log($crate::telemetry::Level::Error, $component, format_args!($($arg)+))
+    };
+}
+
+/// A metric family: name, Prometheus type and help text.
+pub struct Family {
+    pub name: &'static str,
+    pub kind: &'static str,
+    pub help: &'static str,
+}
+
+/// Every metric `git-ast` collects.
+pub const FAMILIES: &[Family] = &[
+    Family {
+        name: "git_ast_parse_seconds",
+        kind: "summary",
+        help: "Time spent parsing source with Tree-sitter.",
+    },
+    Family {
+        name: "git_ast_filter_requests_total",
+        kind: "counter",
+        help: "Files cleaned or smudged by the filter process, by outcome.",
+    },
+    Family {
+        name: "git_ast_filter_bytes_total",
+        kind: "counter",
+        help: "Source bytes read by clean and written by smudge.",
+    },
+    Family {
+        name: "git_ast_filter_seconds_total",
+        kind: "counter",
+        help: "Time spent answering filter requests.",
+    },
+    Family {
+        name: "git_ast_diff_cache_requests_total",
+        kind: "counter",
+        help: "Diff driver cache lookups, by result (hit or miss).",
+    },
+    Family {
+        name: "git_ast_merge_fallbacks_total",
+        kind: "counter",
+        help: "Merges given up for Git's text merge, over the conflict or time budget.",
+    },
+    Family {
+        name: "git_ast_errors_total",
+        kind: "counter",
+        help: "Failed commands, and files the filter failed on.",
+    },
+];
+
+/// Adds `value` to the counter `name` with the given labels.
+pub fn add(name: &str, labels: &[(&str, &str)], value: f64) {
+    let mut metrics = METRICS.lock().unwrap_or_else(|e| e.into_inner());
+    *metrics.entry(series(name, labels)).or_default() += value;
+}
+
+/// Records one observation of the summary `name` (its `_sum` and `_count`).
+pub fn observe(name: &str, labels: &[(&str, &str)], elapsed: Duration) {
+    add(&format!("{}_sum", name), labels, elapsed.as_secs_f64());
+    add(&format!("{}_count", name), labels, 1.0);
+}
+
+/// Runs `f` and records how long it took with [`observe`].
+pub fn timed<T>(name: &str, labels: &[(&str, &str)], f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    observe(name, labels, start.elapsed());
+    result
+}
+
+/// `name{label="value",...}`.
+fn series(name: &str, labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return name.to_string();
+    }
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect();
+    format!("{}{{{}}}", name, labels.join(","))
+}
+
+/// The metrics file of `repo`.
+pub fn metrics_path(repo: &Repository) -> PathBuf {
+    repo.path().join("ast-cache").join("metrics")
+}
+
+/// Adds what this process collected to the totals in `path`, and clears it.
+pub fn flush(path: &Path) -> Result<(), Error> {
+    let collected = std::mem::take(&mut *METRICS.lock().unwrap_or_else(|e| e.into_inner()));
+    if collected.is_empty() {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let lock_path = path.with_extension("lock");
+    let mut lock = lock_file(&lock_path)?;
+    // Recorded once held, so that recovery never removes another's lock.
+    journal::acquire(&lock_path);
+    let merged = (|| {
+        let mut totals = read_totals(path)?;
+        for (series, value) in collected {
+            *totals.entry(series).or_default() += value;
+        }
+        for (series, value) in &totals {
+            writeln!(lock, "{} {}", series, value)?;
+        }
+        lock.sync_all()?;
+        fs::rename(&lock_path, path)?;
+        Ok(())
+    })();
+    if merged.is_err() {
+        let _ = fs::remove_file(&lock_path);
+    }
+    journal::release(&lock_path);
+    merged
+}
+
+/// Creates `path` exclusively, waiting up to a second for another process
+/// holding it.
+fn lock_file(path: &Path) -> Result<fs::File, Error> {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    loop {
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+        {
+            Ok(file) => return Ok(file),
+            Err(e)
+                if e.kind() == std::io::ErrorKind::AlreadyExists && Instant::now() < deadline =>
+            {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            Err(e) => return Err(Error::Io(e)),
+        }
+    }
+}
+
+/// The totals stored in `path` (none if it does not exist).
+pub fn read_totals(path: &Path) -> Result<BTreeMap<String, f64>, Error> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    text.lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (series, value) = line.rsplit_once(' ').unwrap_or((line, ""));
+            let value = value.parse().map_err(|_| {
+                Error::Config(format!("{}: bad metrics line {:?}", path.display(), line))
+            })?;
+            Ok((series.to_string(), value))
+        })
+        .collect()
+}
+
+/// The totals in `path` in the Prometheus text format.
+pub fn render(path: &Path) -> Result<String, Error> {
+    let totals = read_totals(path)?;
+    let mut out = String::new();
+    for family in FAMILIES {
+        let samples: Vec<_> = totals
+            .iter()
+            .filter(|(series, _)| {
+                let name = series.split('{').next().unwrap_or_default();
+                name == family.name
+                    || (family.kind == "summary"
+                        && name
+                            .strip_prefix(family.name)
+                            .is_some_and(|s| s == "_sum" || s == "_count"))
+            })
+            .collect();
+        if samples.is_empty() {
+            continue;
+        }
+        out.push_str(&format!(
+            "# HELP {} {}\n# TYPE {} {}\n",
+            family.name, family.help, family.name, family.kind
+        ));
+        for (series, value) in samples {
+            out.push_str(&format!("{} {}\n", series, value));
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_logs_and_accumulates_metrics() {
+        let now = UNIX_EPOCH + Duration::from_millis(1_500);
+        let text = format_line(
+            LogFormat::Text,
+            Level::Info,
+            "filter",
+            format_args!("Smudging path: {}", "a.rs"),
+            now,
+        );
+        assert_eq!(text, "[filter] Smudging path: a.rs");
+        let json = format_line(
+            LogFormat::Json,
+            Level::Error,
+            "filter",
+            format_args!("clean \"a.rs\" failed"),
+            now,
+        );
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(json["ts"], 1.5);
+        assert_eq!(json["level"], "error");
+        assert_eq!(json["message"], "clean \"a.rs\" failed");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("metrics");
+        for _ in 0..2 {
+            // Labels no other test uses: tests share the process's counters.
+            add(
+                "git_ast_filter_requests_total",
+                &[("command", "test"), ("status", "success")],
+                1.0,
+            );
+            observe(
+                "git_ast_parse_seconds",
+                &[("language", "test")],
+                Duration::from_millis(250),
+            );
+            flush(&path).unwrap();
+        }
+        assert!(!path.with_extension("lock").exists());
+        let rendered = render(&path).unwrap();
+        assert!(
+            rendered.starts_with("# HELP git_ast_parse_seconds "),
+            "{}",
+            rendered
+        );
+        assert!(rendered.contains("git_ast_parse_seconds_count{language=\"test\"} 2\n"));
+        assert!(rendered.contains("git_ast_parse_seconds_sum{language=\"test\"} 0.5\n"));
+        assert!(rendered.contains("# TYPE git_ast_filter_requests_total counter\n"));
+        assert!(rendered
+            .contains("git_ast_filter_requests_total{command=\"test\",status=\"success\"} 2\n"));
+    }
+}
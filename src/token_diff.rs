@@ -0,0 +1,431 @@
+//! Token-Level Diffs
+//!
+//! A line diff shows a renamed argument as a whole line removed and another
+//! added, and leaves the reader to spot the difference. [`render`] marks only
+//! the tokens that changed, in the format of `git diff --word-diff=plain`:
+//!
+//! ```text
+//! @@ -1,3 +1,3 @@
+//! fn parse([-input-]{+source+}: &str) -> Tree {
+//!     [-build-]{+build_tree+}(source)
+//! }
+//! ```
+//!
+//! Tokens are the leaves of the Tree-sitter parse, so `parse(input)` is four
+//! tokens rather than one word, and a changed string literal or comment is
+//! one token however many words it has.
+//!
+//! Hunks are found by a line diff of the canonical source, with
+//! [`CONTEXT_LINES`] of context. Within a run of changed lines, the tokens
+//! of both sides are diffed only if the run is one modified item rather
+//! than one replaced by another: if the sides share fewer of their tokens
+//! than the similarity threshold (see
+//! [`matching`](crate::matching#modified-items)), the old lines are shown
+//! removed and the new ones added, each whole.
+//!
+//! ## Strings and Comments
+//!
+//! String literals and comments (the language's atomic kinds) are compared
+//! as `ast.diffLiterals` says:
+//!
+//! -   **`token`** (default): each is one token, shown whole when it changed.
+//! -   **`words`**: their text is diffed word by word, so an edited sentence
+//!     in a doc comment marks the words that changed.
+//! -   **`ignore`**: comments are left out and strings compared by kind only;
+//!     hunks that change nothing else are not shown at all.
+
+use crate::diff_backend::CONTEXT_LINES;
+use crate::fingerprint;
+use crate::languages::{LanguageProvider, SyntaxRules};
+use crate::parsing::{self, ParseLimits};
+use similar::{Algorithm, DiffOp, TextDiff};
+use std::ops::Range;
+use tree_sitter::Tree;
+
+/// Config key choosing how strings and comments are diffed.
+pub const DIFF_LITERALS_KEY: &str = "ast.diffLiterals";
+
+/// How strings and comments are diffed (see the module docs).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Literals {
+    #[default]
+    Token,
+    Words,
+    Ignore,
+}
+
+impl Literals {
+    /// Its `ast.diffLiterals` value.
+    pub fn name(self) -> &'static str {
+        match self {
+            Literals::Token => "token",
+            Literals::Words => "words",
+            Literals::Ignore => "ignore",
+        }
+    }
+}
+
+/// How [`render`] compares the two sides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    /// Similarity (percent) a run of changed lines needs to be diffed token
+    /// by token.
+    pub similarity: u32,
+    pub literals: Literals,
+}
+
+/// A token: its kind, where it is, and whether it is (part of) a string or
+/// comment.
+#[derive(Debug, Clone)]
+struct Token {
+    kind: &'static str,
+    range: Range<usize>,
+    literal: bool,
+}
+
+/// One side of the diff: its text, where its lines start, and its tokens.
+struct Side<'s> {
+    text: &'s str,
+    /// Byte offset of each line's start, and the text's length last.
+    lines: Vec<usize>,
+    tokens: Vec<Token>,
+    literals: Literals,
+}
+
+impl<'s> Side<'s> {
+    fn new(
+        source: &'s [u8],
+        provider: &LanguageProvider,
+        limits: &ParseLimits,
+        literals: Literals,
+    ) -> Option<Self> {
+        let text = std::str::from_utf8(source).ok()?;
+        let tree = parsing::parse(source, provider, limits).ok()?;
+        let mut lines = vec![0];
+        lines.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        if lines.last() != Some(&text.len()) {
+            lines.push(text.len());
+        }
+        Some(Side {
+            text,
+            lines,
+            tokens: tokens(&tree, text, &provider.syntax, literals),
+            literals,
+        })
+    }
+
+    /// The bytes of lines `range`.
+    fn bytes(&self, range: &Range<usize>) -> Range<usize> {
+        self.lines[range.start]..self.lines[range.end]
+    }
+
+    /// The text of line `i`, without its newline.
+    fn line(&self, i: usize) -> &'s str {
+        self.text[self.bytes(&(i..i + 1))].trim_end_matches('\n')
+    }
+
+    /// The tokens within `bytes`, or `None` if one crosses its edges.
+    fn tokens_in(&self, bytes: &Range<usize>) -> Option<&[Token]> {
+        let first = self.tokens.partition_point(|t| t.range.end <= bytes.start);
+        let last = self.tokens.partition_point(|t| t.range.start < bytes.end);
+        let tokens = &self.tokens[first..last.max(first)];
+        let inside = tokens.first().is_none_or(|t| t.range.start >= bytes.start)
+            && tokens.last().is_none_or(|t| t.range.end <= bytes.end);
+        inside.then_some(tokens)
+    }
+
+    /// What `token` is compared by.
+    fn key(&self, token: &Token) -> (&'static str, &'s str) {
+        match token.literal && self.literals == Literals::Ignore {
+            true => (token.kind, ""),
+            false => (token.kind, &self.text[token.range.clone()]),
+        }
+    }
+
+    /// The keys of the tokens on lines `range`, or `None` if a token crosses
+    /// their edges.
+    fn keys(&self, range: &Range<usize>) -> Option<Vec<(&'static str, &'s str)>> {
+        let tokens = self.tokens_in(&self.bytes(range))?;
+        Some(tokens.iter().map(|t| self.key(t)).collect())
+    }
+}
+
+/// The tokens of `tree`, with strings and comments as `literals` says.
+fn tokens(tree: &Tree, text: &str, rules: &SyntaxRules, literals: Literals) -> Vec<Token> {
+    let mut out = Vec::new();
+    let mut cursor = tree.walk();
+    loop {
+        let node = cursor.node();
+        let kind = node.kind();
+        let comment = rules.comments.contains(&kind);
+        let literal = comment || rules.atomic.contains(&kind);
+        if literal || node.child_count() == 0 {
+            let range = node.byte_range();
+            match literals {
+                _ if !literal => out.push(Token {
+                    kind,
+                    range,
+                    literal,
+                }),
+                Literals::Ignore if comment => {}
+                Literals::Words => out.extend(words(&text[range.clone()]).map(|word| Token {
+                    kind,
+                    range: range.start + word.start..range.start + word.end,
+                    literal,
+                })),
+                _ => out.push(Token {
+                    kind,
+                    range,
+                    literal,
+                }),
+            }
+        } else if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return out;
+            }
+        }
+    }
+}
+
+/// The words of `text`: runs of letters, digits and `_`, and every other
+/// character but whitespace on its own.
+fn words(text: &str) -> impl Iterator<Item = Range<usize>> + '_ {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut chars = text.char_indices().peekable();
+    std::iter::from_fn(move || loop {
+        let (start, c) = chars.next()?;
+        if c.is_whitespace() {
+            continue;
+        }
+        let mut end = start + c.len_utf8();
+        while let Some(&(i, next)) = chars.peek() {
+            if !(is_word(c) && is_word(next)) {
+                break;
+            }
+            end = i + next.len_utf8();
+            chars.next();
+        }
+        return Some(start..end);
+    })
+}
+
+/// `old` → `new` with changed tokens marked (see the module docs); `None`
+/// if either side is not UTF-8 or does not parse.
+pub fn render(
+    old_label: &str,
+    new_label: &str,
+    old: &[u8],
+    new: &[u8],
+    provider: &LanguageProvider,
+    limits: &ParseLimits,
+    options: Options,
+) -> Option<String> {
+    let (old, new) = (
+        Side::new(old, provider, limits, options.literals)?,
+        Side::new(new, provider, limits, options.literals)?,
+    );
+    let diff = TextDiff::from_lines(old.text, new.text);
+    let mut out = String::new();
+    for group in diff.grouped_ops(CONTEXT_LINES) {
+        let ignored = |op: &DiffOp| match op {
+            DiffOp::Equal { .. } => true,
+            _ => {
+                let keys = old.keys(&op.old_range());
+                keys.is_some() && keys == new.keys(&op.new_range())
+            }
+        };
+        if options.literals == Literals::Ignore && group.iter().all(ignored) {
+            continue;
+        }
+        if out.is_empty() {
+            out.push_str(&format!("--- {}\n+++ {}\n", old_label, new_label));
+        }
+        let (first, last) = (&group[0], &group[group.len() - 1]);
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            span(first.old_range().start..last.old_range().end),
+            span(first.new_range().start..last.new_range().end)
+        ));
+        for op in &group {
+            match *op {
+                DiffOp::Equal { old_index, len, .. } => {
+                    for i in old_index..old_index + len {
+                        out.push_str(old.line(i));
+                        out.push('\n');
+                    }
+                }
+                DiffOp::Delete { .. } | DiffOp::Insert { .. } => {
+                    whole_lines(&mut out, &old, op.old_range(), &new, op.new_range());
+                }
+                DiffOp::Replace { .. } => {
+                    let (a, b) = (op.old_range(), op.new_range());
+                    changed(&mut out, &old, a, &new, b, options.similarity);
+                }
+            }
+        }
+    }
+    Some(out)
+}
+
+/// A hunk header's line range, as unified diffs write it.
+fn span(lines: Range<usize>) -> String {
+    match lines.len() {
+        0 => format!("{},0", lines.start),
+        1 => format!("{}", lines.start + 1),
+        n => format!("{},{}", lines.start + 1, n),
+    }
+}
+
+/// Old lines `a` shown removed and new lines `b` added, each whole.
+fn whole_lines(out: &mut String, old: &Side<'_>, a: Range<usize>, new: &Side<'_>, b: Range<usize>) {
+    for (side, lines, open, close) in [(old, a, "[-", "-]"), (new, b, "{+", "+}")] {
+        for i in lines {
+            match side.line(i) {
+                "" => out.push('\n'),
+                line => out.push_str(&format!("{}{}{}\n", open, line, close)),
+            }
+        }
+    }
+}
+
+/// Old lines `a` replaced by new lines `b`, token by token if they are
+/// similar enough.
+fn changed(
+    out: &mut String,
+    old: &Side<'_>,
+    a: Range<usize>,
+    new: &Side<'_>,
+    b: Range<usize>,
+    threshold: u32,
+) {
+    let (old_bytes, new_bytes) = (old.bytes(&a), new.bytes(&b));
+    let (Some(old_tokens), Some(new_tokens)) =
+        (old.tokens_in(&old_bytes), new.tokens_in(&new_bytes))
+    else {
+        return whole_lines(out, old, a, new, b);
+    };
+    let old_texts: Vec<_> = old_tokens.iter().map(|t| old.key(t)).collect();
+    let new_texts: Vec<_> = new_tokens.iter().map(|t| new.key(t)).collect();
+    if fingerprint::score(&old_texts, &new_texts) < threshold {
+        return whole_lines(out, old, a, new, b);
+    }
+    // Copy the new side, with removed tokens spliced in where they were.
+    let mut pos = new_bytes.start;
+    for op in similar::capture_diff_slices(Algorithm::Myers, &old_texts, &new_texts) {
+        let (a, b) = (op.old_range(), op.new_range());
+        if let DiffOp::Equal { .. } = op {
+            let end = new_tokens[b.end - 1].range.end;
+            out.push_str(&new.text[pos..end]);
+            pos = end;
+            continue;
+        }
+        if !b.is_empty() {
+            // The space before the first new token stays unmarked.
+            let start = new_tokens[b.start].range.start;
+            out.push_str(&new.text[pos..start]);
+            pos = start;
+        }
+        if !a.is_empty() {
+            let (first, last) = (&old_tokens[a.start].range, &old_tokens[a.end - 1].range);
+            out.push_str(&format!("[-{}-]", &old.text[first.start..last.end]));
+        }
+        if !b.is_empty() {
+            let end = new_tokens[b.end - 1].range.end;
+            out.push_str(&format!("{{+{}+}}", &new.text[pos..end]));
+            pos = end;
+        }
+    }
+    out.push_str(&new.text[pos..new_bytes.end]);
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::languages;
+
+    fn diff_with(old: &str, new: &str, literals: Literals) -> String {
+        let rust = languages::by_name("rust").unwrap();
+        let limits = ParseLimits::default();
+        let options = Options {
+            similarity: 50,
+            literals,
+        };
+        render(
+            "a/x.rs",
+            "b/x.rs",
+            old.as_bytes(),
+            new.as_bytes(),
+            rust,
+            &limits,
+            options,
+        )
+        .unwrap()
+    }
+
+    fn diff(old: &str, new: &str) -> String {
+        diff_with(old, new, Literals::Token)
+    }
+
+    #[test]
+    fn marks_changed_tokens_of_modified_lines() {
+        let old = "fn parse(input: &str) -> Tree {\n    build(input, 1)\n}\n";
+        let new = "fn parse(source: &str) -> Tree {\n    build(source, 1)\n}\n";
+        assert_eq!(
+            diff(old, new),
+            "--- a/x.rs\n+++ b/x.rs\n@@ -1,3 +1,3 @@\n\
+             fn parse([-input-]{+source+}: &str) -> Tree {\n    \
+             build([-input-]{+source+}, 1)\n}\n"
+        );
+        // Tokens, not words: `x+1` is three of them.
+        assert!(diff("fn f() {\n    x+1;\n}\n", "fn f() {\n    x+2;\n}\n")
+            .contains("    x+[-1-]{+2+};\n"));
+        // Nothing in common: whole lines.
+        let replaced = diff("fn f() {}\n", "struct S;\n");
+        assert!(
+            replaced.ends_with("@@ -1 +1 @@\n[-fn f() {}-]\n{+struct S;+}\n"),
+            "{}",
+            replaced
+        );
+        assert_eq!(diff(old, old), "");
+    }
+
+    #[test]
+    fn diffs_strings_and_comments_as_configured() {
+        let old = "// Parses the input.\nfn f() {\n    g(\"a b c\");\n}\n";
+        let new = "// Parses the whole input.\nfn f() {\n    g(\"a x c\");\n}\n";
+        let whole = diff_with(old, new, Literals::Token);
+        assert!(
+            whole.contains("[-// Parses the input.-]\n{+// Parses the whole input.+}\n"),
+            "{}",
+            whole
+        );
+        assert!(
+            whole.contains("    g([-\"a b c\"-]{+\"a x c\"+});\n"),
+            "{}",
+            whole
+        );
+
+        let words = diff_with(old, new, Literals::Words);
+        assert!(
+            words.contains("// Parses the {+whole+} input.\n"),
+            "{}",
+            words
+        );
+        assert!(words.contains("    g(\"a [-b-]{+x+} c\");\n"), "{}", words);
+
+        assert_eq!(diff_with(old, new, Literals::Ignore), "");
+        let code = "// Parses the whole input.\nfn f() {\n    h(\"a x c\");\n}\n";
+        let ignored = diff_with(old, code, Literals::Ignore);
+        assert!(
+            ignored.contains("    [-g-]{+h+}(\"a x c\");\n"),
+            "{}",
+            ignored
+        );
+    }
+}
@@ -0,0 +1,331 @@
+//! Project Toolchains
+//!
+//! Grammars are compiled into `git-ast`, so the toolchain a project needs is
+//! a `git-ast` build whose grammars and printer match its `.git-ast.lock`
+//! (see [`lockfile`](crate::lockfile)) and which supports every language its
+//! `.git-ast.toml` configures. This module works out whether the running
+//! binary is such a build ([`Requirements`], [`plan`]), builds the release
+//! the lockfile names otherwise ([`install`]), and points a repository's
+//! filter and drivers at the chosen binary ([`configure`]).
+//! `git-ast setup-toolchain` does all three.
+//!
+//! ## Cache
+//!
+//! Releases are built into `<cache>/toolchains/<version>`, `<cache>` being
+//! `$GIT_AST_CACHE_DIR`, else `$XDG_CACHE_HOME/git-ast`, else
+//! `~/.cache/git-ast` (`%LOCALAPPDATA%\git-ast` on Windows). Each is built
+//! once per user, with `cargo install --locked` (the dependency versions the
+//! release was tested with), and shared by every clone that pins it.
+//!
+//! ## Trusted Source
+//!
+//! The lockfile comes with the repository, so it only chooses *which*
+//! release to build, never *where from*: releases are built from the tag
+//! `v<version>` of [`SOURCE_REPOSITORY`], the project's own Git repository,
+//! compiled into this binary. A package of the same name on crates.io (or
+//! any other registry) is never used, so a squatted name cannot get code
+//! built and run through a cloned repository.
+
+use crate::config::{RepoConfigs, REPO_CONFIG_FILE};
+use crate::languages;
+use crate::lockfile::{Lockfile, LOCKFILE};
+use crate::{log_info, Error};
+use git2::Repository;
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// What a repository asks of `git-ast`.
+#[derive(Debug, Clone, Default)]
+pub struct Requirements {
+    /// The worktree's `.git-ast.lock`, if any.
+    pub lock: Option<Lockfile>,
+    /// Languages the root `.git-ast.toml` has sections for.
+    pub configured: BTreeSet<String>,
+}
+
+impl Requirements {
+    /// The requirements of `repo`'s worktree.
+    pub fn load(repo: &Repository) -> Result<Self, Error> {
+        let config = RepoConfigs::new(Some(repo)).for_path(REPO_CONFIG_FILE)?;
+        Ok(Requirements {
+            lock: Lockfile::load(repo)?,
+            configured: config
+                .normalize
+                .keys()
+                .chain(config.diff.languages.keys())
+                .cloned()
+                .collect(),
+        })
+    }
+
+    /// Why the running binary does not meet the requirements, one line each.
+    pub fn mismatches(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(lock) = &self.lock {
+            for name in lock.languages.keys() {
+                match languages::by_name(name) {
+                    Some(provider) => {
+                        problems.extend(lock.check(provider).err().map(|e| e.to_string()))
+                    }
+                    None => {
+                        problems.push(format!("{} pins unsupported language {}", LOCKFILE, name))
+                    }
+                }
+            }
+        }
+        for name in &self.configured {
+            if languages::by_name(name).is_none() {
+                problems.push(format!(
+                    "{} configures unsupported language {}",
+                    REPO_CONFIG_FILE, name
+                ));
+            }
+        }
+        problems
+    }
+}
+
+/// What it takes to meet a repository's [`Requirements`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Plan {
+    /// The running binary meets them.
+    Current,
+    /// Release `version` does (as far as the lockfile says); build it.
+    Install(String),
+}
+
+/// Decides between the running binary and the release the lockfile names.
+pub fn plan(requirements: &Requirements) -> Result<Plan, Error> {
+    let problems = requirements.mismatches();
+    if problems.is_empty() {
+        return Ok(Plan::Current);
+    }
+    let pinned = requirements
+        .lock
+        .as_ref()
+        .and_then(|lock| lock.git_ast.as_deref());
+    match pinned {
+        Some(version) if version != env!("CARGO_PKG_VERSION") => {
+            validate_version(version)?;
+            Ok(Plan::Install(version.to_string()))
+        }
+        _ => Err(Error::Config(format!(
+            "this git-ast does not suit the project, and {} names no other release: {}",
+            LOCKFILE,
+            problems.join("; ")
+        ))),
+    }
+}
+
+/// The per-user cache directory (see the module documentation).
+pub fn cache_dir() -> Option<PathBuf> {
+    let var = |name: &str| {
+        std::env::var_os(name)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    };
+    var("GIT_AST_CACHE_DIR")
+        .or_else(|| var("XDG_CACHE_HOME").map(|dir| dir.join("git-ast")))
+        .or_else(|| match cfg!(windows) {
+            true => var("LOCALAPPDATA").map(|dir| dir.join("git-ast")),
+            false => var("HOME").map(|dir| dir.join(".cache").join("git-ast")),
+        })
+}
+
+/// Where release `version` is (or would be) installed under `cache`.
+pub fn toolchain_exe(cache: &Path, version: &str) -> PathBuf {
+    let name = format!("{}{}", env!("CARGO_PKG_NAME"), std::env::consts::EXE_SUFFIX);
+    cache
+        .join("toolchains")
+        .join(version)
+        .join("bin")
+        .join(name)
+}
+
+/// The Git repository releases are built from (see
+/// [Trusted Source](self#trusted-source)).
+pub const SOURCE_REPOSITORY: &str = env!("CARGO_PKG_REPOSITORY");
+
+/// The `cargo` arguments building release `version` into `root`, from the
+/// `v<version>` tag of [`SOURCE_REPOSITORY`].
+pub fn install_args(version: &str, root: &Path) -> Vec<OsString> {
+    let source = ["install", "--locked", "--git", SOURCE_REPOSITORY, "--tag"];
+    let mut args: Vec<OsString> = source.iter().map(OsString::from).collect();
+    args.push(format!("v{}", version).into());
+    args.push("--root".into());
+    args.push(root.into());
+    args.push(env!("CARGO_PKG_NAME").into());
+    args
+}
+
+/// Builds release `version` under `cache` unless it is already there, and
+/// returns its executable.
+pub fn install(cache: &Path, version: &str) -> Result<PathBuf, Error> {
+    validate_version(version)?;
+    let exe = toolchain_exe(cache, version);
+    if exe.is_file() {
+        return Ok(exe);
+    }
+    let root = cache.join("toolchains").join(version);
+    log_info!(
+        "toolchain",
+        "Building git-ast {} from {} into {}",
+        version,
+        SOURCE_REPOSITORY,
+        root.display()
+    );
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let status = Command::new(cargo)
+        .args(install_args(version, &root))
+        .status()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Error::Config(format!(
+                "building git-ast {} needs cargo; install Rust from https://rustup.rs",
+                version
+            )),
+            _ => e.into(),
+        })?;
+    if !status.success() || !exe.is_file() {
+        return Err(Error::Driver(format!(
+            "cargo install of git-ast {} failed: {}",
+            version, status
+        )));
+    }
+    Ok(exe)
+}
+
+/// The Git config entries defining the `ast` filter and drivers, running
+/// `command` (a program name or a quoted path).
+pub fn driver_config(command: &str) -> Vec<(&'static str, String)> {
+    vec![
+        ("filter.ast.process", format!("{} filter-process", command)),
+        ("filter.ast.required", "true".to_string()),
+        ("diff.ast.command", format!("{} diff-driver", command)),
+        ("merge.ast.name", "AST-based merge driver".to_string()),
+        (
+            "merge.ast.driver",
+            format!("{} merge-driver %O %A %B %L %P", command),
+        ),
+        ("merge.ast.recursive", "binary".to_string()),
+    ]
+}
+
+/// Points `repo`'s `ast` filter and drivers at `exe`; returns the entries set.
+pub fn configure(repo: &Repository, exe: &Path) -> Result<Vec<(String, String)>, Error> {
+    let entries = driver_config(&shell_quote(&exe.display().to_string()));
+    let mut config = repo.config()?.open_level(git2::ConfigLevel::Local)?;
+    for (key, value) in &entries {
+        config.set_str(key, value)?;
+    }
+    Ok(entries
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect())
+}
+
+/// `text` as one word for the shell Git runs filter and driver commands
+/// with: single-quoted, embedded quotes closed, escaped and reopened.
+pub fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// Versions become paths and `cargo` arguments; allow only what releases use.
+pub(crate) fn validate_version(version: &str) -> Result<(), Error> {
+    let valid = !version.is_empty()
+        && version.starts_with(|c: char| c.is_ascii_digit())
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ".-+".contains(c));
+    match valid {
+        true => Ok(()),
+        false => Err(Error::Config(format!(
+            "{}: invalid git-ast version {:?}",
+            LOCKFILE, version
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plans_the_pinned_release_when_this_build_differs() {
+        let rust = languages::by_name("rust").unwrap();
+        let mut lock = Lockfile::for_languages([rust]);
+        let mut requirements = Requirements {
+            lock: Some(lock.clone()),
+            configured: ["python".to_string()].into(),
+        };
+        assert_eq!(plan(&requirements).unwrap(), Plan::Current);
+
+        lock.languages.get_mut("rust").unwrap().grammar = "0.1.0".to_string();
+        lock.git_ast = Some("0.0.9".to_string());
+        requirements.lock = Some(lock.clone());
+        assert_eq!(
+            plan(&requirements).unwrap(),
+            Plan::Install("0.0.9".to_string())
+        );
+
+        lock.git_ast = Some("../0.0.9".to_string());
+        requirements.lock = Some(lock.clone());
+        assert!(plan(&requirements).is_err());
+        lock.git_ast = None;
+        requirements.lock = Some(lock);
+        let error = plan(&requirements).unwrap_err().to_string();
+        assert!(error.contains("pins rust to grammar 0.1.0"), "{}", error);
+
+        requirements.lock = None;
+        requirements.configured.insert("cobol".to_string());
+        let error = plan(&requirements).unwrap_err().to_string();
+        assert!(error.contains("unsupported language cobol"), "{}", error);
+
+        let exe = toolchain_exe(Path::new("/cache"), "0.0.9");
+        assert!(exe.starts_with("/cache/toolchains/0.0.9/bin"));
+    }
+
+    #[test]
+    fn configures_drivers_for_paths_with_quotes() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let exe = Path::new("/opt/it's here/git-ast");
+        configure(&repo, exe).unwrap();
+        let config = repo.config().unwrap().snapshot().unwrap();
+        let filter = config.get_string("filter.ast.process").unwrap();
+        assert_eq!(filter, r"'/opt/it'\''s here/git-ast' filter-process");
+        // The shell reads it back as the one path.
+        #[cfg(unix)]
+        {
+            let echoed = Command::new("sh")
+                .args([
+                    "-c",
+                    &format!("printf %s {}", shell_quote(&exe.display().to_string())),
+                ])
+                .output()
+                .unwrap();
+            assert_eq!(echoed.stdout, exe.display().to_string().into_bytes());
+        }
+    }
+
+    #[test]
+    fn builds_releases_from_the_project_repository_only() {
+        let args = install_args("1.2.0", Path::new("/cache/toolchains/1.2.0"));
+        let args: Vec<_> = args.iter().map(|a| a.to_str().unwrap()).collect();
+        assert_eq!(
+            args,
+            [
+                "install",
+                "--locked",
+                "--git",
+                "https://github.com/bdelanghe/git-ast",
+                "--tag",
+                "v1.2.0",
+                "--root",
+                "/cache/toolchains/1.2.0",
+                "git-ast",
+            ]
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! Atomic File Writes
+//!
+//! Every file `git-ast` writes for someone else to read (the merge result
+//! Git takes from `%A`, files rewritten by `git-ast fmt`, merge records and
+//! sidecars, diff cache entries, snapshots, lists under `.git`) goes through
+//! this module. The content is written to a temporary file next to the
+//! target, flushed to disk, and renamed over the target, so readers and
+//! crashes see either the old file or the new one, never a truncated mix.
+//!
+//! The temporary file is removed if writing fails or the writer is dropped
+//! without [`AtomicFile::commit`], including while unwinding from a panic.
+//! A replaced file keeps its permissions (an executable script stays
+//! executable after `git-ast fmt`). A process killed before either is
+//! cleaned up after through its [journal](super::journal), if it keeps one.
+//!
+//! Files handed to another program (`git merge-file` reading the three
+//! versions of a file) go into a [`PrivateDir`] instead: a new directory
+//! only this user can enter, so nobody can plant a symlink where a
+//! predictable name in the shared temporary directory would be.
+//!
+//! `clippy.toml` disallows `std::fs::write` outside of tests, so new code
+//! cannot bypass this module by accident.
+
+use super::journal;
+use crate::Error;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Distinguishes temporary files of one process.
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Replaces `path` with `contents` atomically.
+pub fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<(), Error> {
+    let mut file = AtomicFile::create(path)?;
+    file.write_all(contents.as_ref())?;
+    file.commit()
+}
+
+/// A file being written in place of `target`; it replaces the target on
+/// [`commit`](AtomicFile::commit) and disappears if dropped before that.
+#[derive(Debug)]
+pub struct AtomicFile {
+    file: Option<File>,
+    tmp: PathBuf,
+    target: PathBuf,
+}
+
+impl AtomicFile {
+    /// Starts writing a replacement for `target`, creating its parent
+    /// directories if needed.
+    pub fn create(target: impl AsRef<Path>) -> Result<AtomicFile, Error> {
+        let target = target.as_ref().to_path_buf();
+        let dir = match target.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        fs::create_dir_all(&dir)?;
+        let name = target.file_name().unwrap_or_default().to_string_lossy();
+        let tmp = dir.join(format!(
+            ".{}.git-ast-{}-{}.tmp",
+            name,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        journal::acquire(&tmp);
+        let file = File::options().write(true).create_new(true).open(&tmp)?;
+        if let Ok(metadata) = fs::metadata(&target) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        Ok(AtomicFile {
+            file: Some(file),
+            tmp,
+            target,
+        })
+    }
+
+    /// Flushes the content to disk and renames it over the target.
+    pub fn commit(mut self) -> Result<(), Error> {
+        let file = self.file.take().expect("file is open until commit");
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&self.tmp, &self.target)?;
+        journal::release(&self.tmp);
+        // Make the rename itself durable.
+        #[cfg(unix)]
+        if let Some(dir) = self.target.parent().filter(|d| !d.as_os_str().is_empty()) {
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.file
+            .as_mut()
+            .expect("file is open until commit")
+            .write(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file
+            .as_mut()
+            .expect("file is open until commit")
+            .flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        // Not committed (an error or a panic): leave the target alone.
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.tmp);
+            journal::release(&self.tmp);
+        }
+    }
+}
+
+/// A directory of the process's own under the system's temporary
+/// directory, removed with its contents when dropped.
+#[derive(Debug)]
+pub struct PrivateDir(PathBuf);
+
+impl PrivateDir {
+    /// Creates a new directory named after `prefix`. Creation fails rather
+    /// than reuse a directory that already exists.
+    pub fn create(prefix: &str) -> Result<PrivateDir, Error> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+        let path = std::env::temp_dir().join(format!(
+            "{}-{}-{}-{:08x}",
+            prefix,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+            nanos
+        ));
+        let mut builder = fs::DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        builder.create(&path)?;
+        Ok(PrivateDir(path))
+    }
+
+    /// Writes `contents` to a new file `name` in the directory, creating
+    /// the subdirectories `name` has (`a/lib.rs`).
+    pub fn write(&self, name: impl AsRef<Path>, contents: &[u8]) -> Result<PathBuf, Error> {
+        let path = self.0.join(name);
+        if let Some(parent) = path.parent().filter(|p| *p != self.0) {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = File::options().write(true).create_new(true).open(&path)?;
+        file.write_all(contents)?;
+        Ok(path)
+    }
+}
+
+impl Drop for PrivateDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_whole_files_or_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("sub").join("a.rs");
+        write(&target, "fn a() {}\n").unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "fn a() {}\n");
+
+        let mut file = AtomicFile::create(&target).unwrap();
+        file.write_all(b"fn b(").unwrap();
+        drop(file);
+        assert_eq!(fs::read_to_string(&target).unwrap(), "fn a() {}\n");
+        let entries: Vec<_> = fs::read_dir(target.parent().unwrap()).unwrap().collect();
+        assert_eq!(entries.len(), 1, "temporary file left behind");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&target, fs::Permissions::from_mode(0o755)).unwrap();
+            write(&target, "fn b() {}\n").unwrap();
+            let mode = fs::metadata(&target).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o755);
+        }
+    }
+
+    #[test]
+    fn private_dirs_are_new_and_removed_when_dropped() {
+        let dir = PrivateDir::create("git-ast-test").unwrap();
+        let path = dir.write("ours", b"a\n").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"a\n");
+        assert!(dir.write("ours", b"b\n").is_err(), "overwrote a file");
+        let nested = dir.write(Path::new("a").join("lib.rs"), b"c\n").unwrap();
+        assert_eq!(nested.parent().unwrap().parent(), path.parent());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(path.parent().unwrap())
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+        drop(dir);
+        assert!(!path.parent().unwrap().exists());
+    }
+}
@@ -0,0 +1,191 @@
+//! Recovery Journal
+//!
+//! A filter process killed mid-checkout (`SIGKILL`, the OOM killer, a
+//! crashed Git) runs no destructors: the temporary files of its unfinished
+//! [atomic writes](super::atomic_io) stay behind, and so does the metrics
+//! lock if it held it, which makes every later flush wait and then fail. So
+//! the filter keeps a journal,
+//!
+//! ```text
+//! .git/ast-cache/journal/<pid>
+//! ```
+//!
+//! with a `+<path>` line before it creates a temporary file or lock and a
+//! `-<path>` line once the file is renamed or removed. A clean exit deletes
+//! the journal. A starting filter first replays the journals of processes
+//! that are no longer running: it deletes every path added and not released,
+//! then the journal.
+//!
+//! Only processes that [`start`] a journal record anything; short-lived
+//! commands clean up on their own.
+
+use crate::{log_info, Error};
+use git2::Repository;
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+static ACTIVE: Mutex<Option<Journal>> = Mutex::new(None);
+
+#[derive(Debug)]
+struct Journal {
+    path: PathBuf,
+    file: File,
+}
+
+/// Keeps this process's journal until dropped, which deletes it.
+#[derive(Debug)]
+pub struct JournalGuard(());
+
+impl Drop for JournalGuard {
+    fn drop(&mut self) {
+        let journal = ACTIVE.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(journal) = journal {
+            drop(journal.file);
+            let _ = fs::remove_file(journal.path);
+        }
+    }
+}
+
+/// The journal directory of `repo`.
+pub fn journal_dir(repo: &Repository) -> PathBuf {
+    repo.path().join("ast-cache").join("journal")
+}
+
+/// Recovers after dead processes, then starts this process's journal.
+pub fn start(repo: &Repository) -> Result<JournalGuard, Error> {
+    let dir = journal_dir(repo);
+    let removed = recover(&dir)?;
+    if !removed.is_empty() {
+        log_info!(
+            "journal",
+            "Removed {} file(s) left by crashed processes",
+            removed.len()
+        );
+    }
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(std::process::id().to_string());
+    let file = File::create(&path)?;
+    *ACTIVE.lock().unwrap_or_else(|e| e.into_inner()) = Some(Journal { path, file });
+    Ok(JournalGuard(()))
+}
+
+/// Notes that this process is about to create `path`.
+pub(crate) fn acquire(path: &Path) {
+    record('+', path);
+}
+
+/// Notes that `path` was renamed or removed.
+pub(crate) fn release(path: &Path) {
+    record('-', path);
+}
+
+fn record(op: char, path: &Path) {
+    let mut active = ACTIVE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(journal) = active.as_mut() {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        // A lost line only costs a leftover file; never fail the write.
+        let _ = writeln!(journal.file, "{}{}", op, path.display());
+    }
+}
+
+/// Replays the journals in `dir` of processes that are not running (or that
+/// ran under this process's id before), and returns the files it removed.
+pub fn recover(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut removed = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|n| n.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        if pid != std::process::id() && is_running(pid) {
+            continue;
+        }
+        let text = fs::read_to_string(entry.path())?;
+        let mut outstanding = BTreeSet::new();
+        for line in text.lines() {
+            match line.split_at_checked(1) {
+                Some(("+", path)) => outstanding.insert(PathBuf::from(path)),
+                Some(("-", path)) => outstanding.remove(Path::new(path)),
+                _ => false,
+            };
+        }
+        for path in outstanding {
+            match fs::remove_file(&path) {
+                Ok(()) => removed.push(path),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        fs::remove_file(entry.path())?;
+    }
+    Ok(removed)
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks that the process exists; EPERM means it does.
+    pid > 0
+        && (unsafe { libc::kill(pid, 0) } == 0
+            || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM))
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    // Without a cheap check, only this process's own old journal is replayed.
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_what_dead_processes_left_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let journals = dir.path().join("journal");
+        fs::create_dir(&journals).unwrap();
+        let tmp = dir.path().join(".a.git-ast-1-0.tmp");
+        let lock = dir.path().join("metrics.lock");
+        let released = dir.path().join(".b.git-ast-1-1.tmp");
+        for path in [&tmp, &lock, &released] {
+            fs::write(path, "").unwrap();
+        }
+        // Beyond Linux's largest process id, so never running.
+        let dead = format!(
+            "+{}\n+{}\n+{}\n-{}\n",
+            tmp.display(),
+            lock.display(),
+            released.display(),
+            released.display()
+        );
+        fs::write(journals.join("4194305"), dead).unwrap();
+        let live = format!("+{}\n", released.display());
+        fs::write(journals.join(std::process::id().to_string()), "").unwrap();
+        fs::write(journals.join("1"), live).unwrap();
+
+        let mut removed = recover(&journals).unwrap();
+        removed.sort();
+        assert_eq!(removed, [tmp.clone(), lock.clone()]);
+        assert!(released.exists());
+        let left: Vec<_> = fs::read_dir(&journals)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(left, ["1"], "journal of a running process replayed");
+    }
+}
@@ -0,0 +1,6 @@
+//! Small helpers shared by several modules.
+
+pub mod atomic_io;
+pub mod journal;
+pub mod parallel;
+pub mod shutdown;
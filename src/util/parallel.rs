@@ -0,0 +1,62 @@
+//! Parallel Work Over a Repository
+//!
+//! Rendering a large diff or range is parsing and printing one file after
+//! another, each independent of the rest. [`map`] spreads such work over
+//! threads. A [`Repository`] cannot be shared between threads, so each
+//! worker opens its own on the same Git directory; results come back in
+//! input order, so the output does not depend on scheduling.
+
+use crate::Error;
+use git2::Repository;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// The number of threads to use by default: one per available CPU.
+pub fn jobs() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// `f` applied to each of `inputs` on up to [`jobs`] threads, in input
+/// order; the first error (by input) wins. A single input is handled on
+/// the calling thread with `repo`.
+pub fn map<T, R>(
+    repo: &Repository,
+    inputs: &[T],
+    f: impl Fn(&Repository, &T) -> Result<R, Error> + Sync,
+) -> Result<Vec<R>, Error>
+where
+    T: Sync,
+    R: Send,
+{
+    let jobs = jobs().min(inputs.len());
+    if jobs <= 1 {
+        return inputs.iter().map(|input| f(repo, input)).collect();
+    }
+    let git_dir = repo.path().to_path_buf();
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<(usize, Result<R, Error>)>> = Mutex::default();
+    let worker = || -> Result<(), Error> {
+        let repo = Repository::open(&git_dir)?;
+        loop {
+            let i = next.fetch_add(1, Ordering::Relaxed);
+            let Some(input) = inputs.get(i) else {
+                return Ok(());
+            };
+            let result = f(&repo, input);
+            results.lock().expect("parallel lock").push((i, result));
+        }
+    };
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs).map(|_| scope.spawn(worker)).collect();
+        workers
+            .into_iter()
+            .map(|w| {
+                w.join()
+                    .unwrap_or_else(|_| Err(Error::Generation("worker panicked".to_string())))
+            })
+            .collect::<Result<Vec<()>, Error>>()
+    })?;
+    let mut results = results.into_inner().expect("parallel lock");
+    results.sort_by_key(|&(i, _)| i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
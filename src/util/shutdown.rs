@@ -0,0 +1,60 @@
+//! Graceful Shutdown
+//!
+//! By default `SIGTERM`, `SIGINT` and `SIGHUP` end a process wherever it
+//! is, without flushing metrics, releasing locks or deleting the
+//! [journal](super::journal). [`install`] makes them set a flag instead,
+//! which the filter process checks between requests, so it finishes the file
+//! at hand and leaves through its normal exit path. A read blocked on Git is
+//! abandoned: [`Interruptible`] turns the interrupted read into an error.
+//! A second signal is handled the same way; `SIGKILL` is left to the
+//! journal.
+
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+/// Handles termination signals by recording them (see [`requested`]).
+pub fn install() {
+    #[cfg(unix)]
+    for signal in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+        // SAFETY: the handler only stores to an atomic, which is
+        // async-signal-safe. Without `SA_RESTART`, blocking reads return
+        // `EINTR` so that the flag is seen.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn handle(signal: libc::c_int) {
+    SIGNAL.store(signal, Ordering::SeqCst);
+}
+
+/// The signal that asked this process to stop, if any.
+pub fn requested() -> Option<i32> {
+    match SIGNAL.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
+
+/// A reader whose reads fail, rather than being retried, once interrupted
+/// by a termination signal.
+#[derive(Debug)]
+pub struct Interruptible<R>(pub R);
+
+impl<R: Read> Read for Interruptible<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted && requested().is_some() => {
+                Err(io::Error::other("terminated by a signal"))
+            }
+            result => result,
+        }
+    }
+}
@@ -0,0 +1,68 @@
+using System;
+using System.Collections.Generic;
+using System.Linq;
+using System.Text.RegularExpressions;
+using Shop;
+
+#nullable enable
+
+var store = new Inventory();
+store.Add(new Item("A-100", 3, null));
+store.Add(new Item("B-200", 0, "backorder"));
+foreach (var (sku, count) in store.Counts())
+{
+    Console.WriteLine($"{sku}: {count,4}");
+}
+return store.IsEmpty ? 1 : 0;
+
+namespace Shop
+{
+    public record Item(string Sku, int Quantity, string? Note);
+
+    public sealed record Discount
+    {
+        public required decimal Rate { get; init; }
+        public string Label => $"{Rate:P0} off";
+    }
+
+    [Serializable]
+    public class Inventory : IDisposable
+    {
+        private static readonly Regex SkuPattern = new Regex(@"^[A-Z]-\d{3}$");
+        private readonly Dictionary<string, List<Item>> _items = new();
+        private int[]? _buckets;
+
+        public bool IsEmpty => _items.Count == 0;
+
+        public event EventHandler<Item>? Added;
+
+        public void Add(Item item)
+        {
+            if (!SkuPattern.IsMatch(item.Sku))
+            {
+                throw new ArgumentException($"bad sku {item.Sku}", nameof(item));
+            }
+            _items.TryAdd(item.Sku, new List<Item>());
+            _items[item.Sku].Add(item);
+            _buckets ??= new int[16];
+            _buckets[item.Quantity % _buckets.Length]++;
+            Added?.Invoke(this, item);
+        }
+
+        public IEnumerable<(string Sku, int Count)> Counts() =>
+            _items.Select(kv => (kv.Key, kv.Value.Sum(i => i.Quantity)));
+
+        public string Describe(Item item) => item switch
+        {
+            { Quantity: 0 } => "out of stock",
+            { Note: not null } n => n.Note!,
+            _ => "in stock",
+        };
+
+        // Disposal is a no-op; kept for using-declarations.
+        public void Dispose()
+        {
+            _items.Clear();
+        }
+    }
+}
@@ -0,0 +1,69 @@
+package com.example.orders;
+
+import java.util.*;
+import java.util.function.Function;
+import java.util.regex.Pattern;
+import java.util.stream.Collectors;
+
+/**
+ * Groups and prices orders for the nightly report.
+ */
+public final class OrderService<K extends Comparable<K>> {
+    private static final Pattern SKU = Pattern.compile("[A-Z]{2}-\\d{4}");
+
+    public record Line(String sku, int quantity, long unitCents) {
+        public Line {
+            if (quantity <= 0) throw new IllegalArgumentException("quantity");
+        }
+
+        long totalCents() { return quantity * unitCents; }
+    }
+
+    public enum Status {
+        OPEN("o"), SHIPPED("s"), CANCELLED("c");
+
+        private final String code;
+
+        Status(String code) {
+            this.code = code;
+        }
+    }
+
+    private final Map<K, List<Line>> linesByKey = new HashMap<>();
+    private final int[] buckets = new int[16];
+
+    @SuppressWarnings("unchecked")
+    public void add(K key, Line... lines) {
+        for (Line line : lines) {
+            if (!SKU.matcher(line.sku()).matches()) {
+                continue; // skip malformed SKUs
+            }
+            linesByKey.computeIfAbsent(key, k -> new ArrayList<>()).add(line);
+            buckets[line.quantity() % buckets.length]++;
+        }
+    }
+
+    @Override
+    public String toString() {
+        return linesByKey.entrySet().stream()
+            .sorted(Map.Entry.comparingByKey())
+            .map(e -> e.getKey() + "=" + e.getValue().size())
+            .collect(Collectors.joining(", ", "{", "}"));
+    }
+
+    static String describe(Status status) {
+        return switch (status) {
+            case OPEN -> "open";
+            case SHIPPED, CANCELLED -> {
+                String s = status.name().toLowerCase();
+                yield s;
+            }
+        };
+    }
+
+    <T> List<T> project(Function<Line, T> f) {
+        List<T> out = new ArrayList<>();
+        linesByKey.values().forEach(ls -> ls.forEach(l -> out.add(f.apply(l))));
+        return out;
+    }
+}
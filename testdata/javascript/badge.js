@@ -0,0 +1,21 @@
+// Renders a small status badge; used by the dashboard widgets.
+import { html, css } from "./templating.js";
+
+const SLUG = /^[a-z0-9]+(?:-[a-z0-9]+)*$/;
+
+export const styles = css`
+  .badge { padding: 2px 6px; border-radius: 3px; }
+`;
+
+export function badge(label, status = "ok") {
+  if (!SLUG.test(status)) {
+    throw new Error(`invalid status: ${status}`);
+  }
+  return html`<span class="badge badge-${status}">${label}</span>`;
+}
+
+export default {
+  render(items) {
+    return items.map((item) => badge(item.label, item.status)).join("");
+  },
+};
@@ -0,0 +1,74 @@
+<?php
+
+declare(strict_types=1);
+
+namespace App\Billing;
+
+use App\Models\{Customer, Order};
+use InvalidArgumentException;
+
+/**
+ * Renders invoices for a customer's orders.
+ */
+final class Invoice implements \JsonSerializable
+{
+    private const SKU = '/^[A-Z]-\d{3}$/';
+
+    /** @var array<string, Order[]> */
+    private array $lines = [];
+
+    public function __construct(
+        private readonly Customer $customer,
+        private ?string $note = null,
+    ) {
+    }
+
+    public function add(Order ...$orders): static
+    {
+        foreach ($orders as $i => $order) {
+            if (!preg_match(self::SKU, $order->sku)) {
+                throw new InvalidArgumentException("bad sku {$order->sku} at #$i");
+            }
+            $this->lines[$order->sku][] = $order;
+        }
+        return $this;
+    }
+
+    public function total(): float
+    {
+        return array_sum(array_map(fn(Order $o) => $o->price * $o->quantity, $this->all()));
+    }
+
+    public function status(): string
+    {
+        return match (true) {
+            $this->total() === 0.0 => 'empty',
+            $this->total() < 100 => 'small',
+            default => 'large',
+        };
+    }
+
+    public function render(): string
+    {
+        $name = $this->customer?->name ?? 'unknown';
+        $body = <<<TEXT
+            Invoice for {$name}
+              total: {$this->total()}
+            TEXT;
+        $footer = <<<'RAW'
+            Prices in $USD, no interpolation here.
+            RAW;
+        return $body . "\n" . $footer;
+    }
+
+    /** @return Order[] */
+    private function all(): array
+    {
+        return array_merge(...array_values($this->lines));
+    }
+
+    public function jsonSerialize(): mixed
+    {
+        return ['customer' => $this->customer->id, 'total' => $this->total(), 'note' => $this->note];
+    }
+}
@@ -0,0 +1,38 @@
+"""Simple inventory tracking used by the warehouse scripts."""
+
+from dataclasses import dataclass, field
+from typing import Dict, Iterable
+
+
+@dataclass
+class Item:
+    sku: str
+    name: str
+    quantity: int = 0
+    tags: list = field(default_factory=list)
+
+    def restock(self, amount: int) -> None:
+        if amount <= 0:
+            raise ValueError(f"restock amount must be positive, got {amount}")
+        self.quantity += amount
+
+
+class Inventory:
+    def __init__(self, items: Iterable[Item] = ()):
+        self._items: Dict[str, Item] = {item.sku: item for item in items}
+
+    def __len__(self):
+        return len(self._items)
+
+    def low_stock(self, threshold=5):
+        # Sorted so reports are stable between runs.
+        return sorted(
+            (i for i in self._items.values() if i.quantity < threshold),
+            key=lambda i: (i.quantity, i.sku),
+        )
+
+
+if __name__ == "__main__":
+    inv = Inventory([Item("A-1", "bolt", 3), Item("B-2", "nut", 40)])
+    for item in inv.low_stock():
+        print(f"{item.sku}: {item.name} ({item.quantity} left)")
@@ -0,0 +1,59 @@
+# frozen_string_literal: true
+
+require "json"
+
+module Reports
+  # Renders a summary of orders.
+  class Summary
+    attr_reader :orders, :title
+
+    SKU = /\A[A-Z]-\d{3}\z/
+
+    def initialize(orders, title: "Weekly")
+      @orders = orders.select { |o| o[:sku] =~ SKU }
+      @title = title
+    end
+
+    def total
+      orders.sum { |o| o[:quantity] * o.fetch(:price, 0) }
+    end
+
+    def empty? = orders.empty?
+
+    def each_line
+      return enum_for(:each_line) unless block_given?
+
+      orders.each_with_index do |order, i|
+        yield "#{i + 1}. #{order[:sku]} x#{order[:quantity]}"
+      end
+    end
+
+    def to_s
+      header = <<~TEXT.strip
+        #{title} report
+          generated #{Time.now.utc}
+      TEXT
+      lines = each_line.map { |l| "  " + l }
+      [header, *lines, "total: %.2f" % total].join("\n")
+    end
+
+    def status
+      case total
+      when 0 then :empty
+      when 1..100
+        :small
+      else
+        :large
+      end
+    end
+
+    private
+
+    def to_json(*args) = { title: title, total: total }.to_json(*args)
+  end
+end
+
+if __FILE__ == $PROGRAM_NAME
+  summary = Reports::Summary.new([{ sku: "A-100", quantity: 2, price: 1.5 }])
+  puts summary unless summary.empty?
+end
@@ -0,0 +1,70 @@
+//! Loads layered settings from disk.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A single configuration layer.
+#[derive(Debug, Clone, Default)]
+pub struct Layer {
+    pub origin: PathBuf,
+    pub values: BTreeMap<String, String>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    Syntax { line: usize, message: String },
+}
+
+impl From<std::io::Error> for LoadError {
+    fn from(e: std::io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl Layer {
+    /// Parses `key = value` lines, ignoring blanks and `#` comments.
+    pub fn load(path: &Path) -> Result<Self, LoadError> {
+        let text = fs::read_to_string(path)?;
+        let mut values = BTreeMap::new();
+        for (idx, raw) in text.lines().enumerate() {
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(LoadError::Syntax {
+                    line: idx + 1,
+                    message: format!("expected `key = value`, found {:?}", line),
+                });
+            };
+            values.insert(key.trim().to_string(), value.trim().to_string());
+        }
+        Ok(Layer { origin: path.to_path_buf(), values })
+    }
+}
+
+pub fn merge<'a>(layers: impl IntoIterator<Item = &'a Layer>) -> BTreeMap<&'a str, &'a str> {
+    let mut merged = BTreeMap::new();
+    for layer in layers {
+        for (k, v) in &layer.values {
+            merged.insert(k.as_str(), v.as_str());
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn later_layers_win() {
+        let mut a = Layer::default();
+        a.values.insert("x".into(), "1".into());
+        let mut b = Layer::default();
+        b.values.insert("x".into(), "2".into());
+        assert_eq!(merge([&a, &b])["x"], "2");
+    }
+}
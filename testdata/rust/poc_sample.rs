@@ -0,0 +1,11 @@
+fn add(a: i32, b: i32) -> i32 {
+    // Simple addition
+    a + b
+}
+
+fn main() {
+    let x = 5;
+    let y = 10;
+    let sum = add(x, y);
+    println!("Sum: {}", sum);
+} 
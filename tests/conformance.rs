@@ -0,0 +1,30 @@
+use git_ast::conformance;
+use git_ast::parsing::ParseLimits;
+use std::path::Path;
+
+#[test]
+fn golden_corpus_conforms() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+    let report = conformance::run(&[root], 8, 0, &ParseLimits::default()).expect("conformance run");
+    assert!(report.skipped.is_empty(), "skipped: {:?}", report.skipped);
+    assert!(
+        report.uncovered.is_empty(),
+        "no corpus files for {:?}",
+        report.uncovered
+    );
+    let details: Vec<String> = report
+        .violations
+        .iter()
+        .map(|v| {
+            format!(
+                "{}: {} {:?}\n{}\nreproducer:\n{}",
+                v.path.display(),
+                v.failure.property,
+                v.failure.profile,
+                v.failure.detail,
+                v.reproducer
+            )
+        })
+        .collect();
+    assert!(report.violations.is_empty(), "{}", details.join("\n"));
+}
@@ -0,0 +1,490 @@
+//! The filter and drivers under real `git add`, `checkout`, `diff` and
+//! `merge` (see `tests/testsupport`).
+
+// Fixtures are written with plain `std::fs::write`; see clippy.toml.
+#![allow(clippy::disallowed_methods)]
+
+mod testsupport;
+
+use testsupport::TestRepo;
+
+const MESSY: &str = "fn  add(a:i32,b:i32)->i32{a+b}\n";
+
+#[test]
+fn add_stores_trees_and_checkout_prints_canonical_source() {
+    let repo = TestRepo::new();
+    repo.write("src/lib.rs", MESSY);
+    repo.commit_all("add");
+    let blob = repo.blob("HEAD:src/lib.rs");
+    assert!(blob.starts_with("git-ast 1\nlanguage rust\n"), "{}", blob);
+
+    std::fs::remove_file(repo.path().join("src/lib.rs")).unwrap();
+    repo.git(&["checkout", "--", "src/lib.rs"]);
+    let canonical = repo.read("src/lib.rs");
+    assert_ne!(canonical, MESSY);
+    assert!(
+        canonical.contains("fn add(a: i32, b: i32) -> i32"),
+        "{}",
+        canonical
+    );
+}
+
+#[test]
+fn single_shot_filter_form_stores_and_prints_the_same() {
+    let repo = TestRepo::new();
+    let bin = format!("'{}'", testsupport::git_ast_bin());
+    repo.git(&["config", "--unset", "filter.ast.process"]);
+    repo.config("filter.ast.clean", &format!("{} clean %f", bin));
+    repo.config("filter.ast.smudge", &format!("{} smudge %f", bin));
+    repo.write("src/lib.rs", MESSY);
+    repo.commit_all("add");
+    let blob = repo.blob("HEAD:src/lib.rs");
+    assert!(blob.starts_with("git-ast 1\nlanguage rust\n"), "{}", blob);
+
+    std::fs::remove_file(repo.path().join("src/lib.rs")).unwrap();
+    repo.git(&["checkout", "--", "src/lib.rs"]);
+    assert!(repo
+        .read("src/lib.rs")
+        .contains("fn add(a: i32, b: i32) -> i32"));
+
+    repo.write("src/lib.rs", "fn add(a: i32 {\n");
+    assert!(!repo.try_git(&["add", "src/lib.rs"]).status.success());
+}
+
+#[test]
+fn fmt_rewrites_regular_files_as_checkout_would() {
+    let repo = TestRepo::new();
+    repo.write("lib.rs", MESSY);
+    repo.commit_all("add");
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink("lib.rs", repo.path().join("link.rs")).unwrap();
+        repo.commit_all("link");
+    }
+
+    let check = repo.try_git_ast(&["fmt", "--check"]);
+    assert!(!check.status.success());
+    assert_eq!(String::from_utf8_lossy(&check.stdout), "lib.rs\n");
+    assert_eq!(repo.git_ast(&["fmt"]), "lib.rs\n");
+    assert_eq!(
+        repo.read("lib.rs"),
+        "fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n"
+    );
+    #[cfg(unix)]
+    assert!(std::fs::symlink_metadata(repo.path().join("link.rs"))
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!(repo.git_ast(&["fmt", "--check"]), "");
+
+    // With hooks, the file is what checkout writes, header included.
+    repo.config("ast.rust.postSmudge", "sed '1i // generated'");
+    repo.config("ast.rust.preClean", "sed '1d'");
+    std::fs::remove_file(repo.path().join("lib.rs")).unwrap();
+    repo.git(&["checkout", "--", "lib.rs"]);
+    let checked_out = repo.read("lib.rs");
+    assert!(checked_out.starts_with("// generated\n"), "{}", checked_out);
+    repo.write("lib.rs", &format!("// generated\n{}", MESSY));
+    assert_eq!(repo.git_ast(&["fmt"]), "lib.rs\n");
+    assert_eq!(repo.read("lib.rs"), checked_out);
+}
+
+#[test]
+fn diff_ignores_formatting_and_shows_changes() {
+    let repo = TestRepo::new();
+    repo.write("lib.rs", MESSY);
+    repo.commit_all("add");
+
+    repo.write("lib.rs", "fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n");
+    repo.git(&["diff", "--exit-code"]);
+
+    repo.write("lib.rs", "fn add(a: i32, b: i32) -> i32 {\n    a - b\n}\n");
+    let diff = repo.git(&["diff"]);
+    assert!(diff.contains("a - b"), "{}", diff);
+}
+
+#[test]
+fn diff_command_compares_worktree_index_and_revisions() {
+    let repo = TestRepo::new();
+    repo.write("lib.rs", MESSY);
+    repo.commit_all("add");
+    repo.write("lib.rs", "fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n");
+    assert_eq!(repo.git_ast(&["diff"]), "");
+
+    repo.write(
+        "lib.rs",
+        "fn add(a:i32,b:i32)->i32{a-b}\nfn one()->i32{1}\n",
+    );
+    let worktree = repo.git_ast(&["diff"]);
+    assert!(
+        worktree.starts_with(" M    lib.rs: changed add; added one\n"),
+        "{}",
+        worktree
+    );
+    assert!(
+        worktree.contains("-    a + b\n+    a - b\n"),
+        "{}",
+        worktree
+    );
+    assert_eq!(repo.git_ast(&["diff", "HEAD"]), worktree);
+    assert_eq!(repo.git_ast(&["diff", "--staged"]), "");
+
+    repo.git(&["add", "lib.rs"]);
+    assert_eq!(repo.git_ast(&["diff"]), "");
+    assert_eq!(repo.git_ast(&["diff", "--staged"]), worktree);
+    repo.commit_all("subtract");
+    assert_eq!(repo.git_ast(&["diff", "HEAD~..HEAD"]), worktree);
+    assert_eq!(
+        repo.git_ast(&["diff", "HEAD~", "HEAD", "--", "other.rs"]),
+        ""
+    );
+    let patch = repo.git_ast(&["diff", "HEAD~", "--format", "patch"]);
+    assert!(
+        patch.contains("diff --git a/lib.rs b/lib.rs\n--- a/lib.rs\n"),
+        "{}",
+        patch
+    );
+}
+
+#[test]
+fn diff_command_pairs_identical_renames_and_summarizes_directories() {
+    let repo = TestRepo::new();
+    repo.write("lib.rs", MESSY);
+    repo.write("src/a.rs", "fn a() {}\n");
+    repo.commit_all("add");
+    repo.git(&["mv", "lib.rs", "src/lib.rs"]);
+    repo.write("src/lib.rs", "fn add(a: i32, b: i32) -> i32 { a + b }\n");
+    let renamed = repo.git_ast(&["diff", "HEAD"]);
+    assert!(
+        renamed.starts_with(" R100 lib.rs -> src/lib.rs\n"),
+        "{}",
+        renamed
+    );
+    assert!(
+        renamed.contains("rename from lib.rs\nrename to src/lib.rs\n"),
+        "{}",
+        renamed
+    );
+    assert_eq!(repo.git_ast(&["diff"]), "");
+
+    repo.write("src/a.rs", "fn a() {}\nfn b() {}\nfn c() {}\n");
+    repo.write("src/b/c.rs", "struct C;\n");
+    repo.commit_all("more");
+    assert_eq!(
+        repo.git_ast(&["diff", "--directories", "HEAD~..HEAD"]),
+        " src/: 2 files; items 2 added\n src/b/: 1 file; items 1 added\n"
+    );
+}
+
+#[test]
+fn diff_shows_mode_changes_and_unmerged_paths() {
+    use std::os::unix::fs::PermissionsExt;
+    let repo = TestRepo::new();
+    repo.write("lib.rs", MESSY);
+    repo.commit_all("add");
+
+    let file = repo.path().join("lib.rs");
+    std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let diff = repo.git(&["diff"]);
+    assert_eq!(diff, "old mode 100644\nnew mode 100755\n");
+    repo.commit_all("chmod");
+
+    repo.git(&["rm", "--quiet", "lib.rs"]);
+    let diff = repo.git(&["diff", "--cached"]);
+    assert!(diff.starts_with("deleted file mode 100755\n"), "{}", diff);
+    assert!(diff.contains("-fn add(a: i32, b: i32) -> i32"), "{}", diff);
+}
+
+#[test]
+fn diff_scores_renames_structurally() {
+    let repo = TestRepo::new();
+    repo.write(
+        "lib.rs",
+        "fn one() -> i32 {\n    1\n}\n\nfn two() -> i32 {\n    2\n}\n",
+    );
+    repo.commit_all("add");
+
+    repo.git(&["mv", "lib.rs", "util.rs"]);
+    repo.write("util.rs", "fn one()->i32{1}\nfn two()->i32{3}\n");
+    repo.git(&["add", "util.rs"]);
+    let diff = repo.git(&["diff", "--cached", "-M10%"]);
+    assert!(
+        diff.starts_with("similarity index 94%\nrename from lib.rs\nrename to util.rs\n"),
+        "{}",
+        diff
+    );
+    assert!(diff.contains("-    2\n+    3\n"), "{}", diff);
+}
+
+#[test]
+fn merge_driver_combines_edits_to_different_items() {
+    let repo = TestRepo::new();
+    repo.write(
+        "lib.rs",
+        "fn one() -> i32 {\n    1\n}\n\nfn two() -> i32 {\n    2\n}\n",
+    );
+    repo.commit_all("base");
+    repo.git(&["checkout", "--quiet", "-b", "topic"]);
+    repo.write(
+        "lib.rs",
+        "fn one() -> i32 {\n    11\n}\n\nfn two() -> i32 {\n    2\n}\n",
+    );
+    repo.commit_all("topic");
+    repo.git(&["checkout", "--quiet", "main"]);
+    repo.write(
+        "lib.rs",
+        "fn one() -> i32 {\n    1\n}\n\nfn two() -> i32 {\n    22\n}\n",
+    );
+    repo.commit_all("main");
+
+    let output = repo.try_git(&["merge", "--no-edit", "topic"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    // The filter and drivers only log errors unless asked to.
+    assert!(!stderr.contains("[driver]"), "{}", stderr);
+    assert!(!stderr.contains("[filter]"), "{}", stderr);
+    let merged = repo.read("lib.rs");
+    assert!(merged.contains("11") && merged.contains("22"), "{}", merged);
+
+    repo.config("ast.logLevel", "info");
+    let output = repo.try_git(&["diff", "HEAD~", "--", "lib.rs"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("[driver] Running diff driver"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn merge_driver_leaves_markers_on_conflict() {
+    let repo = TestRepo::new();
+    repo.write("lib.rs", "fn one() {}\n");
+    repo.commit_all("base");
+    repo.git(&["checkout", "--quiet", "-b", "topic"]);
+    repo.write("lib.rs", "fn one() {}\n\nfn two() {}\n");
+    repo.commit_all("topic");
+    repo.git(&["checkout", "--quiet", "main"]);
+    repo.write("lib.rs", "fn one() {}\n\nfn three() {}\n");
+    repo.commit_all("main");
+
+    assert!(!repo
+        .try_git(&["merge", "--no-edit", "topic"])
+        .status
+        .success());
+    let merged = repo.read("lib.rs");
+    assert!(merged.contains("<<<<<<< main\n"), "{}", merged);
+    assert!(merged.contains(">>>>>>> topic\n"), "{}", merged);
+    assert!(repo.git(&["ls-files", "--unmerged"]).contains("lib.rs"));
+    assert_eq!(repo.git(&["diff", "--cached"]), "* Unmerged path lib.rs\n");
+}
+
+#[test]
+fn merge_driver_explains_and_dry_runs_without_touching_files() {
+    let repo = TestRepo::new();
+    let version = |a: i32, b: i32| {
+        format!("fn a() -> i32 {{\n    {a}\n}}\n\nfn b() -> i32 {{\n    {b}\n}}\n")
+    };
+    repo.write("base.rs", &version(1, 1));
+    repo.write("ours.rs", &version(2, 1));
+    repo.write("theirs.rs", &version(1, 3));
+    let args = ["base.rs", "ours.rs", "theirs.rs", "7", "lib.rs"];
+
+    let explained = repo.git_ast(&[&["merge-driver", "--explain"][..], &args].concat());
+    assert_eq!(
+        explained,
+        "merging lib.rs as rust (line markers, merge style)\n\
+         base line 2 in a: only ours changed: took ours line 2\n\
+         base line 6 in b: only theirs changed: took theirs line 6\n\
+         result: clean\n"
+    );
+    let merged = repo.git_ast(&[&["merge-driver", "--dry-run"][..], &args].concat());
+    assert_eq!(merged, version(2, 3));
+    assert_eq!(repo.read("ours.rs"), version(2, 1));
+}
+
+#[test]
+fn merge_driver_falls_back_to_text_merge_over_budget() {
+    let repo = TestRepo::new();
+    repo.config("ast.mergeMaxConflicts", "1");
+    repo.config("ast.mergeSidecars", "true");
+    let version =
+        |n: i32| format!("fn a() -> i32 {{\n    {n}\n}}\n\nfn b() -> i32 {{\n    {n}\n}}\n");
+    repo.write("lib.rs", &version(1));
+    repo.commit_all("base");
+    repo.git(&["checkout", "--quiet", "-b", "topic"]);
+    repo.write("lib.rs", &version(2));
+    repo.commit_all("topic");
+    repo.git(&["checkout", "--quiet", "main"]);
+    repo.write("lib.rs", &version(3));
+    repo.commit_all("main");
+
+    assert!(!repo
+        .try_git(&["merge", "--no-edit", "topic"])
+        .status
+        .success());
+    let merged = repo.read("lib.rs");
+    assert!(merged.contains("<<<<<<< main\n"), "{}", merged);
+    let report = repo.read("lib.rs.conflict.json");
+    assert!(
+        report.contains("over ast.mergeMaxConflicts = 1"),
+        "{}",
+        report
+    );
+}
+
+#[test]
+fn remote_helper_pushes_source_and_fetches_source_commits_as_trees() {
+    let repo = TestRepo::new();
+    repo.write("lib.rs", MESSY);
+    repo.commit_all("add");
+    let source = tempfile::tempdir().unwrap();
+    let bare = source.path().join("source.git");
+    let bare = bare.to_str().unwrap();
+    repo.git(&["init", "--quiet", "--bare", "--initial-branch=main", bare]);
+    repo.git(&["remote", "add", "source", &format!("ast::{}", bare)]);
+
+    repo.git(&["push", "--quiet", "source", "main"]);
+    let pushed = repo.git(&["--git-dir", bare, "cat-file", "blob", "main:lib.rs"]);
+    assert!(
+        pushed.starts_with("fn add(a: i32, b: i32) -> i32"),
+        "{}",
+        pushed
+    );
+
+    // Someone commits plain source to the source repository.
+    let clone = source.path().join("clone");
+    let clone = clone.to_str().unwrap();
+    repo.git(&["clone", "--quiet", bare, clone]);
+    std::fs::write(
+        source.path().join("clone/lib.rs"),
+        "fn add(a: i32, b: i32) -> i32 { b + a }\n",
+    )
+    .unwrap();
+    repo.git(&[
+        "-C",
+        clone,
+        "commit",
+        "--quiet",
+        "--all",
+        "--message",
+        "swap",
+    ]);
+    repo.git(&["-C", clone, "push", "--quiet", "origin", "main"]);
+
+    repo.git(&["fetch", "--quiet", "source"]);
+    let fetched = repo.blob("source/main:lib.rs");
+    assert!(
+        fetched.starts_with("git-ast 1\nlanguage rust\n"),
+        "{}",
+        fetched
+    );
+    // The pushed commit maps back to the original.
+    assert_eq!(
+        repo.git(&["rev-parse", "source/main~1"]),
+        repo.git(&["rev-parse", "main"])
+    );
+    repo.git(&["merge", "--quiet", "--ff-only", "source/main"]);
+    assert!(repo.read("lib.rs").contains("b + a"));
+}
+
+#[test]
+fn lockfile_pins_grammar_for_the_filter() {
+    let repo = TestRepo::new();
+    repo.write("lib.rs", MESSY);
+    repo.commit_all("add");
+    repo.git_ast(&["lock"]);
+    assert!(repo.read(".git-ast.lock").contains("[languages.rust]"));
+    repo.git_ast(&["lock", "--check"]);
+
+    let lock = repo.read(".git-ast.lock");
+    let pinned = lock
+        .lines()
+        .find(|line| line.starts_with("grammar = "))
+        .unwrap();
+    repo.write(
+        ".git-ast.lock",
+        &lock.replace(pinned, "grammar = \"0.1.0\""),
+    );
+    repo.write("lib.rs", "fn sub(a: i32, b: i32) -> i32 {\n    a - b\n}\n");
+    let output = repo.try_git(&["add", "lib.rs"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("pins rust to grammar 0.1.0"), "{}", stderr);
+}
+
+#[test]
+fn archive_smudges_files_without_a_configured_filter() {
+    let repo = TestRepo::new();
+    repo.write("src/lib.rs", MESSY);
+    repo.commit_all("add");
+    // As in a fresh clone or on a build machine.
+    repo.git(&["config", "--remove-section", "filter.ast"]);
+
+    repo.git(&["archive", "--format=tar", "-o", "plain.tar", "HEAD"]);
+    let plain = std::fs::read(repo.path().join("plain.tar")).unwrap();
+    assert!(String::from_utf8_lossy(&plain).contains("git-ast 1\nlanguage rust\n"));
+
+    repo.git_ast(&["archive", "--format=tar", "-o", "source.tar", "HEAD"]);
+    let source = std::fs::read(repo.path().join("source.tar")).unwrap();
+    let source = String::from_utf8_lossy(&source);
+    assert!(!source.contains("git-ast 1\n"));
+    assert!(
+        source.contains("fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n"),
+        "{}",
+        source
+    );
+}
+
+#[test]
+fn hermetic_mode_ignores_the_users_git_config() {
+    let repo = TestRepo::new();
+    repo.write("user.gitconfig", "[ast]\n\tconflictMarkers = node\n");
+    let global = repo.path().join("user.gitconfig");
+    let get = ["config", "get", "--show-origin", "conflictMarkers"];
+    let user = [
+        ("GIT_CONFIG_GLOBAL", global.to_str().unwrap()),
+        ("GIT_AST_NO_USER_CONFIG", "0"),
+    ];
+
+    assert_eq!(repo.git_ast_with_env(&user, &get), "global\tnode\n");
+    let hermetic = [("GIT_CONFIG_GLOBAL", global.to_str().unwrap())];
+    assert_eq!(repo.git_ast_with_env(&hermetic, &get), "default\tline\n");
+    let flag = [&["--no-user-config"][..], &get].concat();
+    assert_eq!(repo.git_ast_with_env(&user, &flag), "default\tline\n");
+}
+
+#[test]
+fn init_template_sets_new_repositories_up() {
+    let repo = TestRepo::new();
+    let bin = format!("'{}'", testsupport::git_ast_bin());
+    let template = repo.path().join(".git/ast-template");
+    let template = template.to_str().unwrap();
+    repo.git_ast(&["init-template", template, "--command", &bin]);
+    repo.git(&[
+        "init",
+        "--quiet",
+        &format!("--template={}", template),
+        "fresh",
+    ]);
+
+    let fresh = repo.path().join("fresh");
+    std::fs::write(fresh.join("lib.rs"), MESSY).unwrap();
+    let git = |args: &[&str]| {
+        let output = std::process::Command::new("git")
+            .current_dir(&fresh)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert!(git(&["config", "filter.ast.process"]).contains("filter-process"));
+    git(&["add", "lib.rs"]);
+    assert!(git(&["cat-file", "blob", ":lib.rs"]).starts_with("git-ast 1\nlanguage rust\n"));
+    assert!(fresh.join(".git/hooks/pre-push").exists());
+}
@@ -0,0 +1,20 @@
+use git_ast::commands::selftest;
+use std::path::Path;
+
+#[test]
+fn golden_corpus_matches_snapshots() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+    let report = selftest::run_corpus(&root, false, None).expect("corpus run");
+    assert!(report.inputs > 0, "golden corpus is empty");
+    let details: Vec<String> = report
+        .mismatches
+        .iter()
+        .map(|m| format!("{}\n{}", m.snapshot.display(), m.detail))
+        .collect();
+    assert!(
+        report.is_success(),
+        "golden corpus out of date (run `git-ast selftest --bless`):\n{}\nstale: {:?}",
+        details.join("\n"),
+        report.stale
+    );
+}
@@ -0,0 +1,170 @@
+//! Throwaway Git repositories wired to the `git-ast` binary under test.
+//!
+//! [`TestRepo::new`] creates a repository in a temporary directory whose
+//! `.git/config` runs this build of `git-ast` as the `ast` filter, diff
+//! driver and merge driver, with `*.rs` files using all three (through
+//! `.git/info/attributes`, so nothing extra is committed). Tests then drive
+//! the real `git` through [`TestRepo::git`], exactly as a user would.
+//!
+//! Every `git` call is hermetic: the machine's system and global Git
+//! config are ignored (`GIT_CONFIG_NOSYSTEM`, `GIT_CONFIG_GLOBAL`), so are
+//! `git-ast`'s own user-level settings (`GIT_AST_NO_USER_CONFIG`), and the
+//! author, committer and dates are fixed.
+//!
+//! Include it from an integration test with `mod testsupport;`.
+
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use tempfile::TempDir;
+
+/// The `git-ast` binary Cargo built for these tests.
+pub fn git_ast_bin() -> &'static str {
+    env!("CARGO_BIN_EXE_git-ast")
+}
+
+/// A temporary repository using `git-ast`; deleted when dropped.
+pub struct TestRepo {
+    dir: TempDir,
+}
+
+impl TestRepo {
+    /// An empty repository on branch `main`, configured for `git-ast`.
+    pub fn new() -> TestRepo {
+        let repo = TestRepo {
+            dir: tempfile::tempdir().expect("temporary directory"),
+        };
+        repo.git(&["init", "--quiet", "--initial-branch=main"]);
+        let bin = format!("'{}'", git_ast_bin());
+        repo.config("filter.ast.process", &format!("{} filter-process", bin));
+        repo.config("filter.ast.required", "true");
+        repo.config("diff.ast.command", &format!("{} diff-driver", bin));
+        repo.config(
+            "merge.ast.driver",
+            &format!("{} merge-driver %O %A %B %L %P", bin),
+        );
+        repo.config("ast.mergeSidecars", "false");
+        repo.write(
+            ".git/info/attributes",
+            "*.rs filter=ast diff=ast merge=ast\n",
+        );
+        repo
+    }
+
+    /// The working tree.
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Sets a value in the repository's config.
+    pub fn config(&self, key: &str, value: &str) {
+        self.git(&["config", key, value]);
+    }
+
+    /// Writes a working-tree file, creating its directories.
+    pub fn write(&self, path: &str, contents: &str) {
+        let file = self.path().join(path);
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(file, contents).unwrap();
+    }
+
+    /// Reads a working-tree file.
+    pub fn read(&self, path: &str) -> String {
+        std::fs::read_to_string(self.path().join(path)).unwrap()
+    }
+
+    /// Runs `git` in the repository; panics with its stderr if it fails.
+    /// Returns its stdout.
+    pub fn git(&self, args: &[&str]) -> String {
+        let output = self.try_git(args);
+        assert!(
+            output.status.success(),
+            "git {} failed:\n{}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).expect("git output is UTF-8")
+    }
+
+    /// Runs `git` in the repository and returns whatever happened.
+    pub fn try_git(&self, args: &[&str]) -> Output {
+        self.command("git", args)
+            .output()
+            .expect("git is installed")
+    }
+
+    /// Runs `git-ast` in the repository; panics if it fails. Returns stdout.
+    pub fn git_ast(&self, args: &[&str]) -> String {
+        let output = self.command(git_ast_bin(), args).output().unwrap();
+        assert!(
+            output.status.success(),
+            "git-ast {} failed:\n{}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).expect("git-ast output is UTF-8")
+    }
+
+    /// Runs `git-ast` in the repository with extra environment variables;
+    /// panics if it fails. Returns stdout.
+    pub fn git_ast_with_env(&self, env: &[(&str, &str)], args: &[&str]) -> String {
+        let output = self
+            .command(git_ast_bin(), args)
+            .envs(env.iter().copied())
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "git-ast {} failed:\n{}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).expect("git-ast output is UTF-8")
+    }
+
+    /// Runs `git-ast` in the repository and returns whatever happened.
+    pub fn try_git_ast(&self, args: &[&str]) -> Output {
+        self.command(git_ast_bin(), args).output().unwrap()
+    }
+
+    /// The stored (cleaned) blob at `rev:path`.
+    pub fn blob(&self, spec: &str) -> String {
+        self.git(&["cat-file", "blob", spec])
+    }
+
+    /// Stages everything and commits it.
+    pub fn commit_all(&self, message: &str) {
+        self.git(&["add", "--all"]);
+        self.git(&["commit", "--quiet", "--message", message]);
+    }
+
+    fn command(&self, program: &str, args: &[&str]) -> Command {
+        // `git` finds `git-remote-ast` next to the `git-ast` under test.
+        let bin = Path::new(git_ast_bin()).parent().unwrap().to_path_buf();
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        let path = std::iter::once(bin).chain(std::env::split_paths(&path));
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .current_dir(self.path())
+            .env("PATH", std::env::join_paths(path).unwrap())
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_CONFIG_GLOBAL", null_device())
+            .env("GIT_AST_NO_USER_CONFIG", "1")
+            .env("HOME", self.path())
+            .env("GIT_AUTHOR_NAME", "Test")
+            .env("GIT_AUTHOR_EMAIL", "test@example.com")
+            .env("GIT_AUTHOR_DATE", "2024-01-01T00:00:00Z")
+            .env("GIT_COMMITTER_NAME", "Test")
+            .env("GIT_COMMITTER_EMAIL", "test@example.com")
+            .env("GIT_COMMITTER_DATE", "2024-01-01T00:00:00Z")
+            .env_remove("GIT_DIR")
+            .env_remove("GIT_WORK_TREE");
+        command
+    }
+}
+
+fn null_device() -> PathBuf {
+    PathBuf::from(if cfg!(windows) { "NUL" } else { "/dev/null" })
+}